@@ -0,0 +1,72 @@
+/*
+ * C interface of the data manager.
+ *
+ * Build the shared library with:
+ *   cargo rustc --release --features ffi --crate-type cdylib
+ */
+#ifndef DATA_MANAGER_H
+#define DATA_MANAGER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define DM_OK 0
+#define DM_ERROR -1
+
+#define DM_STATUS_DOWNLOADING 0
+#define DM_STATUS_READY 1
+#define DM_STATUS_DELETING 2
+#define DM_STATUS_DELETED 3
+#define DM_STATUS_FAILED 4
+
+typedef struct DataManagerImpl DataManager;
+typedef struct DmChunkRef DmChunkRef;
+
+/* A file of the chunk and the URL to download it from */
+typedef struct {
+    const char *name;
+    const char *url;
+} DmFile;
+
+/* Data chunk description, block range is [block_start, block_end) */
+typedef struct {
+    uint8_t id[32];
+    uint8_t dataset_id[32];
+    uint64_t block_start;
+    uint64_t block_end;
+    const DmFile *files;
+    size_t files_len;
+} DmDataChunk;
+
+/* Called on every status change of a chunk with the chunk id (32 bytes) and one of DM_STATUS_* */
+typedef void (*DmEventCallback)(void *user_data, const uint8_t *chunk_id, int status);
+
+DataManager *dm_new(const char *data_dir);
+void dm_free(DataManager *manager);
+
+int dm_download_chunk(const DataManager *manager, const DmDataChunk *chunk);
+size_t dm_list_chunks(const DataManager *manager, uint8_t *chunk_ids, size_t capacity);
+DmChunkRef *dm_find_chunk(const DataManager *manager, const uint8_t *dataset_id, uint64_t block_number);
+/* Returns DM_ERROR when the chunk is unknown, not ready, or its deletion is refused */
+int dm_delete_chunk(const DataManager *manager, const uint8_t *chunk_id);
+/* Returns DM_ERROR for a NULL callback */
+int dm_set_event_callback(const DataManager *manager, DmEventCallback callback, void *user_data);
+
+/* Reads of the calling thread are attributed to the consumer, a null consumer attributes them to "anonymous" */
+int dm_set_consumer(const char *consumer);
+int dm_consumer_usage(const DataManager *manager, const char *consumer, uint64_t *queries, uint64_t *bytes_served);
+
+/* The chunk stays on disk until its reference is freed */
+const char *dm_chunk_ref_path(const DmChunkRef *chunk_ref);
+int dm_chunk_ref_id(const DmChunkRef *chunk_ref, uint8_t *chunk_id);
+void dm_chunk_ref_free(DmChunkRef *chunk_ref);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* DATA_MANAGER_H */
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use crate::data_chunk::{ChunkId, DataChunkPath, DataChunkRef, DatasetId};
+#[cfg(feature = "dataframes")]
+use {
+    std::fs::File,
+    polars::prelude::*,
+};
+
+/// Column of a chunk file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Column {
+    pub name: String,
+    /// Polars data type of the column, e.g. `u64` or `str`
+    pub data_type: String,
+}
+
+/// Everything a consumer needs to read a chunk, see `DataManagerImpl::acquire`.
+/// The chunk is pinned, so it stays on disk until this is dropped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AcquiredChunk {
+    pub chunk_id: ChunkId,
+    pub dataset_id: DatasetId,
+    pub block_range: Range<u64>,
+    /// Directory of the chunk
+    pub path: PathBuf,
+    /// Names of the files of the chunk, sorted
+    pub files: Vec<String>,
+    /// Columns of the parquet files by their names, read from the file footers.
+    /// Empty without the `dataframes` feature, files which can't be read are left out.
+    pub schema: BTreeMap<String, Vec<Column>>,
+    chunk_path: DataChunkPath,
+}
+
+impl AcquiredChunk {
+    pub(crate) fn new(chunk_path: DataChunkPath) -> Self {
+        let mut files: Vec<String> = chunk_path.chunk.files.keys().cloned().collect();
+        files.sort();
+        let schema = files.iter()
+            .filter_map(|file_name| Some((file_name.clone(), read_columns(&chunk_path.path.join(file_name))?)))
+            .collect();
+        AcquiredChunk {
+            chunk_id: chunk_path.chunk.id,
+            dataset_id: chunk_path.chunk.dataset_id,
+            block_range: chunk_path.chunk.block_range.clone(),
+            path: chunk_path.path.clone(),
+            files,
+            schema,
+            chunk_path,
+        }
+    }
+
+    /// Reference to the chunk, which keeps it pinned as well
+    pub fn chunk_path(&self) -> &DataChunkPath {
+        &self.chunk_path
+    }
+}
+
+impl DataChunkRef for AcquiredChunk {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Columns of the parquet file, only its footer is read
+#[cfg(feature = "dataframes")]
+fn read_columns(file_path: &Path) -> Option<Vec<Column>> {
+    if file_path.extension().is_none_or(|extension| extension != "parquet") {
+        return None;
+    }
+    let schema = ParquetReader::new(File::open(file_path).ok()?).schema().ok()?;
+    Some(schema.iter_values()
+        .map(|field| Column { name: field.name.to_string(), data_type: DataType::from_arrow(&field.dtype, true).to_string() })
+        .collect())
+}
+
+#[cfg(not(feature = "dataframes"))]
+fn read_columns(_file_path: &Path) -> Option<Vec<Column>> {
+    None
+}
+
+#[cfg(all(test, feature = "dataframes"))]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io;
+    use std::sync::Arc;
+    use crate::config::DataManagerConfig;
+    use crate::data_chunk::DataChunk;
+    use crate::data_manager::DataManager;
+    use crate::transfer::ChunkTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// Writes a parquet file with the block numbers of the chunk and an empty log file
+    struct ParquetTransfer;
+
+    impl ChunkTransfer for ParquetTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::create_dir_all(chunk_dir)?;
+            let block_numbers: Vec<u64> = chunk.block_range.clone().collect();
+            let mut df = df!("block_number" => &block_numbers, "hash" => block_numbers.iter().map(|block| format!("0x{:x}", block)).collect::<Vec<String>>())
+                .map_err(io::Error::other)?;
+            ParquetWriter::new(File::create(chunk_dir.join("blocks.parquet"))?).finish(&mut df).map_err(io::Error::other)?;
+            fs::write(chunk_dir.join("logs.jsonl"), [])
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    #[test]
+    fn test_acquire_returns_pinned_chunk_with_schema() {
+        // Arrange
+        let dir = test_support::temp_dir("acquire");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(ParquetTransfer))
+            .build();
+        let chunk = DataChunk {
+            id: [5u8; 32],
+            dataset_id: [6u8; 32],
+            block_range: 100..110,
+            files: HashMap::from([
+                ("logs.jsonl".to_string(), "https://example.com/logs.jsonl".to_string()),
+                ("blocks.parquet".to_string(), "https://example.com/blocks.parquet".to_string()),
+            ]),
+            mirrors: HashMap::new(),
+            size: None,
+            file_sizes: HashMap::new(),
+        };
+        data_manager.download_chunk(chunk.clone());
+        data_manager.data_catalogue.wait_until_downloaded(&[chunk.id]);
+
+        // Act
+        let acquired = data_manager.acquire(chunk.dataset_id, 105).unwrap();
+
+        // Assert
+        assert_eq!(acquired.chunk_id, chunk.id);
+        assert_eq!(acquired.block_range, 100..110);
+        assert_eq!(acquired.files, vec!["blocks.parquet".to_string(), "logs.jsonl".to_string()]);
+        assert_eq!(acquired.schema.len(), 1);
+        assert_eq!(acquired.schema["blocks.parquet"], vec![
+            Column { name: "block_number".to_string(), data_type: "u64".to_string() },
+            Column { name: "hash".to_string(), data_type: "str".to_string() },
+        ]);
+        assert!(acquired.path().join("blocks.parquet").exists());
+        assert_eq!(data_manager.data_catalogue.pins.pin_count(&chunk.id), 1);
+        assert!(data_manager.data_catalogue.last_queried.read().unwrap().contains_key(&chunk.id));
+        assert!(data_manager.acquire(chunk.dataset_id, 110).is_none());
+
+        // cleanup
+        drop(acquired);
+        assert_eq!(data_manager.data_catalogue.pins.pin_count(&chunk.id), 0);
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
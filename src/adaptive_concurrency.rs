@@ -0,0 +1,248 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::download_pool::DEFAULT_MAX_CONCURRENT_DOWNLOADS;
+use crate::workers::Workers;
+
+/// File written by the disk latency probe, in the data directory
+const PROBE_FILE: &str = ".latency_probe";
+const PROBE_BYTES: usize = 4096;
+/// A throughput change smaller than this share is noise, it neither raises nor lowers the concurrency
+const THROUGHPUT_TOLERANCE: f64 = 0.1;
+
+/// Bounds and targets of the concurrency of the downloads and deletions, adjusted as the host gets busy
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdaptiveConcurrencyConfig {
+    pub min_concurrency: usize,
+    pub max_concurrency: usize,
+    /// Latency of a small synced write to the data directory above which the disk is saturated
+    pub max_io_latency: Duration,
+    /// How often the concurrency is adjusted
+    pub interval: Duration,
+}
+
+impl Default for AdaptiveConcurrencyConfig {
+    fn default() -> Self {
+        AdaptiveConcurrencyConfig {
+            min_concurrency: 1,
+            max_concurrency: DEFAULT_MAX_CONCURRENT_DOWNLOADS,
+            max_io_latency: Duration::from_millis(50),
+            interval: Duration::from_secs(10),
+        }
+    }
+}
+
+struct ControllerState {
+    limit: usize,
+    /// Deletions running now
+    deletions: usize,
+    /// Bytes downloaded since the last adjustment
+    bytes: u64,
+    io_latency: Option<Duration>,
+    /// Throughput of the previous window, in bytes per second
+    last_throughput: Option<f64>,
+    window_started: Instant,
+}
+
+/// Feedback controller of the concurrency: it backs off by half when the disk latency exceeds the target,
+/// adds one while more concurrency raises the throughput and removes one when the throughput drops at the same concurrency,
+/// e.g. when the network is saturated by a co-located query engine
+#[derive(Clone)]
+pub struct ConcurrencyController {
+    config: AdaptiveConcurrencyConfig,
+    state: Arc<(Mutex<ControllerState>, Condvar)>,
+}
+
+impl ConcurrencyController {
+    /// Start at `initial`, within the bounds of the config
+    pub fn new(config: AdaptiveConcurrencyConfig, initial: usize) -> Self {
+        let limit = initial.clamp(config.min_concurrency.max(1), config.max_concurrency.max(1));
+        ConcurrencyController {
+            config,
+            state: Arc::new((Mutex::new(ControllerState {
+                limit,
+                deletions: 0,
+                bytes: 0,
+                io_latency: None,
+                last_throughput: None,
+                window_started: Instant::now(),
+            }), Condvar::new())),
+        }
+    }
+
+    /// Current concurrency of the downloads and of the deletions
+    pub fn limit(&self) -> usize {
+        self.state.0.lock().unwrap().limit
+    }
+
+    /// Count the bytes of a completed download towards the throughput of the window
+    pub fn record_transfer(&self, bytes: u64) {
+        self.state.0.lock().unwrap().bytes += bytes;
+    }
+
+    /// Record a measured disk latency, the highest of the window counts
+    pub fn record_io_latency(&self, latency: Duration) {
+        let mut state = self.state.0.lock().unwrap();
+        state.io_latency = Some(state.io_latency.map_or(latency, |io_latency| io_latency.max(latency)));
+    }
+
+    /// Adjust the concurrency by the samples of the window since the last adjustment and start a new window
+    pub fn adjust(&self) -> usize {
+        let (lock, deletion_freed) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        let elapsed = state.window_started.elapsed();
+        let limit = self.next_limit(&mut state, elapsed);
+        state.limit = limit;
+        deletion_freed.notify_all();
+        limit
+    }
+
+    fn next_limit(&self, state: &mut ControllerState, elapsed: Duration) -> usize {
+        let (min, max) = (self.config.min_concurrency.max(1), self.config.max_concurrency.max(1));
+        let throughput = (state.bytes > 0).then(|| state.bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON));
+        let io_latency = state.io_latency.take();
+        state.bytes = 0;
+        state.window_started = Instant::now();
+        if io_latency.is_some_and(|io_latency| io_latency > self.config.max_io_latency) {
+            // the throughput of a saturated host isn't comparable to the next windows
+            state.last_throughput = None;
+            return (state.limit / 2).clamp(min, max);
+        }
+        // an idle window tells nothing about the network, the concurrency stays
+        let Some(throughput) = throughput else { return state.limit };
+        let limit = match state.last_throughput {
+            Some(last_throughput) if throughput < last_throughput * (1.0 - THROUGHPUT_TOLERANCE) => state.limit.saturating_sub(1),
+            Some(last_throughput) if throughput < last_throughput * (1.0 + THROUGHPUT_TOLERANCE) => state.limit,
+            _ => state.limit + 1,
+        };
+        state.last_throughput = Some(throughput);
+        limit.clamp(min, max)
+    }
+
+    /// Wait until fewer deletions than the limit run, the deletion runs until the permit is dropped
+    pub fn acquire_deletion(&self) -> DeletionPermit {
+        let (lock, deletion_freed) = &*self.state;
+        let mut state = deletion_freed.wait_while(lock.lock().unwrap(), |state| state.deletions >= state.limit).unwrap();
+        state.deletions += 1;
+        DeletionPermit { controller: self.clone() }
+    }
+}
+
+/// A running deletion, counted against the limit until dropped
+pub struct DeletionPermit {
+    controller: ConcurrencyController,
+}
+
+impl Drop for DeletionPermit {
+    fn drop(&mut self) {
+        let (lock, deletion_freed) = &*self.controller.state;
+        lock.lock().unwrap().deletions -= 1;
+        deletion_freed.notify_one();
+    }
+}
+
+/// Time a small synced write to the directory, the way the query engine's reads and writes compete for the disk
+pub fn probe_io_latency(dir: &Path) -> io::Result<Duration> {
+    let probe_file = dir.join(PROBE_FILE);
+    let started_at = Instant::now();
+    let mut file = fs::File::create(&probe_file)?;
+    file.write_all(&[0u8; PROBE_BYTES])?;
+    file.sync_all()?;
+    let latency = started_at.elapsed();
+    fs::remove_file(probe_file)?;
+    Ok(latency)
+}
+
+/// Background job probing the disk and adjusting the concurrency of the download pool every interval.
+/// The job stops when the adjuster is dropped.
+pub struct ConcurrencyAdjuster {
+    _stop: mpsc::Sender<()>,
+}
+
+impl ConcurrencyAdjuster {
+    pub(crate) fn start(controller: ConcurrencyController, workers: Workers) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(controller.config.interval) {
+                // a failed probe is no sign of saturation, e.g. the data directory was just mounted
+                if let Ok(latency) = probe_io_latency(&workers.data_source.data_dir) {
+                    controller.record_io_latency(latency);
+                }
+                workers.download_pool.set_max_concurrent(controller.adjust());
+            }
+        });
+        ConcurrencyAdjuster { _stop: stop }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller() -> ConcurrencyController {
+        let config = AdaptiveConcurrencyConfig { min_concurrency: 2, max_concurrency: 6, ..AdaptiveConcurrencyConfig::default() };
+        ConcurrencyController::new(config, 4)
+    }
+
+    fn adjust(controller: &ConcurrencyController, bytes: u64, io_latency: Duration) -> usize {
+        controller.record_transfer(bytes);
+        controller.record_io_latency(io_latency);
+        let mut state = controller.state.0.lock().unwrap();
+        let limit = controller.next_limit(&mut state, Duration::from_secs(1));
+        state.limit = limit;
+        limit
+    }
+
+    #[test]
+    fn test_concurrency_follows_throughput_and_latency() {
+        let controller = controller();
+        let fast = Duration::from_millis(1);
+
+        // more concurrency is tried while the throughput grows
+        assert_eq!(adjust(&controller, 100, fast), 5);
+        assert_eq!(adjust(&controller, 200, fast), 6);
+        assert_eq!(adjust(&controller, 300, fast), 6);
+        // the same throughput keeps the concurrency, a drop lowers it
+        assert_eq!(adjust(&controller, 305, fast), 6);
+        assert_eq!(adjust(&controller, 200, fast), 5);
+        // an idle window changes nothing
+        assert_eq!(adjust(&controller, 0, fast), 5);
+        // a saturated disk halves the concurrency, down to the minimum
+        assert_eq!(adjust(&controller, 200, Duration::from_millis(80)), 2);
+        assert_eq!(adjust(&controller, 200, Duration::from_millis(80)), 2);
+        assert_eq!(adjust(&controller, 200, fast), 3);
+        assert_eq!(ConcurrencyController::new(AdaptiveConcurrencyConfig::default(), 100).limit(), DEFAULT_MAX_CONCURRENT_DOWNLOADS);
+    }
+
+    #[test]
+    fn test_deletions_wait_for_a_permit() {
+        // Arrange
+        let controller = controller();
+        let permits: Vec<DeletionPermit> = (0..4).map(|_| controller.acquire_deletion()).collect();
+        let waiting = controller.clone();
+
+        // Act
+        let deletion = thread::spawn(move || {
+            let _permit = waiting.acquire_deletion();
+        });
+        thread::sleep(Duration::from_millis(50));
+        let waited = !deletion.is_finished();
+        drop(permits);
+        deletion.join().unwrap();
+
+        // Assert
+        assert!(waited);
+        assert_eq!(controller.state.0.lock().unwrap().deletions, 0);
+    }
+
+    #[test]
+    fn test_probe_measures_a_synced_write() {
+        let dir = std::env::temp_dir();
+        assert!(probe_io_latency(&dir).is_ok());
+        assert!(!dir.join(PROBE_FILE).exists());
+    }
+}
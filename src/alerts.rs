@@ -0,0 +1,209 @@
+use std::collections::HashSet;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use crate::data_catalogue::{ChunkStatus, DataCatalogue};
+use crate::storage::StorageUsage;
+
+/// Watermarks of the local state which raise an alert when crossed, a watermark without a threshold is disabled
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlertThresholds {
+    /// How often the watermarks are checked
+    pub interval: Duration,
+    /// Bytes of the local chunks, e.g. 90% of the disk quota
+    pub max_storage_bytes: Option<u64>,
+    pub max_failed_chunks: Option<usize>,
+    /// Rows of the catalogue, including the rows of deleted chunks until the next compaction
+    pub max_catalogue_rows: Option<usize>,
+}
+
+impl Default for AlertThresholds {
+    fn default() -> Self {
+        AlertThresholds {
+            interval: Duration::from_secs(10),
+            max_storage_bytes: None,
+            max_failed_chunks: None,
+            max_catalogue_rows: None,
+        }
+    }
+}
+
+impl AlertThresholds {
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_max_storage_bytes(mut self, max_storage_bytes: u64) -> Self {
+        self.max_storage_bytes = Some(max_storage_bytes);
+        self
+    }
+
+    pub fn with_max_failed_chunks(mut self, max_failed_chunks: usize) -> Self {
+        self.max_failed_chunks = Some(max_failed_chunks);
+        self
+    }
+
+    pub fn with_max_catalogue_rows(mut self, max_catalogue_rows: usize) -> Self {
+        self.max_catalogue_rows = Some(max_catalogue_rows);
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Watermark {
+    StorageBytes,
+    FailedChunks,
+    CatalogueRows,
+}
+
+/// Crossing of a watermark, raised once when the value goes above the threshold and cleared once it's back
+#[derive(Clone, Debug, PartialEq)]
+pub enum Alert {
+    Raised { watermark: Watermark, value: u64, threshold: u64 },
+    Cleared { watermark: Watermark, value: u64, threshold: u64 },
+}
+
+/// Called with every alert, e.g. to page the operators through a webhook
+pub type AlertListener = Box<dyn Fn(&Alert) + Send + Sync>;
+
+/// Checks the watermarks in background and reports their crossings, separately from the lifecycle events
+/// of the chunks, so operators get paged on the conditions which need them only.
+/// The checks stop when the monitor is dropped.
+pub struct AlertMonitor {
+    _stop: mpsc::Sender<()>,
+}
+
+impl AlertMonitor {
+    pub(crate) fn start(thresholds: AlertThresholds, listener: AlertListener, data_catalogue: DataCatalogue, storage: StorageUsage) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let mut raised = HashSet::new();
+            loop {
+                let values = [
+                    (Watermark::StorageBytes, storage.stats().total_bytes),
+                    (Watermark::FailedChunks, failed_chunks(&data_catalogue) as u64),
+                    (Watermark::CatalogueRows, data_catalogue.stats().total_rows() as u64),
+                ];
+                for alert in check(&thresholds, &values, &mut raised) {
+                    listener(&alert);
+                }
+                if !matches!(stopped.recv_timeout(thresholds.interval), Err(RecvTimeoutError::Timeout)) {
+                    break;
+                }
+            }
+        });
+        AlertMonitor { _stop: stop }
+    }
+}
+
+fn failed_chunks(data_catalogue: &DataCatalogue) -> usize {
+    data_catalogue.registry.read().unwrap().values().filter(|info| info.status == ChunkStatus::Failed).count()
+}
+
+/// Alerts of the watermarks which were crossed since the last check, in either direction
+fn check(thresholds: &AlertThresholds, values: &[(Watermark, u64)], raised: &mut HashSet<Watermark>) -> Vec<Alert> {
+    values.iter()
+        .filter_map(|(watermark, value)| {
+            let threshold = match watermark {
+                Watermark::StorageBytes => thresholds.max_storage_bytes,
+                Watermark::FailedChunks => thresholds.max_failed_chunks.map(|threshold| threshold as u64),
+                Watermark::CatalogueRows => thresholds.max_catalogue_rows.map(|threshold| threshold as u64),
+            }?;
+            let (watermark, value) = (*watermark, *value);
+            if value > threshold && raised.insert(watermark) {
+                Some(Alert::Raised { watermark, value, threshold })
+            } else if value <= threshold && raised.remove(&watermark) {
+                Some(Alert::Cleared { watermark, value, threshold })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use crate::config::DataManagerConfig;
+    use crate::data_chunk::DataChunk;
+    use crate::data_manager::DataManager;
+    use crate::transfer::ChunkTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    struct UnreachableTransfer;
+
+    impl ChunkTransfer for UnreachableTransfer {
+        fn download(&self, _chunk: &DataChunk, _chunk_dir: &Path) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::ConnectionRefused, "remote storage is down"))
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    fn chunk(block: u64) -> DataChunk {
+        DataChunk { id: [block as u8; 32], ..DataChunk::new([2u8; 32], block..block + 1) }
+    }
+
+    #[test]
+    fn test_alerts_are_raised_once_and_cleared() {
+        let thresholds = AlertThresholds::default().with_max_storage_bytes(900).with_max_failed_chunks(2);
+        let mut raised = HashSet::new();
+        let mut check_values = |bytes: u64, failed: u64| check(&thresholds, &[
+            (Watermark::StorageBytes, bytes),
+            (Watermark::FailedChunks, failed),
+            (Watermark::CatalogueRows, 1_000_000),
+        ], &mut raised);
+
+        assert_eq!(check_values(800, 0), vec![]);
+        assert_eq!(check_values(950, 3), vec![
+            Alert::Raised { watermark: Watermark::StorageBytes, value: 950, threshold: 900 },
+            Alert::Raised { watermark: Watermark::FailedChunks, value: 3, threshold: 2 },
+        ]);
+        assert_eq!(check_values(990, 4), vec![]);
+        assert_eq!(check_values(900, 4), vec![Alert::Cleared { watermark: Watermark::StorageBytes, value: 900, threshold: 900 }]);
+    }
+
+    #[test]
+    fn test_failed_chunks_raise_alert() {
+        // Arrange
+        let dir = test_support::temp_dir("alerts");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+        let recorded = alerts.clone();
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(UnreachableTransfer))
+            .watermark_alerts(
+                AlertThresholds::default().with_interval(Duration::from_millis(20)).with_max_failed_chunks(1),
+                Box::new(move |alert| recorded.lock().unwrap().push(alert.clone())),
+            )
+            .build();
+
+        // Act
+        for block in 0..3 {
+            data_manager.download_chunk(chunk(block));
+        }
+        data_manager.data_catalogue.wait_until_downloaded(&[chunk(0).id, chunk(1).id, chunk(2).id]);
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(100));
+        });
+
+        // Assert
+        // the failures may be seen by different checks, the alert is raised by the first one above the threshold
+        let alerts = alerts.lock().unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert!(matches!(alerts[0], Alert::Raised { watermark: Watermark::FailedChunks, value: 2..=3, threshold: 1 }));
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
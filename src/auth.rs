@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use crate::compression::ContentEncoding;
+use crate::data_chunk::DatasetId;
+use crate::tls::TlsConfig;
+
+/// Signs a request for the file at the url of the dataset, e.g. by adding the signature to the query of the url
+/// or by computing an `Authorization` header. It's called for every request, so short lived signatures don't expire.
+pub type RequestSigner = Arc<dyn Fn(&DatasetId, &str) -> io::Result<FileRequest> + Send + Sync>;
+
+/// Fetches the credentials of the dataset, e.g. a rotating token or signed query parameters from a token service.
+/// It's called only when the cached credentials are missing, about to expire or refused, see `CachedCredentials`.
+pub type CredentialProvider = Arc<dyn Fn(&DatasetId) -> io::Result<Credentials> + Send + Sync>;
+
+/// Credentials are fetched again this long before they expire, so they don't expire while a request is sent
+const EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+/// Request for a file of a chunk, as sent by a `RangeSource`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileRequest {
+    pub url: String,
+    /// Headers sent with the request, e.g. `Authorization`
+    pub headers: BTreeMap<String, String>,
+    /// URL of the proxy the request is sent through, see `ProxyConfig`
+    pub proxy: Option<String>,
+    /// Root and client certificates of the connection, see `ResumableTransfer::with_tls`
+    pub tls: Option<Arc<TlsConfig>>,
+    /// Longest wait for the connection, see `DownloadTimeouts`, sources which connect should apply it
+    pub connect_timeout: Option<Duration>,
+    /// Longest wait for the next bytes of the response, never past the deadline of the download
+    pub read_timeout: Option<Duration>,
+    /// Encodings the file may be sent with, in the order of preference, sent as `Accept-Encoding`.
+    /// Sources which can't compress their responses send the files as they are.
+    pub accept_encoding: Vec<ContentEncoding>,
+}
+
+impl FileRequest {
+    pub fn new(url: impl Into<String>) -> Self {
+        FileRequest {
+            url: url.into(),
+            headers: BTreeMap::new(),
+            proxy: None,
+            tls: None,
+            connect_timeout: None,
+            read_timeout: None,
+            accept_encoding: Vec::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn with_proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+}
+
+/// Headers and query parameters added to every request for the files of a dataset
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Credentials {
+    pub headers: BTreeMap<String, String>,
+    /// Added to the query of the url as they are, so they must already be url encoded
+    pub query: BTreeMap<String, String>,
+    /// The credentials are fetched again once they expire, never when it's not set
+    pub expires_at: Option<SystemTime>,
+}
+
+impl Credentials {
+    fn expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| SystemTime::now() + EXPIRY_MARGIN >= expires_at)
+    }
+
+    /// Request for the file at the url with the credentials
+    fn request(&self, url: &str) -> FileRequest {
+        let mut url = url.to_string();
+        for (name, value) in self.query.iter() {
+            url.push(if url.contains('?') { '&' } else { '?' });
+            url.push_str(&format!("{}={}", name, value));
+        }
+        FileRequest { headers: self.headers.clone(), ..FileRequest::new(url) }
+    }
+}
+
+/// Credentials of a dataset cached until they expire or a request with them is refused as unauthorized
+#[derive(Clone)]
+pub struct CachedCredentials {
+    provider: CredentialProvider,
+    cached: Arc<Mutex<Option<Credentials>>>,
+}
+
+impl CachedCredentials {
+    pub fn new(provider: CredentialProvider) -> Self {
+        CachedCredentials { provider, cached: Arc::new(Mutex::new(None)) }
+    }
+
+    /// The cached credentials, fetched when there are none or they expire.
+    /// They're fetched under the lock, so concurrent downloads wait for a single refresh.
+    pub fn credentials(&self, dataset_id: &DatasetId) -> io::Result<Credentials> {
+        let mut cached = self.cached.lock().unwrap();
+        match cached.as_ref() {
+            Some(credentials) if !credentials.expired() => Ok(credentials.clone()),
+            _ => {
+                let credentials = (self.provider)(dataset_id)?;
+                *cached = Some(credentials.clone());
+                Ok(credentials)
+            }
+        }
+    }
+
+    /// Drop the cached credentials, the next request fetches them again
+    pub fn invalidate(&self) {
+        self.cached.lock().unwrap().take();
+    }
+}
+
+/// How the file requests of a dataset are authenticated, see `ResumableTransfer::with_auth`
+#[derive(Clone)]
+pub enum AuthProvider {
+    /// Sent as `Authorization: Bearer <token>`
+    BearerToken(String),
+    /// Sent with every request as they are
+    Headers(BTreeMap<String, String>),
+    Signer(RequestSigner),
+    /// Fetched by the provider and cached, see `CachedCredentials`
+    Credentials(CachedCredentials),
+}
+
+impl AuthProvider {
+    /// Request for the file at the url
+    pub fn request(&self, dataset_id: &DatasetId, url: &str) -> io::Result<FileRequest> {
+        match self {
+            AuthProvider::BearerToken(token) => Ok(FileRequest::new(url).with_header("Authorization", format!("Bearer {}", token))),
+            AuthProvider::Headers(headers) => Ok(FileRequest { headers: headers.clone(), ..FileRequest::new(url) }),
+            AuthProvider::Signer(signer) => signer(dataset_id, url),
+            AuthProvider::Credentials(credentials) => Ok(credentials.credentials(dataset_id)?.request(url)),
+        }
+    }
+
+    /// Drop the cached credentials after a request was refused as unauthorized, false when there are none to refresh
+    pub fn refresh(&self) -> bool {
+        match self {
+            AuthProvider::Credentials(credentials) => {
+                credentials.invalidate();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A request refused with `401 Unauthorized`, sources report it as `PermissionDenied`
+pub fn is_unauthorized(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::PermissionDenied
+}
+
+impl fmt::Debug for AuthProvider {
+    /// The secrets are left out, so the provider can be logged
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthProvider::BearerToken(_) => write!(f, "BearerToken(..)"),
+            AuthProvider::Headers(headers) => f.debug_tuple("Headers").field(&headers.keys().collect::<Vec<_>>()).finish(),
+            AuthProvider::Signer(_) => write!(f, "Signer(..)"),
+            AuthProvider::Credentials(_) => write!(f, "Credentials(..)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_provider_request() {
+        let dataset_id = [1u8; 32];
+        let url = "https://example.com/blocks.parquet";
+        assert_eq!(
+            AuthProvider::BearerToken("secret".to_string()).request(&dataset_id, url).unwrap(),
+            FileRequest::new(url).with_header("Authorization", "Bearer secret")
+        );
+        let headers = BTreeMap::from([("X-Api-Key".to_string(), "secret".to_string())]);
+        assert_eq!(AuthProvider::Headers(headers.clone()).request(&dataset_id, url).unwrap().headers, headers);
+        let signer: RequestSigner = Arc::new(|dataset_id: &DatasetId, url: &str| Ok(FileRequest::new(format!("{}?sig={}", url, dataset_id[0]))));
+        assert_eq!(AuthProvider::Signer(signer).request(&dataset_id, url).unwrap(), FileRequest::new(format!("{}?sig=1", url)));
+        assert_eq!(format!("{:?}", AuthProvider::BearerToken("secret".to_string())), "BearerToken(..)");
+    }
+
+    #[test]
+    fn test_credentials_are_cached_until_they_expire_or_are_refused() {
+        // Arrange
+        let fetches = Arc::new(Mutex::new(0));
+        let provider: CredentialProvider = {
+            let fetches = fetches.clone();
+            Arc::new(move |_dataset_id: &DatasetId| {
+                let mut fetches = fetches.lock().unwrap();
+                *fetches += 1;
+                Ok(Credentials {
+                    headers: BTreeMap::from([("X-Token".to_string(), format!("token-{}", fetches))]),
+                    query: BTreeMap::from([("sig".to_string(), fetches.to_string())]),
+                    // the second credentials are already about to expire
+                    expires_at: Some(SystemTime::now() + if *fetches == 2 { EXPIRY_MARGIN / 2 } else { Duration::from_secs(3600) }),
+                })
+            })
+        };
+        let auth = AuthProvider::Credentials(CachedCredentials::new(provider));
+        let url = "https://example.com/blocks.parquet?v=1";
+
+        // Act
+        let first = auth.request(&[1u8; 32], url).unwrap();
+        let cached = auth.request(&[1u8; 32], url).unwrap();
+        let refreshed = auth.refresh();
+        let expiring = auth.request(&[1u8; 32], url).unwrap();
+        let renewed = auth.request(&[1u8; 32], url).unwrap();
+
+        // Assert
+        assert_eq!(first, FileRequest::new("https://example.com/blocks.parquet?v=1&sig=1").with_header("X-Token", "token-1"));
+        assert_eq!(cached, first);
+        assert!(refreshed);
+        assert_eq!(expiring.url, "https://example.com/blocks.parquet?v=1&sig=2");
+        assert_eq!(renewed.url, "https://example.com/blocks.parquet?v=1&sig=3");
+        assert_eq!(*fetches.lock().unwrap(), 3);
+        assert!(!AuthProvider::BearerToken("secret".to_string()).refresh());
+    }
+}
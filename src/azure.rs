@@ -0,0 +1,260 @@
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::data_source::{ObjectStore, ObjectStoreDataSource};
+use crate::gcs::AccessToken;
+
+/// Tokens expiring sooner are requested again before the request, so they don't expire in flight
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+/// Chunks kept in a container of Azure Blob Storage, created with an `AzureBlobStore`.
+/// Given to `DataManagerBuilder::data_source`, the data manager keeps its chunks in the container.
+pub type AzureBlobDataSource = ObjectStoreDataSource;
+
+/// Issues access tokens of the managed identity for the `https://storage.azure.com/` resource,
+/// e.g. by asking the instance metadata service
+pub trait ManagedIdentityCredentials: Send + Sync {
+    fn access_token(&self) -> io::Result<AccessToken>;
+}
+
+/// How the requests to the storage account are authorized
+#[derive(Clone)]
+pub enum AzureAuth {
+    /// Shared access signature, the query string granting access to the container
+    SasToken(String),
+    ManagedIdentity(Arc<dyn ManagedIdentityCredentials>),
+}
+
+/// Authorization of a single request
+#[derive(Clone, Debug, PartialEq)]
+pub enum Authorization {
+    /// Appended to the query of the URL
+    Sas(String),
+    /// Sent as `Authorization: Bearer <token>`
+    Bearer(String),
+}
+
+/// Requests of the Blob service REST API, implemented on top of an HTTP client
+pub trait AzureBlobClient: Send + Sync {
+    /// `PUT <blob url>` with `x-ms-blob-type: BlockBlob` and `Content-Length: <content_length>`, the body streamed from the reader
+    fn put_blob(&self, blob_url: &str, body: &mut dyn Read, content_length: u64, authorization: &Authorization) -> io::Result<()>;
+
+    /// `DELETE <blob url>`
+    fn delete_blob(&self, blob_url: &str, authorization: &Authorization) -> io::Result<()>;
+
+    /// Names of the blobs starting with the prefix, `GET <container url>?restype=container&comp=list&prefix=<prefix>`
+    /// following the `NextMarker` of every page
+    fn list_blobs(&self, container_url: &str, prefix: &str, authorization: &Authorization) -> io::Result<Vec<String>>;
+}
+
+/// Blobs of the containers of a storage account, as the objects of an `AzureBlobDataSource`
+pub struct AzureBlobStore {
+    client: Arc<dyn AzureBlobClient>,
+    /// Name of the storage account, e.g. `mystorageaccount`
+    pub account: String,
+    auth: AzureAuth,
+    token: Mutex<Option<AccessToken>>,
+}
+
+impl AzureBlobStore {
+    pub fn new(client: Arc<dyn AzureBlobClient>, account: impl Into<String>, auth: AzureAuth) -> Self {
+        AzureBlobStore { client, account: account.into(), auth, token: Mutex::new(None) }
+    }
+
+    pub fn container_url(&self, container: &str) -> String {
+        format!("https://{}.blob.core.windows.net/{}", self.account, container)
+    }
+
+    /// Authorization of the next request, the token of the managed identity is cached until shortly before it expires
+    fn authorization(&self) -> io::Result<Authorization> {
+        match &self.auth {
+            AzureAuth::SasToken(sas_token) => Ok(Authorization::Sas(sas_token.trim_start_matches('?').to_string())),
+            AzureAuth::ManagedIdentity(credentials) => {
+                let mut token = self.token.lock().unwrap();
+                if token.as_ref().is_none_or(|token| token.expires_at <= Instant::now() + TOKEN_REFRESH_MARGIN) {
+                    *token = Some(credentials.access_token()?);
+                }
+                Ok(Authorization::Bearer(token.as_ref().unwrap().value.clone()))
+            }
+        }
+    }
+}
+
+impl ObjectStore for AzureBlobStore {
+    fn put_object(&self, container: &str, key: &str, body: &mut dyn Read, content_length: u64) -> io::Result<()> {
+        self.client.put_blob(&self.object_url(container, key), body, content_length, &self.authorization()?)
+    }
+
+    fn delete_object(&self, container: &str, key: &str) -> io::Result<()> {
+        self.client.delete_blob(&self.object_url(container, key), &self.authorization()?)
+    }
+
+    fn list_objects(&self, container: &str, prefix: &str) -> io::Result<Vec<String>> {
+        self.client.list_blobs(&self.container_url(container), prefix, &self.authorization()?)
+    }
+
+    fn object_url(&self, container: &str, key: &str) -> String {
+        format!("{}/{}", self.container_url(container), key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use crate::config::DataManagerConfig;
+    use crate::data_catalogue::ChunkStatus;
+    use crate::data_chunk::DataChunk;
+    use crate::data_source::DataSource;
+    use crate::transfer::ChunkTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// Storage account kept in memory by blob url, recording the authorization of every request
+    #[derive(Default)]
+    struct InMemoryAccount {
+        blobs: Mutex<BTreeMap<String, Vec<u8>>>,
+        authorizations: Mutex<Vec<Authorization>>,
+    }
+
+    impl AzureBlobClient for InMemoryAccount {
+        fn put_blob(&self, blob_url: &str, body: &mut dyn Read, _content_length: u64, authorization: &Authorization) -> io::Result<()> {
+            self.authorizations.lock().unwrap().push(authorization.clone());
+            let mut blob = Vec::new();
+            body.read_to_end(&mut blob)?;
+            self.blobs.lock().unwrap().insert(blob_url.to_string(), blob);
+            Ok(())
+        }
+
+        fn delete_blob(&self, blob_url: &str, authorization: &Authorization) -> io::Result<()> {
+            self.authorizations.lock().unwrap().push(authorization.clone());
+            self.blobs.lock().unwrap().remove(blob_url);
+            Ok(())
+        }
+
+        fn list_blobs(&self, container_url: &str, prefix: &str, authorization: &Authorization) -> io::Result<Vec<String>> {
+            self.authorizations.lock().unwrap().push(authorization.clone());
+            let blob_prefix = format!("{}/{}", container_url, prefix);
+            Ok(self.blobs.lock().unwrap().keys()
+                .filter_map(|blob_url| blob_url.strip_prefix(&format!("{}/", container_url)).filter(|_| blob_url.starts_with(&blob_prefix)))
+                .map(str::to_string)
+                .collect())
+        }
+    }
+
+    /// Issues `token-1`, `token-2`, ... each valid for the given time
+    struct ExpiringIdentity {
+        valid_for: Duration,
+        issued: AtomicU32,
+    }
+
+    impl ManagedIdentityCredentials for ExpiringIdentity {
+        fn access_token(&self) -> io::Result<AccessToken> {
+            let issued = self.issued.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(AccessToken { value: format!("token-{}", issued), expires_at: Instant::now() + self.valid_for })
+        }
+    }
+
+    struct FileTransfer;
+
+    impl ChunkTransfer for FileTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            for file_name in chunk.files.keys() {
+                fs::write(chunk_dir.join(file_name), file_name)?;
+            }
+            Ok(())
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    fn chunk(block_range: std::ops::Range<u64>) -> DataChunk {
+        DataChunk {
+            files: HashMap::from([("blocks.parquet".to_string(), "https://origin/blocks.parquet".to_string())]),
+            ..DataChunk::new([3u8; 32], block_range)
+        }
+    }
+
+    #[test]
+    fn test_chunks_are_stored_in_container_with_sas_token() {
+        // Arrange
+        let account = Arc::new(InMemoryAccount::default());
+        let store = AzureBlobStore::new(account.clone(), "chunkstore", AzureAuth::SasToken("?sv=2022-11-02&sig=abc".to_string()));
+        let data_source = AzureBlobDataSource::new(Arc::new(store), "chunks", "")
+            .with_transfer(Arc::new(FileTransfer))
+            .with_scratch_dir(test_support::temp_dir("azure"));
+        let (kept, deleted) = (chunk(0..10), chunk(10..20));
+
+        // Act
+        data_source.download_chunk(kept.clone()).unwrap();
+        data_source.download_chunk(deleted.clone()).unwrap();
+        data_source.delete_chunk(&deleted).unwrap();
+
+        // Assert
+        let listed = data_source.list_local_chunks();
+        assert_eq!(listed.iter().map(|chunk| chunk.id).collect::<Vec<_>>(), vec![kept.id]);
+        assert_eq!(
+            listed[0].files["blocks.parquet"],
+            format!("https://chunkstore.blob.core.windows.net/chunks/dataset_id={}/block_range=0_10/blocks.parquet", hex::encode(kept.dataset_id))
+        );
+        assert_eq!(account.blobs.lock().unwrap().len(), 1);
+        assert!(account.authorizations.lock().unwrap().iter().all(|authorization| *authorization == Authorization::Sas("sv=2022-11-02&sig=abc".to_string())));
+    }
+
+    #[test]
+    fn test_data_manager_keeps_chunks_in_the_container() {
+        // Arrange
+        let dir = test_support::temp_dir("azure_backend");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let account = Arc::new(InMemoryAccount::default());
+        let store = AzureBlobStore::new(account.clone(), "chunkstore", AzureAuth::SasToken("sv=2022-11-02&sig=abc".to_string()));
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(FileTransfer))
+            .data_source(Arc::new(AzureBlobDataSource::new(Arc::new(store), "chunks", "")))
+            .build();
+        let chunk = chunk(0..10);
+
+        // Act
+        let result = data_manager.download_chunk_with_handle(chunk.clone()).wait();
+
+        // Assert
+        assert_eq!(result, Ok(()));
+        let blob_url = format!(
+            "https://chunkstore.blob.core.windows.net/chunks/dataset_id={}/block_range=0_10/blocks.parquet",
+            hex::encode(chunk.dataset_id),
+        );
+        assert_eq!(account.blobs.lock().unwrap().get(&blob_url), Some(&b"blocks.parquet".to_vec()));
+        assert_eq!(data_manager.get_chunk_info(chunk.id).map(|info| info.status), Some(ChunkStatus::Ready));
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_managed_identity_token_is_cached_until_it_expires() {
+        // Arrange
+        let account = Arc::new(InMemoryAccount::default());
+        let long_lived = Arc::new(ExpiringIdentity { valid_for: Duration::from_secs(3600), issued: AtomicU32::new(0) });
+        let short_lived = Arc::new(ExpiringIdentity { valid_for: Duration::from_secs(60), issued: AtomicU32::new(0) });
+        let cached = AzureBlobStore::new(account.clone(), "chunkstore", AzureAuth::ManagedIdentity(long_lived.clone()));
+        let expiring = AzureBlobStore::new(account.clone(), "chunkstore", AzureAuth::ManagedIdentity(short_lived.clone()));
+
+        // Act
+        for key in ["a", "b", "c"] {
+            cached.put_object("chunks", key, &mut io::empty(), 0).unwrap();
+            expiring.put_object("chunks", key, &mut io::empty(), 0).unwrap();
+        }
+
+        // Assert
+        assert_eq!(long_lived.issued.load(Ordering::SeqCst), 1);
+        assert_eq!(short_lived.issued.load(Ordering::SeqCst), 3);
+        assert_eq!(account.authorizations.lock().unwrap()[..2], [Authorization::Bearer("token-1".to_string()), Authorization::Bearer("token-1".to_string())]);
+    }
+}
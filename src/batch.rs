@@ -0,0 +1,174 @@
+use std::fmt;
+use crate::data_catalogue::ChunkStatus;
+use crate::data_chunk::{ChunkId, DataChunk};
+use crate::free_space::InsufficientSpace;
+use crate::planning;
+
+/// Why a chunk of a batch can't be downloaded at all
+#[derive(Clone, Debug, PartialEq)]
+pub enum InvalidChunk {
+    /// The chunk id isn't the one derived from the dataset id and the block range
+    ChunkId,
+    /// The block range has no blocks
+    EmptyBlockRange,
+    /// The chunk has no files
+    NoFiles,
+    /// Files of the chunk without a url
+    MissingUrls(Vec<String>),
+    /// A file name which isn't a single plain file name, e.g. `../x`, so it would be written outside the chunk directory
+    FileName(String),
+}
+
+impl fmt::Display for InvalidChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidChunk::ChunkId => write!(f, "chunk id doesn't match the dataset and block range"),
+            InvalidChunk::EmptyBlockRange => write!(f, "block range is empty"),
+            InvalidChunk::NoFiles => write!(f, "chunk has no files"),
+            InvalidChunk::MissingUrls(file_names) => write!(f, "files {:?} have no url", file_names),
+            InvalidChunk::FileName(file_name) => write!(f, "file name {:?} isn't a plain file name", file_name),
+        }
+    }
+}
+
+impl std::error::Error for InvalidChunk {}
+
+/// Why a valid chunk of a batch isn't downloaded
+#[derive(Clone, Debug, PartialEq)]
+pub enum SkipReason {
+    /// The chunk is already in the catalogue with this status, neither `Deleted` nor `Failed`
+    InCatalogue(ChunkStatus),
+    /// The chunk is listed earlier in the same batch
+    Duplicate,
+    /// The download was refused, by the overlap policy or because its dataset is frozen
+    Refused,
+    /// The chunk doesn't fit in the free space of the data directory
+    InsufficientSpace(InsufficientSpace),
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::InCatalogue(status) => write!(f, "chunk is already in the catalogue as {}", status),
+            SkipReason::Duplicate => write!(f, "chunk is listed twice"),
+            SkipReason::Refused => write!(f, "download was refused"),
+            SkipReason::InsufficientSpace(shortage) => write!(f, "download was refused: {}", shortage),
+        }
+    }
+}
+
+/// Outcome of `download_chunks`, the chunk ids of every list are in the order of the batch
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BatchSubmission {
+    /// Chunks whose downloads were started or queued
+    pub accepted: Vec<ChunkId>,
+    pub skipped: Vec<(ChunkId, SkipReason)>,
+    pub invalid: Vec<(ChunkId, InvalidChunk)>,
+}
+
+/// Check the chunk is well formed, before anything is recorded for it
+pub(crate) fn check_chunk(chunk: &DataChunk) -> Result<(), InvalidChunk> {
+    if chunk.block_range.is_empty() {
+        return Err(InvalidChunk::EmptyBlockRange);
+    }
+    if chunk.id != planning::generate_chunk_id(&chunk.dataset_id, &chunk.block_range) {
+        return Err(InvalidChunk::ChunkId);
+    }
+    if chunk.files.is_empty() {
+        return Err(InvalidChunk::NoFiles);
+    }
+    check_file_names(chunk)?;
+    let mut missing_urls: Vec<String> = chunk.files
+        .iter()
+        .filter(|(_, url)| url.is_empty())
+        .map(|(file_name, _)| file_name.clone())
+        .collect();
+    if !missing_urls.is_empty() {
+        missing_urls.sort();
+        return Err(InvalidChunk::MissingUrls(missing_urls));
+    }
+    Ok(())
+}
+
+/// Check the files of the chunk stay in its directory, done for every download, whatever API requested it
+pub(crate) fn check_file_names(chunk: &DataChunk) -> Result<(), InvalidChunk> {
+    match chunk.invalid_file_name() {
+        Some(file_name) => Err(InvalidChunk::FileName(file_name.to_string())),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::download_handle::DownloadError;
+    use crate::test_support::{self, chunk};
+    use super::*;
+
+    #[test]
+    fn test_check_chunk() {
+        let mut without_url = chunk(12, 0..10);
+        without_url.files.insert("logs.parquet".to_string(), String::new());
+        let mut without_files = chunk(12, 0..10);
+        without_files.files.clear();
+        let mut escaping = chunk(12, 0..10);
+        escaping.files.insert("../logs.parquet".to_string(), "https://example.com/logs.parquet".to_string());
+
+        assert_eq!(check_chunk(&chunk(12, 0..10)), Ok(()));
+        assert_eq!(check_chunk(&DataChunk { id: [0u8; 32], ..chunk(12, 0..10) }), Err(InvalidChunk::ChunkId));
+        assert_eq!(check_chunk(&chunk(12, 10..10)), Err(InvalidChunk::EmptyBlockRange));
+        assert_eq!(check_chunk(&without_files), Err(InvalidChunk::NoFiles));
+        assert_eq!(check_chunk(&without_url), Err(InvalidChunk::MissingUrls(vec!["logs.parquet".to_string()])));
+        assert_eq!(check_chunk(&escaping), Err(InvalidChunk::FileName("../logs.parquet".to_string())));
+    }
+
+    #[test]
+    fn test_downloads_with_unsafe_file_names_are_refused() {
+        // Arrange
+        let dir = test_support::temp_dir("batch_file_names");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let data_manager = test_support::manager_builder(&dir).build();
+        let mut escaping = chunk(12, 0..10);
+        escaping.files.insert("/tmp/logs.parquet".to_string(), "https://example.com/logs.parquet".to_string());
+
+        // Act
+        let result = data_manager.download_chunk_with_handle(escaping.clone()).wait();
+
+        // Assert
+        assert_eq!(result, Err(DownloadError::Invalid(InvalidChunk::FileName("/tmp/logs.parquet".to_string()))));
+        assert!(data_manager.get_chunk_info(escaping.id).is_none());
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_batch_is_deduplicated_against_the_catalogue() {
+        // Arrange
+        let dir = test_support::temp_dir("batch");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let data_manager = test_support::manager_builder(&dir).build();
+        let (held, first, second) = (chunk(12, 0..10), chunk(12, 10..20), chunk(12, 20..30));
+        data_manager.download_chunks(vec![held.clone()]);
+        data_manager.data_catalogue.wait_until_downloaded(&[held.id]);
+        let corrupt = DataChunk { id: [1u8; 32], ..chunk(12, 30..40) };
+
+        // Act
+        let submission = data_manager.download_chunks(vec![held.clone(), first.clone(), corrupt.clone(), second.clone(), first.clone()]);
+        data_manager.data_catalogue.wait_until_downloaded(&[first.id, second.id]);
+        // the status listeners save the pending downloads after the waiting threads are woken up
+        test_support::wait_until("the finished downloads are never dropped from the pending ones", || data_manager.pending_downloads.downloads().is_empty());
+
+        // Assert
+        assert_eq!(submission.accepted, vec![first.id, second.id]);
+        assert_eq!(submission.skipped, vec![(held.id, SkipReason::InCatalogue(ChunkStatus::Ready)), (first.id, SkipReason::Duplicate)]);
+        assert_eq!(submission.invalid, vec![(corrupt.id, InvalidChunk::ChunkId)]);
+        assert_eq!(data_manager.get_chunk_info(second.id).map(|info| info.status), Some(ChunkStatus::Ready));
+        assert!(data_manager.get_chunk_info(corrupt.id).is_none());
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,293 @@
+//! Standardized workloads run against a data directory, reported in a comparable form,
+//! to qualify new hardware and new versions of the crate. Run with `data-manager bench <data_dir>`.
+//!
+//! The workloads run on synthetic chunks in a scratch directory inside the target directory,
+//! which is removed afterwards, so the data already in the target directory is never touched.
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::config::DataManagerConfig;
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
+use crate::data_manager::DataManager;
+use crate::devtools::{self, DatasetSpec};
+use crate::planning::DirectoryLayout;
+use crate::storage;
+use crate::transfer::ChunkTransfer;
+use crate::DataManagerImpl;
+
+/// Dataset of the synthetic chunks
+const BENCH_DATASET: DatasetId = [0xbe; 32];
+
+pub const USAGE: &str = "usage: data-manager bench <data_dir> [--chunks <n>] [--blocks-per-chunk <n>] [--queries <n>] [--churn <n>]";
+
+/// Size of the workloads, the defaults take a few seconds on a laptop
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchConfig {
+    /// Synthetic chunks downloaded by the bulk download
+    pub chunks: u64,
+    pub blocks_per_chunk: u64,
+    /// `find_chunk` calls of the query workload
+    pub queries: usize,
+    /// Forget and register cycles of the catalogue churn
+    pub churn_cycles: usize,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig { chunks: 100, blocks_per_chunk: 100, queries: 100_000, churn_cycles: 200 }
+    }
+}
+
+impl BenchConfig {
+    /// Parse the arguments following `bench`, returns the target data directory and the config
+    pub fn from_args(args: &[String]) -> Result<(PathBuf, BenchConfig), String> {
+        let mut data_dir = None;
+        let mut config = BenchConfig::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                if data_dir.replace(PathBuf::from(arg)).is_some() {
+                    return Err(format!("unexpected argument {}", arg));
+                }
+                continue;
+            }
+            let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+            let number = value.parse::<u64>().map_err(|_| format!("{} must be a number, got {}", arg, value))?;
+            if number == 0 {
+                return Err(format!("{} must be at least 1", arg));
+            }
+            match arg.as_str() {
+                "--chunks" => config.chunks = number,
+                "--blocks-per-chunk" => config.blocks_per_chunk = number,
+                "--queries" => config.queries = number as usize,
+                "--churn" => config.churn_cycles = number as usize,
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+        Ok((data_dir.ok_or("the data_dir is missing")?, config))
+    }
+}
+
+/// Measurements of a single workload
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkloadReport {
+    pub name: &'static str,
+    pub operations: usize,
+    pub duration: Duration,
+    /// Bytes moved by the workload, 0 when it doesn't move any data
+    pub bytes: u64,
+    /// Latencies of single operations, `None` when only the whole workload is timed
+    pub p50: Option<Duration>,
+    pub p99: Option<Duration>,
+}
+
+impl WorkloadReport {
+    fn timed(name: &'static str, duration: Duration, mut latencies: Vec<Duration>) -> Self {
+        latencies.sort();
+        WorkloadReport {
+            name,
+            operations: latencies.len(),
+            duration,
+            bytes: 0,
+            p50: percentile(&latencies, 0.50),
+            p99: percentile(&latencies, 0.99),
+        }
+    }
+
+    pub fn ops_per_second(&self) -> f64 {
+        self.operations as f64 / self.duration.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn bytes_per_second(&self) -> f64 {
+        self.bytes as f64 / self.duration.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Report of a benchmark run, printed as a table with the same columns on every run
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchReport {
+    pub data_dir: PathBuf,
+    /// Version of the crate which ran the benchmark
+    pub version: &'static str,
+    pub config: BenchConfig,
+    pub workloads: Vec<WorkloadReport>,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "data-manager {} bench of {}", self.version, self.data_dir.display())?;
+        writeln!(
+            f,
+            "chunks: {}, blocks per chunk: {}, queries: {}, churn cycles: {}",
+            self.config.chunks, self.config.blocks_per_chunk, self.config.queries, self.config.churn_cycles
+        )?;
+        writeln!(f, "{:<16} {:>10} {:>12} {:>14} {:>12} {:>12} {:>12}", "workload", "ops", "seconds", "ops/s", "MB/s", "p50 us", "p99 us")?;
+        for workload in self.workloads.iter() {
+            let micros = |latency: Option<Duration>| latency.map_or("-".to_string(), |latency| latency.as_micros().to_string());
+            writeln!(
+                f,
+                "{:<16} {:>10} {:>12.3} {:>14.1} {:>12.1} {:>12} {:>12}",
+                workload.name,
+                workload.operations,
+                workload.duration.as_secs_f64(),
+                workload.ops_per_second(),
+                workload.bytes_per_second() / 1_000_000.0,
+                micros(workload.p50),
+                micros(workload.p99),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies the synthetic chunks from the scratch remote directory, their files map to local paths
+struct LocalCopyTransfer;
+
+impl ChunkTransfer for LocalCopyTransfer {
+    fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(chunk_dir)?;
+        for (file_name, source) in chunk.files.iter() {
+            fs::copy(source, chunk_dir.join(file_name))?;
+        }
+        Ok(())
+    }
+
+    fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+        fs::remove_dir_all(chunk_dir)
+    }
+}
+
+/// Run the bulk download, the `find_chunk` queries and the catalogue churn against the target data directory
+pub fn run(data_dir: &Path, config: &BenchConfig) -> io::Result<BenchReport> {
+    let scratch_dir = data_dir.join(format!("data-manager-bench-{}", std::process::id()));
+    let result = run_workloads(&scratch_dir, config);
+    let _ = fs::remove_dir_all(&scratch_dir);
+    Ok(BenchReport {
+        data_dir: data_dir.to_path_buf(),
+        version: env!("CARGO_PKG_VERSION"),
+        config: config.clone(),
+        workloads: result?,
+    })
+}
+
+fn run_workloads(scratch_dir: &Path, config: &BenchConfig) -> io::Result<Vec<WorkloadReport>> {
+    let remote_dir = scratch_dir.join("remote");
+    let dataset = DatasetSpec::new(BENCH_DATASET, 0, config.chunks, config.blocks_per_chunk);
+    let chunks = devtools::generate(&remote_dir, DirectoryLayout::default(), &[dataset])?;
+    let bytes = storage::chunk_size(&remote_dir)?;
+
+    fs::create_dir_all(scratch_dir.join("data"))?;
+    // only the measured work runs, no background jobs
+    let data_manager = DataManagerImpl::builder()
+        .config(DataManagerConfig::new(scratch_dir.join("data"))
+            .with_catalogue_file(scratch_dir.join("registry.parquet"))
+            .with_watchdog(None)
+            .with_storage_sampling(None))
+        .chunk_transfer(Arc::new(LocalCopyTransfer))
+        .build();
+
+    let bulk_download = bulk_download(&data_manager, &chunks, bytes);
+    let queries = find_chunk_queries(&data_manager, config);
+    let churn = catalogue_churn(&data_manager, &chunks, config.churn_cycles);
+    Ok(vec![bulk_download, queries, churn])
+}
+
+fn bulk_download(data_manager: &DataManagerImpl, chunks: &[DataChunk], bytes: u64) -> WorkloadReport {
+    let started_at = Instant::now();
+    for chunk in chunks {
+        data_manager.download_chunk(chunk.clone());
+    }
+    data_manager.data_catalogue.wait_until_downloaded(&chunks.iter().map(|chunk| chunk.id).collect::<Vec<ChunkId>>());
+    WorkloadReport {
+        name: "bulk_download",
+        operations: chunks.len(),
+        duration: started_at.elapsed(),
+        bytes,
+        p50: None,
+        p99: None,
+    }
+}
+
+fn find_chunk_queries(data_manager: &DataManagerImpl, config: &BenchConfig) -> WorkloadReport {
+    let blocks = config.chunks * config.blocks_per_chunk;
+    let mut latencies = Vec::with_capacity(config.queries);
+    let started_at = Instant::now();
+    for query in 0..config.queries as u64 {
+        // spread the queries over all the chunks, the same blocks on every run
+        let block_number = query.wrapping_mul(0x9E37_79B9_7F4A_7C15) % blocks;
+        let query_started_at = Instant::now();
+        let found = data_manager.find_chunk(BENCH_DATASET, block_number);
+        latencies.push(query_started_at.elapsed());
+        drop(found);
+    }
+    WorkloadReport::timed("find_chunk", started_at.elapsed(), latencies)
+}
+
+/// Every cycle forgets a chunk and registers it again, both persisting the catalogue
+fn catalogue_churn(data_manager: &DataManagerImpl, chunks: &[DataChunk], cycles: usize) -> WorkloadReport {
+    let mut latencies = Vec::with_capacity(cycles * 2);
+    let started_at = Instant::now();
+    for chunk in chunks.iter().cycle().take(cycles) {
+        let forget_started_at = Instant::now();
+        let _ = data_manager.forget_chunk(chunk.id);
+        latencies.push(forget_started_at.elapsed());
+        let register_started_at = Instant::now();
+        let _ = data_manager.register_chunk(chunk.clone(), false);
+        latencies.push(register_started_at.elapsed());
+    }
+    WorkloadReport::timed("catalogue_churn", started_at.elapsed(), latencies)
+}
+
+fn percentile(sorted: &[Duration], quantile: f64) -> Option<Duration> {
+    let last = sorted.len().checked_sub(1)?;
+    Some(sorted[(last as f64 * quantile).round() as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support;
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_arguments_are_parsed() {
+        assert_eq!(
+            BenchConfig::from_args(&args(&["/mnt/nvme", "--chunks", "10", "--churn", "5"])),
+            Ok((PathBuf::from("/mnt/nvme"), BenchConfig { chunks: 10, churn_cycles: 5, ..BenchConfig::default() })),
+        );
+        assert_eq!(BenchConfig::from_args(&args(&["--queries", "10"])), Err("the data_dir is missing".to_string()));
+        assert_eq!(BenchConfig::from_args(&args(&["/mnt/nvme", "--queries", "many"])), Err("--queries must be a number, got many".to_string()));
+        assert_eq!(BenchConfig::from_args(&args(&["/mnt/nvme", "--threads", "4"])), Err("unknown option --threads".to_string()));
+    }
+
+    #[test]
+    fn test_workloads_are_reported() {
+        // Arrange
+        let data_dir = test_support::temp_dir("bench");
+        fs::create_dir_all(&data_dir).unwrap();
+        let config = BenchConfig { chunks: 4, blocks_per_chunk: 10, queries: 100, churn_cycles: 6 };
+
+        // Act
+        let report = run(&data_dir, &config).unwrap();
+
+        // Assert
+        let operations: Vec<(&str, usize)> = report.workloads.iter().map(|workload| (workload.name, workload.operations)).collect();
+        assert_eq!(operations, vec![("bulk_download", 4), ("find_chunk", 100), ("catalogue_churn", 12)]);
+        assert!(report.workloads[0].bytes > 0);
+        assert!(report.workloads[1].p50 <= report.workloads[1].p99);
+        let printed = report.to_string();
+        assert!(printed.starts_with(&format!("data-manager {} bench of {}", env!("CARGO_PKG_VERSION"), data_dir.display())));
+        assert_eq!(printed.lines().count(), 6);
+        // the scratch directory is removed
+        assert_eq!(fs::read_dir(&data_dir).unwrap().count(), 0);
+
+        // cleanup
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+//! Command line of the data manager, built with the `devtools` feature:
+//! `cargo run --features devtools --bin data-manager -- bench <data_dir>`
+use std::env;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use data_manager::bench::{self, BenchConfig};
+use data_manager::chunk_lookup;
+use data_manager::config::DataManagerConfig;
+use data_manager::self_test::{self, SelfTestSource};
+use data_manager::DataManagerImpl;
+
+const EXPLAIN_USAGE: &str = "usage: data-manager explain <chunk_id> [<catalogue_file>]";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.split_first() {
+        Some((command, bench_args)) if command == "bench" => run_bench(bench_args),
+        Some((command, explain_args)) if command == "explain" => run_explain(explain_args),
+        Some((command, self_test_args)) if command == "self-test" => run_self_test(self_test_args),
+        _ => {
+            eprintln!("{}\n{}\n{}", bench::USAGE, EXPLAIN_USAGE, self_test::USAGE);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Print the dataset and blocks behind a chunk id, looked up in the catalogue file, the default one when not given
+fn run_explain(args: &[String]) -> ExitCode {
+    let (chunk_id, catalogue_file) = match args {
+        [chunk_id] => (chunk_id, DataManagerConfig::default().catalogue_file),
+        [chunk_id, catalogue_file] => (chunk_id, PathBuf::from(catalogue_file)),
+        _ => {
+            eprintln!("{}", EXPLAIN_USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let Some(chunk_id) = chunk_lookup::parse_chunk_id(chunk_id) else {
+        eprintln!("{} is not a chunk id, expected 32 hex encoded bytes\n{}", chunk_id, EXPLAIN_USAGE);
+        return ExitCode::FAILURE;
+    };
+    match chunk_lookup::describe_in_catalogue_file(&catalogue_file, &chunk_id) {
+        Some(description) => {
+            print!("{}", description);
+            ExitCode::SUCCESS
+        }
+        None => {
+            eprintln!("chunk {} is not in {}", hex::encode(chunk_id), catalogue_file.display());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run the built-in fixture chunk through the data directory and the catalogue file, the default one when not given.
+/// The command line has no client of the remote storage, the endpoint self-test is run by the applications through `DataManagerImpl::self_test`.
+fn run_self_test(args: &[String]) -> ExitCode {
+    let (data_dir, catalogue_file) = match args {
+        [data_dir] => (data_dir, DataManagerConfig::default().catalogue_file),
+        [data_dir, catalogue_file] => (data_dir, PathBuf::from(catalogue_file)),
+        _ => {
+            eprintln!("{}", self_test::USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let data_manager = DataManagerImpl::builder()
+        .config(DataManagerConfig::new(PathBuf::from(data_dir)).with_catalogue_file(catalogue_file))
+        .build();
+    let report = data_manager.self_test(&SelfTestSource::Fixture);
+    print!("{}", report);
+    match report.passed() {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    }
+}
+
+fn run_bench(args: &[String]) -> ExitCode {
+    let (data_dir, config) = match BenchConfig::from_args(args) {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            eprintln!("{}\n{}", error, bench::USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    match bench::run(&data_dir, &config) {
+        Ok(report) => {
+            print!("{}", report);
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("bench of {} failed: {}", data_dir.display(), error);
+            ExitCode::FAILURE
+        }
+    }
+}
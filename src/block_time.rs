@@ -0,0 +1,179 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use crate::data_chunk::DatasetId;
+#[cfg(feature = "dataframes")]
+use {std::fs::File, polars::prelude::*};
+#[cfg(not(feature = "dataframes"))]
+use crate::jsonl::{self, Row};
+
+/// Timestamp of a block, in seconds since the unix epoch
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockTime {
+    pub block_number: u64,
+    pub timestamp: u64,
+}
+
+impl BlockTime {
+    pub fn new(block_number: u64, timestamp: u64) -> Self {
+        BlockTime { block_number, timestamp }
+    }
+}
+
+/// Persisted index from the timestamps of the blocks to the block numbers, per dataset.
+/// Block times are recorded from the manifests carrying them, the index is saved on every recording.
+#[derive(Clone)]
+pub struct BlockTimeIndex {
+    /// last block produced at every timestamp, per dataset
+    blocks: Arc<RwLock<HashMap<DatasetId, BTreeMap<u64, u64>>>>,
+    file_path: PathBuf,
+}
+
+impl BlockTimeIndex {
+    /// Load the index saved in the file, a missing or unreadable file gives an empty index
+    pub fn open(file_path: &Path) -> Self {
+        let index = BlockTimeIndex { blocks: Arc::new(RwLock::new(HashMap::new())), file_path: file_path.to_path_buf() };
+        {
+            let mut blocks = index.blocks.write().unwrap();
+            for (dataset_id, block_time) in read_block_times(file_path) {
+                insert(blocks.entry(dataset_id).or_default(), block_time);
+            }
+        }
+        index
+    }
+
+    /// Add the block times of the dataset to the index and save it
+    pub fn record(&self, dataset_id: DatasetId, block_times: &[BlockTime]) -> io::Result<()> {
+        if block_times.is_empty() {
+            return Ok(());
+        }
+        let mut blocks = self.blocks.write().unwrap();
+        let dataset_blocks = blocks.entry(dataset_id).or_default();
+        for block_time in block_times {
+            insert(dataset_blocks, *block_time);
+        }
+        // saved under the lock, so concurrent recordings can't overwrite each other's times
+        self.save(&blocks)
+    }
+
+    /// Last block of the dataset produced at or before the timestamp, `None` before the first known block
+    pub fn block_at(&self, dataset_id: &DatasetId, timestamp: u64) -> Option<u64> {
+        let blocks = self.blocks.read().unwrap();
+        blocks.get(dataset_id)?.range(..=timestamp).next_back().map(|(_, block_number)| *block_number)
+    }
+
+    /// Number of distinct timestamps known for the dataset
+    pub fn known_timestamps(&self, dataset_id: &DatasetId) -> usize {
+        self.blocks.read().unwrap().get(dataset_id).map_or(0, BTreeMap::len)
+    }
+
+    fn save(&self, blocks: &HashMap<DatasetId, BTreeMap<u64, u64>>) -> io::Result<()> {
+        let rows = blocks.iter().flat_map(|(dataset_id, times)| times.iter().map(move |(timestamp, block_number)| (dataset_id, *block_number, *timestamp)));
+        write_block_times(&self.file_path, rows)
+    }
+}
+
+/// Several blocks may share a timestamp, the last of them covers it
+fn insert(times: &mut BTreeMap<u64, u64>, block_time: BlockTime) {
+    let block_number = times.entry(block_time.timestamp).or_insert(block_time.block_number);
+    *block_number = (*block_number).max(block_time.block_number);
+}
+
+#[cfg(feature = "dataframes")]
+fn write_block_times<'a>(file_path: &Path, rows: impl Iterator<Item = (&'a DatasetId, u64, u64)>) -> io::Result<()> {
+    let (mut dataset_ids, mut block_numbers, mut timestamps) = (Vec::new(), Vec::new(), Vec::new());
+    for (dataset_id, block_number, timestamp) in rows {
+        dataset_ids.push(hex::encode(dataset_id));
+        block_numbers.push(block_number);
+        timestamps.push(timestamp);
+    }
+    let mut df = df!(
+        "dataset_id" => dataset_ids,
+        "block_number" => block_numbers,
+        "timestamp" => timestamps
+    ).map_err(polars_error)?;
+    ParquetWriter::new(File::create(file_path)?).finish(&mut df).map_err(polars_error)?;
+    Ok(())
+}
+
+#[cfg(feature = "dataframes")]
+fn read_block_times(file_path: &Path) -> Vec<(DatasetId, BlockTime)> {
+    File::open(file_path).map_err(PolarsError::from)
+        .and_then(|file| ParquetReader::new(file).finish())
+        .and_then(|df| block_times_from_dataframe(&df))
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "dataframes")]
+fn block_times_from_dataframe(df: &DataFrame) -> PolarsResult<Vec<(DatasetId, BlockTime)>> {
+    let dataset_ids = df.column("dataset_id")?.str()?;
+    let block_numbers = df.column("block_number")?.u64()?;
+    let timestamps = df.column("timestamp")?.u64()?;
+    Ok((0..df.height())
+        .filter_map(|i| {
+            let dataset_id: DatasetId = hex::decode(dataset_ids.get(i)?).ok()?.try_into().ok()?;
+            Some((dataset_id, BlockTime::new(block_numbers.get(i)?, timestamps.get(i)?)))
+        })
+        .collect())
+}
+
+#[cfg(feature = "dataframes")]
+fn polars_error(error: PolarsError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(not(feature = "dataframes"))]
+fn write_block_times<'a>(file_path: &Path, rows: impl Iterator<Item = (&'a DatasetId, u64, u64)>) -> io::Result<()> {
+    jsonl::write_rows(file_path, rows.map(|(dataset_id, block_number, timestamp)| {
+        let mut row = Row::new();
+        row.insert("dataset_id".to_string(), hex::encode(dataset_id).into());
+        row.insert("block_number".to_string(), block_number.into());
+        row.insert("timestamp".to_string(), timestamp.into());
+        row
+    }))
+}
+
+#[cfg(not(feature = "dataframes"))]
+fn read_block_times(file_path: &Path) -> Vec<(DatasetId, BlockTime)> {
+    jsonl::read_rows(file_path).unwrap_or_default()
+        .iter()
+        .filter_map(|row| {
+            let dataset_id: DatasetId = hex::decode(jsonl::str_column(row, "dataset_id")?).ok()?.try_into().ok()?;
+            Some((dataset_id, BlockTime::new(jsonl::u64_column(row, "block_number")?, jsonl::u64_column(row, "timestamp")?)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::test_support;
+    use super::*;
+
+    #[test]
+    fn test_timestamps_map_to_covering_blocks() {
+        // Arrange
+        let file_path = test_support::temp_dir("block_times").with_extension("parquet");
+        let index = BlockTimeIndex::open(&file_path);
+
+        // Act
+        index.record([1u8; 32], &[BlockTime::new(10, 1_000), BlockTime::new(11, 1_012), BlockTime::new(12, 1_012), BlockTime::new(13, 1_024)]).unwrap();
+        index.record([2u8; 32], &[BlockTime::new(500, 1_000)]).unwrap();
+        let reopened = BlockTimeIndex::open(&file_path);
+
+        // Assert
+        for index in [&index, &reopened] {
+            assert_eq!(index.block_at(&[1u8; 32], 999), None);
+            assert_eq!(index.block_at(&[1u8; 32], 1_000), Some(10));
+            assert_eq!(index.block_at(&[1u8; 32], 1_011), Some(10));
+            assert_eq!(index.block_at(&[1u8; 32], 1_012), Some(12));
+            assert_eq!(index.block_at(&[1u8; 32], 5_000), Some(13));
+            assert_eq!(index.block_at(&[2u8; 32], 1_500), Some(500));
+            assert_eq!(index.known_timestamps(&[1u8; 32]), 3);
+        }
+
+        // cleanup
+        fs::remove_file(file_path).unwrap();
+    }
+}
@@ -0,0 +1,397 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::adaptive_concurrency::{ConcurrencyAdjuster, ConcurrencyController};
+use crate::alerts::{AlertListener, AlertMonitor, AlertThresholds};
+use crate::block_time::BlockTimeIndex;
+use crate::cancellation::Cancellations;
+use crate::checksum::ChecksumRegistry;
+use crate::chunk_errors::ChunkErrorKind;
+use crate::config::{ConfigError, DataManagerConfig};
+use crate::consumers::ConsumerStats;
+use crate::content_store::ContentStore;
+use crate::data_catalogue::{ChunkStatus, DataCatalogue};
+use crate::data_chunk::DataChunk;
+use crate::deadline::DeadlineMonitor;
+use crate::download_pool::DownloadPool;
+use crate::event_loop::TasksManager;
+use crate::fair_queue::FairQueue;
+use crate::federation::SecondaryCatalogue;
+use crate::free_space::{DiskSpace, SpacePreflight, StatvfsDiskSpace};
+use crate::planning::DirectoryLayout;
+use crate::published_checksums::PublishedChecksums;
+use crate::hooks::{HookDispatcher, HookMode, LifecycleEvent, LifecycleHooks};
+use crate::data_source::DataSource;
+use crate::local_data_source::LocalDataSource;
+use crate::notifications::{CoalescedNotifier, DeltaListener};
+use crate::maintenance::VerificationLog;
+use crate::origin::OriginFetcher;
+use crate::pending_downloads::PendingDownloads;
+use crate::download_handle::DownloadWaiters;
+#[cfg(feature = "dataframes")]
+use crate::query_cache::QueryCache;
+use crate::replication::{Replica, ReplicationStream};
+use crate::slo::SloTracker;
+use crate::storage::{self, StorageSampler, StorageUsage};
+use crate::throttle::BandwidthThrottle;
+use crate::tip::{ManifestSource, TipFollower};
+use crate::transfer::ChunkTransfer;
+use crate::transform::ChunkTransformer;
+#[cfg(feature = "dataframes")]
+use {
+    std::collections::HashMap,
+    crate::data_chunk::DatasetId,
+    crate::verification::BlockHashVerifier,
+};
+use crate::watchdog::Watchdog;
+use crate::DataManagerImpl;
+
+/// Block time index, saved next to the catalogue
+#[cfg(feature = "dataframes")]
+const BLOCK_TIMES_FILE: &str = "block_times.parquet";
+#[cfg(not(feature = "dataframes"))]
+const BLOCK_TIMES_FILE: &str = "block_times.jsonl";
+#[cfg(feature = "dataframes")]
+pub(crate) const PUBLISHED_CHECKSUMS_FILE: &str = "published_checksums.parquet";
+#[cfg(not(feature = "dataframes"))]
+pub(crate) const PUBLISHED_CHECKSUMS_FILE: &str = "published_checksums.jsonl";
+/// Downloads not finished yet, saved next to the catalogue
+#[cfg(feature = "dataframes")]
+pub(crate) const PENDING_DOWNLOADS_FILE: &str = "pending_downloads.parquet";
+#[cfg(not(feature = "dataframes"))]
+pub(crate) const PENDING_DOWNLOADS_FILE: &str = "pending_downloads.jsonl";
+
+/// Builds the `DataManagerImpl` with optional extensions
+#[derive(Default)]
+pub struct DataManagerBuilder {
+    config: DataManagerConfig,
+    hooks: Vec<(Arc<dyn LifecycleHooks>, HookMode)>,
+    transformers: Vec<Arc<dyn ChunkTransformer>>,
+    secondary_catalogues: Vec<Arc<dyn SecondaryCatalogue>>,
+    origin_fetcher: Option<Arc<dyn OriginFetcher>>,
+    manifest_source: Option<Arc<dyn ManifestSource>>,
+    chunk_transfer: Option<Arc<dyn ChunkTransfer>>,
+    backend: Option<Arc<dyn DataSource>>,
+    disk_space: Option<Arc<dyn DiskSpace>>,
+    #[cfg(feature = "dataframes")]
+    hash_verifiers: HashMap<DatasetId, BlockHashVerifier>,
+    replication_stream: Option<ReplicationStream>,
+    delta_listeners: Vec<(Duration, DeltaListener)>,
+    alerts: Option<(AlertThresholds, AlertListener)>,
+}
+
+impl DataManagerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn config(mut self, config: DataManagerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn data_dir(mut self, data_dir: PathBuf) -> Self {
+        self.config.data_dir = data_dir;
+        self
+    }
+
+    pub fn layout(mut self, layout: DirectoryLayout) -> Self {
+        self.config.layout = layout;
+        self
+    }
+
+    /// Register hooks run synchronously by the worker doing the transition
+    pub fn lifecycle_hooks(mut self, hooks: Arc<dyn LifecycleHooks>) -> Self {
+        self.hooks.push((hooks, HookMode::Sync));
+        self
+    }
+
+    /// Register hooks run in background, without holding up the transition
+    pub fn async_lifecycle_hooks(mut self, hooks: Arc<dyn LifecycleHooks>) -> Self {
+        self.hooks.push((hooks, HookMode::Async));
+        self
+    }
+
+    /// Register a transformer run over the files of every downloaded chunk, before the chunk is marked `Ready`.
+    /// Transformers run in the order they were registered.
+    pub fn chunk_transformer(mut self, transformer: Arc<dyn ChunkTransformer>) -> Self {
+        self.transformers.push(transformer);
+        self
+    }
+
+    /// Attach a read-only catalogue, which `find_chunk` consults when the chunk isn't held locally.
+    /// Catalogues are consulted in the order they were attached.
+    pub fn secondary_catalogue(mut self, catalogue: Arc<dyn SecondaryCatalogue>) -> Self {
+        self.secondary_catalogues.push(catalogue);
+        self
+    }
+
+    /// Serve reads of files of ready chunks, which turn out missing or corrupt, from their origin URLs,
+    /// while the chunks are downloaded again in background
+    pub fn origin_fallback(mut self, fetcher: Arc<dyn OriginFetcher>) -> Self {
+        self.origin_fetcher = Some(fetcher);
+        self
+    }
+
+    /// Source of the published chunks, needed to follow the tips of datasets
+    pub fn manifest_source(mut self, manifest_source: Arc<dyn ManifestSource>) -> Self {
+        self.manifest_source = Some(manifest_source);
+        self
+    }
+
+    /// Remote storage the chunks are downloaded from, instead of the simulated one
+    pub fn chunk_transfer(mut self, transfer: Arc<dyn ChunkTransfer>) -> Self {
+        self.chunk_transfer = Some(transfer);
+        self
+    }
+
+    /// Storage holding the chunks instead of the data directory, e.g. an `ObjectStoreDataSource` or an `AzureBlobDataSource`.
+    /// The downloads are still staged and verified in the data directory, and handed over to the storage once verified.
+    /// The catalogue starts with the chunks the storage lists, and the deletions remove the chunks from it.
+    /// The files are only read from the data directory, so reads of a chunk kept in another storage fail.
+    pub fn data_source(mut self, backend: Arc<dyn DataSource>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Free space of the data directory checked before the downloads, instead of the one reported by `statvfs`
+    pub fn disk_space(mut self, disk_space: Arc<dyn DiskSpace>) -> Self {
+        self.disk_space = Some(disk_space);
+        self
+    }
+
+    /// Check sampled block hashes of every downloaded chunk of the dataset against a trusted chain,
+    /// chunks which don't match are marked `Failed` rather than `Ready`
+    #[cfg(feature = "dataframes")]
+    pub fn block_hash_verifier(mut self, dataset_id: DatasetId, verifier: BlockHashVerifier) -> Self {
+        self.hash_verifiers.insert(dataset_id, verifier);
+        self
+    }
+
+    /// Follow the leader publishing its changes to the stream, see `replication::feed`.
+    /// The chunks of the leader are mirrored on their first read, the follower is eventually consistent with the leader.
+    pub fn follow_leader(mut self, stream: ReplicationStream) -> Self {
+        self.replication_stream = Some(stream);
+        self
+    }
+
+    /// Register a listener called with one summarized delta of the catalogue per `interval`,
+    /// instead of an event per transition, see `CoalescedNotifier`
+    pub fn coalesced_notifications(mut self, interval: Duration, listener: DeltaListener) -> Self {
+        self.delta_listeners.push((interval, listener));
+        self
+    }
+
+    /// Check the watermarks in background and call the listener when one of them is crossed, see `AlertMonitor`
+    pub fn watermark_alerts(mut self, thresholds: AlertThresholds, listener: AlertListener) -> Self {
+        self.alerts = Some((thresholds, listener));
+        self
+    }
+
+    /// Validate the configuration before building, so problems are reported up front
+    /// rather than deep inside the worker threads
+    pub fn try_build(self) -> Result<DataManagerImpl, ConfigError> {
+        self.config.validate()?;
+        Ok(self.build())
+    }
+
+    pub fn build(self) -> DataManagerImpl {
+        let cancellations = Cancellations::default();
+        let mut data_source = LocalDataSource::new(self.config.data_dir.clone())
+            .with_layout(self.config.layout)
+            .with_cancellations(cancellations.clone())
+            .with_volumes(DataCatalogue::read_chunk_volumes(&self.config.catalogue_file));
+        if let Some(transfer) = self.chunk_transfer {
+            data_source = data_source.with_transfer(transfer);
+        }
+        if self.config.deduplicated_files {
+            data_source = data_source.with_content_store(ContentStore::in_data_dir(&self.config.data_dir));
+        }
+        if let Some(download_bandwidth) = self.config.download_bandwidth.clone() {
+            data_source = data_source.with_throttle(BandwidthThrottle::new(download_bandwidth));
+        }
+        // the local chunks are streamed into the catalogue as they are found
+        let local_chunks: Box<dyn Iterator<Item = DataChunk>> = match &self.backend {
+            Some(backend) => Box::new(backend.list_local_chunks().into_iter()),
+            None => Box::new(data_source.scan_local_chunks(self.config.catalogue_load_parallelism).into_iter()),
+        };
+        let backend = self.backend.unwrap_or_else(|| Arc::new(data_source.clone()));
+        let tasks_manager = TasksManager::default();
+        let concurrency_controller = self.config.adaptive_concurrency.clone()
+            .map(|adaptive_config| ConcurrencyController::new(adaptive_config, self.config.max_concurrent_downloads));
+
+        let mut data_manager = DataManagerImpl {
+            config: self.config.clone(),
+            data_source,
+            backend,
+            hooks: HookDispatcher::new(self.hooks, tasks_manager.clone()),
+            tasks_manager,
+            data_catalogue: DataCatalogue::open(&self.config.catalogue_file, local_chunks)
+                .with_compaction(self.config.compaction)
+                .with_error_history(self.config.error_history)
+                .with_chunk_preference(self.config.chunk_preference),
+            slo: SloTracker::new(self.config.slo.clone()),
+            consumer_stats: ConsumerStats::default(),
+            transformers: Arc::new(self.transformers),
+            #[cfg(feature = "dataframes")]
+            query_cache: self.config.query_cache.clone().map(QueryCache::new),
+            secondary_catalogues: self.secondary_catalogues,
+            watchdog: None,
+            origin_fetcher: self.origin_fetcher,
+            download_queue: self.config.download_scheduling.clone().map(FairQueue::new),
+            storage: StorageUsage::default(),
+            storage_sampler: None,
+            tip_follower: None,
+            manifest_source: self.manifest_source.clone(),
+            deadlines: None,
+            #[cfg(feature = "dataframes")]
+            hash_verifiers: Arc::new(self.hash_verifiers),
+            replica: None,
+            verification_log: VerificationLog::default(),
+            notifiers: Vec::new(),
+            alert_monitor: None,
+            checksums: ChecksumRegistry::default()
+                .with_published(PublishedChecksums::open(&self.config.catalogue_file.with_file_name(PUBLISHED_CHECKSUMS_FILE))),
+            cancellations,
+            download_pool: DownloadPool::new(concurrency_controller.as_ref().map_or(self.config.max_concurrent_downloads, ConcurrencyController::limit))
+                .with_scheduling(self.config.download_scheduling.clone().unwrap_or_default()),
+            concurrency_controller,
+            concurrency_adjuster: None,
+            block_times: BlockTimeIndex::open(&self.config.catalogue_file.with_file_name(BLOCK_TIMES_FILE)),
+            pending_downloads: PendingDownloads::open(&self.config.catalogue_file.with_file_name(PENDING_DOWNLOADS_FILE)),
+            download_waiters: DownloadWaiters::default(),
+            space_preflight: self.config.free_space.clone().map(|free_space| {
+                let disk_space = self.disk_space.unwrap_or_else(|| Arc::new(StatvfsDiskSpace));
+                SpacePreflight::new(free_space, disk_space, self.config.data_dir.clone())
+            }),
+        };
+        #[cfg(feature = "dataframes")]
+        if let Some(query_cache) = data_manager.query_cache.clone() {
+            // any change of a chunk may change the results including its blocks
+            data_manager.data_catalogue.add_status_listener(Box::new(move |chunk, _| query_cache.invalidate(chunk)));
+        }
+        let hooks = data_manager.hooks.clone();
+        data_manager.data_catalogue.add_compaction_listener(Box::new(move |run| hooks.emit(LifecycleEvent::Compaction(run.clone()))));
+        for info in data_manager.data_catalogue.registry.read().unwrap().values() {
+            data_manager.storage.track(&info.chunk);
+        }
+        let storage = data_manager.storage.clone();
+        let data_source = data_manager.data_source.clone();
+        // the sizes are recorded as the chunks change, so the data directory never has to be walked
+        data_manager.data_catalogue.add_status_listener(Box::new(move |chunk, status| match status {
+            ChunkStatus::Ready => match storage::chunk_size(&data_source.chunk_path(chunk.clone()).path) {
+                Ok(bytes) => storage.record(chunk, bytes),
+                Err(_) => storage.track(chunk),
+            },
+            ChunkStatus::Deleted | ChunkStatus::Failed => storage.remove(&chunk.id),
+            ChunkStatus::Downloading | ChunkStatus::Deleting => {}
+        }));
+        let checksums = data_manager.checksums.clone();
+        // the checksums are only needed while the chunk may be downloaded again
+        data_manager.data_catalogue.add_status_listener(Box::new(move |chunk, status| {
+            if *status == ChunkStatus::Deleted {
+                checksums.remove(&chunk.id);
+            }
+        }));
+        let data_source = data_manager.data_source.clone();
+        // a deleted chunk is downloaded into the data directory again
+        data_manager.data_catalogue.add_status_listener(Box::new(move |chunk, status| {
+            if *status == ChunkStatus::Deleted {
+                data_source.set_volume(chunk.id, None);
+            }
+        }));
+        let progress = data_manager.data_source.progress.clone();
+        data_manager.data_catalogue.add_status_listener(Box::new(move |chunk, status| {
+            if *status != ChunkStatus::Downloading {
+                progress.finish(&chunk.id);
+            }
+        }));
+        let cancellations = data_manager.cancellations.clone();
+        // a cancellation arriving as the download completed is dropped, the chunk is deleted by the next sync
+        data_manager.data_catalogue.add_status_listener(Box::new(move |chunk, status| {
+            if matches!(status, ChunkStatus::Ready | ChunkStatus::Failed | ChunkStatus::Deleted) {
+                cancellations.take(&chunk.id);
+            }
+        }));
+        let pending_downloads = data_manager.pending_downloads.clone();
+        // a download is pending until it's done, failed or given up
+        data_manager.data_catalogue.add_status_listener(Box::new(move |chunk, status| {
+            if matches!(status, ChunkStatus::Ready | ChunkStatus::Failed | ChunkStatus::Deleted) {
+                let _ = pending_downloads.remove(&chunk.id);
+            }
+        }));
+        let download_waiters = data_manager.download_waiters.clone();
+        data_manager.data_catalogue.add_status_listener(Box::new(move |chunk, status| download_waiters.status_changed(&chunk.id, status)));
+        // the scan left out the chunks with files it couldn't read, they are kept as failed rather than served incomplete
+        for unreadable in data_manager.data_source.take_unreadable_chunks() {
+            let files: Vec<String> = unreadable.unreadable_files.iter().map(|file| file.display().to_string()).collect();
+            let message = format!("files with a name which isn't UTF-8: {}", files.join(", "));
+            data_manager.data_catalogue.register_failed_chunk(&unreadable.chunk, ChunkErrorKind::Verification, message);
+        }
+        data_manager.notifiers = self.delta_listeners.into_iter()
+            .map(|(interval, listener)| CoalescedNotifier::start(interval, listener, &data_manager.data_catalogue))
+            .collect();
+        data_manager.alert_monitor = self.alerts
+            .map(|(thresholds, listener)| AlertMonitor::start(thresholds, listener, data_manager.data_catalogue.clone(), data_manager.storage.clone()));
+        data_manager.storage_sampler = self.config.storage_sampling
+            .map(|sampling_config| StorageSampler::start(sampling_config, data_manager.storage.clone(), data_manager.workers()));
+        data_manager.tip_follower = self.manifest_source
+            .map(|manifest_source| TipFollower::start(self.config.tip_following.clone(), manifest_source, data_manager.workers()));
+        data_manager.replica = self.replication_stream
+            .map(|stream| Replica::start(stream, data_manager.workers()));
+        data_manager.deadlines = Some(DeadlineMonitor::start(data_manager.workers()));
+        data_manager.concurrency_adjuster = data_manager.concurrency_controller.clone()
+            .map(|controller| ConcurrencyAdjuster::start(controller, data_manager.workers()));
+        data_manager.watchdog =self.config.watchdog
+            .map(|watchdog_config| Watchdog::start(watchdog_config, data_manager.workers()));
+        // once everything is started, so the resumed downloads are handled like any other
+        data_manager.resume_pending_downloads();
+        data_manager
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support;
+    use super::*;
+
+    #[test]
+    fn test_try_build_reports_invalid_config() {
+        let result = DataManagerBuilder::new().data_dir(PathBuf::from("./missing_data_dir")).try_build();
+        let error = result.err().unwrap();
+        assert_eq!(error.diagnostics.len(), 1);
+        assert_eq!(error.diagnostics[0].field, "data_dir");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_chunks_with_non_utf8_file_names_are_registered_failed() {
+        use std::ffi::OsStr;
+        use std::fs;
+        use std::os::unix::ffi::OsStrExt;
+
+        // Arrange
+        let dir = test_support::temp_dir("builder_non_utf8");
+        let chunk = DataChunk::new([1u8; 32], 0..10);
+        let chunk_dir = LocalDataSource::new(dir.join("data")).chunk_path(chunk.clone()).path;
+        fs::create_dir_all(&chunk_dir).unwrap();
+        fs::write(chunk_dir.join("blocks.parquet"), b"blocks").unwrap();
+        fs::write(chunk_dir.join(OsStr::from_bytes(b"logs-\xff.parquet")), b"logs").unwrap();
+
+        // Act
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .build();
+
+        // Assert
+        let info = data_manager.data_catalogue.get_chunk_info(&chunk.id).unwrap();
+        assert_eq!(info.status, ChunkStatus::Failed);
+        assert_eq!(info.errors.len(), 1);
+        assert_eq!(info.errors[0].kind, ChunkErrorKind::Verification);
+        assert!(data_manager.data_catalogue.find_chunk(&chunk.dataset_id, 5).is_none());
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
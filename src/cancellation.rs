@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use crate::data_catalogue::{ChunkStatus, DataCatalogue};
+use crate::data_chunk::ChunkId;
+use crate::fair_queue::FairQueue;
+
+/// Downloads which are no longer needed, e.g. the chunk dropped out of the manifest passed to `ensure_chunks`.
+/// A cancelled download stops at the next checkpoint: once it gets a download slot, after the transfer
+/// and after the verification. Its files are removed and the chunk is marked `Deleted`.
+/// Transfers streaming the files stop at their next write, others may still finish their transfer.
+#[derive(Clone, Default)]
+pub struct Cancellations {
+    cancelled: Arc<Mutex<HashSet<ChunkId>>>,
+}
+
+impl Cancellations {
+    pub fn cancel(&self, chunk_id: ChunkId) {
+        self.cancelled.lock().unwrap().insert(chunk_id);
+    }
+
+    pub fn is_cancelled(&self, chunk_id: &ChunkId) -> bool {
+        self.cancelled.lock().unwrap().contains(chunk_id)
+    }
+
+    /// Forget the cancellation, returns whether the download was cancelled
+    pub fn take(&self, chunk_id: &ChunkId) -> bool {
+        self.cancelled.lock().unwrap().remove(chunk_id)
+    }
+
+    /// Cancel the download of the chunk and withdraw it from the queue, false when it isn't being downloaded
+    pub(crate) fn cancel_download(&self, data_catalogue: &DataCatalogue, download_queue: Option<&FairQueue>, chunk_id: ChunkId) -> bool {
+        let registry = data_catalogue.registry.read().unwrap();
+        if registry.get(&chunk_id).is_none_or(|info| info.status != ChunkStatus::Downloading) {
+            return false;
+        }
+        // under the lock of the registry, so the download can't complete meanwhile
+        self.cancel(chunk_id);
+        if let Some(download_queue) = download_queue {
+            download_queue.withdraw(&chunk_id);
+        }
+        true
+    }
+
+    /// Stop the download at a checkpoint when it was cancelled
+    pub(crate) fn check(&self, chunk_id: &ChunkId) -> io::Result<()> {
+        if self.is_cancelled(chunk_id) {
+            return Err(cancelled());
+        }
+        Ok(())
+    }
+}
+
+/// Not `Interrupted`, which `write_all` and `io::copy` retry
+pub(crate) fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "download cancelled")
+}
+
+/// Writer failing every write once the download is cancelled, so a streamed transfer stops mid-file
+pub struct CancellableWriter<'a> {
+    inner: &'a mut dyn Write,
+    cancelled: &'a dyn Fn() -> bool,
+}
+
+impl<'a> CancellableWriter<'a> {
+    pub fn new(inner: &'a mut dyn Write, cancelled: &'a dyn Fn() -> bool) -> Self {
+        CancellableWriter { inner, cancelled }
+    }
+}
+
+impl Write for CancellableWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if (self.cancelled)() {
+            return Err(cancelled());
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::thread;
+    use std::time::Duration;
+    use crate::config::DataManagerConfig;
+    use crate::data_catalogue::ChunkStatus;
+    use crate::data_chunk::DataChunk;
+    use crate::data_manager::DataManager;
+    use crate::fair_queue::DownloadSchedulingConfig;
+    use crate::transfer::{ChunkTransfer, RangeSource, ResumableTransfer};
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// Remote storage taking a while per chunk, remembering the chunks it transferred
+    #[derive(Default)]
+    struct SlowTransfer {
+        transferred: Mutex<Vec<ChunkId>>,
+    }
+
+    impl ChunkTransfer for SlowTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            self.transferred.lock().unwrap().push(chunk.id);
+            thread::sleep(Duration::from_millis(100));
+            fs::create_dir_all(chunk_dir)?;
+            fs::write(chunk_dir.join("blocks.parquet"), [])
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    /// Remote file streamed in small pieces, counting the bytes it sent
+    #[derive(Default)]
+    struct TricklingSource {
+        sent: Mutex<u64>,
+    }
+
+    impl RangeSource for TricklingSource {
+        fn content_length(&self, _url: &str) -> io::Result<u64> {
+            Ok(1_000)
+        }
+
+        fn read_range(&self, _url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            for _ in offset / 10..100 {
+                writer.write_all(&[0u8; 10])?;
+                *self.sent.lock().unwrap() += 10;
+                thread::sleep(Duration::from_millis(10));
+            }
+            Ok(1_000 - offset)
+        }
+    }
+
+    fn chunk(block: u64) -> DataChunk {
+        test_support::chunk(6, block..block + 10)
+    }
+
+    #[test]
+    fn test_downloads_dropped_from_manifest_are_cancelled() {
+        // Arrange
+        let dir = test_support::temp_dir("cancellation");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let transfer = Arc::new(SlowTransfer::default());
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data"))
+                .with_catalogue_file(dir.join("registry.parquet"))
+                .with_download_scheduling(DownloadSchedulingConfig { slots: 1, ..DownloadSchedulingConfig::default() }))
+            .chunk_transfer(transfer.clone())
+            .build();
+        let (transferring, queued, kept) = (chunk(0), chunk(10), chunk(20));
+        data_manager.download_chunk(transferring.clone());
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(20));
+        });
+        data_manager.download_chunk(queued.clone());
+        data_manager.download_chunk(kept.clone());
+
+        // Act
+        let plan = data_manager.ensure_chunks([6u8; 32], std::slice::from_ref(&kept));
+        data_manager.data_catalogue.wait_until_downloaded(&[transferring.id, queued.id, kept.id]);
+
+        // Assert
+        assert_eq!(plan.deletions.len(), 2);
+        let status = |chunk: &DataChunk| data_manager.get_chunk_info(chunk.id).map(|info| info.status);
+        assert_eq!(status(&transferring), Some(ChunkStatus::Deleted));
+        assert_eq!(status(&queued), Some(ChunkStatus::Deleted));
+        assert_eq!(status(&kept), Some(ChunkStatus::Ready));
+        assert_eq!(*transfer.transferred.lock().unwrap(), vec![transferring.id, kept.id]);
+        assert!(!data_manager.data_source.chunk_path(transferring.clone()).path.exists());
+        assert!(!data_manager.cancel_download(kept.id));
+        assert!(!data_manager.cancellations.is_cancelled(&transferring.id));
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_cancelled_transfer_stops_mid_file() {
+        // Arrange
+        let dir = test_support::temp_dir("cancelled_transfer");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let source = Arc::new(TricklingSource::default());
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(ResumableTransfer::new(source.clone())))
+            .build();
+        data_manager.download_chunk(chunk(0));
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(100));
+        });
+
+        // Act
+        let cancelled = data_manager.cancel_download(chunk(0).id);
+        data_manager.data_catalogue.wait_until_downloaded(&[chunk(0).id]);
+
+        // Assert
+        assert!(cancelled);
+        // the whole file takes a second
+        assert!(*source.sent.lock().unwrap() < 500);
+        assert_eq!(data_manager.get_chunk_info(chunk(0).id).map(|info| info.status), Some(ChunkStatus::Deleted));
+        assert!(!data_manager.data_source.chunk_path(chunk(0)).path.exists());
+        assert!(data_manager.list_chunks().is_empty());
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
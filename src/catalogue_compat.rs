@@ -0,0 +1,266 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::data_catalogue::ChunkStatus;
+#[cfg(feature = "dataframes")]
+use {std::fs::File, polars::prelude::*};
+#[cfg(not(feature = "dataframes"))]
+use crate::jsonl::{self, Row};
+
+/// Statuses as persisted by their `Display`
+const STATUSES: [ChunkStatus; 5] = [ChunkStatus::Downloading, ChunkStatus::Ready, ChunkStatus::Deleting, ChunkStatus::Deleted, ChunkStatus::Failed];
+
+/// How the column names and the statuses persisted by earlier versions map to the current ones.
+/// The catalogue is read with the default rules, registries with other names can be rewritten with `normalize_catalogue`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompatRules {
+    /// Earlier name of a column and its current name
+    pub column_aliases: Vec<(String, String)>,
+    /// Earlier status and the current one, matched case-insensitively.
+    /// The current statuses are matched case-insensitively as well.
+    pub status_aliases: Vec<(String, ChunkStatus)>,
+}
+
+impl Default for CompatRules {
+    fn default() -> Self {
+        CompatRules {
+            // the start of the block range was persisted with a typo
+            column_aliases: vec![("block_form".to_string(), "block_from".to_string())],
+            status_aliases: Vec::new(),
+        }
+    }
+}
+
+impl CompatRules {
+    pub fn with_column_alias(mut self, earlier: impl Into<String>, current: impl Into<String>) -> Self {
+        self.column_aliases.push((earlier.into(), current.into()));
+        self
+    }
+
+    pub fn with_status_alias(mut self, earlier: impl Into<String>, current: ChunkStatus) -> Self {
+        self.status_aliases.push((earlier.into(), current));
+        self
+    }
+
+    /// Current status of the persisted one, `None` for the statuses no rule knows
+    pub fn status(&self, persisted: &str) -> Option<ChunkStatus> {
+        STATUSES.iter()
+            .find(|status| status.to_string().eq_ignore_ascii_case(persisted))
+            .or_else(|| self.status_aliases.iter().find(|(earlier, _)| earlier.eq_ignore_ascii_case(persisted)).map(|(_, status)| status))
+            .cloned()
+    }
+
+    /// Current name of the persisted column, the columns no rule knows keep their names
+    pub fn column<'a>(&'a self, persisted: &'a str) -> &'a str {
+        self.column_aliases.iter()
+            .find(|(earlier, _)| earlier == persisted)
+            .map_or(persisted, |(_, current)| current.as_str())
+    }
+}
+
+/// What `normalize_catalogue` changed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Normalization {
+    /// Earlier and current names of the renamed columns
+    pub renamed_columns: Vec<(String, String)>,
+    /// Rows whose status was rewritten to its current name
+    pub rewritten_statuses: usize,
+    /// Statuses no rule knows, left as they are, the chunks are treated as `Deleted` when they're read
+    pub unknown_statuses: Vec<String>,
+}
+
+impl Normalization {
+    /// Whether the registry was persisted with the current names
+    pub fn is_empty(&self) -> bool {
+        self.renamed_columns.is_empty() && self.rewritten_statuses == 0
+    }
+
+    fn unknown_status(&mut self, status: &str) {
+        if !self.unknown_statuses.iter().any(|unknown| unknown == status) {
+            self.unknown_statuses.push(status.to_string());
+        }
+    }
+}
+
+/// Rewrite the persisted registry with the current column names and statuses, so it doesn't need to be fixed by hand after an upgrade.
+/// The registry is replaced only when something changed, through a temporary file next to it.
+/// A missing registry needs no rewrite.
+pub fn normalize_catalogue(catalogue_file: &Path, rules: &CompatRules) -> io::Result<Normalization> {
+    if !catalogue_file.exists() {
+        return Ok(Normalization::default());
+    }
+    let (rewritten, normalization) = normalized(catalogue_file, rules)?;
+    if !normalization.is_empty() {
+        let mut temp_path = catalogue_file.as_os_str().to_owned();
+        temp_path.push(".normalized");
+        write(Path::new(&temp_path), rewritten)?;
+        fs::rename(&temp_path, catalogue_file)?;
+    }
+    Ok(normalization)
+}
+
+/// Rename the columns of the registry and rewrite its statuses by the rules
+#[cfg(feature = "dataframes")]
+pub(crate) fn normalize_dataframe(df: &mut DataFrame, rules: &CompatRules) -> Normalization {
+    let mut normalization = Normalization::default();
+    let names: Vec<String> = df.get_column_names().iter().map(|name| name.to_string()).collect();
+    for name in names.iter() {
+        let current = rules.column(name);
+        // a registry with both names keeps the current column
+        if current != name && !names.iter().any(|existing| existing == current) && df.rename(name, current.into()).is_ok() {
+            normalization.renamed_columns.push((name.clone(), current.to_string()));
+        }
+    }
+    let Some(statuses) = df.column("status").ok().and_then(|statuses| statuses.str().ok()) else { return normalization };
+    let rewritten: Vec<Option<String>> = statuses.into_iter()
+        .map(|status| {
+            let status = status?;
+            match rules.status(status).map(|current| current.to_string()) {
+                Some(current) if current != status => {
+                    normalization.rewritten_statuses += 1;
+                    Some(current)
+                }
+                Some(_) => Some(status.to_string()),
+                None => {
+                    normalization.unknown_status(status);
+                    Some(status.to_string())
+                }
+            }
+        })
+        .collect();
+    if normalization.rewritten_statuses > 0 {
+        df.with_column(Series::new("status".into(), rewritten)).unwrap();
+    }
+    normalization
+}
+
+/// Rename the columns of a row of the registry and rewrite its status by the rules
+#[cfg(not(feature = "dataframes"))]
+pub(crate) fn normalize_row(row: &mut Row, rules: &CompatRules, normalization: &mut Normalization) {
+    let names: Vec<String> = row.keys().cloned().collect();
+    for name in names.iter() {
+        let current = rules.column(name).to_string();
+        if current != *name && !row.contains_key(&current) {
+            let value = row.remove(name).unwrap();
+            row.insert(current.clone(), value);
+            if !normalization.renamed_columns.iter().any(|(earlier, _)| earlier == name) {
+                normalization.renamed_columns.push((name.clone(), current));
+            }
+        }
+    }
+    let Some(status) = jsonl::str_column(row, "status").map(str::to_string) else { return };
+    match rules.status(&status).map(|current| current.to_string()) {
+        Some(current) if current != status => {
+            normalization.rewritten_statuses += 1;
+            row.insert("status".to_string(), current.into());
+        }
+        Some(_) => {}
+        None => normalization.unknown_status(&status),
+    }
+}
+
+#[cfg(feature = "dataframes")]
+fn normalized(catalogue_file: &Path, rules: &CompatRules) -> io::Result<(DataFrame, Normalization)> {
+    let mut df = ParquetReader::new(File::open(catalogue_file)?).finish().map_err(io::Error::other)?;
+    let normalization = normalize_dataframe(&mut df, rules);
+    Ok((df, normalization))
+}
+
+#[cfg(feature = "dataframes")]
+fn write(file_path: &Path, mut df: DataFrame) -> io::Result<()> {
+    ParquetWriter::new(File::create(file_path)?).finish(&mut df).map_err(io::Error::other)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "dataframes"))]
+fn normalized(catalogue_file: &Path, rules: &CompatRules) -> io::Result<(Vec<Row>, Normalization)> {
+    let mut rows = jsonl::read_rows(catalogue_file)?;
+    let mut normalization = Normalization::default();
+    for row in rows.iter_mut() {
+        normalize_row(row, rules, &mut normalization);
+    }
+    Ok((rows, normalization))
+}
+
+#[cfg(not(feature = "dataframes"))]
+fn write(file_path: &Path, rows: Vec<Row>) -> io::Result<()> {
+    jsonl::write_rows(file_path, rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_catalogue::DataCatalogue;
+    use crate::data_chunk::DataChunk;
+    use crate::test_support;
+    use super::*;
+
+    #[test]
+    fn test_status_rules() {
+        let rules = CompatRules::default().with_status_alias("Downloaded", ChunkStatus::Ready);
+        assert_eq!(rules.status("Ready"), Some(ChunkStatus::Ready));
+        assert_eq!(rules.status("FAILED"), Some(ChunkStatus::Failed));
+        assert_eq!(rules.status("downloaded"), Some(ChunkStatus::Ready));
+        assert_eq!(rules.status("Evicted"), None);
+        assert_eq!(rules.column("block_form"), "block_from");
+        assert_eq!(rules.column("status"), "status");
+    }
+
+    #[cfg(feature = "dataframes")]
+    fn write_legacy_registry(file_path: &Path, chunk: &DataChunk, statuses: [&str; 3]) {
+        let df = df!(
+            "id" => [hex::encode(chunk.id), hex::encode([1u8; 32]), hex::encode([2u8; 32])],
+            "dataset_id" => vec![hex::encode(chunk.dataset_id); 3],
+            "block_form" => [chunk.block_range.start, 20, 30],
+            "block_to" => [chunk.block_range.end, 30, 40],
+            "files" => [serde_json::to_string(&chunk.files).unwrap(), "{}".to_string(), "{}".to_string()],
+            "status" => statuses
+        ).unwrap();
+        write(file_path, df).unwrap();
+    }
+
+    #[cfg(not(feature = "dataframes"))]
+    fn write_legacy_registry(file_path: &Path, chunk: &DataChunk, statuses: [&str; 3]) {
+        let rows = [(chunk.id, chunk.block_range.clone(), serde_json::to_string(&chunk.files).unwrap()), ([1u8; 32], 20..30, "{}".to_string()), ([2u8; 32], 30..40, "{}".to_string())]
+            .into_iter()
+            .zip(statuses)
+            .map(|((id, block_range, files), status)| serde_json::json!({
+                "id": hex::encode(id),
+                "dataset_id": hex::encode(chunk.dataset_id),
+                "block_form": block_range.start,
+                "block_to": block_range.end,
+                "files": files,
+                "status": status,
+            }).as_object().unwrap().clone())
+            .collect::<Vec<Row>>();
+        write(file_path, rows).unwrap();
+    }
+
+    #[test]
+    fn test_legacy_registry_is_normalized() {
+        // Arrange
+        let file_path = test_support::temp_dir("compat");
+        let chunk = test_support::chunk(8, 0..10);
+        write_legacy_registry(&file_path, &chunk, ["ready", "Downloaded", "Evicted"]);
+        let rules = CompatRules::default().with_status_alias("Downloaded", ChunkStatus::Ready);
+
+        // Act
+        let tolerant = DataCatalogue::read_persisted_chunk(&file_path, &chunk.id);
+        let normalization = normalize_catalogue(&file_path, &rules).unwrap();
+        let normalized_again = normalize_catalogue(&file_path, &rules).unwrap();
+
+        // Assert
+        let tolerant = tolerant.unwrap();
+        assert_eq!((tolerant.chunk, tolerant.status), (chunk.clone(), ChunkStatus::Ready));
+        assert_eq!(normalization, Normalization {
+            renamed_columns: vec![("block_form".to_string(), "block_from".to_string())],
+            rewritten_statuses: 2,
+            unknown_statuses: vec!["Evicted".to_string()],
+        });
+        assert!(normalized_again.is_empty());
+        let persisted = DataCatalogue::read_persisted_chunk(&file_path, &[1u8; 32]).unwrap();
+        assert_eq!((persisted.chunk.block_range, persisted.status), (20..30, ChunkStatus::Ready));
+
+        // cleanup
+        fs::remove_file(file_path).unwrap();
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use crate::data_chunk::{ChunkId, DataChunk};
+use crate::ipfs;
+use crate::published_checksums::PublishedChecksums;
+
+/// Expected sha256 of the files of a chunk as hex, by file name, files without one aren't checked
+pub type FileChecksums = HashMap<String, String>;
+
+/// Checksums of the chunk files, kept next to `DataChunk::files` and checked after every download
+/// before the chunk is marked `Ready`
+#[derive(Clone, Default)]
+pub struct ChecksumRegistry {
+    checksums: Arc<RwLock<HashMap<ChunkId, FileChecksums>>>,
+    /// Checksums imported from the publishers, for the files without one of their own
+    published: Option<PublishedChecksums>,
+}
+
+impl ChecksumRegistry {
+    pub fn with_published(mut self, published: PublishedChecksums) -> Self {
+        self.published = Some(published);
+        self
+    }
+
+    pub fn published(&self) -> Option<&PublishedChecksums> {
+        self.published.as_ref()
+    }
+
+    /// Check the files of the next downloads of the chunk against the checksums
+    pub fn expect(&self, chunk_id: ChunkId, checksums: FileChecksums) {
+        self.checksums.write().unwrap().insert(chunk_id, checksums);
+    }
+
+    pub fn get(&self, chunk_id: &ChunkId) -> Option<FileChecksums> {
+        self.checksums.read().unwrap().get(chunk_id).cloned()
+    }
+
+    pub fn remove(&self, chunk_id: &ChunkId) {
+        self.checksums.write().unwrap().remove(chunk_id);
+    }
+
+    /// Checksums of the files of the chunk from all the sources, in the precedence of `verify`
+    pub fn file_checksums(&self, chunk: &DataChunk) -> FileChecksums {
+        let mut checksums = self.published.as_ref().map(|published| published.chunk_checksums(chunk)).unwrap_or_default();
+        checksums.extend(ipfs::file_checksums(chunk));
+        checksums.extend(self.get(&chunk.id).unwrap_or_default());
+        checksums
+    }
+
+    /// Compare the files of the chunk in the directory with their checksums, the checksums of the chunk
+    /// take precedence over the ones of the `ipfs://` CIDs, which take precedence over the published ones.
+    /// A mismatch is reported as an `InvalidData` error wrapping the `ChecksumMismatch`.
+    pub fn verify(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+        for (file_name, expected) in self.file_checksums(chunk).iter() {
+            let actual = sha256::digest(fs::read(chunk_dir.join(file_name))?);
+            if !actual.eq_ignore_ascii_case(expected) {
+                let mismatch = ChecksumMismatch { file_name: file_name.clone(), expected: expected.clone(), actual };
+                return Err(io::Error::new(io::ErrorKind::InvalidData, mismatch));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A downloaded file differs from its checksum, e.g. it was corrupted in transit or on the remote storage
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChecksumMismatch {
+    pub file_name: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "file {} has sha256 {}, expected {}", self.file_name, self.actual, self.expected)
+    }
+}
+
+impl Error for ChecksumMismatch {}
+
+/// Whether the error is a failed checksum, rather than a problem reading the files
+pub fn is_checksum_mismatch(error: &io::Error) -> bool {
+    error.get_ref().is_some_and(|error| error.is::<ChecksumMismatch>())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+    use crate::chunk_errors::ChunkErrorKind;
+    use crate::config::DataManagerConfig;
+    use crate::data_catalogue::ChunkStatus;
+    use crate::data_manager::DataManager;
+    use crate::transfer::ChunkTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// Remote storage serving the same content for every file
+    struct FixedContentTransfer;
+
+    impl ChunkTransfer for FixedContentTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::create_dir_all(chunk_dir)?;
+            for file_name in chunk.files.keys() {
+                fs::write(chunk_dir.join(file_name), b"blocks")?;
+            }
+            Ok(())
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    fn chunk(block: u64) -> DataChunk {
+        test_support::chunk(9, block..block + 10)
+    }
+
+    #[test]
+    fn test_corrupt_file_fails_download() {
+        // Arrange
+        let dir = test_support::temp_dir("checksums");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(FixedContentTransfer))
+            .build();
+        let (intact, corrupt) = (chunk(0), chunk(10));
+
+        // Act
+        data_manager.download_chunk_with_checksums(intact.clone(), FileChecksums::from([("blocks.parquet".to_string(), sha256::digest("blocks").to_uppercase())]));
+        data_manager.download_chunk_with_checksums(corrupt.clone(), FileChecksums::from([("blocks.parquet".to_string(), sha256::digest("other blocks"))]));
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(200));
+        });
+
+        // Assert
+        assert_eq!(data_manager.get_chunk_info(intact.id).unwrap().status, ChunkStatus::Ready);
+        let corrupt_info = data_manager.get_chunk_info(corrupt.id).unwrap();
+        assert_eq!(corrupt_info.status, ChunkStatus::Failed);
+        assert_eq!(corrupt_info.errors[0].kind, ChunkErrorKind::Verification);
+        assert!(corrupt_info.errors[0].message.starts_with("file blocks.parquet has sha256"));
+        assert!(data_manager.find_chunk(corrupt.dataset_id, 15).is_none());
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde_json::json;
+
+/// Number of errors kept per chunk by default
+pub const DEFAULT_ERROR_HISTORY: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChunkErrorKind {
+    Download,
+    /// The files of the chunk didn't pass the verification, e.g. on registration
+    Verification,
+    Delete,
+}
+
+impl fmt::Display for ChunkErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// An error which happened to a chunk, kept in the catalogue together with the chunk
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkError {
+    pub kind: ChunkErrorKind,
+    pub message: String,
+    pub at: SystemTime,
+}
+
+impl ChunkError {
+    pub fn new(kind: ChunkErrorKind, message: impl Into<String>) -> Self {
+        ChunkError { kind, message: message.into(), at: SystemTime::now() }
+    }
+}
+
+/// Errors in the form persisted in the catalogue, timestamps are in milliseconds since the unix epoch
+pub(crate) fn errors_to_json(errors: &[ChunkError]) -> String {
+    json!(errors.iter().map(|error| json!({
+        "kind": error.kind.to_string(),
+        "message": error.message,
+        "at": error.at.duration_since(UNIX_EPOCH).map(|at| at.as_millis() as u64).unwrap_or_default(),
+    })).collect::<Vec<serde_json::Value>>()).to_string()
+}
+
+/// Errors which can't be read are skipped, the history is only diagnostic
+pub(crate) fn errors_from_json(errors: &str) -> Vec<ChunkError> {
+    let Ok(serde_json::Value::Array(errors)) = serde_json::from_str(errors) else { return Vec::new() };
+    errors.iter()
+        .filter_map(|error| {
+            let kind = match error.get("kind")?.as_str()? {
+                "Download" => ChunkErrorKind::Download,
+                "Verification" => ChunkErrorKind::Verification,
+                "Delete" => ChunkErrorKind::Delete,
+                _ => return None,
+            };
+            Some(ChunkError {
+                kind,
+                message: error.get("message")?.as_str()?.to_string(),
+                at: UNIX_EPOCH + Duration::from_millis(error.get("at")?.as_u64()?),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_json_round_trip() {
+        let errors = vec![
+            ChunkError { kind: ChunkErrorKind::Download, message: "timeout".to_string(), at: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123) },
+            ChunkError { kind: ChunkErrorKind::Delete, message: "permission denied".to_string(), at: UNIX_EPOCH },
+        ];
+        assert_eq!(errors_from_json(&errors_to_json(&errors)), errors);
+        assert_eq!(errors_from_json(""), Vec::new());
+    }
+}
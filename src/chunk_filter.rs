@@ -0,0 +1,116 @@
+use crate::data_chunk::ChunkId;
+
+const COUNTERS_PER_CHUNK: usize = 10;
+const HASH_COUNT: u64 = 7;
+const MIN_CAPACITY: usize = 1024;
+
+/// Counting bloom filter over chunk ids, answering "definitely not present" without touching the registry.
+///
+/// Counters make removals possible, so the filter follows the catalogue without being rebuilt on every change.
+/// With 10 counters per chunk and 7 hashes there is about 1% of false positives.
+/// Once it holds more chunks than it was sized for, it has to be rebuilt bigger.
+#[derive(Clone, Debug)]
+pub struct ChunkFilter {
+    counters: Vec<u8>,
+    capacity: usize,
+    len: usize,
+}
+
+impl ChunkFilter {
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        ChunkFilter {
+            counters: vec![0; capacity * COUNTERS_PER_CHUNK],
+            capacity,
+            len: 0,
+        }
+    }
+
+    /// Build the filter from the chunk ids, leaving room for the same number of chunks to come
+    pub fn from_chunk_ids<'a>(chunk_ids: impl ExactSizeIterator<Item = &'a ChunkId>) -> Self {
+        let mut filter = Self::with_capacity(chunk_ids.len() * 2);
+        for chunk_id in chunk_ids {
+            filter.insert(chunk_id);
+        }
+        filter
+    }
+
+    pub fn insert(&mut self, chunk_id: &ChunkId) {
+        for index in self.indexes(chunk_id) {
+            // a saturated counter is never decremented, so it can't cause a false negative
+            self.counters[index] = self.counters[index].saturating_add(1);
+        }
+        self.len += 1;
+    }
+
+    /// Remove a chunk id, which was inserted before
+    pub fn remove(&mut self, chunk_id: &ChunkId) {
+        for index in self.indexes(chunk_id) {
+            if self.counters[index] != u8::MAX {
+                self.counters[index] = self.counters[index].saturating_sub(1);
+            }
+        }
+        self.len = self.len.saturating_sub(1);
+    }
+
+    /// `false` means the chunk is definitely not present, `true` means it probably is
+    pub fn might_contain(&self, chunk_id: &ChunkId) -> bool {
+        self.indexes(chunk_id).all(|index| self.counters[index] > 0)
+    }
+
+    pub fn is_overloaded(&self) -> bool {
+        self.len > self.capacity
+    }
+
+    fn indexes(&self, chunk_id: &ChunkId) -> impl Iterator<Item = usize> {
+        // chunk ids are sha256 digests, so their bytes are already well distributed
+        let first = u64::from_le_bytes(chunk_id[0..8].try_into().unwrap());
+        let second = u64::from_le_bytes(chunk_id[8..16].try_into().unwrap()) | 1;
+        let size = self.counters.len() as u64;
+        (0..HASH_COUNT).map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % size) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::planning::generate_chunk_id;
+    use super::*;
+
+    fn chunk_id(i: u64) -> ChunkId {
+        generate_chunk_id(&[1u8; 32], &(i..i + 1))
+    }
+
+    #[test]
+    fn test_insert_and_remove() {
+        // Arrange
+        let mut filter = ChunkFilter::with_capacity(10);
+
+        // Act
+        filter.insert(&chunk_id(1));
+        filter.insert(&chunk_id(2));
+        filter.remove(&chunk_id(1));
+
+        // Assert
+        assert!(!filter.might_contain(&chunk_id(1)));
+        assert!(filter.might_contain(&chunk_id(2)));
+    }
+
+    #[test]
+    fn test_false_positive_rate() {
+        let chunk_ids: Vec<ChunkId> = (0..10_000).map(chunk_id).collect();
+        let filter = ChunkFilter::from_chunk_ids(chunk_ids.iter());
+
+        assert!(chunk_ids.iter().all(|chunk_id| filter.might_contain(chunk_id)));
+        let false_positives = (10_000..20_000).filter(|i| filter.might_contain(&chunk_id(*i))).count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_overloaded_filter() {
+        let mut filter = ChunkFilter::with_capacity(0);
+        (0..MIN_CAPACITY as u64).for_each(|i| filter.insert(&chunk_id(i)));
+        assert!(!filter.is_overloaded());
+        filter.insert(&chunk_id(MIN_CAPACITY as u64));
+        assert!(filter.is_overloaded());
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use crate::data_catalogue::{ChunkInfo, ChunkStatus, DataCatalogue};
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
+use crate::lineage::LineageEvent;
+use crate::tip::ManifestSource;
+
+/// Where the chunk behind an id was found
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChunkOrigin {
+    /// The catalogue of the running data manager
+    Catalogue,
+    /// The persisted catalogue, which keeps the chunks until the catalogue is saved again
+    CatalogueFile,
+    /// A manifest of the published chunks, the chunk was never known locally
+    Manifest,
+}
+
+/// Dataset and blocks behind a chunk id, see `describe_chunk_id`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkDescription {
+    pub chunk_id: ChunkId,
+    pub dataset_id: DatasetId,
+    pub block_range: Range<u64>,
+    /// Status of the chunk, `None` when it's known only from a manifest
+    pub status: Option<ChunkStatus>,
+    pub origin: ChunkOrigin,
+    /// How the chunk was derived from earlier chunks, see `chunk_lineage`
+    pub lineage: Vec<LineageEvent>,
+}
+
+impl ChunkDescription {
+    pub(crate) fn from_info(info: ChunkInfo, origin: ChunkOrigin) -> Self {
+        ChunkDescription {
+            chunk_id: info.chunk.id,
+            dataset_id: info.chunk.dataset_id,
+            block_range: info.chunk.block_range,
+            status: Some(info.status),
+            origin,
+            lineage: info.lineage,
+        }
+    }
+
+    fn from_manifest(chunk: DataChunk) -> Self {
+        ChunkDescription {
+            chunk_id: chunk.id,
+            dataset_id: chunk.dataset_id,
+            block_range: chunk.block_range,
+            status: None,
+            origin: ChunkOrigin::Manifest,
+            lineage: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for ChunkDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "chunk    {}", hex::encode(self.chunk_id))?;
+        writeln!(f, "dataset  {}", hex::encode(self.dataset_id))?;
+        // the end of the range is exclusive
+        writeln!(f, "blocks   {}..{}", self.block_range.start, self.block_range.end)?;
+        match &self.status {
+            Some(status) => writeln!(f, "status   {}", status)?,
+            None => writeln!(f, "status   not known locally")?,
+        }
+        writeln!(f, "found in {:?}", self.origin)?;
+        for event in self.lineage.iter() {
+            let at = event.at.duration_since(UNIX_EPOCH).map(|at| at.as_secs()).unwrap_or_default();
+            writeln!(f, "lineage  {} {} at {}", hex::encode(event.chunk_id), event.kind, at)?;
+            for parent in event.parents.iter() {
+                writeln!(f, "           from {} blocks {}..{}", hex::encode(parent.chunk_id), parent.block_range.start, parent.block_range.end)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Chunk id as written in the logs, hex encoded with an optional `0x` prefix
+pub fn parse_chunk_id(chunk_id: &str) -> Option<ChunkId> {
+    let chunk_id = chunk_id.trim();
+    hex::decode(chunk_id.strip_prefix("0x").unwrap_or(chunk_id)).ok()?.try_into().ok()
+}
+
+/// Look the chunk up in the persisted catalogue, e.g. of a data manager which isn't running
+pub fn describe_in_catalogue_file(catalogue_file: &Path, chunk_id: &ChunkId) -> Option<ChunkDescription> {
+    DataCatalogue::read_persisted_chunk(catalogue_file, chunk_id).map(|info| ChunkDescription::from_info(info, ChunkOrigin::CatalogueFile))
+}
+
+/// Look the chunk up among the chunks published for the datasets, the manifests which can't be read are skipped
+pub fn describe_in_manifest(
+    manifest: &dyn ManifestSource,
+    dataset_ids: &BTreeSet<DatasetId>,
+    chunk_id: &ChunkId,
+) -> Option<ChunkDescription> {
+    dataset_ids.iter()
+        .filter_map(|dataset_id| manifest.published_chunks(dataset_id, 0).ok())
+        .flatten()
+        .find(|chunk| chunk.id == *chunk_id)
+        .map(ChunkDescription::from_manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+    use std::sync::Arc;
+    use crate::data_manager::DataManager;
+    use crate::test_support::{self, chunk};
+    use super::*;
+
+    struct StaticManifest(Vec<DataChunk>);
+
+    impl ManifestSource for StaticManifest {
+        fn published_chunks(&self, dataset_id: &DatasetId, from_block: u64) -> io::Result<Vec<DataChunk>> {
+            Ok(self.0.iter()
+                .filter(|chunk| chunk.dataset_id == *dataset_id && chunk.block_range.start >= from_block)
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_parse_chunk_id() {
+        assert_eq!(parse_chunk_id(&hex::encode([7u8; 32])), Some([7u8; 32]));
+        assert_eq!(parse_chunk_id(&format!("0x{}\n", hex::encode([7u8; 32]))), Some([7u8; 32]));
+        assert_eq!(parse_chunk_id("0x0707"), None);
+        assert_eq!(parse_chunk_id("not hex"), None);
+    }
+
+    #[test]
+    fn test_chunk_ids_are_described() {
+        // Arrange
+        let dir = test_support::temp_dir("lookup");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let (downloaded, published, unknown) = (chunk(5, 0..10), chunk(5, 10..20), chunk(5, 20..30));
+        let data_manager = test_support::manager_builder(&dir)
+            .manifest_source(Arc::new(StaticManifest(vec![published.clone()])))
+            .build();
+
+        // Act
+        // the published chunk is in the manifest of a dataset held locally, but isn't downloaded
+        data_manager.download_chunk(downloaded.clone());
+        data_manager.data_catalogue.wait_until_downloaded(&[downloaded.id]);
+        let described = data_manager.describe_chunk_id(downloaded.id);
+        let described_from_manifest = data_manager.describe_chunk_id(published.id);
+        let described_unknown = data_manager.describe_chunk_id(unknown.id);
+        drop(data_manager);
+        // the catalogue is saved right after the chunk got ready
+        futures::executor::block_on(async {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        });
+        let described_from_file = describe_in_catalogue_file(&dir.join("registry.parquet"), &downloaded.id);
+
+        // Assert
+        assert_eq!(described, Some(ChunkDescription {
+            chunk_id: downloaded.id,
+            dataset_id: [5u8; 32],
+            block_range: 0..10,
+            status: Some(ChunkStatus::Ready),
+            origin: ChunkOrigin::Catalogue,
+            lineage: Vec::new(),
+        }));
+        assert_eq!(described_from_manifest.map(|description| (description.block_range, description.origin)), Some((10..20, ChunkOrigin::Manifest)));
+        assert_eq!(described_unknown, None);
+        assert_eq!(described_from_file.map(|description| (description.block_range, description.origin)), Some((0..10, ChunkOrigin::CatalogueFile)));
+
+        // cleanup
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use crate::data_chunk::ChunkId;
+
+/// Counts readers holding a chunk, so its files are not deleted from under them
+#[derive(Clone, Default)]
+pub struct ChunkPins {
+    state: Arc<(Mutex<HashMap<ChunkId, usize>>, Condvar)>,
+    /// Times the files of the chunks being deleted are removed at, even when still pinned
+    deprecations: Arc<Mutex<HashMap<ChunkId, Instant>>>,
+}
+
+impl ChunkPins {
+    /// Pin the chunk until the returned guard and all its clones are dropped
+    pub fn pin(&self, chunk_id: &ChunkId) -> ChunkPin {
+        let (counts, _) = &*self.state;
+        *counts.lock().unwrap().entry(*chunk_id).or_insert(0) += 1;
+        ChunkPin {
+            pins: self.clone(),
+            chunk_id: *chunk_id,
+        }
+    }
+
+    pub fn pin_count(&self, chunk_id: &ChunkId) -> usize {
+        let (counts, _) = &*self.state;
+        counts.lock().unwrap().get(chunk_id).copied().unwrap_or(0)
+    }
+
+    /// Block the current thread until nobody holds the chunk.
+    /// To be called only from background workers, never from the API methods.
+    pub fn wait_until_unpinned(&self, chunk_id: &ChunkId) {
+        let (counts, released) = &*self.state;
+        let counts = counts.lock().unwrap();
+        let _counts = released
+            .wait_while(counts, |counts| counts.contains_key(chunk_id))
+            .unwrap();
+    }
+
+    /// Block the current thread until nobody holds the chunk or the timeout elapses, returns whether it's unpinned.
+    /// To be called only from background workers, never from the API methods.
+    pub fn wait_until_unpinned_for(&self, chunk_id: &ChunkId, timeout: Duration) -> bool {
+        let (counts, released) = &*self.state;
+        let counts = counts.lock().unwrap();
+        let (counts, _) = released
+            .wait_timeout_while(counts, timeout, |counts| counts.contains_key(chunk_id))
+            .unwrap();
+        !counts.contains_key(chunk_id)
+    }
+
+    /// Announce the removal of the files of the chunk at the deadline to the readers holding it
+    pub(crate) fn deprecate(&self, chunk_id: &ChunkId, deadline: Instant) {
+        self.deprecations.lock().unwrap().insert(*chunk_id, deadline);
+    }
+
+    pub(crate) fn clear_deprecation(&self, chunk_id: &ChunkId) {
+        self.deprecations.lock().unwrap().remove(chunk_id);
+    }
+
+    /// Time the files of the chunk are removed at, `None` unless it's being deleted within a grace
+    pub fn deprecated_until(&self, chunk_id: &ChunkId) -> Option<Instant> {
+        self.deprecations.lock().unwrap().get(chunk_id).copied()
+    }
+
+    fn unpin(&self, chunk_id: &ChunkId) {
+        let (counts, released) = &*self.state;
+        let mut counts = counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(chunk_id) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(chunk_id);
+                released.notify_all();
+            }
+        }
+    }
+}
+
+/// Guard keeping the chunk pinned, the chunk can be deleted once all guards are dropped
+pub struct ChunkPin {
+    pins: ChunkPins,
+    chunk_id: ChunkId,
+}
+
+impl ChunkPin {
+    pub fn chunk_id(&self) -> &ChunkId {
+        &self.chunk_id
+    }
+
+    /// Time the files of the chunk are removed at, whether or not the pin is still held,
+    /// so a long-running reader should checkpoint and drop the pin before
+    pub fn deprecated_until(&self) -> Option<Instant> {
+        self.pins.deprecated_until(&self.chunk_id)
+    }
+}
+
+impl Clone for ChunkPin {
+    fn clone(&self) -> Self {
+        self.pins.pin(&self.chunk_id)
+    }
+}
+
+impl Drop for ChunkPin {
+    fn drop(&mut self) {
+        self.pins.unpin(&self.chunk_id);
+    }
+}
+
+impl PartialEq for ChunkPin {
+    fn eq(&self, other: &Self) -> bool {
+        self.chunk_id == other.chunk_id
+    }
+}
+
+impl fmt::Debug for ChunkPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChunkPin").field("chunk_id", &hex::encode(self.chunk_id)).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn test_pin_and_unpin() {
+        let pins = ChunkPins::default();
+        let pin = pins.pin(&[1u8; 32]);
+        let cloned_pin = pin.clone();
+        assert_eq!(pins.pin_count(&[1u8; 32]), 2);
+
+        drop(pin);
+        assert_eq!(pins.pin_count(&[1u8; 32]), 1);
+        drop(cloned_pin);
+        assert_eq!(pins.pin_count(&[1u8; 32]), 0);
+    }
+
+    #[test]
+    fn test_wait_until_unpinned() {
+        // Arrange
+        let pins = ChunkPins::default();
+        let pin = pins.pin(&[1u8; 32]);
+        let waiting_pins = pins.clone();
+        let waiting = thread::spawn(move || waiting_pins.wait_until_unpinned(&[1u8; 32]));
+
+        // Assert the deletion waits for the reader
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+
+        // Act
+        drop(pin);
+
+        // Assert
+        waiting.join().unwrap();
+        assert_eq!(pins.pin_count(&[1u8; 32]), 0);
+    }
+
+    #[test]
+    fn test_wait_until_unpinned_times_out() {
+        // Arrange
+        let pins = ChunkPins::default();
+        let pin = pins.pin(&[1u8; 32]);
+
+        // Act
+        let unpinned = pins.wait_until_unpinned_for(&[1u8; 32], Duration::from_millis(20));
+
+        // Assert
+        assert!(!unpinned);
+        drop(pin);
+        assert!(pins.wait_until_unpinned_for(&[1u8; 32], Duration::from_millis(20)));
+    }
+}
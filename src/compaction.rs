@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+/// When the catalogue drops the rows of deleted chunks.
+/// The catalogue is compacted once the share of such rows exceeds `max_dead_ratio`,
+/// small catalogues below `min_rows` are never compacted automatically.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompactionConfig {
+    pub max_dead_ratio: f64,
+    pub min_rows: usize,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        CompactionConfig {
+            max_dead_ratio: 0.5,
+            min_rows: 1000,
+        }
+    }
+}
+
+/// Rows of the persisted catalogue
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CatalogueStats {
+    pub live_rows: usize,
+    /// Rows of `Deleted` chunks, kept only until the next compaction
+    pub dead_rows: usize,
+}
+
+impl CatalogueStats {
+    pub fn total_rows(&self) -> usize {
+        self.live_rows + self.dead_rows
+    }
+
+    /// Share of the rows which are dead, 0.0 for an empty catalogue
+    pub fn dead_ratio(&self) -> f64 {
+        if self.total_rows() == 0 { 0.0 } else { self.dead_rows as f64 / self.total_rows() as f64 }
+    }
+
+    pub fn needs_compaction(&self, config: &CompactionConfig) -> bool {
+        self.total_rows() >= config.min_rows && self.dead_ratio() > config.max_dead_ratio
+    }
+}
+
+/// A single compaction of the catalogue
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompactionRun {
+    pub removed_rows: usize,
+    pub remaining_rows: usize,
+    pub duration: Duration,
+}
+
+/// Compactions since the data manager started
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompactionStats {
+    pub runs: u64,
+    pub removed_rows: u64,
+    pub total_duration: Duration,
+    pub last_run: Option<CompactionRun>,
+}
+
+impl CompactionStats {
+    pub(crate) fn record(&mut self, run: &CompactionRun) {
+        self.runs += 1;
+        self.removed_rows += run.removed_rows as u64;
+        self.total_duration += run.duration;
+        self.last_run = Some(run.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_needs_compaction() {
+        let config = CompactionConfig { max_dead_ratio: 0.5, min_rows: 10 };
+        assert!(!CatalogueStats { live_rows: 2, dead_rows: 7 }.needs_compaction(&config));
+        assert!(!CatalogueStats { live_rows: 5, dead_rows: 5 }.needs_compaction(&config));
+        assert!(CatalogueStats { live_rows: 4, dead_rows: 6 }.needs_compaction(&config));
+        assert_eq!(CatalogueStats::default().dead_ratio(), 0.0);
+    }
+
+    #[test]
+    fn test_record_runs() {
+        let mut stats = CompactionStats::default();
+        stats.record(&CompactionRun { removed_rows: 3, remaining_rows: 2, duration: Duration::from_millis(2) });
+        stats.record(&CompactionRun { removed_rows: 1, remaining_rows: 2, duration: Duration::from_millis(1) });
+        assert_eq!((stats.runs, stats.removed_rows, stats.total_duration), (2, 4, Duration::from_millis(3)));
+        assert_eq!(stats.last_run.map(|run| run.removed_rows), Some(1));
+    }
+}
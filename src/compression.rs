@@ -0,0 +1,210 @@
+use std::fmt;
+use std::io::{self, Write};
+
+/// Encoding of the bytes of a file transfer, e.g. the `Content-Encoding` of the response.
+/// The file is written decoded, only the transfer is compressed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ContentEncoding {
+    /// The file as it is
+    #[default]
+    Identity,
+    Gzip,
+    Zstd,
+}
+
+impl ContentEncoding {
+    /// Encoding of a `Content-Encoding` header value, `None` for the encodings which aren't supported
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Some(ContentEncoding::Identity),
+            "gzip" | "x-gzip" => Some(ContentEncoding::Gzip),
+            "zstd" => Some(ContentEncoding::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Token of the encoding in the `Content-Encoding` and `Accept-Encoding` headers
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Identity => "identity",
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Zstd => "zstd",
+        }
+    }
+}
+
+impl fmt::Display for ContentEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Decodes the bytes written to it into the inner writer, as they are received
+pub(crate) enum DecodingWriter<W: Write> {
+    Identity(W),
+    #[cfg(feature = "compression")]
+    Gzip(flate2::write::GzDecoder<W>),
+    #[cfg(feature = "compression")]
+    Zstd(zstd::stream::write::Decoder<'static, W>),
+}
+
+impl<W: Write> DecodingWriter<W> {
+    pub(crate) fn new(encoding: ContentEncoding, writer: W) -> io::Result<Self> {
+        match encoding {
+            ContentEncoding::Identity => Ok(DecodingWriter::Identity(writer)),
+            #[cfg(feature = "compression")]
+            ContentEncoding::Gzip => Ok(DecodingWriter::Gzip(flate2::write::GzDecoder::new(writer))),
+            #[cfg(feature = "compression")]
+            ContentEncoding::Zstd => Ok(DecodingWriter::Zstd(zstd::stream::write::Decoder::new(writer)?)),
+            #[cfg(not(feature = "compression"))]
+            encoding => Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} transfers need the compression feature", encoding))),
+        }
+    }
+
+    /// Write the rest of the decoded bytes to the inner writer
+    pub(crate) fn finish(self) -> io::Result<()> {
+        match self {
+            DecodingWriter::Identity(mut writer) => writer.flush(),
+            #[cfg(feature = "compression")]
+            DecodingWriter::Gzip(decoder) => decoder.finish()?.flush(),
+            #[cfg(feature = "compression")]
+            DecodingWriter::Zstd(mut decoder) => {
+                decoder.flush()?;
+                decoder.into_inner().flush()
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for DecodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            DecodingWriter::Identity(writer) => writer.write(buf),
+            #[cfg(feature = "compression")]
+            DecodingWriter::Gzip(decoder) => decoder.write(buf),
+            #[cfg(feature = "compression")]
+            DecodingWriter::Zstd(decoder) => decoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            DecodingWriter::Identity(writer) => writer.flush(),
+            #[cfg(feature = "compression")]
+            DecodingWriter::Gzip(decoder) => decoder.flush(),
+            #[cfg(feature = "compression")]
+            DecodingWriter::Zstd(decoder) => decoder.flush(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use crate::auth::FileRequest;
+    use crate::data_chunk::DataChunk;
+    use crate::transfer::{ChunkTransfer, RangeSource, ResumableTransfer, PARTIAL_SUFFIX};
+    use crate::test_support;
+    use super::*;
+
+    /// Remote file sent zstd compressed to the requests accepting it, and always gzip compressed when `gzip_only`,
+    /// as by a server keeping the file gzipped
+    struct CompressingSource {
+        content: Vec<u8>,
+        gzip_only: bool,
+        encodings: Mutex<Vec<ContentEncoding>>,
+    }
+
+    impl CompressingSource {
+        fn encoding(&self, request: &FileRequest) -> ContentEncoding {
+            match self.gzip_only {
+                true => ContentEncoding::Gzip,
+                false => request.accept_encoding.first().copied().unwrap_or_default(),
+            }
+        }
+
+        fn encoded(&self, encoding: ContentEncoding) -> Vec<u8> {
+            match encoding {
+                ContentEncoding::Identity => self.content.clone(),
+                ContentEncoding::Gzip => {
+                    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                    encoder.write_all(&self.content).unwrap();
+                    encoder.finish().unwrap()
+                }
+                ContentEncoding::Zstd => zstd::encode_all(&self.content[..], 0).unwrap(),
+            }
+        }
+    }
+
+    impl RangeSource for CompressingSource {
+        fn content_length(&self, url: &str) -> io::Result<u64> {
+            self.request_content_length(&FileRequest::new(url))
+        }
+
+        fn read_range(&self, url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            self.request_range(&FileRequest::new(url), offset, writer)
+        }
+
+        fn request_content_length(&self, request: &FileRequest) -> io::Result<u64> {
+            Ok(self.encoded(self.encoding(request)).len() as u64)
+        }
+
+        fn request_content_encoding(&self, request: &FileRequest) -> io::Result<ContentEncoding> {
+            Ok(self.encoding(request))
+        }
+
+        fn request_range(&self, request: &FileRequest, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            let encoding = self.encoding(request);
+            self.encodings.lock().unwrap().push(encoding);
+            let encoded = self.encoded(encoding);
+            writer.write_all(&encoded[offset as usize..])?;
+            Ok(encoded.len() as u64 - offset)
+        }
+    }
+
+    fn chunk() -> DataChunk {
+        DataChunk { id: [16u8; 32], ..test_support::chunk(16, 0..10) }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(ContentEncoding::parse(" GZIP"), Some(ContentEncoding::Gzip));
+        assert_eq!(ContentEncoding::parse("zstd"), Some(ContentEncoding::Zstd));
+        assert_eq!(ContentEncoding::parse(""), Some(ContentEncoding::Identity));
+        assert_eq!(ContentEncoding::parse("br"), None);
+    }
+
+    #[test]
+    fn test_compressed_transfers_are_decoded() {
+        // Arrange
+        let dir = test_support::temp_dir("compression");
+        let content: Vec<u8> = (0..4096u32).map(|i| (i / 64) as u8).collect();
+        let source = Arc::new(CompressingSource { content: content.clone(), gzip_only: false, encodings: Mutex::new(Vec::new()) });
+        let gzip_source = Arc::new(CompressingSource { content: content.clone(), gzip_only: true, encodings: Mutex::new(Vec::new()) });
+        // a compressed stream can't be resumed halfway, the partial file is downloaded again
+        fs::create_dir_all(dir.join("gzip")).unwrap();
+        fs::write(dir.join("gzip").join(format!("blocks.parquet{}", PARTIAL_SUFFIX)), &content[..100]).unwrap();
+
+        // Act
+        let plain = ResumableTransfer::new(source.clone()).download(&chunk(), &dir.join("plain"));
+        let compressed = ResumableTransfer::new(source.clone())
+            .with_compressed_transfer(chunk().dataset_id)
+            .download(&chunk(), &dir.join("compressed"));
+        let gzipped = ResumableTransfer::new(gzip_source.clone()).download(&chunk(), &dir.join("gzip"));
+
+        // Assert
+        plain.unwrap();
+        compressed.unwrap();
+        gzipped.unwrap();
+        assert_eq!(*source.encodings.lock().unwrap(), vec![ContentEncoding::Identity, ContentEncoding::Zstd]);
+        for name in ["plain", "compressed", "gzip"] {
+            assert_eq!(fs::read(dir.join(name).join("blocks.parquet")).unwrap(), content);
+        }
+        assert!(!Path::new(&dir.join("gzip").join(format!("blocks.parquet{}", PARTIAL_SUFFIX))).exists());
+
+        // cleanup
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
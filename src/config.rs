@@ -0,0 +1,578 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use crate::adaptive_concurrency::AdaptiveConcurrencyConfig;
+use crate::chunk_errors::DEFAULT_ERROR_HISTORY;
+use crate::compaction::CompactionConfig;
+use crate::data_catalogue::LOCAL_CATALOGUE;
+use crate::data_chunk::DatasetId;
+use crate::download_pool::DEFAULT_MAX_CONCURRENT_DOWNLOADS;
+use crate::epoch::EpochLayout;
+use crate::eviction::RetentionPolicy;
+use crate::fair_queue::DownloadSchedulingConfig;
+use crate::free_space::FreeSpaceConfig;
+use crate::local_data_source::LOCAL_DATA_DIR;
+use crate::maintenance::MaintenancePriorities;
+use crate::overlap::{ChunkPreference, OverlapPolicy};
+use crate::permissions::PermissionsConfig;
+use crate::placement::PlacementPolicy;
+use crate::planning::DirectoryLayout;
+#[cfg(feature = "dataframes")]
+use crate::query_cache::QueryCacheConfig;
+use crate::retry::RetryPolicy;
+use crate::slo::SloConfig;
+use crate::throttle::BandwidthLimit;
+use crate::tip::TipFollowingConfig;
+use crate::storage::StorageSamplingConfig;
+use crate::watchdog::WatchdogConfig;
+
+/// Configuration of the `DataManagerImpl`
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataManagerConfig {
+    /// Directory where the data chunks are stored
+    pub data_dir: PathBuf,
+    /// Naming of the dataset directories in the `data_dir`
+    pub layout: DirectoryLayout,
+    /// Parquet file the catalogue is persisted to, its directory must exist
+    pub catalogue_file: PathBuf,
+    /// Number of threads scanning the `data_dir` when the catalogue is loaded on startup
+    pub catalogue_load_parallelism: usize,
+    /// Repairs chunks stuck in `Downloading` or `Deleting`, disabled when `None`
+    pub watchdog: Option<WatchdogConfig>,
+    /// Grouping of blocks into epochs, needed by the epoch operations
+    pub epochs: Option<EpochLayout>,
+    /// How many chunks of a dataset are kept, datasets without a policy are kept whole
+    pub retention: HashMap<DatasetId, RetentionPolicy>,
+    /// Latency targets reported by `slo_report`
+    pub slo: SloConfig,
+    /// Cache of `scan_blocks` results, disabled when `None`
+    #[cfg(feature = "dataframes")]
+    pub query_cache: Option<QueryCacheConfig>,
+    /// Automatic compaction of the catalogue, disabled when `None`
+    pub compaction: Option<CompactionConfig>,
+    /// Number of errors kept per chunk, see `get_chunk_info`
+    pub error_history: usize,
+    /// What `download_chunk` does with chunks overlapping chunks being downloaded
+    pub overlap_policy: OverlapPolicy,
+    /// Which chunk `find_chunk` returns when overlapping ready chunks hold the block
+    pub chunk_preference: ChunkPreference,
+    /// Sampling correcting the recorded sizes of the chunks, disabled when `None`
+    pub storage_sampling: Option<StorageSamplingConfig>,
+    /// Weighted sharing of a limited number of download slots between datasets, unlimited downloads when `None`
+    pub download_scheduling: Option<DownloadSchedulingConfig>,
+    /// Datasets whose new chunks are downloaded as they are published, needs a manifest source
+    pub tip_following: HashMap<DatasetId, TipFollowingConfig>,
+    /// Order of the datasets in the maintenance jobs, all datasets are equal by default
+    pub maintenance_priorities: MaintenancePriorities,
+    /// Retries of the failed downloads, a chunk is marked `Failed` on its first failed download when `None`
+    pub download_retry: Option<RetryPolicy>,
+    /// Bandwidth shared by all the downloads, e.g. to leave room for a query engine on the same network, unlimited when `None`
+    pub download_bandwidth: Option<BandwidthLimit>,
+    /// Chunks downloaded at once, the downloads requested over the limit wait in the order they were requested
+    pub max_concurrent_downloads: usize,
+    /// Adjusts the concurrency of the downloads and the deletions within bounds as the host gets saturated,
+    /// starting from `max_concurrent_downloads`. The concurrency is fixed and the deletions unlimited when `None`.
+    pub adaptive_concurrency: Option<AdaptiveConcurrencyConfig>,
+    /// How long a deletion waits for the readers still holding the chunk, which are notified with `on_deprecate`,
+    /// before its files are removed from under them. Deletions wait until the chunk is unpinned when `None`.
+    pub deletion_grace: Option<Duration>,
+    /// Failure domains of the volumes and the datasets whose replicas must not share one, see `placement_violations`
+    pub placement: PlacementPolicy,
+    /// Check of the free space of the data directory against the declared size of a chunk before its download starts,
+    /// disabled when `None`
+    pub free_space: Option<FreeSpaceConfig>,
+    /// Modes and ownership given to the chunks as they're downloaded or registered, left as they are when `None`.
+    /// Whether the data manager can delete the chunk files is checked either way.
+    pub permissions: Option<PermissionsConfig>,
+    /// Store identical files of different chunks once, in a `ContentStore` in the data directory linked from the chunks
+    pub deduplicated_files: bool,
+}
+
+impl Default for DataManagerConfig {
+    fn default() -> Self {
+        DataManagerConfig {
+            data_dir: PathBuf::from(LOCAL_DATA_DIR),
+            layout: DirectoryLayout::default(),
+            catalogue_file: PathBuf::from(LOCAL_CATALOGUE),
+            catalogue_load_parallelism: default_parallelism(),
+            watchdog: Some(WatchdogConfig::default()),
+            epochs: None,
+            retention: HashMap::new(),
+            slo: SloConfig::default(),
+            #[cfg(feature = "dataframes")]
+            query_cache: None,
+            compaction: Some(CompactionConfig::default()),
+            error_history: DEFAULT_ERROR_HISTORY,
+            overlap_policy: OverlapPolicy::default(),
+            chunk_preference: ChunkPreference::default(),
+            storage_sampling: Some(StorageSamplingConfig::default()),
+            download_scheduling: None,
+            tip_following: HashMap::new(),
+            maintenance_priorities: MaintenancePriorities::default(),
+            download_retry: None,
+            download_bandwidth: None,
+            max_concurrent_downloads: DEFAULT_MAX_CONCURRENT_DOWNLOADS,
+            adaptive_concurrency: None,
+            deletion_grace: None,
+            placement: PlacementPolicy::default(),
+            free_space: Some(FreeSpaceConfig::default()),
+            permissions: Some(PermissionsConfig::default()),
+            deduplicated_files: false,
+        }
+    }
+}
+
+impl DataManagerConfig {
+    pub fn new(data_dir: PathBuf) -> Self {
+        DataManagerConfig {
+            data_dir,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_layout(mut self, layout: DirectoryLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn with_catalogue_file(mut self, catalogue_file: PathBuf) -> Self {
+        self.catalogue_file = catalogue_file;
+        self
+    }
+
+    pub fn with_catalogue_load_parallelism(mut self, parallelism: usize) -> Self {
+        // at least one thread is always needed to scan the directory
+        self.catalogue_load_parallelism = parallelism.max(1);
+        self
+    }
+
+    pub fn with_watchdog(mut self, watchdog: Option<WatchdogConfig>) -> Self {
+        self.watchdog = watchdog;
+        self
+    }
+
+    pub fn with_epochs(mut self, epochs: EpochLayout) -> Self {
+        self.epochs = Some(epochs);
+        self
+    }
+
+    pub fn with_retention(mut self, dataset_id: DatasetId, policy: RetentionPolicy) -> Self {
+        self.retention.insert(dataset_id, policy);
+        self
+    }
+
+    #[cfg(feature = "dataframes")]
+    pub fn with_query_cache(mut self, query_cache: QueryCacheConfig) -> Self {
+        self.query_cache = Some(query_cache);
+        self
+    }
+
+    /// Compact the catalogue at another threshold, `None` disables the automatic compaction
+    pub fn with_compaction(mut self, compaction: Option<CompactionConfig>) -> Self {
+        self.compaction = compaction;
+        self
+    }
+
+    pub fn with_error_history(mut self, error_history: usize) -> Self {
+        self.error_history = error_history;
+        self
+    }
+
+    pub fn with_overlap_policy(mut self, overlap_policy: OverlapPolicy) -> Self {
+        self.overlap_policy = overlap_policy;
+        self
+    }
+
+    pub fn with_chunk_preference(mut self, chunk_preference: ChunkPreference) -> Self {
+        self.chunk_preference = chunk_preference;
+        self
+    }
+
+    pub fn with_storage_sampling(mut self, storage_sampling: Option<StorageSamplingConfig>) -> Self {
+        self.storage_sampling = storage_sampling;
+        self
+    }
+
+    pub fn with_download_scheduling(mut self, download_scheduling: DownloadSchedulingConfig) -> Self {
+        self.download_scheduling = Some(download_scheduling);
+        self
+    }
+
+    pub fn with_tip_following(mut self, dataset_id: DatasetId, tip_following: TipFollowingConfig) -> Self {
+        self.tip_following.insert(dataset_id, tip_following);
+        self
+    }
+
+    /// Maintain the chunks of the dataset before the datasets with a lower priority
+    pub fn with_maintenance_priority(mut self, dataset_id: DatasetId, priority: u32) -> Self {
+        self.maintenance_priorities.priorities.insert(dataset_id, priority);
+        self
+    }
+
+    /// Retry the failed downloads, the chunk is marked `Failed` only once the attempts are exhausted
+    pub fn with_download_retry(mut self, download_retry: RetryPolicy) -> Self {
+        self.download_retry = Some(download_retry);
+        self
+    }
+
+    pub fn with_download_bandwidth(mut self, download_bandwidth: BandwidthLimit) -> Self {
+        self.download_bandwidth = Some(download_bandwidth);
+        self
+    }
+
+    pub fn with_max_concurrent_downloads(mut self, max_concurrent_downloads: usize) -> Self {
+        self.max_concurrent_downloads = max_concurrent_downloads;
+        self
+    }
+
+    /// Back off when the query engine on the same host saturates the disk or the network
+    pub fn with_adaptive_concurrency(mut self, adaptive_concurrency: AdaptiveConcurrencyConfig) -> Self {
+        self.adaptive_concurrency = Some(adaptive_concurrency);
+        self
+    }
+
+    /// Let the readers of a chunk being deleted checkpoint within the grace, rather than keeping it on disk until they finish
+    pub fn with_deletion_grace(mut self, deletion_grace: Duration) -> Self {
+        self.deletion_grace = Some(deletion_grace);
+        self
+    }
+
+    /// Tag the volume, the data directory or one the chunks are relocated to, with its failure domain, e.g. its disk or rack
+    pub fn with_failure_domain(mut self, volume: PathBuf, domain: impl Into<String>) -> Self {
+        self.placement.failure_domains.insert(volume, domain.into());
+        self
+    }
+
+    /// Keep the replicas of the dataset, its ready chunks sharing blocks, in different failure domains
+    pub fn with_domain_separation(mut self, dataset_id: DatasetId) -> Self {
+        self.placement.separated_datasets.insert(dataset_id);
+        self
+    }
+
+    pub fn with_free_space(mut self, free_space: Option<FreeSpaceConfig>) -> Self {
+        self.free_space = free_space;
+        self
+    }
+
+    pub fn with_permissions(mut self, permissions: Option<PermissionsConfig>) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    pub fn with_deduplicated_files(mut self, deduplicated_files: bool) -> Self {
+        self.deduplicated_files = deduplicated_files;
+        self
+    }
+
+    /// Check the whole configuration and report all the problems at once
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut diagnostics = Vec::new();
+
+        match fs::metadata(&self.data_dir) {
+            Ok(metadata) if !metadata.is_dir() => diagnostics.push(ConfigDiagnostic::new(
+                "data_dir",
+                format!("{} is not a directory", self.data_dir.display()),
+            )),
+            Ok(metadata) if metadata.permissions().readonly() => diagnostics.push(ConfigDiagnostic::new(
+                "data_dir",
+                format!("{} is read-only, chunks can't be downloaded into it", self.data_dir.display()),
+            )),
+            Ok(_) => {}
+            Err(error) => diagnostics.push(ConfigDiagnostic::new(
+                "data_dir",
+                format!("{} is not accessible: {}", self.data_dir.display(), error),
+            )),
+        }
+
+        // a bare file name is in the working directory
+        let catalogue_dir = self.catalogue_file.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        if !catalogue_dir.is_dir() {
+            diagnostics.push(ConfigDiagnostic::new(
+                "catalogue_file",
+                format!("{} is not a directory", catalogue_dir.display()),
+            ));
+        }
+
+        if self.catalogue_load_parallelism == 0 {
+            diagnostics.push(ConfigDiagnostic::new(
+                "catalogue_load_parallelism",
+                "must be at least 1".to_string(),
+            ));
+        }
+
+        if let Some(watchdog) = &self.watchdog {
+            if watchdog.check_interval.is_zero() {
+                diagnostics.push(ConfigDiagnostic::new(
+                    "watchdog.check_interval",
+                    "must be longer than zero".to_string(),
+                ));
+            }
+        }
+
+        for (field, target) in [("slo.find_chunk.objective", self.slo.find_chunk), ("slo.chunk_ready.objective", self.slo.chunk_ready)] {
+            if !(target.objective > 0.0 && target.objective <= 1.0) {
+                diagnostics.push(ConfigDiagnostic::new(field, format!("must be within (0, 1], got {}", target.objective)));
+            }
+        }
+        if self.slo.window.is_zero() {
+            diagnostics.push(ConfigDiagnostic::new("slo.window", "must be longer than zero".to_string()));
+        }
+
+        if let Some(storage_sampling) = &self.storage_sampling {
+            if storage_sampling.interval.is_zero() {
+                diagnostics.push(ConfigDiagnostic::new(
+                    "storage_sampling.interval",
+                    "must be longer than zero".to_string(),
+                ));
+            }
+        }
+
+        if let Some(download_scheduling) = &self.download_scheduling {
+            if download_scheduling.slots == 0 {
+                diagnostics.push(ConfigDiagnostic::new("download_scheduling.slots", "must be at least 1".to_string()));
+            }
+            if download_scheduling.default_weight == 0 || download_scheduling.weights.values().any(|weight| *weight == 0) {
+                diagnostics.push(ConfigDiagnostic::new("download_scheduling.weights", "must be at least 1".to_string()));
+            }
+        }
+
+        if let Some(download_retry) = &self.download_retry {
+            if download_retry.max_attempts == 0 {
+                diagnostics.push(ConfigDiagnostic::new("download_retry.max_attempts", "must be at least 1".to_string()));
+            }
+            if download_retry.multiplier.is_nan() || download_retry.multiplier < 1.0 {
+                diagnostics.push(ConfigDiagnostic::new(
+                    "download_retry.multiplier",
+                    format!("must be at least 1, got {}", download_retry.multiplier),
+                ));
+            }
+            if !(download_retry.jitter >= 0.0 && download_retry.jitter <= 1.0) {
+                diagnostics.push(ConfigDiagnostic::new(
+                    "download_retry.jitter",
+                    format!("must be within [0, 1], got {}", download_retry.jitter),
+                ));
+            }
+        }
+
+        if let Some(download_bandwidth) = &self.download_bandwidth {
+            if download_bandwidth.bytes_per_second == 0 {
+                diagnostics.push(ConfigDiagnostic::new("download_bandwidth.bytes_per_second", "must be at least 1".to_string()));
+            }
+            if download_bandwidth.burst_bytes == 0 {
+                diagnostics.push(ConfigDiagnostic::new("download_bandwidth.burst_bytes", "must be at least 1".to_string()));
+            }
+        }
+
+        if self.max_concurrent_downloads == 0 {
+            diagnostics.push(ConfigDiagnostic::new("max_concurrent_downloads", "must be at least 1".to_string()));
+        }
+
+        if let Some(adaptive_concurrency) = &self.adaptive_concurrency {
+            if adaptive_concurrency.min_concurrency == 0 {
+                diagnostics.push(ConfigDiagnostic::new("adaptive_concurrency.min_concurrency", "must be at least 1".to_string()));
+            }
+            if adaptive_concurrency.max_concurrency < adaptive_concurrency.min_concurrency {
+                diagnostics.push(ConfigDiagnostic::new(
+                    "adaptive_concurrency.max_concurrency",
+                    format!("must be at least min_concurrency {}", adaptive_concurrency.min_concurrency),
+                ));
+            }
+            if adaptive_concurrency.interval.is_zero() {
+                diagnostics.push(ConfigDiagnostic::new("adaptive_concurrency.interval", "must be longer than zero".to_string()));
+            }
+        }
+
+        if self.deletion_grace.is_some_and(|deletion_grace| deletion_grace.is_zero()) {
+            diagnostics.push(ConfigDiagnostic::new("deletion_grace", "must be longer than zero".to_string()));
+        }
+
+        if self.tip_following.values().any(|tip_following| tip_following.poll_interval.is_zero()) {
+            diagnostics.push(ConfigDiagnostic::new("tip_following.poll_interval", "must be longer than zero".to_string()));
+        }
+
+        if self.free_space.as_ref().is_some_and(|free_space| free_space.recheck_interval.is_zero()) {
+            diagnostics.push(ConfigDiagnostic::new("free_space.recheck_interval", "must be longer than zero".to_string()));
+        }
+
+        if let Some(permissions) = &self.permissions {
+            for (field, mode) in [("permissions.file_mode", permissions.file_mode), ("permissions.dir_mode", permissions.dir_mode)] {
+                if mode > 0o7777 {
+                    diagnostics.push(ConfigDiagnostic::new(field, format!("must be a mode within 0o7777, got {:#o}", mode)));
+                }
+            }
+            // the data manager must still be able to list, replace and delete what it downloads
+            if permissions.dir_mode & 0o700 != 0o700 {
+                diagnostics.push(ConfigDiagnostic::new(
+                    "permissions.dir_mode",
+                    format!("must give the owner read, write and search permissions, got {:#o}", permissions.dir_mode),
+                ));
+            }
+        }
+
+        if let Some(compaction) = &self.compaction {
+            if !(compaction.max_dead_ratio >= 0.0 && compaction.max_dead_ratio < 1.0) {
+                diagnostics.push(ConfigDiagnostic::new(
+                    "compaction.max_dead_ratio",
+                    format!("must be within [0, 1), got {}", compaction.max_dead_ratio),
+                ));
+            }
+        }
+
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError { diagnostics })
+        }
+    }
+}
+
+/// A problem with a single option of the configuration
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigDiagnostic {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl ConfigDiagnostic {
+    pub fn new(field: &'static str, message: String) -> Self {
+        ConfigDiagnostic { field, message }
+    }
+}
+
+impl fmt::Display for ConfigDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// All the problems found in the configuration
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigError {
+    pub diagnostics: Vec<ConfigDiagnostic>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid data manager configuration ({} problems):", self.diagnostics.len())?;
+        for diagnostic in self.diagnostics.iter() {
+            write!(f, "\n  - {}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn default_parallelism() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config() {
+        let config = DataManagerConfig::default();
+        assert_eq!(config.data_dir, PathBuf::from(LOCAL_DATA_DIR));
+        assert!(config.catalogue_load_parallelism >= 1);
+    }
+
+    #[test]
+    fn test_parallelism_is_at_least_one() {
+        let config = DataManagerConfig::new(PathBuf::from(LOCAL_DATA_DIR)).with_catalogue_load_parallelism(0);
+        assert_eq!(config.catalogue_load_parallelism, 1);
+    }
+
+    #[test]
+    fn test_valid_config() {
+        assert_eq!(DataManagerConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_all_problems_are_reported() {
+        // Arrange
+        let mut config = DataManagerConfig::new(PathBuf::from("./missing_data_dir"));
+        config.catalogue_load_parallelism = 0;
+
+        // Act
+        let error = config.validate().unwrap_err();
+
+        // Assert
+        assert_eq!(error.diagnostics.len(), 2);
+        assert_eq!(error.diagnostics[0].field, "data_dir");
+        assert_eq!(error.diagnostics[1].field, "catalogue_load_parallelism");
+        assert!(error.to_string().starts_with("invalid data manager configuration (2 problems):\n  - data_dir: ./missing_data_dir is not accessible"));
+    }
+
+    #[test]
+    fn test_watchdog_check_interval_must_not_be_zero() {
+        let config = DataManagerConfig::default().with_watchdog(Some(WatchdogConfig {
+            check_interval: std::time::Duration::ZERO,
+            ..WatchdogConfig::default()
+        }));
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.diagnostics[0].field, "watchdog.check_interval");
+    }
+
+    #[test]
+    fn test_slo_objective_must_be_a_ratio() {
+        let mut config = DataManagerConfig::default();
+        config.slo.chunk_ready.objective = 99.0;
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.diagnostics, vec![ConfigDiagnostic::new("slo.chunk_ready.objective", "must be within (0, 1], got 99".to_string())]);
+    }
+
+    #[test]
+    fn test_compaction_ratio_must_be_below_one() {
+        let config = DataManagerConfig::default().with_compaction(Some(CompactionConfig { max_dead_ratio: 1.0, min_rows: 0 }));
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.diagnostics, vec![ConfigDiagnostic::new("compaction.max_dead_ratio", "must be within [0, 1), got 1".to_string())]);
+    }
+
+    #[test]
+    fn test_download_scheduling_weights_must_be_positive() {
+        let config = DataManagerConfig::default().with_download_scheduling(DownloadSchedulingConfig::default().with_weight([1u8; 32], 0));
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.diagnostics, vec![ConfigDiagnostic::new("download_scheduling.weights", "must be at least 1".to_string())]);
+    }
+
+    #[test]
+    fn test_download_retry_needs_an_attempt() {
+        let config = DataManagerConfig::default().with_download_retry(RetryPolicy { max_attempts: 0, ..RetryPolicy::default() });
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.diagnostics, vec![ConfigDiagnostic::new("download_retry.max_attempts", "must be at least 1".to_string())]);
+    }
+
+    #[test]
+    fn test_deletion_grace_must_be_positive() {
+        let config = DataManagerConfig::default().with_deletion_grace(Duration::ZERO);
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.diagnostics, vec![ConfigDiagnostic::new("deletion_grace", "must be longer than zero".to_string())]);
+    }
+
+    #[test]
+    fn test_max_concurrent_downloads_must_be_positive() {
+        let config = DataManagerConfig::default().with_max_concurrent_downloads(0);
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.diagnostics, vec![ConfigDiagnostic::new("max_concurrent_downloads", "must be at least 1".to_string())]);
+    }
+
+    #[test]
+    fn test_adaptive_concurrency_bounds_must_be_ordered() {
+        let config = DataManagerConfig::default()
+            .with_adaptive_concurrency(AdaptiveConcurrencyConfig { min_concurrency: 4, max_concurrency: 2, ..AdaptiveConcurrencyConfig::default() });
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.diagnostics, vec![ConfigDiagnostic::new("adaptive_concurrency.max_concurrency", "must be at least min_concurrency 4".to_string())]);
+    }
+
+    #[test]
+    fn test_data_dir_must_be_directory() {
+        let config = DataManagerConfig::new(PathBuf::from("./Cargo.toml"));
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.diagnostics, vec![ConfigDiagnostic::new("data_dir", "./Cargo.toml is not a directory".to_string())]);
+    }
+}
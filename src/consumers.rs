@@ -0,0 +1,127 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// Consumer the reads made outside of any `scope` are attributed to
+pub const ANONYMOUS_CONSUMER: &str = "anonymous";
+
+/// Reads served to a consumer since the data manager started or the usage was last taken
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConsumerUsage {
+    /// Calls of `find_chunk`, `acquire`, `find_chunk_by_time`, `scan_blocks`, `mmap_chunk_file` and `stream_chunk_rows`
+    pub queries: u64,
+    /// Bytes of the mapped and streamed files and of the scanned frames. Readers of the chunk paths
+    /// read the files themselves, their reads are counted as queries only.
+    pub bytes_served: u64,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// The consumer the reads of the current thread are attributed to
+pub fn current() -> Option<String> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Run `work` on behalf of the consumer, e.g. the tenant or the service of the request being served,
+/// so the reads it makes are attributed to it in `DataManagerImpl::consumer_usage`
+pub fn scope<T>(consumer: Option<String>, work: impl FnOnce() -> T) -> T {
+    let previous = CURRENT.with(|current| current.replace(consumer));
+    // restore the outer consumer even when the work panics
+    let _restore = Restore(previous);
+    work()
+}
+
+/// Attribute the following reads of the current thread to the consumer, for callers which can't wrap them in a `scope`
+pub(crate) fn set_current(consumer: Option<String>) {
+    CURRENT.with(|current| *current.borrow_mut() = consumer);
+}
+
+struct Restore(Option<String>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        set_current(self.0.take());
+    }
+}
+
+/// Usage of the reads per consumer, for fairness decisions between tenants and for finding noisy neighbours
+#[derive(Clone, Default)]
+pub struct ConsumerStats {
+    usage: Arc<Mutex<HashMap<String, ConsumerUsage>>>,
+}
+
+impl ConsumerStats {
+    /// Count a read of the current consumer
+    pub(crate) fn record(&self, bytes_served: u64) {
+        let consumer = current().unwrap_or_else(|| ANONYMOUS_CONSUMER.to_string());
+        let mut usage = self.usage.lock().unwrap();
+        let consumer_usage = usage.entry(consumer).or_default();
+        consumer_usage.queries += 1;
+        consumer_usage.bytes_served += bytes_served;
+    }
+
+    pub fn usage(&self) -> BTreeMap<String, ConsumerUsage> {
+        self.usage.lock().unwrap().iter().map(|(consumer, usage)| (consumer.clone(), *usage)).collect()
+    }
+
+    /// The usage so far, counting starts over, e.g. for reporting per interval
+    pub fn take(&self) -> BTreeMap<String, ConsumerUsage> {
+        self.usage.lock().unwrap().drain().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+    use crate::test_support::{self, chunk};
+    use super::*;
+
+    #[test]
+    fn test_nested_scopes() {
+        scope(Some("outer".to_string()), || {
+            scope(Some("inner".to_string()), || assert_eq!(current(), Some("inner".to_string())));
+            assert_eq!(current(), Some("outer".to_string()));
+        });
+        assert_eq!(current(), None);
+    }
+
+    #[test]
+    fn test_reads_are_attributed_to_their_consumers() {
+        // Arrange
+        let dir = test_support::temp_dir("consumers");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let data_manager = test_support::manager_builder(&dir).build();
+        let chunk = chunk(15, 0..10);
+        data_manager.download_chunk_with_handle(chunk.clone()).wait().unwrap();
+        // the other status listeners may still be saving the pending downloads
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(100));
+        });
+
+        // Act
+        scope(Some("indexer".to_string()), || {
+            data_manager.find_chunk_path(chunk.dataset_id, 5);
+            data_manager.mmap_chunk_file(chunk.id, "blocks.parquet").unwrap();
+            data_manager.mmap_chunk_file(chunk.id, "blocks.parquet").unwrap();
+        });
+        scope(Some("dashboard".to_string()), || data_manager.find_chunk_path(chunk.dataset_id, 50));
+        data_manager.find_chunk_path(chunk.dataset_id, 5);
+        let usage = data_manager.consumer_usage();
+        let taken = data_manager.consumer_stats.take();
+
+        // Assert
+        assert_eq!(usage["indexer"], ConsumerUsage { queries: 3, bytes_served: 12 });
+        assert_eq!(usage["dashboard"], ConsumerUsage { queries: 1, bytes_served: 0 });
+        assert_eq!(usage[ANONYMOUS_CONSUMER], ConsumerUsage { queries: 1, bytes_served: 0 });
+        assert_eq!(taken, usage);
+        assert!(data_manager.consumer_usage().is_empty());
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
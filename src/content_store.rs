@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use crate::checksum::FileChecksums;
+use crate::data_chunk::{ChunkId, DataChunk};
+
+/// Directory of the content store in the data directory, the scan of the data directory skips it
+pub const CONTENT_STORE_DIR: &str = ".content";
+
+/// Files of the chunks stored once per content, under their sha256, and hard linked into the chunk directories,
+/// e.g. for datasets republishing identical files across overlapping chunks.
+/// The link count of a stored file is its reference count: a stored file linked from no chunk directory is removed
+/// once the last chunk holding it is deleted. The store must be on the file system of the chunk directories,
+/// and the chunk files must never be modified in place, as every chunk holding the same content would see it.
+#[derive(Clone)]
+pub struct ContentStore {
+    dir: PathBuf,
+    /// Digests of the files stored for every chunk since the start, under the lock the store is changed with
+    digests: Arc<Mutex<HashMap<ChunkId, Vec<String>>>>,
+}
+
+impl ContentStore {
+    pub fn new(dir: PathBuf) -> Self {
+        ContentStore { dir, digests: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    pub fn in_data_dir(data_dir: &Path) -> Self {
+        Self::new(data_dir.join(CONTENT_STORE_DIR))
+    }
+
+    /// Path of the stored file with the sha256 digest given as hex
+    pub fn path(&self, digest: &str) -> PathBuf {
+        let digest = digest.to_ascii_lowercase();
+        self.dir.join(&digest[..2.min(digest.len())]).join(&digest)
+    }
+
+    pub fn contains(&self, digest: &str) -> bool {
+        self.path(digest).is_file()
+    }
+
+    /// Link the files of the chunk, whose checksums are known and whose content is stored already, into the directory.
+    /// Returns the chunk without the linked files, which are left to be transferred.
+    pub fn link_known_files(&self, chunk: &DataChunk, dir: &Path, checksums: &FileChecksums) -> io::Result<DataChunk> {
+        let mut remaining = chunk.clone();
+        let _digests = self.digests.lock().unwrap();
+        for (file_name, digest) in checksums.iter() {
+            if !chunk.files.contains_key(file_name) || !self.contains(digest) {
+                continue;
+            }
+            link(&self.path(digest), &dir.join(file_name))?;
+            remaining.files.remove(file_name);
+            remaining.mirrors.remove(file_name);
+            remaining.file_sizes.remove(file_name);
+            // the declared total no longer applies, it's checked on the whole chunk once the files are complete
+            remaining.size = None;
+        }
+        Ok(remaining)
+    }
+
+    /// Put the files of the chunk in the directory into the store, replacing the ones with a content stored already
+    /// by links to it, so identical files take their space once
+    pub fn store_files(&self, chunk: &DataChunk, dir: &Path) -> io::Result<()> {
+        let file_digests = file_digests(chunk, dir)?;
+        let mut digests = self.digests.lock().unwrap();
+        for (file, digest) in file_digests {
+            let stored = self.path(&digest);
+            if let Some(digest_dir) = stored.parent() {
+                fs::create_dir_all(digest_dir)?;
+            }
+            match fs::hard_link(&file, &stored) {
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                    if !is_same_file(&file, &stored)? {
+                        link(&stored, &file)?;
+                    }
+                }
+                result => result?,
+            }
+            let chunk_digests = digests.entry(chunk.id).or_default();
+            if !chunk_digests.contains(&digest) {
+                chunk_digests.push(digest);
+            }
+        }
+        Ok(())
+    }
+
+    /// Learn the stored files of a chunk stored before the start from the files in its directory,
+    /// called before they're removed, so its `release` finds them
+    pub fn recall_files(&self, chunk: &DataChunk, dir: &Path) -> io::Result<()> {
+        if self.digests.lock().unwrap().contains_key(&chunk.id) {
+            return Ok(());
+        }
+        let file_digests = file_digests(chunk, dir)?;
+        let mut digests = self.digests.lock().unwrap();
+        let chunk_digests = digests.entry(chunk.id).or_default();
+        for (file, digest) in file_digests {
+            if !chunk_digests.contains(&digest) && is_same_file(&file, &self.path(&digest)).unwrap_or(false) {
+                chunk_digests.push(digest);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the stored files of the deleted chunk, which no other chunk links to.
+    /// A chunk neither stored nor recalled since the start has no known stored files, they're left to `collect_garbage`.
+    pub fn release(&self, chunk_id: &ChunkId) -> io::Result<()> {
+        let mut digests = self.digests.lock().unwrap();
+        for digest in digests.remove(chunk_id).unwrap_or_default() {
+            remove_unreferenced(&self.path(&digest))?;
+        }
+        Ok(())
+    }
+
+    /// Remove the stored files no chunk links to, e.g. left by chunks relocated or replaced in place, returns their number
+    pub fn collect_garbage(&self) -> io::Result<usize> {
+        let _digests = self.digests.lock().unwrap();
+        collect(&self.dir)
+    }
+}
+
+/// The sha256 of the files of the chunk in the directory, the missing files are skipped
+fn file_digests(chunk: &DataChunk, dir: &Path) -> io::Result<Vec<(PathBuf, String)>> {
+    let mut file_digests = Vec::new();
+    for file_name in chunk.files.keys() {
+        let file = dir.join(file_name);
+        if file.is_file() {
+            let digest = sha256::digest(fs::read(&file)?);
+            file_digests.push((file, digest));
+        }
+    }
+    Ok(file_digests)
+}
+
+fn collect(store_dir: &Path) -> io::Result<usize> {
+    let mut removed = 0;
+    let Ok(digest_dirs) = fs::read_dir(store_dir) else { return Ok(0) };
+    for digest_dir in digest_dirs {
+        for entry in fs::read_dir(digest_dir?.path())? {
+            if remove_unreferenced(&entry?.path())? {
+                removed += 1;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Replace the file by a link to the stored file at once, so readers see either of them in whole
+fn link(stored: &Path, file: &Path) -> io::Result<()> {
+    let link = PathBuf::from(format!("{}.link", file.display()));
+    let _ = fs::remove_file(&link);
+    fs::hard_link(stored, &link)?;
+    fs::rename(&link, file)
+}
+
+/// Returns whether the stored file was removed
+#[cfg(unix)]
+fn remove_unreferenced(stored: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    match fs::metadata(stored) {
+        Ok(metadata) if metadata.nlink() <= 1 => fs::remove_file(stored).map(|_| true),
+        Ok(_) => Ok(false),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+/// Link counts are only read on unix, elsewhere the stored files stay
+#[cfg(not(unix))]
+fn remove_unreferenced(_stored: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(unix)]
+fn is_same_file(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (a, b) = (fs::metadata(a)?, fs::metadata(b)?);
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_a: &Path, _b: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::ops::Range;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use crate::config::DataManagerConfig;
+    use crate::data_catalogue::ChunkStatus;
+    use crate::data_manager::DataManager;
+    use crate::transfer::ChunkTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// Remote storage serving the same content for every chunk, counting the files transferred
+    #[derive(Default)]
+    struct RepublishingTransfer {
+        files: AtomicUsize,
+    }
+
+    impl ChunkTransfer for RepublishingTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::create_dir_all(chunk_dir)?;
+            for file_name in chunk.files.keys() {
+                self.files.fetch_add(1, Ordering::SeqCst);
+                fs::write(chunk_dir.join(file_name), file_name.as_bytes())?;
+            }
+            Ok(())
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    fn chunk(block_range: Range<u64>) -> DataChunk {
+        DataChunk::new([23u8; 32], block_range).with_example_files(&["blocks.parquet", "logs.parquet"])
+    }
+
+    fn wait_for(data_manager: &DataManagerImpl, chunk: &DataChunk, status: ChunkStatus) {
+        for _ in 0..100 {
+            if data_manager.get_chunk_info(chunk.id).is_some_and(|info| info.status == status) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("chunk didn't get {}", status);
+    }
+
+    #[test]
+    fn test_identical_files_stored_once() {
+        // Arrange
+        let dir = test_support::temp_dir("content_store");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let transfer = Arc::new(RepublishingTransfer::default());
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")).with_deduplicated_files(true))
+            .chunk_transfer(transfer.clone())
+            .build();
+        let store = ContentStore::in_data_dir(&dir.join("data"));
+        let blocks_digest = sha256::digest(b"blocks.parquet".to_vec());
+        let (first, second, third) = (chunk(0..10), chunk(10..20), chunk(20..30));
+
+        // Act
+        data_manager.download_chunk(first.clone());
+        wait_for(&data_manager, &first, ChunkStatus::Ready);
+        data_manager.download_chunk(second.clone());
+        wait_for(&data_manager, &second, ChunkStatus::Ready);
+        // with the checksum known up front, the stored file isn't transferred at all
+        let checksums = FileChecksums::from([("blocks.parquet".to_string(), blocks_digest.clone())]);
+        data_manager.download_chunk_with_checksums(third.clone(), checksums);
+        wait_for(&data_manager, &third, ChunkStatus::Ready);
+        let transferred = transfer.files.load(Ordering::SeqCst);
+        let shared_path = data_manager.data_source.chunk_path(first.clone()).path.join("blocks.parquet");
+        let shared = is_same_file(&shared_path, &data_manager.data_source.chunk_path(second.clone()).path.join("blocks.parquet")).unwrap();
+        for chunk in [&first, &second] {
+            data_manager.delete_chunk(chunk.id);
+            wait_for(&data_manager, chunk, ChunkStatus::Deleted);
+        }
+        let kept_for_third = store.contains(&blocks_digest);
+        data_manager.delete_chunk(third.id);
+        wait_for(&data_manager, &third, ChunkStatus::Deleted);
+
+        // Assert
+        assert_eq!(transferred, 5);
+        assert!(shared);
+        assert!(kept_for_third);
+        assert!(!store.contains(&blocks_digest));
+        assert_eq!(store.collect_garbage().unwrap(), 0);
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_files_stored_before_a_restart_are_released() {
+        // Arrange
+        let dir = test_support::temp_dir("content_store_restart");
+        let chunk_dir = dir.join("chunk");
+        fs::create_dir_all(&chunk_dir).unwrap();
+        let chunk = chunk(0..10);
+        for file_name in chunk.files.keys() {
+            fs::write(chunk_dir.join(file_name), file_name.as_bytes()).unwrap();
+        }
+        let store = ContentStore::new(dir.join("store"));
+        store.store_files(&chunk, &chunk_dir).unwrap();
+        let orphan = sha256::digest(b"orphan".to_vec());
+        fs::create_dir_all(store.path(&orphan).parent().unwrap()).unwrap();
+        fs::write(store.path(&orphan), b"orphan").unwrap();
+        drop(store);
+        let restarted = ContentStore::new(dir.join("store"));
+
+        // Act
+        restarted.recall_files(&chunk, &chunk_dir).unwrap();
+        fs::remove_dir_all(&chunk_dir).unwrap();
+        restarted.release(&chunk.id).unwrap();
+
+        // Assert
+        assert!(!restarted.contains(&sha256::digest(b"blocks.parquet".to_vec())));
+        assert!(!restarted.contains(&sha256::digest(b"logs.parquet".to_vec())));
+        // the release doesn't collect the whole store
+        assert!(restarted.contains(&orphan));
+
+        // cleanup
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Id tying together everything done on behalf of a single request,
+/// so the journey of a chunk can be traced across the scheduler, the manager and the storage logs
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CorrelationId(String);
+
+impl CorrelationId {
+    pub fn new(id: impl Into<String>) -> Self {
+        CorrelationId(id.into())
+    }
+
+    /// A new id unique within the process, for callers which don't get one from upstream
+    pub fn generate() -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let started_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_micros()).unwrap_or_default();
+        CorrelationId(format!("{:x}-{:x}", started_at, COUNTER.fetch_add(1, Ordering::Relaxed)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<CorrelationId>> = const { RefCell::new(None) };
+}
+
+/// The correlation id of the work running in the current thread
+pub fn current() -> Option<CorrelationId> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Run `work` with the correlation id.
+/// Background tasks spawned by the data manager inside the scope carry the id with them,
+/// so it's available to lifecycle hooks and status listeners through `current()`.
+pub fn scope<T>(correlation_id: Option<CorrelationId>, work: impl FnOnce() -> T) -> T {
+    let previous = CURRENT.with(|current| current.replace(correlation_id));
+    // restore the outer id even when the work panics
+    let _restore = Restore(previous);
+    work()
+}
+
+struct Restore(Option<CorrelationId>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+
+    #[test]
+    fn test_nested_scopes() {
+        scope(Some(CorrelationId::new("outer")), || {
+            scope(Some(CorrelationId::new("inner")), || {
+                assert_eq!(current(), Some(CorrelationId::new("inner")));
+            });
+            assert_eq!(current(), Some(CorrelationId::new("outer")));
+            scope(None, || assert_eq!(current(), None));
+        });
+        assert_eq!(current(), None);
+    }
+
+    #[test]
+    fn test_scope_is_per_thread() {
+        scope(Some(CorrelationId::new("outer")), || {
+            let in_other_thread = thread::spawn(current).join().unwrap();
+            assert_eq!(in_other_thread, None);
+        });
+    }
+
+    #[test]
+    fn test_generated_ids_are_unique() {
+        assert_ne!(CorrelationId::generate(), CorrelationId::generate());
+    }
+}
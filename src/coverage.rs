@@ -0,0 +1,206 @@
+use std::fmt;
+use std::ops::Range;
+use serde_json::json;
+use crate::data_catalogue::{ChunkInfo, ChunkStatus};
+use crate::data_chunk::{DataChunk, DatasetId};
+
+const SVG_WIDTH: f64 = 1000.0;
+const SVG_HEIGHT: u32 = 24;
+
+/// State of a span of blocks along the chain
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CoverageState {
+    Ready,
+    Downloading,
+    Deleting,
+    Failed,
+    /// No chunk holds the blocks
+    Missing,
+}
+
+impl CoverageState {
+    fn color(&self) -> &'static str {
+        match self {
+            CoverageState::Ready => "#2e7d32",
+            CoverageState::Downloading => "#1565c0",
+            CoverageState::Deleting => "#ef6c00",
+            CoverageState::Failed => "#c62828",
+            CoverageState::Missing => "#bdbdbd",
+        }
+    }
+}
+
+impl fmt::Display for CoverageState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoverageSegment {
+    pub block_range: Range<u64>,
+    pub state: CoverageState,
+}
+
+/// Map of the blocks of a dataset from its first to its last known block
+#[derive(Clone, Debug, PartialEq)]
+pub struct Coverage {
+    pub dataset_id: DatasetId,
+    pub segments: Vec<CoverageSegment>,
+}
+
+impl Coverage {
+    /// Coverage of a dataset, given the chunks currently in the catalogue.
+    /// Where chunks overlap, the blocks are attributed to the chunk starting first.
+    pub fn new<'a>(dataset_id: DatasetId, catalogue_chunks: impl IntoIterator<Item = &'a ChunkInfo>) -> Self {
+        let mut chunks: Vec<(&DataChunk, CoverageState)> = catalogue_chunks
+            .into_iter()
+            .filter(|info| info.chunk.dataset_id == dataset_id)
+            .filter_map(|info| Some((&info.chunk, state_of(&info.status)?)))
+            .collect();
+        chunks.sort_by_key(|(chunk, _)| (chunk.block_range.start, chunk.block_range.end));
+
+        let mut segments: Vec<CoverageSegment> = Vec::new();
+        let mut next_block = chunks.first().map(|(chunk, _)| chunk.block_range.start).unwrap_or_default();
+        for (chunk, state) in chunks {
+            if chunk.block_range.start > next_block {
+                push_segment(&mut segments, next_block..chunk.block_range.start, CoverageState::Missing);
+            }
+            let start = chunk.block_range.start.max(next_block);
+            if start < chunk.block_range.end {
+                push_segment(&mut segments, start..chunk.block_range.end, state);
+                next_block = chunk.block_range.end;
+            }
+        }
+        Coverage { dataset_id, segments }
+    }
+
+    pub fn block_range(&self) -> Range<u64> {
+        match (self.segments.first(), self.segments.last()) {
+            (Some(first), Some(last)) => first.block_range.start..last.block_range.end,
+            _ => 0..0,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let block_range = self.block_range();
+        json!({
+            "dataset_id": hex::encode(self.dataset_id),
+            "start": block_range.start,
+            "end": block_range.end,
+            "segments": self.segments.iter().map(|segment| json!({
+                "start": segment.block_range.start,
+                "end": segment.block_range.end,
+                "state": segment.state.to_string(),
+            })).collect::<Vec<serde_json::Value>>(),
+        })
+    }
+
+    /// Horizontal bar of the segments scaled to the blocks they span, with the block ranges in tooltips
+    pub fn to_svg(&self) -> String {
+        let block_range = self.block_range();
+        let blocks = (block_range.end - block_range.start).max(1) as f64;
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+            SVG_WIDTH, SVG_HEIGHT, SVG_WIDTH, SVG_HEIGHT
+        );
+        svg.push_str(&format!("<title>dataset {} blocks {}..{}</title>\n", hex::encode(self.dataset_id), block_range.start, block_range.end));
+        for segment in self.segments.iter() {
+            let x = (segment.block_range.start - block_range.start) as f64 / blocks * SVG_WIDTH;
+            let width = (segment.block_range.end - segment.block_range.start) as f64 / blocks * SVG_WIDTH;
+            svg.push_str(&format!(
+                "<rect x=\"{:.2}\" y=\"0\" width=\"{:.2}\" height=\"{}\" fill=\"{}\"><title>{}..{} {}</title></rect>\n",
+                x, width, SVG_HEIGHT, segment.state.color(), segment.block_range.start, segment.block_range.end, segment.state
+            ));
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+/// Deleted chunks don't hold any blocks anymore
+fn state_of(status: &ChunkStatus) -> Option<CoverageState> {
+    match status {
+        ChunkStatus::Ready => Some(CoverageState::Ready),
+        ChunkStatus::Downloading => Some(CoverageState::Downloading),
+        ChunkStatus::Deleting => Some(CoverageState::Deleting),
+        ChunkStatus::Failed => Some(CoverageState::Failed),
+        ChunkStatus::Deleted => None,
+    }
+}
+
+/// Adjacent segments in the same state are merged, so the map stays small after large syncs
+fn push_segment(segments: &mut Vec<CoverageSegment>, block_range: Range<u64>, state: CoverageState) {
+    match segments.last_mut() {
+        Some(last) if last.state == state && last.block_range.end == block_range.start => last.block_range.end = block_range.end,
+        _ => segments.push(CoverageSegment { block_range, state }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(block_range: Range<u64>, status: ChunkStatus) -> ChunkInfo {
+        let dataset_id = [1u8; 32];
+        ChunkInfo::new(
+            DataChunk::new(dataset_id, block_range),
+            status,
+        )
+    }
+
+    fn segment(block_range: Range<u64>, state: CoverageState) -> CoverageSegment {
+        CoverageSegment { block_range, state }
+    }
+
+    #[test]
+    fn test_coverage_segments() {
+        // Arrange
+        let held = vec![
+            info(10..20, ChunkStatus::Ready),
+            info(20..30, ChunkStatus::Ready),
+            info(40..50, ChunkStatus::Downloading),
+            info(30..40, ChunkStatus::Deleted),
+            info(45..60, ChunkStatus::Failed),
+        ];
+
+        // Act
+        let coverage = Coverage::new([1u8; 32], &held);
+
+        // Assert
+        assert_eq!(coverage.segments, vec![
+            segment(10..30, CoverageState::Ready),
+            segment(30..40, CoverageState::Missing),
+            segment(40..50, CoverageState::Downloading),
+            segment(50..60, CoverageState::Failed),
+        ]);
+        assert_eq!(coverage.block_range(), 10..60);
+    }
+
+    #[test]
+    fn test_coverage_json() {
+        let coverage = Coverage::new([1u8; 32], &[info(0..10, ChunkStatus::Ready)]);
+        assert_eq!(coverage.to_json(), json!({
+            "dataset_id": hex::encode([1u8; 32]),
+            "start": 0,
+            "end": 10,
+            "segments": [{"start": 0, "end": 10, "state": "Ready"}],
+        }));
+    }
+
+    #[test]
+    fn test_coverage_svg() {
+        let coverage = Coverage::new([1u8; 32], &[info(0..10, ChunkStatus::Ready), info(30..40, ChunkStatus::Ready)]);
+        let svg = coverage.to_svg();
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.contains("<rect x=\"250.00\" y=\"0\" width=\"500.00\" height=\"24\" fill=\"#bdbdbd\"><title>10..30 Missing</title></rect>"));
+        assert_eq!(svg.matches("<rect").count(), 3);
+    }
+
+    #[test]
+    fn test_empty_coverage() {
+        let coverage = Coverage::new([1u8; 32], &Vec::new());
+        assert!(coverage.segments.is_empty());
+        assert_eq!(coverage.to_svg().matches("<rect").count(), 0);
+    }
+}
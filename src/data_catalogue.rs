@@ -1,35 +1,118 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::Range;
-use std::sync::{Arc, RwLock};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use crate::catalogue_compat::{self, CompatRules};
+use crate::correlation::{self, CorrelationId};
+use crate::chunk_errors::{self, ChunkError, ChunkErrorKind, DEFAULT_ERROR_HISTORY};
+use crate::chunk_filter::ChunkFilter;
+use crate::compaction::{CatalogueStats, CompactionConfig, CompactionRun, CompactionStats};
+use crate::snapshot::CatalogueSnapshot;
+use crate::chunk_pins::{ChunkPin, ChunkPins};
 use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
-use polars::prelude::*;
-use crate::local_data_source::{LocalDataSource, LOCAL_DATA_DIR};
-
-const LOCAL_CATALOGUE: &str = "./local_catalogue_dir/registry.parquet";
+use crate::eviction::{self, EvictionOrder};
+use crate::legal_hold::{LegalHold, LegalHolds};
+use crate::lineage::{self, LineageEvent, LineageKind};
+use crate::overlap::{self, ChunkPreference};
+use crate::epoch::{self, EpochLayout, EpochStatus};
+use crate::planning;
+use crate::registration::ForgetError;
+use crate::reassignment::ReassignError;
+use crate::relocation::RelocateError;
+use crate::sync_plan::{self, SyncPlan};
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum ChunkStatus {
-    Downloading,
-    Ready,
-    Deleting,
-    Deleted,
-}
+pub use crate::planning::ChunkStatus;
+#[cfg(feature = "dataframes")]
+use polars::prelude::*;
+#[cfg(not(feature = "dataframes"))]
+use {crate::catalogue_compat::Normalization, crate::jsonl::{self, Row}};
 
-impl ToString for ChunkStatus {
-    fn to_string(&self) -> String {
-        format!("{:?}", self)
-    }
-}
+#[cfg(all(feature = "dataframes", not(test)))]
+pub(crate) const LOCAL_CATALOGUE: &str = "./local_catalogue_dir/registry.parquet";
+#[cfg(all(not(feature = "dataframes"), not(test)))]
+pub(crate) const LOCAL_CATALOGUE: &str = "./local_catalogue_dir/registry.jsonl";
+// the tests save their catalogue next to the checked-in one, which running them leaves untouched
+#[cfg(all(feature = "dataframes", test))]
+pub(crate) const LOCAL_CATALOGUE: &str = "./local_catalogue_dir/test_registry.parquet";
+#[cfg(all(not(feature = "dataframes"), test))]
+pub(crate) const LOCAL_CATALOGUE: &str = "./local_catalogue_dir/test_registry.jsonl";
+#[cfg(feature = "dataframes")]
+const LEGAL_HOLDS_FILE: &str = "legal_holds.parquet";
+#[cfg(not(feature = "dataframes"))]
+const LEGAL_HOLDS_FILE: &str = "legal_holds.jsonl";
 
 #[derive(Clone, Debug)]
 pub struct ChunkInfo {
     pub chunk: DataChunk,
     pub status: ChunkStatus,
+    /// When the chunk got into its current status, not persisted
+    pub updated_at: Instant,
+    /// Correlation id of the request, which moved the chunk into its current status, not persisted
+    pub correlation_id: Option<CorrelationId>,
+    /// The files were rewritten for faster scans, see `ParquetOptimizer`
+    pub optimized: bool,
+    /// Latest errors of the chunk, oldest first, kept across status changes
+    pub errors: Vec<ChunkError>,
+    /// Data directory the chunk was moved to with `relocate_chunk`, `None` when it's in the `data_dir`.
+    /// Kept across status changes until the chunk is deleted.
+    pub volume: Option<PathBuf>,
+    /// How the chunk was derived from the chunks it replaced, including their own lineage, oldest first.
+    /// Kept across status changes, empty for a chunk downloaded as published.
+    pub lineage: Vec<LineageEvent>,
 }
 
+impl ChunkInfo {
+    pub fn new(chunk: DataChunk, status: ChunkStatus) -> Self {
+        ChunkInfo {
+            chunk,
+            status,
+            updated_at: Instant::now(),
+            correlation_id: correlation::current(),
+            optimized: false,
+            errors: Vec::new(),
+            volume: None,
+            lineage: Vec::new(),
+        }
+    }
+}
+
+/// Called with the chunk and its new status on every update of the registry
+pub type StatusListener = Box<dyn Fn(&DataChunk, &ChunkStatus) + Send + Sync>;
+
+/// Called after every compaction of the catalogue
+pub type CompactionListener = Box<dyn Fn(&CompactionRun) + Send + Sync>;
+
 #[derive(Clone)]
 pub struct DataCatalogue {
     pub registry: Arc<RwLock<HashMap<ChunkId, ChunkInfo>>>,
+    pub listeners: Arc<RwLock<Vec<StatusListener>>>,
+    /// readers holding the chunks, the chunk files are deleted only when nobody holds them
+    pub pins: ChunkPins,
+    /// when the chunks were last returned to a query, used to pick chunks for eviction
+    pub last_queried: Arc<RwLock<HashMap<ChunkId, Instant>>>,
+    /// ids of the ready chunks, updated together with the registry
+    ready_filter: Arc<RwLock<ChunkFilter>>,
+    /// automatic compaction, disabled when `None`
+    compaction: Option<CompactionConfig>,
+    compaction_stats: Arc<Mutex<CompactionStats>>,
+    compaction_listeners: Arc<RwLock<Vec<CompactionListener>>>,
+    /// datasets whose chunks can't start downloading or deleting
+    frozen: Arc<RwLock<HashSet<DatasetId>>>,
+    /// chunks which can't start deleting or be replaced, saved next to the registry
+    legal_holds: LegalHolds,
+    /// ready chunks whose damaged files are being fetched again, they stay `Ready` and findable meanwhile
+    repairing: Arc<Mutex<HashSet<ChunkId>>>,
+    /// number of errors kept per chunk
+    error_history: usize,
+    /// which of the overlapping ready chunks `find_chunk` returns
+    chunk_preference: ChunkPreference,
+    /// notified after every change of a status, see `wait_until_downloaded`
+    status_changed: Arc<(Mutex<()>, Condvar)>,
+    /// held while the registry is persisted, so a snapshot is never overwritten by an older one
+    saving: Arc<Mutex<()>>,
+    /// file the registry is persisted to, parquet or JSONL without the `dataframes` feature
+    catalogue_file: PathBuf,
 }
 
 impl DataCatalogue {
@@ -37,45 +120,135 @@ impl DataCatalogue {
         Self::new(Vec::new())
     }
 
-    pub fn new(local_chunks: Vec<DataChunk>) -> Self {
+    /// Create the catalogue from the chunks found in the local data directory.
+    ///
+    /// The local chunks are consumed one by one, so a streaming scan of the data directory
+    /// can be passed in without materializing all the chunks first.
+    pub fn new(local_chunks: impl IntoIterator<Item = DataChunk>) -> Self {
+        Self::open(Path::new(LOCAL_CATALOGUE), local_chunks)
+    }
+
+    /// Same as `new`, with the registry persisted to the given file
+    pub fn open(catalogue_file: &Path, local_chunks: impl IntoIterator<Item = DataChunk>) -> Self {
         let catalogue = DataCatalogue {
             registry: Arc::new(RwLock::new(HashMap::new())),
+            listeners: Arc::new(RwLock::new(Vec::new())),
+            pins: ChunkPins::default(),
+            last_queried: Arc::new(RwLock::new(HashMap::new())),
+            ready_filter: Arc::new(RwLock::new(ChunkFilter::with_capacity(0))),
+            compaction: None,
+            compaction_stats: Arc::new(Mutex::new(CompactionStats::default())),
+            compaction_listeners: Arc::new(RwLock::new(Vec::new())),
+            frozen: Arc::new(RwLock::new(HashSet::new())),
+            legal_holds: LegalHolds::open(&catalogue_file.with_file_name(LEGAL_HOLDS_FILE)),
+            repairing: Arc::new(Mutex::new(HashSet::new())),
+            error_history: DEFAULT_ERROR_HISTORY,
+            chunk_preference: ChunkPreference::default(),
+            status_changed: Arc::new((Mutex::new(()), Condvar::new())),
+            saving: Arc::new(Mutex::new(())),
+            catalogue_file: catalogue_file.to_path_buf(),
         };
 
-        // load local chunks into the registry
-        let db_chunk_infos = DataCatalogue::read_parquet_to_chunks(LOCAL_CATALOGUE);
-        for local_chunk in local_chunks.iter() {
-
+        // registries of earlier versions get the current column names and statuses first, so the scans below find them.
+        // A registry which can't be rewritten is still read by the rules.
+        let _ = catalogue_compat::normalize_catalogue(catalogue_file, &CompatRules::default());
+        // only the ids of chunks which were not ready are needed for the data integrity check
+        let not_ready_chunk_ids = DataCatalogue::read_not_ready_chunk_ids(catalogue_file);
+        let optimized_chunk_ids = DataCatalogue::read_optimized_chunk_ids(catalogue_file);
+        let mut chunk_errors = DataCatalogue::read_chunk_errors(catalogue_file);
+        let mut volumes = DataCatalogue::read_chunk_volumes(catalogue_file);
+        let mut chunk_lineage = DataCatalogue::read_chunk_lineage(catalogue_file);
+        for local_chunk in local_chunks {
             // data integrity check and update
-            if db_chunk_infos.iter().any(|db_chunk_info| {
-                db_chunk_info.chunk.id == local_chunk.id && db_chunk_info.status != ChunkStatus::Ready
-            }) {
+            if not_ready_chunk_ids.contains(&local_chunk.id) {
                 continue;
             }
-            catalogue.registry.write().unwrap().insert(local_chunk.id, ChunkInfo {
-                chunk: local_chunk.clone(),
-                status: ChunkStatus::Ready,
-            });
+            let optimized = optimized_chunk_ids.contains(&local_chunk.id);
+            let mut registry = catalogue.registry.write().unwrap();
+            let errors = chunk_errors.remove(&local_chunk.id).unwrap_or_default();
+            let volume = volumes.remove(&local_chunk.id);
+            let lineage = chunk_lineage.remove(&local_chunk.id).unwrap_or_default();
+            catalogue.set_info(&mut registry, ChunkInfo { optimized, errors, volume, lineage, ..ChunkInfo::new(local_chunk, ChunkStatus::Ready) });
         }
         catalogue
     }
 
+    /// Keep the latest `error_history` errors of every chunk
+    pub fn with_error_history(mut self, error_history: usize) -> Self {
+        self.error_history = error_history;
+        self
+    }
+
+    /// Pick the chunk `find_chunk` returns among the overlapping ready chunks holding the block by the preference
+    pub fn with_chunk_preference(mut self, chunk_preference: ChunkPreference) -> Self {
+        self.chunk_preference = chunk_preference;
+        self
+    }
+
+    /// Compact the catalogue automatically whenever it crosses the threshold of the `config`
+    pub fn with_compaction(mut self, config: Option<CompactionConfig>) -> Self {
+        self.compaction = config;
+        self
+    }
+
     /// This function generates a unique chunk id from the dataset id and block range
     /// Note: To be used everywhere to have consistent chunk ids!!!
     pub fn generate_chunk_id(dataset_id: &DatasetId, block_range: &Range<u64>) -> ChunkId {
-        let dataset = hex::encode(dataset_id);
-        let chunk_id_str = format!("{}{}{}", dataset, block_range.start, block_range.end);
-        let chunk_id_vec = hex::decode(sha256::digest(chunk_id_str.as_bytes())).unwrap();
-        let mut chunk_id_array = [0u8; 32];
-        chunk_id_array.copy_from_slice(&chunk_id_vec);
-        chunk_id_array
+        planning::generate_chunk_id(dataset_id, block_range)
+    }
+
+    /// Same as `generate_chunk_id` for many block ranges of the dataset, see `ChunkIdGenerator`
+    pub fn generate_chunk_ids(dataset_id: &DatasetId, block_ranges: &[Range<u64>]) -> Vec<ChunkId> {
+        planning::generate_chunk_ids(dataset_id, block_ranges)
+    }
+
+    /// Stop any downloads and deletions of the dataset from starting, returns `false` when it was already frozen.
+    /// Operations started before keep running.
+    pub fn freeze(&self, dataset_id: DatasetId) -> bool {
+        self.frozen.write().unwrap().insert(dataset_id)
+    }
+
+    /// Returns `false` when the dataset wasn't frozen
+    pub fn unfreeze(&self, dataset_id: &DatasetId) -> bool {
+        self.frozen.write().unwrap().remove(dataset_id)
+    }
+
+    pub fn is_frozen(&self, dataset_id: &DatasetId) -> bool {
+        self.frozen.read().unwrap().contains(dataset_id)
+    }
+
+    pub fn frozen_datasets(&self) -> Vec<DatasetId> {
+        let mut frozen: Vec<DatasetId> = self.frozen.read().unwrap().iter().copied().collect();
+        frozen.sort();
+        frozen
+    }
+
+    /// Keep the chunks of the hold until it's lifted, returns `false` when it was already placed.
+    /// Deletions started before keep running.
+    pub fn place_legal_hold(&self, hold: LegalHold) -> std::io::Result<bool> {
+        self.legal_holds.place(hold)
+    }
+
+    /// Returns `false` when the hold wasn't placed
+    pub fn lift_legal_hold(&self, hold: &LegalHold) -> std::io::Result<bool> {
+        self.legal_holds.lift(hold)
+    }
+
+    pub fn legal_holds(&self) -> Vec<LegalHold> {
+        self.legal_holds.holds()
+    }
+
+    pub fn is_under_legal_hold(&self, chunk: &DataChunk) -> bool {
+        self.legal_holds.is_held(chunk)
     }
 
     pub fn start_download(&self, chunk: &DataChunk) -> bool {
+        if self.is_frozen(&chunk.dataset_id) {
+            return false;
+        }
         {
             let registry = self.registry.read().unwrap();
-            if registry.contains_key(&chunk.id) && registry.get(&chunk.id).unwrap().status != ChunkStatus::Deleted
-                || !registry.get(&chunk.id).is_none() {
+            if registry.get(&chunk.id).is_some_and(|info| !matches!(info.status, ChunkStatus::Deleted | ChunkStatus::Failed)) {
                 // don't download the chunk if it's already being downloaded, or it's not deleted
                 return false;
             }
@@ -84,7 +257,91 @@ impl DataCatalogue {
         true
     }
 
+    /// Put a chunk, whose download was started but never spawned, back as it was before `start_download`,
+    /// `previous` being its info then. A chunk which wasn't in the catalogue is dropped, listeners see it as `Deleted`.
+    /// Nothing changes once the chunk isn't `Downloading` anymore.
+    pub fn undo_download(&self, chunk: &DataChunk, previous: Option<ChunkInfo>) {
+        let status = {
+            let mut registry = self.registry.write().unwrap();
+            if !registry.get(&chunk.id).is_some_and(|info| info.status == ChunkStatus::Downloading) {
+                return;
+            }
+            match previous {
+                Some(previous) => {
+                    let status = previous.status.clone();
+                    self.set_info(&mut registry, previous);
+                    status
+                }
+                None => {
+                    self.remove_info(&mut registry, &chunk.id);
+                    ChunkStatus::Deleted
+                }
+            }
+        };
+        self.save_and_notify(std::slice::from_ref(chunk), &status);
+    }
+
+    /// Start downloading new files of a `Ready` chunk into its place
+    pub fn start_redownload(&self, chunk: &DataChunk) -> bool {
+        // the files of a held chunk must stay as they are, and a chunk being repaired gets its files already
+        if self.is_frozen(&chunk.dataset_id) || self.is_under_legal_hold(chunk) || self.is_repairing(&chunk.id) {
+            return false;
+        }
+        {
+            let registry = self.registry.read().unwrap();
+            if registry.get(&chunk.id).map(|info| &info.status) != Some(&ChunkStatus::Ready) {
+                // only a ready chunk can be replaced, otherwise it's still being processed
+                return false;
+            }
+        }
+        self.update_chunk(chunk, &ChunkStatus::Downloading);
+        true
+    }
+
+    /// Start fetching the damaged files of a `Ready` chunk again. The chunk stays `Ready`, so it's still read meanwhile.
+    /// Returns `false` when the chunk isn't ready, its files must stay as they are, or it's already being repaired.
+    pub fn start_repair(&self, chunk: &DataChunk) -> bool {
+        // the files of a held chunk must stay as they are
+        if self.is_frozen(&chunk.dataset_id) || self.is_under_legal_hold(chunk) {
+            return false;
+        }
+        let registry = self.registry.read().unwrap();
+        if registry.get(&chunk.id).map(|info| &info.status) != Some(&ChunkStatus::Ready) {
+            return false;
+        }
+        self.repairing.lock().unwrap().insert(chunk.id)
+    }
+
+    /// Whether the damaged files of the chunk are being fetched again, see `start_repair`
+    pub fn is_repairing(&self, chunk_id: &ChunkId) -> bool {
+        self.repairing.lock().unwrap().contains(chunk_id)
+    }
+
+    /// End the repair of the chunk. With `optimized`, the repaired files were swapped in and the chunk is marked `Ready` again,
+    /// recording whether they were optimized, unless it was forgotten meanwhile. Without it the chunk stays as it was.
+    pub fn finish_repair(&self, chunk: &DataChunk, optimized: Option<bool>) {
+        let repaired = {
+            let mut registry = self.registry.write().unwrap();
+            self.repairing.lock().unwrap().remove(&chunk.id);
+            let is_ready = registry.get(&chunk.id).is_some_and(|info| info.status == ChunkStatus::Ready);
+            match optimized {
+                Some(optimized) if is_ready => {
+                    self.set_info(&mut registry, ChunkInfo { optimized, ..ChunkInfo::new(chunk.clone(), ChunkStatus::Ready) });
+                    true
+                }
+                _ => false,
+            }
+        };
+        if repaired {
+            self.save_and_notify(std::slice::from_ref(chunk), &ChunkStatus::Ready);
+        }
+    }
+
     pub fn start_deletion(&self, chunk: &DataChunk) -> bool {
+        // the files of a chunk being repaired are about to be swapped in
+        if self.is_frozen(&chunk.dataset_id) || self.is_under_legal_hold(chunk) || self.is_repairing(&chunk.id) {
+            return false;
+        }
         {
             let registry = self.registry.read().unwrap();
             if (registry.contains_key(&chunk.id) && registry.get(&chunk.id).unwrap().status != ChunkStatus::Ready)
@@ -101,42 +358,367 @@ impl DataCatalogue {
         self.registry.read().unwrap()
             .iter()
             .filter(|(_, info)| info.status == ChunkStatus::Ready)
-            .map(|(id, _)| id.clone())
+            .map(|(id, _)| *id)
             .collect()
     }
 
+    /// Start downloading all the chunks, or none of them when any is already being processed
+    pub fn start_downloads(&self, chunks: &[DataChunk]) -> bool {
+        self.update_all_chunks_if(chunks, &ChunkStatus::Downloading, |info| {
+            info.is_none_or(|info| matches!(info.status, ChunkStatus::Deleted | ChunkStatus::Failed))
+        })
+    }
+
+    /// Start deleting all the chunks, or none of them when any is not ready, under a legal hold or being repaired
+    pub fn start_deletions(&self, chunks: &[DataChunk]) -> bool {
+        self.update_all_chunks_if(chunks, &ChunkStatus::Deleting, |info| {
+            info.is_some_and(|info| info.status == ChunkStatus::Ready && !self.is_under_legal_hold(&info.chunk) && !self.is_repairing(&info.chunk.id))
+        })
+    }
+
     pub fn update_chunk(&self, chunk: &DataChunk, status: &ChunkStatus) {
         {
             let mut registry = self.registry.write().unwrap();
-            match status {
-                ChunkStatus::Downloading => {
-                    registry.insert(chunk.id, ChunkInfo {
-                        chunk: chunk.clone(),
-                        status: ChunkStatus::Downloading,
-                    });
-                }
-                ChunkStatus::Ready => {
-                    registry.insert(chunk.id, ChunkInfo {
-                        chunk: chunk.clone(),
-                        status: ChunkStatus::Ready,
-                    });
-                }
-                ChunkStatus::Deleting => {
-                    registry.insert(chunk.id, ChunkInfo {
-                        chunk: chunk.clone(),
-                        status: ChunkStatus::Deleting,
-                    });
-                }
-                ChunkStatus::Deleted => {
-                    registry.insert(chunk.id, ChunkInfo {
-                        chunk: chunk.clone(),
-                        status: ChunkStatus::Deleted,
-                    });
+            self.set_status(&mut registry, chunk, status);
+        }
+        self.save_and_notify(std::slice::from_ref(chunk), status);
+    }
+
+    /// Add a chunk placed in the data directory by another process as `Ready`.
+    /// Fails with the status of the chunk, when it's known and neither deleted nor failed.
+    pub fn register_chunk(&self, chunk: &DataChunk) -> Result<(), ChunkStatus> {
+        {
+            let mut registry = self.registry.write().unwrap();
+            if let Some(info) = registry.get(&chunk.id).filter(|info| !matches!(info.status, ChunkStatus::Deleted | ChunkStatus::Failed)) {
+                return Err(info.status.clone());
+            }
+            self.set_info(&mut registry, ChunkInfo::new(chunk.clone(), ChunkStatus::Ready));
+        }
+        self.save_and_notify(std::slice::from_ref(chunk), &ChunkStatus::Ready);
+        Ok(())
+    }
+
+    /// Add a chunk whose files can't be served as they are as `Failed`, with the reason in its error history,
+    /// so it's downloaded again instead of found.
+    pub fn register_failed_chunk(&self, chunk: &DataChunk, kind: ChunkErrorKind, message: impl Into<String>) {
+        {
+            let mut registry = self.registry.write().unwrap();
+            self.set_info(&mut registry, ChunkInfo { errors: vec![ChunkError::new(kind, message)], ..ChunkInfo::new(chunk.clone(), ChunkStatus::Failed) });
+        }
+        self.save_and_notify(std::slice::from_ref(chunk), &ChunkStatus::Failed);
+    }
+
+    /// Remove a chunk from the catalogue without touching its files.
+    /// Chunks being downloaded or deleted, or under a legal hold, can't be forgotten.
+    /// Listeners see the chunk as `Deleted`, as it's no longer available for queries.
+    pub fn forget_chunk(&self, chunk_id: &ChunkId) -> Result<Option<DataChunk>, ForgetError> {
+        let forgotten = {
+            let mut registry = self.registry.write().unwrap();
+            match registry.get(chunk_id) {
+                Some(info) if matches!(info.status, ChunkStatus::Downloading | ChunkStatus::Deleting) => return Err(ForgetError::Busy(info.status.clone())),
+                Some(info) if self.is_under_legal_hold(&info.chunk) => return Err(ForgetError::LegalHold),
+                Some(_) => self.remove_info(&mut registry, chunk_id).map(|info| info.chunk),
+                None => None,
+            }
+        };
+        if let Some(chunk) = &forgotten {
+            self.save_and_notify(std::slice::from_ref(chunk), &ChunkStatus::Deleted);
+        }
+        Ok(forgotten)
+    }
+
+    /// Mark a downloaded chunk `Ready`, recording whether its files were optimized
+    pub fn mark_ready(&self, chunk: &DataChunk, optimized: bool) {
+        {
+            let mut registry = self.registry.write().unwrap();
+            self.set_info(&mut registry, ChunkInfo { optimized, ..ChunkInfo::new(chunk.clone(), ChunkStatus::Ready) });
+        }
+        self.save_and_notify(std::slice::from_ref(chunk), &ChunkStatus::Ready);
+    }
+
+    /// Update all the chunks under a single lock, so nobody sees only some of them updated
+    fn update_all_chunks_if(
+        &self,
+        chunks: &[DataChunk],
+        status: &ChunkStatus,
+        allowed: impl Fn(Option<&ChunkInfo>) -> bool,
+    ) -> bool {
+        if chunks.iter().any(|chunk| self.is_frozen(&chunk.dataset_id)) {
+            return false;
+        }
+        {
+            let mut registry = self.registry.write().unwrap();
+            if !chunks.iter().all(|chunk| allowed(registry.get(&chunk.id))) {
+                return false;
+            }
+            for chunk in chunks {
+                self.set_status(&mut registry, chunk, status);
+            }
+        }
+        self.save_and_notify(chunks, status);
+        true
+    }
+
+    /// The files keep their format whatever the status, so the optimized flag is carried over until `mark_ready` records it anew
+    fn set_status(&self, registry: &mut HashMap<ChunkId, ChunkInfo>, chunk: &DataChunk, status: &ChunkStatus) {
+        let optimized = registry.get(&chunk.id).is_some_and(|info| info.optimized);
+        self.set_info(registry, ChunkInfo { optimized, ..ChunkInfo::new(chunk.clone(), status.clone()) });
+    }
+
+    /// Every change of the registry goes through here or `remove_info`, so the ready filter stays in sync with it.
+    /// The error history, the volume and the lineage of the chunk are carried over from its previous info.
+    fn set_info(&self, registry: &mut HashMap<ChunkId, ChunkInfo>, mut info: ChunkInfo) {
+        let chunk_id = info.chunk.id;
+        let is_ready = info.status == ChunkStatus::Ready;
+        if let Some(previous) = registry.get_mut(&chunk_id) {
+            if info.errors.is_empty() {
+                info.errors = std::mem::take(&mut previous.errors);
+            }
+            if info.volume.is_none() && info.status != ChunkStatus::Deleted {
+                info.volume = previous.volume.take();
+            }
+            if info.lineage.is_empty() {
+                info.lineage = std::mem::take(&mut previous.lineage);
+            }
+        }
+        let previous = registry.insert(chunk_id, info);
+        let was_ready = previous.is_some_and(|info| info.status == ChunkStatus::Ready);
+        if was_ready == is_ready {
+            return;
+        }
+        let mut ready_filter = self.ready_filter.write().unwrap();
+        if is_ready {
+            ready_filter.insert(&chunk_id);
+        } else {
+            ready_filter.remove(&chunk_id);
+        }
+        if ready_filter.is_overloaded() {
+            let ready_chunk_ids: Vec<ChunkId> = registry.values()
+                .filter(|info| info.status == ChunkStatus::Ready)
+                .map(|info| info.chunk.id)
+                .collect();
+            *ready_filter = ChunkFilter::from_chunk_ids(ready_chunk_ids.iter());
+        }
+    }
+
+    fn remove_info(&self, registry: &mut HashMap<ChunkId, ChunkInfo>, chunk_id: &ChunkId) -> Option<ChunkInfo> {
+        let removed = registry.remove(chunk_id)?;
+        if removed.status == ChunkStatus::Ready {
+            self.ready_filter.write().unwrap().remove(chunk_id);
+        }
+        self.last_queried.write().unwrap().remove(chunk_id);
+        Some(removed)
+    }
+
+    /// Cheap check whether a chunk may be ready, without locking the registry.
+    /// `false` means the chunk is definitely not ready.
+    pub fn may_be_ready(&self, chunk_id: &ChunkId) -> bool {
+        self.ready_filter.read().unwrap().might_contain(chunk_id)
+    }
+
+    /// Persist the current registry, returns the number of the persisted rows.
+    /// The snapshot is taken under the save lock, so the saves of concurrent updates are written in order.
+    fn save(&self) -> usize {
+        let _saving = self.saving.lock().unwrap();
+        let chunk_infos = self.registry.read().unwrap().values().cloned().collect::<Vec<ChunkInfo>>();
+        DataCatalogue::save_chunk_infos(&chunk_infos, &self.catalogue_file);
+        chunk_infos.len()
+    }
+
+    fn save_and_notify(&self, chunks: &[DataChunk], status: &ChunkStatus) {
+        self.save();
+        if status == &ChunkStatus::Deleted && self.compaction.is_some_and(|config| self.stats().needs_compaction(&config)) {
+            self.compact();
+        }
+
+        {
+            let (lock, status_changed) = &*self.status_changed;
+            let _lock = lock.lock().unwrap();
+            status_changed.notify_all();
+        }
+
+        let listeners = self.listeners.read().unwrap();
+        for chunk in chunks {
+            for listener in listeners.iter() {
+                listener(chunk, status);
+            }
+        }
+    }
+
+    /// Other chunks of the dataset being downloaded, which share some blocks with the chunk
+    pub fn in_flight_overlaps(&self, chunk: &DataChunk) -> Vec<DataChunk> {
+        overlap::in_flight_overlaps(chunk, self.registry.read().unwrap().values())
+    }
+
+    /// Block the current thread until none of the chunks is `Downloading`.
+    /// To be called only from background workers, never from the API methods.
+    pub fn wait_until_downloaded(&self, chunk_ids: &[ChunkId]) {
+        let (lock, status_changed) = &*self.status_changed;
+        let lock = lock.lock().unwrap();
+        let _lock = status_changed
+            .wait_while(lock, |_| {
+                let registry = self.registry.read().unwrap();
+                chunk_ids.iter().any(|chunk_id| registry.get(chunk_id).is_some_and(|info| info.status == ChunkStatus::Downloading))
+            })
+            .unwrap();
+    }
+
+    /// Block the current thread until the chunk is neither `Downloading` nor `Deleting`, or the timeout elapses.
+    /// Returns the status of the chunk then, `None` when it's not in the catalogue.
+    pub fn wait_until_settled_for(&self, chunk_id: &ChunkId, timeout: Duration) -> Option<ChunkStatus> {
+        let status = || self.registry.read().unwrap().get(chunk_id).map(|info| info.status.clone());
+        let (lock, status_changed) = &*self.status_changed;
+        let lock = lock.lock().unwrap();
+        let _lock = status_changed
+            .wait_timeout_while(lock, timeout, |_| matches!(status(), Some(ChunkStatus::Downloading | ChunkStatus::Deleting)))
+            .unwrap();
+        status()
+    }
+
+    /// Add the error to the history of the chunk, dropping the oldest errors over the limit.
+    /// Returns `false` when the chunk isn't in the catalogue.
+    pub fn record_error(&self, chunk_id: &ChunkId, kind: ChunkErrorKind, message: impl Into<String>) -> bool {
+        {
+            let mut registry = self.registry.write().unwrap();
+            let Some(info) = registry.get_mut(chunk_id) else { return false };
+            info.errors.push(ChunkError::new(kind, message));
+            let excess = info.errors.len().saturating_sub(self.error_history);
+            info.errors.drain(..excess);
+        }
+        self.save();
+        true
+    }
+
+    /// Record the chunk as moved to the volume, `None` being the data directory, when it's `Ready` and no reader holds it.
+    /// `moved` runs under the registry lock, so nobody finds the chunk between the switch of its path and this record.
+    pub fn record_volume(&self, chunk_id: &ChunkId, volume: Option<PathBuf>, moved: impl FnOnce()) -> Result<(), RelocateError> {
+        {
+            let mut registry = self.registry.write().unwrap();
+            let Some(info) = registry.get_mut(chunk_id) else { return Err(RelocateError::UnknownChunk(*chunk_id)) };
+            if info.status != ChunkStatus::Ready {
+                return Err(RelocateError::NotReady(info.status.clone()));
+            }
+            // pins are only taken under the registry lock, so none can be taken until the switch is recorded
+            let pins = self.pins.pin_count(chunk_id);
+            if pins > 0 {
+                return Err(RelocateError::InUse(pins));
+            }
+            info.volume = volume;
+            moved();
+        }
+        self.save();
+        Ok(())
+    }
+
+    /// Replace a `Ready` chunk by the same blocks under another dataset, once `moved` renamed its files.
+    /// The old chunk becomes `Deleted` and the new one `Ready` in a single save, keeping the volume, the errors and the optimized flag.
+    /// The lineage of the new chunk records the reassignment.
+    pub fn reassign_chunk(&self, chunk_id: &ChunkId, new_chunk: &DataChunk, moved: impl FnOnce(&ChunkInfo) -> std::io::Result<()>) -> Result<DataChunk, ReassignError> {
+        let old_chunk = {
+            let mut registry = self.registry.write().unwrap();
+            let Some(info) = registry.get(chunk_id) else { return Err(ReassignError::UnknownChunk(*chunk_id)) };
+            if info.status != ChunkStatus::Ready {
+                return Err(ReassignError::NotReady(info.status.clone()));
+            }
+            // pins are only taken under the registry lock, so none can be taken until the rename is recorded
+            let pins = self.pins.pin_count(chunk_id);
+            if pins > 0 {
+                return Err(ReassignError::InUse(pins));
+            }
+            if self.is_under_legal_hold(&info.chunk) {
+                return Err(ReassignError::LegalHold);
+            }
+            if let Some(dataset_id) = [info.chunk.dataset_id, new_chunk.dataset_id].into_iter().find(|dataset_id| self.is_frozen(dataset_id)) {
+                return Err(ReassignError::Frozen(dataset_id));
+            }
+            match registry.get(&new_chunk.id) {
+                Some(target) if target.status != ChunkStatus::Deleted && target.status != ChunkStatus::Failed => {
+                    return Err(ReassignError::AlreadyRegistered(target.status.clone()));
                 }
+                _ => {}
+            }
+            moved(info).map_err(|error| ReassignError::Io(error.to_string()))?;
+
+            let old = self.remove_info(&mut registry, chunk_id).unwrap();
+            self.set_status(&mut registry, &old.chunk, &ChunkStatus::Deleted);
+            let ChunkInfo { optimized, errors, volume, lineage, .. } = old;
+            let lineage = lineage::derive(LineageKind::Reassigned, new_chunk, &[(&old.chunk, &lineage)]);
+            self.set_info(&mut registry, ChunkInfo { optimized, errors, volume, lineage, ..ChunkInfo::new(new_chunk.clone(), ChunkStatus::Ready) });
+            old.chunk
+        };
+        self.save_and_notify(&[old_chunk], &ChunkStatus::Deleted);
+        self.save_and_notify(std::slice::from_ref(new_chunk), &ChunkStatus::Ready);
+        Ok(new_chunk.clone())
+    }
+
+    pub fn get_chunk_info(&self, chunk_id: &ChunkId) -> Option<ChunkInfo> {
+        self.registry.read().unwrap().get(chunk_id).cloned()
+    }
+
+    /// Record that the chunk replaced the parents, after it became `Ready` and before the parents are deleted.
+    /// Returns `false` when the chunk isn't in the catalogue.
+    pub fn record_lineage(&self, chunk: &DataChunk, kind: LineageKind, parents: &[DataChunk]) -> bool {
+        {
+            let mut registry = self.registry.write().unwrap();
+            let parent_lineage: Vec<Vec<LineageEvent>> = parents.iter()
+                .map(|parent| registry.get(&parent.id).map(|info| info.lineage.clone()).unwrap_or_default())
+                .collect();
+            let parents: Vec<(&DataChunk, &[LineageEvent])> = parents.iter().zip(parent_lineage.iter()).map(|(parent, lineage)| (parent, lineage.as_slice())).collect();
+            let Some(info) = registry.get_mut(&chunk.id) else { return false };
+            info.lineage = lineage::derive(kind, chunk, &parents);
+        }
+        self.save();
+        true
+    }
+
+    /// Copy of all the chunks, taken under a single read lock
+    pub fn snapshot(&self) -> CatalogueSnapshot {
+        let registry = self.registry.read().unwrap();
+        CatalogueSnapshot { taken_at: SystemTime::now(), chunks: registry.values().cloned().collect() }
+    }
+
+    /// Live and dead rows of the catalogue
+    pub fn stats(&self) -> CatalogueStats {
+        let registry = self.registry.read().unwrap();
+        let dead_rows = registry.values().filter(|info| info.status == ChunkStatus::Deleted).count();
+        CatalogueStats { live_rows: registry.len() - dead_rows, dead_rows }
+    }
+
+    /// Drop the rows of the deleted chunks and persist the rest
+    pub fn compact(&self) -> CompactionRun {
+        let started_at = Instant::now();
+        let removed_rows = {
+            let mut registry = self.registry.write().unwrap();
+            let deleted: Vec<ChunkId> = registry.values()
+                .filter(|info| info.status == ChunkStatus::Deleted)
+                .map(|info| info.chunk.id)
+                .collect();
+            for chunk_id in deleted.iter() {
+                self.remove_info(&mut registry, chunk_id);
             }
+            deleted.len()
+        };
+        let remaining_rows = self.save();
+        let run = CompactionRun { removed_rows, remaining_rows, duration: started_at.elapsed() };
+        self.compaction_stats.lock().unwrap().record(&run);
+        for listener in self.compaction_listeners.read().unwrap().iter() {
+            listener(&run);
         }
-        let chunk_infos = self.registry.read().unwrap().values().map(|info| info.clone()).collect::<Vec<ChunkInfo>>();
-        DataCatalogue::save_chunk_infos_to_parquet(&chunk_infos, LOCAL_CATALOGUE);
+        run
+    }
+
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.compaction_stats.lock().unwrap().clone()
+    }
+
+    pub fn add_compaction_listener(&self, listener: CompactionListener) {
+        self.compaction_listeners.write().unwrap().push(listener);
+    }
+
+    /// Listen to status changes of chunks.
+    /// Listeners are called from the thread doing the update, so they must be cheap.
+    pub fn add_status_listener(&self, listener: StatusListener) {
+        self.listeners.write().unwrap().push(listener);
     }
 
     pub fn get_chunk_by_id(&self, chunk_id: &ChunkId) -> Option<DataChunk> {
@@ -144,18 +726,107 @@ impl DataCatalogue {
     }
 
     pub fn find_chunk(&self, dataset_id: &DatasetId, block_number: u64) -> Option<DataChunk> {
+        let registry = self.registry.read().unwrap();
+        self.ready_chunk_holding(&registry, dataset_id, block_number)
+            .map(|info| {
+                self.record_query(&info.chunk.id);
+                info.chunk.clone()
+            })
+    }
+
+    /// Find a ready chunk and pin it, so it can't be deleted while it's being read.
+    /// The pin is taken under the registry lock, so the chunk can't start its deletion in between.
+    pub fn find_and_pin_chunk(&self, dataset_id: &DatasetId, block_number: u64) -> Option<(DataChunk, ChunkPin)> {
+        let registry = self.registry.read().unwrap();
+        self.ready_chunk_holding(&registry, dataset_id, block_number)
+            .map(|info| {
+                self.record_query(&info.chunk.id);
+                (info.chunk.clone(), self.pins.pin(&info.chunk.id))
+            })
+    }
+
+    /// Ready chunk of the dataset holding the block, picked by the chunk preference when several chunks overlap
+    fn ready_chunk_holding<'a>(&self, registry: &'a HashMap<ChunkId, ChunkInfo>, dataset_id: &DatasetId, block_number: u64) -> Option<&'a ChunkInfo> {
+        let holding = registry.values().filter(|info| {
+            info.chunk.dataset_id == *dataset_id
+                && info.chunk.block_range.contains(&block_number)
+                && info.status == ChunkStatus::Ready
+        });
+        overlap::preferred(self.chunk_preference, holding)
+    }
+
+    /// Pin a ready chunk by its id
+    pub fn pin_ready_chunk(&self, chunk_id: &ChunkId) -> Option<(DataChunk, ChunkPin)> {
+        if !self.may_be_ready(chunk_id) {
+            return None;
+        }
+        let registry = self.registry.read().unwrap();
+        registry.get(chunk_id)
+            .filter(|info| info.status == ChunkStatus::Ready)
+            .map(|info| {
+                self.record_query(chunk_id);
+                (info.chunk.clone(), self.pins.pin(chunk_id))
+            })
+    }
+
+    /// Pin the ready chunks of a dataset overlapping the blocks, ordered by their blocks
+    pub fn pin_ready_chunks(&self, dataset_id: &DatasetId, block_range: &Range<u64>) -> Vec<(DataChunk, ChunkPin)> {
+        let registry = self.registry.read().unwrap();
+        let mut chunks: Vec<(DataChunk, ChunkPin)> = registry.values()
+            .filter(|info| {
+                info.chunk.dataset_id == *dataset_id
+                    && info.status == ChunkStatus::Ready
+                    && info.chunk.block_range.start < block_range.end
+                    && block_range.start < info.chunk.block_range.end
+            })
+            .map(|info| {
+                self.record_query(&info.chunk.id);
+                (info.chunk.clone(), self.pins.pin(&info.chunk.id))
+            })
+            .collect();
+        chunks.sort_by_key(|(chunk, _)| chunk.block_range.start);
+        chunks
+    }
+
+    fn record_query(&self, chunk_id: &ChunkId) {
+        self.last_queried.write().unwrap().insert(*chunk_id, Instant::now());
+    }
+
+    /// Ready chunks of a dataset in the order they should be evicted
+    pub fn eviction_order(&self, dataset_id: DatasetId, order: EvictionOrder) -> Vec<DataChunk> {
+        let registry = self.registry.read().unwrap();
+        let last_queried = self.last_queried.read().unwrap();
+        eviction::eviction_order(dataset_id, order, registry.values(), &last_queried)
+    }
+
+    /// Status of an epoch of a dataset from the current state of the registry
+    pub fn epoch_status(&self, layout: &EpochLayout, dataset_id: DatasetId, epoch: u64) -> EpochStatus {
+        let registry = self.registry.read().unwrap();
+        epoch::epoch_status(layout, dataset_id, epoch, registry.values())
+    }
+
+    /// Chunks of a dataset which aren't deleted and overlap the epoch
+    pub fn epoch_chunks(&self, layout: &EpochLayout, dataset_id: DatasetId, epoch: u64) -> Vec<ChunkInfo> {
+        let block_range = layout.block_range(epoch);
         self.registry.read().unwrap().values()
-            .find(|info|
-                {
-                    info.chunk.dataset_id == *dataset_id
-                        && info.chunk.block_range.contains(&block_number)
-                        && info.status == ChunkStatus::Ready
-                }
-            )
-            .map(|info| info.chunk.clone())
+            .filter(|info| info.chunk.dataset_id == dataset_id)
+            .filter(|info| info.chunk.block_range.start < block_range.end && block_range.start < info.chunk.block_range.end)
+            .filter(|info| !matches!(info.status, ChunkStatus::Deleted | ChunkStatus::Failed))
+            .cloned()
+            .collect()
     }
 
-    fn save_chunk_infos_to_parquet(chunk_infos: &[ChunkInfo], file_path: &str) {
+    /// Plan the sync of a dataset against its `manifest` from the current state of the registry
+    pub fn plan_sync(&self, dataset_id: DatasetId, manifest: &[DataChunk]) -> SyncPlan {
+        let registry = self.registry.read().unwrap();
+        sync_plan::plan_sync(dataset_id, manifest, registry.values().map(|info| (&info.chunk, &info.status)))
+    }
+}
+
+/// The registry is persisted as parquet, its columns can be scanned without reading the whole registry
+#[cfg(feature = "dataframes")]
+impl DataCatalogue {
+    fn save_chunk_infos(chunk_infos: &[ChunkInfo], file_path: impl AsRef<Path>) {
         let mut df = DataCatalogue::chunk_infos_to_dataframe(chunk_infos);
 
         let writer = std::fs::File::create(file_path).unwrap();
@@ -163,7 +834,8 @@ impl DataCatalogue {
         p_writer.finish(&mut df).unwrap();
     }
 
-    fn read_parquet_to_chunks(file_path: &str) -> Vec<ChunkInfo> {
+    #[cfg(test)]
+    fn read_chunk_infos(file_path: &str) -> Vec<ChunkInfo> {
         let reader = std::fs::File::open(file_path).unwrap();
         let p_reader = ParquetReader::new(reader);
         let df = p_reader.finish().unwrap();
@@ -171,28 +843,144 @@ impl DataCatalogue {
         DataCatalogue::dataframe_to_chunk_infos(df)
     }
 
-    fn dataframe_to_chunk_infos(df: DataFrame) -> Vec<ChunkInfo> {
+    fn read_not_ready_chunk_ids(file_path: impl AsRef<Path>) -> HashSet<ChunkId> {
+        DataCatalogue::read_chunk_ids_where(file_path, col("status").neq(lit(ChunkStatus::Ready.to_string())))
+    }
+
+    fn read_optimized_chunk_ids(file_path: impl AsRef<Path>) -> HashSet<ChunkId> {
+        DataCatalogue::read_chunk_ids_where(file_path, col("optimized"))
+    }
+
+    /// Read only the ids of chunks matching the predicate, when the registry was saved.
+    /// A missing registry, or one saved without the columns of the predicate, is treated as an empty one.
+    fn read_chunk_ids_where(file_path: impl AsRef<Path>, predicate: Expr) -> HashSet<ChunkId> {
+        let Ok(lazy_frame) = LazyFrame::scan_parquet(file_path, ScanArgsParquet::default()) else {
+            return HashSet::new();
+        };
+        let Ok(df) = lazy_frame
+            .filter(predicate)
+            .select([col("id")])
+            .collect() else {
+            return HashSet::new();
+        };
+        let ids = df.column("id").unwrap().str().unwrap();
+        ids.into_iter()
+            .flatten()
+            .filter_map(|id| hex::decode(id).ok()?.try_into().ok())
+            .collect()
+    }
+
+    /// Error histories of the persisted chunks, registries saved before the history was kept have none
+    fn read_chunk_errors(file_path: impl AsRef<Path>) -> HashMap<ChunkId, Vec<ChunkError>> {
+        let Ok(lazy_frame) = LazyFrame::scan_parquet(file_path, ScanArgsParquet::default()) else {
+            return HashMap::new();
+        };
+        let Ok(df) = lazy_frame
+            .filter(col("errors").neq(lit("[]")))
+            .select([col("id"), col("errors")])
+            .collect() else {
+            return HashMap::new();
+        };
+        let ids = df.column("id").unwrap().str().unwrap();
+        let errors = df.column("errors").unwrap().str().unwrap();
+        ids.into_iter()
+            .zip(errors)
+            .filter_map(|(id, errors)| {
+                let chunk_id: ChunkId = hex::decode(id?).ok()?.try_into().ok()?;
+                Some((chunk_id, chunk_errors::errors_from_json(errors?)))
+            })
+            .collect()
+    }
+
+    /// Lineage of the persisted chunks, registries saved before the lineage was kept have none
+    fn read_chunk_lineage(file_path: impl AsRef<Path>) -> HashMap<ChunkId, Vec<LineageEvent>> {
+        let Ok(lazy_frame) = LazyFrame::scan_parquet(file_path, ScanArgsParquet::default()) else {
+            return HashMap::new();
+        };
+        let Ok(df) = lazy_frame
+            .filter(col("lineage").neq(lit("[]")))
+            .select([col("id"), col("lineage")])
+            .collect() else {
+            return HashMap::new();
+        };
+        let ids = df.column("id").unwrap().str().unwrap();
+        let lineage = df.column("lineage").unwrap().str().unwrap();
+        ids.into_iter()
+            .zip(lineage)
+            .filter_map(|(id, lineage)| {
+                let chunk_id: ChunkId = hex::decode(id?).ok()?.try_into().ok()?;
+                Some((chunk_id, lineage::lineage_from_json(lineage?)))
+            })
+            .collect()
+    }
+
+    /// Chunk as it was persisted, including the chunks which weren't loaded as they're no longer held locally
+    pub(crate) fn read_persisted_chunk(file_path: impl AsRef<Path>, chunk_id: &ChunkId) -> Option<ChunkInfo> {
+        let df = LazyFrame::scan_parquet(file_path, ScanArgsParquet::default()).ok()?
+            .filter(col("id").eq(lit(hex::encode(chunk_id))))
+            .collect()
+            .ok()?;
+        DataCatalogue::dataframe_to_chunk_infos(df).pop()
+    }
+
+    /// Volumes of the relocated chunks, registries saved before the chunks could be relocated have none
+    pub(crate) fn read_chunk_volumes(file_path: impl AsRef<Path>) -> HashMap<ChunkId, PathBuf> {
+        let Ok(lazy_frame) = LazyFrame::scan_parquet(file_path, ScanArgsParquet::default()) else {
+            return HashMap::new();
+        };
+        let Ok(df) = lazy_frame
+            .filter(col("volume").neq(lit("")))
+            .select([col("id"), col("volume")])
+            .collect() else {
+            return HashMap::new();
+        };
+        let ids = df.column("id").unwrap().str().unwrap();
+        let volumes = df.column("volume").unwrap().str().unwrap();
+        ids.into_iter()
+            .zip(volumes)
+            .filter_map(|(id, volume)| {
+                let chunk_id: ChunkId = hex::decode(id?).ok()?.try_into().ok()?;
+                Some((chunk_id, PathBuf::from(volume?)))
+            })
+            .collect()
+    }
+
+    fn dataframe_to_chunk_infos(mut df: DataFrame) -> Vec<ChunkInfo> {
+        catalogue_compat::normalize_dataframe(&mut df, &CompatRules::default());
         let id = df.column("id").unwrap().str().unwrap();
         let dataset_id = df.column("dataset_id").unwrap().str().unwrap();
-        let block_form = df.column("block_form").unwrap().as_any().downcast_ref::<UInt64Chunked>().unwrap();
+        let block_from = df.column("block_from").unwrap().as_any().downcast_ref::<UInt64Chunked>().unwrap();
         let block_to = df.column("block_to").unwrap().as_any().downcast_ref::<UInt64Chunked>().unwrap();
         let files = df.column("files").unwrap().str().unwrap();
         let status = df.column("status").unwrap().str().unwrap();
+        // registries saved before the chunks were optimized don't have the column
+        let optimized = df.column("optimized").ok().map(|optimized| optimized.bool().unwrap());
+        let errors = df.column("errors").ok().map(|errors| errors.str().unwrap());
+        let volume = df.column("volume").ok().map(|volume| volume.str().unwrap());
+        let mirrors = df.column("mirrors").ok().map(|mirrors| mirrors.str().unwrap());
+        let size = df.column("size").ok().and_then(|size| size.as_any().downcast_ref::<UInt64Chunked>());
+        let file_sizes = df.column("file_sizes").ok().map(|file_sizes| file_sizes.str().unwrap());
+        let lineage = df.column("lineage").ok().map(|lineage| lineage.str().unwrap());
         (0..df.height())
             .map(|i| {
-                ChunkInfo {
-                    chunk: DataChunk {
+                let info = ChunkInfo::new(
+                    DataChunk {
                         id: hex::decode(id.get(i).unwrap()).unwrap().try_into().unwrap(),
                         dataset_id: hex::decode(dataset_id.get(i).unwrap()).unwrap().try_into().unwrap(),
-                        block_range: block_form.get(i).unwrap()..block_to.get(i).unwrap(),
+                        block_range: block_from.get(i).unwrap()..block_to.get(i).unwrap(),
                         files: serde_json::from_str(files.get(i).unwrap()).unwrap(),
+                        mirrors: mirrors.and_then(|mirrors| mirrors.get(i)).and_then(|mirrors| serde_json::from_str(mirrors).ok()).unwrap_or_default(),
+                        size: size.and_then(|size| size.get(i)),
+                        file_sizes: file_sizes.and_then(|file_sizes| file_sizes.get(i)).and_then(|file_sizes| serde_json::from_str(file_sizes).ok()).unwrap_or_default(),
                     },
-                    status: match status.get(i).unwrap() {
-                        "Downloading" => ChunkStatus::Downloading,
-                        "Ready" => ChunkStatus::Ready,
-                        "Deleting" => ChunkStatus::Deleting,
-                        _ => ChunkStatus::Deleted,
-                    }
+                    parse_status(status.get(i).unwrap()),
+                );
+                ChunkInfo {
+                    optimized: optimized.is_some_and(|optimized| optimized.get(i) == Some(true)),
+                    errors: errors.and_then(|errors| errors.get(i)).map(chunk_errors::errors_from_json).unwrap_or_default(),
+                    volume: volume.and_then(|volume| volume.get(i)).filter(|volume| !volume.is_empty()).map(PathBuf::from),
+                    lineage: lineage.and_then(|lineage| lineage.get(i)).map(lineage::lineage_from_json).unwrap_or_default(),
+                    ..info
                 }
             }).collect()
     }
@@ -201,21 +989,152 @@ impl DataCatalogue {
         df!(
             "id" => chunks.iter().map(|x| hex::encode(x.chunk.id)).collect::<Vec<String>>(),
             "dataset_id" => chunks.iter().map(|x| hex::encode(x.chunk.dataset_id)).collect::<Vec<String>>(),
-            "block_form" => chunks.iter().map(|x| x.chunk.block_range.start).collect::<Vec<u64>>(),
+            "block_from" => chunks.iter().map(|x| x.chunk.block_range.start).collect::<Vec<u64>>(),
             "block_to" => chunks.iter().map(|x| x.chunk.block_range.end).collect::<Vec<u64>>(),
             "files" => chunks.iter().map(|x| serde_json::to_string(&x.chunk.files).unwrap()).collect::<Vec<String>>(),
-            "status" => chunks.iter().map(|x| x.status.to_string()).collect::<Vec<String>>()
+            "mirrors" => chunks.iter().map(|x| serde_json::to_string(&x.chunk.mirrors).unwrap()).collect::<Vec<String>>(),
+            "size" => chunks.iter().map(|x| x.chunk.size).collect::<Vec<Option<u64>>>(),
+            "file_sizes" => chunks.iter().map(|x| serde_json::to_string(&x.chunk.file_sizes).unwrap()).collect::<Vec<String>>(),
+            "status" => chunks.iter().map(|x| x.status.to_string()).collect::<Vec<String>>(),
+            "optimized" => chunks.iter().map(|x| x.optimized).collect::<Vec<bool>>(),
+            "errors" => chunks.iter().map(|x| chunk_errors::errors_to_json(&x.errors)).collect::<Vec<String>>(),
+            "volume" => chunks.iter().map(|x| x.volume.as_ref().map(|volume| volume.display().to_string()).unwrap_or_default()).collect::<Vec<String>>(),
+            "lineage" => chunks.iter().map(|x| lineage::lineage_to_json(&x.lineage)).collect::<Vec<String>>()
         ).unwrap()
     }
 }
 
+/// Without the `dataframes` feature the registry is persisted as JSONL, a line per chunk with the columns of the parquet file
+#[cfg(not(feature = "dataframes"))]
+impl DataCatalogue {
+    fn save_chunk_infos(chunk_infos: &[ChunkInfo], file_path: impl AsRef<Path>) {
+        jsonl::write_rows(file_path.as_ref(), chunk_infos.iter().map(DataCatalogue::chunk_info_to_row)).unwrap();
+    }
+
+    #[cfg(test)]
+    fn read_chunk_infos(file_path: &str) -> Vec<ChunkInfo> {
+        DataCatalogue::read_persisted_chunks(file_path)
+    }
+
+    fn read_not_ready_chunk_ids(file_path: impl AsRef<Path>) -> HashSet<ChunkId> {
+        DataCatalogue::read_persisted_chunks(file_path).into_iter()
+            .filter(|info| info.status != ChunkStatus::Ready)
+            .map(|info| info.chunk.id)
+            .collect()
+    }
+
+    fn read_optimized_chunk_ids(file_path: impl AsRef<Path>) -> HashSet<ChunkId> {
+        DataCatalogue::read_persisted_chunks(file_path).into_iter()
+            .filter(|info| info.optimized)
+            .map(|info| info.chunk.id)
+            .collect()
+    }
+
+    /// Error histories of the persisted chunks
+    fn read_chunk_errors(file_path: impl AsRef<Path>) -> HashMap<ChunkId, Vec<ChunkError>> {
+        DataCatalogue::read_persisted_chunks(file_path).into_iter()
+            .filter(|info| !info.errors.is_empty())
+            .map(|info| (info.chunk.id, info.errors))
+            .collect()
+    }
+
+    /// Lineage of the persisted chunks
+    fn read_chunk_lineage(file_path: impl AsRef<Path>) -> HashMap<ChunkId, Vec<LineageEvent>> {
+        DataCatalogue::read_persisted_chunks(file_path).into_iter()
+            .filter(|info| !info.lineage.is_empty())
+            .map(|info| (info.chunk.id, info.lineage))
+            .collect()
+    }
+
+    /// Chunk as it was persisted, including the chunks which weren't loaded as they're no longer held locally
+    pub(crate) fn read_persisted_chunk(file_path: impl AsRef<Path>, chunk_id: &ChunkId) -> Option<ChunkInfo> {
+        DataCatalogue::read_persisted_chunks(file_path).into_iter().find(|info| info.chunk.id == *chunk_id)
+    }
+
+    /// Volumes of the relocated chunks
+    pub(crate) fn read_chunk_volumes(file_path: impl AsRef<Path>) -> HashMap<ChunkId, PathBuf> {
+        DataCatalogue::read_persisted_chunks(file_path).into_iter()
+            .filter_map(|info| Some((info.chunk.id, info.volume?)))
+            .collect()
+    }
+
+    /// A missing registry is treated as an empty one, and the lines which can't be read are skipped
+    fn read_persisted_chunks(file_path: impl AsRef<Path>) -> Vec<ChunkInfo> {
+        jsonl::read_rows(file_path.as_ref()).unwrap_or_default()
+            .iter()
+            .filter_map(DataCatalogue::row_to_chunk_info)
+            .collect()
+    }
+
+    fn row_to_chunk_info(row: &Row) -> Option<ChunkInfo> {
+        let mut row = row.clone();
+        catalogue_compat::normalize_row(&mut row, &CompatRules::default(), &mut Normalization::default());
+        let row = &row;
+        let info = ChunkInfo::new(
+            DataChunk {
+                id: hex::decode(jsonl::str_column(row, "id")?).ok()?.try_into().ok()?,
+                dataset_id: hex::decode(jsonl::str_column(row, "dataset_id")?).ok()?.try_into().ok()?,
+                block_range: jsonl::u64_column(row, "block_from")?..jsonl::u64_column(row, "block_to")?,
+                files: serde_json::from_str(jsonl::str_column(row, "files")?).ok()?,
+                mirrors: jsonl::str_column(row, "mirrors").and_then(|mirrors| serde_json::from_str(mirrors).ok()).unwrap_or_default(),
+                size: jsonl::u64_column(row, "size"),
+                file_sizes: jsonl::str_column(row, "file_sizes").and_then(|file_sizes| serde_json::from_str(file_sizes).ok()).unwrap_or_default(),
+            },
+            parse_status(jsonl::str_column(row, "status")?),
+        );
+        Some(ChunkInfo {
+            optimized: row.get("optimized").and_then(|optimized| optimized.as_bool()) == Some(true),
+            errors: jsonl::str_column(row, "errors").map(chunk_errors::errors_from_json).unwrap_or_default(),
+            volume: jsonl::str_column(row, "volume").filter(|volume| !volume.is_empty()).map(PathBuf::from),
+            lineage: jsonl::str_column(row, "lineage").map(lineage::lineage_from_json).unwrap_or_default(),
+            ..info
+        })
+    }
+
+    fn chunk_info_to_row(info: &ChunkInfo) -> Row {
+        let mut row = Row::new();
+        row.insert("id".to_string(), hex::encode(info.chunk.id).into());
+        row.insert("dataset_id".to_string(), hex::encode(info.chunk.dataset_id).into());
+        row.insert("block_from".to_string(), info.chunk.block_range.start.into());
+        row.insert("block_to".to_string(), info.chunk.block_range.end.into());
+        row.insert("files".to_string(), serde_json::to_string(&info.chunk.files).unwrap().into());
+        row.insert("mirrors".to_string(), serde_json::to_string(&info.chunk.mirrors).unwrap().into());
+        row.insert("size".to_string(), info.chunk.size.into());
+        row.insert("file_sizes".to_string(), serde_json::to_string(&info.chunk.file_sizes).unwrap().into());
+        row.insert("status".to_string(), info.status.to_string().into());
+        row.insert("optimized".to_string(), info.optimized.into());
+        row.insert("errors".to_string(), chunk_errors::errors_to_json(&info.errors).into());
+        row.insert("volume".to_string(), info.volume.as_ref().map(|volume| volume.display().to_string()).unwrap_or_default().into());
+        row.insert("lineage".to_string(), lineage::lineage_to_json(&info.lineage).into());
+        row
+    }
+}
+
+/// Status as persisted by its `Display`, or by an earlier version, see `CompatRules`.
+/// Statuses no rule knows are read as `Deleted`.
+fn parse_status(status: &str) -> ChunkStatus {
+    CompatRules::default().status(status).unwrap_or(ChunkStatus::Deleted)
+}
+
+#[cfg(test)]
+pub(crate) fn load_catalogue_with_local_chunks() {
+    use crate::local_data_source::{LocalDataSource, LOCAL_DATA_DIR};
+
+    // cleanup
+    let data_source = LocalDataSource::new(LOCAL_DATA_DIR.into());
+    let chunks = data_source.get_local_chunks();
+    let chunk_infos = chunks.iter().map(|chunk| ChunkInfo::new(chunk.clone(), ChunkStatus::Ready)).collect::<Vec<ChunkInfo>>();
+    DataCatalogue::save_chunk_infos(&chunk_infos, LOCAL_CATALOGUE);
+}
+
 #[cfg(test)]
 mod tests {
     use serial_test::serial;
     use crate::DataCatalogue;
-    use crate::data_catalogue::{ChunkInfo, LOCAL_CATALOGUE};
+    use crate::chunk_errors::ChunkErrorKind;
+    use crate::data_catalogue::{load_catalogue_with_local_chunks, ChunkInfo, ChunkStatus, LOCAL_CATALOGUE};
     use crate::local_data_source::{LocalDataSource, LOCAL_DATA_DIR};
-
+    
     #[test]
     fn test_get_chunk_id_from_dataset_and_block_range() {
         let dataset_id = [0u8; 32];
@@ -238,16 +1157,13 @@ mod tests {
         // Arrange
         std::fs::remove_file(LOCAL_CATALOGUE).unwrap();
         let data_source = LocalDataSource::new(LOCAL_DATA_DIR.into());
-        let chunk_infos = data_source.get_local_chunks().iter().map(|chunk| ChunkInfo {
-            chunk: chunk.clone(),
-            status: super::ChunkStatus::Ready,
-        }).collect::<Vec<ChunkInfo>>();
+        let chunk_infos = data_source.get_local_chunks().iter().map(|chunk| ChunkInfo::new(chunk.clone(), super::ChunkStatus::Ready)).collect::<Vec<ChunkInfo>>();
 
         // Act
-        DataCatalogue::save_chunk_infos_to_parquet(&chunk_infos, LOCAL_CATALOGUE);
+        DataCatalogue::save_chunk_infos(&chunk_infos, LOCAL_CATALOGUE);
 
         // Assert file exists in LOCAL_CATALOGUE
-        assert_eq!(std::path::Path::new(LOCAL_CATALOGUE).exists(), true);
+        assert!(std::path::Path::new(LOCAL_CATALOGUE).exists());
     }
 
     #[test]
@@ -256,33 +1172,130 @@ mod tests {
         // Arrange
         std::fs::remove_file(LOCAL_CATALOGUE).unwrap();
         let data_source = LocalDataSource::new(LOCAL_DATA_DIR.into());
-        let chunk_infos = data_source.get_local_chunks().iter().map(|chunk| ChunkInfo {
-            chunk: chunk.clone(),
-            status: super::ChunkStatus::Ready,
-        }).collect::<Vec<ChunkInfo>>();
-        DataCatalogue::save_chunk_infos_to_parquet(&chunk_infos, LOCAL_CATALOGUE);
+        let chunk_infos = data_source.get_local_chunks().iter().map(|chunk| ChunkInfo::new(chunk.clone(), super::ChunkStatus::Ready)).collect::<Vec<ChunkInfo>>();
+        DataCatalogue::save_chunk_infos(&chunk_infos, LOCAL_CATALOGUE);
 
         // Act
-        let actual = DataCatalogue::read_parquet_to_chunks(LOCAL_CATALOGUE);
+        let actual = DataCatalogue::read_chunk_infos(LOCAL_CATALOGUE);
 
         // Assert
         assert_eq!(actual.len(), 8);
-        assert_eq!(actual[0].chunk.id, [147, 161, 202, 94, 141, 129, 235, 161, 211, 123, 214, 159, 212, 119, 7, 59, 107, 144, 48, 224, 108, 245, 142, 139, 2, 173, 240, 231, 54, 58, 115, 159]);
-        assert_eq!(actual[0].chunk.dataset_id, [17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17]);
-        assert_eq!(actual[0].chunk.block_range, 0..35);
-        assert_eq!(actual[0].chunk.files.len(), 3);
-        assert_eq!(actual[0].chunk.files.get("part-1.parquet").unwrap(), "./local_data_dir/dataset_id=1111111111111111111111111111111111111111111111111111111111111111/block_range=0_35/part-1.parquet");
-        assert_eq!(actual[0].status, super::ChunkStatus::Ready);
+        let chunk_id = [147, 161, 202, 94, 141, 129, 235, 161, 211, 123, 214, 159, 212, 119, 7, 59, 107, 144, 48, 224, 108, 245, 142, 139, 2, 173, 240, 231, 54, 58, 115, 159];
+        let chunk_info = actual.iter().find(|info| info.chunk.id == chunk_id).unwrap();
+        assert_eq!(chunk_info.chunk.dataset_id, [17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17]);
+        assert_eq!(chunk_info.chunk.block_range, 0..35);
+        assert_eq!(chunk_info.chunk.files.len(), 3);
+        assert_eq!(chunk_info.chunk.files.get("part-1.parquet").unwrap(), "./local_data_dir/dataset_id=1111111111111111111111111111111111111111111111111111111111111111/block_range=0_35/part-1.parquet");
+        assert_eq!(chunk_info.status, super::ChunkStatus::Ready);
     }
-}
 
-pub(crate) fn load_catalogue_with_local_chunks() {
-    // cleanup
-    let data_source = LocalDataSource::new(LOCAL_DATA_DIR.into());
-    let chunks = data_source.get_local_chunks();
-    let chunk_infos = chunks.iter().map(|chunk| ChunkInfo {
-        chunk: chunk.clone(),
-        status: ChunkStatus::Ready,
-    }).collect::<Vec<ChunkInfo>>();
-    DataCatalogue::save_chunk_infos_to_parquet(&chunk_infos, LOCAL_CATALOGUE);
+    #[test]
+    #[serial]
+    fn test_not_ready_chunks_are_skipped_on_load() {
+        // Arrange
+        let data_source = LocalDataSource::new(LOCAL_DATA_DIR.into());
+        let chunks = data_source.get_local_chunks();
+        let chunk_infos = chunks.iter().enumerate().map(|(i, chunk)| ChunkInfo::new(chunk.clone(), if i == 0 { ChunkStatus::Downloading } else { ChunkStatus::Ready })).collect::<Vec<ChunkInfo>>();
+        DataCatalogue::save_chunk_infos(&chunk_infos, LOCAL_CATALOGUE);
+
+        // Act
+        let catalogue = DataCatalogue::new(data_source.scan_local_chunks(4));
+
+        // Assert
+        {
+            let registry = catalogue.registry.read().unwrap();
+            assert_eq!(registry.len(), 7);
+            assert!(!registry.contains_key(&chunks[0].id));
+        }
+
+        // cleanup
+        load_catalogue_with_local_chunks();
+    }
+
+    #[test]
+    #[serial]
+    fn test_ready_filter_follows_registry() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_source = LocalDataSource::new(LOCAL_DATA_DIR.into());
+        let catalogue = DataCatalogue::new(data_source.get_local_chunks());
+        let chunk = crate::local_data_source::get_test_chunk_111111_0_35();
+        assert!(catalogue.may_be_ready(&chunk.id));
+
+        // Act
+        catalogue.update_chunk(&chunk, &ChunkStatus::Deleting);
+
+        // Assert
+        assert!(!catalogue.may_be_ready(&chunk.id));
+        assert!(catalogue.pin_ready_chunk(&chunk.id).is_none());
+        catalogue.update_chunk(&chunk, &ChunkStatus::Ready);
+        assert!(catalogue.may_be_ready(&chunk.id));
+    }
+
+    #[test]
+    #[serial]
+    fn test_optimized_chunks_are_remembered_on_load() {
+        // Arrange
+        let data_source = LocalDataSource::new(LOCAL_DATA_DIR.into());
+        let chunk = crate::local_data_source::get_test_chunk_111111_0_35();
+        let chunk_infos = data_source.get_local_chunks().iter()
+            .map(|local_chunk| ChunkInfo { optimized: local_chunk.id == chunk.id, ..ChunkInfo::new(local_chunk.clone(), ChunkStatus::Ready) })
+            .collect::<Vec<ChunkInfo>>();
+        DataCatalogue::save_chunk_infos(&chunk_infos, LOCAL_CATALOGUE);
+
+        // Act
+        let catalogue = DataCatalogue::new(data_source.get_local_chunks());
+
+        // Assert
+        let registry = catalogue.registry.read().unwrap();
+        assert_eq!(registry.values().filter(|info| info.optimized).map(|info| info.chunk.id).collect::<Vec<_>>(), vec![chunk.id]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_optimized_flag_survives_status_changes() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_source = LocalDataSource::new(LOCAL_DATA_DIR.into());
+        let catalogue = DataCatalogue::new(data_source.get_local_chunks());
+        let chunk = crate::local_data_source::get_test_chunk_111111_0_35();
+        catalogue.mark_ready(&chunk, true);
+
+        // Act
+        catalogue.update_chunk(&chunk, &ChunkStatus::Deleting);
+        catalogue.update_chunk(&chunk, &ChunkStatus::Ready);
+
+        // Assert
+        assert!(catalogue.registry.read().unwrap()[&chunk.id].optimized);
+
+        // cleanup
+        load_catalogue_with_local_chunks();
+    }
+
+    #[test]
+    #[serial]
+    fn test_error_history_is_limited_and_remembered_on_load() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_source = LocalDataSource::new(LOCAL_DATA_DIR.into());
+        let catalogue = DataCatalogue::new(data_source.get_local_chunks()).with_error_history(2);
+        let chunk = crate::local_data_source::get_test_chunk_111111_0_35();
+
+        // Act
+        for message in ["first", "second", "third"] {
+            assert!(catalogue.record_error(&chunk.id, ChunkErrorKind::Delete, message));
+        }
+        catalogue.update_chunk(&chunk, &ChunkStatus::Ready);
+        let reloaded = DataCatalogue::new(data_source.get_local_chunks());
+
+        // Assert
+        for info in [catalogue.get_chunk_info(&chunk.id).unwrap(), reloaded.get_chunk_info(&chunk.id).unwrap()] {
+            assert_eq!(info.errors.iter().map(|error| error.message.as_str()).collect::<Vec<_>>(), vec!["second", "third"]);
+            assert_eq!(info.errors[0].kind, ChunkErrorKind::Delete);
+        }
+        assert!(!catalogue.record_error(&[0u8; 32], ChunkErrorKind::Download, "unknown chunk"));
+
+        // cleanup
+        load_catalogue_with_local_chunks();
+    }
 }
@@ -1,14 +1,30 @@
 use std::collections::HashMap;
 use std::ops::Range;
+#[cfg(feature = "runtime")]
+use std::fs::File;
+#[cfg(feature = "runtime")]
+use std::ops::Deref;
+#[cfg(feature = "runtime")]
 use std::path::{Path, PathBuf};
-use crate::local_data_source::LOCAL_DATA_DIR;
+#[cfg(feature = "runtime")]
+use std::time::Instant;
+#[cfg(feature = "runtime")]
+use memmap2::Mmap;
+#[cfg(feature = "runtime")]
+use crate::chunk_pins::ChunkPin;
+use crate::planning;
+#[cfg(feature = "runtime")]
+use crate::planning::DirectoryLayout;
 
 pub type DatasetId = [u8; 32];
 pub type ChunkId = [u8; 32];
 
+/// Column of the parquet files holding the block number of a row
+pub const BLOCK_NUMBER_COLUMN: &str = "block_number";
+
 
 /// data chunk description
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct DataChunk {
     pub id: ChunkId,
     /// Dataset (blockchain) id
@@ -19,36 +35,177 @@ pub struct DataChunk {
     /// A mapping between file names and HTTP URLs to download files from.
     /// Usually contains 1 - 10 files of various sizes.
     /// The total size of all files in the chunk is about 200 MB.
-    pub files: HashMap<String, String>
+    pub files: HashMap<String, String>,
+    /// Mirror URLs of the files by file name, tried in order when the URL in `files` fails.
+    /// Files without mirrors are downloaded from their URL only.
+    pub mirrors: HashMap<String, Vec<String>>,
+    /// Bytes of all the files as declared by the manifest, checked once the files are downloaded.
+    /// `None` when the manifest doesn't declare it.
+    pub size: Option<u64>,
+    /// Bytes of the files by file name as declared by the manifest, files without one aren't checked
+    pub file_sizes: HashMap<String, u64>,
+}
+
+impl DataChunk {
+    /// Chunk of the dataset with the id generated from the block range and nothing else,
+    /// e.g. to be completed with `DataChunk { files, ..DataChunk::new(dataset_id, block_range) }`
+    pub fn new(dataset_id: DatasetId, block_range: Range<u64>) -> Self {
+        DataChunk { id: planning::generate_chunk_id(&dataset_id, &block_range), dataset_id, block_range, ..Default::default() }
+    }
+
+    /// The chunk with the files downloaded from `https://example.com/<file name>`
+    #[cfg(test)]
+    pub(crate) fn with_example_files(mut self, file_names: &[&str]) -> Self {
+        self.files = file_names.iter().map(|file_name| (file_name.to_string(), format!("https://example.com/{}", file_name))).collect();
+        self
+    }
+
+    /// Candidate URLs of the file, its URL in `files` followed by its mirrors
+    pub fn file_urls(&self, file_name: &str) -> Vec<&str> {
+        self.files.get(file_name)
+            .into_iter()
+            .chain(self.mirrors.get(file_name).into_iter().flatten())
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// A file name which isn't a single plain file name, so it would be written outside the chunk directory
+    pub fn invalid_file_name(&self) -> Option<&str> {
+        self.files.keys().map(String::as_str).find(|file_name| !is_plain_file_name(file_name))
+    }
+
+    /// Bytes the files of the chunk take once downloaded, the declared total or the sum of the declared file sizes
+    /// when every file has one, e.g. for the quota checks before the download
+    pub fn expected_size(&self) -> Option<u64> {
+        let every_file_sized = !self.files.is_empty() && self.files.keys().all(|file_name| self.file_sizes.contains_key(file_name));
+        self.size.or_else(|| every_file_sized.then(|| self.files.keys().map(|file_name| self.file_sizes[file_name]).sum()))
+    }
+}
+
+/// Whether the name is a single plain file name, which stays in the chunk directory once joined to it,
+/// rather than e.g. an empty name, an absolute path, which replaces the directory, or `../x`
+pub fn is_plain_file_name(file_name: &str) -> bool {
+    let mut components = std::path::Path::new(file_name).components();
+    matches!((components.next(), components.next()), (Some(std::path::Component::Normal(_)), None))
 }
 
+#[cfg(feature = "runtime")]
+/// Where the chunk found by `find_chunk` lives
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChunkSource {
+    /// Managed by this data manager in its data directory
+    Local,
+    /// Found in a read-only secondary catalogue with the given name, not managed by this data manager
+    Secondary(String),
+}
+
+#[cfg(feature = "runtime")]
 /// Data chunk path
 #[derive(Clone, Debug, PartialEq)]
 pub struct DataChunkPath {
     pub chunk: DataChunk,
     pub path: PathBuf,
+    pub source: ChunkSource,
+    /// keeps the chunk from being deleted while the reference is alive
+    pin: Option<ChunkPin>,
 }
 
+#[cfg(feature = "runtime")]
 impl DataChunkPath {
-    pub fn new(chunk: DataChunk) -> Self {
-        let path = PathBuf::from(format!(
-            "{}/dataset_id={}/block_range={}_{}/",
-            LOCAL_DATA_DIR,
-            hex::encode(chunk.dataset_id),
-            chunk.block_range.start,
-            chunk.block_range.end
-        ));
-        DataChunkPath { chunk, path }
+    pub fn new(data_dir: &Path, layout: DirectoryLayout, chunk: DataChunk) -> Self {
+        let path = PathBuf::from(format!("{}/{}/", data_dir.display(), layout.chunk_dir(&chunk.dataset_id, &chunk.block_range)));
+        DataChunkPath { chunk, path, source: ChunkSource::Local, pin: None }
+    }
+
+    /// Path of a chunk in the data directory of a secondary catalogue
+    pub fn secondary(catalogue_name: &str, data_dir: &Path, layout: DirectoryLayout, chunk: DataChunk) -> Self {
+        DataChunkPath {
+            source: ChunkSource::Secondary(catalogue_name.to_string()),
+            ..Self::new(data_dir, layout, chunk)
+        }
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.source == ChunkSource::Local
+    }
+
+    /// Time the files of the chunk are removed at while it's still held, see `DataManagerConfig::deletion_grace`
+    pub fn deprecated_until(&self) -> Option<Instant> {
+        self.pin.as_ref()?.deprecated_until()
+    }
+
+    /// Path of the chunk, which stays on disk until the reference is dropped
+    pub fn pinned(data_dir: &Path, layout: DirectoryLayout, chunk: DataChunk, pin: ChunkPin) -> Self {
+        DataChunkPath {
+            pin: Some(pin),
+            ..Self::new(data_dir, layout, chunk)
+        }
+    }
+}
+
+#[cfg(feature = "runtime")]
+/// Memory mapped file of a chunk.
+/// The chunk is pinned, so the mapping stays valid until this is dropped.
+pub struct MappedChunkFile {
+    contents: FileContents,
+    path: PathBuf,
+    _pin: ChunkPin,
+}
+
+#[cfg(feature = "runtime")]
+enum FileContents {
+    Mapped(Mmap),
+    /// Fetched from the origin, as the local file was missing or corrupt
+    Fetched(Vec<u8>),
+}
+
+#[cfg(feature = "runtime")]
+impl MappedChunkFile {
+    pub fn open(path: PathBuf, pin: ChunkPin) -> std::io::Result<Self> {
+        let file = File::open(&path)?;
+        // SAFETY: the chunk is pinned, so the manager doesn't modify or delete the file while it's mapped
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(MappedChunkFile { contents: FileContents::Mapped(mmap), path, _pin: pin })
+    }
+
+    /// File read from its origin URL instead of the local `path`
+    pub fn fetched(path: PathBuf, bytes: Vec<u8>, pin: ChunkPin) -> Self {
+        MappedChunkFile { contents: FileContents::Fetched(bytes), path, _pin: pin }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_fetched(&self) -> bool {
+        matches!(self.contents, FileContents::Fetched(_))
+    }
+}
+
+#[cfg(feature = "runtime")]
+impl Deref for MappedChunkFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.contents {
+            FileContents::Mapped(mmap) => mmap,
+            FileContents::Fetched(bytes) => bytes,
+        }
     }
 }
 
 
-// Data chunk must remain available and untouched till this reference is not dropped
+#[cfg(feature = "runtime")]
+// Data chunk must remain available and untouched till this reference is not dropped.
+// The path is stable for the lifetime of the reference: the chunk is neither deleted nor relocated
+// while it's held, `relocate_chunk` refuses to move it until all the references are dropped.
+// Once dropped, the path may change, so the chunk has to be looked up again rather than the path kept.
 pub trait DataChunkRef: Send + Sync + Clone {
-    // Data chunk directory
+    // Data chunk directory, valid for the lifetime of the reference
     fn path(&self) -> &Path;
 }
 
+#[cfg(feature = "runtime")]
 impl DataChunkRef for DataChunkPath {
     fn path(&self) -> &Path {
         &self.path
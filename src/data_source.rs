@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::fs;
+use std::time::Instant;
+use crate::data_chunk::DataChunk;
+use crate::operation::{OperationKind, OperationResult};
+use crate::planning::{parse_block_range_dir_name, DirectoryLayout};
+use crate::transfer::{self, ChunkTransfer, SimulatedTransfer};
+
+pub use crate::local_data_source::LocalDataSource;
+
+/// Storage backend holding the chunks, laid out as `dataset_id=<id>/block_range=<start>_<end>/<file>`
+pub trait DataSource: Send + Sync {
+    /// Download the files of the chunk into the storage
+    fn download_chunk(&self, chunk: DataChunk) -> io::Result<OperationResult>;
+
+    fn delete_chunk(&self, chunk: &DataChunk) -> io::Result<OperationResult>;
+
+    /// Chunks held by the storage, ordered by dataset id and block range
+    fn list_local_chunks(&self) -> Vec<DataChunk>;
+
+    /// Take over the files of the chunk the data manager downloaded and verified in the staging directory.
+    /// The staging directory is gone once the chunk is stored.
+    fn publish_staged_chunk(&self, chunk: &DataChunk, staging_dir: &Path) -> io::Result<()>;
+
+    /// Whether files of the chunk may be stored, so a cancelled download is only deleted when there is something to delete.
+    /// A storage which can't tell it cheaply answers `true`.
+    fn may_hold_chunk(&self, _chunk: &DataChunk) -> bool {
+        true
+    }
+}
+
+impl DataSource for LocalDataSource {
+    fn download_chunk(&self, chunk: DataChunk) -> io::Result<OperationResult> {
+        LocalDataSource::download_chunk(self, chunk)
+    }
+
+    fn delete_chunk(&self, chunk: &DataChunk) -> io::Result<OperationResult> {
+        LocalDataSource::delete_chunk(self, chunk)
+    }
+
+    fn list_local_chunks(&self) -> Vec<DataChunk> {
+        self.get_local_chunks()
+    }
+
+    fn publish_staged_chunk(&self, chunk: &DataChunk, staging_dir: &Path) -> io::Result<()> {
+        LocalDataSource::publish_staged_chunk(self, chunk, staging_dir)
+    }
+
+    fn may_hold_chunk(&self, chunk: &DataChunk) -> bool {
+        self.chunk_path(chunk.clone()).path.exists()
+    }
+}
+
+/// Objects of a bucket, implemented by the caller on top of the client of their object storage,
+/// e.g. an S3 client sending `PutObject`, `DeleteObject` and `ListObjectsV2`. `AzureBlobStore` is the only one included.
+pub trait ObjectStore: Send + Sync {
+    /// Store the `content_length` bytes read from the body, which streams the file being uploaded,
+    /// e.g. as the body of a single request or as the parts of a multipart upload
+    fn put_object(&self, bucket: &str, key: &str, body: &mut dyn Read, content_length: u64) -> io::Result<()>;
+
+    fn delete_object(&self, bucket: &str, key: &str) -> io::Result<()>;
+
+    /// Keys of all the objects starting with the prefix, across all the pages of the listing
+    fn list_objects(&self, bucket: &str, prefix: &str) -> io::Result<Vec<String>>;
+
+    /// URL of the object, the files of the listed chunks point to it
+    fn object_url(&self, bucket: &str, key: &str) -> String {
+        format!("s3://{}/{}", bucket, key)
+    }
+}
+
+/// Generic adapter keeping the chunks in a bucket of any object storage under the prefix, with the same layout
+/// as the local data directory. It has no client of its own: the requests, their signing and the endpoint
+/// are up to the `ObjectStore` it's given.
+/// Files are downloaded by the transfer into a scratch directory, uploaded and removed from it.
+#[derive(Clone)]
+pub struct ObjectStoreDataSource {
+    store: Arc<dyn ObjectStore>,
+    /// Bucket, or container of Azure Blob Storage
+    pub bucket: String,
+    /// Key prefix of the chunks, e.g. `datasets/`, empty for the root of the bucket
+    pub prefix: String,
+    pub layout: DirectoryLayout,
+    /// Remote storage the chunks are downloaded from
+    pub transfer: Arc<dyn ChunkTransfer>,
+    pub scratch_dir: PathBuf,
+}
+
+impl ObjectStoreDataSource {
+    pub fn new(store: Arc<dyn ObjectStore>, bucket: impl Into<String>, prefix: impl Into<String>) -> Self {
+        ObjectStoreDataSource {
+            store,
+            bucket: bucket.into(),
+            prefix: prefix.into(),
+            layout: DirectoryLayout::default(),
+            transfer: Arc::new(SimulatedTransfer),
+            scratch_dir: std::env::temp_dir().join("data_manager_scratch"),
+        }
+    }
+
+    pub fn with_layout(mut self, layout: DirectoryLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn with_transfer(mut self, transfer: Arc<dyn ChunkTransfer>) -> Self {
+        self.transfer = transfer;
+        self
+    }
+
+    pub fn with_scratch_dir(mut self, scratch_dir: PathBuf) -> Self {
+        self.scratch_dir = scratch_dir;
+        self
+    }
+
+    /// Key prefix of the chunk, ending with `/`
+    pub fn chunk_prefix(&self, chunk: &DataChunk) -> String {
+        format!("{}{}/", self.prefix, self.layout.chunk_dir(&chunk.dataset_id, &chunk.block_range))
+    }
+
+    /// Upload the files of the directory, returns their size.
+    /// Every file is streamed from the disk, so it's never held in memory as a whole.
+    fn upload_dir(&self, chunk: &DataChunk, dir: &Path) -> io::Result<u64> {
+        let chunk_prefix = self.chunk_prefix(chunk);
+        let mut bytes = 0;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                let key = format!("{}{}", chunk_prefix, entry.file_name().to_string_lossy());
+                let mut file = fs::File::open(entry.path())?;
+                let content_length = file.metadata()?.len();
+                self.store.put_object(&self.bucket, &key, &mut file, content_length)?;
+                bytes += content_length;
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Read the key as a file of a chunk, `None` for the keys outside of the layout
+    fn parse_key(&self, key: &str) -> Option<(DataChunk, String)> {
+        let relative_key = key.strip_prefix(&self.prefix)?;
+        let (chunk_dir, file_name) = relative_key.rsplit_once('/')?;
+        let (dataset_dir, block_range_dir) = chunk_dir.rsplit_once('/')?;
+        let dataset_id = self.layout.parse_dataset_dir(dataset_dir)?;
+        let block_range = parse_block_range_dir_name(block_range_dir)?;
+        let chunk = DataChunk::new(dataset_id, block_range);
+        Some((chunk, file_name.to_string()))
+    }
+}
+
+impl DataSource for ObjectStoreDataSource {
+    fn download_chunk(&self, chunk: DataChunk) -> io::Result<OperationResult> {
+        let started_at = Instant::now();
+        transfer::check_file_names(&chunk)?;
+        let staging_dir = self.scratch_dir.join(hex::encode(chunk.id));
+        fs::create_dir_all(&staging_dir)?;
+        let uploaded = self.transfer.download(&chunk, &staging_dir).and_then(|_| self.upload_dir(&chunk, &staging_dir));
+        // the scratch copy is not needed anymore, whether the upload succeeded or not
+        let _ = fs::remove_dir_all(&staging_dir);
+        let bytes = uploaded?;
+        Ok(OperationResult::succeeded(OperationKind::Download, chunk.id, started_at, Some(bytes)))
+    }
+
+    /// The size of the deleted objects isn't known, the store doesn't list it
+    fn delete_chunk(&self, chunk: &DataChunk) -> io::Result<OperationResult> {
+        let started_at = Instant::now();
+        for key in self.store.list_objects(&self.bucket, &self.chunk_prefix(chunk))? {
+            self.store.delete_object(&self.bucket, &key)?;
+        }
+        Ok(OperationResult::succeeded(OperationKind::Delete, chunk.id, started_at, None))
+    }
+
+    /// Chunks of the keys under the prefix, a listing failure is reported as no chunks
+    fn list_local_chunks(&self) -> Vec<DataChunk> {
+        let keys = self.store.list_objects(&self.bucket, &self.prefix).unwrap_or_default();
+        let mut chunks: HashMap<_, DataChunk> = HashMap::new();
+        for key in keys {
+            let Some((chunk, file_name)) = self.parse_key(&key) else { continue };
+            let chunk = chunks.entry(chunk.id).or_insert(chunk);
+            chunk.files.insert(file_name, self.store.object_url(&self.bucket, &key));
+        }
+        let mut chunks: Vec<DataChunk> = chunks.into_values().collect();
+        chunks.sort_by_key(|chunk| (chunk.dataset_id, chunk.block_range.start, chunk.block_range.end));
+        chunks
+    }
+
+    /// The staged files are uploaded, and removed whether the upload succeeded or not
+    fn publish_staged_chunk(&self, chunk: &DataChunk, staging_dir: &Path) -> io::Result<()> {
+        let uploaded = self.upload_dir(chunk, staging_dir);
+        let _ = fs::remove_dir_all(staging_dir);
+        uploaded.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use crate::cancellation::Cancellations;
+    use crate::config::DataManagerConfig;
+    use crate::data_catalogue::ChunkStatus;
+    use crate::data_chunk::ChunkId;
+    use crate::data_manager::DataManager;
+    use crate::download_handle::DownloadError;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// Bucket kept in memory, by bucket and key
+    #[derive(Default)]
+    struct InMemoryStore {
+        objects: Mutex<BTreeMap<(String, String), Vec<u8>>>,
+        cancel_on_upload: Mutex<Option<(Cancellations, ChunkId)>>,
+    }
+
+    impl ObjectStore for InMemoryStore {
+        fn put_object(&self, bucket: &str, key: &str, body: &mut dyn Read, content_length: u64) -> io::Result<()> {
+            let mut object = Vec::new();
+            body.read_to_end(&mut object)?;
+            assert_eq!(object.len() as u64, content_length);
+            self.objects.lock().unwrap().insert((bucket.to_string(), key.to_string()), object);
+            // a cancellation arriving once the chunk is uploaded
+            if let Some((cancellations, chunk_id)) = self.cancel_on_upload.lock().unwrap().as_ref() {
+                cancellations.cancel(*chunk_id);
+            }
+            Ok(())
+        }
+
+        fn delete_object(&self, bucket: &str, key: &str) -> io::Result<()> {
+            self.objects.lock().unwrap().remove(&(bucket.to_string(), key.to_string()));
+            Ok(())
+        }
+
+        fn list_objects(&self, bucket: &str, prefix: &str) -> io::Result<Vec<String>> {
+            Ok(self.objects.lock().unwrap().keys()
+                .filter(|(object_bucket, key)| object_bucket == bucket && key.starts_with(prefix))
+                .map(|(_, key)| key.clone())
+                .collect())
+        }
+    }
+
+    struct FileTransfer;
+
+    impl ChunkTransfer for FileTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            for file_name in chunk.files.keys() {
+                fs::write(chunk_dir.join(file_name), format!("{}-{}", chunk.block_range.start, file_name))?;
+            }
+            Ok(())
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    fn chunk(dataset: u8, block_range: std::ops::Range<u64>) -> DataChunk {
+        DataChunk {
+            files: HashMap::from([("blocks.parquet".to_string(), "https://origin/blocks.parquet".to_string())]),
+            ..DataChunk::new([dataset; 32], block_range)
+        }
+    }
+
+    #[test]
+    fn test_chunks_are_stored_in_the_bucket_layout() {
+        // Arrange
+        let store = Arc::new(InMemoryStore::default());
+        let scratch_dir = test_support::temp_dir("s3");
+        let data_source = ObjectStoreDataSource::new(store.clone(), "chunks", "datasets/")
+            .with_transfer(Arc::new(FileTransfer))
+            .with_scratch_dir(scratch_dir.clone());
+        let (first, second) = (chunk(1, 0..10), chunk(2, 10..20));
+
+        // Act
+        data_source.download_chunk(second.clone()).unwrap();
+        let result = data_source.download_chunk(first.clone()).unwrap();
+
+        // Assert
+        let key = format!("datasets/dataset_id={}/block_range=0_10/blocks.parquet", hex::encode(first.dataset_id));
+        assert_eq!(store.objects.lock().unwrap().get(&("chunks".to_string(), key.clone())), Some(&b"0-blocks.parquet".to_vec()));
+        let listed = data_source.list_local_chunks();
+        assert_eq!(listed.iter().map(|chunk| chunk.id).collect::<Vec<_>>(), vec![first.id, second.id]);
+        assert_eq!(listed[0].files["blocks.parquet"], format!("s3://chunks/{}", key));
+        assert!(!scratch_dir.join(hex::encode(first.id)).exists());
+        assert_eq!((result.kind, result.chunk_id, result.bytes), (OperationKind::Download, first.id, Some(16)));
+    }
+
+    #[test]
+    fn test_data_manager_keeps_chunks_in_the_bucket() {
+        // Arrange
+        let dir = test_support::temp_dir("s3_backend");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let store = Arc::new(InMemoryStore::default());
+        let build = || DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(FileTransfer))
+            .data_source(Arc::new(ObjectStoreDataSource::new(store.clone(), "chunks", "")))
+            .build();
+        let data_manager = build();
+        let (kept, deleted) = (chunk(1, 0..10), chunk(1, 10..20));
+
+        // Act
+        let downloaded = [kept.clone(), deleted.clone()].map(|chunk| data_manager.download_chunk_with_handle(chunk).wait());
+        data_manager.delete_chunk(deleted.id);
+        let deleted_status = data_manager.data_catalogue.wait_until_settled_for(&deleted.id, Duration::from_secs(5));
+        drop(data_manager);
+        let restarted = build();
+
+        // Assert
+        assert_eq!(downloaded, [Ok(()), Ok(())]);
+        assert_eq!(deleted_status, Some(ChunkStatus::Deleted));
+        let keys: Vec<String> = store.objects.lock().unwrap().keys().map(|(_, key)| key.clone()).collect();
+        assert_eq!(keys, vec![format!("dataset_id={}/block_range=0_10/blocks.parquet", hex::encode(kept.dataset_id))]);
+        assert!(!restarted.data_source.chunk_path(kept.clone()).path.exists());
+        assert_eq!(restarted.get_chunk_info(kept.id).map(|info| info.status), Some(ChunkStatus::Ready));
+
+        // cleanup
+        drop(restarted);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_deleted_chunk_is_removed_from_the_bucket() {
+        // Arrange
+        let store = Arc::new(InMemoryStore::default());
+        let data_source: Box<dyn DataSource> = Box::new(ObjectStoreDataSource::new(store.clone(), "chunks", "")
+            .with_transfer(Arc::new(FileTransfer))
+            .with_scratch_dir(test_support::temp_dir("s3_delete")));
+        let (kept, deleted) = (chunk(1, 0..10), chunk(1, 10..20));
+        data_source.download_chunk(kept.clone()).unwrap();
+        data_source.download_chunk(deleted.clone()).unwrap();
+        store.put_object("chunks", "README.md", &mut &b"not a chunk"[..], 11).unwrap();
+
+        // Act
+        data_source.delete_chunk(&deleted).unwrap();
+
+        // Assert
+        assert_eq!(data_source.list_local_chunks().iter().map(|chunk| chunk.id).collect::<Vec<_>>(), vec![kept.id]);
+        assert_eq!(store.objects.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_download_cancelled_once_uploaded_is_removed_from_the_bucket() {
+        // Arrange
+        let dir = test_support::temp_dir("s3_cancel");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let store = Arc::new(InMemoryStore::default());
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(FileTransfer))
+            .data_source(Arc::new(ObjectStoreDataSource::new(store.clone(), "chunks", "")))
+            .build();
+        let cancelled = chunk(1, 0..10);
+        *store.cancel_on_upload.lock().unwrap() = Some((data_manager.cancellations.clone(), cancelled.id));
+
+        // Act
+        let result = data_manager.download_chunk_with_handle(cancelled.clone()).wait();
+
+        // Assert
+        assert_eq!(result, Err(DownloadError::Cancelled));
+        assert_eq!(data_manager.get_chunk_info(cancelled.id).map(|info| info.status), Some(ChunkStatus::Deleted));
+        assert!(store.objects.lock().unwrap().is_empty());
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::data_catalogue::ChunkStatus;
+use crate::data_chunk::DataChunk;
+use crate::hooks::LifecycleEvent;
+use crate::workers::Workers;
+
+/// Download requested to be ready by a deadline
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Deadline {
+    at: Instant,
+    /// How long after the request the chunk was needed
+    within: Duration,
+    chunk: DataChunk,
+}
+
+impl Ord for Deadline {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.at.cmp(&other.at).then_with(|| self.chunk.id.cmp(&other.chunk.id))
+    }
+}
+
+impl PartialOrd for Deadline {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Background job checking the chunks which were needed by a deadline.
+/// A chunk which isn't `Ready` by then is reported with `on_deadline_missed` hooks
+/// and its download is moved ahead of the other downloads waiting for a slot.
+/// The job stops when the monitor is dropped.
+pub struct DeadlineMonitor {
+    deadlines: mpsc::Sender<Deadline>,
+}
+
+impl DeadlineMonitor {
+    pub(crate) fn start(workers: Workers) -> Self {
+        let (deadlines, requested) = mpsc::channel::<Deadline>();
+        thread::spawn(move || {
+            let mut pending: BinaryHeap<Reverse<Deadline>> = BinaryHeap::new();
+            loop {
+                let timeout = pending.peek().map(|Reverse(next)| next.at.saturating_duration_since(Instant::now()));
+                let received = match timeout {
+                    Some(timeout) => requested.recv_timeout(timeout),
+                    None => requested.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match received {
+                    Ok(deadline) => pending.push(Reverse(deadline)),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                while pending.peek().is_some_and(|Reverse(next)| next.at <= Instant::now()) {
+                    let Reverse(deadline) = pending.pop().unwrap();
+                    check_deadline(&workers, deadline.chunk, deadline.within);
+                }
+            }
+        });
+        DeadlineMonitor { deadlines }
+    }
+
+    /// Check that the chunk is `Ready` within the given time from now
+    pub fn watch(&self, chunk: DataChunk, within: Duration) {
+        // the job only stops together with the monitor
+        let _ = self.deadlines.send(Deadline { at: Instant::now() + within, within, chunk });
+    }
+}
+
+/// Report the chunk and escalate its download, when it isn't ready. Returns whether the deadline was missed.
+pub(crate) fn check_deadline(workers: &Workers, chunk: DataChunk, within: Duration) -> bool {
+    let status = workers.data_catalogue.get_chunk_info(&chunk.id).map(|info| info.status);
+    if status == Some(ChunkStatus::Ready) {
+        return false;
+    }
+    // the download waits either for a thread of the pool, or for a slot of the queue once it has the thread
+    workers.download_pool.escalate(&chunk.id);
+    if let Some(download_queue) = &workers.download_queue {
+        download_queue.escalate(&chunk.id);
+    }
+    workers.hooks.emit(LifecycleEvent::DeadlineMissed(chunk, within));
+    true
+}
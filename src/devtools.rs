@@ -0,0 +1,132 @@
+//! Generator of synthetic datasets in a data directory, for tests and performance measurements.
+//!
+//! The chunks get parquet files with rows for every block of their range, laid out like downloaded chunks,
+//! so the data manager picks them up by the scan of the data directory.
+//! The checked-in `./local_data_dir` stays next to it for the tests asserting the exact ids and paths of its chunks.
+use std::fs::{self, File};
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use polars::prelude::*;
+use crate::data_chunk::{DataChunk, DatasetId, BLOCK_NUMBER_COLUMN};
+use crate::local_data_source::LocalDataSource;
+use crate::planning::DirectoryLayout;
+
+/// A parquet file of every generated chunk
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileSpec {
+    pub name: String,
+    pub rows_per_block: usize,
+    /// Length of the `payload` column of a row, to get files of realistic sizes
+    pub payload_bytes: usize,
+}
+
+impl FileSpec {
+    pub fn new(name: &str, rows_per_block: usize, payload_bytes: usize) -> Self {
+        FileSpec { name: name.to_string(), rows_per_block, payload_bytes }
+    }
+}
+
+/// Chunks of a dataset to generate
+#[derive(Clone, Debug, PartialEq)]
+pub struct DatasetSpec {
+    pub dataset_id: DatasetId,
+    pub block_ranges: Vec<Range<u64>>,
+    pub files: Vec<FileSpec>,
+}
+
+impl DatasetSpec {
+    /// `chunks` consecutive chunks of `blocks_per_chunk` blocks from `first_block`,
+    /// with a blocks file and a transactions file
+    pub fn new(dataset_id: DatasetId, first_block: u64, chunks: u64, blocks_per_chunk: u64) -> Self {
+        DatasetSpec {
+            dataset_id,
+            block_ranges: (0..chunks)
+                .map(|chunk| first_block + chunk * blocks_per_chunk..first_block + (chunk + 1) * blocks_per_chunk)
+                .collect(),
+            files: vec![FileSpec::new("blocks.parquet", 1, 64), FileSpec::new("transactions.parquet", 10, 256)],
+        }
+    }
+
+    pub fn with_files(mut self, files: Vec<FileSpec>) -> Self {
+        self.files = files;
+        self
+    }
+}
+
+/// Write the chunks of the datasets into the data directory, returns the generated chunks.
+/// The content only depends on the specs, so the same specs always generate the same files.
+pub fn generate(data_dir: &Path, layout: DirectoryLayout, datasets: &[DatasetSpec]) -> io::Result<Vec<DataChunk>> {
+    let data_source = LocalDataSource::new(data_dir.to_path_buf()).with_layout(layout);
+    let mut chunks = Vec::new();
+    for dataset in datasets {
+        for block_range in dataset.block_ranges.iter() {
+            let mut chunk = DataChunk::new(dataset.dataset_id, block_range.clone());
+            let chunk_dir = data_source.chunk_path(chunk.clone()).path;
+            fs::create_dir_all(&chunk_dir)?;
+            for file in dataset.files.iter() {
+                let path = chunk_dir.join(&file.name);
+                let mut rows = generate_rows(block_range, file).map_err(polars_error)?;
+                ParquetWriter::new(File::create(&path)?).finish(&mut rows).map_err(polars_error)?;
+                chunk.files.insert(file.name.clone(), path.display().to_string());
+            }
+            chunks.push(chunk);
+        }
+    }
+    Ok(chunks)
+}
+
+fn generate_rows(block_range: &Range<u64>, file: &FileSpec) -> PolarsResult<DataFrame> {
+    let rows = (block_range.end - block_range.start) as usize * file.rows_per_block;
+    let block_numbers: Vec<u64> = block_range.clone()
+        .flat_map(|block_number| std::iter::repeat_n(block_number, file.rows_per_block))
+        .collect();
+    let indexes: Vec<u32> = (0..rows).map(|row| (row % file.rows_per_block.max(1)) as u32).collect();
+    let hashes: Vec<String> = block_numbers.iter().zip(indexes.iter())
+        .map(|(block_number, index)| format!("0x{}", sha256::digest(format!("{}:{}:{}", file.name, block_number, index))))
+        .collect();
+    let payloads: Vec<String> = hashes.iter()
+        .map(|hash| hash.chars().cycle().take(file.payload_bytes).collect())
+        .collect();
+    df!(
+        BLOCK_NUMBER_COLUMN => block_numbers,
+        "index" => indexes,
+        "hash" => hashes,
+        "payload" => payloads
+    )
+}
+
+fn polars_error(error: PolarsError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support;
+    use super::*;
+
+    #[test]
+    fn test_generated_chunks_are_found_by_the_scan() {
+        // Arrange
+        let data_dir = test_support::temp_dir("devtools");
+        let dataset = DatasetSpec::new([5u8; 32], 100, 3, 10);
+
+        // Act
+        let chunks = generate(&data_dir, DirectoryLayout::ShardedBase32, &[dataset]).unwrap();
+
+        // Assert
+        assert_eq!(chunks.iter().map(|chunk| chunk.block_range.clone()).collect::<Vec<_>>(), vec![100..110, 110..120, 120..130]);
+        let data_source = LocalDataSource::new(data_dir.clone()).with_layout(DirectoryLayout::ShardedBase32);
+        let mut found = data_source.get_local_chunk_ids();
+        found.sort();
+        let mut generated = chunks.iter().map(|chunk| chunk.id).collect::<Vec<_>>();
+        generated.sort();
+        assert_eq!(found, generated);
+        let transactions = ParquetReader::new(File::open(&chunks[0].files["transactions.parquet"]).unwrap()).finish().unwrap();
+        assert_eq!(transactions.height(), 100);
+        assert_eq!(transactions.column("payload").unwrap().str().unwrap().get(0).unwrap().len(), 256);
+
+        // cleanup
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use futures::channel::oneshot;
+use crate::batch::InvalidChunk;
+use crate::cancellation::Cancellations;
+use crate::chunk_errors::ChunkError;
+use crate::data_catalogue::{ChunkStatus, DataCatalogue};
+use crate::data_chunk::ChunkId;
+use crate::fair_queue::FairQueue;
+use crate::free_space::InsufficientSpace;
+
+/// Why a download awaited with a `DownloadHandle` didn't make its chunk `Ready`
+#[derive(Clone, Debug, PartialEq)]
+pub enum DownloadError {
+    /// The chunk is `Failed`, with the last error recorded for it
+    Failed(Option<ChunkError>),
+    /// The download was cancelled, or the chunk deleted before it got ready
+    Cancelled,
+    /// The download wasn't started, it was refused by the overlap policy or its dataset is frozen
+    Refused,
+    /// The download wasn't started, the chunk doesn't fit in the free space of the data directory
+    InsufficientSpace(InsufficientSpace),
+    /// The download wasn't started, the chunk is malformed, e.g. a file name would be written outside the chunk directory
+    Invalid(InvalidChunk),
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::Failed(Some(error)) => write!(f, "download failed: {}", error.message),
+            DownloadError::Failed(None) => write!(f, "download failed"),
+            DownloadError::Cancelled => write!(f, "download was cancelled"),
+            DownloadError::Refused => write!(f, "download was refused"),
+            DownloadError::InsufficientSpace(shortage) => write!(f, "download was refused: {}", shortage),
+            DownloadError::Invalid(invalid) => write!(f, "download was refused: {}", invalid),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+type DownloadResult = Result<(), DownloadError>;
+
+/// Handles waiting for their chunks, completed by a status listener of the catalogue once the chunk is done
+#[derive(Clone, Default)]
+pub struct DownloadWaiters {
+    waiters: Arc<Mutex<HashMap<ChunkId, Vec<oneshot::Sender<DownloadResult>>>>>,
+}
+
+impl DownloadWaiters {
+    pub(crate) fn wait(&self, chunk_id: ChunkId) -> oneshot::Receiver<DownloadResult> {
+        let (sender, receiver) = oneshot::channel();
+        self.waiters.lock().unwrap().entry(chunk_id).or_default().push(sender);
+        receiver
+    }
+
+    /// Complete the handles of the chunk once it's `Ready`, `Failed` or `Deleted`
+    pub(crate) fn status_changed(&self, chunk_id: &ChunkId, status: &ChunkStatus) {
+        let result = match status {
+            ChunkStatus::Ready => Ok(()),
+            // the error is looked up by the handle, the catalogue can't be kept by its own listener
+            ChunkStatus::Failed => Err(DownloadError::Failed(None)),
+            ChunkStatus::Deleted => Err(DownloadError::Cancelled),
+            ChunkStatus::Downloading | ChunkStatus::Deleting => return,
+        };
+        for sender in self.waiters.lock().unwrap().remove(chunk_id).unwrap_or_default() {
+            // the handle may have been dropped
+            let _ = sender.send(result.clone());
+        }
+    }
+}
+
+/// Download started with `download_chunk_with_handle`. It can be polled for the status of the chunk, cancelled,
+/// and awaited, or waited for with `wait`, for the final result: `Ok` once the chunk is `Ready`, a `DownloadError` otherwise.
+/// Dropping the handle doesn't stop the download.
+pub struct DownloadHandle {
+    chunk_id: ChunkId,
+    receiver: oneshot::Receiver<DownloadResult>,
+    data_catalogue: DataCatalogue,
+    cancellations: Cancellations,
+    download_queue: Option<FairQueue>,
+}
+
+impl DownloadHandle {
+    pub(crate) fn new(
+        chunk_id: ChunkId,
+        receiver: oneshot::Receiver<DownloadResult>,
+        data_catalogue: DataCatalogue,
+        cancellations: Cancellations,
+        download_queue: Option<FairQueue>,
+    ) -> Self {
+        DownloadHandle { chunk_id, receiver, data_catalogue, cancellations, download_queue }
+    }
+
+    /// Handle of a download which is already done, e.g. of a chunk which was `Ready` before
+    pub(crate) fn completed(mut self, result: DownloadResult) -> Self {
+        let (sender, receiver) = oneshot::channel();
+        let _ = sender.send(result);
+        self.receiver = receiver;
+        self
+    }
+
+    pub fn chunk_id(&self) -> ChunkId {
+        self.chunk_id
+    }
+
+    /// Current status of the chunk, `None` once it's forgotten or compacted away
+    pub fn status(&self) -> Option<ChunkStatus> {
+        self.data_catalogue.get_chunk_info(&self.chunk_id).map(|info| info.status)
+    }
+
+    /// Same as `DataManagerImpl::cancel_download`, the handle completes with `DownloadError::Cancelled`
+    pub fn cancel(&self) -> bool {
+        self.cancellations.cancel_download(&self.data_catalogue, self.download_queue.as_ref(), self.chunk_id)
+    }
+
+    /// Block the current thread until the download is done
+    pub fn wait(self) -> DownloadResult {
+        futures::executor::block_on(self)
+    }
+}
+
+impl Future for DownloadHandle {
+    type Output = DownloadResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.receiver).poll(cx).map(|result| match result {
+            Ok(Err(DownloadError::Failed(None))) => {
+                let error = self.data_catalogue.get_chunk_info(&self.chunk_id).and_then(|info| info.errors.last().cloned());
+                Err(DownloadError::Failed(error))
+            }
+            Ok(result) => result,
+            // the waiters are dropped without a result only together with the catalogue
+            Err(oneshot::Canceled) => Err(DownloadError::Cancelled),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use crate::chunk_errors::ChunkErrorKind;
+    use crate::config::DataManagerConfig;
+    use crate::data_chunk::DataChunk;
+    use crate::transfer::ChunkTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support::{self, chunk};
+    use super::*;
+
+    /// Fails the chunks starting at block 100, and holds the others while `blocked` is set
+    struct GatedTransfer {
+        blocked: Arc<AtomicBool>,
+    }
+
+    impl ChunkTransfer for GatedTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            if chunk.block_range.start == 100 {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "404 Not Found"));
+            }
+            while self.blocked.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(10));
+            }
+            fs::create_dir_all(chunk_dir)?;
+            fs::write(chunk_dir.join("blocks.parquet"), b"blocks")
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    #[test]
+    fn test_handles_complete_with_the_result_of_the_download() {
+        // Arrange
+        let dir = test_support::temp_dir("handle");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let blocked = Arc::new(AtomicBool::new(true));
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(GatedTransfer { blocked: blocked.clone() }))
+            .build();
+        let (ready, failed, cancelled) = (chunk(13, 0..10), chunk(13, 100..110), chunk(13, 10..20));
+
+        // Act
+        let ready_handle = data_manager.download_chunk_with_handle(ready.clone());
+        let failed_handle = data_manager.download_chunk_with_handle(failed.clone());
+        let cancelled_handle = data_manager.download_chunk_with_handle(cancelled.clone());
+        let status_while_blocked = ready_handle.status();
+        let cancelled_result = cancelled_handle.cancel();
+        blocked.store(false, Ordering::SeqCst);
+        let ready_result = ready_handle.wait();
+        let failed_result = failed_handle.wait();
+        let cancelled_outcome = cancelled_handle.wait();
+        let already_ready = data_manager.download_chunk_with_handle(ready.clone()).wait();
+
+        // Assert
+        assert_eq!(status_while_blocked, Some(ChunkStatus::Downloading));
+        assert!(cancelled_result);
+        assert_eq!(ready_result, Ok(()));
+        match failed_result {
+            Err(DownloadError::Failed(Some(error))) => {
+                assert_eq!(error.kind, ChunkErrorKind::Download);
+                assert!(error.message.contains("404 Not Found"));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(cancelled_outcome, Err(DownloadError::Cancelled));
+        assert_eq!(already_ready, Ok(()));
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
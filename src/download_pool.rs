@@ -0,0 +1,568 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use crate::data_chunk::{ChunkId, DatasetId};
+use crate::fair_queue::{DownloadPriority, DownloadSchedulingConfig};
+
+/// Number of chunks downloaded at once by default
+pub const DEFAULT_MAX_CONCURRENT_DOWNLOADS: usize = 8;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Waiting downloads of a priority, taken from the datasets by weighted round-robin,
+/// so a dataset with weight 3 starts three downloads in its turn and a dataset with weight 1 one
+#[derive(Default)]
+struct DatasetRing {
+    /// Datasets with waiting downloads, the one whose turn it is first
+    datasets: VecDeque<DatasetId>,
+    jobs: HashMap<DatasetId, VecDeque<(ChunkId, Job)>>,
+    /// Downloads the first dataset started in its current turn
+    taken: u64,
+}
+
+impl DatasetRing {
+    fn push(&mut self, dataset_id: DatasetId, chunk_id: ChunkId, job: Job) {
+        let jobs = self.jobs.entry(dataset_id).or_default();
+        if jobs.is_empty() {
+            // a dataset without waiting downloads gets its turn after the others
+            self.datasets.push_back(dataset_id);
+        }
+        jobs.push_back((chunk_id, job));
+    }
+
+    /// Take the waiting download of the chunk out of its turn
+    fn remove(&mut self, chunk_id: &ChunkId) -> Option<(DatasetId, Job)> {
+        let (dataset_id, position) = self.jobs.iter().find_map(|(dataset_id, jobs)| {
+            jobs.iter().position(|(id, _)| id == chunk_id).map(|position| (*dataset_id, position))
+        })?;
+        let jobs = self.jobs.get_mut(&dataset_id)?;
+        let (_, job) = jobs.remove(position)?;
+        if jobs.is_empty() {
+            self.jobs.remove(&dataset_id);
+            if self.datasets.front() == Some(&dataset_id) {
+                self.taken = 0;
+            }
+            self.datasets.retain(|id| *id != dataset_id);
+        }
+        Some((dataset_id, job))
+    }
+
+    fn pop(&mut self, scheduling: &DownloadSchedulingConfig) -> Option<Job> {
+        let dataset_id = *self.datasets.front()?;
+        let jobs = self.jobs.get_mut(&dataset_id)?;
+        let job = jobs.pop_front().map(|(_, job)| job);
+        self.taken += 1;
+        if jobs.is_empty() {
+            self.jobs.remove(&dataset_id);
+            self.datasets.pop_front();
+            self.taken = 0;
+        } else if self.taken >= scheduling.weight(&dataset_id) {
+            self.datasets.rotate_left(1);
+            self.taken = 0;
+        }
+        job
+    }
+
+    fn len(&self) -> usize {
+        self.jobs.values().map(VecDeque::len).sum()
+    }
+}
+
+#[derive(Default)]
+struct PoolState {
+    /// Waiting downloads per priority, the highest priority first
+    queue: BTreeMap<DownloadPriority, DatasetRing>,
+    threads: usize,
+    /// No more downloads are started, the queued ones wait until the pool is resumed
+    paused: bool,
+}
+
+impl PoolState {
+    fn pop(&mut self, scheduling: &DownloadSchedulingConfig) -> Option<Job> {
+        if self.paused {
+            return None;
+        }
+        self.queue.values_mut().find_map(|jobs| jobs.pop(scheduling))
+    }
+
+    fn queued(&self) -> usize {
+        self.queue.values().map(DatasetRing::len).sum()
+    }
+}
+
+/// Runs the downloads on at most `max_concurrent` threads, the downloads over the limit wait by priority.
+/// Within the same priority the datasets take turns by their weights, and the downloads of a dataset start in submission order,
+/// so a large backfill of one dataset doesn't hold back the downloads of the others.
+/// Threads are started as the downloads are submitted and stop once the queue is empty, so an idle pool holds none.
+/// The limit may be changed while the pool runs, see `set_max_concurrent`.
+#[derive(Clone)]
+pub struct DownloadPool {
+    max_concurrent: Arc<AtomicUsize>,
+    /// Weights of the datasets, all the datasets take equal turns by default
+    scheduling: Arc<DownloadSchedulingConfig>,
+    state: Arc<Mutex<PoolState>>,
+}
+
+impl DownloadPool {
+    pub fn new(max_concurrent: usize) -> Self {
+        DownloadPool {
+            max_concurrent: Arc::new(AtomicUsize::new(max_concurrent.max(1))),
+            scheduling: Arc::new(DownloadSchedulingConfig::default()),
+            state: Arc::new(Mutex::new(PoolState::default())),
+        }
+    }
+
+    /// Share the threads between the datasets by the weights of the scheduling, its slots are left to the `FairQueue`
+    pub fn with_scheduling(mut self, scheduling: DownloadSchedulingConfig) -> Self {
+        self.scheduling = Arc::new(scheduling);
+        self
+    }
+
+    /// Queue the download of the chunk, it starts once a thread is free, all the downloads of a higher priority started
+    /// and it's the turn of the dataset
+    pub fn submit(&self, priority: DownloadPriority, dataset_id: DatasetId, chunk_id: ChunkId, job: impl FnOnce() + Send + 'static) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.entry(priority).or_default().push(dataset_id, chunk_id, Box::new(job));
+        if !state.paused && state.threads < self.max_concurrent() {
+            self.spawn_thread(&mut state);
+        }
+    }
+
+    /// Move the queued download of the chunk to the high priority, behind the high priority downloads of its dataset.
+    /// Returns `false` when it isn't queued, e.g. it runs already.
+    pub fn escalate(&self, chunk_id: &ChunkId) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let removed = state.queue.iter_mut()
+            .filter(|(priority, _)| **priority != DownloadPriority::High)
+            .find_map(|(_, jobs)| jobs.remove(chunk_id));
+        let Some((dataset_id, job)) = removed else {
+            return false;
+        };
+        state.queue.entry(DownloadPriority::High).or_default().push(dataset_id, *chunk_id, job);
+        true
+    }
+
+    /// Change the limit, new threads start right away for the queued downloads,
+    /// the threads over a lowered limit stop once their running downloads complete
+    pub fn set_max_concurrent(&self, max_concurrent: usize) {
+        let mut state = self.state.lock().unwrap();
+        self.max_concurrent.store(max_concurrent.max(1), Ordering::SeqCst);
+        if !state.paused {
+            self.spawn_threads(&mut state);
+        }
+    }
+
+    /// Downloads run at once at most
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent.load(Ordering::SeqCst)
+    }
+
+    /// Stop starting the queued downloads, the running ones complete. Downloads submitted meanwhile are queued.
+    pub fn pause(&self) {
+        self.state.lock().unwrap().paused = true;
+    }
+
+    /// Start the queued downloads again, in the same order as if the pool was never paused
+    pub fn resume(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.paused = false;
+        self.spawn_threads(&mut state);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().paused
+    }
+
+    /// Downloads waiting for a thread
+    pub fn queued(&self) -> usize {
+        self.state.lock().unwrap().queued()
+    }
+
+    /// Threads of the pool, at most `max_concurrent` once the threads over a lowered limit stopped
+    pub fn threads(&self) -> usize {
+        self.state.lock().unwrap().threads
+    }
+
+    /// Start threads for the queued downloads, up to the limit
+    fn spawn_threads(&self, state: &mut PoolState) {
+        while state.threads < self.max_concurrent().min(state.queued()) {
+            self.spawn_thread(state);
+        }
+    }
+
+    /// The thread takes the queued downloads until there are none, the pool is paused or the threads are over the limit.
+    /// A download which panics ends its thread, which is still taken off the count, so the pool doesn't shrink.
+    fn spawn_thread(&self, state: &mut PoolState) {
+        state.threads += 1;
+        let pool = self.clone();
+        thread::spawn(move || {
+            let mut unwinding = PoolThread(Some(pool.clone()));
+            loop {
+                let job = {
+                    let mut state = pool.state.lock().unwrap();
+                    let next = match state.threads > pool.max_concurrent() {
+                        true => None,
+                        false => state.pop(&pool.scheduling),
+                    };
+                    match next {
+                        Some(job) => job,
+                        None => {
+                            // under the same lock as the check, so a download submitted meanwhile starts a new thread
+                            state.threads -= 1;
+                            unwinding.0 = None;
+                            return;
+                        }
+                    }
+                };
+                job();
+            }
+        });
+    }
+}
+
+/// Takes the thread of a download which panicked off the count of the pool, and starts another one for the queued downloads
+struct PoolThread(Option<DownloadPool>);
+
+impl Drop for PoolThread {
+    fn drop(&mut self) {
+        let Some(pool) = self.0.take() else { return };
+        let mut state = pool.state.lock().unwrap();
+        state.threads -= 1;
+        if !state.paused {
+            pool.spawn_threads(&mut state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+    use std::sync::Barrier;
+    use std::time::Duration;
+    use crate::config::DataManagerConfig;
+    use crate::data_catalogue::ChunkStatus;
+    use crate::data_chunk::DataChunk;
+    use crate::data_manager::DataManager;
+    use crate::transfer::ChunkTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// Remote storage recording the first blocks of the chunks in the order they're downloaded
+    #[derive(Default)]
+    struct RecordingTransfer {
+        downloaded: Mutex<Vec<u64>>,
+    }
+
+    impl ChunkTransfer for RecordingTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            self.downloaded.lock().unwrap().push(chunk.block_range.start);
+            thread::sleep(Duration::from_millis(50));
+            fs::create_dir_all(chunk_dir)?;
+            fs::write(chunk_dir.join("blocks.parquet"), [])
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    #[test]
+    fn test_downloads_run_bounded_in_submission_order() {
+        // Arrange
+        let pool = DownloadPool::new(2);
+        let started = Arc::new(Mutex::new(Vec::new()));
+
+        // Act
+        for download in 0..6 {
+            let started = started.clone();
+            pool.submit(DownloadPriority::Normal, [1u8; 32], [0u8; 32], move || {
+                started.lock().unwrap().push(download);
+                thread::sleep(Duration::from_millis(50));
+            });
+        }
+        let (threads, queued) = (pool.threads(), pool.queued());
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(250));
+        });
+
+        // Assert
+        assert_eq!(threads, 2);
+        assert!(queued >= 3);
+        let started = started.lock().unwrap();
+        assert_eq!(started.len(), 6);
+        // the two threads take the queued downloads one by one
+        assert!(started.windows(3).all(|window| window[0] < window[2]));
+        assert_eq!(pool.threads(), 0);
+    }
+
+    #[test]
+    fn test_limit_changes_while_downloads_run() {
+        // Arrange
+        let pool = DownloadPool::new(1);
+        let running = Arc::new(Mutex::new((0, 0)));
+        for _ in 0..12 {
+            let running = running.clone();
+            pool.submit(DownloadPriority::Normal, [1u8; 32], [0u8; 32], move || {
+                {
+                    let mut running = running.lock().unwrap();
+                    running.0 += 1;
+                    running.1 = running.1.max(running.0);
+                }
+                thread::sleep(Duration::from_millis(50));
+                running.lock().unwrap().0 -= 1;
+            });
+        }
+
+        // Act
+        pool.set_max_concurrent(3);
+        let raised_threads = pool.threads();
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(75));
+        });
+        pool.set_max_concurrent(1);
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(75));
+        });
+        let lowered_threads = pool.threads();
+
+        // Assert
+        assert_eq!(raised_threads, 3);
+        assert_eq!(running.lock().unwrap().1, 3);
+        // the threads over the lowered limit stopped once their downloads completed
+        assert_eq!(lowered_threads, 1);
+        assert_eq!(pool.max_concurrent(), 1);
+    }
+
+    #[test]
+    fn test_high_priority_downloads_jump_the_queue() {
+        // Arrange
+        let pool = DownloadPool::new(1);
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let submit = |priority: DownloadPriority, download: &'static str| {
+            let started = started.clone();
+            pool.submit(priority, [1u8; 32], [0u8; 32], move || {
+                started.lock().unwrap().push(download);
+                thread::sleep(Duration::from_millis(50));
+            });
+        };
+
+        // Act
+        submit(DownloadPriority::Normal, "running");
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(10));
+        });
+        submit(DownloadPriority::Normal, "backfill 1");
+        submit(DownloadPriority::Normal, "backfill 2");
+        submit(DownloadPriority::High, "urgent 1");
+        submit(DownloadPriority::High, "urgent 2");
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(400));
+        });
+
+        // Assert
+        // the running download isn't interrupted, the urgent ones start next in their order
+        assert_eq!(*started.lock().unwrap(), vec!["running", "urgent 1", "urgent 2", "backfill 1", "backfill 2"]);
+    }
+
+    #[test]
+    fn test_panicking_download_frees_its_thread() {
+        // Arrange
+        let pool = DownloadPool::new(1);
+        let completed = Arc::new(Mutex::new(Vec::new()));
+        pool.submit(DownloadPriority::Normal, [1u8; 32], [0u8; 32], || panic!("download panicked"));
+
+        // Act
+        for download in 1..3u8 {
+            let completed = completed.clone();
+            pool.submit(DownloadPriority::Normal, [1u8; 32], [download; 32], move || completed.lock().unwrap().push(download));
+        }
+        while pool.threads() > 0 || pool.queued() > 0 {
+            thread::yield_now();
+        }
+
+        // Assert
+        assert_eq!(*completed.lock().unwrap(), vec![1, 2]);
+        assert_eq!(pool.threads(), 0);
+    }
+
+    #[test]
+    fn test_escalated_download_starts_next() {
+        // Arrange
+        let pool = DownloadPool::new(1);
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let (running, release) = (Arc::new(Barrier::new(2)), Arc::new(Barrier::new(2)));
+        {
+            let (running, release) = (running.clone(), release.clone());
+            pool.submit(DownloadPriority::Normal, [1u8; 32], [0u8; 32], move || {
+                running.wait();
+                release.wait();
+            });
+        }
+        running.wait();
+        for download in 1..4u8 {
+            let started = started.clone();
+            pool.submit(DownloadPriority::Normal, [1u8; 32], [download; 32], move || started.lock().unwrap().push(download));
+        }
+
+        // Act
+        let escalated = pool.escalate(&[3u8; 32]);
+        let running_escalated = pool.escalate(&[0u8; 32]);
+        release.wait();
+        while pool.threads() > 0 {
+            thread::yield_now();
+        }
+
+        // Assert
+        assert!(escalated);
+        assert!(!running_escalated);
+        assert_eq!(*started.lock().unwrap(), vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn test_datasets_take_turns_by_weight() {
+        // Arrange
+        let (backfill, tip) = ([1u8; 32], [2u8; 32]);
+        let pool = DownloadPool::new(1).with_scheduling(DownloadSchedulingConfig::default().with_weight(tip, 2));
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let submit = |dataset_id: DatasetId, download: &'static str| {
+            let started = started.clone();
+            pool.submit(DownloadPriority::Normal, dataset_id, [0u8; 32], move || {
+                started.lock().unwrap().push(download);
+                thread::sleep(Duration::from_millis(20));
+            });
+        };
+
+        // Act
+        submit(backfill, "running");
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(5));
+        });
+        for download in ["backfill 1", "backfill 2", "backfill 3", "backfill 4"] {
+            submit(backfill, download);
+        }
+        for download in ["tip 1", "tip 2", "tip 3", "tip 4"] {
+            submit(tip, download);
+        }
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(400));
+        });
+
+        // Assert
+        // the backfill queued first doesn't hold back the tip, which starts two downloads in its turn
+        assert_eq!(*started.lock().unwrap(), vec![
+            "running", "backfill 1", "tip 1", "tip 2", "backfill 2", "tip 3", "tip 4", "backfill 3", "backfill 4",
+        ]);
+    }
+
+    #[test]
+    fn test_urgent_chunks_are_downloaded_before_backfill() {
+        // Arrange
+        let dir = test_support::temp_dir("priority");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let transfer = Arc::new(RecordingTransfer::default());
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data"))
+                .with_catalogue_file(dir.join("registry.parquet"))
+                .with_max_concurrent_downloads(1))
+            .chunk_transfer(transfer.clone())
+            .build();
+        let chunk = |block: u64| test_support::chunk(6, block..block + 10);
+
+        // Act
+        data_manager.download_chunk(chunk(0));
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(10));
+        });
+        data_manager.download_chunk(chunk(10));
+        data_manager.download_chunk(chunk(20));
+        data_manager.download_chunk_with_priority(chunk(30), DownloadPriority::High);
+        data_manager.data_catalogue.wait_until_downloaded(&[chunk(0).id, chunk(10).id, chunk(20).id, chunk(30).id]);
+
+        // Assert
+        assert_eq!(*transfer.downloaded.lock().unwrap(), vec![0, 30, 10, 20]);
+        assert_eq!(data_manager.list_chunks().len(), 4);
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_downloads_missing_their_deadline_start_next() {
+        // Arrange
+        let dir = test_support::temp_dir("deadline_order");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let transfer = Arc::new(RecordingTransfer::default());
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data"))
+                .with_catalogue_file(dir.join("registry.parquet"))
+                .with_max_concurrent_downloads(1))
+            .chunk_transfer(transfer.clone())
+            .build();
+        let chunk = |block: u64| test_support::chunk(8, block..block + 10);
+
+        // Act
+        data_manager.download_chunk(chunk(0));
+        data_manager.download_chunk(chunk(10));
+        data_manager.download_chunk(chunk(20));
+        // missed while the first download still runs
+        data_manager.download_chunk_with_deadline(chunk(30), Duration::from_millis(1));
+        data_manager.data_catalogue.wait_until_downloaded(&[chunk(0).id, chunk(10).id, chunk(20).id, chunk(30).id]);
+
+        // Assert
+        assert_eq!(*transfer.downloaded.lock().unwrap(), vec![0, 30, 10, 20]);
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_paused_downloads_resume_in_order() {
+        // Arrange
+        let dir = test_support::temp_dir("paused");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let transfer = Arc::new(RecordingTransfer::default());
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data"))
+                .with_catalogue_file(dir.join("registry.parquet"))
+                .with_max_concurrent_downloads(1))
+            .chunk_transfer(transfer.clone())
+            .build();
+        let chunk = |block: u64| test_support::chunk(7, block..block + 10);
+        data_manager.download_chunk(chunk(0));
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(10));
+        });
+
+        // Act
+        data_manager.pause_downloads();
+        data_manager.download_chunk(chunk(10));
+        data_manager.download_chunk(chunk(20));
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(200));
+        });
+        let downloaded_while_paused = transfer.downloaded.lock().unwrap().clone();
+        let status_while_paused = data_manager.get_chunk_info(chunk(10).id).map(|info| info.status);
+        let paused = data_manager.downloads_paused();
+        data_manager.resume_downloads();
+        data_manager.data_catalogue.wait_until_downloaded(&[chunk(10).id, chunk(20).id]);
+
+        // Assert
+        // the running download completes
+        assert_eq!(downloaded_while_paused, vec![0]);
+        assert_eq!(status_while_paused, Some(ChunkStatus::Downloading));
+        assert!(paused);
+        assert!(!data_manager.downloads_paused());
+        assert_eq!(*transfer.downloaded.lock().unwrap(), vec![0, 10, 20]);
+        assert_eq!(data_manager.list_chunks().len(), 3);
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
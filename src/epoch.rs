@@ -0,0 +1,193 @@
+use std::fmt;
+use std::ops::Range;
+use crate::data_catalogue::{ChunkInfo, ChunkStatus};
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
+use crate::download_handle::DownloadError;
+
+/// Grouping of the blocks of every dataset into epochs of the same length,
+/// e.g. 100k blocks, the way the datasets are published and assigned
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EpochLayout {
+    pub blocks_per_epoch: u64,
+}
+
+impl EpochLayout {
+    pub fn new(blocks_per_epoch: u64) -> Self {
+        // an epoch has at least one block
+        EpochLayout { blocks_per_epoch: blocks_per_epoch.max(1) }
+    }
+
+    /// The epoch containing `block_number`
+    pub fn epoch_of(&self, block_number: u64) -> u64 {
+        block_number / self.blocks_per_epoch
+    }
+
+    pub fn block_range(&self, epoch: u64) -> Range<u64> {
+        let start = epoch.saturating_mul(self.blocks_per_epoch);
+        start..start.saturating_add(self.blocks_per_epoch)
+    }
+
+    /// Whether the whole chunk belongs to the epoch
+    pub fn contains(&self, epoch: u64, chunk: &DataChunk) -> bool {
+        let epoch_range = self.block_range(epoch);
+        epoch_range.start <= chunk.block_range.start && chunk.block_range.end <= epoch_range.end
+    }
+}
+
+/// How much of an epoch of a dataset is available locally
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpochStatus {
+    pub dataset_id: DatasetId,
+    pub epoch: u64,
+    pub block_range: Range<u64>,
+    /// Ready chunks overlapping the epoch, ordered by their blocks
+    pub ready_chunks: Vec<ChunkId>,
+    /// Blocks of the epoch, which are not covered by any ready chunk
+    pub missing_ranges: Vec<Range<u64>>,
+}
+
+impl EpochStatus {
+    pub fn is_complete(&self) -> bool {
+        self.missing_ranges.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EpochError {
+    /// No `EpochLayout` is set in the configuration
+    NotConfigured,
+    /// The chunk belongs to another dataset or reaches out of the epoch
+    ChunkOutsideEpoch(ChunkId),
+    /// The given chunks don't cover these blocks of the epoch
+    Incomplete(Vec<Range<u64>>),
+    /// Some chunks of the epoch are being processed, so nothing was started
+    Busy,
+    /// The download of the chunk was refused, e.g. it doesn't fit in the free space, so nothing was started
+    Refused(ChunkId, DownloadError),
+    /// The dataset is frozen, so nothing was started
+    Frozen,
+    /// A chunk of the epoch is under a legal hold, so nothing was deleted
+    LegalHold(ChunkId),
+}
+
+impl fmt::Display for EpochError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EpochError::NotConfigured => write!(f, "epochs are not configured"),
+            EpochError::ChunkOutsideEpoch(chunk_id) => write!(f, "chunk {} is not part of the epoch", hex::encode(chunk_id)),
+            EpochError::Incomplete(missing_ranges) => write!(f, "epoch is missing blocks {:?}", missing_ranges),
+            EpochError::Busy => write!(f, "chunks of the epoch are being processed"),
+            EpochError::Refused(chunk_id, error) => write!(f, "chunk {}: {}", hex::encode(chunk_id), error),
+            EpochError::Frozen => write!(f, "dataset is frozen"),
+            EpochError::LegalHold(chunk_id) => write!(f, "chunk {} is under a legal hold", hex::encode(chunk_id)),
+        }
+    }
+}
+
+impl std::error::Error for EpochError {}
+
+/// Status of an epoch of a dataset, given the chunks currently in the catalogue
+pub fn epoch_status<'a>(
+    layout: &EpochLayout,
+    dataset_id: DatasetId,
+    epoch: u64,
+    catalogue_chunks: impl IntoIterator<Item = &'a ChunkInfo>,
+) -> EpochStatus {
+    let block_range = layout.block_range(epoch);
+    let ready: Vec<&DataChunk> = catalogue_chunks
+        .into_iter()
+        .filter(|info| info.status == ChunkStatus::Ready)
+        .map(|info| &info.chunk)
+        .filter(|chunk| chunk.dataset_id == dataset_id && overlaps(&chunk.block_range, &block_range))
+        .collect();
+    EpochStatus {
+        dataset_id,
+        epoch,
+        missing_ranges: missing_ranges(&block_range, &ready),
+        ready_chunks: sorted(ready).iter().map(|chunk| chunk.id).collect(),
+        block_range,
+    }
+}
+
+/// Blocks of `block_range` not covered by any of the `chunks`
+pub(crate) fn missing_ranges(block_range: &Range<u64>, chunks: &[&DataChunk]) -> Vec<Range<u64>> {
+    let mut missing = Vec::new();
+    let mut next_block = block_range.start;
+    for chunk in sorted(chunks.to_vec()) {
+        if chunk.block_range.start > next_block {
+            missing.push(next_block..chunk.block_range.start.min(block_range.end));
+        }
+        next_block = next_block.max(chunk.block_range.end);
+        if next_block >= block_range.end {
+            return missing;
+        }
+    }
+    missing.push(next_block..block_range.end);
+    missing
+}
+
+fn sorted(mut chunks: Vec<&DataChunk>) -> Vec<&DataChunk> {
+    chunks.sort_by_key(|chunk| (chunk.block_range.start, chunk.block_range.end));
+    chunks
+}
+
+fn overlaps(a: &Range<u64>, b: &Range<u64>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::chunk;
+    use super::*;
+
+    #[test]
+    fn test_epoch_layout() {
+        let layout = EpochLayout::new(100_000);
+        assert_eq!(layout.epoch_of(0), 0);
+        assert_eq!(layout.epoch_of(99_999), 0);
+        assert_eq!(layout.epoch_of(100_000), 1);
+        assert_eq!(layout.block_range(2), 200_000..300_000);
+        assert!(layout.contains(1, &chunk(1, 100_000..150_000)));
+        assert!(!layout.contains(1, &chunk(1, 150_000..250_000)));
+    }
+
+    #[test]
+    fn test_complete_epoch() {
+        // Arrange
+        let held = vec![
+            ChunkInfo::new(chunk(1, 50..100), ChunkStatus::Ready),
+            ChunkInfo::new(chunk(1, 0..50), ChunkStatus::Ready),
+        ];
+
+        // Act
+        let status = epoch_status(&EpochLayout::new(100), [1u8; 32], 0, &held);
+
+        // Assert
+        assert!(status.is_complete());
+        assert_eq!(status.ready_chunks, vec![chunk(1, 0..50).id, chunk(1, 50..100).id]);
+    }
+
+    #[test]
+    fn test_incomplete_epoch() {
+        // Arrange
+        let held = vec![
+            ChunkInfo::new(chunk(1, 110..150), ChunkStatus::Ready),
+            ChunkInfo::new(chunk(1, 150..170), ChunkStatus::Downloading),
+            ChunkInfo::new(chunk(1, 170..250), ChunkStatus::Ready),
+        ];
+
+        // Act
+        let status = epoch_status(&EpochLayout::new(100), [1u8; 32], 1, &held);
+
+        // Assert
+        assert!(!status.is_complete());
+        assert_eq!(status.missing_ranges, vec![100..110, 150..170]);
+        assert_eq!(status.ready_chunks, vec![chunk(1, 110..150).id, chunk(1, 170..250).id]);
+    }
+
+    #[test]
+    fn test_empty_epoch() {
+        let status = epoch_status(&EpochLayout::new(100), [1u8; 32], 3, &Vec::new());
+        assert_eq!(status.missing_ranges, vec![300..400]);
+    }
+}
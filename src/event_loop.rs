@@ -1,9 +1,18 @@
-use std::sync::{Arc, RwLock};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use futures::executor::ThreadPool;
-use crate::io_operation::TaskWaker;
+use crate::correlation;
+use crate::data_chunk::ChunkId;
+use crate::io_operation::{IOOperation, OperationSender};
+use crate::operation::{OperationLog, OperationResult};
 
+#[derive(Clone)]
 pub struct TasksManager {
     pool_managing_async_tasks: ThreadPool,
+    /// number of running background tasks per chunk
+    active_tasks: Arc<Mutex<HashMap<ChunkId, usize>>>,
+    /// Results of the finished operations
+    pub operations: OperationLog,
 }
 
 impl Default for TasksManager {
@@ -16,30 +25,84 @@ impl TasksManager {
     pub fn new() -> Self {
         TasksManager {
             pool_managing_async_tasks: ThreadPool::new().expect("Failed to create thread pool"),
+            active_tasks: Arc::new(Mutex::new(HashMap::new())),
+            operations: OperationLog::default(),
         }
     }
 
-    pub fn add_future_to_manager_pool(&self) -> Arc<RwLock<TaskWaker>> {
-        let shared_waker = Arc::new(RwLock::new(TaskWaker { waker: None }));
-        
-        // the future
-        let io_operation = crate::io_operation::IOOperation {
-            task_waker: shared_waker.clone(),
-        };
+    pub fn add_future_to_manager_pool(&self) -> OperationSender {
+        let (sender, io_operation) = IOOperation::new();
 
         // spawn the future in a thread pool
-        self.pool_managing_async_tasks.spawn_ok(async {
-            let result = io_operation.await;
-            println!("{}", result);
+        let operations = self.operations.clone();
+        self.pool_managing_async_tasks.spawn_ok(async move {
+            if let Some(result) = io_operation.await {
+                operations.record(result);
+            }
         });
-        shared_waker
+        sender
     }
     
-    /// Wake the future to allow it to finish
-    pub fn wake_the_future(shared_waker: Arc<RwLock<TaskWaker>>) {
-        let task_waker = shared_waker.read().unwrap();
-        if let Some(waker) = &task_waker.waker {
-            waker.wake_by_ref();
+    /// Run a short task in the thread pool
+    pub fn spawn_task(&self, task: impl FnOnce() + Send + 'static) {
+        let correlation_id = correlation::current();
+        self.pool_managing_async_tasks.spawn_ok(async move { correlation::scope(correlation_id, task) });
+    }
+
+    /// Complete the future with the result of the operation
+    pub fn wake_the_future(sender: OperationSender, result: Option<OperationResult>) {
+        // the future is gone only when the pool is shut down
+        let _ = sender.send(result);
+    }
+
+    /// Mark a background task working on the chunk as running, until the returned guard is dropped
+    pub fn track_task(&self, chunk_id: &ChunkId) -> ActiveTask {
+        *self.active_tasks.lock().unwrap().entry(*chunk_id).or_insert(0) += 1;
+        ActiveTask {
+            active_tasks: self.active_tasks.clone(),
+            chunk_id: *chunk_id,
+        }
+    }
+
+    /// Whether any background task is working on the chunk
+    pub fn is_active(&self, chunk_id: &ChunkId) -> bool {
+        self.active_tasks.lock().unwrap().contains_key(chunk_id)
+    }
+}
+
+/// A running background task of a chunk
+pub struct ActiveTask {
+    active_tasks: Arc<Mutex<HashMap<ChunkId, usize>>>,
+    chunk_id: ChunkId,
+}
+
+impl Drop for ActiveTask {
+    fn drop(&mut self) {
+        let mut active_tasks = self.active_tasks.lock().unwrap();
+        if let Some(count) = active_tasks.get_mut(&self.chunk_id) {
+            *count -= 1;
+            if *count == 0 {
+                active_tasks.remove(&self.chunk_id);
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_track_active_tasks() {
+        let tasks_manager = TasksManager::default();
+        let first_task = tasks_manager.track_task(&[1u8; 32]);
+        let second_task = tasks_manager.track_task(&[1u8; 32]);
+        assert!(tasks_manager.is_active(&[1u8; 32]));
+        assert!(!tasks_manager.is_active(&[2u8; 32]));
+
+        drop(first_task);
+        assert!(tasks_manager.is_active(&[1u8; 32]));
+        drop(second_task);
+        assert!(!tasks_manager.is_active(&[1u8; 32]));
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::time::Instant;
+use crate::data_catalogue::{ChunkInfo, ChunkStatus};
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
+
+/// Which chunks of a dataset are removed first, when some must be removed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EvictionOrder {
+    /// Chunks with the oldest blocks, the farthest from the dataset tip, go first
+    #[default]
+    FarthestFromTip,
+    /// Chunks which weren't queried for the longest time go first, never queried chunks before all others
+    LeastRecentlyQueried,
+}
+
+/// How many chunks of a dataset are kept locally
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetentionPolicy {
+    pub max_chunks: usize,
+    pub order: EvictionOrder,
+}
+
+/// Ready chunks of a dataset in the order they should be evicted
+pub fn eviction_order<'a>(
+    dataset_id: DatasetId,
+    order: EvictionOrder,
+    catalogue_chunks: impl IntoIterator<Item = &'a ChunkInfo>,
+    last_queried: &HashMap<ChunkId, Instant>,
+) -> Vec<DataChunk> {
+    let mut chunks: Vec<DataChunk> = catalogue_chunks
+        .into_iter()
+        .filter(|info| info.chunk.dataset_id == dataset_id && info.status == ChunkStatus::Ready)
+        .map(|info| info.chunk.clone())
+        .collect();
+    // the tip is the same for the whole dataset, so the distance from it only depends on the end of the chunk
+    chunks.sort_by_key(|chunk| (chunk.block_range.end, chunk.block_range.start));
+    if order == EvictionOrder::LeastRecentlyQueried {
+        // stable sort keeps the chunks queried at the same time ordered by the distance from the tip
+        chunks.sort_by_key(|chunk| last_queried.get(&chunk.id).copied());
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::test_support::chunk;
+    use super::*;
+
+    fn held() -> Vec<ChunkInfo> {
+        vec![
+            ChunkInfo::new(chunk(1, 20..30), ChunkStatus::Ready),
+            ChunkInfo::new(chunk(1, 0..10), ChunkStatus::Ready),
+            ChunkInfo::new(chunk(1, 30..40), ChunkStatus::Downloading),
+            ChunkInfo::new(chunk(1, 10..20), ChunkStatus::Ready),
+        ]
+    }
+
+    #[test]
+    fn test_farthest_from_tip_first() {
+        let order = eviction_order([1u8; 32], EvictionOrder::FarthestFromTip, &held(), &HashMap::new());
+        assert_eq!(order, vec![chunk(1, 0..10), chunk(1, 10..20), chunk(1, 20..30)]);
+    }
+
+    #[test]
+    fn test_least_recently_queried_first() {
+        // Arrange
+        let now = Instant::now();
+        let last_queried = HashMap::from([
+            (chunk(1, 0..10).id, now),
+            (chunk(1, 20..30).id, now - Duration::from_secs(60)),
+        ]);
+
+        // Act
+        let order = eviction_order([1u8; 32], EvictionOrder::LeastRecentlyQueried, &held(), &last_queried);
+
+        // Assert
+        assert_eq!(order, vec![chunk(1, 10..20), chunk(1, 20..30), chunk(1, 0..10)]);
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Condvar, Mutex};
+use crate::data_chunk::{ChunkId, DatasetId};
+
+/// Virtual time one download of a dataset with weight 1 takes
+const WEIGHT_SCALE: u64 = 1_000_000;
+
+/// Limits the concurrent downloads and shares them between datasets proportionally to their weights,
+/// so a large backfill of one dataset doesn't starve the downloads of other datasets
+#[derive(Clone, Debug, PartialEq)]
+pub struct DownloadSchedulingConfig {
+    /// Number of chunks downloaded at once
+    pub slots: usize,
+    /// Share of the slots of each dataset relative to the others
+    pub weights: HashMap<DatasetId, u32>,
+    /// Weight of the datasets without one in `weights`
+    pub default_weight: u32,
+}
+
+impl Default for DownloadSchedulingConfig {
+    fn default() -> Self {
+        DownloadSchedulingConfig {
+            slots: 4,
+            weights: HashMap::new(),
+            default_weight: 1,
+        }
+    }
+}
+
+impl DownloadSchedulingConfig {
+    pub fn with_weight(mut self, dataset_id: DatasetId, weight: u32) -> Self {
+        self.weights.insert(dataset_id, weight);
+        self
+    }
+
+    pub(crate) fn weight(&self, dataset_id: &DatasetId) -> u64 {
+        self.weights.get(dataset_id).copied().unwrap_or(self.default_weight).max(1) as u64
+    }
+}
+
+/// Downloads of the same priority share the slots by weight, high priority downloads get the free slots first
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DownloadPriority {
+    /// e.g. new chunks at the tip of a followed dataset, or chunks needed by active queries
+    High,
+    #[default]
+    Normal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Ticket {
+    priority: DownloadPriority,
+    tag: u64,
+    arrival: u64,
+    dataset_id: DatasetId,
+    chunk_id: ChunkId,
+}
+
+#[derive(Default)]
+struct QueueState {
+    free_slots: usize,
+    /// Tag of the last download granted a slot
+    virtual_time: u64,
+    /// Tag of the last download queued per dataset
+    last_tags: HashMap<DatasetId, u64>,
+    /// Queued downloads by their priority, tag and arrival
+    waiting: BTreeSet<Ticket>,
+    arrivals: u64,
+}
+
+/// Weighted fair queue of the downloads waiting for a slot.
+/// Every queued download gets a tag advancing by the inverse of the weight of its dataset,
+/// the download with the lowest tag gets the next free slot.
+#[derive(Clone)]
+pub struct FairQueue {
+    config: Arc<DownloadSchedulingConfig>,
+    state: Arc<(Mutex<QueueState>, Condvar)>,
+}
+
+/// Slot of a running download, freed when this is dropped
+pub struct DownloadSlot {
+    queue: FairQueue,
+}
+
+impl FairQueue {
+    pub fn new(config: DownloadSchedulingConfig) -> Self {
+        let state = QueueState { free_slots: config.slots.max(1), ..QueueState::default() };
+        FairQueue { config: Arc::new(config), state: Arc::new((Mutex::new(state), Condvar::new())) }
+    }
+
+    /// Block the current thread until the download gets a slot, `None` when the download was withdrawn while it waited.
+    /// To be called only from background workers, never from the API methods.
+    pub fn acquire(&self, chunk_id: &ChunkId, dataset_id: &DatasetId, priority: DownloadPriority) -> Option<DownloadSlot> {
+        let (state, slot_freed) = &*self.state;
+        let mut state = state.lock().unwrap();
+        let start = state.virtual_time.max(state.last_tags.get(dataset_id).copied().unwrap_or(0));
+        let tag = start + WEIGHT_SCALE / self.config.weight(dataset_id);
+        state.last_tags.insert(*dataset_id, tag);
+        state.arrivals += 1;
+        let arrival = state.arrivals;
+        state.waiting.insert(Ticket { priority, tag, arrival, dataset_id: *dataset_id, chunk_id: *chunk_id });
+
+        // the priority of the ticket may be escalated while it waits, the arrival identifies it
+        let mut state = slot_freed
+            .wait_while(state, |state| {
+                let queued = state.waiting.iter().any(|ticket| ticket.arrival == arrival);
+                queued && (state.free_slots == 0 || state.waiting.first().is_none_or(|first| first.arrival != arrival))
+            })
+            .unwrap();
+        if state.waiting.first().is_none_or(|first| first.arrival != arrival) {
+            return None;
+        }
+        state.waiting.pop_first();
+        state.free_slots -= 1;
+        state.virtual_time = tag;
+        // the next download in the queue may get another free slot
+        slot_freed.notify_all();
+        Some(DownloadSlot { queue: self.clone() })
+    }
+
+    /// Number of downloads waiting for a slot per dataset
+    pub fn waiting(&self) -> HashMap<DatasetId, usize> {
+        let mut waiting = HashMap::new();
+        for ticket in self.state.0.lock().unwrap().waiting.iter() {
+            *waiting.entry(ticket.dataset_id).or_default() += 1;
+        }
+        waiting
+    }
+
+    /// Move the queued download of the chunk to the high priority, returns `false` when it isn't queued
+    pub fn escalate(&self, chunk_id: &ChunkId) -> bool {
+        let (state, slot_freed) = &*self.state;
+        let mut state = state.lock().unwrap();
+        let Some(ticket) = state.waiting.iter().find(|ticket| ticket.chunk_id == *chunk_id).copied() else {
+            return false;
+        };
+        state.waiting.remove(&ticket);
+        state.waiting.insert(Ticket { priority: DownloadPriority::High, ..ticket });
+        slot_freed.notify_all();
+        true
+    }
+
+    /// Remove the queued download of the chunk, so it never gets a slot, returns `false` when it isn't queued
+    pub fn withdraw(&self, chunk_id: &ChunkId) -> bool {
+        let (state, slot_freed) = &*self.state;
+        let mut state = state.lock().unwrap();
+        let Some(ticket) = state.waiting.iter().find(|ticket| ticket.chunk_id == *chunk_id).copied() else {
+            return false;
+        };
+        state.waiting.remove(&ticket);
+        slot_freed.notify_all();
+        true
+    }
+}
+
+impl Drop for DownloadSlot {
+    fn drop(&mut self) {
+        let (state, slot_freed) = &*self.queue.state;
+        state.lock().unwrap().free_slots += 1;
+        slot_freed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn test_slots_are_shared_by_weight() {
+        // Arrange
+        let (backfill, tip) = ([1u8; 32], [2u8; 32]);
+        let queue = FairQueue::new(DownloadSchedulingConfig { slots: 1, ..DownloadSchedulingConfig::default() }.with_weight(tip, 3));
+        let slot = queue.acquire(&[0u8; 32], &backfill, DownloadPriority::Normal);
+        let granted = Arc::new(Mutex::new(Vec::new()));
+        let downloads: Vec<_> = [backfill; 4].into_iter().chain([tip; 4])
+            .map(|dataset_id| {
+                let (queue, granted) = (queue.clone(), granted.clone());
+                thread::spawn(move || {
+                    let _slot = queue.acquire(&[0u8; 32], &dataset_id, DownloadPriority::Normal);
+                    granted.lock().unwrap().push(dataset_id);
+                })
+            })
+            .collect();
+        while queue.waiting().values().sum::<usize>() < 8 {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        // Act
+        drop(slot);
+        for download in downloads {
+            download.join().unwrap();
+        }
+
+        // Assert
+        let granted = granted.lock().unwrap();
+        assert_eq!(granted[..2], [tip, tip]);
+        assert_eq!(granted[..5].iter().filter(|dataset_id| **dataset_id == tip).count(), 4);
+        assert!(queue.waiting().is_empty());
+    }
+
+    #[test]
+    fn test_high_priority_goes_first() {
+        // Arrange
+        let queue = FairQueue::new(DownloadSchedulingConfig { slots: 1, ..DownloadSchedulingConfig::default() });
+        let slot = queue.acquire(&[0u8; 32], &[1u8; 32], DownloadPriority::Normal);
+        let granted = Arc::new(Mutex::new(Vec::new()));
+        let downloads: Vec<_> = [DownloadPriority::Normal, DownloadPriority::High]
+            .into_iter()
+            .enumerate()
+            .map(|(queued, priority)| {
+                let (download_queue, granted) = (queue.clone(), granted.clone());
+                let download = thread::spawn(move || {
+                    let _slot = download_queue.acquire(&[0u8; 32], &[1u8; 32], priority);
+                    granted.lock().unwrap().push(priority);
+                });
+                // the normal download is queued first
+                while queue.waiting().values().sum::<usize>() <= queued {
+                    thread::sleep(Duration::from_millis(5));
+                }
+                download
+            })
+            .collect();
+
+        // Act
+        drop(slot);
+        for download in downloads {
+            download.join().unwrap();
+        }
+
+        // Assert
+        assert_eq!(*granted.lock().unwrap(), vec![DownloadPriority::High, DownloadPriority::Normal]);
+    }
+
+    #[test]
+    fn test_escalated_download_goes_first() {
+        // Arrange
+        let queue = FairQueue::new(DownloadSchedulingConfig { slots: 1, ..DownloadSchedulingConfig::default() });
+        let slot = queue.acquire(&[0u8; 32], &[1u8; 32], DownloadPriority::Normal);
+        let granted = Arc::new(Mutex::new(Vec::new()));
+        let downloads: Vec<_> = [[1u8; 32], [2u8; 32]]
+            .into_iter()
+            .enumerate()
+            .map(|(queued, chunk_id)| {
+                let (download_queue, granted) = (queue.clone(), granted.clone());
+                let download = thread::spawn(move || {
+                    let _slot = download_queue.acquire(&chunk_id, &[1u8; 32], DownloadPriority::Normal);
+                    granted.lock().unwrap().push(chunk_id);
+                });
+                while queue.waiting().values().sum::<usize>() <= queued {
+                    thread::sleep(Duration::from_millis(5));
+                }
+                download
+            })
+            .collect();
+
+        // Act
+        let escalated = queue.escalate(&[2u8; 32]);
+        drop(slot);
+        for download in downloads {
+            download.join().unwrap();
+        }
+
+        // Assert
+        assert!(escalated);
+        assert!(!queue.escalate(&[2u8; 32]));
+        assert_eq!(*granted.lock().unwrap(), vec![[2u8; 32], [1u8; 32]]);
+    }
+}
@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+use std::sync::RwLock;
+use crate::data_chunk::{DataChunk, DataChunkPath, DatasetId};
+use crate::local_data_source::LocalDataSource;
+use crate::planning::DirectoryLayout;
+
+/// Read-only catalogue consulted by `find_chunk` when the chunk isn't held locally,
+/// e.g. an NFS-mounted archive or an export of a peer.
+/// The data manager never downloads or deletes chunks of a secondary catalogue.
+pub trait SecondaryCatalogue: Send + Sync {
+    /// Name reported in the `ChunkSource` of the found chunks
+    fn name(&self) -> &str;
+
+    fn find_chunk(&self, dataset_id: &DatasetId, block_number: u64) -> Option<DataChunkPath>;
+}
+
+/// Secondary catalogue over a directory laid out the same way as a data directory
+pub struct DirectoryCatalogue {
+    name: String,
+    source: LocalDataSource,
+    chunks: RwLock<Vec<DataChunk>>,
+}
+
+impl DirectoryCatalogue {
+    /// Scan the directory for chunks
+    pub fn open(name: &str, data_dir: PathBuf) -> Self {
+        Self::open_with_layout(name, data_dir, DirectoryLayout::default())
+    }
+
+    /// Scan the directory with the given layout for chunks
+    pub fn open_with_layout(name: &str, data_dir: PathBuf, layout: DirectoryLayout) -> Self {
+        let catalogue = DirectoryCatalogue {
+            name: name.to_string(),
+            source: LocalDataSource::new(data_dir).with_layout(layout),
+            chunks: RwLock::new(Vec::new()),
+        };
+        catalogue.refresh();
+        catalogue
+    }
+
+    /// Scan the directory again, to pick up the chunks added to it since
+    pub fn refresh(&self) {
+        let chunks = self.source.get_local_chunks();
+        *self.chunks.write().unwrap() = chunks;
+    }
+}
+
+impl SecondaryCatalogue for DirectoryCatalogue {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn find_chunk(&self, dataset_id: &DatasetId, block_number: u64) -> Option<DataChunkPath> {
+        self.chunks.read().unwrap()
+            .iter()
+            .find(|chunk| chunk.dataset_id == *dataset_id && chunk.block_range.contains(&block_number))
+            .map(|chunk| DataChunkPath::secondary(&self.name, &self.source.data_dir, self.source.layout, chunk.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::data_chunk::ChunkSource;
+    use crate::test_support;
+    use super::*;
+
+    #[test]
+    fn test_directory_catalogue() {
+        // Arrange
+        let data_dir = test_support::temp_dir("archive");
+        fs::create_dir_all(data_dir.join(format!("dataset_id={}/block_range=0_10", hex::encode([1u8; 32])))).unwrap();
+        let catalogue = DirectoryCatalogue::open("archive", data_dir.clone());
+        fs::create_dir_all(data_dir.join(format!("dataset_id={}/block_range=10_20", hex::encode([1u8; 32])))).unwrap();
+
+        // Act
+        let before_refresh = catalogue.find_chunk(&[1u8; 32], 15);
+        catalogue.refresh();
+        let after_refresh = catalogue.find_chunk(&[1u8; 32], 15);
+
+        // Assert
+        assert!(before_refresh.is_none());
+        let chunk_path = after_refresh.unwrap();
+        assert_eq!(chunk_path.source, ChunkSource::Secondary("archive".to_string()));
+        assert_eq!(chunk_path.chunk.block_range, 10..20);
+        assert!(chunk_path.path.starts_with(&data_dir));
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
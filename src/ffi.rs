@@ -0,0 +1,514 @@
+//! C interface of the data manager, enabled by the `ffi` feature.
+//!
+//! Build the shared library with:
+//! `cargo rustc --release --features ffi --crate-type cdylib`
+//!
+//! The declarations for C are in `include/data_manager.h`.
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+use crate::consumers;
+use crate::data_catalogue::ChunkStatus;
+use crate::data_chunk::{ChunkId, DataChunk, DataChunkPath, DataChunkRef, DatasetId};
+use crate::data_manager::DataManager;
+use crate::fair_queue::DownloadPriority;
+use crate::planning;
+use crate::DataManagerImpl;
+
+pub const DM_OK: c_int = 0;
+pub const DM_ERROR: c_int = -1;
+
+pub const DM_STATUS_DOWNLOADING: c_int = 0;
+pub const DM_STATUS_READY: c_int = 1;
+pub const DM_STATUS_DELETING: c_int = 2;
+pub const DM_STATUS_DELETED: c_int = 3;
+pub const DM_STATUS_FAILED: c_int = 4;
+
+/// A file of the chunk and the URL to download it from
+#[repr(C)]
+pub struct DmFile {
+    pub name: *const c_char,
+    pub url: *const c_char,
+}
+
+/// Data chunk description, block range is `[block_start, block_end)`
+#[repr(C)]
+pub struct DmDataChunk {
+    pub id: [u8; 32],
+    pub dataset_id: [u8; 32],
+    pub block_start: u64,
+    pub block_end: u64,
+    pub files: *const DmFile,
+    pub files_len: usize,
+}
+
+/// Chunk found by `dm_find_chunk`, the chunk stays on disk until it's freed
+pub struct DmChunkRef {
+    chunk_path: DataChunkPath,
+    path: CString,
+}
+
+/// Called on every status change of a chunk with the chunk id (32 bytes) and one of `DM_STATUS_*`
+pub type DmEventCallback = extern "C" fn(user_data: *mut c_void, chunk_id: *const u8, status: c_int);
+
+struct UserData(*mut c_void);
+
+// the caller is responsible for the user data being usable from any thread
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+/// Create a data manager using `data_dir`, returns null when the path is not valid UTF-8
+///
+/// # Safety
+/// `data_dir` must be a valid null terminated string
+#[no_mangle]
+pub unsafe extern "C" fn dm_new(data_dir: *const c_char) -> *mut DataManagerImpl {
+    guard(ptr::null_mut(), || {
+        if data_dir.is_null() {
+            return ptr::null_mut();
+        }
+        let Ok(data_dir) = CStr::from_ptr(data_dir).to_str() else {
+            return ptr::null_mut();
+        };
+        Box::into_raw(Box::new(DataManagerImpl::new(PathBuf::from(data_dir))))
+    })
+}
+
+/// Free the data manager created by `dm_new`
+///
+/// # Safety
+/// `manager` must come from `dm_new` and must not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn dm_free(manager: *mut DataManagerImpl) {
+    guard((), || {
+        if !manager.is_null() {
+            drop(Box::from_raw(manager));
+        }
+    })
+}
+
+/// Schedule the chunk download in background. Returns `DM_ERROR` when the chunk id doesn't match the dataset
+/// and block range, or the download is refused, e.g. because the chunk is already being processed.
+///
+/// # Safety
+/// `manager` must come from `dm_new`, `chunk` must point to a valid chunk with `files_len` files
+#[no_mangle]
+pub unsafe extern "C" fn dm_download_chunk(manager: *const DataManagerImpl, chunk: *const DmDataChunk) -> c_int {
+    guard(DM_ERROR, || {
+        let (Some(manager), Some(chunk)) = (manager.as_ref(), chunk.as_ref()) else {
+            return DM_ERROR;
+        };
+        match to_data_chunk(chunk) {
+            Some(chunk) => match manager.request_download(chunk, DownloadPriority::Normal) {
+                Ok(()) => DM_OK,
+                Err(_) => DM_ERROR,
+            },
+            None => DM_ERROR,
+        }
+    })
+}
+
+/// Copy ids of available chunks into `chunk_ids` (32 bytes each) up to `capacity` ids.
+/// Returns the number of available chunks, which may be larger than `capacity`.
+///
+/// # Safety
+/// `manager` must come from `dm_new`, `chunk_ids` must have room for `capacity * 32` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dm_list_chunks(manager: *const DataManagerImpl, chunk_ids: *mut u8, capacity: usize) -> usize {
+    guard(0, || {
+        let Some(manager) = manager.as_ref() else {
+            return 0;
+        };
+        let available = manager.list_chunks();
+        if !chunk_ids.is_null() {
+            for (i, chunk_id) in available.iter().take(capacity).enumerate() {
+                ptr::copy_nonoverlapping(chunk_id.as_ptr(), chunk_ids.add(i * 32), 32);
+            }
+        }
+        available.len()
+    })
+}
+
+/// Find a chunk of the dataset responsible for `block_number`, returns null when there is none.
+/// The returned reference must be freed with `dm_chunk_ref_free`.
+///
+/// # Safety
+/// `manager` must come from `dm_new`, `dataset_id` must point to 32 bytes
+#[no_mangle]
+pub unsafe extern "C" fn dm_find_chunk(manager: *const DataManagerImpl, dataset_id: *const u8, block_number: u64) -> *mut DmChunkRef {
+    guard(ptr::null_mut(), || {
+        let (Some(manager), Some(dataset_id)) = (manager.as_ref(), read_id(dataset_id)) else {
+            return ptr::null_mut();
+        };
+        let Some(chunk_ref) = manager.find_chunk_path(dataset_id, block_number) else {
+            return ptr::null_mut();
+        };
+        let Ok(path) = CString::new(chunk_ref.path().to_string_lossy().into_owned()) else {
+            return ptr::null_mut();
+        };
+        Box::into_raw(Box::new(DmChunkRef { chunk_path: chunk_ref, path }))
+    })
+}
+
+/// Directory of the chunk, valid until the reference is freed
+///
+/// # Safety
+/// `chunk_ref` must come from `dm_find_chunk`
+#[no_mangle]
+pub unsafe extern "C" fn dm_chunk_ref_path(chunk_ref: *const DmChunkRef) -> *const c_char {
+    guard(ptr::null(), || {
+        match chunk_ref.as_ref() {
+            Some(chunk_ref) => chunk_ref.path.as_ptr(),
+            None => ptr::null(),
+        }
+    })
+}
+
+/// Copy the chunk id (32 bytes) of the reference into `chunk_id`
+///
+/// # Safety
+/// `chunk_ref` must come from `dm_find_chunk`, `chunk_id` must have room for 32 bytes
+#[no_mangle]
+pub unsafe extern "C" fn dm_chunk_ref_id(chunk_ref: *const DmChunkRef, chunk_id: *mut u8) -> c_int {
+    guard(DM_ERROR, || {
+        match chunk_ref.as_ref() {
+            Some(chunk_ref) if !chunk_id.is_null() => {
+                ptr::copy_nonoverlapping(chunk_ref.chunk_path.chunk.id.as_ptr(), chunk_id, 32);
+                DM_OK
+            }
+            _ => DM_ERROR,
+        }
+    })
+}
+
+/// Release the chunk reference, the chunk may be deleted afterwards
+///
+/// # Safety
+/// `chunk_ref` must come from `dm_find_chunk` and must not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn dm_chunk_ref_free(chunk_ref: *mut DmChunkRef) {
+    guard((), || {
+        if !chunk_ref.is_null() {
+            drop(Box::from_raw(chunk_ref));
+        }
+    })
+}
+
+/// Schedule the chunk deletion in background. Returns `DM_ERROR` when the chunk is unknown or not ready,
+/// or its deletion is refused, e.g. because it's under a legal hold or its dataset is frozen.
+///
+/// # Safety
+/// `manager` must come from `dm_new`, `chunk_id` must point to 32 bytes
+#[no_mangle]
+pub unsafe extern "C" fn dm_delete_chunk(manager: *const DataManagerImpl, chunk_id: *const u8) -> c_int {
+    guard(DM_ERROR, || {
+        let (Some(manager), Some(chunk_id)) = (manager.as_ref(), read_id(chunk_id)) else {
+            return DM_ERROR;
+        };
+        if manager.request_deletion(chunk_id) { DM_OK } else { DM_ERROR }
+    })
+}
+
+/// Register a callback for chunk status changes, returns `DM_ERROR` for a null callback.
+/// The callback is called from the threads doing the work, so it must be cheap and thread safe.
+///
+/// # Safety
+/// `manager` must come from `dm_new`, `user_data` must stay valid while the manager lives
+#[no_mangle]
+pub unsafe extern "C" fn dm_set_event_callback(manager: *const DataManagerImpl, callback: Option<DmEventCallback>, user_data: *mut c_void) -> c_int {
+    guard(DM_ERROR, || {
+        // a null function pointer arrives as `None`
+        let (Some(manager), Some(callback)) = (manager.as_ref(), callback) else {
+            return DM_ERROR;
+        };
+        let user_data = UserData(user_data);
+        manager.data_catalogue.add_status_listener(Box::new(move |chunk, status| {
+            let user_data = &user_data;
+            callback(user_data.0, chunk.id.as_ptr(), status_code(status));
+        }));
+        DM_OK
+    })
+}
+
+/// Attribute the following reads of the calling thread to the consumer, e.g. the tenant of the request being served.
+/// A null `consumer` attributes them to `anonymous` again.
+///
+/// # Safety
+/// `consumer` must be null or a valid null terminated string
+#[no_mangle]
+pub unsafe extern "C" fn dm_set_consumer(consumer: *const c_char) -> c_int {
+    guard(DM_ERROR, || {
+        if consumer.is_null() {
+            consumers::set_current(None);
+            return DM_OK;
+        }
+        let Ok(consumer) = CStr::from_ptr(consumer).to_str() else {
+            return DM_ERROR;
+        };
+        consumers::set_current(Some(consumer.to_string()));
+        DM_OK
+    })
+}
+
+/// Copy the queries and bytes served to the consumer into `queries` and `bytes_served`, zeros for an unknown consumer
+///
+/// # Safety
+/// `manager` must come from `dm_new`, `consumer` must be a valid null terminated string,
+/// `queries` and `bytes_served` must point to writable `uint64_t`s
+#[no_mangle]
+pub unsafe extern "C" fn dm_consumer_usage(manager: *const DataManagerImpl, consumer: *const c_char, queries: *mut u64, bytes_served: *mut u64) -> c_int {
+    guard(DM_ERROR, || {
+        let Some(manager) = manager.as_ref() else {
+            return DM_ERROR;
+        };
+        if consumer.is_null() || queries.is_null() || bytes_served.is_null() {
+            return DM_ERROR;
+        }
+        let Ok(consumer) = CStr::from_ptr(consumer).to_str() else {
+            return DM_ERROR;
+        };
+        let usage = manager.consumer_usage().get(consumer).copied().unwrap_or_default();
+        *queries = usage.queries;
+        *bytes_served = usage.bytes_served;
+        DM_OK
+    })
+}
+
+/// Run the body of an exported function, a panic mustn't unwind into the C caller, so it returns `on_panic` instead
+fn guard<T>(on_panic: T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or(on_panic)
+}
+
+fn status_code(status: &ChunkStatus) -> c_int {
+    match status {
+        ChunkStatus::Downloading => DM_STATUS_DOWNLOADING,
+        ChunkStatus::Ready => DM_STATUS_READY,
+        ChunkStatus::Deleting => DM_STATUS_DELETING,
+        ChunkStatus::Deleted => DM_STATUS_DELETED,
+        ChunkStatus::Failed => DM_STATUS_FAILED,
+    }
+}
+
+unsafe fn read_id(id: *const u8) -> Option<ChunkId> {
+    if id.is_null() {
+        return None;
+    }
+    let mut result = [0u8; 32];
+    ptr::copy_nonoverlapping(id, result.as_mut_ptr(), 32);
+    Some(result)
+}
+
+unsafe fn to_data_chunk(chunk: &DmDataChunk) -> Option<DataChunk> {
+    let mut files = HashMap::new();
+    if chunk.files_len > 0 {
+        if chunk.files.is_null() {
+            return None;
+        }
+        for file in std::slice::from_raw_parts(chunk.files, chunk.files_len) {
+            if file.name.is_null() || file.url.is_null() {
+                return None;
+            }
+            let name = CStr::from_ptr(file.name).to_str().ok()?;
+            let url = CStr::from_ptr(file.url).to_str().ok()?;
+            files.insert(name.to_string(), url.to_string());
+        }
+    }
+    let dataset_id: DatasetId = chunk.dataset_id;
+    // a chunk with a foreign id would be found under another chunk's directory
+    if planning::generate_chunk_id(&dataset_id, &(chunk.block_start..chunk.block_end)) != chunk.id {
+        return None;
+    }
+    Some(DataChunk {
+        id: chunk.id,
+        dataset_id,
+        block_range: chunk.block_start..chunk.block_end,
+        files,
+        mirrors: HashMap::new(),
+        size: None,
+        file_sizes: HashMap::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use serial_test::serial;
+    use crate::data_catalogue::load_catalogue_with_local_chunks;
+    use crate::local_data_source::{get_test_chunk_111111_0_35, get_test_chunk_111111_95_106};
+    use crate::test_support;
+    use super::*;
+
+    extern "C" fn count_events(user_data: *mut c_void, _chunk_id: *const u8, _status: c_int) {
+        let counter = unsafe { &*(user_data as *const AtomicUsize) };
+        counter.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Wait until the callback counted the events
+    fn wait_for_events(counter: &AtomicUsize, events: usize) {
+        test_support::wait_until("not all the events arrived", || counter.load(Ordering::SeqCst) >= events);
+    }
+
+    #[test]
+    #[serial]
+    fn test_list_and_find_chunks() {
+        unsafe {
+            // Arrange
+            load_catalogue_with_local_chunks();
+            let data_dir = CString::new("./local_data_dir").unwrap();
+            let manager = dm_new(data_dir.as_ptr());
+
+            // Act
+            let mut chunk_ids = [0u8; 32 * 4];
+            let count = dm_list_chunks(manager, chunk_ids.as_mut_ptr(), 4);
+            let chunk_ref = dm_find_chunk(manager, [17u8; 32].as_ptr(), 12);
+
+            // Assert
+            assert_eq!(count, 8);
+            assert!(!chunk_ref.is_null());
+            let path = CStr::from_ptr(dm_chunk_ref_path(chunk_ref)).to_str().unwrap();
+            assert_eq!(path, "./local_data_dir/dataset_id=1111111111111111111111111111111111111111111111111111111111111111/block_range=0_35/");
+            assert!(dm_find_chunk(manager, [17u8; 32].as_ptr(), 300).is_null());
+
+            dm_chunk_ref_free(chunk_ref);
+            dm_free(manager);
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_reads_are_attributed_to_the_consumer() {
+        unsafe {
+            // Arrange
+            load_catalogue_with_local_chunks();
+            let data_dir = CString::new("./local_data_dir").unwrap();
+            let manager = dm_new(data_dir.as_ptr());
+            let consumer = CString::new("tenant-a").unwrap();
+            let (mut queries, mut bytes_served) = (0u64, 0u64);
+
+            // Act
+            assert_eq!(dm_set_consumer(consumer.as_ptr()), DM_OK);
+            let chunk_ref = dm_find_chunk(manager, [17u8; 32].as_ptr(), 12);
+            dm_find_chunk(manager, [17u8; 32].as_ptr(), 300);
+            assert_eq!(dm_set_consumer(ptr::null()), DM_OK);
+            dm_find_chunk(manager, [17u8; 32].as_ptr(), 300);
+            let result = dm_consumer_usage(manager, consumer.as_ptr(), &mut queries, &mut bytes_served);
+
+            // Assert
+            assert_eq!(result, DM_OK);
+            assert_eq!((queries, bytes_served), (2, 0));
+            assert_eq!((*manager).consumer_usage()[consumers::ANONYMOUS_CONSUMER].queries, 1);
+
+            dm_chunk_ref_free(chunk_ref);
+            dm_free(manager);
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_refused_downloads_are_errors() {
+        unsafe {
+            // Arrange
+            load_catalogue_with_local_chunks();
+            let data_dir = CString::new("./local_data_dir").unwrap();
+            let manager = dm_new(data_dir.as_ptr());
+            let ready = get_test_chunk_111111_0_35();
+            let dm_chunk = |id: ChunkId| DmDataChunk {
+                id,
+                dataset_id: ready.dataset_id,
+                block_start: ready.block_range.start,
+                block_end: ready.block_range.end,
+                files: ptr::null(),
+                files_len: 0,
+            };
+
+            // Act
+            let foreign_id = dm_download_chunk(manager, &dm_chunk([7u8; 32]));
+            let already_ready = dm_download_chunk(manager, &dm_chunk(ready.id));
+
+            // Assert
+            assert_eq!(foreign_id, DM_ERROR);
+            assert_eq!(already_ready, DM_ERROR);
+            assert_eq!(dm_list_chunks(manager, ptr::null_mut(), 0), 8);
+
+            dm_free(manager);
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_refused_deletions_are_errors() {
+        unsafe {
+            // Arrange
+            load_catalogue_with_local_chunks();
+            let data_dir = CString::new("./local_data_dir").unwrap();
+            let manager = dm_new(data_dir.as_ptr());
+            let ready = get_test_chunk_111111_0_35();
+            (*manager).data_catalogue.freeze(ready.dataset_id);
+
+            // Act
+            let unknown = dm_delete_chunk(manager, [7u8; 32].as_ptr());
+            let frozen = dm_delete_chunk(manager, ready.id.as_ptr());
+            let null_callback = dm_set_event_callback(manager, None, ptr::null_mut());
+
+            // Assert
+            assert_eq!(unknown, DM_ERROR);
+            assert_eq!(frozen, DM_ERROR);
+            assert_eq!(null_callback, DM_ERROR);
+            assert_eq!(dm_list_chunks(manager, ptr::null_mut(), 0), 8);
+
+            dm_free(manager);
+        }
+    }
+
+    #[test]
+    fn test_panics_do_not_unwind_into_the_caller() {
+        assert_eq!(guard(DM_ERROR, || panic!("bug in the data manager")), DM_ERROR);
+        assert!(guard(ptr::null_mut::<DmChunkRef>(), || panic!("bug in the data manager")).is_null());
+    }
+
+    #[test]
+    #[serial]
+    fn test_download_with_events() {
+        unsafe {
+            // Arrange
+            load_catalogue_with_local_chunks();
+            let data_dir = CString::new("./local_data_dir").unwrap();
+            let manager = dm_new(data_dir.as_ptr());
+            let counter = AtomicUsize::new(0);
+            dm_set_event_callback(manager, Some(count_events), &counter as *const AtomicUsize as *mut c_void);
+            let chunk = get_test_chunk_111111_95_106();
+            let names: Vec<CString> = chunk.files.keys().map(|name| CString::new(name.as_str()).unwrap()).collect();
+            let urls: Vec<CString> = chunk.files.values().map(|url| CString::new(url.as_str()).unwrap()).collect();
+            let files: Vec<DmFile> = names.iter().zip(urls.iter())
+                .map(|(name, url)| DmFile { name: name.as_ptr(), url: url.as_ptr() })
+                .collect();
+            let dm_chunk = DmDataChunk {
+                id: chunk.id,
+                dataset_id: chunk.dataset_id,
+                block_start: chunk.block_range.start,
+                block_end: chunk.block_range.end,
+                files: files.as_ptr(),
+                files_len: files.len(),
+            };
+
+            // Act
+            assert_eq!(dm_download_chunk(manager, &dm_chunk), DM_OK);
+            wait_for_events(&counter, 2);
+
+            // Assert: Downloading and Ready
+            assert_eq!(counter.load(Ordering::SeqCst), 2);
+            assert_eq!(dm_list_chunks(manager, ptr::null_mut(), 0), 9);
+
+            // cleanup, a chunk already being deleted can't be deleted again
+            assert_eq!(dm_delete_chunk(manager, chunk.id.as_ptr()), DM_OK);
+            assert_eq!(dm_delete_chunk(manager, chunk.id.as_ptr()), DM_ERROR);
+            // Deleting and Deleted
+            wait_for_events(&counter, 4);
+            assert_eq!(counter.load(Ordering::SeqCst), 4);
+            dm_free(manager);
+        }
+    }
+}
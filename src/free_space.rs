@@ -0,0 +1,274 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use crate::data_catalogue::{ChunkStatus, DataCatalogue};
+use crate::data_chunk::DataChunk;
+
+/// What happens to a download when the data directory hasn't room for the chunk
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum InsufficientSpaceAction {
+    /// The download isn't started, the caller gets an `InsufficientSpace` error
+    #[default]
+    Refuse,
+    /// The download waits outside the catalogue until there is room, e.g. while old chunks are being deleted
+    Defer,
+}
+
+/// Check of the free space of the data directory before a chunk is moved to `Downloading`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FreeSpaceConfig {
+    /// Bytes always left free in the data directory, e.g. for the catalogue and the logs
+    pub reserve_bytes: u64,
+    pub when_insufficient: InsufficientSpaceAction,
+    /// How often a deferred download checks the free space again
+    pub recheck_interval: Duration,
+}
+
+impl Default for FreeSpaceConfig {
+    fn default() -> Self {
+        FreeSpaceConfig {
+            reserve_bytes: 0,
+            when_insufficient: InsufficientSpaceAction::Refuse,
+            recheck_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// The chunk doesn't fit in the free space of the data directory
+#[derive(Clone, Debug, PartialEq)]
+pub struct InsufficientSpace {
+    /// Declared size of the chunk, with the reserve and the declared sizes of the downloads in flight
+    pub required_bytes: u64,
+    pub available_bytes: u64,
+}
+
+impl fmt::Display for InsufficientSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "data directory has {} bytes available, the download needs {}", self.available_bytes, self.required_bytes)
+    }
+}
+
+impl std::error::Error for InsufficientSpace {}
+
+/// Free space of the file system holding a directory
+pub trait DiskSpace: Send + Sync {
+    /// Bytes the process may still write to the file system of the directory
+    fn available_bytes(&self, dir: &Path) -> io::Result<u64>;
+}
+
+/// Free space as reported by `statvfs`, only available on unix
+pub struct StatvfsDiskSpace;
+
+impl DiskSpace for StatvfsDiskSpace {
+    #[cfg(unix)]
+    // the widths of the fields differ between the platforms
+    #[allow(clippy::unnecessary_cast)]
+    fn available_bytes(&self, dir: &Path) -> io::Result<u64> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        let path = CString::new(dir.as_os_str().as_bytes()).map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+
+    #[cfg(not(unix))]
+    fn available_bytes(&self, _dir: &Path) -> io::Result<u64> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "free space is only read on unix"))
+    }
+}
+
+/// Compares the declared size of a chunk with the free space of the data directory, shared by the manager and the workers
+#[derive(Clone)]
+pub struct SpacePreflight {
+    pub config: FreeSpaceConfig,
+    disk_space: Arc<dyn DiskSpace>,
+    data_dir: PathBuf,
+}
+
+impl SpacePreflight {
+    pub fn new(config: FreeSpaceConfig, disk_space: Arc<dyn DiskSpace>, data_dir: PathBuf) -> Self {
+        SpacePreflight { config, disk_space, data_dir }
+    }
+
+    /// Whether the chunk fits next to the reserve and the other downloads in flight, whose declared sizes are counted
+    /// as taken even for the part already written.
+    /// Chunks without a declared size pass, as does everything when the free space can't be read,
+    /// so an unsupported file system doesn't hold up the downloads.
+    pub fn check(&self, data_catalogue: &DataCatalogue, chunk: &DataChunk) -> Result<(), InsufficientSpace> {
+        let Some(chunk_bytes) = chunk.expected_size() else { return Ok(()) };
+        let Ok(available_bytes) = self.disk_space.available_bytes(&self.data_dir) else { return Ok(()) };
+        let in_flight_bytes: u64 = data_catalogue.registry.read().unwrap()
+            .values()
+            .filter(|info| info.status == ChunkStatus::Downloading && info.chunk.id != chunk.id)
+            .filter_map(|info| info.chunk.expected_size())
+            .sum();
+        let required_bytes = chunk_bytes + self.config.reserve_bytes + in_flight_bytes;
+        if required_bytes > available_bytes {
+            return Err(InsufficientSpace { required_bytes, available_bytes });
+        }
+        Ok(())
+    }
+
+    /// Wait until the chunk fits, or return the shortage right away when the downloads aren't deferred
+    pub fn wait_for_space(&self, data_catalogue: &DataCatalogue, chunk: &DataChunk) -> Result<(), InsufficientSpace> {
+        loop {
+            match self.check(data_catalogue, chunk) {
+                Err(_) if self.config.when_insufficient == InsufficientSpaceAction::Defer => thread::sleep(self.config.recheck_interval),
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::ops::Range;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use crate::config::DataManagerConfig;
+    use crate::download_handle::DownloadError;
+    use crate::epoch::{EpochError, EpochLayout};
+    use crate::transfer::ChunkTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// File system with a settable free space
+    #[derive(Default)]
+    struct FixedDiskSpace(AtomicU64);
+
+    impl DiskSpace for FixedDiskSpace {
+        fn available_bytes(&self, _dir: &Path) -> io::Result<u64> {
+            Ok(self.0.load(Ordering::SeqCst))
+        }
+    }
+
+    struct SmallTransfer;
+
+    impl ChunkTransfer for SmallTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::create_dir_all(chunk_dir)?;
+            // as many bytes as declared, so the download passes the size check
+            fs::write(chunk_dir.join("blocks.parquet"), vec![0u8; chunk.size.unwrap_or_default() as usize])
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    fn chunk(block_range: Range<u64>, size: Option<u64>) -> DataChunk {
+        DataChunk { size, ..test_support::chunk(19, block_range) }
+    }
+
+    #[test]
+    fn test_check_counts_reserve_and_downloads_in_flight() {
+        let dir = test_support::temp_dir("free_space_check");
+        fs::create_dir_all(&dir).unwrap();
+        let data_catalogue = DataCatalogue::open(&dir.join("registry.parquet"), Vec::new());
+        let disk_space = Arc::new(FixedDiskSpace(AtomicU64::new(1_000)));
+        let config = FreeSpaceConfig { reserve_bytes: 200, ..FreeSpaceConfig::default() };
+        let preflight = SpacePreflight::new(config, disk_space, dir.clone());
+        data_catalogue.start_download(&chunk(0..10, Some(500)));
+
+        assert_eq!(preflight.check(&data_catalogue, &chunk(10..20, Some(300))), Ok(()));
+        assert_eq!(
+            preflight.check(&data_catalogue, &chunk(10..20, Some(301))),
+            Err(InsufficientSpace { required_bytes: 1_001, available_bytes: 1_000 }),
+        );
+        // the chunk itself isn't counted twice, and a chunk without a declared size can't be checked
+        assert_eq!(preflight.check(&data_catalogue, &chunk(0..10, Some(800))), Ok(()));
+        assert_eq!(preflight.check(&data_catalogue, &chunk(20..30, None)), Ok(()));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_downloads_refused_or_deferred_without_space() {
+        // Arrange
+        let dir = test_support::temp_dir("free_space");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let disk_space = Arc::new(FixedDiskSpace(AtomicU64::new(10)));
+        let deferring = FreeSpaceConfig {
+            when_insufficient: InsufficientSpaceAction::Defer,
+            recheck_interval: Duration::from_millis(10),
+            ..FreeSpaceConfig::default()
+        };
+        let build = |free_space: FreeSpaceConfig, name: &str| {
+            DataManagerImpl::builder()
+                .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join(name)).with_free_space(Some(free_space)))
+                .chunk_transfer(Arc::new(SmallTransfer))
+                .disk_space(disk_space.clone())
+                .build()
+        };
+        let refusing_manager = build(FreeSpaceConfig::default(), "refusing.parquet");
+        let deferring_manager = build(deferring, "deferring.parquet");
+        let (refused, deferred) = (chunk(0..10, Some(100)), chunk(10..20, Some(100)));
+
+        // Act
+        let refused_result = refusing_manager.download_chunk_with_handle(refused.clone()).wait();
+        let deferred_handle = deferring_manager.download_chunk_with_handle(deferred.clone());
+        thread::sleep(Duration::from_millis(50));
+        let deferred_status = deferring_manager.get_chunk_info(deferred.id).map(|info| info.status);
+        disk_space.0.store(1_000, Ordering::SeqCst);
+        let deferred_result = deferred_handle.wait();
+
+        // Assert
+        assert_eq!(refused_result, Err(DownloadError::InsufficientSpace(InsufficientSpace { required_bytes: 100, available_bytes: 10 })));
+        assert!(refusing_manager.get_chunk_info(refused.id).is_none());
+        assert_eq!(deferred_status, None);
+        assert_eq!(deferred_result, Ok(()));
+        assert_eq!(deferring_manager.get_chunk_info(deferred.id).unwrap().status, ChunkStatus::Ready);
+
+        // cleanup
+        drop(refusing_manager);
+        drop(deferring_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_epoch_which_does_not_fit_starts_nothing() {
+        // Arrange
+        let dir = test_support::temp_dir("free_space_epoch");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let disk_space = Arc::new(FixedDiskSpace(AtomicU64::new(600)));
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data"))
+                .with_catalogue_file(dir.join("registry.parquet"))
+                .with_free_space(Some(FreeSpaceConfig::default()))
+                .with_epochs(EpochLayout::new(100)))
+            .chunk_transfer(Arc::new(SmallTransfer))
+            .disk_space(disk_space.clone())
+            .build();
+        let (first, second) = (chunk(0..50, Some(400)), chunk(50..100, Some(400)));
+
+        // Act
+        let refused = data_manager.download_epoch(first.dataset_id, 0, vec![first.clone(), second.clone()]);
+        let first_info = data_manager.get_chunk_info(first.id);
+        let pending = data_manager.pending_downloads.downloads().len();
+        disk_space.0.store(1_000, Ordering::SeqCst);
+        let accepted = data_manager.download_epoch(first.dataset_id, 0, vec![first.clone(), second.clone()]);
+
+        // Assert
+        let shortage = InsufficientSpace { required_bytes: 800, available_bytes: 600 };
+        assert_eq!(refused, Err(EpochError::Refused(second.id, DownloadError::InsufficientSpace(shortage))));
+        assert!(first_info.is_none());
+        assert_eq!(pending, 0);
+        assert_eq!(accepted, Ok(()));
+        for chunk_id in [first.id, second.id] {
+            assert_eq!(data_manager.data_catalogue.wait_until_settled_for(&chunk_id, Duration::from_secs(5)), Some(ChunkStatus::Ready));
+        }
+        // the downloads are dropped from the persisted queue once ready, which rewrites it in the data directory
+        test_support::wait_until("downloads of the epoch are still pending", || data_manager.pending_downloads.downloads().is_empty());
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::retry::RetryPolicy;
+use crate::transfer::RangeSource;
+
+/// Tokens expiring sooner are refreshed before the request, so they don't expire in flight
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// OAuth2 access token of a service account
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccessToken {
+    pub value: String,
+    pub expires_at: Instant,
+}
+
+/// Issues access tokens of a service account with the `devstorage.read_only` scope,
+/// e.g. by exchanging a JWT signed with the key of the account or by asking the metadata server
+pub trait ServiceAccountCredentials: Send + Sync {
+    fn access_token(&self) -> io::Result<AccessToken>;
+}
+
+/// Failed request of the GCS JSON API
+#[derive(Clone, Debug, PartialEq)]
+pub struct GcsError {
+    /// HTTP status of the response, `None` when no response was received, e.g. a connection reset
+    pub status: Option<u16>,
+    pub message: String,
+}
+
+impl fmt::Display for GcsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.status {
+            Some(status) => write!(f, "GCS request failed with status {}: {}", status, self.message),
+            None => write!(f, "GCS request failed: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for GcsError {}
+
+impl GcsError {
+    /// Whether another attempt may succeed, as recommended by GCS: no response, 408, 429 and 5xx
+    pub fn is_transient(&self) -> bool {
+        match self.status {
+            None => true,
+            Some(status) => status == 408 || status == 429 || status >= 500,
+        }
+    }
+
+    fn is_unauthorized(&self) -> bool {
+        self.status == Some(401)
+    }
+}
+
+/// Metadata of an object, the generation changes whenever the object is replaced
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GcsObject {
+    pub size: u64,
+    pub generation: i64,
+}
+
+/// Requests of the GCS JSON API, implemented on top of an HTTP client sending the token as a bearer token
+pub trait GcsClient: Send + Sync {
+    /// `GET /storage/v1/b/<bucket>/o/<object>`
+    fn object(&self, bucket: &str, object: &str, token: &str) -> Result<GcsObject, GcsError>;
+
+    /// `GET /storage/v1/b/<bucket>/o/<object>?alt=media&generation=<generation>` with `Range: bytes=<offset>-`,
+    /// writing the received bytes as they arrive, returns the number of bytes written
+    fn read_object(&self, bucket: &str, object: &str, generation: i64, offset: u64, token: &str, writer: &mut dyn Write) -> Result<u64, GcsError>;
+}
+
+/// Serves the files of `gs://<bucket>/<object>` urls to a `ResumableTransfer`.
+///
+/// Transient failures are retried with the backoff of the retry policy, and a download cut short
+/// continues with a range request after the bytes already received, pinned to the generation of the object
+/// seen by `content_length`, so the bytes of a replaced object are never mixed with the old ones.
+/// An expired token is refreshed once on `401 Unauthorized`.
+pub struct GcsSource {
+    client: Arc<dyn GcsClient>,
+    credentials: Arc<dyn ServiceAccountCredentials>,
+    pub retry: RetryPolicy,
+    token: Mutex<Option<AccessToken>>,
+    /// Generations of the objects by url
+    generations: Mutex<HashMap<String, i64>>,
+}
+
+impl GcsSource {
+    pub fn new(client: Arc<dyn GcsClient>, credentials: Arc<dyn ServiceAccountCredentials>) -> Self {
+        GcsSource {
+            client,
+            credentials,
+            retry: RetryPolicy::default(),
+            token: Mutex::new(None),
+            generations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn token(&self, refresh: bool) -> io::Result<String> {
+        let mut token = self.token.lock().unwrap();
+        let expired = token.as_ref().is_none_or(|token| token.expires_at <= Instant::now() + TOKEN_REFRESH_MARGIN);
+        if refresh || expired {
+            *token = Some(self.credentials.access_token()?);
+        }
+        Ok(token.as_ref().unwrap().value.clone())
+    }
+
+    /// Run the request with a valid token until it succeeds, fails permanently or the attempts of the retry policy are exhausted
+    fn with_retries<T>(&self, url: &str, mut request: impl FnMut(&str) -> Result<T, RequestError>) -> io::Result<T> {
+        let mut refresh = false;
+        let mut attempt = 1;
+        loop {
+            let token = self.token(refresh)?;
+            let error = match request(&token) {
+                Ok(value) => return Ok(value),
+                Err(RequestError::Write(error)) => return Err(error),
+                Err(RequestError::Gcs(error)) => error,
+            };
+            if error.is_unauthorized() && !refresh {
+                // the token was revoked or expired early, a fresh one doesn't count as another attempt
+                refresh = true;
+                continue;
+            }
+            refresh = false;
+            let backoff = error.is_transient().then(|| self.retry.backoff(attempt)).flatten();
+            let Some(backoff) = backoff else {
+                return Err(io::Error::other(format!("{}: {}", url, error)));
+            };
+            thread::sleep(backoff);
+            attempt += 1;
+        }
+    }
+}
+
+impl RangeSource for GcsSource {
+    fn content_length(&self, url: &str) -> io::Result<u64> {
+        let (bucket, object) = parse_gs_url(url)?;
+        let metadata = self.with_retries(url, |token| self.client.object(bucket, object, token).map_err(RequestError::Gcs))?;
+        self.generations.lock().unwrap().insert(url.to_string(), metadata.generation);
+        Ok(metadata.size)
+    }
+
+    fn read_range(&self, url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+        let (bucket, object) = parse_gs_url(url)?;
+        let generation = match self.generations.lock().unwrap().get(url) {
+            Some(generation) => *generation,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}: content length was not requested", url))),
+        };
+        let mut writer = CountingWriter { inner: writer, written: 0, error: None };
+        self.with_retries(url, |token| {
+            let result = self.client.read_object(bucket, object, generation, offset + writer.written, token, &mut writer);
+            match (result, writer.error.take()) {
+                (Ok(_), _) => Ok(writer.written),
+                // failures of the writer, e.g. a cancelled download or a full disk, aren't failures of GCS
+                (Err(_), Some(error)) => Err(RequestError::Write(error)),
+                (Err(error), None) => Err(RequestError::Gcs(error)),
+            }
+        })
+    }
+}
+
+enum RequestError {
+    Gcs(GcsError),
+    Write(io::Error),
+}
+
+/// Counts the bytes received across the attempts and keeps the error of the underlying writer
+struct CountingWriter<'a> {
+    inner: &'a mut dyn Write,
+    written: u64,
+    error: Option<io::Error>,
+}
+
+impl Write for CountingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.inner.write(buf) {
+            Ok(written) => {
+                self.written += written as u64;
+                Ok(written)
+            }
+            Err(error) => {
+                let reported = io::Error::new(error.kind(), error.to_string());
+                self.error = Some(error);
+                Err(reported)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Bucket and object name of a `gs://<bucket>/<object>` url
+pub fn parse_gs_url(url: &str) -> io::Result<(&str, &str)> {
+    url.strip_prefix("gs://")
+        .and_then(|path| path.split_once('/'))
+        .filter(|(bucket, object)| !bucket.is_empty() && !object.is_empty())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a gs:// url", url)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use crate::data_chunk::DataChunk;
+    use crate::transfer::{ChunkTransfer, ResumableTransfer};
+    use crate::test_support;
+    use super::*;
+
+    /// Issues `token-1`, `token-2`, ... each valid for an hour
+    #[derive(Default)]
+    struct CountingCredentials {
+        issued: AtomicU32,
+    }
+
+    impl ServiceAccountCredentials for CountingCredentials {
+        fn access_token(&self) -> io::Result<AccessToken> {
+            let issued = self.issued.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(AccessToken { value: format!("token-{}", issued), expires_at: Instant::now() + Duration::from_secs(3600) })
+        }
+    }
+
+    /// Bucket failing the reads with the queued errors, after writing the given number of bytes
+    struct FlakyBucket {
+        content: Vec<u8>,
+        failures: Mutex<Vec<(usize, GcsError)>>,
+        requests: Mutex<Vec<(String, u64, String)>>,
+    }
+
+    impl GcsClient for FlakyBucket {
+        fn object(&self, _bucket: &str, _object: &str, _token: &str) -> Result<GcsObject, GcsError> {
+            Ok(GcsObject { size: self.content.len() as u64, generation: 7 })
+        }
+
+        fn read_object(&self, bucket: &str, object: &str, generation: i64, offset: u64, token: &str, writer: &mut dyn Write) -> Result<u64, GcsError> {
+            assert_eq!(generation, 7);
+            self.requests.lock().unwrap().push((format!("{}/{}", bucket, object), offset, token.to_string()));
+            let remaining = &self.content[offset as usize..];
+            let failure = self.failures.lock().unwrap().pop();
+            let io_error = |error: io::Error| GcsError { status: None, message: error.to_string() };
+            match failure {
+                Some((sent, error)) => {
+                    writer.write_all(&remaining[..sent]).map_err(io_error)?;
+                    Err(error)
+                }
+                None => {
+                    writer.write_all(remaining).map_err(io_error)?;
+                    Ok(remaining.len() as u64)
+                }
+            }
+        }
+    }
+
+    fn no_backoff() -> RetryPolicy {
+        RetryPolicy { initial_backoff: Duration::ZERO, jitter: 0.0, ..RetryPolicy::default() }
+    }
+
+    fn error(status: Option<u16>) -> GcsError {
+        GcsError { status, message: "failed".to_string() }
+    }
+
+    #[test]
+    fn test_gs_urls_are_parsed() {
+        assert_eq!(parse_gs_url("gs://chunks/dataset/blocks.parquet").unwrap(), ("chunks", "dataset/blocks.parquet"));
+        assert!(parse_gs_url("gs://chunks/").is_err());
+        assert!(parse_gs_url("https://storage.googleapis.com/chunks/blocks.parquet").is_err());
+    }
+
+    #[test]
+    fn test_interrupted_reads_continue_after_received_bytes() {
+        // Arrange
+        let chunk_dir = test_support::temp_dir("gcs");
+        let content: Vec<u8> = (0..100).collect();
+        // failures are popped from the end
+        let bucket = Arc::new(FlakyBucket {
+            content: content.clone(),
+            failures: Mutex::new(vec![(5, error(Some(503))), (0, error(Some(401))), (30, error(None))]),
+            requests: Mutex::new(Vec::new()),
+        });
+        let credentials = Arc::new(CountingCredentials::default());
+        let source = GcsSource::new(bucket.clone(), credentials.clone()).with_retry(no_backoff());
+        let chunk = DataChunk {
+            id: [1u8; 32],
+            dataset_id: [1u8; 32],
+            block_range: 0..10,
+            files: HashMap::from([("blocks.parquet".to_string(), "gs://chunks/dataset/blocks.parquet".to_string())]),
+            mirrors: HashMap::new(),
+            size: None,
+            file_sizes: HashMap::new(),
+        };
+
+        // Act
+        ResumableTransfer::new(Arc::new(source)).download(&chunk, &chunk_dir).unwrap();
+
+        // Assert
+        assert_eq!(fs::read(chunk_dir.join("blocks.parquet")).unwrap(), content);
+        let requests: Vec<(u64, String)> = bucket.requests.lock().unwrap().iter().map(|(_, offset, token)| (*offset, token.clone())).collect();
+        assert_eq!(requests, vec![
+            (0, "token-1".to_string()),
+            (30, "token-1".to_string()),
+            (30, "token-2".to_string()),
+            (35, "token-2".to_string()),
+        ]);
+        fs::remove_dir_all(chunk_dir).unwrap();
+    }
+
+    #[test]
+    fn test_permanent_failures_are_not_retried() {
+        // Arrange
+        let bucket = Arc::new(FlakyBucket {
+            content: vec![1, 2, 3],
+            failures: Mutex::new(vec![(0, error(Some(403)))]),
+            requests: Mutex::new(Vec::new()),
+        });
+        let source = GcsSource::new(bucket.clone(), Arc::new(CountingCredentials::default())).with_retry(no_backoff());
+        let url = "gs://chunks/blocks.parquet";
+        let mut received = Vec::new();
+
+        // Act
+        source.content_length(url).unwrap();
+        let result = source.read_range(url, 0, &mut received);
+
+        // Assert
+        assert!(result.unwrap_err().to_string().contains("status 403"));
+        assert_eq!(bucket.requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_transient_failures_exhaust_retry_policy() {
+        // Arrange
+        let bucket = Arc::new(FlakyBucket {
+            content: vec![1, 2, 3],
+            failures: Mutex::new(vec![(0, error(Some(429))), (1, error(Some(500))), (1, error(Some(503)))]),
+            requests: Mutex::new(Vec::new()),
+        });
+        let source = GcsSource::new(bucket.clone(), Arc::new(CountingCredentials::default())).with_retry(no_backoff());
+        let url = "gs://chunks/blocks.parquet";
+        let mut received = Vec::new();
+
+        // Act
+        source.content_length(url).unwrap();
+        let result = source.read_range(url, 0, &mut received);
+
+        // Assert
+        assert!(result.is_err());
+        assert_eq!(received, vec![1, 2]);
+        assert_eq!(bucket.requests.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_failed_writes_are_not_retried() {
+        // Arrange
+        struct FullDisk;
+        impl Write for FullDisk {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only filesystem"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let bucket = Arc::new(FlakyBucket { content: vec![1, 2, 3], failures: Mutex::new(Vec::new()), requests: Mutex::new(Vec::new()) });
+        let source = GcsSource::new(bucket.clone(), Arc::new(CountingCredentials::default())).with_retry(no_backoff());
+        let url = "gs://chunks/blocks.parquet";
+
+        // Act
+        source.content_length(url).unwrap();
+        let result = source.read_range(url, 0, &mut FullDisk);
+
+        // Assert
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(bucket.requests.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
+use crate::planning::generate_chunk_ids;
+
+const FORMAT_VERSION: u8 = 1;
+
+/// Chunks held locally, exchanged between workers and coordinators.
+///
+/// Chunk ids are derived from the dataset id and the block range, so only the block ranges are sent.
+/// The encoding is:
+/// - format version, 1 byte
+/// - number of datasets, varint
+/// - for every dataset: dataset id (32 bytes), number of chunks (varint) and for every chunk
+///   the distance of its start from the start of the previous chunk (varint) and its length (varint)
+///
+/// Varints are LEB128, so a chunk usually takes a few bytes instead of a 64 characters long hex id.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Holdings {
+    pub datasets: BTreeMap<DatasetId, Vec<Range<u64>>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HoldingsError {
+    UnsupportedVersion(u8),
+    /// The input ended in the middle of a value
+    Truncated,
+    /// A number doesn't fit into 64 bits
+    Overflow,
+}
+
+impl fmt::Display for HoldingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HoldingsError::UnsupportedVersion(version) => write!(f, "unsupported holdings format version {}", version),
+            HoldingsError::Truncated => write!(f, "holdings are truncated"),
+            HoldingsError::Overflow => write!(f, "holdings contain a number out of range"),
+        }
+    }
+}
+
+impl std::error::Error for HoldingsError {}
+
+impl Holdings {
+    pub fn from_chunks<'a>(chunks: impl IntoIterator<Item = &'a DataChunk>) -> Self {
+        let mut datasets: BTreeMap<DatasetId, Vec<Range<u64>>> = BTreeMap::new();
+        for chunk in chunks {
+            datasets.entry(chunk.dataset_id).or_default().push(chunk.block_range.clone());
+        }
+        for block_ranges in datasets.values_mut() {
+            block_ranges.sort_by_key(|block_range| (block_range.start, block_range.end));
+        }
+        Holdings { datasets }
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.datasets.values().map(|block_ranges| block_ranges.len()).sum()
+    }
+
+    pub fn chunk_ids(&self) -> Vec<ChunkId> {
+        self.datasets
+            .iter()
+            .flat_map(|(dataset_id, block_ranges)| generate_chunk_ids(dataset_id, block_ranges))
+            .collect()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![FORMAT_VERSION];
+        write_varint(&mut bytes, self.datasets.len() as u64);
+        for (dataset_id, block_ranges) in self.datasets.iter() {
+            bytes.extend_from_slice(dataset_id);
+            write_varint(&mut bytes, block_ranges.len() as u64);
+            let mut previous_start = 0;
+            for block_range in block_ranges {
+                write_varint(&mut bytes, block_range.start - previous_start);
+                write_varint(&mut bytes, block_range.end.saturating_sub(block_range.start));
+                previous_start = block_range.start;
+            }
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, HoldingsError> {
+        let mut reader = Reader { bytes, position: 0 };
+        let version = reader.read_byte()?;
+        if version != FORMAT_VERSION {
+            return Err(HoldingsError::UnsupportedVersion(version));
+        }
+
+        let mut datasets = BTreeMap::new();
+        for _ in 0..reader.read_varint()? {
+            let mut dataset_id = [0u8; 32];
+            dataset_id.copy_from_slice(reader.read_bytes(32)?);
+            let chunk_count = reader.read_varint()?;
+            // every chunk takes at least two bytes, so a broken count can't allocate too much
+            let mut block_ranges = Vec::with_capacity((chunk_count as usize).min(reader.remaining() / 2));
+            let mut start = 0u64;
+            for _ in 0..chunk_count {
+                start = start.checked_add(reader.read_varint()?).ok_or(HoldingsError::Overflow)?;
+                let end = start.checked_add(reader.read_varint()?).ok_or(HoldingsError::Overflow)?;
+                block_ranges.push(start..end);
+            }
+            datasets.insert(dataset_id, block_ranges);
+        }
+        Ok(Holdings { datasets })
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    fn read_byte(&mut self) -> Result<u8, HoldingsError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], HoldingsError> {
+        if self.remaining() < count {
+            return Err(HoldingsError::Truncated);
+        }
+        let bytes = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn read_varint(&mut self) -> Result<u64, HoldingsError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_byte()?;
+            let bits = (byte & 0x7f) as u64;
+            if shift == 63 && bits > 1 {
+                return Err(HoldingsError::Overflow);
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(HoldingsError::Overflow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(dataset_id: DatasetId, block_range: Range<u64>) -> DataChunk {
+        DataChunk::new(dataset_id, block_range)
+    }
+
+    #[test]
+    fn test_encode_and_decode() {
+        // Arrange
+        let chunks = vec![
+            chunk([2u8; 32], (1 << 40)..(1 << 40) + 100_000),
+            chunk([1u8; 32], 100_000..200_000),
+            chunk([1u8; 32], 0..100_000),
+        ];
+        let holdings = Holdings::from_chunks(&chunks);
+
+        // Act
+        let decoded = Holdings::decode(&holdings.encode()).unwrap();
+
+        // Assert
+        assert_eq!(decoded, holdings);
+        assert_eq!(decoded.chunk_count(), 3);
+        assert_eq!(decoded.chunk_ids(), vec![chunks[2].id, chunks[1].id, chunks[0].id]);
+    }
+
+    #[test]
+    fn test_encoding_is_compact() {
+        let chunks: Vec<DataChunk> = (0..1000u64)
+            .map(|i| chunk([1u8; 32], i * 100_000..(i + 1) * 100_000))
+            .collect();
+        let bytes = Holdings::from_chunks(&chunks).encode();
+        // version, dataset count, dataset id, chunk count, the first chunk starting at 0 and the other chunks
+        assert_eq!(bytes.len(), 1 + 1 + 32 + 2 + 4 + 999 * 6);
+    }
+
+    #[test]
+    fn test_decode_broken_input() {
+        let bytes = Holdings::from_chunks(&[chunk([1u8; 32], 0..10)]).encode();
+        assert_eq!(Holdings::decode(&bytes[..bytes.len() - 1]), Err(HoldingsError::Truncated));
+        assert_eq!(Holdings::decode(&[7]), Err(HoldingsError::UnsupportedVersion(7)));
+        assert_eq!(Holdings::decode(&[FORMAT_VERSION, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]), Err(HoldingsError::Overflow));
+    }
+}
@@ -0,0 +1,167 @@
+use std::sync::Arc;
+use std::time::Duration;
+use crate::compaction::CompactionRun;
+use crate::data_chunk::DataChunk;
+use crate::event_loop::TasksManager;
+use crate::operation::OperationResult;
+
+/// Custom logic run at the transitions of a chunk, e.g. invalidating caches of a query engine.
+/// All methods have empty default implementations, so only the interesting ones need to be implemented.
+pub trait LifecycleHooks: Send + Sync {
+    /// The chunk was scheduled for download
+    fn on_download_start(&self, _chunk: &DataChunk) {}
+
+    /// The chunk was downloaded and is available for queries
+    fn on_download_complete(&self, _chunk: &DataChunk) {}
+
+    /// The chunk could not be downloaded
+    fn on_download_failed(&self, _chunk: &DataChunk, _error: &str) {}
+
+    /// The chunk is being deleted while readers still hold it, its files are removed once they drop it
+    /// or the grace elapses, see `DataManagerConfig::deletion_grace`
+    fn on_deprecate(&self, _chunk: &DataChunk, _grace: Duration) {}
+
+    /// The chunk files were deleted
+    fn on_delete(&self, _chunk: &DataChunk) {}
+
+    /// The chunk files were deleted to free space, rather than on request
+    fn on_evict(&self, _chunk: &DataChunk) {}
+
+    /// The chunk placed in the data directory by another process was registered and is available for queries
+    fn on_register(&self, _chunk: &DataChunk) {}
+
+    /// The rows of deleted chunks were dropped from the catalogue
+    fn on_compaction(&self, _run: &CompactionRun) {}
+
+    /// The chunk wasn't ready within the time it was needed in, see `download_chunk_with_deadline`
+    fn on_deadline_missed(&self, _chunk: &DataChunk, _within: Duration) {}
+
+    /// A background download, deletion or eviction finished, successfully or not
+    fn on_operation(&self, _result: &OperationResult) {}
+
+    /// The origin refused a download of the chunk with a 429 or 503 response, `retry_after` is the wait it asked for.
+    /// The download is retried after it when the `RetryPolicy` allows another attempt.
+    fn on_throttled(&self, _chunk: &DataChunk, _retry_after: Option<Duration>) {}
+}
+
+/// How the hooks are run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HookMode {
+    /// In the worker thread doing the transition, the transition waits for the hook
+    Sync,
+    /// In the tasks manager thread pool, the transition doesn't wait for the hook
+    Async,
+}
+
+/// A transition of a chunk, dispatched to the matching method of the hooks
+#[derive(Clone, Debug, PartialEq)]
+pub enum LifecycleEvent {
+    DownloadStart(DataChunk),
+    DownloadComplete(DataChunk),
+    DownloadFailed(DataChunk, String),
+    Deprecate(DataChunk, Duration),
+    Delete(DataChunk),
+    Evict(DataChunk),
+    Register(DataChunk),
+    Compaction(CompactionRun),
+    DeadlineMissed(DataChunk, Duration),
+    Operation(OperationResult),
+    Throttled(DataChunk, Option<Duration>),
+}
+
+impl LifecycleEvent {
+    fn dispatch(&self, hooks: &dyn LifecycleHooks) {
+        match self {
+            LifecycleEvent::DownloadStart(chunk) => hooks.on_download_start(chunk),
+            LifecycleEvent::DownloadComplete(chunk) => hooks.on_download_complete(chunk),
+            LifecycleEvent::DownloadFailed(chunk, error) => hooks.on_download_failed(chunk, error),
+            LifecycleEvent::Deprecate(chunk, grace) => hooks.on_deprecate(chunk, *grace),
+            LifecycleEvent::Delete(chunk) => hooks.on_delete(chunk),
+            LifecycleEvent::Evict(chunk) => hooks.on_evict(chunk),
+            LifecycleEvent::Register(chunk) => hooks.on_register(chunk),
+            LifecycleEvent::Compaction(run) => hooks.on_compaction(run),
+            LifecycleEvent::DeadlineMissed(chunk, within) => hooks.on_deadline_missed(chunk, *within),
+            LifecycleEvent::Operation(result) => hooks.on_operation(result),
+            LifecycleEvent::Throttled(chunk, retry_after) => hooks.on_throttled(chunk, *retry_after),
+        }
+    }
+}
+
+/// Hooks registered on the builder
+#[derive(Clone)]
+pub struct HookDispatcher {
+    hooks: Arc<Vec<(Arc<dyn LifecycleHooks>, HookMode)>>,
+    tasks_manager: TasksManager,
+}
+
+impl HookDispatcher {
+    pub fn new(hooks: Vec<(Arc<dyn LifecycleHooks>, HookMode)>, tasks_manager: TasksManager) -> Self {
+        HookDispatcher {
+            hooks: Arc::new(hooks),
+            tasks_manager,
+        }
+    }
+
+    pub(crate) fn emit(&self, event: LifecycleEvent) {
+        for (hooks, mode) in self.hooks.iter() {
+            match mode {
+                HookMode::Sync => event.dispatch(hooks.as_ref()),
+                HookMode::Async => {
+                    let hooks = hooks.clone();
+                    let event = event.clone();
+                    self.tasks_manager.spawn_task(move || event.dispatch(hooks.as_ref()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+    use super::*;
+
+    #[derive(Default)]
+    struct RecordingHooks {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl LifecycleHooks for RecordingHooks {
+        fn on_download_start(&self, _chunk: &DataChunk) {
+            self.calls.lock().unwrap().push("download_start".to_string());
+        }
+
+        fn on_download_failed(&self, _chunk: &DataChunk, error: &str) {
+            self.calls.lock().unwrap().push(format!("download_failed: {}", error));
+        }
+    }
+
+    fn chunk() -> DataChunk {
+        DataChunk { id: [1u8; 32], ..DataChunk::new([1u8; 32], 0..10) }
+    }
+
+    #[test]
+    fn test_sync_hooks_are_called_in_place() {
+        let hooks = Arc::new(RecordingHooks::default());
+        let dispatcher = HookDispatcher::new(vec![(hooks.clone(), HookMode::Sync)], TasksManager::default());
+
+        dispatcher.emit(LifecycleEvent::DownloadStart(chunk()));
+        dispatcher.emit(LifecycleEvent::DownloadComplete(chunk()));
+        dispatcher.emit(LifecycleEvent::DownloadFailed(chunk(), "timeout".to_string()));
+
+        assert_eq!(*hooks.calls.lock().unwrap(), vec!["download_start", "download_failed: timeout"]);
+    }
+
+    #[test]
+    fn test_async_hooks_are_called_in_background() {
+        let hooks = Arc::new(RecordingHooks::default());
+        let dispatcher = HookDispatcher::new(vec![(hooks.clone(), HookMode::Async)], TasksManager::default());
+
+        dispatcher.emit(LifecycleEvent::DownloadStart(chunk()));
+        thread::sleep(Duration::from_millis(50));
+
+        assert_eq!(*hooks.calls.lock().unwrap(), vec!["download_start"]);
+    }
+}
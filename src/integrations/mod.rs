@@ -0,0 +1,2 @@
+//! Adapters connecting the data manager to the schedulers of existing networks, so it drops in without glue code
+pub mod subsquid;
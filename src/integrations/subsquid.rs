@@ -0,0 +1,245 @@
+//! Assignments of the subsquid network scheduler, telling a worker which chunks of which datasets it serves.
+//!
+//! The assignment message lists the datasets with the chunks as published by the network:
+//!
+//! ```json
+//! {
+//!   "datasets": [{
+//!     "id": "s3://ethereum-mainnet",
+//!     "base_url": "https://ethereum-mainnet.example.com",
+//!     "chunks": [{
+//!       "id": "0000000000/0000000000-0000009999-1a2b3c4d",
+//!       "files": { "blocks.parquet": "blocks.parquet", "logs.parquet": "https://cdn.example.com/logs.parquet" }
+//!     }]
+//!   }]
+//! }
+//! ```
+//!
+//! The chunk id ends with the first and the last block of the chunk, both inclusive.
+//! File urls relative to the chunk are resolved against `<chunk base_url>`, which defaults to `<dataset base_url>/<chunk id>`.
+use std::collections::HashMap;
+use std::fmt;
+use serde_json::Value;
+use crate::data_chunk::{is_plain_file_name, DataChunk, DatasetId};
+use crate::planning::ChunkIdGenerator;
+use crate::sync_plan::SyncPlan;
+use crate::DataManagerImpl;
+
+/// Chunks the scheduler assigned to the worker
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assignment {
+    pub datasets: Vec<AssignedDataset>,
+}
+
+/// Chunks of a single dataset of the assignment
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssignedDataset {
+    /// Name of the dataset in the network, e.g. `s3://ethereum-mainnet`
+    pub name: String,
+    /// Id of the dataset in the data manager, see `dataset_id`
+    pub dataset_id: DatasetId,
+    pub chunks: Vec<DataChunk>,
+}
+
+/// Why an assignment message could not be parsed
+#[derive(Clone, Debug, PartialEq)]
+pub enum AssignmentError {
+    /// The message is not JSON
+    InvalidJson(String),
+    /// A field of the message is missing or has an unexpected type, with the path to it
+    InvalidField(String),
+    /// The chunk id doesn't end with the block range of the chunk
+    InvalidChunkId(String),
+}
+
+impl fmt::Display for AssignmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssignmentError::InvalidJson(message) => write!(f, "assignment is not valid JSON: {}", message),
+            AssignmentError::InvalidField(path) => write!(f, "assignment field {} is missing or invalid", path),
+            AssignmentError::InvalidChunkId(chunk_id) => write!(f, "chunk id {} doesn't end with a block range", chunk_id),
+        }
+    }
+}
+
+impl std::error::Error for AssignmentError {}
+
+/// Id of the dataset with the name in the network, the sha256 of the name
+pub fn dataset_id(name: &str) -> DatasetId {
+    let mut dataset_id = [0u8; 32];
+    hex::decode_to_slice(sha256::digest(name), &mut dataset_id).unwrap();
+    dataset_id
+}
+
+/// Parse the assignment message of the scheduler
+pub fn parse_assignment(message: &str) -> Result<Assignment, AssignmentError> {
+    let message: Value = serde_json::from_str(message).map_err(|error| AssignmentError::InvalidJson(error.to_string()))?;
+    let datasets = array(&message["datasets"], "datasets")?
+        .iter()
+        .enumerate()
+        .map(|(i, dataset)| parse_dataset(dataset, &format!("datasets[{}]", i)))
+        .collect::<Result<Vec<AssignedDataset>, AssignmentError>>()?;
+    Ok(Assignment { datasets })
+}
+
+/// Sync the datasets of the assignment with `ensure_chunks`, returns the plans being executed.
+/// Chunks no longer assigned are deleted, datasets missing from the assignment are left as they are.
+pub fn apply_assignment(data_manager: &DataManagerImpl, assignment: &Assignment) -> Vec<SyncPlan> {
+    assignment.datasets.iter()
+        .map(|dataset| data_manager.ensure_chunks(dataset.dataset_id, &dataset.chunks))
+        .collect()
+}
+
+fn parse_dataset(dataset: &Value, path: &str) -> Result<AssignedDataset, AssignmentError> {
+    let name = string(&dataset["id"], &format!("{}.id", path))?;
+    let dataset_id = dataset_id(name);
+    let base_url = dataset["base_url"].as_str();
+    let chunk_ids = ChunkIdGenerator::new(&dataset_id);
+    let chunks = array(&dataset["chunks"], &format!("{}.chunks", path))?
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| parse_chunk(chunk, dataset_id, &chunk_ids, base_url, &format!("{}.chunks[{}]", path, i)))
+        .collect::<Result<Vec<DataChunk>, AssignmentError>>()?;
+    Ok(AssignedDataset { name: name.to_string(), dataset_id, chunks })
+}
+
+fn parse_chunk(chunk: &Value, dataset_id: DatasetId, chunk_ids: &ChunkIdGenerator, dataset_url: Option<&str>, path: &str) -> Result<DataChunk, AssignmentError> {
+    let chunk_id = string(&chunk["id"], &format!("{}.id", path))?;
+    // e.g. `0000000000/0000000000-0000009999-1a2b3c4d`
+    let mut parts = chunk_id.rsplit('/').next().unwrap_or_default().split('-');
+    let block_range = match (parts.next().map(str::parse::<u64>), parts.next().map(str::parse::<u64>)) {
+        (Some(Ok(first)), Some(Ok(last))) if first <= last => first..last + 1,
+        _ => return Err(AssignmentError::InvalidChunkId(chunk_id.to_string())),
+    };
+    let chunk_url = match (chunk["base_url"].as_str(), dataset_url) {
+        (Some(chunk_url), _) => chunk_url.to_string(),
+        (None, Some(dataset_url)) => format!("{}/{}", dataset_url.trim_end_matches('/'), chunk_id),
+        (None, None) => return Err(AssignmentError::InvalidField(format!("{}.base_url", path))),
+    };
+    let files_path = format!("{}.files", path);
+    let files = chunk["files"].as_object()
+        .ok_or_else(|| AssignmentError::InvalidField(files_path.clone()))?
+        .iter()
+        .map(|(file_name, url)| {
+            // the names become paths in the chunk directory
+            if !is_plain_file_name(file_name) {
+                return Err(AssignmentError::InvalidField(format!("{}.{}", files_path, file_name)));
+            }
+            let url = string(url, &format!("{}.{}", files_path, file_name))?;
+            let url = if url.contains("://") { url.to_string() } else { format!("{}/{}", chunk_url.trim_end_matches('/'), url) };
+            Ok((file_name.clone(), url))
+        })
+        .collect::<Result<HashMap<String, String>, AssignmentError>>()?;
+    Ok(DataChunk {
+        id: chunk_ids.chunk_id(&block_range),
+        dataset_id,
+        block_range,
+        files,
+        mirrors: HashMap::new(),
+        size: None,
+        file_sizes: HashMap::new(),
+    })
+}
+
+fn array<'a>(value: &'a Value, path: &str) -> Result<&'a Vec<Value>, AssignmentError> {
+    value.as_array().ok_or_else(|| AssignmentError::InvalidField(path.to_string()))
+}
+
+fn string<'a>(value: &'a Value, path: &str) -> Result<&'a str, AssignmentError> {
+    value.as_str().ok_or_else(|| AssignmentError::InvalidField(path.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::data_catalogue::{ChunkStatus, DataCatalogue};
+    use crate::data_chunk::ChunkId;
+    use crate::data_manager::DataManager;
+    use crate::test_support;
+    use super::*;
+
+    const ASSIGNMENT: &str = r#"{
+        "datasets": [{
+            "id": "s3://ethereum-mainnet",
+            "base_url": "https://ethereum-mainnet.example.com/",
+            "chunks": [
+                {
+                    "id": "0000000000/0000000000-0000009999-1a2b3c4d",
+                    "files": { "blocks.parquet": "blocks.parquet", "logs.parquet": "https://cdn.example.com/logs.parquet" }
+                },
+                {
+                    "id": "0000000000/0000010000-0000019999-5e6f7a8b",
+                    "base_url": "https://mirror.example.com/10000",
+                    "files": { "blocks.parquet": "blocks.parquet" }
+                }
+            ]
+        }]
+    }"#;
+
+    #[test]
+    fn test_assignment_is_parsed_into_chunks() {
+        // Act
+        let assignment = parse_assignment(ASSIGNMENT).unwrap();
+
+        // Assert
+        let dataset = &assignment.datasets[0];
+        assert_eq!(dataset.name, "s3://ethereum-mainnet");
+        assert_eq!(dataset.dataset_id, dataset_id("s3://ethereum-mainnet"));
+        assert_eq!(dataset.chunks[0].block_range, 0..10_000);
+        assert_eq!(dataset.chunks[0].id, DataCatalogue::generate_chunk_id(&dataset.dataset_id, &(0..10_000)));
+        assert_eq!(
+            dataset.chunks[0].files["blocks.parquet"],
+            "https://ethereum-mainnet.example.com/0000000000/0000000000-0000009999-1a2b3c4d/blocks.parquet"
+        );
+        assert_eq!(dataset.chunks[0].files["logs.parquet"], "https://cdn.example.com/logs.parquet");
+        assert_eq!(dataset.chunks[1].block_range, 10_000..20_000);
+        assert_eq!(dataset.chunks[1].files["blocks.parquet"], "https://mirror.example.com/10000/blocks.parquet");
+    }
+
+    #[test]
+    fn test_invalid_assignments_are_rejected() {
+        assert!(matches!(parse_assignment("datasets"), Err(AssignmentError::InvalidJson(_))));
+        assert_eq!(
+            parse_assignment(r#"{"datasets": [{"id": "s3://base", "base_url": "https://base.example.com", "chunks": [{"files": {}}]}]}"#),
+            Err(AssignmentError::InvalidField("datasets[0].chunks[0].id".to_string()))
+        );
+        assert_eq!(
+            parse_assignment(r#"{"datasets": [{"id": "s3://base", "base_url": "https://base.example.com", "chunks": [{"id": "0000000000/latest", "files": {}}]}]}"#),
+            Err(AssignmentError::InvalidChunkId("0000000000/latest".to_string()))
+        );
+        assert_eq!(
+            parse_assignment(r#"{"datasets": [{"id": "s3://base", "base_url": "https://base.example.com", "chunks": [{"id": "0000000000/0000000000-0000000009", "files": {"../../x": "x"}}]}]}"#),
+            Err(AssignmentError::InvalidField("datasets[0].chunks[0].files.../../x".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_assignment_is_synced() {
+        // Arrange
+        let dir = test_support::temp_dir("subsquid");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let data_manager = test_support::manager_builder(&dir).build();
+        let assignment = parse_assignment(ASSIGNMENT).unwrap();
+        let mut reassignment = assignment.clone();
+        reassignment.datasets[0].chunks.remove(0);
+
+        // Act
+        let plans = apply_assignment(&data_manager, &assignment);
+        let chunk_ids: Vec<ChunkId> = assignment.datasets[0].chunks.iter().map(|chunk| chunk.id).collect();
+        data_manager.data_catalogue.wait_until_downloaded(&chunk_ids);
+        let replans = apply_assignment(&data_manager, &reassignment);
+        futures::executor::block_on(async {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+        });
+
+        // Assert
+        assert_eq!(plans[0].downloads.len(), 2);
+        assert_eq!(replans[0].deletions, vec![chunk_ids[0]]);
+        assert_eq!(data_manager.get_chunk_info(chunk_ids[0]).map(|info| info.status), Some(ChunkStatus::Deleted));
+        assert_eq!(data_manager.list_chunks(), vec![chunk_ids[1]]);
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,30 +1,29 @@
 use std::future::Future;
-use std::sync::{Arc, RwLock};
-use std::task::{Context, Poll, Waker};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use futures::channel::oneshot;
+use crate::operation::OperationResult;
 
-// Shared state between the IO operation and the event loop that will wake it
-pub struct TaskWaker {
-    pub waker: Option<Waker>,
-}
+/// Completes the `IOOperation` with the result of the operation, `None` when it didn't run, e.g. a queued download which couldn't start.
+/// A sender dropped without sending, e.g. by a worker which panicked, completes it with `None` as well.
+pub type OperationSender = oneshot::Sender<Option<OperationResult>>;
 
-// An asynchronous I/O operation that waits for some external event to complete
+// An asynchronous I/O operation that waits for the worker running it to send its result
 pub struct IOOperation {
-    pub task_waker: Arc<RwLock<TaskWaker>>,
+    receiver: oneshot::Receiver<Option<OperationResult>>,
 }
 
-impl Future for IOOperation {
-    type Output = String;
+impl IOOperation {
+    pub fn new() -> (OperationSender, Self) {
+        let (sender, receiver) = oneshot::channel();
+        (sender, IOOperation { receiver })
+    }
+}
 
-    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut task_waker = self.task_waker.write().unwrap();
+impl Future for IOOperation {
+    type Output = Option<OperationResult>;
 
-        if task_waker.waker.is_none() {
-            // Store the waker so the other thread can wake it later
-            task_waker.waker = Some(cx.waker().clone());
-            Poll::Pending
-        } else {
-            // Once woken by the separate thread, return ready
-            Poll::Ready("I/O Operation completed!".to_string())
-        }
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.receiver).poll(cx).map(|result| result.ok().flatten())
     }
-}
\ No newline at end of file
+}
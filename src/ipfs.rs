@@ -0,0 +1,248 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Arc;
+use crate::auth::FileRequest;
+use crate::compression::ContentEncoding;
+use crate::data_chunk::DataChunk;
+use crate::checksum::FileChecksums;
+use crate::planning::base32_decode_bytes;
+use crate::transfer::RangeSource;
+
+pub const IPFS_SCHEME: &str = "ipfs://";
+
+/// Multicodec of the raw binary content
+const RAW_CODEC: u64 = 0x55;
+/// Multihash of sha2-256
+const SHA2_256: u64 = 0x12;
+
+/// Why a CID can't be used to validate the content it addresses
+#[derive(Clone, Debug, PartialEq)]
+pub enum CidError {
+    /// Not a base32 CIDv1, e.g. a `Qm...` CIDv0
+    UnsupportedEncoding(String),
+    /// The content is a DAG, e.g. a chunked UnixFS file, rather than the raw bytes of the file
+    UnsupportedCodec(u64),
+    UnsupportedHash(u64),
+    Malformed(String),
+}
+
+impl fmt::Display for CidError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CidError::UnsupportedEncoding(cid) => write!(f, "{} is not a base32 CIDv1", cid),
+            CidError::UnsupportedCodec(codec) => write!(f, "CID codec 0x{:x} is not raw, the content can't be validated", codec),
+            CidError::UnsupportedHash(hash) => write!(f, "CID hash 0x{:x} is not sha2-256", hash),
+            CidError::Malformed(cid) => write!(f, "{} is not a valid CID", cid),
+        }
+    }
+}
+
+impl std::error::Error for CidError {}
+
+/// Sha256 of the content addressed by the CID as hex.
+/// Only the CIDv1 of raw content hashed with sha2-256 are supported, e.g. the files added with `--cid-version 1 --raw-leaves`
+/// small enough for a single block, since the CIDs of chunked files hash their DAG rather than their bytes.
+pub fn cid_sha256(cid: &str) -> Result<String, CidError> {
+    let encoded = cid.strip_prefix('b').ok_or_else(|| CidError::UnsupportedEncoding(cid.to_string()))?;
+    let bytes = base32_decode_bytes(encoded).ok_or_else(|| CidError::UnsupportedEncoding(cid.to_string()))?;
+    let malformed = || CidError::Malformed(cid.to_string());
+    let mut input = bytes.as_slice();
+    if read_varint(&mut input).ok_or_else(malformed)? != 1 {
+        return Err(CidError::UnsupportedEncoding(cid.to_string()));
+    }
+    let codec = read_varint(&mut input).ok_or_else(malformed)?;
+    if codec != RAW_CODEC {
+        return Err(CidError::UnsupportedCodec(codec));
+    }
+    let hash = read_varint(&mut input).ok_or_else(malformed)?;
+    if hash != SHA2_256 {
+        return Err(CidError::UnsupportedHash(hash));
+    }
+    match read_varint(&mut input) {
+        Some(32) if input.len() == 32 => Ok(hex::encode(input)),
+        _ => Err(malformed()),
+    }
+}
+
+/// Unsigned LEB128 varint, as used by the multiformats
+fn read_varint(input: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = input.split_first()?;
+        *input = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// CID of an `ipfs://<cid>` url
+fn url_cid(url: &str) -> Option<&str> {
+    let path = url.strip_prefix(IPFS_SCHEME)?;
+    Some(path.split('/').next().unwrap_or(path))
+}
+
+/// Expected sha256 of the chunk files with `ipfs://` urls, checked by the `ChecksumRegistry` after every download.
+/// Files whose CIDs can't be validated are left out, `IpfsGateway` refuses to fetch them.
+pub fn file_checksums(chunk: &DataChunk) -> FileChecksums {
+    chunk.files.iter()
+        .filter_map(|(file_name, url)| Some((file_name.clone(), cid_sha256(url_cid(url)?).ok()?)))
+        .collect()
+}
+
+/// Serves the files of `ipfs://<cid>` urls from an IPFS gateway, e.g. `https://ipfs.io`,
+/// through a `RangeSource` sending the HTTP requests, so a `ResumableTransfer` can fetch them.
+/// Other urls are passed to the source as they are.
+///
+/// The content is validated against the CID before the chunk is marked `Ready`, see `file_checksums`,
+/// so an untrusted gateway can't serve other bytes. CIDs which can't be validated are refused.
+pub struct IpfsGateway {
+    /// Base url of the gateway, the files are requested from `<gateway_url>/ipfs/<cid>`
+    pub gateway_url: String,
+    source: Arc<dyn RangeSource>,
+}
+
+impl IpfsGateway {
+    pub fn new(gateway_url: impl Into<String>, source: Arc<dyn RangeSource>) -> Self {
+        IpfsGateway { gateway_url: gateway_url.into(), source }
+    }
+
+    /// Url of the file on the gateway, other urls are returned as they are
+    pub fn gateway_url_of(&self, url: &str) -> io::Result<String> {
+        let Some(cid) = url_cid(url) else { return Ok(url.to_string()) };
+        cid_sha256(cid).map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        Ok(format!("{}/ipfs/{}", self.gateway_url.trim_end_matches('/'), &url[IPFS_SCHEME.len()..]))
+    }
+}
+
+impl RangeSource for IpfsGateway {
+    fn content_length(&self, url: &str) -> io::Result<u64> {
+        self.source.content_length(&self.gateway_url_of(url)?)
+    }
+
+    fn read_range(&self, url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+        self.source.read_range(&self.gateway_url_of(url)?, offset, writer)
+    }
+
+    fn request_content_length(&self, request: &FileRequest) -> io::Result<u64> {
+        self.source.request_content_length(&FileRequest { url: self.gateway_url_of(&request.url)?, ..request.clone() })
+    }
+
+    fn request_content_encoding(&self, request: &FileRequest) -> io::Result<ContentEncoding> {
+        self.source.request_content_encoding(&FileRequest { url: self.gateway_url_of(&request.url)?, ..request.clone() })
+    }
+
+    fn request_range(&self, request: &FileRequest, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+        self.source.request_range(&FileRequest { url: self.gateway_url_of(&request.url)?, ..request.clone() }, offset, writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+    use crate::config::DataManagerConfig;
+    use crate::data_catalogue::ChunkStatus;
+    use crate::data_manager::DataManager;
+    use crate::planning::base32_encode;
+    use crate::transfer::ResumableTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// CIDv1 of `hello world` stored as raw content
+    const HELLO_WORLD_CID: &str = "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e";
+
+    /// Gateway serving the given content for every url, recording the urls
+    struct FixedGateway {
+        content: Vec<u8>,
+        urls: Mutex<Vec<String>>,
+    }
+
+    impl RangeSource for FixedGateway {
+        fn content_length(&self, url: &str) -> io::Result<u64> {
+            self.urls.lock().unwrap().push(url.to_string());
+            Ok(self.content.len() as u64)
+        }
+
+        fn read_range(&self, _url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            writer.write_all(&self.content[offset as usize..])?;
+            Ok(self.content.len() as u64 - offset)
+        }
+    }
+
+    fn ipfs_chunk(block: u64) -> DataChunk {
+        DataChunk {
+            id: [block as u8; 32],
+            dataset_id: [9u8; 32],
+            block_range: block..block + 1,
+            files: HashMap::from([("blocks.parquet".to_string(), format!("{}{}", IPFS_SCHEME, HELLO_WORLD_CID))]),
+            mirrors: HashMap::new(),
+            size: None,
+            file_sizes: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_cid_sha256() {
+        assert_eq!(cid_sha256(HELLO_WORLD_CID).unwrap(), sha256::digest("hello world"));
+        assert_eq!(
+            cid_sha256("QmWATWQ7fVPP2EFGu71UkfnqhYXDYH566qy47CnJDgvs8u"),
+            Err(CidError::UnsupportedEncoding("QmWATWQ7fVPP2EFGu71UkfnqhYXDYH566qy47CnJDgvs8u".to_string()))
+        );
+        // the same digest with the dag-pb codec of chunked UnixFS files
+        let dag_pb = format!("b{}", base32_encode(&[&[0x01, 0x70, 0x12, 0x20][..], &[0u8; 32][..]].concat()));
+        assert_eq!(cid_sha256(&dag_pb), Err(CidError::UnsupportedCodec(0x70)));
+        assert_eq!(cid_sha256(&HELLO_WORLD_CID[..33]), Err(CidError::Malformed(HELLO_WORLD_CID[..33].to_string())));
+    }
+
+    #[test]
+    fn test_ipfs_urls_are_fetched_from_gateway() {
+        let gateway = IpfsGateway::new("https://ipfs.io/", Arc::new(FixedGateway { content: Vec::new(), urls: Mutex::new(Vec::new()) }));
+        assert_eq!(
+            gateway.gateway_url_of(&format!("ipfs://{}", HELLO_WORLD_CID)).unwrap(),
+            format!("https://ipfs.io/ipfs/{}", HELLO_WORLD_CID)
+        );
+        assert_eq!(gateway.gateway_url_of("https://example.com/blocks.parquet").unwrap(), "https://example.com/blocks.parquet");
+        assert_eq!(gateway.gateway_url_of("ipfs://QmWATWQ7fVPP2EFGu71UkfnqhYXDYH566qy47CnJDgvs8u").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_content_is_validated_against_cid() {
+        // Arrange
+        let dir = test_support::temp_dir("ipfs");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let build = |content: &[u8], data_dir: &str| {
+            let gateway = Arc::new(FixedGateway { content: content.to_vec(), urls: Mutex::new(Vec::new()) });
+            let data_manager = DataManagerImpl::builder()
+                .config(DataManagerConfig::new(dir.join(data_dir)).with_catalogue_file(dir.join(format!("{}.parquet", data_dir))))
+                .chunk_transfer(Arc::new(ResumableTransfer::new(Arc::new(IpfsGateway::new("https://ipfs.io", gateway.clone())))))
+                .build();
+            (data_manager, gateway)
+        };
+        let (honest, honest_gateway) = build(b"hello world", "honest");
+        let (forged, _) = build(b"hello forged", "forged");
+
+        // Act
+        honest.download_chunk(ipfs_chunk(1));
+        forged.download_chunk(ipfs_chunk(2));
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(200));
+        });
+
+        // Assert
+        assert_eq!(honest.get_chunk_info(ipfs_chunk(1).id).unwrap().status, ChunkStatus::Ready);
+        assert_eq!(*honest_gateway.urls.lock().unwrap(), vec![format!("https://ipfs.io/ipfs/{}", HELLO_WORLD_CID)]);
+        assert_eq!(forged.get_chunk_info(ipfs_chunk(2).id).unwrap().status, ChunkStatus::Failed);
+
+        // cleanup
+        drop(honest);
+        drop(forged);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use serde_json::{Map, Value};
+
+/// Row of a JSONL file, a JSON object by its column names
+pub(crate) type Row = Map<String, Value>;
+
+/// Write the rows to the file, a JSON object per line
+pub(crate) fn write_rows(file_path: &Path, rows: impl IntoIterator<Item = Row>) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    for row in rows {
+        serde_json::to_writer(&mut writer, &row)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+/// Rows of the file, the lines which aren't JSON objects are skipped, e.g. a line cut short by a crash
+pub(crate) fn read_rows(file_path: &Path) -> io::Result<Vec<Row>> {
+    Ok(fs::read_to_string(file_path)?
+        .lines()
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(Value::Object(row)) => Some(row),
+            _ => None,
+        })
+        .collect())
+}
+
+pub(crate) fn str_column<'a>(row: &'a Row, column: &str) -> Option<&'a str> {
+    row.get(column)?.as_str()
+}
+
+pub(crate) fn u64_column(row: &Row, column: &str) -> Option<u64> {
+    row.get(column)?.as_u64()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::test_support;
+    use super::*;
+
+    #[test]
+    fn test_rows_round_trip() {
+        // Arrange
+        let file_path = test_support::temp_dir("jsonl").with_extension("jsonl");
+        let rows = vec![
+            json!({"id": "01", "block": 7}).as_object().unwrap().clone(),
+            json!({"id": "02", "block": 8}).as_object().unwrap().clone(),
+        ];
+
+        // Act
+        write_rows(&file_path, rows.clone()).unwrap();
+        fs::write(&file_path, fs::read_to_string(&file_path).unwrap() + "{\"id\": \"03\", \"blo").unwrap();
+        let read = read_rows(&file_path).unwrap();
+
+        // Assert
+        assert_eq!(read, rows);
+        assert_eq!(str_column(&read[1], "id"), Some("02"));
+        assert_eq!(u64_column(&read[1], "block"), Some(8));
+        assert_eq!(u64_column(&read[1], "id"), None);
+
+        // cleanup
+        fs::remove_file(file_path).unwrap();
+    }
+}
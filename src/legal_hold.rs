@@ -0,0 +1,199 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
+#[cfg(feature = "dataframes")]
+use {std::fs::File, polars::prelude::*};
+#[cfg(not(feature = "dataframes"))]
+use crate::jsonl::{self, Row};
+
+/// Chunks or a whole dataset which must be kept for compliance, e.g. during litigation or an audit.
+/// Chunks under a hold can't start deleting, being evicted or replaced, nor be forgotten, until the hold is lifted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LegalHold {
+    /// All the chunks of the dataset, including the ones downloaded after the hold was placed
+    Dataset(DatasetId),
+    Chunk(ChunkId),
+}
+
+impl LegalHold {
+    /// Whether the hold covers the chunk
+    pub fn covers(&self, chunk: &DataChunk) -> bool {
+        match self {
+            LegalHold::Dataset(dataset_id) => chunk.dataset_id == *dataset_id,
+            LegalHold::Chunk(chunk_id) => chunk.id == *chunk_id,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            LegalHold::Dataset(_) => "dataset",
+            LegalHold::Chunk(_) => "chunk",
+        }
+    }
+
+    fn id(&self) -> &[u8; 32] {
+        match self {
+            LegalHold::Dataset(id) | LegalHold::Chunk(id) => id,
+        }
+    }
+
+    fn parse(kind: &str, id: &str) -> Option<Self> {
+        let id: [u8; 32] = hex::decode(id).ok()?.try_into().ok()?;
+        match kind {
+            "dataset" => Some(LegalHold::Dataset(id)),
+            "chunk" => Some(LegalHold::Chunk(id)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LegalHold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "legal hold of {} {}", self.kind(), hex::encode(self.id()))
+    }
+}
+
+/// Legal holds of the catalogue, saved on every change, so they outlive restarts
+#[derive(Clone)]
+pub struct LegalHolds {
+    holds: Arc<RwLock<BTreeSet<LegalHold>>>,
+    file_path: PathBuf,
+}
+
+impl LegalHolds {
+    /// Load the holds saved in the file, a missing file gives no holds
+    pub fn open(file_path: &Path) -> Self {
+        LegalHolds { holds: Arc::new(RwLock::new(read_holds(file_path).into_iter().collect())), file_path: file_path.to_path_buf() }
+    }
+
+    /// Place the hold and save it, returns `false` when it was already placed
+    pub fn place(&self, hold: LegalHold) -> io::Result<bool> {
+        let mut holds = self.holds.write().unwrap();
+        if !holds.insert(hold) {
+            return Ok(false);
+        }
+        // the hold is kept only when it's saved, so it's never lost silently on a restart
+        write_holds(&self.file_path, holds.iter()).inspect_err(|_| {
+            holds.remove(&hold);
+        })?;
+        Ok(true)
+    }
+
+    /// Lift the hold and save the rest, returns `false` when it wasn't placed
+    pub fn lift(&self, hold: &LegalHold) -> io::Result<bool> {
+        let mut holds = self.holds.write().unwrap();
+        if !holds.remove(hold) {
+            return Ok(false);
+        }
+        write_holds(&self.file_path, holds.iter()).inspect_err(|_| {
+            holds.insert(*hold);
+        })?;
+        Ok(true)
+    }
+
+    /// Whether any hold covers the chunk
+    pub fn is_held(&self, chunk: &DataChunk) -> bool {
+        self.holds.read().unwrap().iter().any(|hold| hold.covers(chunk))
+    }
+
+    /// All the holds, dataset holds first
+    pub fn holds(&self) -> Vec<LegalHold> {
+        self.holds.read().unwrap().iter().copied().collect()
+    }
+}
+
+#[cfg(feature = "dataframes")]
+fn write_holds<'a>(file_path: &Path, holds: impl Iterator<Item = &'a LegalHold>) -> io::Result<()> {
+    let (mut kinds, mut ids) = (Vec::new(), Vec::new());
+    for hold in holds {
+        kinds.push(hold.kind());
+        ids.push(hex::encode(hold.id()));
+    }
+    let mut df = df!(
+        "kind" => kinds,
+        "id" => ids
+    ).map_err(polars_error)?;
+    ParquetWriter::new(File::create(file_path)?).finish(&mut df).map_err(polars_error)?;
+    Ok(())
+}
+
+#[cfg(feature = "dataframes")]
+fn read_holds(file_path: &Path) -> Vec<LegalHold> {
+    File::open(file_path).map_err(PolarsError::from)
+        .and_then(|file| ParquetReader::new(file).finish())
+        .and_then(|df| holds_from_dataframe(&df))
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "dataframes")]
+fn holds_from_dataframe(df: &DataFrame) -> PolarsResult<Vec<LegalHold>> {
+    let kinds = df.column("kind")?.str()?;
+    let ids = df.column("id")?.str()?;
+    Ok((0..df.height())
+        .filter_map(|i| LegalHold::parse(kinds.get(i)?, ids.get(i)?))
+        .collect())
+}
+
+#[cfg(feature = "dataframes")]
+fn polars_error(error: PolarsError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(not(feature = "dataframes"))]
+fn write_holds<'a>(file_path: &Path, holds: impl Iterator<Item = &'a LegalHold>) -> io::Result<()> {
+    jsonl::write_rows(file_path, holds.map(|hold| {
+        let mut row = Row::new();
+        row.insert("kind".to_string(), hold.kind().into());
+        row.insert("id".to_string(), hex::encode(hold.id()).into());
+        row
+    }))
+}
+
+#[cfg(not(feature = "dataframes"))]
+fn read_holds(file_path: &Path) -> Vec<LegalHold> {
+    jsonl::read_rows(file_path).unwrap_or_default()
+        .iter()
+        .filter_map(|row| LegalHold::parse(jsonl::str_column(row, "kind")?, jsonl::str_column(row, "id")?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::test_support;
+    use super::*;
+
+    fn chunk(dataset_id: DatasetId, block: u64) -> DataChunk {
+        DataChunk::new(dataset_id, block..block + 10)
+    }
+
+    #[test]
+    fn test_holds_are_saved() {
+        // Arrange
+        let file_path = test_support::temp_dir("legal_holds");
+        let holds = LegalHolds::open(&file_path);
+        let held_chunk = chunk([1u8; 32], 0);
+
+        // Act
+        holds.place(LegalHold::Dataset([2u8; 32])).unwrap();
+        holds.place(LegalHold::Chunk(held_chunk.id)).unwrap();
+        let placed_again = holds.place(LegalHold::Chunk(held_chunk.id)).unwrap();
+        let reopened = LegalHolds::open(&file_path);
+
+        // Assert
+        assert!(!placed_again);
+        assert_eq!(reopened.holds(), vec![LegalHold::Dataset([2u8; 32]), LegalHold::Chunk(held_chunk.id)]);
+        assert!(reopened.is_held(&held_chunk));
+        assert!(reopened.is_held(&chunk([2u8; 32], 100)));
+        assert!(!reopened.is_held(&chunk([1u8; 32], 10)));
+        assert!(reopened.lift(&LegalHold::Dataset([2u8; 32])).unwrap());
+        assert!(!reopened.lift(&LegalHold::Dataset([2u8; 32])).unwrap());
+        assert_eq!(LegalHolds::open(&file_path).holds(), vec![LegalHold::Chunk(held_chunk.id)]);
+
+        // cleanup
+        fs::remove_file(file_path).unwrap();
+    }
+}
@@ -1,61 +1,1269 @@
-use crate::data_chunk::{DataChunkPath, DataChunkRef};
-use std::path::PathBuf;
-use std::thread;
-use crate::data_catalogue::{ChunkStatus, DataCatalogue};
-use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
-use crate::data_manager::DataManager;
-use crate::event_loop::TasksManager;
-use crate::local_data_source::{LocalDataSource, LOCAL_DATA_DIR};
+// everything but the planning core needs the filesystem and threads
+#[cfg(feature = "runtime")]
+use {
+    crate::acquire::AcquiredChunk,
+    crate::data_chunk::{DataChunkPath, DataChunkRef, MappedChunkFile},
+    crate::chunk_errors::ChunkErrorKind,
+    crate::data_catalogue::{ChunkInfo, DataCatalogue},
+    crate::data_chunk::{ChunkId, DataChunk, DatasetId},
+    crate::data_manager::DataManager,
+    crate::event_loop::TasksManager,
+    crate::builder::DataManagerBuilder,
+    crate::config::DataManagerConfig,
+    crate::hooks::{HookDispatcher, LifecycleEvent},
+    crate::local_data_source::LocalDataSource,
+    crate::data_source::DataSource,
+    crate::workers::Workers,
+    crate::watchdog::Watchdog,
+    crate::storage::{StorageSampler, StorageStats, StorageUsage},
+    crate::origin::OriginFetcher,
+    crate::download_pool::DownloadPool,
+    crate::fair_queue::{DownloadPriority, FairQueue},
+    crate::tip::{ManifestSource, TipFollower, TipFollowingConfig},
+    crate::deadline::DeadlineMonitor,
+    crate::block_time::{BlockTime, BlockTimeIndex},
+    crate::replication::Replica,
+    crate::maintenance::{VerificationLog, VerificationRun},
+    crate::notifications::CoalescedNotifier,
+    crate::alerts::AlertMonitor,
+    crate::adaptive_concurrency::{ConcurrencyAdjuster, ConcurrencyController},
+    crate::checksum::{ChecksumRegistry, FileChecksums},
+    crate::cancellation::Cancellations,
+    std::collections::{BTreeSet, HashSet},
+    std::time::Duration,
+    crate::epoch::{EpochError, EpochLayout, EpochStatus},
+    crate::eviction::EvictionOrder,
+    crate::legal_hold::LegalHold,
+    crate::lineage::LineageEvent,
+    crate::holdings::Holdings,
+    crate::slo::{Slo, SloReport, SloTracker},
+    crate::consumers::{ConsumerStats, ConsumerUsage},
+    crate::snapshot::{CatalogueSnapshot, SnapshotStats},
+    std::collections::BTreeMap,
+    crate::transform::ChunkTransformer,
+    crate::coverage::Coverage,
+    crate::compaction::{CatalogueStats, CompactionRun, CompactionStats},
+    crate::federation::SecondaryCatalogue,
+    crate::registration::{ForgetError, RegisterError},
+    crate::batch::{BatchSubmission, SkipReason},
+    crate::reassignment::ReassignError,
+    crate::self_test::{SelfTestReport, SelfTestSource},
+    crate::relocation::RelocateError,
+    crate::repair::RepairError,
+    crate::placement::PlacementViolation,
+    crate::published_checksums::ChecksumImportError,
+    crate::chunk_lookup::{ChunkDescription, ChunkOrigin},
+    crate::sync_plan::{Replacement, SyncPlan},
+    crate::overlap::OverlapDecision,
+    crate::pending_downloads::PendingDownloads,
+    crate::download_handle::{DownloadError, DownloadHandle, DownloadWaiters},
+    crate::free_space::{InsufficientSpaceAction, SpacePreflight},
+    crate::onboarding::{OnboardingError, OnboardingOptions, OnboardingReport},
+    crate::progress::ChunkProgress,
+    crate::operation::OperationResult,
+    std::io,
+    std::path::{Path, PathBuf},
+    std::sync::Arc,
+    std::time::Instant,
+};
 
+// the DataFrame APIs need polars
+#[cfg(feature = "dataframes")]
+use {
+    crate::verification::BlockHashVerifier,
+    crate::row_stream::{ChunkRowStream, DEFAULT_BATCH_ROWS},
+    crate::scan::ParquetSource,
+    crate::query_cache::{QueryCache, QueryFingerprint},
+    polars::prelude::{DataFrame, Expr},
+    std::collections::HashMap,
+    std::ops::Range,
+};
+
+#[cfg(feature = "runtime")]
+pub mod acquire;
+#[cfg(feature = "runtime")]
+pub mod adaptive_concurrency;
+#[cfg(feature = "runtime")]
+pub mod alerts;
+#[cfg(feature = "runtime")]
+pub mod auth;
+#[cfg(feature = "runtime")]
+pub mod azure;
+#[cfg(feature = "runtime")]
+pub mod batch;
+#[cfg(all(feature = "dataframes", any(test, feature = "devtools")))]
+pub mod bench;
+#[cfg(feature = "runtime")]
+pub mod block_time;
+#[cfg(feature = "runtime")]
+pub mod builder;
+#[cfg(feature = "runtime")]
+pub mod cancellation;
+#[cfg(feature = "runtime")]
+pub mod checksum;
+#[cfg(feature = "runtime")]
+pub mod catalogue_compat;
+#[cfg(feature = "runtime")]
+pub mod chunk_errors;
+#[cfg(feature = "runtime")]
+pub mod chunk_lookup;
+#[cfg(feature = "runtime")]
+pub mod compaction;
+#[cfg(feature = "runtime")]
+pub mod compression;
+mod chunk_filter;
+#[cfg(feature = "runtime")]
+mod chunk_pins;
+#[cfg(feature = "runtime")]
+pub mod config;
+#[cfg(feature = "runtime")]
+pub mod consumers;
+#[cfg(feature = "runtime")]
+pub mod coverage;
+#[cfg(feature = "runtime")]
+pub mod correlation;
 pub mod data_chunk;
-mod data_manager;
+#[cfg(feature = "runtime")]
+pub mod data_manager;
+#[cfg(feature = "runtime")]
+pub mod data_source;
+#[cfg(feature = "runtime")]
 mod local_data_source;
+#[cfg(feature = "runtime")]
 mod io_operation;
+#[cfg(feature = "runtime")]
 mod event_loop;
+#[cfg(feature = "runtime")]
 mod data_catalogue;
+#[cfg(feature = "runtime")]
+pub mod hooks;
+pub mod sync_plan;
+#[cfg(feature = "runtime")]
+mod workers;
+#[cfg(feature = "runtime")]
+pub mod watchdog;
+#[cfg(feature = "runtime")]
+pub mod deadline;
+#[cfg(all(feature = "dataframes", any(test, feature = "devtools")))]
+pub mod devtools;
+#[cfg(feature = "runtime")]
+pub mod download_handle;
+#[cfg(feature = "runtime")]
+pub mod download_pool;
+#[cfg(feature = "runtime")]
+pub mod epoch;
+#[cfg(feature = "runtime")]
+pub mod content_store;
+#[cfg(feature = "runtime")]
+pub mod fair_queue;
+#[cfg(feature = "runtime")]
+pub mod permissions;
+#[cfg(feature = "runtime")]
+pub mod free_space;
+#[cfg(feature = "runtime")]
+pub mod federation;
+#[cfg(feature = "runtime")]
+pub mod gcs;
+#[cfg(feature = "runtime")]
+pub mod eviction;
+pub mod holdings;
+pub mod manifest_entry;
+pub mod planning;
+pub mod simulation;
+#[cfg(feature = "runtime")]
+pub mod self_test;
+#[cfg(feature = "runtime")]
+pub mod size_check;
+#[cfg(feature = "runtime")]
+pub mod slo;
+#[cfg(feature = "runtime")]
+pub mod snapshot;
+#[cfg(feature = "runtime")]
+pub mod integrations;
+#[cfg(feature = "runtime")]
+pub mod ipfs;
+#[cfg(all(feature = "runtime", not(feature = "dataframes")))]
+mod jsonl;
+#[cfg(feature = "runtime")]
+pub mod legal_hold;
+#[cfg(feature = "runtime")]
+pub mod lineage;
+#[cfg(feature = "runtime")]
+pub mod maintenance;
+#[cfg(feature = "runtime")]
+pub mod notifications;
+#[cfg(feature = "runtime")]
+pub mod onboarding;
+#[cfg(feature = "runtime")]
+pub mod operation;
+#[cfg(feature = "runtime")]
+pub mod origin;
+#[cfg(feature = "runtime")]
+pub mod overlap;
+#[cfg(feature = "runtime")]
+pub mod pending_downloads;
+#[cfg(feature = "runtime")]
+pub mod placement;
+#[cfg(feature = "runtime")]
+pub mod storage;
+#[cfg(feature = "runtime")]
+pub mod progress;
+#[cfg(feature = "runtime")]
+pub mod proxy;
+#[cfg(feature = "runtime")]
+pub mod published_checksums;
+#[cfg(feature = "dataframes")]
+pub mod query_cache;
+#[cfg(feature = "runtime")]
+pub mod rate_limit;
+#[cfg(feature = "runtime")]
+pub mod reassignment;
+#[cfg(feature = "runtime")]
+pub mod registration;
+#[cfg(feature = "runtime")]
+pub mod relocation;
+#[cfg(feature = "runtime")]
+pub mod repair;
+#[cfg(feature = "runtime")]
+pub mod replication;
+#[cfg(feature = "runtime")]
+pub mod retry;
+#[cfg(feature = "dataframes")]
+pub mod row_stream;
+#[cfg(feature = "dataframes")]
+mod scan;
+#[cfg(feature = "runtime")]
+pub mod throttle;
+#[cfg(feature = "runtime")]
+pub mod timeout;
+#[cfg(feature = "runtime")]
+pub mod tip;
+#[cfg(feature = "runtime")]
+pub mod tls;
+#[cfg(feature = "runtime")]
+pub mod transfer;
+#[cfg(feature = "runtime")]
+pub mod transform;
+#[cfg(feature = "runtime")]
+pub mod verification;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(all(feature = "runtime", test))]
+mod state_machine_tests;
+#[cfg(all(feature = "runtime", test))]
+mod test_support;
+
 
+/// How a download accepted by `request_download` starts
+#[cfg(feature = "runtime")]
+enum Admission {
+    /// Once the in-flight chunks overlapping it are done
+    QueueBehind(Vec<ChunkId>),
+    /// Once the data directory has space for it
+    Deferred,
+    /// Right away, replacing the given chunks when there are any
+    Start(Vec<DataChunk>),
+    /// Right away into the place of the `Ready` chunk once its readers are done, replacing the given chunks too
+    Redownload(Vec<DataChunk>),
+}
 
+#[cfg(feature = "runtime")]
 pub struct DataManagerImpl {
+    pub config: DataManagerConfig,
+    /// Data directory the chunks are downloaded and verified in, and read from
     pub data_source: LocalDataSource,
+    /// Storage holding the chunks, the `data_source` unless another one is given to `DataManagerBuilder::data_source`
+    pub backend: Arc<dyn DataSource>,
     pub tasks_manager: TasksManager,
     pub data_catalogue: DataCatalogue,
+    pub hooks: HookDispatcher,
+    pub slo: SloTracker,
+    /// Queries and bytes served per consumer, see `consumers::scope`
+    pub consumer_stats: ConsumerStats,
+    /// Run over the files of every downloaded chunk before it's marked `Ready`
+    pub transformers: Arc<Vec<Arc<dyn ChunkTransformer>>>,
+    #[cfg(feature = "dataframes")]
+    pub query_cache: Option<QueryCache>,
+    /// Consulted by `find_chunk` after the local catalogue
+    pub secondary_catalogues: Vec<Arc<dyn SecondaryCatalogue>>,
+    /// Stops repairing stuck chunks once the data manager is dropped
+    pub watchdog: Option<Watchdog>,
+    /// Serves reads of missing or corrupt files, disabled when `None`
+    pub origin_fetcher: Option<Arc<dyn OriginFetcher>>,
+    /// Shares the download slots between the datasets, unlimited when `None`
+    pub download_queue: Option<FairQueue>,
+    /// Sizes of the local chunks
+    pub storage: StorageUsage,
+    /// Stops sampling the chunk sizes once the data manager is dropped
+    pub storage_sampler: Option<StorageSampler>,
+    /// Follows the tips of datasets, when there is a manifest source
+    pub tip_follower: Option<TipFollower>,
+    /// Chunks published for the datasets, consulted by the tip follower and `describe_chunk_id`
+    pub manifest_source: Option<Arc<dyn ManifestSource>>,
+    /// Checks the downloads requested with a deadline, started once the workers are available
+    pub deadlines: Option<DeadlineMonitor>,
+    /// Blocks by their timestamps, for `find_chunk_by_time`
+    pub block_times: BlockTimeIndex,
+    /// Downloads requested and not finished yet, requested again on the next start
+    pub pending_downloads: PendingDownloads,
+    /// Handles of `download_chunk_with_handle` waiting for their chunks
+    pub download_waiters: DownloadWaiters,
+    /// Check the block hashes of the downloaded chunks per dataset before they get ready
+    #[cfg(feature = "dataframes")]
+    pub hash_verifiers: Arc<HashMap<DatasetId, BlockHashVerifier>>,
+    /// Catalogue of the leader, when the data manager follows one
+    pub replica: Option<Replica>,
+    /// When the ready chunks were last verified by `verify_chunks`
+    pub verification_log: VerificationLog,
+    /// Deliver the summarized deltas of the catalogue, until the data manager is dropped
+    pub notifiers: Vec<CoalescedNotifier>,
+    /// Reports the crossed watermarks, until the data manager is dropped
+    pub alert_monitor: Option<AlertMonitor>,
+    /// Checksums of the files of the chunks downloaded with `download_chunk_with_checksums`
+    pub checksums: ChecksumRegistry,
+    /// Downloads cancelled by `cancel_download`, until they stop
+    pub cancellations: Cancellations,
+    /// Threads running the downloads, at most `max_concurrent_downloads` of them
+    pub download_pool: DownloadPool,
+    /// Adjusts the concurrency of the downloads and the deletions, when `adaptive_concurrency` is configured
+    pub concurrency_controller: Option<ConcurrencyController>,
+    /// Stops adjusting the concurrency once the data manager is dropped
+    pub concurrency_adjuster: Option<ConcurrencyAdjuster>,
+    /// Checks the free space of the data directory before the downloads, when `free_space` is configured
+    pub space_preflight: Option<SpacePreflight>,
 }
 
-impl DataManagerImpl {
+#[cfg(feature = "runtime")]
+impl Default for DataManagerImpl {
     fn default() -> Self {
-        Self::new(PathBuf::from(LOCAL_DATA_DIR))
+        Self::with_config(DataManagerConfig::default())
     }
 }
 
-impl DataManager for DataManagerImpl {
-    fn new(data_dir: PathBuf) -> Self {
-        let data_source = LocalDataSource::new(data_dir);
-        let local_chunks = data_source.get_local_chunks();
+#[cfg(feature = "runtime")]
+impl DataManagerImpl {
+    pub fn builder() -> DataManagerBuilder {
+        DataManagerBuilder::new()
+    }
 
-        DataManagerImpl {
-            data_source,
-            tasks_manager: TasksManager::default(),
-            data_catalogue: DataCatalogue::new(local_chunks),
+    pub fn with_config(config: DataManagerConfig) -> Self {
+        Self::builder().config(config).build()
+    }
+
+    /// Plan the downloads, deletions and replacements needed to match the `manifest` of a dataset,
+    /// without executing them.
+    pub fn plan_sync(&self, dataset_id: DatasetId, manifest: &[DataChunk]) -> SyncPlan {
+        self.data_catalogue.plan_sync(dataset_id, manifest)
+    }
+
+    /// Schedule the operations needed to match the `manifest` of a dataset in background.
+    /// Returns the plan which is being executed.
+    pub fn ensure_chunks(&self, dataset_id: DatasetId, manifest: &[DataChunk]) -> SyncPlan {
+        let plan = self.plan_sync(dataset_id, manifest);
+        self.request_downloads(plan.downloads.clone(), DownloadPriority::Normal);
+        for chunk_id in plan.deletions.iter() {
+            // downloads which are no longer needed are stopped, rather than deleted once they complete
+            if !self.cancel_download(*chunk_id) {
+                self.delete_chunk(*chunk_id);
+            }
+        }
+        for replacement in plan.replacements.iter() {
+            self.replace_chunk(replacement.clone());
         }
+        plan
     }
 
-    /// Schedule `chunk` download in background
-    fn download_chunk(&self, chunk: DataChunk) {
-        let task_waker = self.tasks_manager.add_future_to_manager_pool();
-        if !self.data_catalogue.start_download(&chunk) {
+    /// Dry run of the sync of the dataset of the manifest at the url, see `onboarding` for its format.
+    /// The manifest is validated, the bytes to download are estimated from the sizes it gives and the files measured
+    /// at the source, and checked against the quota and the download limit of the options.
+    /// Nothing is downloaded, the chunks of the report's manifest are synced with `ensure_chunks` once the report is accepted.
+    pub fn onboard_dataset(&self, manifest_url: &str, options: &OnboardingOptions) -> Result<OnboardingReport, OnboardingError> {
+        let manifest = onboarding::fetch_manifest(options.source.as_ref(), manifest_url)?;
+        let plan = self.plan_sync(manifest.dataset_id, &manifest.chunks);
+        onboarding::report(manifest, plan, self.storage_stats().total_bytes, self.config.download_bandwidth.as_ref(), options)
+    }
+
+    /// Register a chunk some other process, e.g. a sidecar downloader or a backfill script, has already placed
+    /// in the data directory. The chunk becomes `Ready` without being downloaded or transformed.
+    /// With `verify`, all the files of the chunk must be in its directory, of their declared sizes and with their known checksums.
+    pub fn register_chunk(&self, chunk: DataChunk, verify: bool) -> Result<(), RegisterError> {
+        let chunk_path = self.data_source.chunk_path(chunk.clone());
+        registration::check_chunk(&chunk, &chunk_path.path, verify)
+            .and_then(|_| match verify {
+                true => registration::verify_files(&chunk, &chunk_path.path, &self.checksums),
+                false => Ok(()),
+            })
+            // checked up front, rather than failing the deletion of the chunk much later
+            .and_then(|_| permissions::prepare_chunk_dir(&chunk_path.path, self.config.permissions.as_ref())
+                .map_err(|error| RegisterError::Permissions(error.to_string())))
+            .inspect_err(|error| {
+                // only chunks already in the catalogue keep a history
+                self.data_catalogue.record_error(&chunk.id, ChunkErrorKind::Verification, error.to_string());
+            })?;
+        self.data_catalogue.register_chunk(&chunk).map_err(RegisterError::AlreadyRegistered)?;
+        self.hooks.emit(LifecycleEvent::Register(chunk));
+        Ok(())
+    }
+
+    /// Remove the chunk from the catalogue, leaving its files in place, e.g. to hand the data over to another process
+    /// or to exclude a directory from management while debugging.
+    /// The chunk directory is picked up again by the scan of the data directory on the next start, unless it's moved away.
+    pub fn forget_chunk(&self, chunk_id: ChunkId) -> Result<DataChunk, ForgetError> {
+        self.data_catalogue.forget_chunk(&chunk_id)?
+            .ok_or(ForgetError::UnknownChunk(chunk_id))
+    }
+
+    /// Replicas of the datasets separated by `DataManagerConfig::with_domain_separation` sharing a failure domain,
+    /// e.g. chunks held in the data directory since before the separation, to be relocated with `relocate_chunk`
+    pub fn placement_violations(&self) -> Vec<PlacementViolation> {
+        let registry = self.data_catalogue.registry.read().unwrap();
+        placement::violations(&self.config.placement, &self.data_source.data_dir, registry.values())
+    }
+
+    /// Move the files of a `Ready` chunk to another volume, e.g. to free up the disk of the data directory.
+    /// The chunk is copied while it stays readable, and switched over only when no reader holds a reference to it,
+    /// so the path of every `DataChunkRef` stays valid until the reference is dropped.
+    /// The move is recorded in the catalogue, subsequent `find_chunk` calls return the new path.
+    /// Passing the data directory moves the chunk back. Returns the new path of the chunk.
+    pub fn relocate_chunk(&self, chunk_id: ChunkId, new_volume: PathBuf) -> Result<PathBuf, RelocateError> {
+        let info = self.data_catalogue.get_chunk_info(&chunk_id).ok_or(RelocateError::UnknownChunk(chunk_id))?;
+        if info.status != data_catalogue::ChunkStatus::Ready {
+            return Err(RelocateError::NotReady(info.status));
+        }
+        // the copy is skipped, when the chunk can't be switched over anyway
+        let pins = self.data_catalogue.pins.pin_count(&chunk_id);
+        if pins > 0 {
+            return Err(RelocateError::InUse(pins));
+        }
+        {
+            let registry = self.data_catalogue.registry.read().unwrap();
+            if let Some((domain, replica_id)) = placement::conflicting_replica(&self.config.placement, &self.data_source.data_dir, &info.chunk, &new_volume, registry.values()) {
+                return Err(RelocateError::SharedFailureDomain(domain, replica_id));
+            }
+        }
+        let from = self.data_source.chunk_path(info.chunk.clone()).path;
+        let to = DataChunkPath::new(&new_volume, self.data_source.layout, info.chunk).path;
+        if from == to {
+            return Ok(to);
+        }
+
+        {
+            // the chunk can't be deleted while it's copied
+            let _pin = self.data_catalogue.pins.pin(&chunk_id);
+            relocation::copy_chunk_dir(&from, &to).map_err(|error| {
+                let _ = std::fs::remove_dir_all(&to);
+                RelocateError::Io(error.to_string())
+            })?;
+        }
+        let volume = (new_volume != self.data_source.data_dir).then_some(new_volume);
+        let recorded = self.data_catalogue.record_volume(&chunk_id, volume.clone(), || self.data_source.set_volume(chunk_id, volume));
+        if let Err(error) = recorded {
+            let _ = std::fs::remove_dir_all(&to);
+            return Err(error);
+        }
+        // nobody holds the old path anymore, leftovers of a failed removal are skipped by the scan on start
+        let _ = std::fs::remove_dir_all(&from);
+        Ok(to)
+    }
+
+    /// Move a `Ready` chunk to another dataset whose files are identical, e.g. after the manifest was re-keyed,
+    /// instead of deleting it and downloading the same files again.
+    /// The chunk id is recomputed, the chunk directory renamed and the catalogue updated under the registry lock,
+    /// so queries find either the old chunk or the new one. Returns the chunk under the new dataset.
+    pub fn reassign_chunk(&self, chunk_id: ChunkId, new_dataset_id: DatasetId) -> Result<DataChunk, ReassignError> {
+        let info = self.data_catalogue.get_chunk_info(&chunk_id).ok_or(ReassignError::UnknownChunk(chunk_id))?;
+        let new_chunk = DataChunk {
+            id: DataCatalogue::generate_chunk_id(&new_dataset_id, &info.chunk.block_range),
+            dataset_id: new_dataset_id,
+            ..info.chunk.clone()
+        };
+        self.data_catalogue.reassign_chunk(&chunk_id, &new_chunk, |info| {
+            // the chunk stays on its volume
+            let volume = info.volume.clone().unwrap_or_else(|| self.data_source.data_dir.clone());
+            let from = self.data_source.chunk_path(info.chunk.clone()).path;
+            let to = DataChunkPath::new(&volume, self.data_source.layout, new_chunk.clone()).path;
+            reassignment::rename_chunk_dir(&from, &to)?;
+            self.data_source.set_volume(chunk_id, None);
+            self.data_source.set_volume(new_chunk.id, info.volume.clone());
+            Ok(())
+        })
+    }
+
+    /// Run a tiny built-in chunk through the full path: download, verify, find, read and delete it,
+    /// e.g. after a deployment or a config change. Blocks until every step is done, see `SelfTestReport::passed`.
+    pub fn self_test(&self, source: &SelfTestSource) -> SelfTestReport {
+        self_test::run(self, source)
+    }
+
+    /// How the chunk was derived from the chunks it replaced, merged, split or was reassigned from, oldest first,
+    /// `None` when the chunk is neither in the catalogue nor in the persisted catalogue.
+    /// A chunk downloaded as published has no lineage.
+    pub fn chunk_lineage(&self, chunk_id: ChunkId) -> Option<Vec<LineageEvent>> {
+        self.data_catalogue.get_chunk_info(&chunk_id)
+            .or_else(|| DataCatalogue::read_persisted_chunk(&self.config.catalogue_file, &chunk_id))
+            .map(|info| info.lineage)
+    }
+
+    /// The chunk as the catalogue knows it, including its latest errors
+    pub fn get_chunk_info(&self, chunk_id: ChunkId) -> Option<ChunkInfo> {
+        self.data_catalogue.get_chunk_info(&chunk_id)
+    }
+
+    /// Dataset and blocks behind an opaque chunk id, e.g. one found in the logs.
+    /// The catalogue is searched first, then the persisted catalogue, which still has the chunks forgotten since it was saved,
+    /// and last the manifests of the followed datasets and the datasets held locally.
+    pub fn describe_chunk_id(&self, chunk_id: ChunkId) -> Option<ChunkDescription> {
+        if let Some(info) = self.data_catalogue.get_chunk_info(&chunk_id) {
+            return Some(ChunkDescription::from_info(info, ChunkOrigin::Catalogue));
+        }
+        if let Some(description) = chunk_lookup::describe_in_catalogue_file(&self.config.catalogue_file, &chunk_id) {
+            return Some(description);
+        }
+        let manifest_source = self.manifest_source.as_ref()?;
+        let mut dataset_ids: BTreeSet<DatasetId> = self.config.tip_following.keys().copied().collect();
+        dataset_ids.extend(self.data_catalogue.registry.read().unwrap().values().map(|info| info.chunk.dataset_id));
+        chunk_lookup::describe_in_manifest(manifest_source.as_ref(), &dataset_ids, &chunk_id)
+    }
+
+    /// Same as `download_chunk`, with high priority downloads starting before all the normal priority downloads waiting,
+    /// e.g. for chunks needed by active queries while a backfill is running. Downloads already running aren't interrupted.
+    pub fn download_chunk_with_priority(&self, chunk: DataChunk, priority: DownloadPriority) {
+        let _ = self.request_download(chunk, priority);
+    }
+
+    /// Same as `download_chunk`, returning a handle to await the result of the download, check its status or cancel it.
+    /// A chunk which is already being downloaded completes with that download, a chunk which is `Ready` completes right away.
+    pub fn download_chunk_with_handle(&self, chunk: DataChunk) -> DownloadHandle {
+        let chunk_id = chunk.id;
+        // waiting before the download is requested, so a download which completes right away isn't missed
+        let handle = DownloadHandle::new(
+            chunk_id,
+            self.download_waiters.wait(chunk_id),
+            self.data_catalogue.clone(),
+            self.cancellations.clone(),
+            self.download_queue.clone(),
+        );
+        let refusal = match self.request_download(chunk, DownloadPriority::Normal) {
+            Ok(()) => return handle,
+            Err(refusal) => refusal,
+        };
+        match handle.status() {
+            Some(data_catalogue::ChunkStatus::Ready) => handle.completed(Ok(())),
+            Some(data_catalogue::ChunkStatus::Downloading) => handle,
+            _ => handle.completed(Err(refusal)),
+        }
+    }
+
+    /// Download the chunks of a batch, e.g. handed over by a scheduler, in the given order.
+    /// Chunks which are malformed are reported as invalid, the ones already in the catalogue, listed twice
+    /// or refused by the overlap policy or a freeze as skipped, and all the others are accepted and queued.
+    pub fn download_chunks(&self, chunks: Vec<DataChunk>) -> BatchSubmission {
+        let mut submission = BatchSubmission::default();
+        let mut submitted: HashSet<ChunkId> = HashSet::new();
+        // skipped chunks by their position in the batch, the requested ones are only skipped once they're refused
+        let mut skipped: Vec<(usize, ChunkId, SkipReason)> = Vec::new();
+        let mut requested: Vec<(usize, DataChunk)> = Vec::new();
+        for (position, chunk) in chunks.into_iter().enumerate() {
+            if let Err(invalid) = batch::check_chunk(&chunk) {
+                submission.invalid.push((chunk.id, invalid));
+                continue;
+            }
+            if !submitted.insert(chunk.id) {
+                skipped.push((position, chunk.id, SkipReason::Duplicate));
+                continue;
+            }
+            match self.data_catalogue.get_chunk_info(&chunk.id).map(|info| info.status) {
+                Some(status) if !matches!(status, data_catalogue::ChunkStatus::Deleted | data_catalogue::ChunkStatus::Failed) => {
+                    skipped.push((position, chunk.id, SkipReason::InCatalogue(status)));
+                }
+                _ => requested.push((position, chunk)),
+            }
+        }
+        let positions: Vec<usize> = requested.iter().map(|(position, _)| *position).collect();
+        let results = self.request_downloads(requested.into_iter().map(|(_, chunk)| chunk).collect(), DownloadPriority::Normal);
+        for (position, (chunk_id, result)) in positions.into_iter().zip(results) {
+            match result {
+                Ok(()) => submission.accepted.push(chunk_id),
+                Err(DownloadError::InsufficientSpace(shortage)) => skipped.push((position, chunk_id, SkipReason::InsufficientSpace(shortage))),
+                Err(_) => skipped.push((position, chunk_id, SkipReason::Refused)),
+            }
+        }
+        skipped.sort_by_key(|(position, _, _)| *position);
+        submission.skipped = skipped.into_iter().map(|(_, chunk_id, reason)| (chunk_id, reason)).collect();
+        submission
+    }
+
+    /// Start or queue the download. Fails with `Refused` when it's refused by the overlap policy or the chunk is already
+    /// being processed, with `InsufficientSpace` when the chunk doesn't fit in the data directory and isn't deferred,
+    /// and with `Invalid` when a file name of the chunk would be written outside its directory.
+    fn request_download(&self, chunk: DataChunk, priority: DownloadPriority) -> Result<(), DownloadError> {
+        let admission = self.admit_download(&chunk)?;
+        self.persist_pending_download(&chunk, priority);
+        self.start_admitted_download(chunk, priority, admission);
+        Ok(())
+    }
+
+    /// Same as `request_download` for every chunk in order, saving the persisted queue once for all the admitted ones.
+    /// Returns the result of every chunk.
+    fn request_downloads(&self, chunks: Vec<DataChunk>, priority: DownloadPriority) -> Vec<(ChunkId, Result<(), DownloadError>)> {
+        let mut results = Vec::with_capacity(chunks.len());
+        let mut admitted = Vec::new();
+        for chunk in chunks {
+            match self.admit_download(&chunk) {
+                Ok(admission) => {
+                    results.push((chunk.id, Ok(())));
+                    admitted.push((chunk, admission));
+                }
+                Err(error) => results.push((chunk.id, Err(error))),
+            }
+        }
+        // persisted before any of them starts, so one which completes right away is dropped from the queue again
+        let _ = self.pending_downloads.add_all(admitted.iter()
+            .map(|(chunk, _)| (chunk, priority, self.checksums.get(&chunk.id).unwrap_or_default())));
+        for (chunk, admission) in admitted {
+            self.start_admitted_download(chunk, priority, admission);
+        }
+        results
+    }
+
+    /// Decide how the download starts, a download started right away is `Downloading` once admitted
+    fn admit_download(&self, chunk: &DataChunk) -> Result<Admission, DownloadError> {
+        self.admit(chunk, None)
+    }
+
+    /// Same as `admit_download` for a replacement of a sync plan, which starts right away or not at all
+    fn admit_replacement(&self, replacement: &Replacement) -> Result<Admission, DownloadError> {
+        self.admit(&replacement.chunk, Some(&replacement.replaced))
+    }
+
+    /// A download `replacing` chunks, which may include the chunk itself when only its files change,
+    /// resolved its overlaps already, so it can't wait outside the catalogue and is refused instead
+    fn admit(&self, chunk: &DataChunk, replacing: Option<&[ChunkId]>) -> Result<Admission, DownloadError> {
+        batch::check_file_names(chunk).map_err(DownloadError::Invalid)?;
+        // blocks being downloaded as part of another chunk aren't downloaded twice
+        let overlaps = self.data_catalogue.in_flight_overlaps(chunk);
+        let mut replaced_chunks = match overlap::resolve(self.config.overlap_policy, chunk, overlaps) {
+            OverlapDecision::Download => Vec::new(),
+            OverlapDecision::Reject => return Err(DownloadError::Refused),
+            OverlapDecision::QueueBehind(_) if replacing.is_some() => return Err(DownloadError::Refused),
+            OverlapDecision::QueueBehind(overlapping_chunk_ids) => return Ok(Admission::QueueBehind(overlapping_chunk_ids)),
+            OverlapDecision::Replace(replaced_chunks) => replaced_chunks,
+        };
+        for chunk_id in replacing.unwrap_or_default() {
+            if *chunk_id != chunk.id && !replaced_chunks.iter().any(|replaced| replaced.id == *chunk_id) {
+                replaced_chunks.extend(self.data_catalogue.get_chunk_by_id(chunk_id));
+            }
+        }
+        // checked before the chunk is `Downloading`, rather than failing the download once the disk is full
+        if let Some(preflight) = &self.space_preflight {
+            if let Err(shortage) = preflight.check(&self.data_catalogue, chunk) {
+                if preflight.config.when_insufficient == InsufficientSpaceAction::Defer && replacing.is_none() && replaced_chunks.is_empty() {
+                    return Ok(Admission::Deferred);
+                }
+                // only a chunk already in the catalogue keeps the error in its history
+                self.data_catalogue.record_error(&chunk.id, ChunkErrorKind::Download, shortage.to_string());
+                return Err(DownloadError::InsufficientSpace(shortage));
+            }
+        }
+        let in_place = replacing.is_some_and(|replacing| replacing.contains(&chunk.id));
+        let started = if in_place {
+            self.data_catalogue.start_redownload(chunk)
+        } else {
+            self.data_catalogue.start_download(chunk)
+        };
+        if !started {
             // don't try to download the chunk if it's already being processed
+            return Err(DownloadError::Refused);
+        }
+        Ok(if in_place { Admission::Redownload(replaced_chunks) } else { Admission::Start(replaced_chunks) })
+    }
+
+    fn start_admitted_download(&self, chunk: DataChunk, priority: DownloadPriority, admission: Admission) {
+        match admission {
+            Admission::QueueBehind(overlapping_chunk_ids) => self.workers().spawn_queued_download(chunk, overlapping_chunk_ids, priority),
+            Admission::Deferred => self.workers().spawn_queued_download(chunk, Vec::new(), priority),
+            Admission::Start(replaced_chunks) => {
+                self.hooks.emit(LifecycleEvent::DownloadStart(chunk.clone()));
+                if replaced_chunks.is_empty() {
+                    self.workers().spawn_prioritized_download(chunk, priority);
+                } else {
+                    self.workers().spawn_replacement(chunk, replaced_chunks, priority);
+                }
+            }
+            Admission::Redownload(replaced_chunks) => {
+                self.hooks.emit(LifecycleEvent::DownloadStart(chunk.clone()));
+                self.workers().spawn_replacement(chunk, replaced_chunks, priority);
+            }
+        }
+    }
+
+    /// Start the deletion in background. Returns `false` when the chunk doesn't exist, isn't ready,
+    /// or its deletion is refused, e.g. it's under a legal hold or its dataset is frozen.
+    fn request_deletion(&self, chunk_id: ChunkId) -> bool {
+        let Some(chunk) = self.data_catalogue.get_chunk_by_id(&chunk_id) else { return false };
+        if !self.data_catalogue.start_deletion(&chunk) {
+            return false;
+        }
+        self.workers().spawn_deletion(chunk);
+        true
+    }
+
+    /// Record the download in the persisted queue, so it's requested again when the process stops before it's done.
+    /// A download which can't be recorded still goes on, it's only not resumed after a restart.
+    fn persist_pending_download(&self, chunk: &DataChunk, priority: DownloadPriority) {
+        let checksums = self.checksums.get(&chunk.id).unwrap_or_default();
+        let _ = self.pending_downloads.add(chunk, priority, checksums);
+    }
+
+    /// Request the downloads left pending when the process stopped, in the order they were requested.
+    /// The chunks which got ready before it stopped are dropped from the queue instead.
+    pub(crate) fn resume_pending_downloads(&self) {
+        for download in self.pending_downloads.downloads() {
+            if self.data_catalogue.get_chunk_info(&download.chunk.id).is_some_and(|info| info.status == data_catalogue::ChunkStatus::Ready) {
+                let _ = self.pending_downloads.remove(&download.chunk.id);
+                continue;
+            }
+            if !download.checksums.is_empty() {
+                self.checksums.expect(download.chunk.id, download.checksums);
+            }
+            self.download_chunk_with_priority(download.chunk, download.priority);
+        }
+    }
+
+    /// Same as `download_chunk`, for a chunk needed within the given time.
+    /// When the chunk isn't `Ready` by then, the `on_deadline_missed` hooks are run
+    /// and its download starts next, ahead of the other waiting downloads.
+    /// A refused download isn't watched.
+    pub fn download_chunk_with_deadline(&self, chunk: DataChunk, within: Duration) {
+        if self.request_download(chunk.clone(), DownloadPriority::Normal).is_err() {
             return;
         }
+        if let Some(deadlines) = &self.deadlines {
+            deadlines.watch(chunk, within);
+        }
+    }
+
+    /// Same as `download_chunk`, checking the sha256 of the downloaded files before the chunk is marked `Ready`.
+    /// A chunk with a corrupt file is marked `Failed` with a `Verification` error instead.
+    pub fn download_chunk_with_checksums(&self, chunk: DataChunk, checksums: FileChecksums) {
+        self.checksums.expect(chunk.id, checksums);
+        self.download_chunk(chunk);
+    }
+
+    /// Stop starting downloads, e.g. for a maintenance window. The running downloads complete,
+    /// the queued ones and the ones requested meanwhile stay `Downloading` until `resume_downloads`.
+    pub fn pause_downloads(&self) {
+        self.download_pool.pause();
+    }
+
+    /// Start the downloads queued while paused, in the order they would have started
+    pub fn resume_downloads(&self) {
+        self.download_pool.resume();
+    }
+
+    pub fn downloads_paused(&self) -> bool {
+        self.download_pool.is_paused()
+    }
+
+    /// Import the checksum file of a publisher for the files of the dataset, e.g. its `SHA256SUMS`, see `parse_sha256sums`.
+    /// The downloads of the chunks check their files against it from then on, as with `download_chunk_with_checksums`,
+    /// the checksums are kept next to the catalogue. Returns the number of imported checksums,
+    /// or `NoPublishedStore` when the data manager keeps no published checksums.
+    pub fn import_checksums(&self, dataset_id: DatasetId, checksums_file: &Path) -> Result<usize, ChecksumImportError> {
+        let checksums = published_checksums::read_sha256sums(checksums_file)?;
+        let Some(published) = self.checksums.published() else { return Err(ChecksumImportError::NoPublishedStore) };
+        let imported = checksums.len();
+        published.import(dataset_id, checksums).map_err(|error| ChecksumImportError::Io(error.to_string()))?;
+        Ok(imported)
+    }
+
+    /// Bytes received for every file of a chunk being downloaded, with the overall percentage and the time left once the sizes are known.
+    /// A chunk waiting for a download slot has no bytes yet. Returns `None` when the chunk isn't being downloaded.
+    pub fn download_progress(&self, chunk_id: ChunkId) -> Option<ChunkProgress> {
+        let info = self.data_catalogue.get_chunk_info(&chunk_id).filter(|info| info.status == data_catalogue::ChunkStatus::Downloading)?;
+        Some(self.data_source.progress.get(&chunk_id).unwrap_or_else(|| ChunkProgress::new(&info.chunk)))
+    }
+
+    /// Stop the download of the chunk, its files are removed, including the partially downloaded ones, and it's marked `Deleted`.
+    /// Transfers streaming the files, e.g. `ResumableTransfer`, stop at their next write, see `Cancellations`.
+    /// Returns `false` when the chunk isn't being downloaded.
+    pub fn cancel_download(&self, chunk_id: ChunkId) -> bool {
+        self.cancellations.cancel_download(&self.data_catalogue, self.download_queue.as_ref(), chunk_id)
+    }
+
+    /// Download the chunks of the dataset published past its local tip with high priority, as they appear in the manifest.
+    /// Returns `false` when there is no manifest source to follow.
+    pub fn follow_tip(&self, dataset_id: DatasetId, config: TipFollowingConfig) -> bool {
+        let Some(tip_follower) = &self.tip_follower else { return false };
+        tip_follower.follow(dataset_id, config);
+        true
+    }
+
+    /// Returns `false` when the dataset wasn't followed
+    pub fn unfollow_tip(&self, dataset_id: &DatasetId) -> bool {
+        self.tip_follower.as_ref().is_some_and(|tip_follower| tip_follower.unfollow(dataset_id))
+    }
+
+    /// Disk usage of the local chunks, as recorded when they got ready and corrected by sampling
+    pub fn storage_stats(&self) -> StorageStats {
+        self.storage.stats()
+    }
+
+    /// Live and dead rows of the persisted catalogue
+    pub fn catalogue_stats(&self) -> CatalogueStats {
+        self.data_catalogue.stats()
+    }
+
+    /// Copy of the catalogue at this moment, unaffected by the changes made after
+    pub fn catalogue_snapshot(&self) -> CatalogueSnapshot {
+        self.data_catalogue.snapshot()
+    }
+
+    /// Chunk counts, bytes and coverage of every dataset, all computed from the same `catalogue_snapshot`,
+    /// so they are consistent with each other even during a large sync, unlike separate calls of `coverage` and `storage_stats`
+    pub fn stats_at_snapshot(&self) -> SnapshotStats {
+        self.catalogue_snapshot().stats(&self.storage.recorded_bytes())
+    }
+
+    /// Compactions of the catalogue since the start, both automatic and requested with `compact_catalogue`
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.data_catalogue.compaction_stats()
+    }
+
+    /// Drop the rows of the deleted chunks from the catalogue now, regardless of the threshold
+    pub fn compact_catalogue(&self) -> CompactionRun {
+        self.data_catalogue.compact()
+    }
+
+    /// Same as `find_chunk`, but returns the concrete chunk reference.
+    /// Chunks which aren't held locally are looked up in the secondary catalogues,
+    /// such references have a `ChunkSource::Secondary` source.
+    /// On a follower, a chunk of the leader which isn't mirrored yet isn't found, its first read starts mirroring it.
+    pub fn find_chunk_path(&self, dataset_id: DatasetId, block_number: u64) -> Option<DataChunkPath> {
+        let started_at = Instant::now();
+        let chunk_path = self.data_catalogue
+            .find_and_pin_chunk(&dataset_id, block_number)
+            .map(|(chunk, pin)| self.data_source.pinned_chunk_path(chunk, pin))
+            .or_else(|| {
+                // a follower mirrors the chunks of its leader on their first read
+                if let Some(replica) = &self.replica {
+                    replica.mirror(&self.workers(), &dataset_id, block_number);
+                }
+                self.secondary_catalogues.iter()
+                    .find_map(|catalogue| catalogue.find_chunk(&dataset_id, block_number))
+            });
+        self.slo.record(Slo::FindChunk, started_at.elapsed());
+        self.consumer_stats.record(0);
+        chunk_path
+    }
+
+    /// Find and pin the chunk of the dataset holding the block, as `find_chunk_path` does,
+    /// and return its path, block range, files and schema at once
+    pub fn acquire(&self, dataset_id: DatasetId, block_number: u64) -> Option<AcquiredChunk> {
+        self.find_chunk_path(dataset_id, block_number).map(AcquiredChunk::new)
+    }
+
+    /// Find the chunk of the dataset holding the last block produced at or before the unix timestamp, in seconds.
+    /// Only blocks whose times were recorded can be found, see `record_block_times`.
+    pub fn find_chunk_by_time(&self, dataset_id: DatasetId, timestamp: u64) -> Option<DataChunkPath> {
+        let block_number = self.block_times.block_at(&dataset_id, timestamp)?;
+        self.find_chunk_path(dataset_id, block_number)
+    }
+
+    /// Add the block times from a manifest to the persisted block time index.
+    /// Manifests followed with `follow_tip` record their block times themselves.
+    pub fn record_block_times(&self, dataset_id: DatasetId, block_times: &[BlockTime]) -> io::Result<()> {
+        self.block_times.record(dataset_id, block_times)
+    }
+
+    /// Read the rows of the blocks from the given file of the ready chunks of a dataset,
+    /// keeping only the projected columns, or all of them when the projection is empty.
+    /// Results are served from the query cache when it's configured.
+    #[cfg(feature = "dataframes")]
+    pub fn scan_blocks(&self, dataset_id: DatasetId, file_name: &str, block_range: Range<u64>, projection: &[&str]) -> io::Result<DataFrame> {
+        let fingerprint = QueryFingerprint {
+            dataset_id,
+            file_name: file_name.to_string(),
+            block_range,
+            projection: projection.iter().map(|column| column.to_string()).collect(),
+        };
+        if let Some(result) = self.query_cache.as_ref().and_then(|query_cache| query_cache.get(&fingerprint)) {
+            self.consumer_stats.record(result.estimated_size() as u64);
+            return Ok(result);
+        }
 
-        let data_dir = self.data_source.data_dir.clone();
-        let data_catalogue = self.data_catalogue.clone();
-        thread::spawn(move || {
-            let result = LocalDataSource::download_chunk(data_dir, chunk.clone());
-            TasksManager::wake_the_future(task_waker);
-            data_catalogue.update_chunk(&chunk, &ChunkStatus::Ready);
-            result
+        let generation = self.query_cache.as_ref().map(|query_cache| query_cache.generation());
+        // the chunks stay pinned until the scan finishes
+        let chunks = self.data_catalogue.pin_ready_chunks(&dataset_id, &fingerprint.block_range);
+        let sources = chunks.iter()
+            .filter(|(chunk, _)| chunk.files.contains_key(file_name))
+            .map(|(chunk, _)| {
+                let path = self.data_source.chunk_path(chunk.clone()).path.join(file_name);
+                Ok(match self.fetch_if_broken(chunk, file_name, &path)? {
+                    Some(bytes) => ParquetSource::Fetched(bytes),
+                    None => ParquetSource::File(path),
+                })
+            })
+            .collect::<io::Result<Vec<ParquetSource>>>()?;
+        let result = scan::scan_parquet_files(&sources, &fingerprint.block_range, &fingerprint.projection)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+        if let (Some(query_cache), Some(generation)) = (&self.query_cache, generation) {
+            query_cache.insert(fingerprint, result.clone(), generation);
         }
-        );
+        self.consumer_stats.record(result.estimated_size() as u64);
+        Ok(result)
+    }
+
+    /// Results of the latest background downloads, deletions and evictions, oldest first,
+    /// of the chunk or of all the chunks when `None`. The hooks get them as they finish, see `LifecycleHooks::on_operation`.
+    pub fn operation_results(&self, chunk_id: Option<ChunkId>) -> Vec<OperationResult> {
+        self.tasks_manager.operations.results(chunk_id.as_ref())
+    }
+
+    /// Downloads run at once at most, adjusted over time with `adaptive_concurrency`
+    pub fn download_concurrency(&self) -> usize {
+        self.download_pool.max_concurrent()
+    }
+
+    /// Compliance and burn rate of the latency targets of `find_chunk` and downloads
+    pub fn slo_report(&self) -> SloReport {
+        self.slo.report()
+    }
+
+    /// Queries and bytes served per consumer, reads outside of a `consumers::scope` are `anonymous`
+    pub fn consumer_usage(&self) -> BTreeMap<String, ConsumerUsage> {
+        self.consumer_stats.usage()
+    }
+
+    /// Memory map a file of a ready chunk.
+    /// The chunk is pinned until the mapping is dropped, so a deletion waits for the reader to finish.
+    pub fn mmap_chunk_file(&self, chunk_id: ChunkId, file_name: &str) -> io::Result<MappedChunkFile> {
+        let Some((chunk, pin)) = self.data_catalogue.pin_ready_chunk(&chunk_id) else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("chunk {} is not available", hex::encode(chunk_id))));
+        };
+        if !chunk.files.contains_key(file_name) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("chunk {} has no file {}", hex::encode(chunk_id), file_name)));
+        }
+        let path = self.data_source.chunk_path(chunk.clone()).path.join(file_name);
+        let mapped = match self.fetch_if_broken(&chunk, file_name, &path)? {
+            Some(bytes) => MappedChunkFile::fetched(path, bytes, pin),
+            None => MappedChunkFile::open(path, pin)?,
+        };
+        self.consumer_stats.record(mapped.len() as u64);
+        Ok(mapped)
+    }
+
+    /// Stream the rows of a file of a ready chunk in batches, keeping only the projected columns,
+    /// or all of them when the projection is empty, and the rows matching the filter.
+    /// The chunk is pinned until the stream is dropped.
+    #[cfg(feature = "dataframes")]
+    pub fn stream_chunk_rows(&self, chunk_id: ChunkId, file_name: &str, projection: &[&str], filter: Option<Expr>) -> io::Result<ChunkRowStream> {
+        let Some((chunk, pin)) = self.data_catalogue.pin_ready_chunk(&chunk_id) else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("chunk {} is not available", hex::encode(chunk_id))));
+        };
+        if !chunk.files.contains_key(file_name) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("chunk {} has no file {}", hex::encode(chunk_id), file_name)));
+        }
+        let path = self.data_source.chunk_path(chunk).path.join(file_name);
+        let stream = ChunkRowStream::open(&path, projection, filter, DEFAULT_BATCH_ROWS, pin)?;
+        // the whole file is read, whatever the filter keeps
+        self.consumer_stats.record(std::fs::metadata(&path)?.len());
+        Ok(stream)
+    }
+
+    /// Check the block hashes of up to `max_chunks` ready chunks again, e.g. within a maintenance window.
+    /// Datasets with a higher maintenance priority go first, then the chunks verified longest ago.
+    /// Chunks from another fork are deleted.
+    pub fn verify_chunks(&self, max_chunks: usize) -> VerificationRun {
+        maintenance::verify_ready_chunks(&self.workers(), &self.verification_log, &self.config.maintenance_priorities, max_chunks)
+    }
+
+    /// Fetch again only the files of a ready or failed chunk which are missing, unreadable, of another size than declared
+    /// or with another checksum than known, and swap them into its directory. A ready chunk stays `Ready` and is read meanwhile.
+    /// Returns the damaged files, none when the chunk is intact. With transformers every file is fetched again,
+    /// as the transformed files can't be told from damaged ones.
+    pub fn repair_chunk(&self, chunk_id: ChunkId) -> Result<Vec<String>, RepairError> {
+        let info = self.data_catalogue.get_chunk_info(&chunk_id).ok_or(RepairError::UnknownChunk(chunk_id))?;
+        if !matches!(info.status, data_catalogue::ChunkStatus::Ready | data_catalogue::ChunkStatus::Failed) {
+            return Err(RepairError::Busy(info.status));
+        }
+        let chunk = info.chunk;
+        let chunk_dir = self.data_source.chunk_path(chunk.clone()).path;
+        if !chunk_dir.is_dir() {
+            return Err(RepairError::MissingDirectory(chunk_dir));
+        }
+        let mut damaged_files = repair::damaged_files(&chunk, &chunk_dir, &self.checksums.file_checksums(&chunk));
+        if damaged_files.is_empty() {
+            return Ok(damaged_files);
+        }
+        if !self.transformers.is_empty() {
+            damaged_files = chunk.files.keys().cloned().collect();
+            damaged_files.sort();
+        }
+        let started = match info.status {
+            data_catalogue::ChunkStatus::Ready => self.data_catalogue.start_repair(&chunk),
+            _ => self.data_catalogue.start_download(&chunk),
+        };
+        if !started {
+            return Err(if self.data_catalogue.is_repairing(&chunk_id) { RepairError::InProgress } else { RepairError::Refused });
+        }
+        self.hooks.emit(LifecycleEvent::DownloadStart(chunk.clone()));
+        self.workers().spawn_repair(chunk, damaged_files.clone());
+        Ok(damaged_files)
+    }
+
+    /// With an origin fetcher, a missing or corrupt file of a ready chunk is fetched from its origin URL,
+    /// and the damaged files of the chunk are repaired in background. Returns `None` for intact files.
+    fn fetch_if_broken(&self, chunk: &DataChunk, file_name: &str, path: &Path) -> io::Result<Option<Vec<u8>>> {
+        let Some(origin_fetcher) = &self.origin_fetcher else { return Ok(None) };
+        if origin::is_intact(path) {
+            return Ok(None);
+        }
+        self.data_catalogue.record_error(&chunk.id, ChunkErrorKind::Verification, format!("file {} is missing or corrupt", file_name));
+        // the chunk stays ready during the repair, so the reads until the files are swapped in are served from the origin too.
+        // A repair already running is left to finish.
+        let _ = self.repair_chunk(chunk.id);
+        let mut result = Err(io::Error::new(io::ErrorKind::NotFound, format!("chunk {} has no file {}", hex::encode(chunk.id), file_name)));
+        // the mirrors are tried when the origin fails
+        for url in chunk.file_urls(file_name) {
+            result = origin_fetcher.fetch(url);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result.map(Some)
+    }
+
+    /// How much of an epoch of a dataset is available locally
+    pub fn epoch_status(&self, dataset_id: DatasetId, epoch: u64) -> Result<EpochStatus, EpochError> {
+        let layout = self.epoch_layout()?;
+        Ok(self.data_catalogue.epoch_status(&layout, dataset_id, epoch))
+    }
+
+    /// Schedule the download of a whole epoch in background.
+    /// The `chunks` must cover the whole epoch, chunks which are already ready are skipped.
+    /// The other chunks are admitted like `download_chunk` in order, either all of them are, or none of them
+    /// when any is being processed or refused, the chunks admitted before it are put back as they were.
+    pub fn download_epoch(&self, dataset_id: DatasetId, epoch: u64, chunks: Vec<DataChunk>) -> Result<(), EpochError> {
+        let layout = self.epoch_layout()?;
+        if self.data_catalogue.is_frozen(&dataset_id) {
+            return Err(EpochError::Frozen);
+        }
+        if let Some(chunk) = chunks.iter().find(|chunk| chunk.dataset_id != dataset_id || !layout.contains(epoch, chunk)) {
+            return Err(EpochError::ChunkOutsideEpoch(chunk.id));
+        }
+        let missing_ranges = epoch::missing_ranges(&layout.block_range(epoch), &chunks.iter().collect::<Vec<_>>());
+        if !missing_ranges.is_empty() {
+            return Err(EpochError::Incomplete(missing_ranges));
+        }
+
+        let ready_chunk_ids = self.data_catalogue.epoch_status(&layout, dataset_id, epoch).ready_chunks;
+        let chunks: Vec<DataChunk> = chunks.into_iter()
+            .filter(|chunk| !ready_chunk_ids.contains(&chunk.id))
+            .collect();
+        let mut admitted = Vec::with_capacity(chunks.len());
+        for chunk in chunks {
+            let previous = self.data_catalogue.get_chunk_info(&chunk.id);
+            match self.admit_download(&chunk) {
+                Ok(admission) => admitted.push((chunk, previous, admission)),
+                Err(error) => {
+                    for (admitted_chunk, previous, admission) in admitted {
+                        if matches!(admission, Admission::Start(_)) {
+                            self.data_catalogue.undo_download(&admitted_chunk, previous);
+                        }
+                    }
+                    return Err(match error {
+                        DownloadError::Refused => EpochError::Busy,
+                        error => EpochError::Refused(chunk.id, error),
+                    });
+                }
+            }
+        }
+        // persisted before any of them starts, so one which completes right away is dropped from the queue again
+        let _ = self.pending_downloads.add_all(admitted.iter()
+            .map(|(chunk, _, _)| (chunk, DownloadPriority::Normal, self.checksums.get(&chunk.id).unwrap_or_default())));
+        for (chunk, _, admission) in admitted {
+            self.start_admitted_download(chunk, DownloadPriority::Normal, admission);
+        }
+        Ok(())
+    }
+
+    /// Schedule the deletion of all chunks of an epoch in background, returns the deleted chunks.
+    /// Either all the chunks start deleting, or none of them when any is being processed.
+    pub fn delete_epoch(&self, dataset_id: DatasetId, epoch: u64) -> Result<Vec<ChunkId>, EpochError> {
+        let layout = self.epoch_layout()?;
+        if self.data_catalogue.is_frozen(&dataset_id) {
+            return Err(EpochError::Frozen);
+        }
+        let chunks: Vec<DataChunk> = self.data_catalogue.epoch_chunks(&layout, dataset_id, epoch)
+            .into_iter()
+            .map(|info| info.chunk)
+            .collect();
+        // a chunk reaching into another epoch would leave a hole in it
+        if let Some(chunk) = chunks.iter().find(|chunk| !layout.contains(epoch, chunk)) {
+            return Err(EpochError::ChunkOutsideEpoch(chunk.id));
+        }
+        if let Some(chunk) = chunks.iter().find(|chunk| self.data_catalogue.is_under_legal_hold(chunk)) {
+            return Err(EpochError::LegalHold(chunk.id));
+        }
+        if !self.data_catalogue.start_deletions(&chunks) {
+            return Err(EpochError::Busy);
+        }
+        let workers = self.workers();
+        let chunk_ids = chunks.iter().map(|chunk| chunk.id).collect();
+        for chunk in chunks {
+            workers.spawn_deletion(chunk);
+        }
+        Ok(chunk_ids)
+    }
+
+    /// Cheap check whether a chunk is ready, answered from a bloom filter without locking the catalogue.
+    /// `false` means the chunk is definitely not available, `true` means it most likely is.
+    pub fn may_have_chunk(&self, chunk_id: &ChunkId) -> bool {
+        self.data_catalogue.may_be_ready(chunk_id)
+    }
+
+    /// Ready, downloading and missing block ranges of a dataset along the chain
+    pub fn coverage(&self, dataset_id: DatasetId) -> Coverage {
+        let registry = self.data_catalogue.registry.read().unwrap();
+        Coverage::new(dataset_id, registry.values())
+    }
+
+    /// Coverage of a dataset rendered as a horizontal bar, to confirm the coverage visually after large syncs
+    pub fn coverage_svg(&self, dataset_id: DatasetId) -> String {
+        self.coverage(dataset_id).to_svg()
+    }
+
+    pub fn coverage_json(&self, dataset_id: DatasetId) -> String {
+        self.coverage(dataset_id).to_json().to_string()
+    }
+
+    /// Ready chunks in the compact form exchanged with coordinators, see `Holdings::encode`
+    pub fn holdings(&self) -> Holdings {
+        let registry = self.data_catalogue.registry.read().unwrap();
+        Holdings::from_chunks(
+            registry.values()
+                .filter(|info| info.status == data_catalogue::ChunkStatus::Ready)
+                .map(|info| &info.chunk),
+        )
+    }
+
+    /// Ready chunks of a dataset in the order they would be evicted,
+    /// following the retention policy of the dataset. Chunks under a legal hold are never evicted.
+    pub fn eviction_order(&self, dataset_id: DatasetId) -> Vec<ChunkId> {
+        self.data_catalogue
+            .eviction_order(dataset_id, self.eviction_order_of(&dataset_id))
+            .iter()
+            .filter(|chunk| !self.data_catalogue.is_under_legal_hold(chunk))
+            .map(|chunk| chunk.id)
+            .collect()
+    }
+
+    /// Stop any downloads, deletions and evictions of the dataset from starting until it's unfrozen,
+    /// e.g. while investigating data issues or during a coordinated migration.
+    /// Operations started before keep running. Returns `false` when the dataset was already frozen.
+    pub fn freeze_dataset(&self, dataset_id: DatasetId) -> bool {
+        self.data_catalogue.freeze(dataset_id)
+    }
+
+    /// Returns `false` when the dataset wasn't frozen
+    pub fn unfreeze_dataset(&self, dataset_id: DatasetId) -> bool {
+        self.data_catalogue.unfreeze(&dataset_id)
+    }
+
+    pub fn frozen_datasets(&self) -> Vec<DatasetId> {
+        self.data_catalogue.frozen_datasets()
+    }
+
+    /// Keep the chunks of the hold, e.g. all the chunks of a dataset under litigation, until the hold is lifted.
+    /// They can't start deleting, being evicted or replaced, nor be forgotten, while downloads go on as usual.
+    /// The hold is saved next to the catalogue and survives restarts. Returns `false` when it was already placed.
+    pub fn place_legal_hold(&self, hold: LegalHold) -> io::Result<bool> {
+        self.data_catalogue.place_legal_hold(hold)
+    }
+
+    /// Returns `false` when the hold wasn't placed
+    pub fn lift_legal_hold(&self, hold: LegalHold) -> io::Result<bool> {
+        self.data_catalogue.lift_legal_hold(&hold)
+    }
+
+    pub fn legal_holds(&self) -> Vec<LegalHold> {
+        self.data_catalogue.legal_holds()
+    }
+
+    /// Evict chunks of the datasets holding more chunks than their retention policy allows.
+    /// Returns the chunks which are being evicted.
+    pub fn enforce_retention(&self) -> Vec<ChunkId> {
+        let workers = self.workers();
+        let mut evicted = Vec::new();
+        for (dataset_id, policy) in self.config.retention.iter() {
+            let chunks = self.data_catalogue.eviction_order(*dataset_id, policy.order);
+            let excess = chunks.len().saturating_sub(policy.max_chunks);
+            // held chunks count against the limit, the other chunks are evicted in their place
+            let evictable = chunks.into_iter().filter(|chunk| !self.data_catalogue.is_under_legal_hold(chunk));
+            for chunk in evictable.take(excess) {
+                if !self.data_catalogue.start_deletion(&chunk) {
+                    continue;
+                }
+                evicted.push(chunk.id);
+                workers.spawn_eviction(chunk);
+            }
+        }
+        evicted
+    }
+
+    fn eviction_order_of(&self, dataset_id: &DatasetId) -> EvictionOrder {
+        self.config.retention.get(dataset_id).map(|policy| policy.order).unwrap_or_default()
+    }
+
+    fn epoch_layout(&self) -> Result<EpochLayout, EpochError> {
+        self.config.epochs.ok_or(EpochError::NotConfigured)
+    }
+
+    /// Download the replacement chunk and delete the replaced chunks only once it's ready,
+    /// so the blocks stay available during the replacement
+    fn replace_chunk(&self, replacement: Replacement) {
+        let Ok(admission) = self.admit_replacement(&replacement) else { return };
+        self.persist_pending_download(&replacement.chunk, DownloadPriority::Normal);
+        self.start_admitted_download(replacement.chunk, DownloadPriority::Normal, admission);
+    }
+
+    pub(crate) fn workers(&self) -> Workers {
+        Workers {
+            data_source: self.data_source.clone(),
+            backend: self.backend.clone(),
+            data_catalogue: self.data_catalogue.clone(),
+            tasks_manager: self.tasks_manager.clone(),
+            hooks: self.hooks.clone(),
+            slo: self.slo.clone(),
+            transformers: self.transformers.clone(),
+            download_queue: self.download_queue.clone(),
+            block_times: self.block_times.clone(),
+            #[cfg(feature = "dataframes")]
+            hash_verifiers: self.hash_verifiers.clone(),
+            maintenance_priorities: Arc::new(self.config.maintenance_priorities.clone()),
+            checksums: self.checksums.clone(),
+            cancellations: self.cancellations.clone(),
+            download_retry: self.config.download_retry.clone(),
+            download_pool: self.download_pool.clone(),
+            deletion_grace: self.config.deletion_grace,
+            concurrency_controller: self.concurrency_controller.clone(),
+            space_preflight: self.space_preflight.clone(),
+            permissions: self.config.permissions.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "runtime")]
+impl DataManager for DataManagerImpl {
+    fn new(data_dir: PathBuf) -> Self {
+        Self::with_config(DataManagerConfig::new(data_dir))
+    }
+
+    /// Schedule `chunk` download in background
+    fn download_chunk(&self, chunk: DataChunk) {
+        self.download_chunk_with_priority(chunk, DownloadPriority::Normal);
     }
 
     /// List chunks, that are currently available
@@ -65,50 +1273,25 @@ impl DataManager for DataManagerImpl {
 
     /// Find a chunk from a given dataset, that is responsible for `block_number`.
     fn find_chunk(&self, dataset_id: DatasetId, block_number: u64) -> Option<impl DataChunkRef> {
-        match self.data_catalogue.find_chunk(&dataset_id, block_number) {
-            Some(chunk) => Some(DataChunkPath::new(chunk)),
-            None => None,
-        }
+        self.find_chunk_path(dataset_id, block_number)
     }
 
     fn delete_chunk(&self, chunk_id: ChunkId) {
-        let task_waker = self.tasks_manager.add_future_to_manager_pool();
-        let chunk = self.data_catalogue.get_chunk_by_id(&chunk_id);
-        match chunk {
-            Some(chunk) => {
-                if !self.data_catalogue.start_deletion(&chunk) {
-                    // don't try to delete the chunk if it's not ready
-                    return;
-                }
-                thread::spawn({
-                    let data_dir = self.data_source.data_dir.clone();
-                    let chunk = chunk.clone();
-                    let task_waker = task_waker.clone();
-                    let data_catalogue = self.data_catalogue.clone();
-
-                    move || {
-                        let result = LocalDataSource::delete_chunk(data_dir, chunk_id);
-                        TasksManager::wake_the_future(task_waker);
-
-                        data_catalogue.update_chunk(&chunk, &ChunkStatus::Deleted);
-                        result
-                    }
-                });
-            }
-            None => {
-                // don't try to delete the chunk if it doesn't exist
-                return;
-            }
-        }
+        self.request_deletion(chunk_id);
     }
 }
 
+#[cfg(feature = "runtime")]
 #[cfg(test)]
 mod tests {
+    use std::ops::Range;
+    use std::thread;
+    use crate::data_catalogue::ChunkStatus;
     use crate::local_data_source::LOCAL_DATA_DIR;
     use serial_test::serial;
     use crate::data_catalogue::load_catalogue_with_local_chunks;
     use crate::local_data_source::{get_test_chunk_111111_0_35, get_test_chunk_111111_107_135, get_test_chunk_111111_95_106};
+    use crate::test_support;
     use super::*;
 
     #[test]
@@ -341,7 +1524,1128 @@ mod tests {
         // Assert
         assert!(chunk.is_none());
     }
-}
 
+    #[test]
+    #[serial]
+    fn test_plan_sync() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let chunk_0_35 = get_test_chunk_111111_0_35();
+        let chunk_95_106 = get_test_chunk_111111_95_106();
 
+        // Act
+        let plan = data_manager.plan_sync(chunk_0_35.dataset_id, &[chunk_0_35.clone(), chunk_95_106.clone()]);
 
+        // Assert: nothing is executed, 36..94 is not in the manifest anymore
+        assert_eq!(plan.downloads, vec![chunk_95_106.clone()]);
+        assert_eq!(plan.deletions, vec![DataCatalogue::generate_chunk_id(&chunk_0_35.dataset_id, &(36..94))]);
+        assert!(plan.replacements.is_empty());
+        let registry = data_manager.data_catalogue.registry.read().unwrap();
+        assert_eq!(registry.len(), 8);
+        assert!(!registry.contains_key(&chunk_95_106.id));
+    }
+
+    #[test]
+    #[serial]
+    fn test_ensure_chunks() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let dataset_id = get_test_chunk_111111_0_35().dataset_id;
+        let mut manifest: Vec<DataChunk> = data_manager.data_source.get_local_chunks()
+            .into_iter()
+            .filter(|chunk| chunk.dataset_id == dataset_id)
+            .collect();
+        let chunk = get_test_chunk_111111_95_106();
+        manifest.push(chunk.clone());
+
+        // Act
+        let plan = data_manager.ensure_chunks(dataset_id, &manifest);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+
+        // Assert
+        assert_eq!(plan.downloads, vec![chunk.clone()]);
+        assert!(plan.deletions.is_empty());
+        {
+            let registry = data_manager.data_catalogue.registry.read().unwrap();
+            assert_eq!(registry.get(&chunk.id).unwrap().status, ChunkStatus::Ready);
+        }
+        assert!(data_manager.plan_sync(dataset_id, &manifest).is_empty());
+
+        // cleanup
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+    }
+
+    #[test]
+    #[serial]
+    fn test_mmap_chunk_file() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let chunk = get_test_chunk_111111_0_35();
+
+        // Act
+        let mapped_file = data_manager.mmap_chunk_file(chunk.id, "part-1.parquet").unwrap();
+
+        // Assert
+        assert_eq!(mapped_file.path().to_str().unwrap(), "./local_data_dir/dataset_id=1111111111111111111111111111111111111111111111111111111111111111/block_range=0_35/part-1.parquet");
+        assert_eq!(mapped_file.len(), 0);
+        assert_eq!(data_manager.data_catalogue.pins.pin_count(&chunk.id), 1);
+        drop(mapped_file);
+        assert_eq!(data_manager.data_catalogue.pins.pin_count(&chunk.id), 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_mmap_missing_chunk_file() {
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let chunk = get_test_chunk_111111_0_35();
+
+        let missing_file = data_manager.mmap_chunk_file(chunk.id, "part-9.parquet");
+        let missing_chunk = data_manager.mmap_chunk_file([3u8; 32], "part-1.parquet");
+
+        assert_eq!(missing_file.err().unwrap().kind(), io::ErrorKind::NotFound);
+        assert_eq!(missing_chunk.err().unwrap().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    #[serial]
+    fn test_deletion_waits_for_mapped_file() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let chunk = get_test_chunk_111111_95_106();
+        data_manager.download_chunk(chunk.clone());
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+        let mapped_file = data_manager.mmap_chunk_file(chunk.id, "part-1.parquet").unwrap();
+
+        // Act
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+
+        // Assert the files stay while mapped
+        {
+            let registry = data_manager.data_catalogue.registry.read().unwrap();
+            assert_eq!(registry.get(&chunk.id).unwrap().status, ChunkStatus::Deleting);
+        }
+        assert!(mapped_file.path().exists());
+
+        // Assert deleted after the mapping is dropped
+        let file_path = mapped_file.path().to_path_buf();
+        drop(mapped_file);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+        let registry = data_manager.data_catalogue.registry.read().unwrap();
+        assert_eq!(registry.get(&chunk.id).unwrap().status, ChunkStatus::Deleted);
+        assert!(!file_path.exists());
+    }
+
+    #[derive(Default)]
+    struct RecordingHooks {
+        calls: std::sync::Mutex<Vec<&'static str>>,
+    }
+
+    impl hooks::LifecycleHooks for RecordingHooks {
+        fn on_download_start(&self, _chunk: &DataChunk) {
+            self.calls.lock().unwrap().push("download_start");
+        }
+
+        fn on_download_complete(&self, _chunk: &DataChunk) {
+            self.calls.lock().unwrap().push("download_complete");
+        }
+
+        fn on_deprecate(&self, _chunk: &DataChunk, _grace: Duration) {
+            self.calls.lock().unwrap().push("deprecate");
+        }
+
+        fn on_delete(&self, _chunk: &DataChunk) {
+            self.calls.lock().unwrap().push("delete");
+        }
+
+        fn on_evict(&self, _chunk: &DataChunk) {
+            self.calls.lock().unwrap().push("evict");
+        }
+
+        fn on_register(&self, _chunk: &DataChunk) {
+            self.calls.lock().unwrap().push("register");
+        }
+
+        fn on_compaction(&self, _run: &compaction::CompactionRun) {
+            self.calls.lock().unwrap().push("compaction");
+        }
+
+        fn on_deadline_missed(&self, _chunk: &DataChunk, _within: Duration) {
+            self.calls.lock().unwrap().push("deadline_missed");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_lifecycle_hooks() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let hooks = std::sync::Arc::new(RecordingHooks::default());
+        let data_manager = DataManagerImpl::builder()
+            .data_dir(PathBuf::from(LOCAL_DATA_DIR))
+            .lifecycle_hooks(hooks.clone())
+            .build();
+        let chunk = get_test_chunk_111111_95_106();
+
+        // Act
+        data_manager.download_chunk(chunk.clone());
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+
+        // Assert
+        assert_eq!(*hooks.calls.lock().unwrap(), vec!["download_start", "download_complete", "delete"]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_held_chunk_is_deleted_after_grace() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let hooks = std::sync::Arc::new(RecordingHooks::default());
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(PathBuf::from(LOCAL_DATA_DIR)).with_deletion_grace(Duration::from_millis(300)))
+            .lifecycle_hooks(hooks.clone())
+            .build();
+        let chunk = get_test_chunk_111111_95_106();
+        data_manager.download_chunk(chunk.clone());
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+        let chunk_path = data_manager.find_chunk_path(chunk.dataset_id, 100).unwrap();
+        assert_eq!(chunk_path.deprecated_until(), None);
+
+        // Act
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(100));
+        });
+
+        // Assert the reader is notified and the files stay within the grace
+        assert!(chunk_path.deprecated_until().is_some());
+        assert!(data_manager.find_chunk_path(chunk.dataset_id, 100).is_none());
+        assert!(chunk_path.path.exists());
+        assert_eq!(*hooks.calls.lock().unwrap(), vec!["download_start", "download_complete", "deprecate"]);
+
+        // Assert deleted once the grace elapsed, although the reader still holds it
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(400));
+        });
+        assert_eq!(data_manager.get_chunk_info(chunk.id).unwrap().status, ChunkStatus::Deleted);
+        assert!(!chunk_path.path.exists());
+        assert_eq!(chunk_path.deprecated_until(), None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_epochs_must_be_configured() {
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        assert_eq!(data_manager.epoch_status([1u8; 32], 0), Err(EpochError::NotConfigured));
+    }
+
+    #[test]
+    #[serial]
+    fn test_download_epoch_needs_whole_epoch() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let config = DataManagerConfig::new(PathBuf::from(LOCAL_DATA_DIR)).with_epochs(EpochLayout::new(100));
+        let data_manager = DataManagerImpl::with_config(config);
+        let chunk_95_106 = get_test_chunk_111111_95_106();
+        let chunk_107_135 = get_test_chunk_111111_107_135();
+
+        // Act
+        let outside = data_manager.download_epoch(chunk_95_106.dataset_id, 0, vec![chunk_95_106.clone()]);
+        let incomplete = data_manager.download_epoch(chunk_107_135.dataset_id, 1, vec![chunk_107_135.clone()]);
+
+        // Assert
+        assert_eq!(outside, Err(EpochError::ChunkOutsideEpoch(chunk_95_106.id)));
+        assert_eq!(incomplete, Err(EpochError::Incomplete(vec![100..107, 135..200])));
+        let registry = data_manager.data_catalogue.registry.read().unwrap();
+        assert!(!registry.contains_key(&chunk_107_135.id));
+    }
+
+    #[test]
+    #[serial]
+    fn test_delete_epoch() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let config = DataManagerConfig::new(PathBuf::from(LOCAL_DATA_DIR)).with_epochs(EpochLayout::new(100));
+        let data_manager = DataManagerImpl::with_config(config);
+        let mut dataset_id = [0u8; 32];
+        dataset_id.copy_from_slice(&hex::decode("7b5ea4c3d6e7f8a9b0c1d2e3f4a5b6c7d8e9f0a1b2c3d4e5f6a7b8c9d0e1f2a3").unwrap());
+        let chunk_652_666 = DataCatalogue::generate_chunk_id(&dataset_id, &(652..666));
+        let chunk_667_669 = DataCatalogue::generate_chunk_id(&dataset_id, &(667..669));
+        let chunk_160_162 = DataCatalogue::generate_chunk_id(&dataset_id, &(160..162));
+
+        // Act
+        let mut deleted = data_manager.delete_epoch(dataset_id, 6).unwrap();
+        // the deletions save the catalogue once done, which must not happen after the fixture of the next test is loaded
+        // and the status is set before the save, so the tasks are waited for too
+        for chunk_id in deleted.iter() {
+            data_manager.data_catalogue.wait_until_settled_for(chunk_id, Duration::from_secs(5));
+            test_support::wait_until("deletion of the epoch is still running", || !data_manager.tasks_manager.is_active(chunk_id));
+        }
+
+        // Assert
+        deleted.sort();
+        let mut expected = vec![chunk_652_666, chunk_667_669];
+        expected.sort();
+        assert_eq!(deleted, expected);
+        assert!(data_manager.epoch_status(dataset_id, 6).unwrap().ready_chunks.is_empty());
+        assert_eq!(data_manager.epoch_status(dataset_id, 1).unwrap().ready_chunks, vec![chunk_160_162]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_enforce_retention_evicts_farthest_from_tip() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let dataset_id: DatasetId = core::array::from_fn(|i| i as u8);
+        let config = DataManagerConfig::new(PathBuf::from(LOCAL_DATA_DIR))
+            .with_retention(dataset_id, eviction::RetentionPolicy { max_chunks: 1, order: EvictionOrder::FarthestFromTip });
+        let hooks = std::sync::Arc::new(RecordingHooks { calls: std::sync::Mutex::new(Vec::new()) });
+        let data_manager = DataManagerImpl::builder().config(config).lifecycle_hooks(hooks.clone()).build();
+        let chunk_0_150 = DataCatalogue::generate_chunk_id(&dataset_id, &(0..150));
+        let chunk_151_260 = DataCatalogue::generate_chunk_id(&dataset_id, &(151..260));
+        let chunk_261_395 = DataCatalogue::generate_chunk_id(&dataset_id, &(261..395));
+        assert_eq!(data_manager.eviction_order(dataset_id), vec![chunk_0_150, chunk_151_260, chunk_261_395]);
+
+        // Act
+        let evicted = data_manager.enforce_retention();
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+
+        // Assert
+        assert_eq!(evicted, vec![chunk_0_150, chunk_151_260]);
+        assert_eq!(data_manager.eviction_order(dataset_id), vec![chunk_261_395]);
+        assert_eq!(*hooks.calls.lock().unwrap(), vec!["evict", "evict"]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_queried_chunks_are_evicted_last() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let dataset_id: DatasetId = core::array::from_fn(|i| i as u8);
+        let config = DataManagerConfig::new(PathBuf::from(LOCAL_DATA_DIR))
+            .with_retention(dataset_id, eviction::RetentionPolicy { max_chunks: 3, order: EvictionOrder::LeastRecentlyQueried });
+        let data_manager = DataManagerImpl::with_config(config);
+
+        // Act
+        data_manager.find_chunk(dataset_id, 10);
+
+        // Assert
+        assert_eq!(data_manager.eviction_order(dataset_id), vec![
+            DataCatalogue::generate_chunk_id(&dataset_id, &(151..260)),
+            DataCatalogue::generate_chunk_id(&dataset_id, &(261..395)),
+            DataCatalogue::generate_chunk_id(&dataset_id, &(0..150)),
+        ]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_holdings() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+
+        // Act
+        let holdings = holdings::Holdings::decode(&data_manager.holdings().encode()).unwrap();
+
+        // Assert
+        let mut chunk_ids = holdings.chunk_ids();
+        chunk_ids.sort();
+        let mut expected = data_manager.list_chunks();
+        expected.sort();
+        assert_eq!(chunk_ids, expected);
+    }
+
+    struct CorrelationRecordingHooks {
+        correlation_ids: std::sync::Mutex<Vec<Option<correlation::CorrelationId>>>,
+    }
+
+    impl hooks::LifecycleHooks for CorrelationRecordingHooks {
+        fn on_download_complete(&self, _chunk: &DataChunk) {
+            self.correlation_ids.lock().unwrap().push(correlation::current());
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_correlation_id_is_propagated_to_background_tasks() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let hooks = std::sync::Arc::new(CorrelationRecordingHooks { correlation_ids: std::sync::Mutex::new(Vec::new()) });
+        let data_manager = DataManagerImpl::builder()
+            .data_dir(PathBuf::from(LOCAL_DATA_DIR))
+            .lifecycle_hooks(hooks.clone())
+            .async_lifecycle_hooks(hooks.clone())
+            .build();
+        let chunk = get_test_chunk_111111_95_106();
+        let correlation_id = correlation::CorrelationId::new("request-42");
+
+        // Act
+        correlation::scope(Some(correlation_id.clone()), || data_manager.download_chunk(chunk.clone()));
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+
+        // Assert
+        assert_eq!(*hooks.correlation_ids.lock().unwrap(), vec![Some(correlation_id.clone()), Some(correlation_id.clone())]);
+        {
+            let registry = data_manager.data_catalogue.registry.read().unwrap();
+            assert_eq!(registry.get(&chunk.id).unwrap().correlation_id, Some(correlation_id));
+        }
+
+        // cleanup
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+    }
+
+    #[test]
+    #[serial]
+    fn test_slo_report() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let chunk = get_test_chunk_111111_95_106();
+
+        // Act
+        data_manager.find_chunk(chunk.dataset_id, 10);
+        data_manager.download_chunk(chunk.clone());
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+
+        // Assert
+        let report = data_manager.slo_report();
+        assert_eq!(report.get(Slo::FindChunk).unwrap().total, 1);
+        let chunk_ready = report.get(Slo::ChunkReady).unwrap();
+        assert_eq!((chunk_ready.total, chunk_ready.breaches), (1, 0));
+
+        // cleanup
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+    }
+
+    struct RecordingTransformer {
+        fail: bool,
+        transformed: std::sync::Mutex<Vec<(ChunkId, bool)>>,
+    }
+
+    impl ChunkTransformer for RecordingTransformer {
+        fn transform(&self, chunk: &DataChunk, chunk_dir: &std::path::Path) -> io::Result<()> {
+            self.transformed.lock().unwrap().push((chunk.id, chunk_dir.join("part-1.parquet").exists()));
+            if self.fail {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "column can't be redacted"));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_chunk_is_transformed_before_ready() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let transformer = Arc::new(RecordingTransformer { fail: false, transformed: std::sync::Mutex::new(Vec::new()) });
+        let data_manager = DataManagerImpl::builder()
+            .data_dir(PathBuf::from(LOCAL_DATA_DIR))
+            .chunk_transformer(transformer.clone())
+            .build();
+        let chunk = get_test_chunk_111111_95_106();
+
+        // Act
+        data_manager.download_chunk(chunk.clone());
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+
+        // Assert
+        assert_eq!(*transformer.transformed.lock().unwrap(), vec![(chunk.id, true)]);
+        {
+            let registry = data_manager.data_catalogue.registry.read().unwrap();
+            assert_eq!(registry.get(&chunk.id).unwrap().status, ChunkStatus::Ready);
+        }
+
+        // cleanup
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+    }
+
+    #[test]
+    #[serial]
+    fn test_failed_transform_fails_download() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let transformer = Arc::new(RecordingTransformer { fail: true, transformed: std::sync::Mutex::new(Vec::new()) });
+        let data_manager = DataManagerImpl::builder()
+            .data_dir(PathBuf::from(LOCAL_DATA_DIR))
+            .chunk_transformer(transformer.clone())
+            .build();
+        let chunk = get_test_chunk_111111_95_106();
+
+        // Act
+        data_manager.download_chunk(chunk.clone());
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+
+        // Assert
+        {
+            let registry = data_manager.data_catalogue.registry.read().unwrap();
+            assert_eq!(registry.get(&chunk.id).unwrap().status, ChunkStatus::Failed);
+        }
+        let errors = data_manager.get_chunk_info(chunk.id).unwrap().errors;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, chunk_errors::ChunkErrorKind::Download);
+        // the files which failed the transform never left the staging directory, which was removed
+        assert!(!data_manager.data_source.chunk_path(chunk).path.exists());
+        assert_eq!(data_manager.data_source.get_local_chunk_ids().len(), 8);
+    }
+
+    #[cfg(feature = "dataframes")]
+    #[test]
+    #[serial]
+    fn test_optimized_chunk_is_recorded() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::builder()
+            .data_dir(PathBuf::from(LOCAL_DATA_DIR))
+            .chunk_transformer(Arc::new(transform::ParquetOptimizer::default()))
+            .build();
+        let chunk = get_test_chunk_111111_95_106();
+
+        // Act
+        data_manager.download_chunk(chunk.clone());
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+
+        // Assert
+        {
+            let registry = data_manager.data_catalogue.registry.read().unwrap();
+            let info = registry.get(&chunk.id).unwrap();
+            assert_eq!(info.status, ChunkStatus::Ready);
+            assert!(info.optimized);
+        }
+
+        // cleanup
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+    }
+
+    #[cfg(feature = "dataframes")]
+    #[test]
+    #[serial]
+    fn test_scan_blocks_is_cached_until_chunk_changes() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_dir = test_support::temp_dir("scan");
+        let chunk_dir = data_dir.join(format!("dataset_id={}/block_range=0_10", hex::encode([1u8; 32])));
+        std::fs::create_dir_all(&chunk_dir).unwrap();
+        let mut blocks = polars::df!(
+            "block_number" => (0..10u64).collect::<Vec<u64>>(),
+            "hash" => (0..10u64).map(|block_number| format!("0x{:x}", block_number)).collect::<Vec<String>>()
+        ).unwrap();
+        polars::prelude::ParquetWriter::new(std::fs::File::create(chunk_dir.join("blocks.parquet")).unwrap()).finish(&mut blocks).unwrap();
+        let config = DataManagerConfig::new(data_dir.clone()).with_query_cache(query_cache::QueryCacheConfig::default());
+        let data_manager = DataManagerImpl::with_config(config);
+        let query_cache = data_manager.query_cache.clone().unwrap();
+
+        // Act
+        let result = data_manager.scan_blocks([1u8; 32], "blocks.parquet", 2..5, &["hash"]).unwrap();
+        let cached = data_manager.scan_blocks([1u8; 32], "blocks.parquet", 2..5, &["hash"]).unwrap();
+
+        // Assert
+        let hashes: Vec<&str> = result.column("hash").unwrap().str().unwrap().into_no_null_iter().collect();
+        assert_eq!(hashes, vec!["0x2", "0x3", "0x4"]);
+        assert_eq!(cached, result);
+        assert_eq!(query_cache.stats().hits, 1);
+        let chunk = data_manager.data_catalogue.find_chunk(&[1u8; 32], 3).unwrap();
+        data_manager.data_catalogue.update_chunk(&chunk, &ChunkStatus::Deleting);
+        assert!(query_cache.is_empty());
+        assert_eq!(data_manager.scan_blocks([1u8; 32], "blocks.parquet", 2..5, &["hash"]).unwrap().height(), 0);
+
+        // cleanup
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_coverage_json() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let dataset_id = get_test_chunk_111111_0_35().dataset_id;
+
+        // Act
+        let coverage: serde_json::Value = serde_json::from_str(&data_manager.coverage_json(dataset_id)).unwrap();
+
+        // Assert
+        assert_eq!(coverage["start"], 0);
+        assert_eq!(coverage["end"], 94);
+        assert_eq!(coverage["segments"], serde_json::json!([
+            {"start": 0, "end": 35, "state": "Ready"},
+            {"start": 35, "end": 36, "state": "Missing"},
+            {"start": 36, "end": 94, "state": "Ready"},
+        ]));
+        assert_eq!(data_manager.coverage_svg(dataset_id).matches("<rect").count(), 3);
+    }
+
+    #[test]
+    #[serial]
+    fn test_find_chunk_in_secondary_catalogue() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let archive_dir = test_support::temp_dir("secondary");
+        std::fs::create_dir_all(archive_dir.join(format!("dataset_id={}/block_range=0_10", hex::encode([1u8; 32])))).unwrap();
+        let data_manager = DataManagerImpl::builder()
+            .data_dir(PathBuf::from(LOCAL_DATA_DIR))
+            .secondary_catalogue(Arc::new(federation::DirectoryCatalogue::open("archive", archive_dir.clone())))
+            .build();
+        let local_chunk = get_test_chunk_111111_0_35();
+
+        // Act
+        let secondary = data_manager.find_chunk_path([1u8; 32], 5).unwrap();
+        let local = data_manager.find_chunk_path(local_chunk.dataset_id, 5).unwrap();
+
+        // Assert
+        assert_eq!(secondary.source, data_chunk::ChunkSource::Secondary("archive".to_string()));
+        assert!(secondary.path.starts_with(&archive_dir));
+        assert!(local.is_local());
+        assert!(data_manager.find_chunk_path([1u8; 32], 10).is_none());
+
+        // cleanup
+        std::fs::remove_dir_all(archive_dir).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_download_chunk_with_sharded_layout() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_dir = test_support::temp_dir("sharded");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let data_manager = DataManagerImpl::builder()
+            .data_dir(data_dir.clone())
+            .layout(planning::DirectoryLayout::ShardedBase32)
+            .build();
+        let chunk = get_test_chunk_111111_95_106();
+
+        // Act
+        data_manager.download_chunk(chunk.clone());
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+
+        // Assert
+        let chunk_path = data_manager.find_chunk_path(chunk.dataset_id, 100).unwrap();
+        let expected_dir = data_dir.join(planning::DirectoryLayout::ShardedBase32.chunk_dir(&chunk.dataset_id, &chunk.block_range));
+        assert_eq!(chunk_path.path, PathBuf::from(format!("{}/", expected_dir.display())));
+        assert!(expected_dir.join("part-1.parquet").exists());
+        let rescanned = local_data_source::LocalDataSource::new(data_dir.clone())
+            .with_layout(planning::DirectoryLayout::ShardedBase32)
+            .get_local_chunk_ids();
+        assert_eq!(rescanned, vec![chunk.id]);
+
+        // cleanup
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_register_chunk() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_dir = test_support::temp_dir("register");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let hooks = std::sync::Arc::new(RecordingHooks::default());
+        let data_manager = DataManagerImpl::builder().data_dir(data_dir.clone()).lifecycle_hooks(hooks.clone()).build();
+        let chunk = test_support::chunk(1, 0..10);
+        let chunk_dir = data_manager.data_source.chunk_path(chunk.clone()).path;
+
+        // Act
+        let before_placed = data_manager.register_chunk(chunk.clone(), false);
+        std::fs::create_dir_all(&chunk_dir).unwrap();
+        let not_verified = data_manager.register_chunk(chunk.clone(), true);
+        std::fs::write(chunk_dir.join("blocks.parquet"), []).unwrap();
+        let wrong_size = data_manager.register_chunk(DataChunk { size: Some(6), ..chunk.clone() }, true);
+        data_manager.checksums.expect(chunk.id, FileChecksums::from([("blocks.parquet".to_string(), sha256::digest(b"blocks".to_vec()))]));
+        let wrong_checksum = data_manager.register_chunk(chunk.clone(), true);
+        data_manager.checksums.remove(&chunk.id);
+        let registered = data_manager.register_chunk(chunk.clone(), true);
+        let again = data_manager.register_chunk(chunk.clone(), true);
+
+        // Assert
+        assert_eq!(before_placed, Err(registration::RegisterError::MissingDirectory(chunk_dir.clone())));
+        assert_eq!(not_verified, Err(registration::RegisterError::MissingFiles(vec!["blocks.parquet".to_string()])));
+        assert!(matches!(wrong_size, Err(registration::RegisterError::Verification(_))));
+        assert!(matches!(wrong_checksum, Err(registration::RegisterError::Verification(message)) if message.contains("blocks.parquet")));
+        assert_eq!(registered, Ok(()));
+        assert_eq!(again, Err(registration::RegisterError::AlreadyRegistered(ChunkStatus::Ready)));
+        assert_eq!(data_manager.find_chunk_path([1u8; 32], 5).map(|chunk_path| chunk_path.chunk), Some(chunk));
+        assert_eq!(*hooks.calls.lock().unwrap(), vec!["register"]);
+
+        // cleanup
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_forget_chunk_keeps_files() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let chunk = get_test_chunk_111111_0_35();
+        let chunk_dir = data_manager.data_source.chunk_path(chunk.clone()).path;
+
+        // Act
+        let forgotten = data_manager.forget_chunk(chunk.id);
+        let forgotten_again = data_manager.forget_chunk(chunk.id);
+
+        // Assert
+        assert_eq!(forgotten.map(|chunk| chunk.id), Ok(chunk.id));
+        assert_eq!(forgotten_again, Err(registration::ForgetError::UnknownChunk(chunk.id)));
+        assert!(data_manager.find_chunk(chunk.dataset_id, 10).is_none());
+        assert!(!data_manager.list_chunks().contains(&chunk.id));
+        assert!(chunk_dir.join("part-1.parquet").exists());
+    }
+
+    #[test]
+    #[serial]
+    fn test_forget_chunk_being_downloaded() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let chunk = get_test_chunk_111111_95_106();
+        data_manager.download_chunk(chunk.clone());
+
+        // Act
+        let result = data_manager.forget_chunk(chunk.id);
+
+        // Assert
+        assert_eq!(result, Err(registration::ForgetError::Busy(ChunkStatus::Downloading)));
+
+        // cleanup
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+    }
+
+    #[test]
+    #[serial]
+    fn test_catalogue_is_compacted_past_threshold() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let config = DataManagerConfig::new(PathBuf::from(LOCAL_DATA_DIR))
+            .with_compaction(Some(compaction::CompactionConfig { max_dead_ratio: 0.25, min_rows: 8 }));
+        let hooks = std::sync::Arc::new(RecordingHooks::default());
+        let data_manager = DataManagerImpl::builder().config(config).lifecycle_hooks(hooks.clone()).build();
+        let dataset_id: DatasetId = core::array::from_fn(|i| i as u8);
+        let chunk_ids = [0..150, 151..260, 261..395].map(|block_range| DataCatalogue::generate_chunk_id(&dataset_id, &block_range));
+
+        // Act
+        for chunk_id in chunk_ids.iter().take(2) {
+            data_manager.delete_chunk(*chunk_id);
+        }
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+        let below_threshold = data_manager.catalogue_stats();
+        data_manager.delete_chunk(chunk_ids[2]);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+
+        // Assert
+        assert_eq!(below_threshold, compaction::CatalogueStats { live_rows: 6, dead_rows: 2 });
+        assert_eq!(data_manager.catalogue_stats(), compaction::CatalogueStats { live_rows: 5, dead_rows: 0 });
+        let stats = data_manager.compaction_stats();
+        assert_eq!((stats.runs, stats.removed_rows), (1, 3));
+        assert_eq!(stats.last_run.map(|run| run.remaining_rows), Some(5));
+        assert!(hooks.calls.lock().unwrap().contains(&"compaction"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_compact_catalogue_on_request() {
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let chunk = get_test_chunk_111111_0_35();
+        data_manager.data_catalogue.update_chunk(&chunk, &ChunkStatus::Deleted);
+
+        let run = data_manager.compact_catalogue();
+
+        assert_eq!((run.removed_rows, run.remaining_rows), (1, 7));
+        assert_eq!(data_manager.compaction_stats().runs, 1);
+    }
+
+    #[test]
+    #[serial]
+    fn test_frozen_dataset_is_left_alone() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let dataset_id: DatasetId = core::array::from_fn(|i| i as u8);
+        let config = DataManagerConfig::new(PathBuf::from(LOCAL_DATA_DIR))
+            .with_retention(dataset_id, eviction::RetentionPolicy { max_chunks: 1, order: EvictionOrder::FarthestFromTip })
+            .with_retention([17u8; 32], eviction::RetentionPolicy { max_chunks: 1, order: EvictionOrder::FarthestFromTip });
+        let data_manager = DataManagerImpl::with_config(config);
+        let chunk = get_test_chunk_111111_95_106();
+        let local_chunk = get_test_chunk_111111_0_35();
+
+        // Act
+        assert!(data_manager.freeze_dataset(chunk.dataset_id));
+        assert!(!data_manager.freeze_dataset(chunk.dataset_id));
+        data_manager.download_chunk(chunk.clone());
+        data_manager.delete_chunk(local_chunk.id);
+        let evicted = data_manager.enforce_retention();
+
+        // Assert
+        assert_eq!(data_manager.frozen_datasets(), vec![chunk.dataset_id]);
+        assert!(data_manager.data_catalogue.get_chunk_by_id(&chunk.id).is_none());
+        assert!(data_manager.find_chunk(local_chunk.dataset_id, 10).is_some());
+        // only the other dataset is evicted
+        assert_eq!(evicted.len(), 2);
+        assert!(evicted.iter().all(|chunk_id| data_manager.data_catalogue.get_chunk_by_id(chunk_id).unwrap().dataset_id == dataset_id));
+        assert!(data_manager.unfreeze_dataset(chunk.dataset_id));
+        data_manager.delete_chunk(local_chunk.id);
+        assert!(data_manager.find_chunk(local_chunk.dataset_id, 10).is_none());
+
+        // cleanup
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+    }
+
+    #[test]
+    #[serial]
+    fn test_chunks_under_legal_hold_are_kept() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let dir = test_support::temp_dir("legal_hold");
+        std::fs::create_dir_all(&dir).unwrap();
+        let dataset_id: DatasetId = core::array::from_fn(|i| i as u8);
+        let config = DataManagerConfig::new(PathBuf::from(LOCAL_DATA_DIR))
+            .with_catalogue_file(dir.join("registry.parquet"))
+            .with_retention(dataset_id, eviction::RetentionPolicy { max_chunks: 1, order: EvictionOrder::FarthestFromTip });
+        let data_manager = DataManagerImpl::with_config(config.clone());
+        let local_chunk = get_test_chunk_111111_0_35();
+        let chunk_0_150 = DataCatalogue::generate_chunk_id(&dataset_id, &(0..150));
+        let chunk_151_260 = DataCatalogue::generate_chunk_id(&dataset_id, &(151..260));
+
+        // Act
+        assert!(data_manager.place_legal_hold(LegalHold::Dataset(local_chunk.dataset_id)).unwrap());
+        assert!(data_manager.place_legal_hold(LegalHold::Chunk(chunk_0_150)).unwrap());
+        data_manager.delete_chunk(local_chunk.id);
+        let forgotten = data_manager.forget_chunk(local_chunk.id);
+        let evicted = data_manager.enforce_retention();
+        let restarted = DataManagerImpl::with_config(config);
+
+        // Assert
+        assert!(data_manager.find_chunk(local_chunk.dataset_id, 10).is_some());
+        assert_eq!(forgotten, Err(registration::ForgetError::LegalHold));
+        // the held chunk counts against the limit, so both other chunks are evicted
+        assert_eq!(evicted.len(), 2);
+        assert!(!evicted.contains(&chunk_0_150));
+        assert!(evicted.contains(&chunk_151_260));
+        assert_eq!(restarted.legal_holds(), vec![LegalHold::Dataset(local_chunk.dataset_id), LegalHold::Chunk(chunk_0_150)]);
+        assert!(data_manager.lift_legal_hold(LegalHold::Dataset(local_chunk.dataset_id)).unwrap());
+        assert!(!data_manager.lift_legal_hold(LegalHold::Dataset(local_chunk.dataset_id)).unwrap());
+        data_manager.delete_chunk(local_chunk.id);
+        assert!(data_manager.find_chunk(local_chunk.dataset_id, 10).is_none());
+
+        // cleanup
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn overlapping_chunk_111111(block_range: Range<u64>) -> DataChunk {
+        DataChunk::new([17u8; 32], block_range)
+    }
+
+    fn overlap_data_manager(policy: overlap::OverlapPolicy) -> DataManagerImpl {
+        let config = DataManagerConfig::new(PathBuf::from(LOCAL_DATA_DIR)).with_overlap_policy(policy);
+        DataManagerImpl::builder().config(config).build()
+    }
+
+    #[test]
+    #[serial]
+    fn test_overlapping_download_is_rejected() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = overlap_data_manager(overlap::OverlapPolicy::Reject);
+        let chunk = get_test_chunk_111111_95_106();
+        let overlapping_chunk = overlapping_chunk_111111(100..110);
+        data_manager.download_chunk(chunk.clone());
+
+        // Act
+        data_manager.download_chunk(overlapping_chunk.clone());
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+
+        // Assert
+        assert_eq!(data_manager.get_chunk_info(chunk.id).unwrap().status, ChunkStatus::Ready);
+        assert!(data_manager.get_chunk_info(overlapping_chunk.id).is_none());
+
+        // cleanup
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+    }
+
+    #[test]
+    #[serial]
+    fn test_overlapping_download_queues_behind() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = overlap_data_manager(overlap::OverlapPolicy::QueueBehind);
+        let chunk = get_test_chunk_111111_95_106();
+        let overlapping_chunk = overlapping_chunk_111111(100..110);
+        data_manager.download_chunk(chunk.clone());
+
+        // Act
+        data_manager.download_chunk(overlapping_chunk.clone());
+
+        // Assert
+        assert!(data_manager.get_chunk_info(overlapping_chunk.id).is_none());
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(400));
+        });
+        assert_eq!(data_manager.get_chunk_info(chunk.id).unwrap().status, ChunkStatus::Ready);
+        assert_eq!(data_manager.get_chunk_info(overlapping_chunk.id).unwrap().status, ChunkStatus::Ready);
+
+        // cleanup
+        data_manager.forget_chunk(overlapping_chunk.id).unwrap();
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+    }
+
+    #[test]
+    #[serial]
+    fn test_larger_overlapping_download_replaces_in_flight_chunk() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = overlap_data_manager(overlap::OverlapPolicy::PreferLarger);
+        let chunk = get_test_chunk_111111_95_106();
+        let smaller_chunk = overlapping_chunk_111111(100..105);
+        let larger_chunk = overlapping_chunk_111111(90..120);
+        data_manager.download_chunk(chunk.clone());
+
+        // Act
+        data_manager.download_chunk(smaller_chunk.clone());
+        data_manager.download_chunk(larger_chunk.clone());
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(400));
+        });
+
+        // Assert
+        assert!(data_manager.get_chunk_info(smaller_chunk.id).is_none());
+        assert_eq!(data_manager.get_chunk_info(larger_chunk.id).unwrap().status, ChunkStatus::Ready);
+        assert_eq!(data_manager.get_chunk_info(chunk.id).unwrap().status, ChunkStatus::Deleted);
+        assert!(!data_manager.data_source.chunk_path(chunk).path.exists());
+
+        // cleanup
+        data_manager.forget_chunk(larger_chunk.id).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_storage_stats_follow_downloads_and_deletions() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let chunk = get_test_chunk_111111_95_106();
+        let remote_dir = PathBuf::from("./remote_data_dir/dataset_id=1111111111111111111111111111111111111111111111111111111111111111/block_range=95_106");
+        let chunk_bytes = storage::chunk_size(&remote_dir).unwrap();
+
+        // Act
+        data_manager.download_chunk(chunk.clone());
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+        let downloaded = data_manager.storage_stats();
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+        let deleted = data_manager.storage_stats();
+
+        // Assert
+        assert_eq!((downloaded.measured_chunks, downloaded.total_bytes), (1, chunk_bytes));
+        assert_eq!(downloaded.unmeasured_chunks, 8);
+        assert_eq!((deleted.measured_chunks, deleted.total_bytes), (0, 0));
+    }
+
+    #[cfg(feature = "dataframes")]
+    struct StaticOrigin(Vec<u8>);
+
+    #[cfg(feature = "dataframes")]
+    impl origin::OriginFetcher for StaticOrigin {
+        fn fetch(&self, _url: &str) -> io::Result<Vec<u8>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[cfg(feature = "dataframes")]
+    #[test]
+    #[serial]
+    fn test_corrupt_file_is_read_from_origin() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_dir = test_support::temp_dir("origin_fallback");
+        let chunk_dir = data_dir.join(format!("dataset_id={}/block_range=0_10", hex::encode([1u8; 32])));
+        std::fs::create_dir_all(&chunk_dir).unwrap();
+        let mut blocks = polars::df!(
+            "block_number" => (0..10u64).collect::<Vec<u64>>(),
+            "hash" => (0..10u64).map(|block_number| format!("0x{:x}", block_number)).collect::<Vec<String>>()
+        ).unwrap();
+        polars::prelude::ParquetWriter::new(std::fs::File::create(chunk_dir.join("blocks.parquet")).unwrap()).finish(&mut blocks).unwrap();
+        let origin_bytes = std::fs::read(chunk_dir.join("blocks.parquet")).unwrap();
+        std::fs::write(chunk_dir.join("blocks.parquet"), &origin_bytes[..origin_bytes.len() / 2]).unwrap();
+        let data_manager = DataManagerImpl::builder()
+            .data_dir(data_dir.clone())
+            .origin_fallback(Arc::new(StaticOrigin(origin_bytes.clone())))
+            .build();
+        let chunk = data_manager.data_catalogue.find_chunk(&[1u8; 32], 3).unwrap();
+
+        // Act
+        let result = data_manager.scan_blocks([1u8; 32], "blocks.parquet", 2..5, &["hash"]).unwrap();
+        let status_during_repair = data_manager.get_chunk_info(chunk.id).unwrap().status;
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+        let mapped_file = data_manager.mmap_chunk_file(chunk.id, "blocks.parquet").unwrap();
+
+        // Assert
+        let hashes: Vec<&str> = result.column("hash").unwrap().str().unwrap().into_no_null_iter().collect();
+        assert_eq!(hashes, vec!["0x2", "0x3", "0x4"]);
+        assert_eq!(status_during_repair, ChunkStatus::Ready);
+        assert!(mapped_file.is_fetched());
+        assert_eq!(&mapped_file[..], &origin_bytes[..]);
+        let errors = data_manager.get_chunk_info(chunk.id).unwrap().errors;
+        assert_eq!(errors[0].kind, chunk_errors::ChunkErrorKind::Verification);
+
+        // cleanup
+        drop(mapped_file);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_missed_deadline_is_reported() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let hooks = std::sync::Arc::new(RecordingHooks::default());
+        let data_manager = DataManagerImpl::builder()
+            .data_dir(PathBuf::from(LOCAL_DATA_DIR))
+            .lifecycle_hooks(hooks.clone())
+            .build();
+        let chunk = get_test_chunk_111111_95_106();
+
+        // Act
+        data_manager.download_chunk_with_deadline(get_test_chunk_111111_0_35(), Duration::from_millis(10));
+        data_manager.download_chunk_with_deadline(chunk.clone(), Duration::from_millis(10));
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+
+        // Assert
+        assert_eq!(*hooks.calls.lock().unwrap(), vec!["download_start", "deadline_missed", "download_complete"]);
+
+        // cleanup
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(std::time::Duration::from_millis(200));
+        });
+    }
+
+    #[test]
+    #[serial]
+    fn test_find_chunk_by_time() {
+        // Arrange
+        let catalogue_dir = test_support::temp_dir("block_times");
+        std::fs::create_dir_all(&catalogue_dir).unwrap();
+        let config = DataManagerConfig::new(PathBuf::from(LOCAL_DATA_DIR)).with_catalogue_file(catalogue_dir.join("registry.parquet"));
+        let data_manager = DataManagerImpl::with_config(config.clone());
+        let chunk = get_test_chunk_111111_0_35();
+        data_manager.record_block_times(chunk.dataset_id, &[BlockTime::new(0, 1_700_000_000), BlockTime::new(36, 1_700_000_432)]).unwrap();
+
+        // Act
+        let found = data_manager.find_chunk_by_time(chunk.dataset_id, 1_700_000_100).map(|chunk_path| chunk_path.chunk.id);
+        let found_later = data_manager.find_chunk_by_time(chunk.dataset_id, 1_800_000_000).map(|chunk_path| chunk_path.chunk.id);
+        let found_before = data_manager.find_chunk_by_time(chunk.dataset_id, 1_600_000_000);
+        let restarted = DataManagerImpl::with_config(config);
+
+        // Assert
+        assert_eq!(found, Some(chunk.id));
+        assert_eq!(found_later, data_manager.find_chunk_path(chunk.dataset_id, 36).map(|chunk_path| chunk_path.chunk.id));
+        assert!(found_later.is_some());
+        assert!(found_before.is_none());
+        assert_eq!(restarted.find_chunk_by_time(chunk.dataset_id, 1_700_000_100).map(|chunk_path| chunk_path.chunk.id), Some(chunk.id));
+
+        // cleanup
+        std::fs::remove_dir_all(catalogue_dir).unwrap();
+    }
+
+    #[cfg(feature = "dataframes")]
+    #[test]
+    #[serial]
+    fn test_stream_rows_of_missing_chunk() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let chunk = get_test_chunk_111111_0_35();
+
+        // Act
+        let missing_chunk = data_manager.stream_chunk_rows(get_test_chunk_111111_95_106().id, "blocks.parquet", &[], None).err().unwrap();
+        let missing_file = data_manager.stream_chunk_rows(chunk.id, "missing.parquet", &[], None).err().unwrap();
+
+        // Assert
+        assert_eq!(missing_chunk.kind(), io::ErrorKind::NotFound);
+        assert_eq!(missing_file.kind(), io::ErrorKind::NotFound);
+        assert_eq!(data_manager.data_catalogue.pins.pin_count(&chunk.id), 0);
+    }
+}
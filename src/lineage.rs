@@ -0,0 +1,207 @@
+use std::fmt;
+use std::ops::Range;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde_json::json;
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
+
+/// How a chunk was derived from the chunks it replaced
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineageKind {
+    /// A single chunk was replaced, e.g. by a chunk of a newer manifest with different blocks
+    Replaced,
+    /// Several chunks were replaced by one covering their blocks
+    Merged,
+    /// A larger chunk was replaced by chunks of some of its blocks
+    Split,
+    /// The chunk was moved to another dataset with `reassign_chunk`
+    Reassigned,
+}
+
+impl LineageKind {
+    /// Kind of the replacement of the parents by the chunk
+    pub(crate) fn of(chunk: &DataChunk, parents: &[DataChunk]) -> Self {
+        match parents {
+            [parent] if covers(&parent.block_range, &chunk.block_range) && parent.block_range != chunk.block_range => LineageKind::Split,
+            [_] => LineageKind::Replaced,
+            _ => LineageKind::Merged,
+        }
+    }
+}
+
+impl fmt::Display for LineageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+fn covers(outer: &Range<u64>, inner: &Range<u64>) -> bool {
+    outer.start <= inner.start && inner.end <= outer.end
+}
+
+/// Chunk a chunk was derived from, kept with its dataset and blocks as the chunk itself is eventually forgotten
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineageParent {
+    pub chunk_id: ChunkId,
+    pub dataset_id: DatasetId,
+    pub block_range: Range<u64>,
+}
+
+impl From<&DataChunk> for LineageParent {
+    fn from(chunk: &DataChunk) -> Self {
+        LineageParent { chunk_id: chunk.id, dataset_id: chunk.dataset_id, block_range: chunk.block_range.clone() }
+    }
+}
+
+/// A chunk derived from its parents, kept in the catalogue together with the chunk and persisted with it
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineageEvent {
+    pub kind: LineageKind,
+    /// The derived chunk
+    pub chunk_id: ChunkId,
+    pub parents: Vec<LineageParent>,
+    pub at: SystemTime,
+}
+
+/// Lineage of the chunk derived from the parents: the lineage of every parent, then the derivation itself.
+/// Ancestors shared by several parents are listed once.
+pub(crate) fn derive(kind: LineageKind, chunk: &DataChunk, parents: &[(&DataChunk, &[LineageEvent])]) -> Vec<LineageEvent> {
+    let mut lineage: Vec<LineageEvent> = Vec::new();
+    for event in parents.iter().flat_map(|(_, parent_lineage)| parent_lineage.iter()) {
+        if !lineage.contains(event) {
+            lineage.push(event.clone());
+        }
+    }
+    lineage.push(LineageEvent {
+        kind,
+        chunk_id: chunk.id,
+        parents: parents.iter().map(|(parent, _)| LineageParent::from(*parent)).collect(),
+        at: SystemTime::now(),
+    });
+    lineage
+}
+
+/// Lineage in the form persisted in the catalogue, timestamps are in milliseconds since the unix epoch
+pub(crate) fn lineage_to_json(lineage: &[LineageEvent]) -> String {
+    json!(lineage.iter().map(|event| json!({
+        "kind": event.kind.to_string(),
+        "chunk_id": hex::encode(event.chunk_id),
+        "parents": event.parents.iter().map(|parent| json!({
+            "chunk_id": hex::encode(parent.chunk_id),
+            "dataset_id": hex::encode(parent.dataset_id),
+            "block_from": parent.block_range.start,
+            "block_to": parent.block_range.end,
+        })).collect::<Vec<serde_json::Value>>(),
+        "at": event.at.duration_since(UNIX_EPOCH).map(|at| at.as_millis() as u64).unwrap_or_default(),
+    })).collect::<Vec<serde_json::Value>>()).to_string()
+}
+
+/// Events which can't be read are skipped
+pub(crate) fn lineage_from_json(lineage: &str) -> Vec<LineageEvent> {
+    let Ok(serde_json::Value::Array(events)) = serde_json::from_str(lineage) else { return Vec::new() };
+    let id = |value: &serde_json::Value| -> Option<[u8; 32]> { hex::decode(value.as_str()?).ok()?.try_into().ok() };
+    events.iter()
+        .filter_map(|event| {
+            let kind = match event.get("kind")?.as_str()? {
+                "Replaced" => LineageKind::Replaced,
+                "Merged" => LineageKind::Merged,
+                "Split" => LineageKind::Split,
+                "Reassigned" => LineageKind::Reassigned,
+                _ => return None,
+            };
+            let parents = event.get("parents")?.as_array()?.iter()
+                .map(|parent| Some(LineageParent {
+                    chunk_id: id(parent.get("chunk_id")?)?,
+                    dataset_id: id(parent.get("dataset_id")?)?,
+                    block_range: parent.get("block_from")?.as_u64()?..parent.get("block_to")?.as_u64()?,
+                }))
+                .collect::<Option<Vec<LineageParent>>>()?;
+            Some(LineageEvent {
+                kind,
+                chunk_id: id(event.get("chunk_id")?)?,
+                parents,
+                at: UNIX_EPOCH + Duration::from_millis(event.get("at")?.as_u64()?),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread;
+    use crate::test_support::{self, chunk};
+    use super::*;
+
+    #[test]
+    fn test_lineage_kind() {
+        assert_eq!(LineageKind::of(&chunk(1, 0..20), &[chunk(1, 0..10), chunk(1, 10..20)]), LineageKind::Merged);
+        assert_eq!(LineageKind::of(&chunk(1, 0..10), &[chunk(1, 0..20)]), LineageKind::Split);
+        assert_eq!(LineageKind::of(&chunk(1, 0..20), &[chunk(1, 5..10)]), LineageKind::Replaced);
+        assert_eq!(LineageKind::of(&chunk(2, 0..10), &[chunk(1, 0..10)]), LineageKind::Replaced);
+    }
+
+    #[test]
+    fn test_lineage_is_inherited_and_persisted() {
+        // Arrange
+        let (left, right, merged) = (chunk(1, 0..10), chunk(1, 10..20), chunk(1, 0..20));
+        let left_lineage = derive(LineageKind::Split, &left, &[(&chunk(1, 0..15), &[])]);
+
+        // Act
+        let lineage = derive(LineageKind::Merged, &merged, &[(&left, &left_lineage), (&right, &left_lineage)]);
+
+        // Assert
+        assert_eq!(lineage.len(), 2);
+        assert_eq!(lineage[0], left_lineage[0]);
+        assert_eq!(lineage[1].kind, LineageKind::Merged);
+        assert_eq!(lineage[1].chunk_id, merged.id);
+        assert_eq!(lineage[1].parents, vec![LineageParent::from(&left), LineageParent::from(&right)]);
+        let truncated: Vec<LineageEvent> = lineage.iter()
+            .map(|event| LineageEvent { at: UNIX_EPOCH + Duration::from_millis(event.at.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64), ..event.clone() })
+            .collect();
+        assert_eq!(lineage_from_json(&lineage_to_json(&lineage)), truncated);
+        assert_eq!(lineage_from_json(""), Vec::new());
+    }
+
+    #[test]
+    fn test_merged_and_reassigned_chunk_keeps_its_lineage() {
+        // Arrange
+        let dir = test_support::temp_dir("lineage");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let data_manager = test_support::manager_builder(&dir).build();
+        let (left, right, merged) = (chunk(9, 0..10), chunk(9, 10..20), chunk(9, 0..20));
+        data_manager.ensure_chunks([9u8; 32], &[left.clone(), right.clone()]);
+        data_manager.data_catalogue.wait_until_downloaded(&[left.id, right.id]);
+
+        // Act
+        data_manager.ensure_chunks([9u8; 32], std::slice::from_ref(&merged));
+        data_manager.data_catalogue.wait_until_downloaded(&[merged.id]);
+        let merged_lineage = data_manager.chunk_lineage(merged.id).unwrap();
+        // the replaced chunks are deleted in background
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(300));
+        });
+        let reassigned = data_manager.reassign_chunk(merged.id, [10u8; 32]).unwrap();
+        drop(data_manager);
+        let restarted = test_support::manager_builder(&dir).build();
+        let reassigned_lineage = restarted.chunk_lineage(reassigned.id).unwrap();
+
+        // Assert
+        assert_eq!(merged_lineage.len(), 1);
+        assert_eq!(merged_lineage[0].kind, LineageKind::Merged);
+        assert_eq!(merged_lineage[0].chunk_id, merged.id);
+        let mut parents = merged_lineage[0].parents.clone();
+        parents.sort_by_key(|parent| parent.block_range.start);
+        assert_eq!(parents, vec![LineageParent::from(&left), LineageParent::from(&right)]);
+        assert_eq!(restarted.get_chunk_info(left.id).map(|info| info.status), None);
+        assert_eq!(restarted.chunk_lineage(left.id), Some(Vec::new()));
+        assert_eq!(reassigned_lineage.iter().map(|event| event.kind).collect::<Vec<_>>(), vec![LineageKind::Merged, LineageKind::Reassigned]);
+        assert_eq!(reassigned_lineage[1].parents, vec![LineageParent::from(&merged)]);
+        let description = restarted.describe_chunk_id(reassigned.id).unwrap().to_string();
+        assert!(description.contains(&format!("lineage  {} Merged", hex::encode(merged.id))));
+        assert!(description.contains(&format!("from {} blocks 0..10", hex::encode(left.id))));
+
+        // cleanup
+        drop(restarted);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
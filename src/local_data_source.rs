@@ -1,90 +1,462 @@
-use crate::data_chunk::{ChunkId, DataChunk};
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::{fs, thread};
+use std::time::Instant;
 use std::collections::HashMap;
+#[cfg(test)]
 use crate::data_catalogue::DataCatalogue;
+use crate::chunk_pins::ChunkPin;
+use crate::data_chunk::DataChunkPath;
+use crate::planning::{parse_block_range_dir_name, DirectoryLayout};
+use crate::cancellation::Cancellations;
+use crate::checksum::FileChecksums;
+use crate::content_store::ContentStore;
+use crate::operation::{OperationKind, OperationResult};
+use crate::progress::DownloadProgress;
+use crate::repair;
+use crate::throttle::BandwidthThrottle;
+use crate::transfer::{self, ChunkTransfer, SimulatedTransfer, PARTIAL_SUFFIX};
 
 pub const LOCAL_DATA_DIR: &str = "./local_data_dir";
 
 #[derive(Clone)]
 pub struct LocalDataSource {
     pub data_dir: PathBuf,
+    pub layout: DirectoryLayout,
+    /// Remote storage the chunks are downloaded from
+    pub transfer: Arc<dyn ChunkTransfer>,
+    /// Data directories of the chunks moved out of the `data_dir` with `relocate_chunk`
+    volumes: Arc<RwLock<HashMap<ChunkId, PathBuf>>>,
+    /// Bandwidth shared by all the downloads, unlimited when `None`
+    pub throttle: Option<BandwidthThrottle>,
+    /// Downloads to give up, checked by the transfers as they go
+    pub cancellations: Cancellations,
+    /// Progress of the chunks being transferred, reported by the transfers as they go
+    pub progress: DownloadProgress,
+    /// Holds the files of the chunks once per content, the files are stored in the chunk directories when `None`
+    pub content_store: Option<ContentStore>,
+    /// Chunks the scans found with files they can't read, until they are taken with `take_unreadable_chunks`
+    unreadable_chunks: Arc<Mutex<Vec<UnreadableChunk>>>,
+}
+
+/// A chunk directory holding files with a name which isn't UTF-8, so the chunk can't be served as it is
+#[derive(Clone, Debug)]
+pub struct UnreadableChunk {
+    /// The chunk with the files which could be read
+    pub chunk: DataChunk,
+    /// Paths of the files which couldn't be read
+    pub unreadable_files: Vec<PathBuf>,
 }
 
 impl LocalDataSource {
     pub fn new(data_dir: PathBuf) -> Self {
-        LocalDataSource { data_dir }
+        LocalDataSource {
+            data_dir,
+            layout: DirectoryLayout::default(),
+            transfer: Arc::new(SimulatedTransfer),
+            volumes: Arc::new(RwLock::new(HashMap::new())),
+            throttle: None,
+            cancellations: Cancellations::default(),
+            progress: DownloadProgress::default(),
+            content_store: None,
+            unreadable_chunks: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn with_layout(mut self, layout: DirectoryLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn with_transfer(mut self, transfer: Arc<dyn ChunkTransfer>) -> Self {
+        self.transfer = transfer;
+        self
+    }
+
+    pub fn with_throttle(mut self, throttle: BandwidthThrottle) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    pub fn with_content_store(mut self, content_store: ContentStore) -> Self {
+        self.content_store = Some(content_store);
+        self
+    }
+
+    pub fn with_cancellations(mut self, cancellations: Cancellations) -> Self {
+        self.cancellations = cancellations;
+        self
+    }
+
+    /// Chunks relocated to other volumes, e.g. as recorded in the catalogue
+    pub fn with_volumes(self, volumes: HashMap<ChunkId, PathBuf>) -> Self {
+        *self.volumes.write().unwrap() = volumes;
+        self
+    }
+
+    /// Directory holding the chunk, its volume when it was relocated, otherwise the `data_dir`
+    pub fn chunk_data_dir(&self, chunk_id: &ChunkId) -> PathBuf {
+        self.volumes.read().unwrap().get(chunk_id).cloned().unwrap_or_else(|| self.data_dir.clone())
+    }
+
+    /// Move the chunk to the volume, `None` moves it back to the `data_dir`
+    pub(crate) fn set_volume(&self, chunk_id: ChunkId, volume: Option<PathBuf>) {
+        let mut volumes = self.volumes.write().unwrap();
+        match volume {
+            Some(volume) => volumes.insert(chunk_id, volume),
+            None => volumes.remove(&chunk_id),
+        };
+    }
+
+    pub fn chunk_path(&self, chunk: DataChunk) -> DataChunkPath {
+        DataChunkPath::new(&self.chunk_data_dir(&chunk.id), self.layout, chunk)
+    }
+
+    /// Path of the chunk, which stays valid until the pin is dropped
+    pub fn pinned_chunk_path(&self, chunk: DataChunk, pin: ChunkPin) -> DataChunkPath {
+        DataChunkPath::pinned(&self.chunk_data_dir(&chunk.id), self.layout, chunk, pin)
     }
 
     pub fn get_local_chunk_ids(&self) -> Vec<ChunkId> {
         self.get_local_chunks().iter().map(|chunk| chunk.id).collect()
     }
 
+    /// Read all the local chunks, ordered by dataset id and block range
     pub fn get_local_chunks(&self) -> Vec<DataChunk> {
-        let mut chunks = Vec::new();
-
-        // chunk id is concatenated dataset_id and block_range hashed with sha256 into [u8; 32]
-
-        if let Ok(entries) = fs::read_dir(&self.data_dir) {
-            for entry in entries.flatten() {
-                if let Ok(main_directory) = entry.file_name().into_string() {
-                    if main_directory.contains("=") {
-                        for dataset_directory in entry.path().read_dir().unwrap() {
-                            if let Ok(block_range_directory) = dataset_directory.unwrap().file_name().into_string() {
-                                if block_range_directory.contains("=") {
-
-                                    let mut files = HashMap::new();
-                                    for file in fs::read_dir(format!("{}/{}/{}", &self.data_dir.display(), main_directory, block_range_directory)).unwrap() {
-                                        let file = file.unwrap();
-                                        let file_name = file.file_name().into_string().unwrap();
-                                        let file_path = file.path().into_os_string().into_string().unwrap();
-                                        files.insert(file_name, file_path);
-                                    }
-                                    let dataset_id_str = main_directory.split("=").nth(1).unwrap();
-                                    let dataset_id_vec = hex::decode(dataset_id_str).unwrap();
-                                    let mut dataset_id = [0u8; 32];
-                                    dataset_id.copy_from_slice(&dataset_id_vec);
-
-                                    let block_range = block_range_directory.split("=").nth(1).unwrap();
-                                    let mut parts = block_range.split('_');
-                                    let block_start = parts.next().unwrap().parse::<u64>().unwrap();
-                                    let block_end = parts.next().unwrap().parse::<u64>().unwrap();
-                                    let range = block_start..block_end;
-                                    let chunk_id = DataCatalogue::generate_chunk_id(&dataset_id, &range);
-                                    let data_chunk = DataChunk {
-                                        id: chunk_id,
-                                        dataset_id: dataset_id,
-                                        block_range: range,
-                                        files: files,
-                                    };
-                                    chunks.push(data_chunk);
-                                }
-                            }
+        let mut chunks: Vec<DataChunk> = self.scan_local_chunks(1).into_iter().collect();
+        chunks.sort_by_key(|chunk| (chunk.dataset_id, chunk.block_range.start, chunk.block_range.end));
+        chunks
+    }
+
+    /// Scan the `data_dir` and the volumes of the relocated chunks for local chunks with `parallelism` threads.
+    /// Only the chunks relocated to a volume are taken from it, and only the others from the `data_dir`.
+    ///
+    /// Chunks are streamed through a bounded channel as soon as their directory is read,
+    /// so the whole directory tree is never held in memory. The order of chunks is not defined.
+    pub fn scan_local_chunks(&self, parallelism: usize) -> mpsc::Receiver<DataChunk> {
+        let parallelism = parallelism.max(1);
+        let (dir_sender, dir_receiver) = mpsc::sync_channel::<(DatasetId, PathBuf, PathBuf)>(parallelism * 2);
+        let (chunk_sender, chunk_receiver) = mpsc::sync_channel::<DataChunk>(parallelism * 2);
+
+        // walk the dataset directories and hand over the block range directories to the workers
+        let mut data_dirs = vec![self.data_dir.clone()];
+        for volume in self.volumes.read().unwrap().values() {
+            if !data_dirs.contains(volume) {
+                data_dirs.push(volume.clone());
+            }
+        }
+        let layout = self.layout;
+        thread::spawn(move || {
+            for data_dir in data_dirs {
+                for (dataset_id, dataset_dir) in read_dataset_dirs(&data_dir, layout) {
+                    let Ok(entries) = fs::read_dir(&dataset_dir) else { continue };
+                    for entry in entries.flatten() {
+                        if dir_sender.send((dataset_id, entry.path(), data_dir.clone())).is_err() {
+                            // nobody is interested in the results anymore
+                            return;
                         }
                     }
                 }
             }
+        });
+
+        // read the block range directories in parallel
+        let dir_receiver = Arc::new(Mutex::new(dir_receiver));
+        for _ in 0..parallelism {
+            let dir_receiver = dir_receiver.clone();
+            let chunk_sender = chunk_sender.clone();
+            let data_source = self.clone();
+            thread::spawn(move || loop {
+                let next_dir = dir_receiver.lock().unwrap().recv();
+                let Ok((dataset_id, block_range_dir, data_dir)) = next_dir else { return };
+                // leftovers of a relocation, which didn't finish removing them, are skipped
+                let scanned = read_chunk_dir(dataset_id, &block_range_dir).filter(|(chunk, _)| data_source.chunk_data_dir(&chunk.id) == data_dir);
+                let Some((chunk, unreadable_files)) = scanned else { continue };
+                if !unreadable_files.is_empty() {
+                    // recorded before the chunk would have been sent, so it's there once the scan is drained
+                    data_source.unreadable_chunks.lock().unwrap().push(UnreadableChunk { chunk, unreadable_files });
+                } else if chunk_sender.send(chunk).is_err() {
+                    return;
+                }
+            });
         }
-        chunks
+        chunk_receiver
+    }
+
+    /// Chunks the scans left out because some of their files have a name which isn't UTF-8.
+    /// They are complete only once they're taken after the scan was drained.
+    pub fn take_unreadable_chunks(&self) -> Vec<UnreadableChunk> {
+        std::mem::take(&mut *self.unreadable_chunks.lock().unwrap())
     }
 
-    /// Download the all the chunks to the local_data_dir
-    pub fn download_chunk(data_dir: PathBuf, chunk: DataChunk) -> String {
+    /// Download the all the chunks to the local_data_dir.
+    /// The files are downloaded into a staging directory, which is renamed into place once all of them are complete.
+    pub fn download_chunk(&self, chunk: DataChunk) -> std::io::Result<OperationResult> {
+        let started_at = Instant::now();
         // the actual work of downloading the chunk happens here
-        simulate_downloading_chunk(data_dir.clone(), chunk.clone());
-        format!(
-            "Downloading the chunk {:?} to {} has completed",
-            chunk.id,
-            data_dir.display()
-        )
+        let staging_dir = self.download_chunk_staged(&chunk)?;
+        self.publish_staged_chunk(&chunk, &staging_dir)?;
+        Ok(OperationResult::succeeded(OperationKind::Download, chunk.id, started_at, Some(self.chunk_bytes(&chunk))))
     }
 
-    /// Simulate deleting the chunk by waiting for 100ms
-    pub fn delete_chunk(data_dir: PathBuf, chunk_id: ChunkId) -> String {
+    /// Download the files of a chunk into a staging directory next to its directory, which the scan of the data directory skips,
+    /// so an interrupted download never looks like a complete chunk. The files of an interrupted download stay there for the next attempt.
+    /// Returns the staging directory, it becomes the chunk directory with `publish_staged_chunk`,
+    /// or its files are moved into the directory of a chunk already in the data directory by `swap_chunk_files`.
+    pub fn download_chunk_staged(&self, chunk: &DataChunk) -> std::io::Result<PathBuf> {
+        self.download_chunk_staged_from_store(chunk, &FileChecksums::new())
+    }
+
+    /// Same as `download_chunk_staged`, the files with a known checksum whose content is in the content store
+    /// are linked from it rather than transferred
+    pub fn download_chunk_staged_from_store(&self, chunk: &DataChunk, checksums: &FileChecksums) -> std::io::Result<PathBuf> {
+        transfer::check_file_names(chunk)?;
+        let staging_dir = self.staging_dir(chunk);
+        fs::create_dir_all(&staging_dir)?;
+        let remaining = match &self.content_store {
+            Some(content_store) if !checksums.is_empty() => content_store.link_known_files(chunk, &staging_dir, checksums)?,
+            _ => chunk.clone(),
+        };
+        // a chunk without files of its own, e.g. of the `SimulatedTransfer`, is still transferred
+        if !remaining.files.is_empty() || chunk.files.is_empty() {
+            self.transfer_files(&remaining, &staging_dir)?;
+        }
+        Ok(staging_dir)
+    }
+
+    /// Stage the intact files of the chunk directory, linked or copied, and transfer the damaged files next to them,
+    /// so the staging directory holds the whole chunk to verify while only the damaged files are downloaded
+    pub fn download_damaged_files_staged(&self, chunk: &DataChunk, damaged_files: &[String]) -> std::io::Result<PathBuf> {
+        transfer::check_file_names(chunk)?;
+        let staging_dir = self.staging_dir(chunk);
+        fs::create_dir_all(&staging_dir)?;
+        let chunk_dir = self.chunk_path(chunk.clone()).path;
+        for file_name in chunk.files.keys().filter(|file_name| !damaged_files.contains(file_name)) {
+            let staged = staging_dir.join(file_name);
+            let _ = fs::remove_file(&staged);
+            // a link shares the file with the chunk directory, a copy serves where links aren't supported
+            if fs::hard_link(chunk_dir.join(file_name), &staged).is_err() {
+                fs::copy(chunk_dir.join(file_name), &staged)?;
+            }
+        }
+        self.transfer_files(&repair::damaged_chunk(chunk, damaged_files), &staging_dir)?;
+        Ok(staging_dir)
+    }
+
+    /// Put the verified files of the staging directory into the content store, when there is one
+    pub fn store_staged_files(&self, chunk: &DataChunk, staging_dir: &Path) -> std::io::Result<()> {
+        match &self.content_store {
+            Some(content_store) => content_store.store_files(chunk, staging_dir),
+            None => Ok(()),
+        }
+    }
+
+    fn transfer_files(&self, chunk: &DataChunk, dir: &Path) -> std::io::Result<()> {
+        let cancelled = || self.cancellations.is_cancelled(&chunk.id);
+        let progress = |file_name: &str, file_progress| self.progress.update(&chunk.id, file_name, file_progress);
+        // a retried download is tracked from scratch
+        self.progress.start(chunk);
+        self.transfer.tracked_download(chunk, dir, self.throttle.as_ref(), &cancelled, &progress)
+    }
+
+    /// Rename the staging directory to the chunk directory at once, so the chunk appears with all its files.
+    /// A chunk directory left by an interrupted download which didn't use a staging directory is replaced.
+    pub fn publish_staged_chunk(&self, chunk: &DataChunk, staging_dir: &Path) -> std::io::Result<()> {
+        // a transfer which received no files, e.g. the `SimulatedTransfer`, leaves no chunk directory
+        if fs::read_dir(staging_dir)?.next().is_none() {
+            return fs::remove_dir(staging_dir);
+        }
+        let chunk_dir = self.chunk_path(chunk.clone()).path;
+        if chunk_dir.exists() {
+            fs::remove_dir_all(&chunk_dir)?;
+        }
+        fs::rename(staging_dir, chunk_dir)
+    }
+
+    /// Remove the files of an interrupted download, which shouldn't be continued
+    pub fn discard_staged_files(&self, chunk: &DataChunk) -> std::io::Result<()> {
+        match fs::remove_dir_all(self.staging_dir(chunk)) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /// Replace the files of the chunk one by one by the staged files, each with an atomic rename,
+    /// then remove the files which are no longer part of the chunk.
+    /// Readers see every file either old or new, never partially written.
+    pub fn swap_chunk_files(&self, chunk: &DataChunk, staging_dir: &Path) -> std::io::Result<()> {
+        let chunk_dir = self.chunk_path(chunk.clone()).path;
+        for entry in fs::read_dir(staging_dir)? {
+            let entry = entry?;
+            fs::rename(entry.path(), chunk_dir.join(entry.file_name()))?;
+        }
+        for entry in fs::read_dir(&chunk_dir)? {
+            let entry = entry?;
+            let is_chunk_file = entry.file_name().to_str().is_some_and(|file_name| chunk.files.contains_key(file_name));
+            if !is_chunk_file && entry.file_type()?.is_file() {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        fs::remove_dir_all(staging_dir)
+    }
+
+    /// Whether the directory of the chunk holds files of an interrupted download, which wasn't staged
+    pub fn has_partial_files(&self, chunk: &DataChunk) -> bool {
+        let Ok(entries) = fs::read_dir(self.chunk_path(chunk.clone()).path) else { return false };
+        entries.flatten().any(|entry| entry.file_name().to_str().is_some_and(|file_name| file_name.ends_with(PARTIAL_SUFFIX)))
+    }
+
+    /// Sibling of the chunk directory, which the scan of the data directory skips
+    fn staging_dir(&self, chunk: &DataChunk) -> PathBuf {
+        let chunk_dir = self.chunk_path(chunk.clone()).path;
+        PathBuf::from(format!("{}.staging", chunk_dir.display().to_string().trim_end_matches('/')))
+    }
+
+    pub fn delete_chunk(&self, chunk: &DataChunk) -> std::io::Result<OperationResult> {
+        let started_at = Instant::now();
+        let bytes = self.chunk_bytes(chunk);
+        let chunk_dir = self.chunk_path(chunk.clone()).path;
+        if let Some(content_store) = &self.content_store {
+            // a chunk stored before the start is only known by its files, which are about to be removed
+            let _ = content_store.recall_files(chunk, &chunk_dir);
+        }
         // the actual work of deleting the chunk happens here
-        simulate_deleting_chunk(&data_dir, &chunk_id);
-        format!("Deleting the chunk {:?} from {} has completed", chunk_id, data_dir.display())
+        self.transfer.delete(chunk, &chunk_dir)?;
+        self.discard_staged_files(chunk)?;
+        if let Some(content_store) = &self.content_store {
+            // a stored file which can't be removed now is collected with a later deletion
+            let _ = content_store.release(&chunk.id);
+        }
+        Ok(OperationResult::succeeded(OperationKind::Delete, chunk.id, started_at, Some(bytes)))
+    }
+
+    /// Size of the files in the directory of the chunk, 0 when it has none
+    pub fn chunk_bytes(&self, chunk: &DataChunk) -> u64 {
+        let Ok(entries) = fs::read_dir(self.chunk_path(chunk.clone()).path) else { return 0 };
+        entries.flatten()
+            .filter_map(|entry| entry.metadata().ok())
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+}
+
+/// Find the dataset directories of the `layout` in the `data_dir`
+fn read_dataset_dirs(data_dir: &Path, layout: DirectoryLayout) -> Vec<(DatasetId, PathBuf)> {
+    // relative names of the directories down to the dataset level
+    let mut dirs = vec![(String::new(), data_dir.to_path_buf())];
+    for _ in 0..=layout.shard_depth() {
+        dirs = dirs
+            .into_iter()
+            .flat_map(|(parent_name, parent_dir)| read_sub_dirs(&parent_name, &parent_dir))
+            .collect();
+    }
+    dirs.into_iter()
+        .filter_map(|(dir_name, dir)| Some((layout.parse_dataset_dir(&dir_name)?, dir)))
+        .collect()
+}
+
+fn read_sub_dirs(parent_name: &str, parent_dir: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = fs::read_dir(parent_dir) else { return Vec::new() };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let dir_name = entry.file_name().into_string().ok()?;
+            let dir_name = if parent_name.is_empty() { dir_name } else { format!("{}/{}", parent_name, dir_name) };
+            Some((dir_name, entry.path()))
+        })
+        .collect()
+}
+
+/// Read a `block_range=<start>_<end>` directory as a chunk of the dataset,
+/// together with the paths of the files which were left out of it because their name isn't UTF-8.
+fn read_chunk_dir(dataset_id: DatasetId, block_range_dir: &Path) -> Option<(DataChunk, Vec<PathBuf>)> {
+    let dir_name = block_range_dir.file_name()?.to_str()?;
+    let block_range = parse_block_range_dir_name(dir_name)?;
+
+    let mut files = HashMap::new();
+    let mut unreadable_files = Vec::new();
+    for file in fs::read_dir(block_range_dir).ok()?.flatten() {
+        match (file.file_name().into_string(), file.path().into_os_string().into_string()) {
+            (Ok(file_name), Ok(file_path)) => {
+                files.insert(file_name, file_path);
+            }
+            _ => unreadable_files.push(file.path()),
+        }
+    }
+
+    Some((DataChunk { files, ..DataChunk::new(dataset_id, block_range) }, unreadable_files))
+}
+
+#[cfg(test)]
+pub(crate) fn get_test_chunk_111111_0_35() -> DataChunk {
+    let dataset_id_str = "1111111111111111111111111111111111111111111111111111111111111111";
+    let dataset_id_vec = hex::decode(dataset_id_str).unwrap();
+    let mut dataset_id = [0u8; 32];
+    dataset_id.copy_from_slice(&dataset_id_vec);
+    let block_range = 0..35;
+    let chunk_id = DataCatalogue::generate_chunk_id(&dataset_id, &block_range);
+    DataChunk {
+        id: chunk_id,
+        dataset_id,
+        block_range,
+        files: HashMap::from([
+            ("part-1.parquet".to_string(), "https://example.com/part-1.parquet".to_string()),
+            ("part-2.parquet".to_string(), "https://example.com/part-2.parquet".to_string()),
+            ("part-3.parquet".to_string(), "https://example.com/part-3.parquet".to_string()),
+        ]),
+        mirrors: HashMap::new(),
+        size: None,
+        file_sizes: HashMap::new(),
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn get_test_chunk_111111_95_106() -> DataChunk {
+    let dataset_id_str = "1111111111111111111111111111111111111111111111111111111111111111";
+    let dataset_id_vec = hex::decode(dataset_id_str).unwrap();
+    let mut dataset_id = [0u8; 32];
+    dataset_id.copy_from_slice(&dataset_id_vec);
+    let block_range = 95..106;
+    let chunk_id = DataCatalogue::generate_chunk_id(&dataset_id, &block_range);
+    DataChunk {
+        id: chunk_id,
+        dataset_id,
+        block_range,
+        files: HashMap::from([
+            ("part-1.parquet".to_string(), "https://example.com/part-1.parquet".to_string()),
+            ("part-2.parquet".to_string(), "https://example.com/part-2.parquet".to_string()),
+            ("part-3.parquet".to_string(), "https://example.com/part-3.parquet".to_string()),
+        ]),
+        mirrors: HashMap::new(),
+        size: None,
+        file_sizes: HashMap::new(),
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn get_test_chunk_111111_107_135() -> DataChunk {
+    let dataset_id_str = "1111111111111111111111111111111111111111111111111111111111111111";
+    let dataset_id_vec = hex::decode(dataset_id_str).unwrap();
+    let mut dataset_id = [0u8; 32];
+    dataset_id.copy_from_slice(&dataset_id_vec);
+    let block_range = 107..135;
+    let chunk_id = DataCatalogue::generate_chunk_id(&dataset_id, &block_range);
+    DataChunk {
+        id: chunk_id,
+        dataset_id,
+        block_range,
+        files: HashMap::from([
+            ("part-1.parquet".to_string(), "https://example.com/part-1.parquet".to_string()),
+            ("part-2.parquet".to_string(), "https://example.com/part-2.parquet".to_string()),
+            ("part-3.parquet".to_string(), "https://example.com/part-3.parquet".to_string()),
+            ("part-4.parquet".to_string(), "https://example.com/part-4.parquet".to_string()),
+            ("part-5.parquet".to_string(), "https://example.com/part-5.parquet".to_string()),
+        ]),
+        mirrors: HashMap::new(),
+        size: None,
+        file_sizes: HashMap::new(),
     }
 }
 
@@ -92,6 +464,9 @@ impl LocalDataSource {
 mod tests {
     use std::collections::HashMap;
     use serial_test::serial;
+    #[cfg(feature = "dataframes")]
+    use crate::devtools;
+    use crate::test_support;
     use super::*;
 
     #[test]
@@ -101,6 +476,7 @@ mod tests {
     }
 
     #[test]
+    #[serial]
     fn test_list_files_as_chunk_ids() {
         // Act
         let ds = LocalDataSource::new(PathBuf::from(LOCAL_DATA_DIR));
@@ -108,14 +484,74 @@ mod tests {
 
         // Assert
         assert_eq!(chunk_ids.len(), 8);
-        assert_eq!(chunk_ids[0], [147, 161, 202, 94, 141, 129, 235, 161, 211, 123, 214, 159, 212, 119, 7, 59, 107, 144, 48, 224, 108, 245, 142, 139, 2, 173, 240, 231, 54, 58, 115, 159]);
-        assert_eq!(chunk_ids[1], [52, 249, 87, 41, 193, 143, 108, 194, 169, 137, 151, 250, 99, 44, 49, 211, 165, 208, 160, 65, 58, 31, 238, 223, 208, 29, 143, 142, 93, 6, 220, 211]);
+        assert_eq!(chunk_ids[0], [98, 39, 185, 12, 229, 13, 3, 121, 220, 39, 48, 2, 38, 129, 54, 147, 17, 92, 89, 191, 47, 125, 227, 35, 162, 83, 99, 140, 124, 47, 92, 153]);
+        assert_eq!(chunk_ids[1], [118, 166, 206, 104, 188, 72, 255, 213, 176, 59, 193, 246, 55, 235, 118, 138, 87, 148, 244, 77, 58, 207, 103, 229, 97, 58, 212, 176, 79, 143, 187, 4]);
         assert_eq!(chunk_ids[2], [193, 57, 118, 234, 133, 186, 129, 98, 68, 9, 137, 174, 130, 138, 250, 203, 200, 19, 226, 101, 224, 108, 235, 80, 186, 6, 49, 14, 23, 58, 108, 70]);
-        assert_eq!(chunk_ids[3], [118, 166, 206, 104, 188, 72, 255, 213, 176, 59, 193, 246, 55, 235, 118, 138, 87, 148, 244, 77, 58, 207, 103, 229, 97, 58, 212, 176, 79, 143, 187, 4]);
-        assert_eq!(chunk_ids[4], [98, 39, 185, 12, 229, 13, 3, 121, 220, 39, 48, 2, 38, 129, 54, 147, 17, 92, 89, 191, 47, 125, 227, 35, 162, 83, 99, 140, 124, 47, 92, 153]);
-        assert_eq!(chunk_ids[5], [47, 214, 124, 127, 237, 100, 240, 96, 40, 147, 96, 68, 104, 154, 218, 127, 165, 181, 128, 44, 47, 16, 60, 172, 24, 208, 88, 136, 149, 79, 243, 191]);
-        assert_eq!(chunk_ids[6], [56, 24, 248, 27, 82, 241, 162, 191, 1, 219, 253, 77, 160, 250, 121, 88, 143, 116, 109, 77, 123, 216, 197, 83, 201, 51, 240, 120, 186, 231, 249, 76]);
-        assert_eq!(chunk_ids[7], [168, 77, 161, 67, 100, 46, 30, 66, 3, 236, 122, 88, 18, 185, 131, 120, 153, 130, 152, 113, 236, 29, 91, 3, 244, 6, 254, 177, 61, 66, 182, 178]);
+        assert_eq!(chunk_ids[3], [147, 161, 202, 94, 141, 129, 235, 161, 211, 123, 214, 159, 212, 119, 7, 59, 107, 144, 48, 224, 108, 245, 142, 139, 2, 173, 240, 231, 54, 58, 115, 159]);
+        assert_eq!(chunk_ids[4], [52, 249, 87, 41, 193, 143, 108, 194, 169, 137, 151, 250, 99, 44, 49, 211, 165, 208, 160, 65, 58, 31, 238, 223, 208, 29, 143, 142, 93, 6, 220, 211]);
+        assert_eq!(chunk_ids[5], [56, 24, 248, 27, 82, 241, 162, 191, 1, 219, 253, 77, 160, 250, 121, 88, 143, 116, 109, 77, 123, 216, 197, 83, 201, 51, 240, 120, 186, 231, 249, 76]);
+        assert_eq!(chunk_ids[6], [168, 77, 161, 67, 100, 46, 30, 66, 3, 236, 122, 88, 18, 185, 131, 120, 153, 130, 152, 113, 236, 29, 91, 3, 244, 6, 254, 177, 61, 66, 182, 178]);
+        assert_eq!(chunk_ids[7], [47, 214, 124, 127, 237, 100, 240, 96, 40, 147, 96, 68, 104, 154, 218, 127, 165, 181, 128, 44, 47, 16, 60, 172, 24, 208, 88, 136, 149, 79, 243, 191]);
+    }
+
+    #[test]
+    #[cfg(feature = "dataframes")]
+    fn test_scan_local_chunks_in_parallel() {
+        // Arrange
+        let data_dir = test_support::temp_dir("parallel_scan");
+        let datasets = [devtools::DatasetSpec::new([1u8; 32], 0, 12, 10), devtools::DatasetSpec::new([2u8; 32], 500, 7, 25)];
+        let generated = devtools::generate(&data_dir, DirectoryLayout::default(), &datasets).unwrap();
+        let ds = LocalDataSource::new(data_dir.clone());
+
+        // Act
+        let mut chunk_ids: Vec<ChunkId> = ds.scan_local_chunks(4).into_iter().map(|chunk| chunk.id).collect();
+
+        // Assert
+        let mut expected_chunk_ids: Vec<ChunkId> = generated.iter().map(|chunk| chunk.id).collect();
+        chunk_ids.sort();
+        expected_chunk_ids.sort();
+        assert_eq!(chunk_ids, expected_chunk_ids);
+
+        // cleanup
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_scan_missing_data_dir() {
+        let ds = LocalDataSource::new(PathBuf::from("./missing_data_dir"));
+        assert_eq!(ds.scan_local_chunks(2).into_iter().count(), 0);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_chunks_with_non_utf8_file_names_are_set_aside_by_the_scan() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        // Arrange
+        let dir = test_support::temp_dir("non_utf8_file");
+        let ds = LocalDataSource::new(dir.clone());
+        let chunk = DataChunk::new([1u8; 32], 0..10);
+        let block_range_dir = ds.chunk_path(chunk.clone()).path;
+        fs::create_dir_all(&block_range_dir).unwrap();
+        fs::write(block_range_dir.join("blocks.parquet"), b"blocks").unwrap();
+        let unreadable_file = block_range_dir.join(OsStr::from_bytes(b"logs-\xff.parquet"));
+        fs::write(&unreadable_file, b"logs").unwrap();
+
+        // Act
+        let scanned = ds.scan_local_chunks(2).into_iter().count();
+        let unreadable = ds.take_unreadable_chunks();
+
+        // Assert
+        assert_eq!(scanned, 0);
+        assert_eq!(unreadable.len(), 1);
+        assert_eq!(unreadable[0].chunk.id, chunk.id);
+        assert_eq!(unreadable[0].chunk.files.keys().collect::<Vec<_>>(), vec!["blocks.parquet"]);
+        assert_eq!(unreadable[0].unreadable_files, vec![unreadable_file]);
+        assert!(ds.take_unreadable_chunks().is_empty());
+
+        // cleanup
+        fs::remove_dir_all(dir).unwrap();
     }
 
     #[test]
@@ -132,30 +568,31 @@ mod tests {
         let chunk_id = DataCatalogue::generate_chunk_id(&dataset_id, &block_range);
         let chunk = DataChunk {
             id: chunk_id,
-            dataset_id: dataset_id,
-            block_range: block_range,
+            dataset_id,
+            block_range,
             files: HashMap::from([
                 ("part-1.parquet".to_string(), "https://example.com/par-1.parquet".to_string()),
                 ("part-2.parquet".to_string(), "https://example.com/par-2.parquet".to_string()),
                 ("part-3.parquet".to_string(), "https://example.com/par-3.parquet".to_string()),
             ]),
+            mirrors: HashMap::new(),
+            size: None,
+            file_sizes: HashMap::new(),
         };
 
         // Act
-        let result = LocalDataSource::download_chunk(ds.data_dir.clone(), chunk.clone());
+        let result = ds.download_chunk(chunk.clone()).unwrap();
 
         // Assert
-        assert_eq!(
-            result,
-            "Downloading the chunk [170, 13, 118, 225, 28, 2, 234, 149, 141, 239, 145, 9, 120, 116, 116, 137, 16, 29, 106, 129, 18, 70, 73, 152, 183, 85, 25, 49, 33, 116, 247, 65] to ./local_data_dir has completed"
-        );
+        // the files of the remote fixture are empty
+        assert_eq!((result.kind, result.chunk_id, result.bytes, result.error), (OperationKind::Download, chunk.id, Some(0), None));
 
         let chunk_ids = ds.get_local_chunk_ids();
         assert_eq!(chunk_ids.len(), 9);
 
         assert!(chunk_ids.contains(&chunk.id));
 
-        simulate_deleting_chunk(&ds.data_dir.clone(), &chunk.id);
+        SimulatedTransfer.delete(&chunk, &ds.chunk_path(chunk.clone()).path).unwrap();
     }
 
     #[test]
@@ -172,133 +609,79 @@ mod tests {
         let chunk_id = DataCatalogue::generate_chunk_id(&dataset_id, &block_range);
         let chunk = DataChunk {
             id: chunk_id,
-            dataset_id: dataset_id,
-            block_range: block_range,
+            dataset_id,
+            block_range,
             files: HashMap::from([
                 ("part-1.parquet".to_string(), "https://example.com/par-1.parquet".to_string()),
                 ("part-2.parquet".to_string(), "https://example.com/par-2.parquet".to_string()),
                 ("part-3.parquet".to_string(), "https://example.com/par-3.parquet".to_string()),
             ]),
+            mirrors: HashMap::new(),
+            size: None,
+            file_sizes: HashMap::new(),
         };
-        simulate_downloading_chunk(ds.data_dir.clone(), chunk.clone());
+        SimulatedTransfer.download(&chunk, &ds.chunk_path(chunk.clone()).path).unwrap();
         let chunk_ids = ds.get_local_chunk_ids();
         assert_eq!(chunk_ids.len(), 9);
         assert!(chunk_ids.contains(&chunk.id));
 
         // Act
-        let result = LocalDataSource::delete_chunk(ds.data_dir.clone(), chunk.id);
+        let result = ds.delete_chunk(&chunk).unwrap();
 
         // Assert
-        assert_eq!(
-            result,
-            "Deleting the chunk [170, 13, 118, 225, 28, 2, 234, 149, 141, 239, 145, 9, 120, 116, 116, 137, 16, 29, 106, 129, 18, 70, 73, 152, 183, 85, 25, 49, 33, 116, 247, 65] from ./local_data_dir has completed"
-        );
+        assert_eq!((result.kind, result.chunk_id, result.bytes, result.error), (OperationKind::Delete, chunk.id, Some(0), None));
 
         let chunk_ids = ds.get_local_chunk_ids();
         assert_eq!(chunk_ids.len(), 8);
         assert!(!chunk_ids.contains(&chunk.id));
     }
-}
 
-fn copy_dir_all(src: &Path, dst: &Path) -> std::io::Result<()> {
-    if !dst.exists() {
-        fs::create_dir(dst)?;
-    }
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
-        let file_type = entry.file_type()?;
-        let src_path = entry.path();
-        let dst_path = dst.join(entry.file_name());
-        if file_type.is_dir() {
-            copy_dir_all(&src_path, &dst_path)?;
-        } else {
-            fs::copy(&src_path, &dst_path)?;
-        }
+    #[test]
+    fn test_scan_sharded_layout_with_changed_case() {
+        // Arrange
+        let data_dir = test_support::temp_dir("layout");
+        let layout = DirectoryLayout::ShardedBase32;
+        // e.g. copied over by a tool, which doesn't preserve the case
+        let chunk_dir = data_dir.join(layout.chunk_dir(&[1u8; 32], &(0..10)).to_uppercase());
+        fs::create_dir_all(&chunk_dir).unwrap();
+        fs::write(chunk_dir.join("blocks.parquet"), []).unwrap();
+        let ds = LocalDataSource::new(data_dir.clone()).with_layout(layout);
+
+        // Act
+        let chunks = ds.get_local_chunks();
+        let hex_chunks = LocalDataSource::new(data_dir.clone()).get_local_chunks();
+
+        // Assert
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].id, DataCatalogue::generate_chunk_id(&[1u8; 32], &(0..10)));
+        assert_eq!(chunks[0].files.get("blocks.parquet").map(PathBuf::from), Some(chunk_dir.join("blocks.parquet")));
+        assert!(hex_chunks.is_empty());
+        fs::remove_dir_all(data_dir).unwrap();
     }
-    Ok(())
-}
 
-/// Simulate downloading the chunk taking 100ms
-fn simulate_downloading_chunk(data_dir: PathBuf, chunk: DataChunk) {
-    thread::sleep(Duration::from_millis(20));
-    if chunk.dataset_id == [17u8; 32] && chunk.block_range.start == 95 && chunk.block_range.end == 106 {
-        copy_dir_all(
-            Path::new("./remote_data_dir/dataset_id=1111111111111111111111111111111111111111111111111111111111111111/block_range=95_106"),
-            Path::new(&format!("{}/dataset_id=1111111111111111111111111111111111111111111111111111111111111111/block_range=95_106", data_dir.display()))
-        ).expect("Failed to copy directory");
-    };
-    thread::sleep(Duration::from_millis(80));
-}
+    #[test]
+    fn test_staged_files_are_swapped_into_chunk_dir() {
+        // Arrange
+        let data_dir = test_support::temp_dir("swap");
+        let ds = LocalDataSource::new(data_dir.clone());
+        let chunk = test_support::chunk(1, 0..10);
+        let chunk_dir = ds.chunk_path(chunk.clone()).path;
+        fs::create_dir_all(&chunk_dir).unwrap();
+        fs::write(chunk_dir.join("blocks.parquet"), b"old").unwrap();
+        fs::write(chunk_dir.join("stale.parquet"), b"old").unwrap();
+        let staging_dir = ds.download_chunk_staged(&chunk).unwrap();
+        fs::write(staging_dir.join("blocks.parquet"), b"new").unwrap();
 
-/// Simulate deleting the chunk taking 100ms
-fn simulate_deleting_chunk(data_dir: &PathBuf, chunk_id: &ChunkId) {
-    thread::sleep(Duration::from_millis(20));
-    if chunk_id.eq(&[170, 13, 118, 225, 28, 2, 234, 149, 141, 239, 145, 9, 120, 116, 116, 137, 16, 29, 106, 129, 18, 70, 73, 152, 183, 85, 25, 49, 33, 116, 247, 65]) {
-        fs::remove_dir_all(
-                      Path::new(&format!("{}/dataset_id=1111111111111111111111111111111111111111111111111111111111111111/block_range=95_106", data_dir.display()))
-        ).expect("Failed to remove directory");
-    };
-    thread::sleep(Duration::from_millis(80));
-}
+        // Act
+        ds.swap_chunk_files(&chunk, &staging_dir).unwrap();
 
-pub(crate) fn get_test_chunk_111111_0_35() -> DataChunk {
-    let dataset_id_str = "1111111111111111111111111111111111111111111111111111111111111111";
-    let dataset_id_vec = hex::decode(dataset_id_str).unwrap();
-    let mut dataset_id = [0u8; 32];
-    dataset_id.copy_from_slice(&dataset_id_vec);
-    let block_range = 0..35;
-    let chunk_id = DataCatalogue::generate_chunk_id(&dataset_id, &block_range);
-    let chunk = DataChunk {
-        id: chunk_id,
-        dataset_id: dataset_id,
-        block_range: block_range,
-        files: HashMap::from([
-            ("part-1.parquet".to_string(), "https://example.com/part-1.parquet".to_string()),
-            ("part-2.parquet".to_string(), "https://example.com/part-2.parquet".to_string()),
-            ("part-3.parquet".to_string(), "https://example.com/part-3.parquet".to_string()),
-        ]),
-    };
-    chunk
-}
+        // Assert
+        assert_eq!(fs::read(chunk_dir.join("blocks.parquet")).unwrap(), b"new");
+        assert!(!chunk_dir.join("stale.parquet").exists());
+        assert!(!staging_dir.exists());
+        assert_eq!(ds.get_local_chunk_ids(), vec![chunk.id]);
 
-pub(crate) fn get_test_chunk_111111_95_106() -> DataChunk {
-    let dataset_id_str = "1111111111111111111111111111111111111111111111111111111111111111";
-    let dataset_id_vec = hex::decode(dataset_id_str).unwrap();
-    let mut dataset_id = [0u8; 32];
-    dataset_id.copy_from_slice(&dataset_id_vec);
-    let block_range = 95..106;
-    let chunk_id = DataCatalogue::generate_chunk_id(&dataset_id, &block_range);
-    let chunk = DataChunk {
-        id: chunk_id,
-        dataset_id: dataset_id,
-        block_range: block_range,
-        files: HashMap::from([
-            ("part-1.parquet".to_string(), "https://example.com/part-1.parquet".to_string()),
-            ("part-2.parquet".to_string(), "https://example.com/part-2.parquet".to_string()),
-            ("part-3.parquet".to_string(), "https://example.com/part-3.parquet".to_string()),
-        ]),
-    };
-    chunk
+        // cleanup
+        fs::remove_dir_all(data_dir).unwrap();
+    }
 }
-
-pub(crate) fn get_test_chunk_111111_107_135() -> DataChunk {
-    let dataset_id_str = "1111111111111111111111111111111111111111111111111111111111111111";
-    let dataset_id_vec = hex::decode(dataset_id_str).unwrap();
-    let mut dataset_id = [0u8; 32];
-    dataset_id.copy_from_slice(&dataset_id_vec);
-    let block_range = 107..135;
-    let chunk_id = DataCatalogue::generate_chunk_id(&dataset_id, &block_range);
-    let chunk = DataChunk {
-        id: chunk_id,
-        dataset_id: dataset_id,
-        block_range: block_range,
-        files: HashMap::from([
-            ("part-1.parquet".to_string(), "https://example.com/part-1.parquet".to_string()),
-            ("part-2.parquet".to_string(), "https://example.com/part-2.parquet".to_string()),
-            ("part-3.parquet".to_string(), "https://example.com/part-3.parquet".to_string()),
-            ("part-4.parquet".to_string(), "https://example.com/part-4.parquet".to_string()),
-            ("part-5.parquet".to_string(), "https://example.com/part-5.parquet".to_string()),
-        ]),
-    };
-    chunk
-}
\ No newline at end of file
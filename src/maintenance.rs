@@ -0,0 +1,132 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use crate::chunk_errors::ChunkErrorKind;
+use crate::data_catalogue::ChunkStatus;
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
+use crate::verification;
+use crate::workers::Workers;
+
+/// Order in which the maintenance jobs (storage sampling, stuck chunk repairs, re-verification) process the datasets.
+/// Chunks of datasets with a higher priority are handled first, so business-critical datasets are maintained
+/// before archival ones when a maintenance window is too short for all the chunks.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MaintenancePriorities {
+    pub priorities: HashMap<DatasetId, u32>,
+    /// Priority of the datasets without one in `priorities`
+    pub default_priority: u32,
+}
+
+impl MaintenancePriorities {
+    pub fn with_priority(mut self, dataset_id: DatasetId, priority: u32) -> Self {
+        self.priorities.insert(dataset_id, priority);
+        self
+    }
+
+    pub fn priority(&self, dataset_id: &DatasetId) -> u32 {
+        self.priorities.get(dataset_id).copied().unwrap_or(self.default_priority)
+    }
+
+    /// Sort the items by the priority of their datasets, highest first, keeping the order of the items of equal priority
+    pub fn sort_by_priority<T>(&self, items: &mut [T], dataset_id: impl Fn(&T) -> DatasetId) {
+        items.sort_by_key(|item| Reverse(self.priority(&dataset_id(item))));
+    }
+}
+
+/// Result of a re-verification of the ready chunks
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerificationRun {
+    /// Chunks matching the trusted chain, in the order they were verified
+    pub verified: Vec<ChunkId>,
+    /// Chunks from another fork, they are deleted
+    pub mismatched: Vec<ChunkId>,
+}
+
+/// When the ready chunks were last verified, so every re-verification continues with the chunks verified longest ago
+#[derive(Clone, Default)]
+pub struct VerificationLog {
+    verified_at: Arc<RwLock<HashMap<ChunkId, Instant>>>,
+}
+
+impl VerificationLog {
+    /// Up to `max_chunks` of the candidates, by the priority of their datasets,
+    /// then the never verified chunks, then the ones verified longest ago
+    fn next(&self, mut candidates: Vec<DataChunk>, priorities: &MaintenancePriorities, max_chunks: usize) -> Vec<DataChunk> {
+        let verified_at = self.verified_at.read().unwrap();
+        candidates.sort_by_key(|chunk| verified_at.get(&chunk.id).copied());
+        priorities.sort_by_priority(&mut candidates, |chunk| chunk.dataset_id);
+        candidates.truncate(max_chunks);
+        candidates
+    }
+}
+
+/// Check the block hashes of up to `max_chunks` ready chunks of the datasets with a verifier again.
+/// A chunk from another fork is marked with a `Verification` error and deleted.
+pub(crate) fn verify_ready_chunks(workers: &Workers, log: &VerificationLog, priorities: &MaintenancePriorities, max_chunks: usize) -> VerificationRun {
+    let candidates: Vec<DataChunk> = workers.data_catalogue.registry.read().unwrap()
+        .values()
+        .filter(|info| info.status == ChunkStatus::Ready && workers.has_hash_verifier(&info.chunk.dataset_id))
+        .map(|info| info.chunk.clone())
+        .collect();
+
+    let mut run = VerificationRun::default();
+    for chunk in log.next(candidates, priorities, max_chunks) {
+        let result = {
+            // the chunk can't be deleted while its files are read
+            let _pin = workers.data_catalogue.pins.pin(&chunk.id);
+            let chunk_dir = workers.data_source.chunk_path(chunk.clone()).path;
+            workers.verify(&chunk, &chunk_dir)
+        };
+        match result {
+            Ok(()) => {
+                log.verified_at.write().unwrap().insert(chunk.id, Instant::now());
+                run.verified.push(chunk.id);
+            }
+            Err(error) if verification::is_hash_mismatch(&error) => {
+                log.verified_at.write().unwrap().remove(&chunk.id);
+                workers.data_catalogue.record_error(&chunk.id, ChunkErrorKind::Verification, error.to_string());
+                if workers.data_catalogue.start_deletion(&chunk) {
+                    workers.spawn_deletion(chunk.clone());
+                }
+                run.mismatched.push(chunk.id);
+            }
+            // the chunk is verified again by the next run, when the chain or the files are reachable
+            Err(_) => {}
+        }
+    }
+    run
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(dataset: u8, block: u64) -> DataChunk {
+        DataChunk { id: [block as u8; 32], ..DataChunk::new([dataset; 32], block..block + 1) }
+    }
+
+    #[test]
+    fn test_items_are_sorted_by_priority() {
+        let priorities = MaintenancePriorities::default().with_priority([1u8; 32], 10).with_priority([3u8; 32], 5);
+        let mut datasets = vec![[2u8; 32], [3u8; 32], [1u8; 32], [4u8; 32], [1u8; 32]];
+        priorities.sort_by_priority(&mut datasets, |dataset_id| *dataset_id);
+        assert_eq!(datasets, vec![[1u8; 32], [1u8; 32], [3u8; 32], [2u8; 32], [4u8; 32]]);
+    }
+
+    #[test]
+    fn test_verification_continues_with_chunks_verified_longest_ago() {
+        // Arrange
+        let priorities = MaintenancePriorities::default().with_priority([2u8; 32], 1);
+        let log = VerificationLog::default();
+        let candidates = vec![chunk(1, 1), chunk(1, 2), chunk(2, 3), chunk(2, 4)];
+        log.verified_at.write().unwrap().insert(chunk(2, 3).id, Instant::now());
+
+        // Act
+        let next = log.next(candidates, &priorities, 3);
+
+        // Assert
+        let blocks: Vec<u64> = next.iter().map(|chunk| chunk.block_range.start).collect();
+        assert_eq!(blocks, vec![4, 3, 1]);
+    }
+}
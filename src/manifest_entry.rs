@@ -0,0 +1,233 @@
+//! Chunks built from the entries of a published manifest, see `DataChunk::from_manifest_entry`.
+//!
+//! The chunk ids are derived as everywhere else, see `planning::generate_chunk_id`, so integrators get the ids the scanner
+//! of the data directory gets for the same blocks. The URLs are normalized, so the same file listed by two manifests
+//! is the same URL, and the file hashes are returned with the chunk, ready for `download_chunk_with_checksums`.
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use crate::data_chunk::{DataChunk, DatasetId};
+use crate::planning::ChunkIdGenerator;
+
+/// Entry of a chunk in a manifest
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// Blocks of the chunk, `[start, end)`
+    pub block_range: Range<u64>,
+    /// URLs of the files by file name
+    pub files: HashMap<String, String>,
+    /// Mirror URLs of the files by file name, see `DataChunk::mirrors`
+    pub mirrors: HashMap<String, Vec<String>>,
+    /// Bytes of all the files, see `DataChunk::size`
+    pub size: Option<u64>,
+    pub file_sizes: HashMap<String, u64>,
+    /// sha256 of the files as hex by file name, files without one aren't checked
+    pub file_hashes: HashMap<String, String>,
+}
+
+impl ManifestEntry {
+    pub fn new(block_range: Range<u64>) -> Self {
+        ManifestEntry { block_range, ..ManifestEntry::default() }
+    }
+
+    pub fn with_file(mut self, file_name: impl Into<String>, url: impl Into<String>) -> Self {
+        self.files.insert(file_name.into(), url.into());
+        self
+    }
+
+    pub fn with_mirror(mut self, file_name: impl Into<String>, url: impl Into<String>) -> Self {
+        self.mirrors.entry(file_name.into()).or_default().push(url.into());
+        self
+    }
+
+    pub fn with_size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn with_file_size(mut self, file_name: impl Into<String>, size: u64) -> Self {
+        self.file_sizes.insert(file_name.into(), size);
+        self
+    }
+
+    pub fn with_file_hash(mut self, file_name: impl Into<String>, sha256: impl Into<String>) -> Self {
+        self.file_hashes.insert(file_name.into(), sha256.into());
+        self
+    }
+}
+
+/// Chunk of a manifest entry with the hashes of its files
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestChunk {
+    pub chunk: DataChunk,
+    /// sha256 of the files as lowercase hex by file name, see `FileChecksums`
+    pub checksums: HashMap<String, String>,
+}
+
+/// Why a manifest entry isn't a valid chunk
+#[derive(Clone, Debug, PartialEq)]
+pub enum ManifestEntryError {
+    /// The block range has no blocks
+    EmptyBlockRange,
+    /// The entry has no files
+    NoFiles,
+    /// The URL of the file, or of one of its mirrors, has no scheme, e.g. `https://`
+    InvalidUrl(String, String),
+    /// A size, hash or mirror is given for a file which isn't in `files`
+    UnknownFile(String),
+    /// The hash of the file isn't a sha256 as hex
+    InvalidHash(String),
+}
+
+impl fmt::Display for ManifestEntryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestEntryError::EmptyBlockRange => write!(f, "block range is empty"),
+            ManifestEntryError::NoFiles => write!(f, "entry has no files"),
+            ManifestEntryError::InvalidUrl(file_name, url) => write!(f, "url {} of file {} is not valid", url, file_name),
+            ManifestEntryError::UnknownFile(file_name) => write!(f, "file {} is not in the files of the entry", file_name),
+            ManifestEntryError::InvalidHash(file_name) => write!(f, "hash of file {} is not a sha256", file_name),
+        }
+    }
+}
+
+impl std::error::Error for ManifestEntryError {}
+
+impl DataChunk {
+    /// Chunk of the manifest entry of the dataset, with its id derived from the dataset and the block range,
+    /// and the hashes of its files
+    pub fn from_manifest_entry(dataset_id: DatasetId, entry: ManifestEntry) -> Result<ManifestChunk, ManifestEntryError> {
+        chunk_of(&ChunkIdGenerator::new(&dataset_id), dataset_id, entry)
+    }
+
+    /// Same as `from_manifest_entry` for all the entries of the dataset, fails with the index of the first invalid entry
+    pub fn from_manifest_entries(
+        dataset_id: DatasetId,
+        entries: impl IntoIterator<Item = ManifestEntry>,
+    ) -> Result<Vec<ManifestChunk>, (usize, ManifestEntryError)> {
+        let chunk_ids = ChunkIdGenerator::new(&dataset_id);
+        entries.into_iter()
+            .enumerate()
+            .map(|(i, entry)| chunk_of(&chunk_ids, dataset_id, entry).map_err(|error| (i, error)))
+            .collect()
+    }
+}
+
+fn chunk_of(chunk_ids: &ChunkIdGenerator, dataset_id: DatasetId, entry: ManifestEntry) -> Result<ManifestChunk, ManifestEntryError> {
+    if entry.block_range.is_empty() {
+        return Err(ManifestEntryError::EmptyBlockRange);
+    }
+    if entry.files.is_empty() {
+        return Err(ManifestEntryError::NoFiles);
+    }
+    let listed = |file_name: &String| match entry.files.contains_key(file_name) {
+        true => Ok(()),
+        false => Err(ManifestEntryError::UnknownFile(file_name.clone())),
+    };
+    let normalized = |file_name: &String, url: &str| normalize_url(url).ok_or_else(|| ManifestEntryError::InvalidUrl(file_name.clone(), url.to_string()));
+
+    let mut files = HashMap::new();
+    for (file_name, url) in entry.files.iter() {
+        files.insert(file_name.clone(), normalized(file_name, url)?);
+    }
+    let mut mirrors = HashMap::new();
+    for (file_name, urls) in entry.mirrors.iter().filter(|(_, urls)| !urls.is_empty()) {
+        listed(file_name)?;
+        let urls = urls.iter().map(|url| normalized(file_name, url)).collect::<Result<Vec<String>, ManifestEntryError>>()?;
+        mirrors.insert(file_name.clone(), urls);
+    }
+    entry.file_sizes.keys().try_for_each(listed)?;
+    let mut checksums = HashMap::new();
+    for (file_name, sha256) in entry.file_hashes.iter() {
+        listed(file_name)?;
+        let sha256 = sha256.trim().to_ascii_lowercase();
+        if sha256.len() != 64 || !sha256.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(ManifestEntryError::InvalidHash(file_name.clone()));
+        }
+        checksums.insert(file_name.clone(), sha256);
+    }
+
+    let chunk = DataChunk {
+        id: chunk_ids.chunk_id(&entry.block_range),
+        dataset_id,
+        block_range: entry.block_range,
+        files,
+        mirrors,
+        size: entry.size,
+        file_sizes: entry.file_sizes,
+    };
+    Ok(ManifestChunk { chunk, checksums })
+}
+
+/// The url without surrounding whitespace and its fragment, with its scheme in lowercase, and the host as well for `http` and `https`.
+/// `None` when it has no scheme.
+pub fn normalize_url(url: &str) -> Option<String> {
+    let url = url.trim();
+    let url = url.split_once('#').map_or(url, |(url, _)| url);
+    let (scheme, rest) = url.split_once("://")?;
+    if scheme.is_empty() || !scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) || rest.is_empty() {
+        return None;
+    }
+    let scheme = scheme.to_ascii_lowercase();
+    let rest = match scheme.as_str() {
+        // paths, buckets and content ids may be case sensitive, hosts aren't
+        "http" | "https" => {
+            let host_end = rest.find(['/', '?']).unwrap_or(rest.len());
+            format!("{}{}", rest[..host_end].to_ascii_lowercase(), &rest[host_end..])
+        }
+        _ => rest.to_string(),
+    };
+    Some(format!("{}://{}", scheme, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::planning::generate_chunk_id;
+    use super::*;
+
+    const HASH: &str = "6DD1D8A7E0E8AEC2C8B3C1C2D2C0B9D4E5F60718293A4B5C6D7E8F9011223344";
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(normalize_url(" HTTPS://Example.COM/Data/Blocks.parquet?Sig=AB#part \n").as_deref(), Some("https://example.com/Data/Blocks.parquet?Sig=AB"));
+        assert_eq!(normalize_url("ipfs://QmYwAPJzv5CZsnA").as_deref(), Some("ipfs://QmYwAPJzv5CZsnA"));
+        assert_eq!(normalize_url("example.com/blocks.parquet"), None);
+        assert_eq!(normalize_url("https://"), None);
+    }
+
+    #[test]
+    fn test_chunks_of_manifest_entries() {
+        let dataset_id = [19u8; 32];
+        let entry = ManifestEntry::new(0..10)
+            .with_file("blocks.parquet", "HTTPS://A.example.com/0_10/blocks.parquet")
+            .with_mirror("blocks.parquet", "https://B.example.com/0_10/blocks.parquet")
+            .with_file_size("blocks.parquet", 1024)
+            .with_file_hash("blocks.parquet", HASH);
+
+        let manifest_chunk = DataChunk::from_manifest_entry(dataset_id, entry.clone()).unwrap();
+
+        assert_eq!(manifest_chunk.chunk.id, generate_chunk_id(&dataset_id, &(0..10)));
+        assert_eq!(manifest_chunk.chunk.files["blocks.parquet"], "https://a.example.com/0_10/blocks.parquet");
+        assert_eq!(manifest_chunk.chunk.mirrors["blocks.parquet"], vec!["https://b.example.com/0_10/blocks.parquet"]);
+        assert_eq!(manifest_chunk.chunk.expected_size(), Some(1024));
+        assert_eq!(manifest_chunk.checksums["blocks.parquet"], HASH.to_ascii_lowercase());
+        let entries = vec![entry.clone(), ManifestEntry::new(10..20), entry.clone().with_file_hash("logs.parquet", HASH)];
+        assert_eq!(DataChunk::from_manifest_entries(dataset_id, entries), Err((1, ManifestEntryError::NoFiles)));
+        assert_eq!(
+            DataChunk::from_manifest_entry(dataset_id, entry.clone().with_file_hash("logs.parquet", HASH)),
+            Err(ManifestEntryError::UnknownFile("logs.parquet".to_string())),
+        );
+        assert_eq!(
+            DataChunk::from_manifest_entry(dataset_id, entry.clone().with_file_hash("blocks.parquet", "abc")),
+            Err(ManifestEntryError::InvalidHash("blocks.parquet".to_string())),
+        );
+        assert_eq!(
+            DataChunk::from_manifest_entry(dataset_id, ManifestEntry::new(5..5).with_file("blocks.parquet", "https://example.com")),
+            Err(ManifestEntryError::EmptyBlockRange),
+        );
+        assert_eq!(
+            DataChunk::from_manifest_entry(dataset_id, ManifestEntry::new(0..10).with_file("blocks.parquet", "/0_10/blocks.parquet")),
+            Err(ManifestEntryError::InvalidUrl("blocks.parquet".to_string(), "/0_10/blocks.parquet".to_string())),
+        );
+    }
+}
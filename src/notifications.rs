@@ -0,0 +1,191 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use crate::data_catalogue::{ChunkStatus, DataCatalogue};
+use crate::data_chunk::{ChunkId, DataChunk};
+
+/// Net change of the catalogue over an interval, the transitions in between are coalesced
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CatalogueDelta {
+    /// Chunks which became available
+    pub ready: Vec<DataChunk>,
+    /// Chunks which were available at the start of the interval and aren't anymore
+    pub removed: Vec<DataChunk>,
+    /// Chunks whose download failed and which weren't available before
+    pub failed: Vec<DataChunk>,
+    /// Number of the status changes summarized by the delta
+    pub transitions: usize,
+}
+
+impl CatalogueDelta {
+    pub fn is_empty(&self) -> bool {
+        self.ready.is_empty() && self.removed.is_empty() && self.failed.is_empty()
+    }
+}
+
+/// Called with the summarized delta at the end of every interval which changed the catalogue
+pub type DeltaListener = Box<dyn Fn(&CatalogueDelta) + Send + Sync>;
+
+#[derive(Default)]
+struct PendingChanges {
+    /// ids of the chunks ready at the start of the interval
+    ready: HashSet<ChunkId>,
+    /// last status of the chunks changed within the interval
+    changed: HashMap<ChunkId, (DataChunk, ChunkStatus)>,
+    transitions: usize,
+}
+
+impl PendingChanges {
+    /// Summarize the interval and start the next one
+    fn take_delta(&mut self) -> CatalogueDelta {
+        let mut delta = CatalogueDelta { transitions: self.transitions, ..CatalogueDelta::default() };
+        for (chunk_id, (chunk, status)) in self.changed.drain() {
+            let was_ready = self.ready.contains(&chunk_id);
+            match status {
+                ChunkStatus::Ready if !was_ready => {
+                    self.ready.insert(chunk_id);
+                    delta.ready.push(chunk);
+                }
+                ChunkStatus::Ready => {}
+                _ if was_ready => {
+                    self.ready.remove(&chunk_id);
+                    delta.removed.push(chunk);
+                }
+                ChunkStatus::Failed => delta.failed.push(chunk),
+                _ => {}
+            }
+        }
+        self.transitions = 0;
+        delta
+    }
+}
+
+/// Delivers the changes of the catalogue as one summarized delta per interval instead of an event per transition,
+/// for consumers only interested in the net state, e.g. UIs during big syncs.
+/// Intervals without a net change aren't delivered, the pending changes are delivered when the notifier is dropped.
+pub struct CoalescedNotifier {
+    _stop: mpsc::Sender<()>,
+}
+
+impl CoalescedNotifier {
+    pub(crate) fn start(interval: Duration, listener: DeltaListener, data_catalogue: &DataCatalogue) -> Self {
+        // the notifier is started with the data manager, before any transition runs
+        let ready = data_catalogue.registry.read().unwrap()
+            .values()
+            .filter(|info| info.status == ChunkStatus::Ready)
+            .map(|info| info.chunk.id)
+            .collect();
+        let pending = Arc::new(Mutex::new(PendingChanges { ready, ..PendingChanges::default() }));
+        let recorded = pending.clone();
+        data_catalogue.add_status_listener(Box::new(move |chunk, status| {
+            let mut pending = recorded.lock().unwrap();
+            pending.changed.insert(chunk.id, (chunk.clone(), status.clone()));
+            pending.transitions += 1;
+        }));
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        thread::spawn(move || loop {
+            let result = stopped.recv_timeout(interval);
+            let delta = pending.lock().unwrap().take_delta();
+            if !delta.is_empty() {
+                listener(&delta);
+            }
+            if !matches!(result, Err(RecvTimeoutError::Timeout)) {
+                break;
+            }
+        });
+        CoalescedNotifier { _stop: stop }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::data_manager::DataManager;
+    use crate::test_support;
+    use super::*;
+
+    fn chunk(block: u64) -> DataChunk {
+        DataChunk { id: [block as u8; 32], ..DataChunk::new([1u8; 32], block..block + 1) }
+    }
+
+    fn change(pending: &mut PendingChanges, chunk: &DataChunk, status: ChunkStatus) {
+        pending.changed.insert(chunk.id, (chunk.clone(), status));
+        pending.transitions += 1;
+    }
+
+    #[test]
+    fn test_transitions_are_coalesced_to_net_changes() {
+        // Arrange
+        let mut pending = PendingChanges { ready: HashSet::from([chunk(1).id, chunk(2).id]), ..PendingChanges::default() };
+
+        // Act
+        change(&mut pending, &chunk(1), ChunkStatus::Deleting);
+        change(&mut pending, &chunk(1), ChunkStatus::Deleted);
+        change(&mut pending, &chunk(2), ChunkStatus::Downloading);
+        change(&mut pending, &chunk(2), ChunkStatus::Ready);
+        change(&mut pending, &chunk(3), ChunkStatus::Downloading);
+        change(&mut pending, &chunk(3), ChunkStatus::Ready);
+        change(&mut pending, &chunk(4), ChunkStatus::Downloading);
+        change(&mut pending, &chunk(4), ChunkStatus::Failed);
+        change(&mut pending, &chunk(5), ChunkStatus::Downloading);
+        let delta = pending.take_delta();
+        let next_delta = pending.take_delta();
+
+        // Assert
+        assert_eq!(delta.ready, vec![chunk(3)]);
+        assert_eq!(delta.removed, vec![chunk(1)]);
+        assert_eq!(delta.failed, vec![chunk(4)]);
+        assert_eq!(delta.transitions, 9);
+        assert!(next_delta.is_empty());
+        assert_eq!(next_delta.transitions, 0);
+        assert_eq!(pending.ready, HashSet::from([chunk(2).id, chunk(3).id]));
+    }
+
+    #[test]
+    fn test_sync_is_delivered_as_single_delta() {
+        // Arrange
+        let dir = test_support::temp_dir("notifications");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let deltas = Arc::new(Mutex::new(Vec::new()));
+        let received = deltas.clone();
+        let data_manager = test_support::manager_builder(&dir)
+            .coalesced_notifications(Duration::from_secs(60), Box::new(move |delta| received.lock().unwrap().push(delta.clone())))
+            .build();
+        let chunks: Vec<DataChunk> = (0..3u64)
+            .map(|i| DataChunk::new([8u8; 32], i * 10..(i + 1) * 10).with_example_files(&["blocks.parquet"]))
+            .collect();
+
+        // Act
+        for chunk in chunks.iter() {
+            data_manager.download_chunk(chunk.clone());
+        }
+        data_manager.data_catalogue.wait_until_downloaded(&chunks.iter().map(|chunk| chunk.id).collect::<Vec<ChunkId>>());
+        data_manager.delete_chunk(chunks[0].id);
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(200));
+        });
+        let before_drop = deltas.lock().unwrap().len();
+        drop(data_manager);
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(200));
+        });
+
+        // Assert
+        let deltas = deltas.lock().unwrap();
+        assert_eq!(before_drop, 0);
+        assert_eq!(deltas.len(), 1);
+        let mut ready: Vec<ChunkId> = deltas[0].ready.iter().map(|chunk| chunk.id).collect();
+        ready.sort();
+        let mut expected = vec![chunks[1].id, chunks[2].id];
+        expected.sort();
+        assert_eq!(ready, expected);
+        assert!(deltas[0].removed.is_empty());
+        assert!(deltas[0].transitions >= 8);
+
+        // cleanup
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
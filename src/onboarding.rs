@@ -0,0 +1,365 @@
+//! Dry run of the sync of a new dataset, see `DataManagerImpl::onboard_dataset`.
+//!
+//! The manifest lists the chunks of the dataset, with the bytes of all their files and of every file where the publisher knows them:
+//!
+//! ```json
+//! {
+//!   "dataset_id": "1111111111111111111111111111111111111111111111111111111111111111",
+//!   "chunks": [{
+//!     "block_range": [0, 10000],
+//!     "files": { "blocks.parquet": "https://example.com/0_10000/blocks.parquet" },
+//!     "size": 1048576,
+//!     "file_sizes": { "blocks.parquet": 1048576 }
+//!   }]
+//! }
+//! ```
+//!
+//! The block range is `[start, end)`, as in `DataChunk::block_range`.
+//! The sizes are kept in `DataChunk::size` and `DataChunk::file_sizes`, and checked once the chunk is downloaded.
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+use serde_json::Value;
+use crate::data_chunk::{DataChunk, DatasetId};
+use crate::planning::ChunkIdGenerator;
+use crate::sync_plan::SyncPlan;
+use crate::throttle::BandwidthLimit;
+use crate::transfer::RangeSource;
+
+/// Chunks of the manifest of a dataset
+#[derive(Clone, Debug, PartialEq)]
+pub struct Manifest {
+    pub dataset_id: DatasetId,
+    /// Ordered by block range
+    pub chunks: Vec<DataChunk>,
+}
+
+#[derive(Clone)]
+pub struct OnboardingOptions {
+    /// Fetches the manifest and the lengths of the files of the sampled chunks, no file is downloaded
+    pub source: Arc<dyn RangeSource>,
+    /// Chunks without a size in the manifest whose files are measured,
+    /// the sizes of the others are extrapolated from the bytes per block of the chunks with a known size
+    pub size_samples: usize,
+    /// Bytes the local chunks may take, of all the datasets together
+    pub quota_bytes: Option<u64>,
+    /// Largest download the dataset may start, e.g. to catch a multi-terabyte sync by mistake
+    pub max_download_bytes: Option<u64>,
+    /// Rate the download time is estimated with when `download_bandwidth` isn't configured
+    pub assumed_bytes_per_second: Option<u64>,
+}
+
+impl OnboardingOptions {
+    pub fn new(source: Arc<dyn RangeSource>) -> Self {
+        OnboardingOptions { source, size_samples: 8, quota_bytes: None, max_download_bytes: None, assumed_bytes_per_second: None }
+    }
+
+    pub fn with_size_samples(mut self, size_samples: usize) -> Self {
+        self.size_samples = size_samples;
+        self
+    }
+
+    pub fn with_quota_bytes(mut self, quota_bytes: u64) -> Self {
+        self.quota_bytes = Some(quota_bytes);
+        self
+    }
+
+    pub fn with_max_download_bytes(mut self, max_download_bytes: u64) -> Self {
+        self.max_download_bytes = Some(max_download_bytes);
+        self
+    }
+
+    pub fn with_assumed_bytes_per_second(mut self, assumed_bytes_per_second: u64) -> Self {
+        self.assumed_bytes_per_second = Some(assumed_bytes_per_second);
+        self
+    }
+}
+
+/// Why the sync of the dataset shouldn't be started as it is
+#[derive(Clone, Debug, PartialEq)]
+pub enum OnboardingIssue {
+    /// The local chunks would take more than the quota once the dataset is downloaded
+    QuotaExceeded { required_bytes: u64, quota_bytes: u64 },
+    DownloadTooLarge { download_bytes: u64, max_download_bytes: u64 },
+    /// No chunk to download has a size in the manifest and none was measured, so the estimates are missing
+    UnknownSize,
+}
+
+/// What the sync of a dataset would download, see `DataManagerImpl::onboard_dataset`
+#[derive(Clone, Debug, PartialEq)]
+pub struct OnboardingReport {
+    pub manifest: Manifest,
+    /// Operations the sync would execute, `ensure_chunks` with the chunks of the manifest starts them
+    pub plan: SyncPlan,
+    /// Estimated bytes of the chunks to download
+    pub download_bytes: u64,
+    /// Chunks to download whose size is known, from the manifest or measured, rather than extrapolated
+    pub sized_chunks: usize,
+    /// `None` without a download rate, see `OnboardingOptions::assumed_bytes_per_second`
+    pub estimated_download_time: Option<Duration>,
+    pub issues: Vec<OnboardingIssue>,
+}
+
+impl OnboardingReport {
+    pub fn is_feasible(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Why the dataset couldn't be onboarded
+#[derive(Clone, Debug, PartialEq)]
+pub enum OnboardingError {
+    /// The manifest, or the length of a file, couldn't be fetched
+    Fetch(String),
+    /// The manifest is not JSON
+    InvalidJson(String),
+    /// A field of the manifest is missing or has an unexpected type, with the path to it
+    InvalidField(String),
+    /// Two chunks of the manifest share blocks
+    OverlappingChunks(Range<u64>, Range<u64>),
+}
+
+impl fmt::Display for OnboardingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OnboardingError::Fetch(message) => write!(f, "manifest couldn't be fetched: {}", message),
+            OnboardingError::InvalidJson(message) => write!(f, "manifest is not valid JSON: {}", message),
+            OnboardingError::InvalidField(path) => write!(f, "manifest field {} is missing or invalid", path),
+            OnboardingError::OverlappingChunks(first, second) => write!(f, "manifest chunks {:?} and {:?} overlap", first, second),
+        }
+    }
+}
+
+impl std::error::Error for OnboardingError {}
+
+/// Fetch the manifest at the url and validate it
+pub fn fetch_manifest(source: &dyn RangeSource, manifest_url: &str) -> Result<Manifest, OnboardingError> {
+    let mut manifest = Vec::new();
+    source.read_range(manifest_url, 0, &mut manifest).map_err(|error| OnboardingError::Fetch(format!("{}: {}", manifest_url, error)))?;
+    parse_manifest(&String::from_utf8_lossy(&manifest))
+}
+
+/// Parse the manifest, its chunks must have a block range and at least one file, and must not overlap
+pub fn parse_manifest(manifest: &str) -> Result<Manifest, OnboardingError> {
+    let manifest: Value = serde_json::from_str(manifest).map_err(|error| OnboardingError::InvalidJson(error.to_string()))?;
+    let mut dataset_id = [0u8; 32];
+    manifest["dataset_id"].as_str()
+        .and_then(|hex_id| hex::decode_to_slice(hex_id, &mut dataset_id).ok())
+        .ok_or_else(|| OnboardingError::InvalidField("dataset_id".to_string()))?;
+    let chunk_ids = ChunkIdGenerator::new(&dataset_id);
+    let mut chunks = Vec::new();
+    let listed = manifest["chunks"].as_array().ok_or_else(|| OnboardingError::InvalidField("chunks".to_string()))?;
+    for (i, chunk) in listed.iter().enumerate() {
+        let path = format!("chunks[{}]", i);
+        let block_range = match chunk["block_range"].as_array().map(|range| range.iter().map(Value::as_u64).collect::<Vec<_>>()).as_deref() {
+            Some([Some(start), Some(end)]) if start < end => *start..*end,
+            _ => return Err(OnboardingError::InvalidField(format!("{}.block_range", path))),
+        };
+        let files = chunk["files"].as_object()
+            .filter(|files| !files.is_empty())
+            .ok_or_else(|| OnboardingError::InvalidField(format!("{}.files", path)))?
+            .iter()
+            .map(|(file_name, url)| match url.as_str() {
+                Some(url) if !url.is_empty() => Ok((file_name.clone(), url.to_string())),
+                _ => Err(OnboardingError::InvalidField(format!("{}.files.{}", path, file_name))),
+            })
+            .collect::<Result<HashMap<String, String>, OnboardingError>>()?;
+        let size = match &chunk["size"] {
+            Value::Null => None,
+            size => Some(size.as_u64().ok_or_else(|| OnboardingError::InvalidField(format!("{}.size", path)))?),
+        };
+        let file_sizes = match &chunk["file_sizes"] {
+            Value::Null => HashMap::new(),
+            file_sizes => file_sizes.as_object()
+                .ok_or_else(|| OnboardingError::InvalidField(format!("{}.file_sizes", path)))?
+                .iter()
+                .map(|(file_name, size)| match size.as_u64() {
+                    Some(size) if files.contains_key(file_name) => Ok((file_name.clone(), size)),
+                    _ => Err(OnboardingError::InvalidField(format!("{}.file_sizes.{}", path, file_name))),
+                })
+                .collect::<Result<HashMap<String, u64>, OnboardingError>>()?,
+        };
+        chunks.push(DataChunk {
+            id: chunk_ids.chunk_id(&block_range),
+            dataset_id,
+            block_range,
+            files,
+            mirrors: HashMap::new(),
+            size,
+            file_sizes,
+        });
+    }
+    chunks.sort_by_key(|chunk| (chunk.block_range.start, chunk.block_range.end));
+    if let Some(pair) = chunks.windows(2).find(|pair| pair[1].block_range.start < pair[0].block_range.end) {
+        return Err(OnboardingError::OverlappingChunks(pair[0].block_range.clone(), pair[1].block_range.clone()));
+    }
+    Ok(Manifest { dataset_id, chunks })
+}
+
+/// Estimate the downloads of the plan and check them against the options, without downloading anything.
+/// `stored_bytes` are the bytes of the local chunks of all the datasets.
+pub(crate) fn report(
+    manifest: Manifest,
+    plan: SyncPlan,
+    stored_bytes: u64,
+    bandwidth: Option<&BandwidthLimit>,
+    options: &OnboardingOptions,
+) -> Result<OnboardingReport, OnboardingError> {
+    let downloads: Vec<&DataChunk> = plan.downloads.iter().chain(plan.replacements.iter().map(|replacement| &replacement.chunk)).collect();
+    let mut sizes: Vec<Option<u64>> = downloads.iter().map(|chunk| chunk.expected_size()).collect();
+    // the samples are spread over the unsized chunks, so a dataset growing over time isn't estimated by its first blocks
+    let unsized_chunks: Vec<usize> = (0..downloads.len()).filter(|i| sizes[*i].is_none()).collect();
+    let samples = options.size_samples.min(unsized_chunks.len());
+    for sample in 0..samples {
+        let i = unsized_chunks[sample * unsized_chunks.len() / samples];
+        sizes[i] = Some(measure(options.source.as_ref(), downloads[i])?);
+    }
+
+    let (sized_bytes, sized_blocks) = downloads.iter().zip(&sizes)
+        .filter_map(|(chunk, size)| Some((size.as_ref()?, chunk.block_range.end - chunk.block_range.start)))
+        .fold((0u64, 0u64), |(bytes, blocks), (size, chunk_blocks)| (bytes + size, blocks + chunk_blocks));
+    let unsized_blocks: u64 = downloads.iter().zip(&sizes)
+        .filter(|(_, size)| size.is_none())
+        .map(|(chunk, _)| chunk.block_range.end - chunk.block_range.start)
+        .sum();
+    let sized_chunks = sizes.iter().filter(|size| size.is_some()).count();
+    let download_bytes = match sized_blocks {
+        0 => 0,
+        _ => sized_bytes + (sized_bytes as u128 * unsized_blocks as u128 / sized_blocks as u128) as u64,
+    };
+    let bytes_per_second = bandwidth.map(|bandwidth| bandwidth.bytes_per_second).or(options.assumed_bytes_per_second).filter(|rate| *rate > 0);
+
+    let mut issues = Vec::new();
+    if !downloads.is_empty() && sized_chunks == 0 {
+        issues.push(OnboardingIssue::UnknownSize);
+    }
+    if let Some(quota_bytes) = options.quota_bytes {
+        let required_bytes = stored_bytes + download_bytes;
+        if required_bytes > quota_bytes {
+            issues.push(OnboardingIssue::QuotaExceeded { required_bytes, quota_bytes });
+        }
+    }
+    if let Some(max_download_bytes) = options.max_download_bytes {
+        if download_bytes > max_download_bytes {
+            issues.push(OnboardingIssue::DownloadTooLarge { download_bytes, max_download_bytes });
+        }
+    }
+    Ok(OnboardingReport {
+        manifest,
+        plan,
+        download_bytes,
+        sized_chunks,
+        estimated_download_time: bytes_per_second.map(|rate| Duration::from_secs_f64(download_bytes as f64 / rate as f64)),
+        issues,
+    })
+}
+
+/// Bytes of the files of the chunk, by their lengths at the source
+fn measure(source: &dyn RangeSource, chunk: &DataChunk) -> Result<u64, OnboardingError> {
+    chunk.files.values()
+        .map(|url| source.content_length(url).map_err(|error| OnboardingError::Fetch(format!("{}: {}", url, error))))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{self, Write};
+    use std::sync::Mutex;
+    use crate::config::DataManagerConfig;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// Serves the manifest, every chunk file is 1000 bytes, recording the urls
+    struct ManifestSource {
+        manifest: String,
+        urls: Mutex<Vec<String>>,
+    }
+
+    impl RangeSource for ManifestSource {
+        fn content_length(&self, url: &str) -> io::Result<u64> {
+            self.urls.lock().unwrap().push(url.to_string());
+            Ok(1000)
+        }
+
+        fn read_range(&self, url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            self.urls.lock().unwrap().push(url.to_string());
+            writer.write_all(&self.manifest.as_bytes()[offset as usize..])?;
+            Ok(self.manifest.len() as u64 - offset)
+        }
+    }
+
+    fn manifest_json(chunks: &[(u64, u64, Option<u64>)]) -> String {
+        let chunks: Vec<Value> = chunks.iter()
+            .map(|(start, end, size)| serde_json::json!({
+                "block_range": [start, end],
+                "files": { "blocks.parquet": format!("https://example.com/{}_{}/blocks.parquet", start, end) },
+                "size": size,
+            }))
+            .collect();
+        serde_json::json!({ "dataset_id": hex::encode([7u8; 32]), "chunks": chunks }).to_string()
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = parse_manifest(&manifest_json(&[(10, 20, None), (0, 10, Some(500))])).unwrap();
+        assert_eq!(manifest.dataset_id, [7u8; 32]);
+        assert_eq!(manifest.chunks.iter().map(|chunk| chunk.block_range.clone()).collect::<Vec<_>>(), vec![0..10, 10..20]);
+        assert_eq!(manifest.chunks.iter().map(|chunk| chunk.size).collect::<Vec<_>>(), vec![Some(500), None]);
+        let file_sizes = r#"{"dataset_id": "0707070707070707070707070707070707070707070707070707070707070707", "chunks": [
+            {"block_range": [0, 10], "files": {"blocks.parquet": "https://example.com/blocks.parquet"}, "file_sizes": {"blocks.parquet": 300}}
+        ]}"#;
+        assert_eq!(parse_manifest(file_sizes).unwrap().chunks[0].expected_size(), Some(300));
+        assert_eq!(
+            parse_manifest(&file_sizes.replace("\"file_sizes\": {\"blocks", "\"file_sizes\": {\"logs")),
+            Err(OnboardingError::InvalidField("chunks[0].file_sizes.logs.parquet".to_string()))
+        );
+        assert_eq!(
+            parse_manifest(&manifest_json(&[(0, 10, None), (5, 20, None)])),
+            Err(OnboardingError::OverlappingChunks(0..10, 5..20))
+        );
+        assert_eq!(parse_manifest(&manifest_json(&[(10, 10, None)])), Err(OnboardingError::InvalidField("chunks[0].block_range".to_string())));
+        assert_eq!(
+            parse_manifest(r#"{"dataset_id": "07", "chunks": []}"#),
+            Err(OnboardingError::InvalidField("dataset_id".to_string()))
+        );
+        assert!(matches!(parse_manifest("{"), Err(OnboardingError::InvalidJson(_))));
+    }
+
+    #[test]
+    fn test_onboarding_reports_without_downloading() {
+        // Arrange
+        let dir = test_support::temp_dir("onboarding");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data"))
+                .with_catalogue_file(dir.join("registry.parquet"))
+                .with_download_bandwidth(BandwidthLimit::new(1000)))
+            .build();
+        // 10 chunks of 10 blocks, the first one is 2000 bytes by the manifest, the sampled ones are 1000 bytes
+        let chunks: Vec<(u64, u64, Option<u64>)> = (0..10).map(|i| (i * 10, i * 10 + 10, (i == 0).then_some(2000))).collect();
+        let source = Arc::new(ManifestSource { manifest: manifest_json(&chunks), urls: Mutex::new(Vec::new()) });
+        let options = OnboardingOptions::new(source.clone()).with_size_samples(1).with_quota_bytes(10_000);
+
+        // Act
+        let report = data_manager.onboard_dataset("https://example.com/manifest.json", &options).unwrap();
+
+        // Assert
+        assert_eq!(report.plan.downloads.len(), 10);
+        assert_eq!(report.sized_chunks, 2);
+        // 3000 bytes for the 20 sized blocks, extrapolated to 80 more blocks
+        assert_eq!(report.download_bytes, 15_000);
+        assert_eq!(report.estimated_download_time, Some(Duration::from_secs(15)));
+        assert_eq!(report.issues, vec![OnboardingIssue::QuotaExceeded { required_bytes: 15_000, quota_bytes: 10_000 }]);
+        assert!(!report.is_feasible());
+        assert_eq!(*source.urls.lock().unwrap(), vec!["https://example.com/manifest.json".to_string(), "https://example.com/10_20/blocks.parquet".to_string()]);
+        assert!(data_manager.get_chunk_info(report.manifest.chunks[0].id).is_none());
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
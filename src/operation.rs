@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use crate::data_chunk::ChunkId;
+
+/// Number of results kept by the `OperationLog`, the oldest ones are dropped first
+const OPERATION_LOG_CAPACITY: usize = 1000;
+
+/// What a background operation did to the chunk
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OperationKind {
+    Download,
+    Delete,
+    /// Deletion to free space, rather than on request
+    Evict,
+}
+
+/// Outcome of a background operation on a chunk, see `DataManagerImpl::operation_results`
+#[derive(Clone, Debug, PartialEq)]
+pub struct OperationResult {
+    pub kind: OperationKind,
+    pub chunk_id: ChunkId,
+    /// From the request of the operation, including the waits for a download slot or for the readers of the chunk
+    pub duration: Duration,
+    /// Size of the files downloaded or deleted, `None` when the operation failed or the storage doesn't tell
+    pub bytes: Option<u64>,
+    pub error: Option<String>,
+}
+
+impl OperationResult {
+    pub fn succeeded(kind: OperationKind, chunk_id: ChunkId, started_at: Instant, bytes: Option<u64>) -> Self {
+        OperationResult { kind, chunk_id, duration: started_at.elapsed(), bytes, error: None }
+    }
+
+    pub fn failed(kind: OperationKind, chunk_id: ChunkId, started_at: Instant, error: &io::Error) -> Self {
+        OperationResult { kind, chunk_id, duration: started_at.elapsed(), bytes: None, error: Some(error.to_string()) }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl fmt::Display for OperationResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} of the chunk {} ", self.kind, hex::encode(self.chunk_id))?;
+        match &self.error {
+            Some(error) => write!(f, "failed after {:?}: {}", self.duration, error),
+            None => write!(f, "completed in {:?}", self.duration),
+        }
+    }
+}
+
+/// Results of the latest background operations, oldest first, filled by the tasks manager as the operations finish
+#[derive(Clone, Default)]
+pub struct OperationLog {
+    results: Arc<RwLock<VecDeque<OperationResult>>>,
+}
+
+impl OperationLog {
+    pub fn record(&self, result: OperationResult) {
+        let mut results = self.results.write().unwrap();
+        if results.len() == OPERATION_LOG_CAPACITY {
+            results.pop_front();
+        }
+        results.push_back(result);
+    }
+
+    /// Results of the operations on the chunk, or of all the chunks when `None`
+    pub fn results(&self, chunk_id: Option<&ChunkId>) -> Vec<OperationResult> {
+        self.results.read().unwrap()
+            .iter()
+            .filter(|result| chunk_id.is_none_or(|chunk_id| result.chunk_id == *chunk_id))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Mutex;
+    use std::thread;
+    use crate::config::DataManagerConfig;
+    use crate::data_chunk::DataChunk;
+    use crate::data_manager::DataManager;
+    use crate::hooks::LifecycleHooks;
+    use crate::transfer::ChunkTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    #[test]
+    fn test_log_keeps_the_latest_results() {
+        // Arrange
+        let log = OperationLog::default();
+        let started_at = Instant::now();
+
+        // Act
+        for i in 0..OPERATION_LOG_CAPACITY + 2 {
+            log.record(OperationResult::succeeded(OperationKind::Download, [(i % 3) as u8; 32], started_at, Some(i as u64)));
+        }
+        log.record(OperationResult::failed(OperationKind::Delete, [1u8; 32], started_at, &io::Error::other("disk is read only")));
+
+        // Assert
+        let results = log.results(None);
+        assert_eq!(results.len(), OPERATION_LOG_CAPACITY);
+        assert_eq!(results[0].bytes, Some(3));
+        let last = log.results(Some(&[1u8; 32])).pop().unwrap();
+        assert!(!last.is_ok());
+        assert!(last.to_string().starts_with(&format!("Delete of the chunk {} failed after", hex::encode([1u8; 32]))));
+        assert!(log.results(Some(&[2u8; 32])).iter().all(|result| result.is_ok() && result.kind == OperationKind::Download));
+    }
+
+    /// Remote storage writing 6 bytes per file
+    struct CopyingTransfer;
+
+    impl ChunkTransfer for CopyingTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::create_dir_all(chunk_dir)?;
+            for file_name in chunk.files.keys() {
+                fs::write(chunk_dir.join(file_name), b"blocks")?;
+            }
+            Ok(())
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingHooks {
+        results: Mutex<Vec<OperationResult>>,
+    }
+
+    impl LifecycleHooks for RecordingHooks {
+        fn on_operation(&self, result: &OperationResult) {
+            self.results.lock().unwrap().push(result.clone());
+        }
+    }
+
+    #[test]
+    fn test_operation_results_reach_the_hooks_and_the_log() {
+        // Arrange
+        let dir = test_support::temp_dir("operations");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let hooks = Arc::new(RecordingHooks::default());
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(CopyingTransfer))
+            .lifecycle_hooks(hooks.clone())
+            .build();
+        let chunk = DataChunk::new([4u8; 32], 0..10).with_example_files(&["blocks.parquet", "logs.parquet"]);
+
+        // Act
+        data_manager.download_chunk(chunk.clone());
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(200));
+        });
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(200));
+        });
+
+        // Assert
+        let results = data_manager.operation_results(Some(chunk.id));
+        let summary: Vec<_> = results.iter().map(|result| (result.kind, result.bytes, result.error.clone())).collect();
+        assert_eq!(summary, vec![(OperationKind::Download, Some(12), None), (OperationKind::Delete, Some(12), None)]);
+        assert_eq!(*hooks.results.lock().unwrap(), results);
+        assert!(data_manager.operation_results(Some([5u8; 32])).is_empty());
+
+        // cleanup
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
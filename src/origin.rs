@@ -0,0 +1,69 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const PARQUET_MAGIC: &[u8; 4] = b"PAR1";
+
+/// Reads files of the chunks from the URLs they were downloaded from.
+/// Used to serve reads of files which turn out missing or corrupt, while the chunk is downloaded again.
+pub trait OriginFetcher: Send + Sync {
+    fn fetch(&self, url: &str) -> io::Result<Vec<u8>>;
+}
+
+/// Fetches `file://` URLs and plain paths, e.g. for origins on a shared filesystem
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileOriginFetcher;
+
+impl OriginFetcher for FileOriginFetcher {
+    fn fetch(&self, url: &str) -> io::Result<Vec<u8>> {
+        if url.contains("://") && !url.starts_with("file://") {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} is not a file URL", url)));
+        }
+        fs::read(url.trim_start_matches("file://"))
+    }
+}
+
+/// Whether the file can be read. Parquet files must start and end with the parquet magic,
+/// empty files are placeholders without any rows.
+pub fn is_intact(path: &Path) -> bool {
+    let Ok(metadata) = fs::metadata(path) else { return false };
+    if metadata.len() == 0 || path.extension().is_none_or(|extension| extension != "parquet") {
+        return metadata.is_file();
+    }
+    has_parquet_magic(path).unwrap_or(false)
+}
+
+fn has_parquet_magic(path: &Path) -> io::Result<bool> {
+    let mut file = File::open(path)?;
+    let (mut header, mut footer) = ([0u8; 4], [0u8; 4]);
+    file.read_exact(&mut header)?;
+    file.seek(SeekFrom::End(-4))?;
+    file.read_exact(&mut footer)?;
+    Ok(&header == PARQUET_MAGIC && &footer == PARQUET_MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support;
+    use super::*;
+
+    #[test]
+    fn test_corrupt_and_missing_files_are_not_intact() {
+        let dir = test_support::temp_dir("origin");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("valid.parquet"), b"PAR1 rows PAR1").unwrap();
+        fs::write(dir.join("corrupt.parquet"), b"PAR1 truncated").unwrap();
+        fs::write(dir.join("empty.parquet"), b"").unwrap();
+
+        assert!(is_intact(&dir.join("valid.parquet")));
+        assert!(is_intact(&dir.join("empty.parquet")));
+        assert!(!is_intact(&dir.join("corrupt.parquet")));
+        assert!(!is_intact(&dir.join("missing.parquet")));
+        let url = format!("file://{}", dir.join("valid.parquet").display());
+        assert_eq!(FileOriginFetcher.fetch(&url).unwrap(), b"PAR1 rows PAR1");
+        assert_eq!(FileOriginFetcher.fetch("https://example.com/part-1.parquet").unwrap_err().kind(), io::ErrorKind::Unsupported);
+
+        // cleanup
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+use crate::data_catalogue::{ChunkInfo, ChunkStatus};
+use crate::data_chunk::{ChunkId, DataChunk};
+
+/// What `download_chunk` does with a chunk overlapping other chunks of its dataset which are still being downloaded,
+/// e.g. when schedulers race and cut the same blocks differently
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OverlapPolicy {
+    /// The chunk isn't downloaded
+    #[default]
+    Reject,
+    /// The chunk is downloaded once the overlapping downloads have finished
+    QueueBehind,
+    /// The chunk is downloaded when it's larger than all the overlapping chunks, which are deleted once it's ready.
+    /// Otherwise it isn't downloaded.
+    PreferLarger,
+}
+
+/// Which ready chunk `find_chunk` returns when several chunks of the dataset hold the block,
+/// e.g. while a larger chunk replaces smaller ones. Chunks equal by the preference are ordered by their ids.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ChunkPreference {
+    /// The chunk which got ready last, then the one with the fewest blocks
+    #[default]
+    Newest,
+    /// The chunk with the fewest blocks, then the one which got ready last
+    SmallestRange,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum OverlapDecision {
+    Download,
+    Reject,
+    /// Wait for the downloads of these chunks first
+    QueueBehind(Vec<ChunkId>),
+    /// Download and replace these chunks
+    Replace(Vec<DataChunk>),
+}
+
+/// Other chunks of the dataset of `chunk` being downloaded, which share some blocks with it
+pub fn in_flight_overlaps<'a>(chunk: &DataChunk, catalogue_chunks: impl IntoIterator<Item = &'a ChunkInfo>) -> Vec<DataChunk> {
+    catalogue_chunks
+        .into_iter()
+        .filter(|info| info.status == ChunkStatus::Downloading)
+        .map(|info| &info.chunk)
+        .filter(|other| {
+            other.id != chunk.id
+                && other.dataset_id == chunk.dataset_id
+                && other.block_range.start < chunk.block_range.end
+                && chunk.block_range.start < other.block_range.end
+        })
+        .cloned()
+        .collect()
+}
+
+pub(crate) fn resolve(policy: OverlapPolicy, chunk: &DataChunk, overlaps: Vec<DataChunk>) -> OverlapDecision {
+    if overlaps.is_empty() {
+        return OverlapDecision::Download;
+    }
+    match policy {
+        OverlapPolicy::Reject => OverlapDecision::Reject,
+        OverlapPolicy::QueueBehind => OverlapDecision::QueueBehind(overlaps.iter().map(|other| other.id).collect()),
+        OverlapPolicy::PreferLarger => {
+            let blocks = |chunk: &DataChunk| chunk.block_range.end - chunk.block_range.start;
+            if overlaps.iter().all(|other| blocks(other) < blocks(chunk)) {
+                OverlapDecision::Replace(overlaps)
+            } else {
+                OverlapDecision::Reject
+            }
+        }
+    }
+}
+
+/// The chunk of the candidates the preference picks, independent of the order of the candidates
+pub(crate) fn preferred<'a>(preference: ChunkPreference, candidates: impl IntoIterator<Item = &'a ChunkInfo>) -> Option<&'a ChunkInfo> {
+    let blocks = |info: &ChunkInfo| info.chunk.block_range.end - info.chunk.block_range.start;
+    candidates.into_iter().min_by(|a, b| {
+        let newest = b.updated_at.cmp(&a.updated_at);
+        let smallest = blocks(a).cmp(&blocks(b));
+        match preference {
+            ChunkPreference::Newest => newest.then(smallest),
+            ChunkPreference::SmallestRange => smallest.then(newest),
+        }.then_with(|| a.chunk.id.cmp(&b.chunk.id))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use crate::test_support::chunk;
+    use super::*;
+
+    #[test]
+    fn test_only_in_flight_overlaps_of_the_dataset() {
+        // Arrange
+        let catalogue_chunks = [
+            ChunkInfo::new(chunk(1, 0..100), ChunkStatus::Downloading),
+            ChunkInfo::new(chunk(1, 100..200), ChunkStatus::Downloading),
+            ChunkInfo::new(chunk(1, 50..150), ChunkStatus::Ready),
+            ChunkInfo::new(chunk(2, 0..100), ChunkStatus::Downloading),
+        ];
+
+        // Act
+        let overlaps = in_flight_overlaps(&chunk(1, 90..100), catalogue_chunks.iter());
+
+        // Assert
+        assert_eq!(overlaps, vec![chunk(1, 0..100)]);
+        assert_eq!(in_flight_overlaps(&chunk(1, 0..100), catalogue_chunks.iter()), vec![]);
+    }
+
+    #[test]
+    fn test_resolve_policies() {
+        let in_flight = vec![chunk(1, 0..100)];
+        assert_eq!(resolve(OverlapPolicy::Reject, &chunk(1, 50..150), vec![]), OverlapDecision::Download);
+        assert_eq!(resolve(OverlapPolicy::Reject, &chunk(1, 50..150), in_flight.clone()), OverlapDecision::Reject);
+        assert_eq!(resolve(OverlapPolicy::QueueBehind, &chunk(1, 50..150), in_flight.clone()), OverlapDecision::QueueBehind(vec![in_flight[0].id]));
+        assert_eq!(resolve(OverlapPolicy::PreferLarger, &chunk(1, 50..150), in_flight.clone()), OverlapDecision::Reject);
+        assert_eq!(resolve(OverlapPolicy::PreferLarger, &chunk(1, 0..150), in_flight.clone()), OverlapDecision::Replace(in_flight));
+    }
+
+    #[test]
+    fn test_preferred_chunk_is_independent_of_order() {
+        // Arrange
+        let started_at = Instant::now();
+        let ready_at = |chunk: DataChunk, millis: u64| ChunkInfo { updated_at: started_at + Duration::from_millis(millis), ..ChunkInfo::new(chunk, ChunkStatus::Ready) };
+        let mut holding = [
+            ready_at(chunk(1, 0..100), 10),
+            ready_at(chunk(1, 0..1000), 20),
+            ready_at(chunk(1, 50..150), 10),
+        ];
+
+        // Act
+        let newest = preferred(ChunkPreference::Newest, holding.iter()).map(|info| info.chunk.clone());
+        let smallest = preferred(ChunkPreference::SmallestRange, holding.iter()).map(|info| info.chunk.clone());
+        holding.reverse();
+        let smallest_reversed = preferred(ChunkPreference::SmallestRange, holding.iter()).map(|info| info.chunk.clone());
+
+        // Assert
+        assert_eq!(newest, Some(chunk(1, 0..1000)));
+        // the chunks of 100 blocks ready at the same time are ordered by their ids
+        let (first, second) = (chunk(1, 0..100), chunk(1, 50..150));
+        let lower_id = if first.id < second.id { first } else { second };
+        assert_eq!(smallest, Some(lower_id.clone()));
+        assert_eq!(smallest_reversed, Some(lower_id));
+        assert!(preferred(ChunkPreference::Newest, []).is_none());
+    }
+}
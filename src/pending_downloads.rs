@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use serde_json::json;
+use crate::checksum::FileChecksums;
+use crate::data_chunk::{ChunkId, DataChunk};
+use crate::fair_queue::DownloadPriority;
+#[cfg(feature = "dataframes")]
+use {std::fs::File, polars::prelude::*};
+#[cfg(not(feature = "dataframes"))]
+use crate::jsonl::{self, Row};
+
+/// A download requested but not finished yet
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingDownload {
+    pub chunk: DataChunk,
+    pub priority: DownloadPriority,
+    /// Checksums the download was requested with, see `download_chunk_with_checksums`, empty when none
+    pub checksums: FileChecksums,
+    /// Order of the requests, the downloads are resumed in it
+    sequence: u64,
+}
+
+/// Downloads requested and not yet `Ready`, `Failed` or `Deleted`, persisted next to the catalogue on every change, once per batch for `add_all`,
+/// so the downloads queued or running when the process stopped are requested again on the next start.
+/// The file is removed once no download is pending.
+#[derive(Clone)]
+pub struct PendingDownloads {
+    pending: Arc<Mutex<HashMap<ChunkId, PendingDownload>>>,
+    file_path: PathBuf,
+}
+
+impl PendingDownloads {
+    /// Load the downloads saved in the file, a missing or unreadable file has none
+    pub fn open(file_path: &Path) -> Self {
+        let pending = read_pending(file_path).into_iter().map(|download| (download.chunk.id, download)).collect();
+        PendingDownloads { pending: Arc::new(Mutex::new(pending)), file_path: file_path.to_path_buf() }
+    }
+
+    /// Record the requested download and save the queue. A chunk requested again keeps its place in the queue.
+    pub fn add(&self, chunk: &DataChunk, priority: DownloadPriority, checksums: FileChecksums) -> io::Result<()> {
+        self.add_all([(chunk, priority, checksums)])
+    }
+
+    /// Same as `add` for every download in order, saving the queue once, or not at all when there are none
+    pub fn add_all<'a>(&self, downloads: impl IntoIterator<Item = (&'a DataChunk, DownloadPriority, FileChecksums)>) -> io::Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        let mut added = false;
+        for (chunk, priority, checksums) in downloads {
+            let sequence = pending.get(&chunk.id)
+                .map(|download| download.sequence)
+                .unwrap_or_else(|| pending.values().map(|download| download.sequence + 1).max().unwrap_or_default());
+            pending.insert(chunk.id, PendingDownload { chunk: chunk.clone(), priority, checksums, sequence });
+            added = true;
+        }
+        if !added {
+            return Ok(());
+        }
+        // saved under the lock, so concurrent changes can't overwrite each other
+        self.save(&pending)
+    }
+
+    /// Drop the download once it finished or was given up, saving the queue when it was pending
+    pub fn remove(&self, chunk_id: &ChunkId) -> io::Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.remove(chunk_id) {
+            Some(_) => self.save(&pending),
+            None => Ok(()),
+        }
+    }
+
+    /// The pending downloads in the order they were requested
+    pub fn downloads(&self) -> Vec<PendingDownload> {
+        let mut downloads: Vec<PendingDownload> = self.pending.lock().unwrap().values().cloned().collect();
+        downloads.sort_by_key(|download| download.sequence);
+        downloads
+    }
+
+    fn save(&self, pending: &HashMap<ChunkId, PendingDownload>) -> io::Result<()> {
+        if pending.is_empty() {
+            return match fs::remove_file(&self.file_path) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+                _ => Ok(()),
+            };
+        }
+        let mut downloads: Vec<&PendingDownload> = pending.values().collect();
+        downloads.sort_by_key(|download| download.sequence);
+        write_pending(&self.file_path, &downloads)
+    }
+}
+
+fn priority_name(priority: DownloadPriority) -> &'static str {
+    match priority {
+        DownloadPriority::High => "high",
+        DownloadPriority::Normal => "normal",
+    }
+}
+
+fn parse_priority(priority: &str) -> DownloadPriority {
+    match priority {
+        "high" => DownloadPriority::High,
+        _ => DownloadPriority::Normal,
+    }
+}
+
+/// The chunk in the form persisted in the queue, with the columns of the catalogue
+fn chunk_to_json(chunk: &DataChunk) -> String {
+    json!({
+        "id": hex::encode(chunk.id),
+        "dataset_id": hex::encode(chunk.dataset_id),
+        "block_from": chunk.block_range.start,
+        "block_to": chunk.block_range.end,
+        "files": chunk.files,
+        "mirrors": chunk.mirrors,
+        "size": chunk.size,
+        "file_sizes": chunk.file_sizes,
+    }).to_string()
+}
+
+fn chunk_from_json(chunk: &str) -> Option<DataChunk> {
+    let chunk: serde_json::Value = serde_json::from_str(chunk).ok()?;
+    let id = |column: &str| -> Option<[u8; 32]> { hex::decode(chunk.get(column)?.as_str()?).ok()?.try_into().ok() };
+    Some(DataChunk {
+        id: id("id")?,
+        dataset_id: id("dataset_id")?,
+        block_range: chunk.get("block_from")?.as_u64()?..chunk.get("block_to")?.as_u64()?,
+        files: serde_json::from_value(chunk.get("files")?.clone()).ok()?,
+        mirrors: chunk.get("mirrors").and_then(|mirrors| serde_json::from_value(mirrors.clone()).ok()).unwrap_or_default(),
+        size: chunk.get("size").and_then(|size| size.as_u64()),
+        file_sizes: chunk.get("file_sizes").and_then(|file_sizes| serde_json::from_value(file_sizes.clone()).ok()).unwrap_or_default(),
+    })
+}
+
+/// Rows which can't be read are skipped, their chunks are requested again by the next sync
+fn pending_download(sequence: u64, chunk: &str, priority: &str, checksums: &str) -> Option<PendingDownload> {
+    Some(PendingDownload {
+        chunk: chunk_from_json(chunk)?,
+        priority: parse_priority(priority),
+        checksums: serde_json::from_str(checksums).unwrap_or_default(),
+        sequence,
+    })
+}
+
+#[cfg(feature = "dataframes")]
+fn write_pending(file_path: &Path, downloads: &[&PendingDownload]) -> io::Result<()> {
+    let mut df = df!(
+        "sequence" => downloads.iter().map(|download| download.sequence).collect::<Vec<u64>>(),
+        "chunk" => downloads.iter().map(|download| chunk_to_json(&download.chunk)).collect::<Vec<String>>(),
+        "priority" => downloads.iter().map(|download| priority_name(download.priority)).collect::<Vec<&str>>(),
+        "checksums" => downloads.iter().map(|download| serde_json::to_string(&download.checksums).unwrap()).collect::<Vec<String>>()
+    ).map_err(polars_error)?;
+    ParquetWriter::new(File::create(file_path)?).finish(&mut df).map_err(polars_error)?;
+    Ok(())
+}
+
+#[cfg(feature = "dataframes")]
+fn read_pending(file_path: &Path) -> Vec<PendingDownload> {
+    File::open(file_path).map_err(PolarsError::from)
+        .and_then(|file| ParquetReader::new(file).finish())
+        .and_then(|df| pending_from_dataframe(&df))
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "dataframes")]
+fn pending_from_dataframe(df: &DataFrame) -> PolarsResult<Vec<PendingDownload>> {
+    let sequences = df.column("sequence")?.u64()?;
+    let chunks = df.column("chunk")?.str()?;
+    let priorities = df.column("priority")?.str()?;
+    let checksums = df.column("checksums")?.str()?;
+    Ok((0..df.height())
+        .filter_map(|i| pending_download(sequences.get(i)?, chunks.get(i)?, priorities.get(i)?, checksums.get(i)?))
+        .collect())
+}
+
+#[cfg(feature = "dataframes")]
+fn polars_error(error: PolarsError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(not(feature = "dataframes"))]
+fn write_pending(file_path: &Path, downloads: &[&PendingDownload]) -> io::Result<()> {
+    jsonl::write_rows(file_path, downloads.iter().map(|download| {
+        let mut row = Row::new();
+        row.insert("sequence".to_string(), download.sequence.into());
+        row.insert("chunk".to_string(), chunk_to_json(&download.chunk).into());
+        row.insert("priority".to_string(), priority_name(download.priority).into());
+        row.insert("checksums".to_string(), serde_json::to_string(&download.checksums).unwrap().into());
+        row
+    }))
+}
+
+#[cfg(not(feature = "dataframes"))]
+fn read_pending(file_path: &Path) -> Vec<PendingDownload> {
+    jsonl::read_rows(file_path).unwrap_or_default()
+        .iter()
+        .filter_map(|row| pending_download(
+            jsonl::u64_column(row, "sequence")?,
+            jsonl::str_column(row, "chunk")?,
+            jsonl::str_column(row, "priority")?,
+            jsonl::str_column(row, "checksums")?,
+        ))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+    use std::thread;
+    use std::time::Duration;
+    use crate::builder::PENDING_DOWNLOADS_FILE;
+    use crate::data_catalogue::ChunkStatus;
+    use crate::test_support;
+    use super::*;
+
+    fn chunk(block_range: Range<u64>) -> DataChunk {
+        DataChunk { size: Some(6), ..test_support::chunk(11, block_range) }
+    }
+
+    #[test]
+    fn test_queue_is_saved_in_request_order() {
+        // Arrange
+        let file_path = test_support::temp_dir("pending_queue").with_extension("parquet");
+        let queue = PendingDownloads::open(&file_path);
+        let (first, second) = (chunk(0..10), chunk(10..20));
+        let checksums = FileChecksums::from([("blocks.parquet".to_string(), sha256::digest("blocks"))]);
+
+        // Act
+        queue.add(&first, DownloadPriority::Normal, FileChecksums::new()).unwrap();
+        queue.add(&second, DownloadPriority::High, checksums.clone()).unwrap();
+        queue.add(&first, DownloadPriority::High, FileChecksums::new()).unwrap();
+        let reopened = PendingDownloads::open(&file_path).downloads();
+        queue.remove(&first.id).unwrap();
+        queue.remove(&second.id).unwrap();
+
+        // Assert
+        assert_eq!(reopened.iter().map(|download| download.chunk.clone()).collect::<Vec<_>>(), vec![first, second]);
+        assert_eq!(reopened.iter().map(|download| download.priority).collect::<Vec<_>>(), vec![DownloadPriority::High, DownloadPriority::High]);
+        assert_eq!(reopened[1].checksums, checksums);
+        assert!(!file_path.exists());
+        assert!(PendingDownloads::open(&file_path).downloads().is_empty());
+    }
+
+    #[test]
+    fn test_batch_is_saved_in_request_order() {
+        // Arrange
+        let file_path = test_support::temp_dir("pending_batch").with_extension("parquet");
+        let queue = PendingDownloads::open(&file_path);
+        let (first, second, third) = (chunk(0..10), chunk(10..20), chunk(20..30));
+        queue.add(&second, DownloadPriority::High, FileChecksums::new()).unwrap();
+
+        // Act
+        queue.add_all([]).unwrap();
+        let reopened_without_batch = PendingDownloads::open(&file_path).downloads();
+        queue.add_all([&first, &second, &third].map(|chunk| (chunk, DownloadPriority::Normal, FileChecksums::new()))).unwrap();
+        let reopened = PendingDownloads::open(&file_path).downloads();
+        for chunk in [&first, &second, &third] {
+            queue.remove(&chunk.id).unwrap();
+        }
+
+        // Assert
+        assert_eq!(reopened_without_batch.len(), 1);
+        // a chunk requested again keeps its place in the queue
+        assert_eq!(reopened.iter().map(|download| download.chunk.clone()).collect::<Vec<_>>(), vec![second, first, third]);
+        assert!(reopened.iter().all(|download| download.priority == DownloadPriority::Normal));
+        assert!(!file_path.exists());
+    }
+
+    #[test]
+    fn test_downloads_queued_before_a_restart_are_resumed() {
+        // Arrange
+        let dir = test_support::temp_dir("pending");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let (verified, corrupt) = (chunk(0..10), chunk(10..20));
+        let data_manager = test_support::manager_builder(&dir).build();
+        data_manager.pause_downloads();
+        data_manager.download_chunk_with_checksums(verified.clone(), FileChecksums::from([("blocks.parquet".to_string(), sha256::digest("blocks"))]));
+        data_manager.download_chunk_with_checksums(corrupt.clone(), FileChecksums::from([("blocks.parquet".to_string(), sha256::digest("other"))]));
+        drop(data_manager);
+
+        // Act
+        let restarted = test_support::manager_builder(&dir).build();
+        restarted.data_catalogue.wait_until_downloaded(&[verified.id, corrupt.id]);
+        // the status listeners run after the waiting threads are woken up
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(100));
+        });
+
+        // Assert
+        assert_eq!(restarted.get_chunk_info(verified.id).map(|info| info.status), Some(ChunkStatus::Ready));
+        assert_eq!(restarted.get_chunk_info(corrupt.id).map(|info| info.status), Some(ChunkStatus::Failed));
+        assert!(restarted.pending_downloads.downloads().is_empty());
+        assert!(!dir.join(PENDING_DOWNLOADS_FILE).exists());
+
+        // cleanup
+        drop(restarted);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
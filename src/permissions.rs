@@ -0,0 +1,234 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Modes and ownership given to the chunk directories and their files, when they're downloaded or registered
+#[derive(Clone, Debug, PartialEq)]
+pub struct PermissionsConfig {
+    /// Mode of the chunk files, e.g. readable by a query engine running as another user of the group
+    pub file_mode: u32,
+    /// Mode of the chunk directory and its subdirectories
+    pub dir_mode: u32,
+    /// Owner of the directories and files, left as it is when `None`. Changing it needs the privileges to do so.
+    pub uid: Option<u32>,
+    /// Group of the directories and files, left as it is when `None`
+    pub gid: Option<u32>,
+}
+
+impl Default for PermissionsConfig {
+    fn default() -> Self {
+        PermissionsConfig { file_mode: 0o644, dir_mode: 0o755, uid: None, gid: None }
+    }
+}
+
+/// Give the directory, its subdirectories and its files the modes and the ownership of the config.
+/// Modes and owners which are already right aren't touched, so files placed by another user, e.g. a sidecar run as root,
+/// pass as long as they're as configured. Symlinks are neither changed nor followed, as their targets may be anywhere.
+/// Only unix modes are supported, elsewhere nothing changes.
+pub fn normalize(dir: &Path, config: &PermissionsConfig) -> io::Result<()> {
+    if fs::symlink_metadata(dir)?.file_type().is_symlink() {
+        return Ok(());
+    }
+    normalize_entry(dir, true, config)?;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            continue;
+        }
+        if file_type.is_dir() {
+            normalize(&entry.path(), config)?;
+        } else {
+            normalize_entry(&entry.path(), false, config)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn normalize_entry(path: &Path, is_dir: bool, config: &PermissionsConfig) -> io::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    let metadata = fs::symlink_metadata(path)?;
+    // the owner may change first, so the mode is set by the new owner
+    let uid = config.uid.filter(|uid| *uid != metadata.uid());
+    let gid = config.gid.filter(|gid| *gid != metadata.gid());
+    if uid.is_some() || gid.is_some() {
+        // never the target of a symlink swapped in since the metadata was read
+        std::os::unix::fs::lchown(path, uid, gid).map_err(|error| annotate(error, "can't change the owner of", path))?;
+    }
+    let mode = if is_dir { config.dir_mode } else { config.file_mode };
+    if metadata.mode() & 0o7777 != mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).map_err(|error| annotate(error, "can't change the mode of", path))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn normalize_entry(_path: &Path, _is_dir: bool, _config: &PermissionsConfig) -> io::Result<()> {
+    Ok(())
+}
+
+/// First directory whose entries the process can't remove, among the directory, its parent and its subdirectories,
+/// e.g. a chunk directory owned by root and not writable by the data manager, which would fail its deletion later
+pub fn undeletable_path(dir: &Path) -> io::Result<Option<PathBuf>> {
+    if let Some(parent) = dir.parent() {
+        if !can_remove_entries(parent)? {
+            return Ok(Some(parent.to_path_buf()));
+        }
+    }
+    undeletable_subdir(dir)
+}
+
+fn undeletable_subdir(dir: &Path) -> io::Result<Option<PathBuf>> {
+    if !can_remove_entries(dir)? {
+        return Ok(Some(dir.to_path_buf()));
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            if let Some(path) = undeletable_subdir(&entry.path())? {
+                return Ok(Some(path));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Removing an entry of a directory takes the permissions to write to it and to search it
+#[cfg(unix)]
+fn can_remove_entries(dir: &Path) -> io::Result<bool> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    let path = CString::new(dir.as_os_str().as_bytes()).map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    Ok(unsafe { libc::access(path.as_ptr(), libc::W_OK | libc::X_OK) } == 0)
+}
+
+#[cfg(not(unix))]
+fn can_remove_entries(dir: &Path) -> io::Result<bool> {
+    Ok(!fs::metadata(dir)?.permissions().readonly())
+}
+
+/// Normalize the chunk directory and check its files can be deleted later, with the error naming the offending path
+pub(crate) fn prepare_chunk_dir(chunk_dir: &Path, config: Option<&PermissionsConfig>) -> io::Result<()> {
+    if let Some(config) = config {
+        normalize(chunk_dir, config)?;
+    }
+    match undeletable_path(chunk_dir)? {
+        Some(path) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("chunk files couldn't be deleted, {} isn't writable by the data manager", path.display()),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn annotate(error: io::Error, action: &str, path: &Path) -> io::Error {
+    io::Error::new(error.kind(), format!("{} {}: {}", action, path.display(), error))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use crate::config::DataManagerConfig;
+    use crate::registration::RegisterError;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o7777
+    }
+
+    #[test]
+    fn test_normalize_and_check_deletable() {
+        // Arrange
+        let dir = test_support::temp_dir("permissions");
+        fs::create_dir_all(dir.join("chunk/nested")).unwrap();
+        fs::write(dir.join("chunk/blocks.parquet"), b"blocks").unwrap();
+        fs::write(dir.join("chunk/nested/logs.parquet"), b"logs").unwrap();
+        fs::set_permissions(dir.join("chunk/blocks.parquet"), fs::Permissions::from_mode(0o600)).unwrap();
+        fs::set_permissions(dir.join("chunk/nested"), fs::Permissions::from_mode(0o700)).unwrap();
+
+        // Act
+        normalize(&dir.join("chunk"), &PermissionsConfig { file_mode: 0o640, ..PermissionsConfig::default() }).unwrap();
+        let deletable = undeletable_path(&dir.join("chunk")).unwrap();
+        let nested_mode = mode(&dir.join("chunk/nested"));
+        fs::set_permissions(dir.join("chunk/nested"), fs::Permissions::from_mode(0o555)).unwrap();
+        let read_only = undeletable_path(&dir.join("chunk")).unwrap();
+
+        // Assert
+        assert_eq!(mode(&dir.join("chunk/blocks.parquet")), 0o640);
+        assert_eq!(mode(&dir.join("chunk/nested/logs.parquet")), 0o640);
+        assert_eq!(nested_mode, 0o755);
+        assert_eq!(deletable, None);
+        // root may remove the entries of any directory
+        let is_root = unsafe { libc::geteuid() } == 0;
+        assert_eq!(read_only, (!is_root).then(|| dir.join("chunk/nested")));
+
+        // cleanup
+        fs::set_permissions(dir.join("chunk/nested"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_symlinks_are_not_followed() {
+        // Arrange
+        let dir = test_support::temp_dir("permissions_symlinks");
+        fs::create_dir_all(dir.join("chunk")).unwrap();
+        fs::create_dir_all(dir.join("outside/nested")).unwrap();
+        fs::write(dir.join("outside/secret"), b"secret").unwrap();
+        fs::set_permissions(dir.join("outside/secret"), fs::Permissions::from_mode(0o600)).unwrap();
+        fs::set_permissions(dir.join("outside/nested"), fs::Permissions::from_mode(0o700)).unwrap();
+        std::os::unix::fs::symlink(dir.join("outside/secret"), dir.join("chunk/blocks.parquet")).unwrap();
+        std::os::unix::fs::symlink(dir.join("outside"), dir.join("chunk/linked")).unwrap();
+
+        // Act
+        normalize(&dir.join("chunk"), &PermissionsConfig::default()).unwrap();
+
+        // Assert
+        assert_eq!(mode(&dir.join("outside/secret")), 0o600);
+        assert_eq!(mode(&dir.join("outside/nested")), 0o700);
+
+        // cleanup
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_register_chunk_fails_without_permissions() {
+        // Arrange
+        let dir = test_support::temp_dir("permissions_register");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        // an owner the data manager can't hand the files over to, unless it runs as root
+        let is_root = unsafe { libc::geteuid() } == 0;
+        let foreign_uid = if is_root { 0 } else { (unsafe { libc::geteuid() }) + 1 };
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data"))
+                .with_catalogue_file(dir.join("registry.parquet"))
+                .with_permissions(Some(PermissionsConfig { uid: Some(foreign_uid), ..PermissionsConfig::default() })))
+            .build();
+        let chunk = test_support::chunk(21, 0..10);
+        let chunk_dir = data_manager.data_source.chunk_path(chunk.clone()).path;
+        fs::create_dir_all(&chunk_dir).unwrap();
+        fs::write(chunk_dir.join("blocks.parquet"), b"blocks").unwrap();
+        fs::set_permissions(chunk_dir.join("blocks.parquet"), fs::Permissions::from_mode(0o600)).unwrap();
+
+        // Act
+        let result = data_manager.register_chunk(chunk.clone(), true);
+
+        // Assert
+        match is_root {
+            true => {
+                assert_eq!(result, Ok(()));
+                assert_eq!(mode(&chunk_dir.join("blocks.parquet")), 0o644);
+            }
+            false => {
+                assert!(matches!(result, Err(RegisterError::Permissions(message)) if message.contains("can't change the owner of")));
+                assert!(data_manager.get_chunk_info(chunk.id).is_none());
+            }
+        }
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use crate::data_catalogue::{ChunkInfo, ChunkStatus};
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
+
+/// Failure domains of the volumes holding the chunks, e.g. the disk, controller or rack of a data directory,
+/// and the datasets whose replicas must not share a domain. Replicas are the `Ready` chunks of a dataset sharing blocks,
+/// e.g. the same blocks kept in chunks of different sizes on different volumes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlacementPolicy {
+    /// Domain of every volume, a volume without one is a domain of its own
+    pub failure_domains: HashMap<PathBuf, String>,
+    /// Datasets whose replicas must be in different domains
+    pub separated_datasets: BTreeSet<DatasetId>,
+}
+
+impl PlacementPolicy {
+    /// Domain of the volume, its path when it isn't tagged
+    pub fn domain_of(&self, volume: &Path) -> String {
+        self.failure_domains.get(volume).cloned().unwrap_or_else(|| volume.display().to_string())
+    }
+}
+
+/// Two replicas of a separated dataset in the same failure domain
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlacementViolation {
+    pub dataset_id: DatasetId,
+    pub domain: String,
+    /// The chunk starting first, or the shorter one when they start at the same block
+    pub chunk_id: ChunkId,
+    pub other_chunk_id: ChunkId,
+}
+
+fn overlaps(chunk: &DataChunk, other: &DataChunk) -> bool {
+    chunk.dataset_id == other.dataset_id
+        && chunk.id != other.id
+        && chunk.block_range.start < other.block_range.end
+        && other.block_range.start < chunk.block_range.end
+}
+
+/// Volume of the chunk, the data directory unless it was relocated
+fn volume_of<'a>(info: &'a ChunkInfo, data_dir: &'a Path) -> &'a Path {
+    info.volume.as_deref().unwrap_or(data_dir)
+}
+
+/// A replica of the chunk the volume would share its domain with, when its dataset is separated
+pub(crate) fn conflicting_replica<'a>(
+    policy: &PlacementPolicy,
+    data_dir: &Path,
+    chunk: &DataChunk,
+    volume: &Path,
+    infos: impl IntoIterator<Item = &'a ChunkInfo>,
+) -> Option<(String, ChunkId)> {
+    if !policy.separated_datasets.contains(&chunk.dataset_id) {
+        return None;
+    }
+    let domain = policy.domain_of(volume);
+    infos.into_iter()
+        .filter(|info| info.status == ChunkStatus::Ready && overlaps(chunk, &info.chunk))
+        .find(|info| policy.domain_of(volume_of(info, data_dir)) == domain)
+        .map(|info| (domain.clone(), info.chunk.id))
+}
+
+/// Replicas of the separated datasets sharing a domain, ordered by dataset and blocks
+pub(crate) fn violations<'a>(policy: &PlacementPolicy, data_dir: &Path, infos: impl IntoIterator<Item = &'a ChunkInfo>) -> Vec<PlacementViolation> {
+    let mut replicas: Vec<&ChunkInfo> = infos.into_iter()
+        .filter(|info| info.status == ChunkStatus::Ready && policy.separated_datasets.contains(&info.chunk.dataset_id))
+        .collect();
+    replicas.sort_by_key(|info| (info.chunk.dataset_id, info.chunk.block_range.start, info.chunk.block_range.end));
+    let mut violations = Vec::new();
+    for (i, info) in replicas.iter().enumerate() {
+        let domain = policy.domain_of(volume_of(info, data_dir));
+        for other in replicas[i + 1..].iter().take_while(|other| other.chunk.dataset_id == info.chunk.dataset_id) {
+            if overlaps(&info.chunk, &other.chunk) && policy.domain_of(volume_of(other, data_dir)) == domain {
+                violations.push(PlacementViolation {
+                    dataset_id: info.chunk.dataset_id,
+                    domain: domain.clone(),
+                    chunk_id: info.chunk.id,
+                    other_chunk_id: other.chunk.id,
+                });
+            }
+        }
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::Arc;
+    use crate::config::DataManagerConfig;
+    use crate::relocation::RelocateError;
+    use crate::DataManagerImpl;
+    use crate::test_support::{self, chunk};
+    use super::*;
+
+    fn ready(chunk: DataChunk, volume: Option<&str>) -> ChunkInfo {
+        ChunkInfo { volume: volume.map(PathBuf::from), ..ChunkInfo::new(chunk, ChunkStatus::Ready) }
+    }
+
+    #[test]
+    fn test_violations() {
+        let policy = PlacementPolicy {
+            failure_domains: HashMap::from([(PathBuf::from("/disk1"), "rack-a".to_string()), (PathBuf::from("/disk2"), "rack-a".to_string())]),
+            separated_datasets: BTreeSet::from([[1u8; 32]]),
+        };
+        let data_dir = Path::new("/data");
+        let (whole, first_half, second_half) = (chunk(1, 0..20), chunk(1, 0..10), chunk(1, 10..20));
+        let infos = [
+            ready(whole.clone(), Some("/disk1")),
+            ready(first_half.clone(), Some("/disk2")),
+            ready(second_half.clone(), None),
+            // datasets which aren't separated may share a domain
+            ready(chunk(2, 0..20), Some("/disk1")),
+            ready(chunk(2, 0..10), Some("/disk1")),
+        ];
+
+        assert_eq!(policy.domain_of(Path::new("/disk1")), "rack-a");
+        assert_eq!(policy.domain_of(data_dir), "/data");
+        assert_eq!(violations(&policy, data_dir, infos.iter()), vec![PlacementViolation {
+            dataset_id: [1u8; 32],
+            domain: "rack-a".to_string(),
+            chunk_id: first_half.id,
+            other_chunk_id: whole.id,
+        }]);
+        assert_eq!(conflicting_replica(&policy, data_dir, &second_half, Path::new("/disk2"), infos.iter()), Some(("rack-a".to_string(), whole.id)));
+        assert_eq!(conflicting_replica(&policy, data_dir, &second_half, Path::new("/disk3"), infos.iter()), None);
+    }
+
+    #[test]
+    fn test_replicas_are_not_relocated_into_a_shared_domain() {
+        // Arrange
+        let dir = test_support::temp_dir("placement");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let config = DataManagerConfig::new(dir.join("data"))
+            .with_catalogue_file(dir.join("registry.parquet"))
+            .with_failure_domain(dir.join("disk1"), "rack-a")
+            .with_failure_domain(dir.join("disk2"), "rack-a")
+            .with_failure_domain(dir.join("disk3"), "rack-b")
+            .with_domain_separation([14u8; 32]);
+        let data_manager = DataManagerImpl::builder()
+            .config(config)
+            .chunk_transfer(Arc::new(test_support::PlaceholderTransfer))
+            .build();
+        let (whole, half) = (chunk(14, 0..20), chunk(14, 0..10));
+        // one after the other, the overlapping downloads are rejected
+        for replica in [&whole, &half] {
+            data_manager.download_chunk_with_handle(replica.clone()).wait().unwrap();
+        }
+        let shared_data_dir = data_manager.placement_violations();
+        data_manager.relocate_chunk(whole.id, dir.join("disk1")).unwrap();
+
+        // Act
+        let refused = data_manager.relocate_chunk(half.id, dir.join("disk2"));
+        let relocated = data_manager.relocate_chunk(half.id, dir.join("disk3"));
+
+        // Assert
+        assert_eq!(shared_data_dir.len(), 1);
+        assert_eq!(shared_data_dir[0].domain, dir.join("data").display().to_string());
+        assert_eq!(refused, Err(RelocateError::SharedFailureDomain("rack-a".to_string(), whole.id)));
+        assert!(relocated.is_ok());
+        assert!(data_manager.placement_violations().is_empty());
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
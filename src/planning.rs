@@ -0,0 +1,243 @@
+//! Planning core shared with schedulers and browsers.
+//!
+//! Nothing in here touches the filesystem, threads or clocks, so it builds without the `runtime` feature,
+//! e.g. `cargo build --no-default-features --target wasm32-unknown-unknown`,
+//! and planners compute exactly the same chunk ids and sync plans as the workers.
+use std::fmt;
+use std::ops::Range;
+use sha2::{Digest, Sha256};
+use crate::data_chunk::{ChunkId, DatasetId};
+
+pub use crate::chunk_filter::ChunkFilter;
+pub use crate::simulation::{simulate, Assignment, SimulationProfile, SimulationReport, StepReport};
+pub use crate::sync_plan::{plan_sync, Replacement, SyncPlan};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkStatus {
+    Downloading,
+    Ready,
+    Deleting,
+    Deleted,
+    /// The download didn't complete, the chunk can be downloaded again
+    Failed,
+}
+
+impl fmt::Display for ChunkStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// This function generates a unique chunk id from the dataset id and block range
+/// Note: To be used everywhere to have consistent chunk ids!!!
+pub fn generate_chunk_id(dataset_id: &DatasetId, block_range: &Range<u64>) -> ChunkId {
+    ChunkIdGenerator::new(dataset_id).chunk_id(block_range)
+}
+
+/// Same as `generate_chunk_id` for many block ranges of the dataset, e.g. when reconciling a large manifest
+pub fn generate_chunk_ids(dataset_id: &DatasetId, block_ranges: &[Range<u64>]) -> Vec<ChunkId> {
+    let generator = ChunkIdGenerator::new(dataset_id);
+    block_ranges.iter().map(|block_range| generator.chunk_id(block_range)).collect()
+}
+
+/// Generates the chunk ids of a dataset, the sha256 of `<dataset id as hex><start><end>`.
+/// The dataset id is encoded once, and the digest isn't converted through hex, so it's much cheaper per chunk than `generate_chunk_id`.
+/// It isn't changed by generating ids, so it can be shared by threads.
+#[derive(Clone, Debug)]
+pub struct ChunkIdGenerator {
+    dataset_hex: String,
+}
+
+impl ChunkIdGenerator {
+    pub fn new(dataset_id: &DatasetId) -> Self {
+        ChunkIdGenerator { dataset_hex: hex::encode(dataset_id) }
+    }
+
+    pub fn chunk_id(&self, block_range: &Range<u64>) -> ChunkId {
+        let mut hasher = Sha256::new();
+        hasher.update(self.dataset_hex.as_bytes());
+        hasher.update(block_range.start.to_string().as_bytes());
+        hasher.update(block_range.end.to_string().as_bytes());
+        hasher.finalize().into()
+    }
+}
+
+/// Naming of the dataset directories in the data directory.
+/// Names are parsed case-insensitively, so the chunks are found even when a case-insensitive filesystem
+/// or some tooling changes the case, and the chunk ids derived from them stay the same.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DirectoryLayout {
+    /// `dataset_id=<hex>/block_range=<start>_<end>`
+    #[default]
+    Hex,
+    /// `dataset_id=<base32>/block_range=<start>_<end>`, shorter names for filesystems with path length limits
+    Base32,
+    /// `shard=<first 2 base32 characters>/dataset_id=<base32>/block_range=<start>_<end>`,
+    /// keeps the directories small when there are many datasets
+    ShardedBase32,
+}
+
+impl DirectoryLayout {
+    /// Directory of the dataset relative to the data directory
+    pub fn dataset_dir(&self, dataset_id: &DatasetId) -> String {
+        match self {
+            DirectoryLayout::Hex => format!("dataset_id={}", hex::encode(dataset_id)),
+            DirectoryLayout::Base32 => format!("dataset_id={}", base32_encode(dataset_id)),
+            DirectoryLayout::ShardedBase32 => {
+                let encoded = base32_encode(dataset_id);
+                format!("shard={}/dataset_id={}", &encoded[..SHARD_LENGTH], encoded)
+            }
+        }
+    }
+
+    /// Directory of the chunk relative to the data directory
+    pub fn chunk_dir(&self, dataset_id: &DatasetId, block_range: &Range<u64>) -> String {
+        format!("{}/block_range={}_{}", self.dataset_dir(dataset_id), block_range.start, block_range.end)
+    }
+
+    /// Number of directory levels above the dataset directories
+    pub fn shard_depth(&self) -> usize {
+        match self {
+            DirectoryLayout::Hex | DirectoryLayout::Base32 => 0,
+            DirectoryLayout::ShardedBase32 => 1,
+        }
+    }
+
+    /// Parse the dataset directory relative to the data directory, with `/` separating its levels
+    pub fn parse_dataset_dir(&self, dataset_dir: &str) -> Option<DatasetId> {
+        match self {
+            DirectoryLayout::Hex => {
+                let dataset_id_str = strip_prefix_ignore_case(dataset_dir, "dataset_id=")?;
+                hex::decode(dataset_id_str).ok()?.try_into().ok()
+            }
+            DirectoryLayout::Base32 => base32_decode(strip_prefix_ignore_case(dataset_dir, "dataset_id=")?),
+            DirectoryLayout::ShardedBase32 => {
+                let (shard_dir, dataset_dir) = dataset_dir.split_once('/')?;
+                let shard = strip_prefix_ignore_case(shard_dir, "shard=")?;
+                let dataset_id_str = strip_prefix_ignore_case(dataset_dir, "dataset_id=")?;
+                // a dataset in a wrong shard would never be found by its path
+                if !dataset_id_str.get(..SHARD_LENGTH)?.eq_ignore_ascii_case(shard) {
+                    return None;
+                }
+                base32_decode(dataset_id_str)
+            }
+        }
+    }
+}
+
+pub fn parse_block_range_dir_name(dir_name: &str) -> Option<Range<u64>> {
+    let block_range = strip_prefix_ignore_case(dir_name, "block_range=")?;
+    let (block_start, block_end) = block_range.split_once('_')?;
+    Some(block_start.parse::<u64>().ok()?..block_end.parse::<u64>().ok()?)
+}
+
+fn strip_prefix_ignore_case<'a>(name: &'a str, prefix: &str) -> Option<&'a str> {
+    name.get(..prefix.len())?.eq_ignore_ascii_case(prefix).then(|| &name[prefix.len()..])
+}
+
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+const SHARD_LENGTH: usize = 2;
+
+/// Lowercase RFC 4648 base32 without padding
+pub(crate) fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+/// Case-insensitive, only the canonical encoding of a dataset id is accepted,
+/// so different names never decode to the same id
+fn base32_decode(encoded: &str) -> Option<DatasetId> {
+    base32_decode_bytes(encoded)?.try_into().ok()
+}
+
+/// Lowercase RFC 4648 base32 without padding, e.g. the multibase `b` encoding of CIDs
+pub(crate) fn base32_decode_bytes(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+    for char in encoded.bytes() {
+        let value = BASE32_ALPHABET.iter().position(|c| *c == char.to_ascii_lowercase())? as u16;
+        buffer = ((buffer << 5) | value) & 0xfff;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    if buffer & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_chunk_ids() {
+        let dataset_id = [17u8; 32];
+        let block_ranges = vec![95..106, 0..10, 10_000_000..10_000_001];
+        let chunk_ids = generate_chunk_ids(&dataset_id, &block_ranges);
+        for (block_range, chunk_id) in block_ranges.iter().zip(&chunk_ids) {
+            let digest = sha256::digest(format!("{}{}{}", hex::encode(dataset_id), block_range.start, block_range.end));
+            assert_eq!(hex::encode(chunk_id), digest);
+            assert_eq!(generate_chunk_id(&dataset_id, block_range), *chunk_id);
+        }
+        // the chunk of `./remote_data_dir`
+        assert_eq!(chunk_ids[0], [170, 13, 118, 225, 28, 2, 234, 149, 141, 239, 145, 9, 120, 116, 116, 137, 16, 29, 106, 129, 18, 70, 73, 152, 183, 85, 25, 49, 33, 116, 247, 65]);
+        assert!(generate_chunk_ids(&dataset_id, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_chunk_dir_round_trip() {
+        let dataset_id: DatasetId = std::array::from_fn(|i| (i * 37) as u8);
+        for layout in [DirectoryLayout::Hex, DirectoryLayout::Base32, DirectoryLayout::ShardedBase32] {
+            // Arrange
+            let dir = layout.chunk_dir(&dataset_id, &(0..35));
+
+            // Act
+            let (dataset_dir, block_range_dir) = dir.rsplit_once('/').unwrap();
+
+            // Assert
+            assert_eq!(layout.parse_dataset_dir(dataset_dir), Some(dataset_id), "{:?}", layout);
+            assert_eq!(layout.parse_dataset_dir(&dataset_dir.to_uppercase()), Some(dataset_id), "{:?}", layout);
+            assert_eq!(parse_block_range_dir_name(&block_range_dir.to_uppercase()), Some(0..35));
+        }
+    }
+
+    #[test]
+    fn test_base32_layout() {
+        assert_eq!(DirectoryLayout::Base32.dataset_dir(&[0u8; 32]), format!("dataset_id={}", "a".repeat(52)));
+        assert_eq!(DirectoryLayout::ShardedBase32.dataset_dir(&[255u8; 32]), format!("shard=77/dataset_id={}q", "7".repeat(51)));
+        // non canonical trailing bits and shards not matching the dataset
+        assert_eq!(DirectoryLayout::Base32.parse_dataset_dir(&format!("dataset_id={}b", "a".repeat(51))), None);
+        assert_eq!(DirectoryLayout::ShardedBase32.parse_dataset_dir(&format!("shard=ab/dataset_id={}", "a".repeat(52))), None);
+        assert_eq!(DirectoryLayout::Base32.parse_dataset_dir("dataset_id=aa"), None);
+    }
+
+    #[test]
+    fn test_block_range_dir_name() {
+        assert_eq!(parse_block_range_dir_name("block_range=0_35"), Some(0..35));
+        assert_eq!(parse_block_range_dir_name("block_range=0-35"), None);
+    }
+
+    #[test]
+    fn test_chunk_id_is_stable() {
+        assert_eq!(generate_chunk_id(&[1u8; 32], &(0..35)), generate_chunk_id(&[1u8; 32], &(0..35)));
+        assert_ne!(generate_chunk_id(&[1u8; 32], &(0..35)), generate_chunk_id(&[1u8; 32], &(0..36)));
+    }
+}
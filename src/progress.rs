@@ -0,0 +1,263 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use crate::data_chunk::{ChunkId, DataChunk};
+
+/// Bytes of a file received so far, including the bytes of an interrupted download it continues
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FileProgress {
+    pub downloaded_bytes: u64,
+    /// Size of the remote file, `None` until the transfer learns it
+    pub total_bytes: Option<u64>,
+}
+
+/// Progress of a chunk being downloaded, see `DataManagerImpl::download_progress`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkProgress {
+    /// Progress by file name, all the files of the chunk are listed from the start
+    pub files: BTreeMap<String, FileProgress>,
+    pub started_at: Instant,
+    /// Bytes the files had when their progress was first reported, not received by this download
+    resumed_bytes: u64,
+}
+
+impl ChunkProgress {
+    pub fn new(chunk: &DataChunk) -> Self {
+        ChunkProgress {
+            files: chunk.files.keys().map(|file_name| (file_name.clone(), FileProgress::default())).collect(),
+            started_at: Instant::now(),
+            resumed_bytes: 0,
+        }
+    }
+
+    pub fn downloaded_bytes(&self) -> u64 {
+        self.files.values().map(|file| file.downloaded_bytes).sum()
+    }
+
+    /// Size of the chunk, `None` while the size of any file is unknown
+    pub fn total_bytes(&self) -> Option<u64> {
+        self.files.values().map(|file| file.total_bytes).sum()
+    }
+
+    /// Downloaded share of the chunk from 0 to 100, `None` while its size is unknown
+    pub fn percentage(&self) -> Option<f64> {
+        match self.total_bytes()? {
+            0 => Some(100.0),
+            total_bytes => Some(self.downloaded_bytes() as f64 * 100.0 / total_bytes as f64),
+        }
+    }
+
+    /// Time left at the average rate of the download so far, `None` while the size is unknown or nothing was received yet
+    pub fn eta(&self) -> Option<Duration> {
+        let remaining_bytes = self.total_bytes()?.saturating_sub(self.downloaded_bytes());
+        if remaining_bytes == 0 {
+            return Some(Duration::ZERO);
+        }
+        let received_bytes = self.downloaded_bytes().saturating_sub(self.resumed_bytes);
+        if received_bytes == 0 {
+            return None;
+        }
+        Some(self.started_at.elapsed().mul_f64(remaining_bytes as f64 / received_bytes as f64))
+    }
+
+    fn update(&mut self, file_name: &str, progress: FileProgress) {
+        let file = self.files.entry(file_name.to_string()).or_default();
+        if *file == FileProgress::default() {
+            self.resumed_bytes += progress.downloaded_bytes;
+        }
+        *file = progress;
+    }
+}
+
+/// Progress of the chunks being transferred, updated by the download workers
+#[derive(Clone, Default)]
+pub struct DownloadProgress {
+    chunks: Arc<RwLock<HashMap<ChunkId, ChunkProgress>>>,
+}
+
+impl DownloadProgress {
+    /// Start tracking the transfer of the chunk, from scratch when it was tracked before
+    pub fn start(&self, chunk: &DataChunk) {
+        self.chunks.write().unwrap().insert(chunk.id, ChunkProgress::new(chunk));
+    }
+
+    /// Record the progress of a file, reported by the transfer
+    pub fn update(&self, chunk_id: &ChunkId, file_name: &str, progress: FileProgress) {
+        if let Some(chunk) = self.chunks.write().unwrap().get_mut(chunk_id) {
+            chunk.update(file_name, progress);
+        }
+    }
+
+    /// Stop tracking the chunk, once it's no longer being downloaded
+    pub fn finish(&self, chunk_id: &ChunkId) {
+        self.chunks.write().unwrap().remove(chunk_id);
+    }
+
+    pub fn get(&self, chunk_id: &ChunkId) -> Option<ChunkProgress> {
+        self.chunks.read().unwrap().get(chunk_id).cloned()
+    }
+}
+
+/// Writer reporting the bytes of a file after every write, counting from the bytes it already has
+pub struct ProgressWriter<'a> {
+    inner: &'a mut dyn Write,
+    progress: FileProgress,
+    report: &'a dyn Fn(FileProgress),
+}
+
+impl<'a> ProgressWriter<'a> {
+    pub fn new(inner: &'a mut dyn Write, progress: FileProgress, report: &'a dyn Fn(FileProgress)) -> Self {
+        ProgressWriter { inner, progress, report }
+    }
+}
+
+impl Write for ProgressWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.progress.downloaded_bytes += written as u64;
+        (self.report)(self.progress);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Mutex;
+    use std::thread;
+    use crate::config::DataManagerConfig;
+    use crate::data_manager::DataManager;
+    use crate::transfer::{ChunkTransfer, RangeSource, ResumableTransfer};
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    fn chunk() -> DataChunk {
+        DataChunk::new([9u8; 32], 0..10).with_example_files(&["blocks.parquet", "logs.parquet"])
+    }
+
+    #[test]
+    fn test_chunk_progress() {
+        let mut progress = ChunkProgress::new(&chunk());
+        assert_eq!((progress.downloaded_bytes(), progress.total_bytes(), progress.percentage(), progress.eta()), (0, None, None, None));
+
+        progress.update("blocks.parquet", FileProgress { downloaded_bytes: 40, total_bytes: Some(100) });
+        progress.update("blocks.parquet", FileProgress { downloaded_bytes: 60, total_bytes: Some(100) });
+        assert_eq!(progress.percentage(), None);
+        progress.update("logs.parquet", FileProgress { downloaded_bytes: 0, total_bytes: Some(100) });
+        progress.update("logs.parquet", FileProgress { downloaded_bytes: 20, total_bytes: Some(100) });
+
+        assert_eq!((progress.downloaded_bytes(), progress.total_bytes(), progress.percentage()), (80, Some(200), Some(40.0)));
+        // the 40 bytes resumed don't count for the rate
+        assert_eq!(progress.resumed_bytes, 40);
+        assert!(progress.eta().is_some());
+        progress.update("logs.parquet", FileProgress { downloaded_bytes: 100, total_bytes: Some(100) });
+        progress.update("blocks.parquet", FileProgress { downloaded_bytes: 100, total_bytes: Some(100) });
+        assert_eq!((progress.percentage(), progress.eta()), (Some(100.0), Some(Duration::ZERO)));
+    }
+
+    /// Serves 8 bytes per file a byte at a time, blocking after the first bytes until released
+    struct SlowSource {
+        released: Mutex<bool>,
+    }
+
+    impl RangeSource for SlowSource {
+        fn content_length(&self, _url: &str) -> io::Result<u64> {
+            Ok(8)
+        }
+
+        fn read_range(&self, _url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            for i in offset..8 {
+                if i == 3 {
+                    while !*self.released.lock().unwrap() {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                }
+                writer.write_all(&[i as u8])?;
+            }
+            Ok(8 - offset)
+        }
+    }
+
+    #[test]
+    fn test_download_progress_is_reported_as_files_are_received() {
+        // Arrange
+        let dir = test_support::temp_dir("progress");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let source = Arc::new(SlowSource { released: Mutex::new(false) });
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(ResumableTransfer::new(source.clone())))
+            .build();
+        let chunk = chunk();
+
+        // Act
+        data_manager.download_chunk(chunk.clone());
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(200));
+        });
+        let progress = data_manager.download_progress(chunk.id);
+        *source.released.lock().unwrap() = true;
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(200));
+        });
+
+        // Assert
+        let progress = progress.unwrap();
+        // the files are downloaded one after another, the first one stops at its 3rd byte
+        assert_eq!(progress.downloaded_bytes(), 3);
+        assert_eq!(progress.files.values().filter(|file| file.total_bytes == Some(8)).count(), 1);
+        assert_eq!(progress.files.len(), 2);
+        assert!(data_manager.find_chunk(chunk.dataset_id, 5).is_some());
+        assert_eq!(data_manager.download_progress(chunk.id), None);
+
+        // cleanup
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Remote storage copying whole files, without streaming them
+    struct CopyingTransfer;
+
+    impl ChunkTransfer for CopyingTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::create_dir_all(chunk_dir)?;
+            for file_name in chunk.files.keys() {
+                fs::write(chunk_dir.join(file_name), b"blocks")?;
+            }
+            Ok(())
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    #[test]
+    fn test_transfers_without_streaming_report_completed_files() {
+        // Arrange
+        let chunk_dir = test_support::temp_dir("progress_copy");
+        let progress = DownloadProgress::default();
+        let chunk = chunk();
+        progress.start(&chunk);
+
+        // Act
+        CopyingTransfer.tracked_download(&chunk, &chunk_dir, None, &|| false, &|file_name, file_progress| {
+            progress.update(&chunk.id, file_name, file_progress)
+        }).unwrap();
+
+        // Assert
+        let chunk_progress = progress.get(&chunk.id).unwrap();
+        assert_eq!((chunk_progress.downloaded_bytes(), chunk_progress.percentage()), (12, Some(100.0)));
+        progress.finish(&chunk.id);
+        assert_eq!(progress.get(&chunk.id), None);
+
+        // cleanup
+        fs::remove_dir_all(chunk_dir).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+use crate::rate_limit::host_of;
+
+/// Proxies the requests for the chunk files are sent through, e.g. in networks without a direct outbound connection
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// Proxy of the `http://` urls, e.g. `http://proxy.example.com:3128`
+    pub http_proxy: Option<String>,
+    /// Proxy of the `https://` urls, tunnelled with `CONNECT`
+    pub https_proxy: Option<String>,
+    /// Hosts reached directly, see `bypasses`
+    pub no_proxy: Vec<String>,
+}
+
+impl ProxyConfig {
+    /// Proxies of the `http_proxy`, `https_proxy`, `all_proxy` and `no_proxy` environment variables, or of their uppercase names.
+    /// `all_proxy` is used for the schemes without their own proxy.
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    pub(crate) fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let lookup = |name: &str| var(name).or_else(|| var(&name.to_uppercase())).filter(|value| !value.trim().is_empty());
+        let all_proxy = lookup("all_proxy");
+        ProxyConfig {
+            http_proxy: lookup("http_proxy").or(all_proxy.clone()),
+            https_proxy: lookup("https_proxy").or(all_proxy),
+            no_proxy: lookup("no_proxy")
+                .map(|no_proxy| no_proxy.split(',').map(str::trim).filter(|host| !host.is_empty()).map(str::to_string).collect())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn with_http_proxy(mut self, http_proxy: impl Into<String>) -> Self {
+        self.http_proxy = Some(http_proxy.into());
+        self
+    }
+
+    pub fn with_https_proxy(mut self, https_proxy: impl Into<String>) -> Self {
+        self.https_proxy = Some(https_proxy.into());
+        self
+    }
+
+    pub fn with_no_proxy(mut self, host: impl Into<String>) -> Self {
+        self.no_proxy.push(host.into());
+        self
+    }
+
+    /// Proxy the request for the url is sent through, `None` for a direct connection
+    pub fn proxy_for(&self, url: &str) -> Option<&str> {
+        let proxy = match url.split_once("://").map(|(scheme, _)| scheme.to_ascii_lowercase()).as_deref() {
+            Some("http") => self.http_proxy.as_deref(),
+            Some("https") => self.https_proxy.as_deref(),
+            _ => None,
+        }?;
+        (!self.bypasses(url)).then_some(proxy)
+    }
+
+    /// Whether the host of the url is in `no_proxy`, which follows the usual conventions:
+    /// `*` matches all the hosts, `example.com` and `.example.com` match the domain with its subdomains,
+    /// and an entry with a port, e.g. `example.com:8080`, matches only that port
+    pub fn bypasses(&self, url: &str) -> bool {
+        let authority = host_of(url).to_ascii_lowercase();
+        let (host, port) = split_port(&authority);
+        self.no_proxy.iter().any(|entry| {
+            let entry = entry.to_ascii_lowercase();
+            let (entry_host, entry_port) = split_port(&entry);
+            let entry_host = entry_host.trim_start_matches("*.").trim_start_matches('.');
+            let host_matches = entry_host == "*" || host == entry_host || host.ends_with(&format!(".{}", entry_host));
+            host_matches && entry_port.is_none_or(|entry_port| port == Some(entry_port))
+        })
+    }
+}
+
+/// Host and port of an authority, IPv6 addresses keep their brackets
+fn split_port(authority: &str) -> (&str, Option<&str>) {
+    match authority.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.bytes().all(|byte| byte.is_ascii_digit()) => (host, Some(port)),
+        _ => (authority, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+    use crate::auth::FileRequest;
+    use crate::data_chunk::DataChunk;
+    use crate::transfer::{ChunkTransfer, RangeSource, ResumableTransfer};
+    use crate::test_support;
+    use super::*;
+
+    #[test]
+    fn test_proxy_for() {
+        let proxy = ProxyConfig::default()
+            .with_http_proxy("http://proxy:3128")
+            .with_https_proxy("http://secure-proxy:3128")
+            .with_no_proxy(".internal.example.com")
+            .with_no_proxy("localhost")
+            .with_no_proxy("cdn.example.com:8080");
+        assert_eq!(proxy.proxy_for("http://example.com/blocks.parquet"), Some("http://proxy:3128"));
+        assert_eq!(proxy.proxy_for("HTTPS://example.com/blocks.parquet"), Some("http://secure-proxy:3128"));
+        assert_eq!(proxy.proxy_for("https://chunks.internal.example.com/blocks.parquet"), None);
+        assert_eq!(proxy.proxy_for("https://internal.example.com/blocks.parquet"), None);
+        assert_eq!(proxy.proxy_for("http://localhost:8000/blocks.parquet"), None);
+        assert_eq!(proxy.proxy_for("https://cdn.example.com:8080/blocks.parquet"), None);
+        assert_eq!(proxy.proxy_for("https://cdn.example.com/blocks.parquet"), Some("http://secure-proxy:3128"));
+        assert_eq!(proxy.proxy_for("https://notinternal.example.com/blocks.parquet"), Some("http://secure-proxy:3128"));
+        assert_eq!(proxy.proxy_for("gs://bucket/blocks.parquet"), None);
+        assert_eq!(proxy.with_no_proxy("*").proxy_for("http://example.com/blocks.parquet"), None);
+    }
+
+    #[test]
+    fn test_proxy_from_vars() {
+        let vars = HashMap::from([
+            ("HTTPS_PROXY", "http://secure-proxy:3128"),
+            ("all_proxy", "socks5://proxy:1080"),
+            ("no_proxy", "localhost, .internal ,"),
+        ]);
+        let proxy = ProxyConfig::from_vars(|name| vars.get(name).map(|value| value.to_string()));
+        assert_eq!(proxy, ProxyConfig {
+            http_proxy: Some("socks5://proxy:1080".to_string()),
+            https_proxy: Some("http://secure-proxy:3128".to_string()),
+            no_proxy: vec!["localhost".to_string(), ".internal".to_string()],
+        });
+        assert_eq!(ProxyConfig::from_vars(|_| None), ProxyConfig::default());
+    }
+
+    /// Serves the file to the requests through a proxy, recording them
+    struct ProxiedSource {
+        requests: Mutex<Vec<FileRequest>>,
+    }
+
+    impl RangeSource for ProxiedSource {
+        fn content_length(&self, url: &str) -> io::Result<u64> {
+            self.request_content_length(&FileRequest::new(url))
+        }
+
+        fn read_range(&self, url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            self.request_range(&FileRequest::new(url), offset, writer)
+        }
+
+        fn request_content_length(&self, request: &FileRequest) -> io::Result<u64> {
+            self.requests.lock().unwrap().push(request.clone());
+            Ok(4)
+        }
+
+        fn request_range(&self, request: &FileRequest, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            self.requests.lock().unwrap().push(request.clone());
+            writer.write_all(&b"data"[offset as usize..])?;
+            Ok(4 - offset)
+        }
+    }
+
+    #[test]
+    fn test_files_are_requested_through_the_proxy() {
+        // Arrange
+        let chunk_dir = test_support::temp_dir("proxy");
+        let source = Arc::new(ProxiedSource { requests: Mutex::new(Vec::new()) });
+        let transfer = ResumableTransfer::new(source.clone())
+            .with_proxy(ProxyConfig::default().with_https_proxy("http://proxy:3128").with_no_proxy("internal.example.com"));
+        let chunk = DataChunk {
+            id: [4u8; 32],
+            dataset_id: [4u8; 32],
+            block_range: 0..10,
+            files: HashMap::from([
+                ("blocks.parquet".to_string(), "https://example.com/blocks.parquet".to_string()),
+                ("logs.parquet".to_string(), "https://internal.example.com/logs.parquet".to_string()),
+            ]),
+            mirrors: HashMap::new(),
+            size: None,
+            file_sizes: HashMap::new(),
+        };
+
+        // Act
+        transfer.download(&chunk, &chunk_dir).unwrap();
+
+        // Assert
+        let requests = source.requests.lock().unwrap();
+        assert_eq!(requests.len(), 4);
+        for request in requests.iter() {
+            let expected = request.url.starts_with("https://example.com").then(|| "http://proxy:3128".to_string());
+            assert_eq!(request.proxy, expected);
+        }
+        assert_eq!(fs::read(chunk_dir.join("blocks.parquet")).unwrap(), b"data");
+
+        // cleanup
+        transfer.delete(&chunk, &chunk_dir).unwrap();
+    }
+}
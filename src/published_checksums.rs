@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use crate::data_chunk::{DataChunk, DatasetId};
+#[cfg(feature = "dataframes")]
+use {std::fs::File, polars::prelude::*};
+#[cfg(not(feature = "dataframes"))]
+use crate::jsonl::{self, Row};
+
+/// Sha256 of the files published for the datasets, imported from the checksum files of the publishers, e.g. `SHA256SUMS`.
+/// A file is matched by its path in the checksum file, which must be a suffix of its url in `DataChunk::files`.
+/// The checksums are saved on every import.
+#[derive(Clone)]
+pub struct PublishedChecksums {
+    /// sha256 as lowercase hex by the path of the file, per dataset
+    checksums: Arc<RwLock<HashMap<DatasetId, HashMap<String, String>>>>,
+    file_path: PathBuf,
+}
+
+impl PublishedChecksums {
+    /// Load the checksums saved in the file, a missing or unreadable file gives no checksums
+    pub fn open(file_path: &Path) -> Self {
+        let published = PublishedChecksums { checksums: Arc::new(RwLock::new(HashMap::new())), file_path: file_path.to_path_buf() };
+        {
+            let mut checksums = published.checksums.write().unwrap();
+            for (dataset_id, path, sha256) in read_checksums(file_path) {
+                checksums.entry(dataset_id).or_default().insert(path, sha256);
+            }
+        }
+        published
+    }
+
+    /// Add the checksums of the dataset by the paths of the files and save them, replacing the earlier checksums of the same paths
+    pub fn import(&self, dataset_id: DatasetId, imported: HashMap<String, String>) -> io::Result<()> {
+        let mut checksums = self.checksums.write().unwrap();
+        checksums.entry(dataset_id).or_default().extend(imported);
+        // saved under the lock, so concurrent imports can't overwrite each other's checksums
+        self.save(&checksums)
+    }
+
+    /// Published sha256 of the files of the chunk by file name, files whose urls don't match any path have none
+    pub fn chunk_checksums(&self, chunk: &DataChunk) -> HashMap<String, String> {
+        let checksums = self.checksums.read().unwrap();
+        let Some(dataset_checksums) = checksums.get(&chunk.dataset_id) else { return HashMap::new() };
+        chunk.files.iter()
+            .filter_map(|(file_name, url)| Some((file_name.clone(), find_by_url(dataset_checksums, url)?.clone())))
+            .collect()
+    }
+
+    /// Number of files with a checksum in the dataset
+    pub fn known_files(&self, dataset_id: &DatasetId) -> usize {
+        self.checksums.read().unwrap().get(dataset_id).map_or(0, HashMap::len)
+    }
+
+    fn save(&self, checksums: &HashMap<DatasetId, HashMap<String, String>>) -> io::Result<()> {
+        let rows = checksums.iter()
+            .flat_map(|(dataset_id, dataset_checksums)| dataset_checksums.iter().map(move |(path, sha256)| (dataset_id, path, sha256)));
+        write_checksums(&self.file_path, rows)
+    }
+}
+
+/// Why a checksum file couldn't be imported
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChecksumImportError {
+    /// The file couldn't be read, or the checksums couldn't be saved
+    Io(String),
+    /// A line is neither `<sha256>  <path>` nor `SHA256 (<path>) = <sha256>`, with its number counted from 1
+    InvalidLine(usize, String),
+    /// The data manager keeps no published checksums, so they would be dropped
+    NoPublishedStore,
+}
+
+impl fmt::Display for ChecksumImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChecksumImportError::Io(message) => write!(f, "checksums couldn't be imported: {}", message),
+            ChecksumImportError::InvalidLine(number, line) => write!(f, "line {} isn't a sha256 checksum: {}", number, line),
+            ChecksumImportError::NoPublishedStore => write!(f, "data manager keeps no published checksums"),
+        }
+    }
+}
+
+impl std::error::Error for ChecksumImportError {}
+
+/// Parse a checksum file as written by `sha256sum`, in its text, binary or BSD format, into the sha256 by the path.
+/// Empty lines and lines starting with `#` are skipped.
+pub fn parse_sha256sums(content: &str) -> Result<HashMap<String, String>, ChecksumImportError> {
+    content.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| parse_line(line).ok_or_else(|| ChecksumImportError::InvalidLine(i + 1, line.to_string())))
+        .collect()
+}
+
+/// Read the checksum file and parse it, see `parse_sha256sums`
+pub fn read_sha256sums(file_path: &Path) -> Result<HashMap<String, String>, ChecksumImportError> {
+    let content = fs::read_to_string(file_path).map_err(|error| ChecksumImportError::Io(format!("{}: {}", file_path.display(), error)))?;
+    parse_sha256sums(&content)
+}
+
+fn parse_line(line: &str) -> Option<(String, String)> {
+    let (sha256, path) = match line.strip_prefix("SHA256 (") {
+        // `SHA256 (<path>) = <sha256>`
+        Some(rest) => {
+            let (path, sha256) = rest.rsplit_once(") = ")?;
+            (sha256.trim(), path)
+        }
+        // `<sha256>  <path>`, or `<sha256> *<path>` for files read in binary mode
+        None => {
+            let (sha256, path) = line.split_once(' ')?;
+            (sha256, path.strip_prefix(['*', ' ']).unwrap_or(path))
+        }
+    };
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) || path.is_empty() {
+        return None;
+    }
+    Some((path.strip_prefix("./").unwrap_or(path).to_string(), sha256.to_ascii_lowercase()))
+}
+
+/// Checksum of the longest path, which the url ends with at a `/`, or which is the whole url
+fn find_by_url<'a>(checksums: &'a HashMap<String, String>, url: &str) -> Option<&'a String> {
+    let url = url.split(['?', '#']).next().unwrap_or(url);
+    std::iter::once(url)
+        .chain(url.match_indices('/').map(|(i, _)| &url[i + 1..]))
+        .find_map(|path| checksums.get(path))
+}
+
+#[cfg(feature = "dataframes")]
+fn write_checksums<'a>(file_path: &Path, rows: impl Iterator<Item = (&'a DatasetId, &'a String, &'a String)>) -> io::Result<()> {
+    let (mut dataset_ids, mut paths, mut hashes) = (Vec::new(), Vec::new(), Vec::new());
+    for (dataset_id, path, sha256) in rows {
+        dataset_ids.push(hex::encode(dataset_id));
+        paths.push(path.clone());
+        hashes.push(sha256.clone());
+    }
+    let mut df = df!(
+        "dataset_id" => dataset_ids,
+        "path" => paths,
+        "sha256" => hashes
+    ).map_err(polars_error)?;
+    ParquetWriter::new(File::create(file_path)?).finish(&mut df).map_err(polars_error)?;
+    Ok(())
+}
+
+#[cfg(feature = "dataframes")]
+fn read_checksums(file_path: &Path) -> Vec<(DatasetId, String, String)> {
+    File::open(file_path).map_err(PolarsError::from)
+        .and_then(|file| ParquetReader::new(file).finish())
+        .and_then(|df| checksums_from_dataframe(&df))
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "dataframes")]
+fn checksums_from_dataframe(df: &DataFrame) -> PolarsResult<Vec<(DatasetId, String, String)>> {
+    let dataset_ids = df.column("dataset_id")?.str()?;
+    let paths = df.column("path")?.str()?;
+    let hashes = df.column("sha256")?.str()?;
+    Ok((0..df.height())
+        .filter_map(|i| {
+            let dataset_id: DatasetId = hex::decode(dataset_ids.get(i)?).ok()?.try_into().ok()?;
+            Some((dataset_id, paths.get(i)?.to_string(), hashes.get(i)?.to_string()))
+        })
+        .collect())
+}
+
+#[cfg(feature = "dataframes")]
+fn polars_error(error: PolarsError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(not(feature = "dataframes"))]
+fn write_checksums<'a>(file_path: &Path, rows: impl Iterator<Item = (&'a DatasetId, &'a String, &'a String)>) -> io::Result<()> {
+    jsonl::write_rows(file_path, rows.map(|(dataset_id, path, sha256)| {
+        let mut row = Row::new();
+        row.insert("dataset_id".to_string(), hex::encode(dataset_id).into());
+        row.insert("path".to_string(), path.clone().into());
+        row.insert("sha256".to_string(), sha256.clone().into());
+        row
+    }))
+}
+
+#[cfg(not(feature = "dataframes"))]
+fn read_checksums(file_path: &Path) -> Vec<(DatasetId, String, String)> {
+    jsonl::read_rows(file_path).unwrap_or_default()
+        .iter()
+        .filter_map(|row| {
+            let dataset_id: DatasetId = hex::decode(jsonl::str_column(row, "dataset_id")?).ok()?.try_into().ok()?;
+            Some((dataset_id, jsonl::str_column(row, "path")?.to_string(), jsonl::str_column(row, "sha256")?.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+    use crate::checksum::ChecksumRegistry;
+    use crate::chunk_errors::ChunkErrorKind;
+    use crate::config::DataManagerConfig;
+    use crate::data_catalogue::ChunkStatus;
+    use crate::data_manager::DataManager;
+    use crate::transfer::ChunkTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// Remote storage serving the same content for every file
+    struct FixedContentTransfer;
+
+    impl ChunkTransfer for FixedContentTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::create_dir_all(chunk_dir)?;
+            for file_name in chunk.files.keys() {
+                fs::write(chunk_dir.join(file_name), b"blocks")?;
+            }
+            Ok(())
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    fn chunk(block: u64) -> DataChunk {
+        let path = format!("0000000000/{:010}-{:010}", block, block + 9);
+        DataChunk {
+            files: HashMap::from([("blocks.parquet".to_string(), format!("https://example.com/ethereum/{}/blocks.parquet?sig=1", path))]),
+            ..DataChunk::new([8u8; 32], block..block + 10)
+        }
+    }
+
+    #[test]
+    fn test_parse_sha256sums() {
+        let blocks = sha256::digest("blocks");
+        let content = format!(
+            "# published 2024-01-01\n{}  ./0000000000/0000000000-0000000009/blocks.parquet\n{} *logs.parquet\n\nSHA256 (txs (1).parquet) = {}\n",
+            blocks.to_uppercase(), blocks, blocks
+        );
+
+        let checksums = parse_sha256sums(&content).unwrap();
+
+        assert_eq!(checksums, HashMap::from([
+            ("0000000000/0000000000-0000000009/blocks.parquet".to_string(), blocks.clone()),
+            ("logs.parquet".to_string(), blocks.clone()),
+            ("txs (1).parquet".to_string(), blocks.clone()),
+        ]));
+        assert_eq!(
+            parse_sha256sums(&format!("{}  blocks.parquet\nblocks.parquet {}", blocks, blocks)),
+            Err(ChecksumImportError::InvalidLine(2, format!("blocks.parquet {}", blocks)))
+        );
+    }
+
+    #[test]
+    fn test_imported_checksums_verify_downloads() {
+        // Arrange
+        let dir = test_support::temp_dir("published_checksums");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        fs::write(dir.join("SHA256SUMS"), format!(
+            "{}  0000000000/0000000000-0000000009/blocks.parquet\n{}  0000000000/0000000010-0000000019/blocks.parquet\n",
+            sha256::digest("blocks"), sha256::digest("other blocks")
+        )).unwrap();
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(FixedContentTransfer))
+            .build();
+        let (intact, corrupt, unlisted) = (chunk(0), chunk(10), chunk(20));
+
+        // Act
+        let imported = data_manager.import_checksums([8u8; 32], &dir.join("SHA256SUMS"));
+        for chunk in [&intact, &corrupt, &unlisted] {
+            data_manager.download_chunk(chunk.clone());
+        }
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(200));
+        });
+
+        // Assert
+        assert_eq!(imported, Ok(2));
+        let status = |chunk: &DataChunk| data_manager.get_chunk_info(chunk.id).unwrap().status;
+        assert_eq!(status(&intact), ChunkStatus::Ready);
+        assert_eq!(status(&corrupt), ChunkStatus::Failed);
+        assert_eq!(data_manager.get_chunk_info(corrupt.id).unwrap().errors[0].kind, ChunkErrorKind::Verification);
+        assert_eq!(status(&unlisted), ChunkStatus::Ready);
+        let reopened = PublishedChecksums::open(&dir.join(crate::builder::PUBLISHED_CHECKSUMS_FILE));
+        assert_eq!(reopened.chunk_checksums(&corrupt), HashMap::from([("blocks.parquet".to_string(), sha256::digest("other blocks"))]));
+        assert_eq!(reopened.known_files(&[8u8; 32]), 2);
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_without_a_published_store_fails() {
+        // Arrange
+        let dir = test_support::temp_dir("no_published_checksums");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        fs::write(dir.join("SHA256SUMS"), format!("{}  0000000000/0000000000-0000000009/blocks.parquet\n", sha256::digest("blocks"))).unwrap();
+        let mut data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(FixedContentTransfer))
+            .build();
+        data_manager.checksums = ChecksumRegistry::default();
+
+        // Act
+        let imported = data_manager.import_checksums([8u8; 32], &dir.join("SHA256SUMS"));
+
+        // Assert
+        assert_eq!(imported, Err(ChecksumImportError::NoPublishedStore));
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
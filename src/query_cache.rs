@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use polars::prelude::DataFrame;
+use crate::data_chunk::{DataChunk, DatasetId};
+
+/// Identifies the result of a scan
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct QueryFingerprint {
+    pub dataset_id: DatasetId,
+    pub file_name: String,
+    pub block_range: Range<u64>,
+    pub projection: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryCacheConfig {
+    /// Bigger results aren't cached, the cache is meant for small dashboard-style results
+    pub max_result_bytes: usize,
+    /// Least recently used results are dropped to stay within this
+    pub max_total_bytes: usize,
+}
+
+impl Default for QueryCacheConfig {
+    fn default() -> Self {
+        QueryCacheConfig {
+            max_result_bytes: 1024 * 1024,
+            max_total_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QueryCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub invalidations: u64,
+}
+
+struct CachedResult {
+    result: DataFrame,
+    bytes: usize,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    results: HashMap<QueryFingerprint, CachedResult>,
+    total_bytes: usize,
+    /// incremented on every access, orders the results by their last use
+    clock: u64,
+    /// incremented on every invalidation, so results computed meanwhile aren't cached
+    generation: u64,
+    stats: QueryCacheStats,
+}
+
+/// Materialized scan results, invalidated whenever a chunk the result may depend on changes its status
+#[derive(Clone)]
+pub struct QueryCache {
+    config: QueryCacheConfig,
+    state: Arc<Mutex<CacheState>>,
+}
+
+impl QueryCache {
+    pub fn new(config: QueryCacheConfig) -> Self {
+        QueryCache {
+            config,
+            state: Arc::new(Mutex::new(CacheState::default())),
+        }
+    }
+
+    pub fn get(&self, fingerprint: &QueryFingerprint) -> Option<DataFrame> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        match state.results.get_mut(fingerprint) {
+            Some(cached) => {
+                cached.last_used = clock;
+                let result = cached.result.clone();
+                state.stats.hits += 1;
+                Some(result)
+            }
+            None => {
+                state.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Taken before computing a result, the result is cached only when nothing was invalidated meanwhile
+    pub fn generation(&self) -> u64 {
+        self.state.lock().unwrap().generation
+    }
+
+    pub fn insert(&self, fingerprint: QueryFingerprint, result: DataFrame, generation: u64) {
+        let bytes = result.estimated_size();
+        if bytes > self.config.max_result_bytes || bytes > self.config.max_total_bytes {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.generation != generation {
+            return;
+        }
+        state.clock += 1;
+        let last_used = state.clock;
+        if let Some(previous) = state.results.insert(fingerprint, CachedResult { result, bytes, last_used }) {
+            state.total_bytes -= previous.bytes;
+        }
+        state.total_bytes += bytes;
+        while state.total_bytes > self.config.max_total_bytes {
+            let Some(least_recently_used) = state.results.iter()
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(fingerprint, _)| fingerprint.clone()) else {
+                break;
+            };
+            let evicted = state.results.remove(&least_recently_used).unwrap();
+            state.total_bytes -= evicted.bytes;
+        }
+    }
+
+    /// Drop the results which may include blocks of the chunk
+    pub fn invalidate(&self, chunk: &DataChunk) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        let before = state.results.len();
+        let mut freed_bytes = 0;
+        state.results.retain(|fingerprint, cached| {
+            let affected = fingerprint.dataset_id == chunk.dataset_id
+                && fingerprint.block_range.start < chunk.block_range.end
+                && chunk.block_range.start < fingerprint.block_range.end;
+            if affected {
+                freed_bytes += cached.bytes;
+            }
+            !affected
+        });
+        state.total_bytes -= freed_bytes;
+        state.stats.invalidations += (before - state.results.len()) as u64;
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> QueryCacheStats {
+        self.state.lock().unwrap().stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::prelude::*;
+    use super::*;
+
+    fn fingerprint(block_range: Range<u64>) -> QueryFingerprint {
+        QueryFingerprint {
+            dataset_id: [1u8; 32],
+            file_name: "blocks.parquet".to_string(),
+            block_range,
+            projection: vec!["block_number".to_string()],
+        }
+    }
+
+    fn result(rows: u64) -> DataFrame {
+        df!("block_number" => (0..rows).collect::<Vec<u64>>()).unwrap()
+    }
+
+    fn chunk(dataset_id: DatasetId, block_range: Range<u64>) -> DataChunk {
+        DataChunk { id: [2u8; 32], ..DataChunk::new(dataset_id, block_range) }
+    }
+
+    #[test]
+    fn test_cached_result() {
+        let cache = QueryCache::new(QueryCacheConfig::default());
+        assert_eq!(cache.get(&fingerprint(0..10)), None);
+        cache.insert(fingerprint(0..10), result(10), cache.generation());
+        assert_eq!(cache.get(&fingerprint(0..10)), Some(result(10)));
+        assert_eq!(cache.stats(), QueryCacheStats { hits: 1, misses: 1, invalidations: 0 });
+    }
+
+    #[test]
+    fn test_changed_chunk_invalidates_overlapping_results() {
+        // Arrange
+        let cache = QueryCache::new(QueryCacheConfig::default());
+        cache.insert(fingerprint(0..10), result(10), cache.generation());
+        cache.insert(fingerprint(10..20), result(10), cache.generation());
+
+        // Act
+        cache.invalidate(&chunk([2u8; 32], 0..20));
+        cache.invalidate(&chunk([1u8; 32], 5..8));
+
+        // Assert
+        assert_eq!(cache.get(&fingerprint(0..10)), None);
+        assert_eq!(cache.get(&fingerprint(10..20)), Some(result(10)));
+        assert_eq!(cache.stats().invalidations, 1);
+    }
+
+    #[test]
+    fn test_result_computed_during_invalidation_is_not_cached() {
+        let cache = QueryCache::new(QueryCacheConfig::default());
+        let generation = cache.generation();
+        cache.invalidate(&chunk([1u8; 32], 0..10));
+        cache.insert(fingerprint(0..10), result(10), generation);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_least_recently_used_results_are_dropped() {
+        // Arrange
+        let bytes = result(100).estimated_size();
+        let cache = QueryCache::new(QueryCacheConfig { max_result_bytes: bytes, max_total_bytes: 2 * bytes });
+        cache.insert(fingerprint(0..100), result(100), cache.generation());
+        cache.insert(fingerprint(100..200), result(100), cache.generation());
+        cache.get(&fingerprint(0..100));
+
+        // Act
+        cache.insert(fingerprint(200..300), result(100), cache.generation());
+        cache.insert(fingerprint(300..1300), result(1000), cache.generation());
+
+        // Assert
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&fingerprint(0..100)).is_some());
+        assert!(cache.get(&fingerprint(100..200)).is_none());
+        assert!(cache.get(&fingerprint(300..1300)).is_none());
+    }
+}
@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::auth::FileRequest;
+use crate::compression::ContentEncoding;
+use crate::transfer::RangeSource;
+
+/// The origin refused the request for now, e.g. an HTTP 429 or 503 response
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimited {
+    /// How long the origin asked to wait, the `Retry-After` header of the response
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.retry_after {
+            Some(retry_after) => write!(f, "rate limited by the origin, retry after {:?}", retry_after),
+            None => write!(f, "rate limited by the origin"),
+        }
+    }
+}
+
+impl Error for RateLimited {}
+
+/// Error a `RangeSource` returns for a 429 or 503 response, with its `Retry-After`, see `parse_retry_after`
+pub fn rate_limited(retry_after: Option<Duration>) -> io::Error {
+    io::Error::other(RateLimited { retry_after })
+}
+
+/// The refusal of the origin, when the error is one
+pub fn as_rate_limited(error: &io::Error) -> Option<&RateLimited> {
+    error.get_ref().and_then(|error| error.downcast_ref::<RateLimited>())
+}
+
+/// Parse the value of a `Retry-After` header, either the seconds to wait or an HTTP date like `Wed, 21 Oct 2015 07:28:00 GMT`.
+/// A date in the past means no wait.
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let retry_at = UNIX_EPOCH + Duration::from_secs(parse_http_date(value)?);
+    Some(retry_at.duration_since(now).unwrap_or(Duration::ZERO))
+}
+
+/// Seconds since the unix epoch of an IMF-fixdate, the only date format HTTP/1.1 senders may generate
+fn parse_http_date(value: &str) -> Option<u64> {
+    let [_weekday, day, month, year, time, "GMT"] = value.split_whitespace().collect::<Vec<_>>()[..] else { return None };
+    let month = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"]
+        .iter()
+        .position(|name| *name == month)? as u64 + 1;
+    let (day, year): (u64, u64) = (day.parse().ok()?, year.parse().ok()?);
+    let [hours, minutes, seconds] = time.split(':').map(str::parse::<u64>).collect::<Result<Vec<_>, _>>().ok()?[..] else { return None };
+    if year < 1970 || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    // days from the civil date, counting the years from March so the leap day is the last day of the year
+    let (year, month) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let days = year * 365 + year / 4 - year / 100 + year / 400 + (153 * month + 2) / 5 + day - 1 - 719_468;
+    Some(days * 86_400 + hours * 3_600 + minutes * 60 + seconds)
+}
+
+/// How the requests to rate limited origins are spread and retried
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimitConfig {
+    /// Requests in flight to the same host, at least 1
+    pub max_concurrent_per_host: usize,
+    /// Requests started per second to the same host, by all the downloads together, `None` for no limit
+    pub requests_per_second_per_host: Option<f64>,
+    /// Wait after a refusal without a `Retry-After`, doubled with every refusal of the host in a row
+    pub initial_backoff: Duration,
+    /// Longest wait, also caps the `Retry-After` of the origin
+    pub max_backoff: Duration,
+    /// Refusals in a row after which the request fails, the download then fails as with any other error
+    pub max_refusals: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            max_concurrent_per_host: 4,
+            requests_per_second_per_host: None,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            max_refusals: 10,
+        }
+    }
+}
+
+#[derive(Default)]
+struct HostState {
+    in_flight: usize,
+    /// Nothing is sent to the host before, after it refused a request
+    blocked_until: Option<Instant>,
+    /// Refusals in a row, for the backoff without a `Retry-After`
+    refusals: u32,
+    /// Earliest start of the next request, spacing the requests by the rate of the host
+    next_request_at: Option<Instant>,
+    /// All the refusals of the host, see `RateLimitedSource::refusals`
+    total_refusals: u64,
+}
+
+/// Requests in flight and backoffs per host, shared by all the downloads
+#[derive(Default)]
+struct Hosts {
+    states: Mutex<HashMap<String, HostState>>,
+    changed: Condvar,
+}
+
+struct HostPermit<'a> {
+    hosts: &'a Hosts,
+    host: &'a str,
+}
+
+impl Drop for HostPermit<'_> {
+    fn drop(&mut self) {
+        if let Some(state) = self.hosts.states.lock().unwrap().get_mut(self.host) {
+            state.in_flight -= 1;
+        }
+        self.hosts.changed.notify_all();
+    }
+}
+
+impl Hosts {
+    /// Wait until the host has a free request, its backoff passed and its rate allows another request
+    fn acquire<'a>(&'a self, host: &'a str, config: &RateLimitConfig) -> HostPermit<'a> {
+        let mut states = self.states.lock().unwrap();
+        loop {
+            let state = states.entry(host.to_string()).or_default();
+            let now = Instant::now();
+            let ready_at = state.blocked_until.max(state.next_request_at).filter(|ready_at| *ready_at > now);
+            match ready_at {
+                Some(ready_at) => states = self.changed.wait_timeout(states, ready_at - now).unwrap().0,
+                None if state.in_flight >= config.max_concurrent_per_host.max(1) => states = self.changed.wait(states).unwrap(),
+                None => {
+                    state.in_flight += 1;
+                    if let Some(rate) = config.requests_per_second_per_host.filter(|rate| *rate > 0.0) {
+                        state.next_request_at = Some(now + Duration::from_secs_f64(1.0 / rate));
+                    }
+                    return HostPermit { hosts: self, host };
+                }
+            }
+        }
+    }
+
+    /// Hold back all the requests to the host after it refused one
+    fn back_off(&self, host: &str, retry_after: Option<Duration>, config: &RateLimitConfig) {
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(host.to_string()).or_default();
+        state.refusals += 1;
+        state.total_refusals += 1;
+        let backoff = retry_after
+            .unwrap_or_else(|| config.initial_backoff.saturating_mul(1 << (state.refusals - 1).min(16)))
+            .min(config.max_backoff);
+        let blocked_until = Instant::now() + backoff;
+        state.blocked_until = Some(state.blocked_until.map_or(blocked_until, |blocked| blocked.max(blocked_until)));
+    }
+
+    fn succeeded(&self, host: &str) {
+        if let Some(state) = self.states.lock().unwrap().get_mut(host) {
+            state.refusals = 0;
+        }
+    }
+}
+
+/// Range source respecting the rate limits of the origins, e.g. public data gateways.
+/// At most `max_concurrent_per_host` requests are in flight to a host, at most `requests_per_second_per_host` are started
+/// by all the downloads sharing the source, and a refused request holds back
+/// all the requests to its host for the `Retry-After` of the response, rather than failing the download.
+/// A range read refused midway continues after the bytes it already received.
+pub struct RateLimitedSource {
+    inner: Arc<dyn RangeSource>,
+    config: RateLimitConfig,
+    hosts: Hosts,
+}
+
+impl RateLimitedSource {
+    pub fn new(inner: Arc<dyn RangeSource>, config: RateLimitConfig) -> Self {
+        RateLimitedSource { inner, config, hosts: Hosts::default() }
+    }
+
+    /// Requests refused by every host since the source was created, e.g. to be exported as a metric.
+    /// The refusals retried here never reach the downloads, so they're not reported to the hooks.
+    pub fn refusals(&self) -> HashMap<String, u64> {
+        self.hosts.states.lock().unwrap().iter()
+            .filter(|(_, state)| state.total_refusals > 0)
+            .map(|(host, state)| (host.clone(), state.total_refusals))
+            .collect()
+    }
+
+    fn request<T>(&self, url: &str, mut request: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let host = host_of(url);
+        let mut refusals = 0;
+        loop {
+            let result = {
+                let _permit = self.hosts.acquire(host, &self.config);
+                request()
+            };
+            let retry_after = match &result {
+                Ok(_) => {
+                    self.hosts.succeeded(host);
+                    return result;
+                }
+                Err(error) => match as_rate_limited(error) {
+                    Some(refusal) => refusal.retry_after,
+                    None => return result,
+                },
+            };
+            refusals += 1;
+            if refusals > self.config.max_refusals {
+                return result;
+            }
+            self.hosts.back_off(host, retry_after, &self.config);
+        }
+    }
+}
+
+impl RangeSource for RateLimitedSource {
+    fn content_length(&self, url: &str) -> io::Result<u64> {
+        self.request_content_length(&FileRequest::new(url))
+    }
+
+    fn read_range(&self, url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+        self.request_range(&FileRequest::new(url), offset, writer)
+    }
+
+    fn request_content_length(&self, request: &FileRequest) -> io::Result<u64> {
+        self.request(&request.url, || self.inner.request_content_length(request))
+    }
+
+    fn request_content_encoding(&self, request: &FileRequest) -> io::Result<ContentEncoding> {
+        self.request(&request.url, || self.inner.request_content_encoding(request))
+    }
+
+    fn request_range(&self, request: &FileRequest, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+        let mut written = 0;
+        self.request(&request.url, || {
+            let resume_at = offset + written;
+            self.inner.request_range(request, resume_at, &mut CountingWriter { inner: &mut *writer, written: &mut written })
+        })?;
+        Ok(written)
+    }
+}
+
+struct CountingWriter<'a> {
+    inner: &'a mut dyn Write,
+    written: &'a mut u64,
+}
+
+impl Write for CountingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        *self.written += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Host and port of the url, the requests to it share the limits
+pub(crate) fn host_of(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    authority.rsplit('@').next().unwrap_or(authority)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+
+    /// Origin refusing the first `refusals` requests after sending half of the requested bytes
+    struct RefusingSource {
+        content: Vec<u8>,
+        refusals: Mutex<u32>,
+        offsets: Mutex<Vec<u64>>,
+    }
+
+    impl RangeSource for RefusingSource {
+        fn content_length(&self, _url: &str) -> io::Result<u64> {
+            Ok(self.content.len() as u64)
+        }
+
+        fn read_range(&self, _url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            self.offsets.lock().unwrap().push(offset);
+            let remaining = &self.content[offset as usize..];
+            let mut refusals = self.refusals.lock().unwrap();
+            if *refusals > 0 {
+                *refusals -= 1;
+                writer.write_all(&remaining[..remaining.len() / 2])?;
+                return Err(rate_limited(Some(Duration::from_millis(50))));
+            }
+            writer.write_all(remaining)?;
+            Ok(remaining.len() as u64)
+        }
+    }
+
+    /// Origin taking a while per request, recording the most requests in flight
+    #[derive(Default)]
+    struct SlowSource {
+        in_flight: Mutex<usize>,
+        max_in_flight: Mutex<usize>,
+    }
+
+    impl RangeSource for SlowSource {
+        fn content_length(&self, _url: &str) -> io::Result<u64> {
+            {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                *in_flight += 1;
+                let mut max_in_flight = self.max_in_flight.lock().unwrap();
+                *max_in_flight = (*max_in_flight).max(*in_flight);
+            }
+            thread::sleep(Duration::from_millis(30));
+            *self.in_flight.lock().unwrap() -= 1;
+            Ok(0)
+        }
+
+        fn read_range(&self, _url: &str, _offset: u64, _writer: &mut dyn Write) -> io::Result<u64> {
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_445_412_470);
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        // 1445412480 is Wed, 21 Oct 2015 07:28:00 GMT
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now), Some(Duration::from_secs(10)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("Thu, 29 Feb 2024 00:00:00 GMT", UNIX_EPOCH), Some(Duration::from_secs(1_709_164_800)));
+        assert_eq!(parse_retry_after("soon", now), None);
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 CET", now), None);
+    }
+
+    #[test]
+    fn test_host_of() {
+        assert_eq!(host_of("https://user@gateway.example.com:8080/chunks/blocks.parquet?sig=1"), "gateway.example.com:8080");
+        assert_eq!(host_of("https://gateway.example.com"), "gateway.example.com");
+        assert_eq!(host_of("gateway.example.com/blocks.parquet"), "gateway.example.com");
+    }
+
+    #[test]
+    fn test_refused_reads_continue_after_the_backoff() {
+        // Arrange
+        let content: Vec<u8> = (0..100).collect();
+        let origin = Arc::new(RefusingSource { content: content.clone(), refusals: Mutex::new(2), offsets: Mutex::new(Vec::new()) });
+        let source = RateLimitedSource::new(origin.clone(), RateLimitConfig::default());
+        let started_at = Instant::now();
+
+        // Act
+        let mut received = Vec::new();
+        let written = source.read_range("https://gateway.example.com/blocks.parquet", 0, &mut received).unwrap();
+
+        // Assert
+        assert_eq!(received, content);
+        assert_eq!(written, 100);
+        assert_eq!(*origin.offsets.lock().unwrap(), vec![0, 50, 75]);
+        assert!(started_at.elapsed() >= Duration::from_millis(100));
+        assert_eq!(source.refusals(), HashMap::from([("gateway.example.com".to_string(), 2)]));
+    }
+
+    #[test]
+    fn test_request_fails_once_refusals_are_exhausted() {
+        let origin = Arc::new(RefusingSource { content: vec![0u8; 10], refusals: Mutex::new(5), offsets: Mutex::new(Vec::new()) });
+        let config = RateLimitConfig { max_refusals: 2, ..RateLimitConfig::default() };
+        let source = RateLimitedSource::new(origin.clone(), config);
+
+        let error = source.read_range("https://gateway.example.com/blocks.parquet", 0, &mut Vec::new()).unwrap_err();
+
+        assert_eq!(as_rate_limited(&error), Some(&RateLimited { retry_after: Some(Duration::from_millis(50)) }));
+        assert_eq!(origin.offsets.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_requests_per_host_are_capped() {
+        // Arrange
+        let origin = Arc::new(SlowSource::default());
+        let source = Arc::new(RateLimitedSource::new(origin.clone(), RateLimitConfig { max_concurrent_per_host: 2, ..RateLimitConfig::default() }));
+
+        // Act
+        let requests: Vec<_> = (0..6)
+            .map(|i| {
+                let source = source.clone();
+                thread::spawn(move || source.content_length(&format!("https://gateway.example.com/{}.parquet", i)).unwrap())
+            })
+            .collect();
+        for request in requests {
+            request.join().unwrap();
+        }
+
+        // Assert
+        assert_eq!(*origin.max_in_flight.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_requests_per_host_are_paced() {
+        // Arrange
+        let origin = Arc::new(SlowSource::default());
+        let config = RateLimitConfig { max_concurrent_per_host: 10, requests_per_second_per_host: Some(20.0), ..RateLimitConfig::default() };
+        let source = Arc::new(RateLimitedSource::new(origin.clone(), config));
+        let started_at = Instant::now();
+
+        // Act
+        let requests: Vec<_> = ["gateway.example.com", "gateway.example.com", "gateway.example.com", "gateway.example.com", "mirror.example.com"]
+            .iter()
+            .map(|host| {
+                let source = source.clone();
+                let url = format!("https://{}/blocks.parquet", host);
+                thread::spawn(move || {
+                    source.content_length(&url).unwrap();
+                    started_at.elapsed()
+                })
+            })
+            .collect();
+        let mut elapsed: Vec<Duration> = requests.into_iter().map(|request| request.join().unwrap()).collect();
+        let other_host = elapsed.pop().unwrap();
+
+        // Assert
+        // 4 requests 50ms apart, each taking 30ms
+        assert!(elapsed.iter().max().unwrap() >= &Duration::from_millis(180));
+        assert!(other_host < Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::data_catalogue::ChunkStatus;
+use crate::data_chunk::{ChunkId, DatasetId};
+
+/// Why a chunk could not be moved to another dataset
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReassignError {
+    /// The chunk is not in the catalogue
+    UnknownChunk(ChunkId),
+    /// Only `Ready` chunks can be moved, the chunk has this status
+    NotReady(ChunkStatus),
+    /// Readers hold references to the chunk, its path must stay valid until they are dropped
+    InUse(usize),
+    /// The chunk is under a legal hold, see `LegalHold`
+    LegalHold,
+    /// The dataset is frozen, see `freeze_dataset`
+    Frozen(DatasetId),
+    /// The chunk of the blocks in the new dataset is already in the catalogue with this status
+    AlreadyRegistered(ChunkStatus),
+    /// The chunk directory could not be renamed
+    Io(String),
+}
+
+impl fmt::Display for ReassignError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReassignError::UnknownChunk(chunk_id) => write!(f, "chunk {} is not in the catalogue", hex::encode(chunk_id)),
+            ReassignError::NotReady(status) => write!(f, "chunk is not ready, its status is {}", status),
+            ReassignError::InUse(pins) => write!(f, "chunk is held by {} references", pins),
+            ReassignError::LegalHold => write!(f, "chunk is under a legal hold"),
+            ReassignError::Frozen(dataset_id) => write!(f, "dataset {} is frozen", hex::encode(dataset_id)),
+            ReassignError::AlreadyRegistered(status) => write!(f, "chunk of the new dataset is already registered as {}", status),
+            ReassignError::Io(message) => write!(f, "chunk directory could not be renamed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ReassignError {}
+
+/// Rename the chunk directory into the directory of the new dataset, which is created when missing.
+/// The directory of the old dataset is removed once it's empty.
+pub(crate) fn rename_chunk_dir(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(dataset_dir) = to.parent() {
+        fs::create_dir_all(dataset_dir)?;
+    }
+    fs::rename(from, to)?;
+    if let Some(dataset_dir) = from.parent() {
+        // fails while the dataset has other chunks
+        let _ = fs::remove_dir(dataset_dir);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_catalogue::DataCatalogue;
+    use crate::data_chunk::DataChunkRef;
+    use crate::data_manager::DataManager;
+    use crate::test_support;
+    use super::*;
+
+    #[test]
+    fn test_chunk_is_reassigned_without_downloading_it_again() {
+        // Arrange
+        let dir = test_support::temp_dir("reassignment");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let data_manager = test_support::manager_builder(&dir).build();
+        let chunk = test_support::chunk(6, 0..10);
+        data_manager.download_chunk(chunk.clone());
+        data_manager.data_catalogue.wait_until_downloaded(&[chunk.id]);
+        let old_path = data_manager.data_source.chunk_path(chunk.clone()).path;
+
+        // Act
+        let reference = data_manager.find_chunk(chunk.dataset_id, 5).unwrap();
+        let while_referenced = data_manager.reassign_chunk(chunk.id, [7u8; 32]);
+        drop(reference);
+        let reassigned = data_manager.reassign_chunk(chunk.id, [7u8; 32]).unwrap();
+        let reassigned_again = data_manager.reassign_chunk(chunk.id, [7u8; 32]);
+        let found = data_manager.find_chunk([7u8; 32], 5).unwrap().path().to_path_buf();
+        let found_old = data_manager.find_chunk(chunk.dataset_id, 5).is_some();
+        drop(data_manager);
+        let restarted = test_support::manager_builder(&dir).build();
+        let found_after_restart = restarted.find_chunk([7u8; 32], 5).map(|chunk_ref| chunk_ref.path().to_path_buf());
+
+        // Assert
+        assert_eq!(while_referenced, Err(ReassignError::InUse(1)));
+        assert_eq!(reassigned.id, DataCatalogue::generate_chunk_id(&[7u8; 32], &(0..10)));
+        assert_eq!(reassigned.files, chunk.files);
+        assert_eq!(reassigned_again, Err(ReassignError::NotReady(ChunkStatus::Deleted)));
+        assert_eq!(fs::read(found.join("blocks.parquet")).unwrap(), b"blocks");
+        assert!(!found_old);
+        assert!(!old_path.exists());
+        assert!(!old_path.parent().unwrap().exists());
+        assert_eq!(found_after_restart, Some(found));
+
+        // cleanup
+        drop(restarted);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
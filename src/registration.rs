@@ -0,0 +1,121 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use crate::checksum::ChecksumRegistry;
+use crate::data_catalogue::ChunkStatus;
+use crate::data_chunk::{ChunkId, DataChunk};
+use crate::planning;
+use crate::size_check;
+
+/// Why a chunk placed in the data directory by another process could not be registered
+#[derive(Clone, Debug, PartialEq)]
+pub enum RegisterError {
+    /// The chunk id isn't the one derived from the dataset id and the block range
+    InvalidChunkId(ChunkId),
+    /// The chunk directory doesn't exist
+    MissingDirectory(PathBuf),
+    /// Files of the chunk, which are not in its directory
+    MissingFiles(Vec<String>),
+    /// The chunk is already in the catalogue with this status
+    AlreadyRegistered(ChunkStatus),
+    /// The files of the chunk are of another size than declared, or with another checksum than known
+    Verification(String),
+    /// The files of the chunk couldn't be given the configured modes or owner, or the data manager couldn't delete them,
+    /// e.g. they were placed by a sidecar run as root
+    Permissions(String),
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegisterError::InvalidChunkId(chunk_id) => write!(f, "chunk id {} doesn't match the dataset and block range", hex::encode(chunk_id)),
+            RegisterError::MissingDirectory(path) => write!(f, "chunk directory {} doesn't exist", path.display()),
+            RegisterError::MissingFiles(file_names) => write!(f, "chunk files {:?} are missing", file_names),
+            RegisterError::AlreadyRegistered(status) => write!(f, "chunk is already registered as {}", status),
+            RegisterError::Verification(message) => write!(f, "chunk files failed the verification: {}", message),
+            RegisterError::Permissions(message) => write!(f, "chunk files have wrong permissions: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for RegisterError {}
+
+/// Why a chunk could not be removed from the catalogue
+#[derive(Clone, Debug, PartialEq)]
+pub enum ForgetError {
+    /// The chunk is not in the catalogue
+    UnknownChunk(ChunkId),
+    /// The chunk is being downloaded or deleted
+    Busy(ChunkStatus),
+    /// The chunk is under a legal hold, see `LegalHold`
+    LegalHold,
+}
+
+impl fmt::Display for ForgetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForgetError::UnknownChunk(chunk_id) => write!(f, "chunk {} is not in the catalogue", hex::encode(chunk_id)),
+            ForgetError::Busy(status) => write!(f, "chunk is being processed, its status is {}", status),
+            ForgetError::LegalHold => write!(f, "chunk is under a legal hold"),
+        }
+    }
+}
+
+impl std::error::Error for ForgetError {}
+
+/// Check the chunk can be registered from `chunk_dir`, with `verify` also check all its files are there
+pub(crate) fn check_chunk(chunk: &DataChunk, chunk_dir: &Path, verify: bool) -> Result<(), RegisterError> {
+    if chunk.id != planning::generate_chunk_id(&chunk.dataset_id, &chunk.block_range) {
+        return Err(RegisterError::InvalidChunkId(chunk.id));
+    }
+    if !chunk_dir.is_dir() {
+        return Err(RegisterError::MissingDirectory(chunk_dir.to_path_buf()));
+    }
+    if verify {
+        let mut missing_files: Vec<String> = chunk.files
+            .keys()
+            .filter(|file_name| !chunk_dir.join(file_name).is_file())
+            .cloned()
+            .collect();
+        if !missing_files.is_empty() {
+            missing_files.sort();
+            return Err(RegisterError::MissingFiles(missing_files));
+        }
+    }
+    Ok(())
+}
+
+/// Check the sizes and the checksums of the files in `chunk_dir`, as after a download
+pub(crate) fn verify_files(chunk: &DataChunk, chunk_dir: &Path, checksums: &ChecksumRegistry) -> Result<(), RegisterError> {
+    size_check::verify(chunk, chunk_dir)
+        .and_then(|_| checksums.verify(chunk, chunk_dir))
+        .map_err(|error| RegisterError::Verification(error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::test_support;
+    use super::*;
+
+    #[test]
+    fn test_check_chunk() {
+        // Arrange
+        let chunk_dir = test_support::temp_dir("check");
+        fs::create_dir_all(&chunk_dir).unwrap();
+        fs::write(chunk_dir.join("blocks.parquet"), []).unwrap();
+        let chunk = DataChunk::new([1u8; 32], 0..10).with_example_files(&["blocks.parquet", "logs.parquet"]);
+
+        // Act
+        let unverified = check_chunk(&chunk, &chunk_dir, false);
+        let verified = check_chunk(&chunk, &chunk_dir, true);
+        let missing_dir = check_chunk(&chunk, &chunk_dir.join("missing"), false);
+        let invalid_id = check_chunk(&DataChunk { id: [2u8; 32], ..chunk.clone() }, &chunk_dir, false);
+
+        // Assert
+        assert_eq!(unverified, Ok(()));
+        assert_eq!(verified, Err(RegisterError::MissingFiles(vec!["logs.parquet".to_string()])));
+        assert_eq!(missing_dir, Err(RegisterError::MissingDirectory(chunk_dir.join("missing"))));
+        assert_eq!(invalid_id, Err(RegisterError::InvalidChunkId([2u8; 32])));
+        fs::remove_dir_all(chunk_dir).unwrap();
+    }
+}
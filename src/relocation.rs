@@ -0,0 +1,95 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::data_catalogue::ChunkStatus;
+use crate::data_chunk::ChunkId;
+
+/// Why a chunk could not be moved to another volume
+#[derive(Clone, Debug, PartialEq)]
+pub enum RelocateError {
+    /// The chunk is not in the catalogue
+    UnknownChunk(ChunkId),
+    /// Only `Ready` chunks can be moved, the chunk has this status
+    NotReady(ChunkStatus),
+    /// Readers hold references to the chunk, its path must stay valid until they are dropped
+    InUse(usize),
+    /// The files could not be copied to the volume
+    Io(String),
+    /// A replica of the chunk, see `PlacementPolicy`, is in the failure domain of the volume
+    SharedFailureDomain(String, ChunkId),
+}
+
+impl fmt::Display for RelocateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelocateError::UnknownChunk(chunk_id) => write!(f, "chunk {} is not in the catalogue", hex::encode(chunk_id)),
+            RelocateError::NotReady(status) => write!(f, "chunk is not ready, its status is {}", status),
+            RelocateError::InUse(pins) => write!(f, "chunk is held by {} references", pins),
+            RelocateError::Io(message) => write!(f, "chunk files could not be moved: {}", message),
+            RelocateError::SharedFailureDomain(domain, chunk_id) => write!(f, "replica {} is already in the failure domain {}", hex::encode(chunk_id), domain),
+        }
+    }
+}
+
+impl std::error::Error for RelocateError {}
+
+/// Copy the chunk directory with all its files, the source stays readable meanwhile
+pub(crate) fn copy_chunk_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            copy_chunk_dir(&entry.path(), &to.join(entry.file_name()))?;
+        } else {
+            fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_chunk::DataChunkRef;
+    use crate::data_manager::DataManager;
+    use crate::test_support;
+    use super::*;
+
+    #[test]
+    fn test_chunk_is_relocated_once_unreferenced() {
+        // Arrange
+        let dir = test_support::temp_dir("relocation");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let volume = dir.join("volume");
+        let data_manager = test_support::manager_builder(&dir).build();
+        let chunk = test_support::chunk(5, 0..10);
+        data_manager.download_chunk(chunk.clone());
+        data_manager.data_catalogue.wait_until_downloaded(&[chunk.id]);
+        let old_path = data_manager.data_source.chunk_path(chunk.clone()).path;
+
+        // Act
+        let reference = data_manager.find_chunk(chunk.dataset_id, 5).unwrap();
+        let while_referenced = data_manager.relocate_chunk(chunk.id, volume.clone());
+        let path_while_referenced = reference.path().to_path_buf();
+        drop(reference);
+        let new_path = data_manager.relocate_chunk(chunk.id, volume.clone()).unwrap();
+        let found = data_manager.find_chunk(chunk.dataset_id, 5).unwrap().path().to_path_buf();
+        drop(data_manager);
+        let restarted = test_support::manager_builder(&dir).build();
+        let found_after_restart = restarted.find_chunk(chunk.dataset_id, 5).map(|chunk_ref| chunk_ref.path().to_path_buf());
+
+        // Assert
+        assert_eq!(while_referenced, Err(RelocateError::InUse(1)));
+        assert_eq!(path_while_referenced, old_path);
+        assert!(new_path.starts_with(&volume));
+        assert_eq!(found, new_path);
+        assert_eq!(fs::read(new_path.join("blocks.parquet")).unwrap(), b"blocks");
+        assert!(!old_path.exists());
+        assert_eq!(found_after_restart, Some(new_path));
+        assert_eq!(restarted.get_chunk_info(chunk.id).unwrap().volume, Some(volume));
+
+        // cleanup
+        drop(restarted);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,275 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::checksum::FileChecksums;
+use crate::data_catalogue::ChunkStatus;
+use crate::data_chunk::{ChunkId, DataChunk};
+use crate::origin;
+
+/// Why the files of a chunk could not be repaired
+#[derive(Clone, Debug, PartialEq)]
+pub enum RepairError {
+    /// The chunk is not in the catalogue
+    UnknownChunk(ChunkId),
+    /// Only `Ready` and `Failed` chunks can be repaired, the chunk has this status
+    Busy(ChunkStatus),
+    /// The chunk directory doesn't exist, there is nothing to repair, the chunk is downloaded with `download_chunk`
+    MissingDirectory(PathBuf),
+    /// The files of the chunk can't be replaced, its dataset is frozen or the chunk is under a legal hold
+    Refused,
+    /// The damaged files of the chunk are already being fetched again
+    InProgress,
+}
+
+impl fmt::Display for RepairError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepairError::UnknownChunk(chunk_id) => write!(f, "chunk {} is not in the catalogue", hex::encode(chunk_id)),
+            RepairError::Busy(status) => write!(f, "chunk is being processed, its status is {}", status),
+            RepairError::MissingDirectory(path) => write!(f, "chunk directory {} doesn't exist", path.display()),
+            RepairError::Refused => write!(f, "files of the chunk can't be replaced"),
+            RepairError::InProgress => write!(f, "chunk is already being repaired"),
+        }
+    }
+}
+
+impl std::error::Error for RepairError {}
+
+/// Files of the chunk in the directory which are missing, unreadable, of another size than declared
+/// or with another checksum than known, sorted by name
+pub fn damaged_files(chunk: &DataChunk, chunk_dir: &Path, checksums: &FileChecksums) -> Vec<String> {
+    let mut damaged: Vec<String> = chunk.files
+        .keys()
+        .filter(|file_name| {
+            let path = chunk_dir.join(file_name);
+            if !origin::is_intact(&path) {
+                return true;
+            }
+            let size_differs = chunk.file_sizes.get(*file_name)
+                .is_some_and(|expected| fs::metadata(&path).map_or(true, |metadata| metadata.len() != *expected));
+            size_differs || checksums.get(*file_name).is_some_and(|expected| {
+                fs::read(&path).map_or(true, |content| !sha256::digest(content).eq_ignore_ascii_case(expected))
+            })
+        })
+        .cloned()
+        .collect();
+    damaged.sort();
+    damaged
+}
+
+/// The chunk with only the damaged files, to be transferred next to the intact files already in place
+pub(crate) fn damaged_chunk(chunk: &DataChunk, damaged_files: &[String]) -> DataChunk {
+    let mut damaged = chunk.clone();
+    damaged.files.retain(|file_name, _| damaged_files.contains(file_name));
+    damaged.mirrors.retain(|file_name, _| damaged_files.contains(file_name));
+    damaged.file_sizes.retain(|file_name, _| damaged_files.contains(file_name));
+    // the declared total is checked on the whole chunk once the files are complete
+    damaged.size = None;
+    damaged
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use crate::chunk_errors::ChunkErrorKind;
+    use crate::config::DataManagerConfig;
+    use crate::data_chunk::DataChunkRef;
+    use crate::data_manager::DataManager;
+    use crate::origin::OriginFetcher;
+    use crate::transfer::ChunkTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// Remote storage recording the files it transfers, the transfers wait while the `gate` is held
+    #[derive(Default)]
+    struct RecordingTransfer {
+        transferred: Mutex<Vec<String>>,
+        gate: Mutex<()>,
+    }
+
+    impl ChunkTransfer for RecordingTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            let _gate = self.gate.lock().unwrap();
+            fs::create_dir_all(chunk_dir)?;
+            for file_name in chunk.files.keys() {
+                self.transferred.lock().unwrap().push(file_name.clone());
+                fs::write(chunk_dir.join(file_name), b"PAR1 rows PAR1")?;
+            }
+            Ok(())
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    /// Origin serving every file with the same intact content
+    struct PublishedOrigin;
+
+    impl OriginFetcher for PublishedOrigin {
+        fn fetch(&self, _url: &str) -> io::Result<Vec<u8>> {
+            Ok(b"PAR1 rows PAR1".to_vec())
+        }
+    }
+
+    /// Remote storage whose deletions fail, after removing the `blocks.parquet` file when `removes_blocks` is set
+    struct FailingDeletionTransfer {
+        removes_blocks: AtomicBool,
+    }
+
+    impl ChunkTransfer for FailingDeletionTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            RecordingTransfer::default().download(chunk, chunk_dir)
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            if self.removes_blocks.load(Ordering::SeqCst) {
+                fs::remove_file(chunk_dir.join("blocks.parquet"))?;
+            }
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, "permission denied"))
+        }
+    }
+
+    fn chunk() -> DataChunk {
+        DataChunk {
+            file_sizes: HashMap::from([("traces.parquet".to_string(), 14)]),
+            ..DataChunk::new([29u8; 32], 0..10).with_example_files(&["blocks.parquet", "logs.parquet", "traces.parquet", "transactions.parquet"])
+        }
+    }
+
+    #[test]
+    fn test_damaged_files() {
+        let dir = test_support::temp_dir("damaged_files");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("blocks.parquet"), b"PAR1 rows PAR1").unwrap();
+        fs::write(dir.join("logs.parquet"), b"PAR1 truncated").unwrap();
+        fs::write(dir.join("traces.parquet"), b"PAR1 row PAR1").unwrap();
+        fs::write(dir.join("transactions.parquet"), b"PAR1 rows PAR1").unwrap();
+        let checksums = FileChecksums::from([("transactions.parquet".to_string(), sha256::digest(b"PAR1 other PAR1".to_vec()))]);
+
+        assert_eq!(damaged_files(&chunk(), &dir, &FileChecksums::new()), vec!["logs.parquet", "traces.parquet"]);
+        assert_eq!(damaged_files(&chunk(), &dir, &checksums), vec!["logs.parquet", "traces.parquet", "transactions.parquet"]);
+        fs::remove_file(dir.join("blocks.parquet")).unwrap();
+        assert_eq!(damaged_files(&chunk(), &dir, &checksums)[0], "blocks.parquet");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_partially_deleted_chunk_is_failed() {
+        // Arrange
+        let dir = test_support::temp_dir("failed_deletion");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let transfer = Arc::new(FailingDeletionTransfer { removes_blocks: AtomicBool::new(false) });
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(transfer.clone())
+            .build();
+        let chunk = chunk();
+        data_manager.download_chunk_with_handle(chunk.clone()).wait().unwrap();
+
+        // Act
+        data_manager.delete_chunk(chunk.id);
+        let untouched = data_manager.data_catalogue.wait_until_settled_for(&chunk.id, Duration::from_secs(5));
+        transfer.removes_blocks.store(true, Ordering::SeqCst);
+        data_manager.delete_chunk(chunk.id);
+        let partially_deleted = data_manager.data_catalogue.wait_until_settled_for(&chunk.id, Duration::from_secs(5));
+
+        // Assert
+        assert_eq!(untouched, Some(ChunkStatus::Ready));
+        assert_eq!(partially_deleted, Some(ChunkStatus::Failed));
+        let info = data_manager.get_chunk_info(chunk.id).unwrap();
+        assert_eq!(info.errors.iter().map(|error| error.kind).collect::<Vec<_>>(), vec![ChunkErrorKind::Delete, ChunkErrorKind::Delete]);
+        assert!(data_manager.find_chunk([29u8; 32], 5).is_none());
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_repair_fetches_only_damaged_files() {
+        // Arrange
+        let dir = test_support::temp_dir("repair");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let transfer = Arc::new(RecordingTransfer::default());
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(transfer.clone())
+            .build();
+        let chunk = chunk();
+        data_manager.download_chunk_with_handle(chunk.clone()).wait().unwrap();
+        let chunk_dir = data_manager.data_source.chunk_path(chunk.clone()).path;
+        fs::remove_file(chunk_dir.join("logs.parquet")).unwrap();
+        fs::write(chunk_dir.join("traces.parquet"), b"PAR1 corrupt").unwrap();
+        transfer.transferred.lock().unwrap().clear();
+
+        // Act
+        let gate = transfer.gate.lock().unwrap();
+        let repaired = data_manager.repair_chunk(chunk.id);
+        let found_during_repair = data_manager.find_chunk([29u8; 32], 5).map(|found| found.path().to_path_buf());
+        let repaired_again = data_manager.repair_chunk(chunk.id);
+        drop(gate);
+        test_support::wait_until("the repair never finished", || !data_manager.data_catalogue.is_repairing(&chunk.id));
+        let intact = data_manager.repair_chunk(chunk.id);
+
+        // Assert
+        assert_eq!(repaired, Ok(vec!["logs.parquet".to_string(), "traces.parquet".to_string()]));
+        assert_eq!(found_during_repair, Some(chunk_dir.clone()));
+        assert_eq!(repaired_again, Err(RepairError::InProgress));
+        let mut transferred = transfer.transferred.lock().unwrap().clone();
+        transferred.sort();
+        assert_eq!(transferred, vec!["logs.parquet", "traces.parquet"]);
+        assert_eq!(data_manager.get_chunk_info(chunk.id).unwrap().status, ChunkStatus::Ready);
+        assert_eq!(intact, Ok(Vec::new()));
+        assert_eq!(data_manager.repair_chunk([0u8; 32]), Err(RepairError::UnknownChunk([0u8; 32])));
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_broken_file_is_read_from_origin_during_repair() {
+        // Arrange
+        let dir = test_support::temp_dir("repair_origin");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let transfer = Arc::new(RecordingTransfer::default());
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(transfer.clone())
+            .origin_fallback(Arc::new(PublishedOrigin))
+            .build();
+        let chunk = chunk();
+        data_manager.download_chunk_with_handle(chunk.clone()).wait().unwrap();
+        let chunk_dir = data_manager.data_source.chunk_path(chunk.clone()).path;
+        fs::write(chunk_dir.join("logs.parquet"), b"PAR1 corrupt").unwrap();
+
+        // Act
+        let gate = transfer.gate.lock().unwrap();
+        let first_read = data_manager.mmap_chunk_file(chunk.id, "logs.parquet").unwrap();
+        let second_read = data_manager.mmap_chunk_file(chunk.id, "logs.parquet").unwrap();
+        let status_during_repair = data_manager.get_chunk_info(chunk.id).map(|info| info.status);
+        drop(gate);
+        test_support::wait_until("the repair never finished", || !data_manager.data_catalogue.is_repairing(&chunk.id));
+        let repaired_read = data_manager.mmap_chunk_file(chunk.id, "logs.parquet").unwrap();
+
+        // Assert
+        assert!(first_read.is_fetched());
+        assert!(second_read.is_fetched());
+        assert_eq!(&second_read[..], b"PAR1 rows PAR1");
+        assert_eq!(status_during_repair, Some(ChunkStatus::Ready));
+        assert!(!repaired_read.is_fetched());
+        assert_eq!(&repaired_read[..], b"PAR1 rows PAR1");
+
+        // cleanup
+        drop((first_read, second_read, repaired_read));
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
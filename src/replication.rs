@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{self, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+use crate::data_catalogue::ChunkStatus;
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
+use crate::hooks::{LifecycleEvent, LifecycleHooks};
+use crate::workers::Workers;
+
+/// How often the follower checks whether it was dropped, while no events arrive
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Change of the catalogue of the leader, which followers replicate
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplicationEvent {
+    /// The chunk is ready on the leader
+    Available(DataChunk),
+    /// The chunk was deleted or evicted on the leader
+    Removed(DataChunk),
+}
+
+/// Lifecycle hooks of the leader, publishing the changes of its catalogue to a follower
+#[derive(Clone)]
+pub struct LeaderFeed {
+    events: mpsc::Sender<ReplicationEvent>,
+}
+
+/// Changes of the catalogue of the leader, consumed by the follower built with `DataManagerBuilder::follow_leader`
+pub struct ReplicationStream {
+    events: mpsc::Receiver<ReplicationEvent>,
+}
+
+/// Connect a leader and a follower, the feed is registered as lifecycle hooks of the leader
+pub fn feed() -> (LeaderFeed, ReplicationStream) {
+    let (events, received) = mpsc::channel();
+    (LeaderFeed { events }, ReplicationStream { events: received })
+}
+
+impl LeaderFeed {
+    /// Publish the chunks the leader already held when the follower was connected
+    pub fn announce(&self, chunks: impl IntoIterator<Item = DataChunk>) {
+        for chunk in chunks {
+            self.publish(ReplicationEvent::Available(chunk));
+        }
+    }
+
+    fn publish(&self, event: ReplicationEvent) {
+        // the leader keeps working when the follower is gone
+        let _ = self.events.send(event);
+    }
+}
+
+impl LifecycleHooks for LeaderFeed {
+    fn on_download_complete(&self, chunk: &DataChunk) {
+        self.publish(ReplicationEvent::Available(chunk.clone()));
+    }
+
+    fn on_register(&self, chunk: &DataChunk) {
+        self.publish(ReplicationEvent::Available(chunk.clone()));
+    }
+
+    fn on_delete(&self, chunk: &DataChunk) {
+        self.publish(ReplicationEvent::Removed(chunk.clone()));
+    }
+
+    fn on_evict(&self, chunk: &DataChunk) {
+        self.publish(ReplicationEvent::Removed(chunk.clone()));
+    }
+}
+
+/// Replicated catalogue of the leader of a follower.
+/// Chunks of the leader are mirrored lazily, the first `find_chunk` of their blocks starts their download,
+/// and chunks removed on the leader are deleted on the follower as well.
+/// The job applying the changes stops when the replica is dropped or the leader feed is gone.
+pub struct Replica {
+    leader_chunks: Arc<RwLock<HashMap<ChunkId, DataChunk>>>,
+    _stop: mpsc::Sender<()>,
+}
+
+impl Replica {
+    pub(crate) fn start(stream: ReplicationStream, workers: Workers) -> Self {
+        let leader_chunks = Arc::new(RwLock::new(HashMap::new()));
+        let (stop, stopped) = mpsc::channel::<()>();
+        let replicated = leader_chunks.clone();
+        thread::spawn(move || {
+            while !matches!(stopped.try_recv(), Err(TryRecvError::Disconnected)) {
+                match stream.events.recv_timeout(STOP_CHECK_INTERVAL) {
+                    Ok(event) => apply(&workers, &replicated, event),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+        Replica { leader_chunks, _stop: stop }
+    }
+
+    /// Chunks ready on the leader, as far as the follower knows
+    pub fn leader_chunks(&self) -> Vec<DataChunk> {
+        self.leader_chunks.read().unwrap().values().cloned().collect()
+    }
+
+    /// Start downloading the chunk of the leader holding the block, returns whether a download started
+    pub(crate) fn mirror(&self, workers: &Workers, dataset_id: &DatasetId, block_number: u64) -> bool {
+        let chunk = self.leader_chunks.read().unwrap()
+            .values()
+            .find(|chunk| chunk.dataset_id == *dataset_id && chunk.block_range.contains(&block_number))
+            .cloned();
+        let Some(chunk) = chunk else { return false };
+        if !workers.data_catalogue.start_download(&chunk) {
+            return false;
+        }
+        workers.hooks.emit(LifecycleEvent::DownloadStart(chunk.clone()));
+        workers.spawn_download(chunk);
+        true
+    }
+}
+
+fn apply(workers: &Workers, leader_chunks: &RwLock<HashMap<ChunkId, DataChunk>>, event: ReplicationEvent) {
+    match event {
+        ReplicationEvent::Available(chunk) => {
+            leader_chunks.write().unwrap().insert(chunk.id, chunk);
+        }
+        ReplicationEvent::Removed(chunk) => {
+            leader_chunks.write().unwrap().remove(&chunk.id);
+            let mirrored = workers.data_catalogue.get_chunk_info(&chunk.id).is_some_and(|info| info.status == ChunkStatus::Ready);
+            if mirrored && workers.data_catalogue.start_deletion(&chunk) {
+                workers.spawn_deletion(chunk);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::data_manager::DataManager;
+    use crate::test_support;
+    use super::*;
+
+    fn build(dir: &Path) -> crate::builder::DataManagerBuilder {
+        fs::create_dir_all(dir.join("data")).unwrap();
+        test_support::manager_builder(dir)
+    }
+
+    fn wait() {
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(250));
+        });
+    }
+
+    #[test]
+    fn test_follower_mirrors_chunks_of_leader() {
+        // Arrange
+        let dir = test_support::temp_dir("replication");
+        let (feed, stream) = feed();
+        let leader = build(&dir.join("leader")).lifecycle_hooks(Arc::new(feed)).build();
+        let follower = build(&dir.join("follower")).follow_leader(stream).build();
+        let chunk = test_support::chunk(7, 0..10);
+
+        // Act
+        leader.download_chunk(chunk.clone());
+        wait();
+        let first_read = follower.find_chunk(chunk.dataset_id, 5).is_some();
+        wait();
+        let mirrored_read = follower.find_chunk(chunk.dataset_id, 5).is_some();
+        leader.delete_chunk(chunk.id);
+        wait();
+        wait();
+
+        // Assert
+        assert!(!first_read);
+        assert!(mirrored_read);
+        assert!(follower.replica.as_ref().unwrap().leader_chunks().is_empty());
+        assert!(follower.list_chunks().is_empty());
+
+        // cleanup
+        drop(follower);
+        drop(leader);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
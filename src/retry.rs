@@ -0,0 +1,231 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::time::Duration;
+use crate::checksum;
+use crate::verification;
+
+/// How failed downloads are retried before the chunk is marked `Failed`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts of a download including the first one, at least 1
+    pub max_attempts: u32,
+    /// Wait before the first retry
+    pub initial_backoff: Duration,
+    /// The wait grows by this factor with every retry, at least 1
+    pub multiplier: f64,
+    /// Longest wait between two attempts
+    pub max_backoff: Duration,
+    /// Up to this fraction of every wait is dropped at random, within [0, 1],
+    /// so downloads failed by the same outage don't all retry at once
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            multiplier: 2.0,
+            max_backoff: Duration::from_secs(30),
+            jitter: 0.5,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before the retry following the failed `attempt`, counted from 1, or `None` when the attempts are exhausted
+    pub fn backoff(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(attempt as i32 - 1);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+        Some(Duration::from_secs_f64(backoff * (1.0 - self.jitter * random_fraction())))
+    }
+
+    /// Wait before the retry following an attempt refused by the origin with a 429 or 503 response:
+    /// its `Retry-After` when it's longer than the backoff, capped at `max_backoff`
+    pub fn throttled_backoff(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        let backoff = self.backoff(attempt)?;
+        Some(retry_after.map_or(backoff, |retry_after| retry_after.clamp(backoff, self.max_backoff.max(backoff))))
+    }
+}
+
+/// Whether another attempt may succeed, the files of another fork stay the same however often they're downloaded
+pub(crate) fn is_retryable(error: &io::Error) -> bool {
+    !verification::is_hash_mismatch(error) && !checksum::is_checksum_mismatch(error)
+}
+
+/// Random number within [0, 1), every `RandomState` is seeded differently
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+    use crate::chunk_errors::ChunkErrorKind;
+    use crate::config::DataManagerConfig;
+    use crate::data_catalogue::ChunkStatus;
+    use crate::data_chunk::DataChunk;
+    use crate::data_manager::DataManager;
+    use crate::hooks::LifecycleHooks;
+    use crate::rate_limit;
+    use crate::transfer::ChunkTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// Remote storage failing the first `failures` downloads
+    struct FlakyTransfer {
+        failures: u32,
+        attempts: AtomicU32,
+    }
+
+    impl ChunkTransfer for FlakyTransfer {
+        fn download(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "connection reset"));
+            }
+            fs::create_dir_all(chunk_dir)?;
+            fs::write(chunk_dir.join("blocks.parquet"), [])
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    /// Origin refusing the first download with a `Retry-After` of 150ms
+    struct RefusingTransfer {
+        attempts: AtomicU32,
+    }
+
+    impl ChunkTransfer for RefusingTransfer {
+        fn download(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                return Err(rate_limit::rate_limited(Some(Duration::from_millis(150))));
+            }
+            fs::create_dir_all(chunk_dir)?;
+            fs::write(chunk_dir.join("blocks.parquet"), [])
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    #[derive(Default)]
+    struct ThrottleRecorder {
+        throttled: Mutex<Vec<Option<Duration>>>,
+    }
+
+    impl LifecycleHooks for ThrottleRecorder {
+        fn on_throttled(&self, _chunk: &DataChunk, retry_after: Option<Duration>) {
+            self.throttled.lock().unwrap().push(retry_after);
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy { initial_backoff: Duration::from_millis(10), ..RetryPolicy::default() }
+    }
+
+    #[test]
+    fn test_backoff_grows_up_to_max() {
+        let policy = RetryPolicy { max_attempts: 5, max_backoff: Duration::from_secs(1), jitter: 0.0, ..RetryPolicy::default() };
+        let backoffs: Vec<Option<Duration>> = (1..=5).map(|attempt| policy.backoff(attempt)).collect();
+        assert_eq!(backoffs, vec![
+            Some(Duration::from_millis(500)),
+            Some(Duration::from_secs(1)),
+            Some(Duration::from_secs(1)),
+            Some(Duration::from_secs(1)),
+            None,
+        ]);
+
+        let jittered = RetryPolicy { jitter: 0.5, ..policy.clone() }.backoff(1).unwrap();
+        assert!(jittered > Duration::from_millis(250) && jittered <= Duration::from_millis(500));
+
+        // the origin's wait is honoured when it's longer than the backoff, up to the longest backoff
+        assert_eq!(policy.throttled_backoff(1, Some(Duration::from_millis(800))), Some(Duration::from_millis(800)));
+        assert_eq!(policy.throttled_backoff(1, Some(Duration::from_millis(100))), Some(Duration::from_millis(500)));
+        assert_eq!(policy.throttled_backoff(1, Some(Duration::from_secs(120))), Some(Duration::from_secs(1)));
+        assert_eq!(policy.throttled_backoff(1, None), Some(Duration::from_millis(500)));
+        assert_eq!(policy.throttled_backoff(5, Some(Duration::from_millis(800))), None);
+    }
+
+    #[test]
+    fn test_chunk_fails_only_after_retries_are_exhausted() {
+        // Arrange
+        let dir = test_support::temp_dir("retry");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let transfer = Arc::new(FlakyTransfer { failures: 4, attempts: AtomicU32::new(0) });
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data"))
+                .with_catalogue_file(dir.join("registry.parquet"))
+                .with_download_retry(policy()))
+            .chunk_transfer(transfer.clone())
+            .build();
+        let chunk = |block: u64| test_support::chunk(4, block..block + 10);
+
+        // Act
+        data_manager.download_chunk(chunk(0));
+        data_manager.data_catalogue.wait_until_downloaded(&[chunk(0).id]);
+        let attempts_of_failed = transfer.attempts.load(Ordering::SeqCst);
+        data_manager.download_chunk(chunk(10));
+        data_manager.data_catalogue.wait_until_downloaded(&[chunk(10).id]);
+
+        // Assert
+        assert_eq!(attempts_of_failed, 3);
+        let failed = data_manager.get_chunk_info(chunk(0).id).unwrap();
+        assert_eq!(failed.status, ChunkStatus::Failed);
+        assert_eq!(failed.errors.len(), 3);
+        assert!(failed.errors.iter().all(|error| error.kind == ChunkErrorKind::Download));
+        let retried = data_manager.get_chunk_info(chunk(10).id).unwrap();
+        assert_eq!(retried.status, ChunkStatus::Ready);
+        assert_eq!(retried.errors.len(), 1);
+        assert_eq!(transfer.attempts.load(Ordering::SeqCst), 5);
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_refused_download_is_retried_after_the_requested_delay() {
+        // Arrange
+        let dir = test_support::temp_dir("retry_after");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let hooks = Arc::new(ThrottleRecorder::default());
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data"))
+                .with_catalogue_file(dir.join("registry.parquet"))
+                .with_download_retry(RetryPolicy { jitter: 0.0, ..policy() }))
+            .chunk_transfer(Arc::new(RefusingTransfer { attempts: AtomicU32::new(0) }))
+            .lifecycle_hooks(hooks.clone())
+            .build();
+        let chunk = test_support::chunk(4, 20..30);
+        let started_at = Instant::now();
+
+        // Act
+        data_manager.download_chunk(chunk.clone());
+        data_manager.data_catalogue.wait_until_downloaded(&[chunk.id]);
+
+        // Assert
+        assert!(started_at.elapsed() >= Duration::from_millis(150));
+        let info = data_manager.get_chunk_info(chunk.id).unwrap();
+        assert_eq!(info.status, ChunkStatus::Ready);
+        assert_eq!(info.errors[0].message, "rate limited by the origin, retry after 150ms");
+        assert_eq!(*hooks.throttled.lock().unwrap(), vec![Some(Duration::from_millis(150))]);
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use polars::prelude::*;
+use crate::chunk_pins::ChunkPin;
+
+/// Number of rows in a batch of the stream by default
+pub const DEFAULT_BATCH_ROWS: usize = 65_536;
+
+/// Rows of a chunk file read row group by row group, so a whole chunk is never held in memory.
+/// Only the projected columns, and the columns the filter needs, are decoded,
+/// and the filter is applied to every batch as it's read.
+/// Row groups are split into batches of at most `batch_rows` rows.
+/// The chunk is pinned until the stream is dropped, so it can't be deleted while it's read.
+pub struct ChunkRowStream {
+    reader: BatchedParquetReader,
+    /// Columns of the returned batches, all of them when empty
+    projection: Vec<String>,
+    filter: Option<Expr>,
+    batch_rows: usize,
+    batches: VecDeque<DataFrame>,
+    _pin: ChunkPin,
+}
+
+impl ChunkRowStream {
+    pub(crate) fn open(file_path: &Path, projection: &[&str], filter: Option<Expr>, batch_rows: usize, pin: ChunkPin) -> io::Result<Self> {
+        let mut columns: Vec<String> = projection.iter().map(|column| column.to_string()).collect();
+        if let Some(filter) = &filter {
+            for column in filter.clone().meta().root_names() {
+                if !columns.iter().any(|projected| projected.as_str() == column.as_str()) {
+                    columns.push(column.to_string());
+                }
+            }
+        }
+        let reader = ParquetReader::new(File::open(file_path)?)
+            .with_columns((!projection.is_empty()).then_some(columns))
+            .batched(batch_rows.max(1))
+            .map_err(to_io_error)?;
+        Ok(ChunkRowStream {
+            reader,
+            projection: projection.iter().map(|column| column.to_string()).collect(),
+            filter,
+            batch_rows: batch_rows.max(1),
+            batches: VecDeque::new(),
+            _pin: pin,
+        })
+    }
+
+    fn select(&self, batch: DataFrame) -> PolarsResult<DataFrame> {
+        let mut rows = batch.lazy();
+        if let Some(filter) = &self.filter {
+            rows = rows.filter(filter.clone());
+        }
+        if !self.projection.is_empty() {
+            rows = rows.select(self.projection.iter().map(|column| col(column.as_str())).collect::<Vec<Expr>>());
+        }
+        rows.collect()
+    }
+}
+
+impl Iterator for ChunkRowStream {
+    type Item = io::Result<DataFrame>;
+
+    /// Next batch with at least one row, batches the filter emptied are skipped
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(batch) = self.batches.pop_front() {
+                match self.select(batch) {
+                    Ok(rows) if rows.height() == 0 => continue,
+                    result => return Some(result.map_err(to_io_error)),
+                }
+            }
+            // the reader is only async to fetch remote row groups, local files are read in place
+            match futures::executor::block_on(self.reader.next_batches(1)) {
+                Ok(Some(row_groups)) => {
+                    for row_group in row_groups {
+                        // slices share the buffers of the row group
+                        let slices = (0..row_group.height()).step_by(self.batch_rows).map(|offset| row_group.slice(offset as i64, self.batch_rows));
+                        self.batches.extend(slices);
+                    }
+                }
+                Ok(None) => return None,
+                Err(error) => return Some(Err(to_io_error(error))),
+            }
+        }
+    }
+}
+
+fn to_io_error(error: PolarsError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::chunk_pins::ChunkPins;
+    use crate::devtools::{self, DatasetSpec};
+    use crate::planning::DirectoryLayout;
+    use crate::data_chunk::BLOCK_NUMBER_COLUMN;
+    use crate::test_support;
+    use super::*;
+
+    #[test]
+    fn test_rows_are_streamed_in_batches() {
+        // Arrange
+        let data_dir = test_support::temp_dir("row_stream");
+        let chunk = devtools::generate(&data_dir, DirectoryLayout::default(), &[DatasetSpec::new([6u8; 32], 0, 1, 100)]).unwrap().remove(0);
+        let pins = ChunkPins::default();
+        let filter = col(BLOCK_NUMBER_COLUMN).gt_eq(lit(50u64).cast(DataType::UInt64));
+
+        // Act
+        let stream = ChunkRowStream::open(Path::new(&chunk.files["transactions.parquet"]), &["hash"], Some(filter), 128, pins.pin(&chunk.id)).unwrap();
+        let batches: Vec<DataFrame> = stream.map(Result::unwrap).collect();
+
+        // Assert
+        assert!(batches.iter().all(|batch| batch.height() <= 128));
+        assert_eq!(batches.iter().map(DataFrame::height).sum::<usize>(), 500);
+        assert!(batches.iter().all(|batch| batch.get_column_names() == ["hash"]));
+        assert_eq!(pins.pin_count(&chunk.id), 0);
+
+        // cleanup
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
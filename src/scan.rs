@@ -0,0 +1,40 @@
+use std::io::Cursor;
+use std::ops::Range;
+use std::path::PathBuf;
+use polars::io::HiveOptions;
+use polars::prelude::*;
+use crate::data_chunk::BLOCK_NUMBER_COLUMN;
+
+pub(crate) enum ParquetSource {
+    File(PathBuf),
+    /// Contents of a file fetched from its origin
+    Fetched(Vec<u8>),
+}
+
+/// Read the rows of the blocks from the parquet files, keeping only the projected columns,
+/// or all of them when the projection is empty
+pub(crate) fn scan_parquet_files(sources: &[ParquetSource], block_range: &Range<u64>, projection: &[String]) -> PolarsResult<DataFrame> {
+    if sources.is_empty() {
+        return Ok(DataFrame::empty());
+    }
+    let lazy_frames = sources
+        .iter()
+        .map(|source| match source {
+            ParquetSource::File(path) => {
+                // the `dataset_id=` and `block_range=` directories aren't hive partitions of the data
+                let hive_options = HiveOptions { enabled: Some(false), ..HiveOptions::default() };
+                LazyFrame::scan_parquet(path, ScanArgsParquet { hive_options, ..ScanArgsParquet::default() })
+            }
+            ParquetSource::Fetched(bytes) => Ok(ParquetReader::new(Cursor::new(bytes)).finish()?.lazy()),
+        })
+        .collect::<PolarsResult<Vec<LazyFrame>>>()?;
+    // typed literals, so the row group statistics can be compared with them
+    let start = lit(block_range.start).cast(DataType::UInt64);
+    let end = lit(block_range.end).cast(DataType::UInt64);
+    let mut scan = concat(lazy_frames, UnionArgs::default())?
+        .filter(col(BLOCK_NUMBER_COLUMN).gt_eq(start).and(col(BLOCK_NUMBER_COLUMN).lt(end)));
+    if !projection.is_empty() {
+        scan = scan.select(projection.iter().map(|column| col(column.as_str())).collect::<Vec<Expr>>());
+    }
+    scan.collect()
+}
@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::time::{Duration, Instant};
+use crate::checksum::FileChecksums;
+use crate::data_catalogue::{ChunkStatus, DataCatalogue};
+use crate::data_chunk::{DataChunk, DataChunkRef, DatasetId};
+use crate::data_manager::DataManager;
+use crate::size_check;
+use crate::DataManagerImpl;
+
+/// Dataset of the self-test chunk, reserved so the self-test never touches the chunks of a real dataset
+pub const SELF_TEST_DATASET_ID: DatasetId = [0xEE; 32];
+pub const SELF_TEST_FILE: &str = "self_test.txt";
+/// Content of the only file of the self-test chunk, an echo endpoint must serve exactly these bytes
+pub const SELF_TEST_CONTENT: &[u8] = b"data-manager self-test\n";
+/// Longest wait for the download and the deletion of the self-test chunk
+pub const STEP_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub const USAGE: &str = "usage: data-manager self-test <data_dir> [<catalogue_file>]";
+
+/// Where the self-test chunk comes from
+#[derive(Clone, Debug, PartialEq)]
+pub enum SelfTestSource {
+    /// The file is written into the data directory and the chunk registered, no remote storage is involved
+    Fixture,
+    /// The file is downloaded from this url through the configured `ChunkTransfer`, e.g. an echo endpoint
+    /// next to the remote storage serving `SELF_TEST_CONTENT`
+    Endpoint(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelfTestStep {
+    Download,
+    Verify,
+    Find,
+    Read,
+    Delete,
+}
+
+impl fmt::Display for SelfTestStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SelfTestStep::Download => "download",
+            SelfTestStep::Verify => "verify",
+            SelfTestStep::Find => "find",
+            SelfTestStep::Read => "read",
+            SelfTestStep::Delete => "delete",
+        };
+        // padded, so the steps line up in the report
+        f.pad(name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StepOutcome {
+    Passed,
+    Failed(String),
+    /// An earlier step failed
+    Skipped,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepReport {
+    pub step: SelfTestStep,
+    pub outcome: StepOutcome,
+    pub duration: Duration,
+}
+
+/// Outcome of every step of the self-test, in the order they ran
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelfTestReport {
+    pub source: SelfTestSource,
+    pub steps: Vec<StepReport>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(|step| step.outcome == StepOutcome::Passed)
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            SelfTestSource::Fixture => writeln!(f, "data-manager self-test from the built-in fixture")?,
+            SelfTestSource::Endpoint(url) => writeln!(f, "data-manager self-test from {}", url)?,
+        }
+        for step in self.steps.iter() {
+            let outcome = match &step.outcome {
+                StepOutcome::Passed => "passed".to_string(),
+                StepOutcome::Failed(message) => format!("failed: {}", message),
+                StepOutcome::Skipped => "skipped".to_string(),
+            };
+            writeln!(f, "{:<10} {:>10} ms  {}", step.step, step.duration.as_millis(), outcome)?;
+        }
+        writeln!(f, "{}", if self.passed() { "PASSED" } else { "FAILED" })
+    }
+}
+
+/// The self-test chunk, its file pointing to the url
+pub fn self_test_chunk(url: &str) -> DataChunk {
+    DataChunk {
+        id: DataCatalogue::generate_chunk_id(&SELF_TEST_DATASET_ID, &(0..1)),
+        dataset_id: SELF_TEST_DATASET_ID,
+        block_range: 0..1,
+        files: HashMap::from([(SELF_TEST_FILE.to_string(), url.to_string())]),
+        mirrors: HashMap::new(),
+        size: Some(SELF_TEST_CONTENT.len() as u64),
+        file_sizes: HashMap::from([(SELF_TEST_FILE.to_string(), SELF_TEST_CONTENT.len() as u64)]),
+    }
+}
+
+type StepFn<'a> = dyn Fn() -> Result<(), String> + 'a;
+
+/// Download, verify, find, read and delete the self-test chunk, stopping at the first failed step
+pub(crate) fn run(data_manager: &DataManagerImpl, source: &SelfTestSource) -> SelfTestReport {
+    let chunk = match source {
+        SelfTestSource::Fixture => self_test_chunk(SELF_TEST_FILE),
+        SelfTestSource::Endpoint(url) => self_test_chunk(url),
+    };
+    // leftovers of an interrupted self-test
+    clean_up(data_manager, &chunk);
+
+    let steps: [(SelfTestStep, &StepFn); 5] = [
+        (SelfTestStep::Download, &|| download(data_manager, source, &chunk)),
+        (SelfTestStep::Verify, &|| verify(data_manager, &chunk)),
+        (SelfTestStep::Find, &|| find(data_manager, &chunk)),
+        (SelfTestStep::Read, &|| read(data_manager, &chunk)),
+        (SelfTestStep::Delete, &|| delete(data_manager, &chunk)),
+    ];
+    let mut failed = false;
+    let steps = steps.iter()
+        .map(|(step, run)| {
+            let started_at = Instant::now();
+            let outcome = match failed {
+                true => StepOutcome::Skipped,
+                false => run().map_or_else(StepOutcome::Failed, |_| StepOutcome::Passed),
+            };
+            failed |= matches!(outcome, StepOutcome::Failed(_));
+            StepReport { step: *step, outcome, duration: started_at.elapsed() }
+        })
+        .collect();
+
+    clean_up(data_manager, &chunk);
+    SelfTestReport { source: source.clone(), steps }
+}
+
+fn download(data_manager: &DataManagerImpl, source: &SelfTestSource, chunk: &DataChunk) -> Result<(), String> {
+    match source {
+        SelfTestSource::Fixture => {
+            let chunk_dir = data_manager.data_source.chunk_path(chunk.clone()).path;
+            fs::create_dir_all(&chunk_dir)
+                .and_then(|_| fs::write(chunk_dir.join(SELF_TEST_FILE), SELF_TEST_CONTENT))
+                .map_err(|error| error.to_string())?;
+            data_manager.register_chunk(chunk.clone(), true).map_err(|error| error.to_string())
+        }
+        SelfTestSource::Endpoint(_) => {
+            let checksums = FileChecksums::from([(SELF_TEST_FILE.to_string(), sha256::digest(SELF_TEST_CONTENT))]);
+            data_manager.download_chunk_with_checksums(chunk.clone(), checksums);
+            match data_manager.data_catalogue.wait_until_settled_for(&chunk.id, STEP_TIMEOUT) {
+                Some(ChunkStatus::Ready) => Ok(()),
+                Some(ChunkStatus::Downloading) => Err(format!("not downloaded within {:?}", STEP_TIMEOUT)),
+                _ => Err(last_error(data_manager, chunk).unwrap_or_else(|| "download failed".to_string())),
+            }
+        }
+    }
+}
+
+/// The fixture is only registered, so the files on disk are checked here whatever the source
+fn verify(data_manager: &DataManagerImpl, chunk: &DataChunk) -> Result<(), String> {
+    let chunk_dir = data_manager.data_source.chunk_path(chunk.clone()).path;
+    size_check::verify(chunk, &chunk_dir).map_err(|error| error.to_string())?;
+    let content = fs::read(chunk_dir.join(SELF_TEST_FILE)).map_err(|error| error.to_string())?;
+    match sha256::digest(content.as_slice()) == sha256::digest(SELF_TEST_CONTENT) {
+        true => Ok(()),
+        false => Err(format!("{} has unexpected content", SELF_TEST_FILE)),
+    }
+}
+
+fn find(data_manager: &DataManagerImpl, chunk: &DataChunk) -> Result<(), String> {
+    let expected = data_manager.data_source.chunk_path(chunk.clone()).path;
+    match data_manager.find_chunk(chunk.dataset_id, chunk.block_range.start) {
+        Some(chunk_ref) if chunk_ref.path() == expected => Ok(()),
+        Some(chunk_ref) => Err(format!("found at {} instead of {}", chunk_ref.path().display(), expected.display())),
+        None => Err("not found".to_string()),
+    }
+}
+
+fn read(data_manager: &DataManagerImpl, chunk: &DataChunk) -> Result<(), String> {
+    let file = data_manager.mmap_chunk_file(chunk.id, SELF_TEST_FILE).map_err(|error| error.to_string())?;
+    match &*file == SELF_TEST_CONTENT {
+        true => Ok(()),
+        false => Err(format!("read {} bytes which differ from {}", file.len(), SELF_TEST_FILE)),
+    }
+}
+
+fn delete(data_manager: &DataManagerImpl, chunk: &DataChunk) -> Result<(), String> {
+    data_manager.delete_chunk(chunk.id);
+    match data_manager.data_catalogue.wait_until_settled_for(&chunk.id, STEP_TIMEOUT) {
+        Some(ChunkStatus::Deleted) | None if data_manager.find_chunk(chunk.dataset_id, chunk.block_range.start).is_none() => Ok(()),
+        Some(ChunkStatus::Deleted) | None => Err("still found after the deletion".to_string()),
+        Some(ChunkStatus::Deleting) => Err(format!("not deleted within {:?}", STEP_TIMEOUT)),
+        Some(status) => Err(last_error(data_manager, chunk).unwrap_or_else(|| format!("chunk is {} after the deletion", status))),
+    }
+}
+
+fn last_error(data_manager: &DataManagerImpl, chunk: &DataChunk) -> Option<String> {
+    data_manager.get_chunk_info(chunk.id)?.errors.last().map(|error| error.message.clone())
+}
+
+/// Forget the self-test chunk and remove its files, which the configured transfer may have left behind
+fn clean_up(data_manager: &DataManagerImpl, chunk: &DataChunk) {
+    let _ = data_manager.forget_chunk(chunk.id);
+    let chunk_dir = data_manager.data_source.chunk_path(chunk.clone()).path;
+    // missing once the transfer removed it
+    let _ = fs::remove_dir_all(&chunk_dir);
+    if let Some(dataset_dir) = chunk_dir.parent() {
+        // fails while the dataset directory isn't empty
+        let _ = fs::remove_dir(dataset_dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::path::Path;
+    use std::sync::Arc;
+    use crate::config::DataManagerConfig;
+    use crate::retry::RetryPolicy;
+    use crate::transfer::ChunkTransfer;
+    use crate::test_support;
+    use super::*;
+
+    /// Remote storage serving the self-test content, or failing every download
+    struct EchoTransfer {
+        reachable: bool,
+    }
+
+    impl ChunkTransfer for EchoTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            if !self.reachable {
+                return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "echo endpoint is unreachable"));
+            }
+            fs::create_dir_all(chunk_dir)?;
+            for file_name in chunk.files.keys() {
+                fs::write(chunk_dir.join(file_name), SELF_TEST_CONTENT)?;
+            }
+            Ok(())
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    fn build(dir: &Path, reachable: bool) -> DataManagerImpl {
+        let retry = RetryPolicy { max_attempts: 1, ..RetryPolicy::default() };
+        DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")).with_download_retry(retry))
+            .chunk_transfer(Arc::new(EchoTransfer { reachable }))
+            .build()
+    }
+
+    fn outcomes(report: &SelfTestReport) -> Vec<StepOutcome> {
+        report.steps.iter().map(|step| step.outcome.clone()).collect()
+    }
+
+    #[test]
+    fn test_self_test_passes_from_fixture_and_endpoint() {
+        // Arrange
+        let dir = test_support::temp_dir("self_test");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let data_manager = build(&dir, true);
+        let chunk_dir = data_manager.data_source.chunk_path(self_test_chunk(SELF_TEST_FILE)).path;
+        // leftover of an interrupted self-test
+        fs::create_dir_all(&chunk_dir).unwrap();
+
+        // Act
+        let fixture = data_manager.self_test(&SelfTestSource::Fixture);
+        let endpoint = data_manager.self_test(&SelfTestSource::Endpoint("https://example.com/self_test.txt".to_string()));
+
+        // Assert
+        for report in [&fixture, &endpoint] {
+            assert!(report.passed(), "{}", report);
+            let steps: Vec<SelfTestStep> = report.steps.iter().map(|step| step.step).collect();
+            assert_eq!(steps, vec![SelfTestStep::Download, SelfTestStep::Verify, SelfTestStep::Find, SelfTestStep::Read, SelfTestStep::Delete]);
+        }
+        assert!(fixture.to_string().ends_with("PASSED\n"));
+        assert!(data_manager.get_chunk_info(self_test_chunk(SELF_TEST_FILE).id).is_none());
+        assert!(!chunk_dir.exists());
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_unreachable_endpoint_fails_self_test() {
+        // Arrange
+        let dir = test_support::temp_dir("self_test_unreachable");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let data_manager = build(&dir, false);
+
+        // Act
+        let report = data_manager.self_test(&SelfTestSource::Endpoint("https://example.com/self_test.txt".to_string()));
+
+        // Assert
+        assert!(!report.passed());
+        assert_eq!(outcomes(&report), vec![
+            StepOutcome::Failed("echo endpoint is unreachable".to_string()),
+            StepOutcome::Skipped,
+            StepOutcome::Skipped,
+            StepOutcome::Skipped,
+            StepOutcome::Skipped,
+        ]);
+        assert!(report.to_string().contains("download"));
+        assert!(report.to_string().ends_with("FAILED\n"));
+        assert!(data_manager.get_chunk_info(self_test_chunk(SELF_TEST_FILE).id).is_none());
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
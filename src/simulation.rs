@@ -0,0 +1,337 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, VecDeque};
+use std::time::Duration;
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
+use crate::planning::ChunkStatus;
+use crate::sync_plan::{plan_sync, SyncPlan};
+
+/// Manifests of the datasets assigned to the manager at once, the datasets missing from it are left as they are
+pub type Assignment = BTreeMap<DatasetId, Vec<DataChunk>>;
+
+/// Synthetic costs and failures of the operations, used by `simulate` instead of a remote storage and a disk
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulationProfile {
+    /// Downloads running at once, the same as `DataManagerConfig::max_concurrent_downloads`
+    pub max_concurrent_downloads: usize,
+    /// Size of a chunk per block of its range
+    pub bytes_per_block: u64,
+    /// Throughput of a single download
+    pub download_bytes_per_sec: u64,
+    /// Time before the first byte of a download arrives
+    pub download_latency: Duration,
+    pub delete_duration: Duration,
+    /// Fraction of the download attempts which fail, within [0, 1]
+    pub failure_rate: f64,
+    /// Seed of the failures, the same seed fails the same attempts
+    pub failure_seed: u64,
+    /// Attempts of a download including the first one, the chunk is `Failed` after the last one
+    pub max_attempts: u32,
+    /// Wait before the next attempt of a failed download
+    pub retry_backoff: Duration,
+}
+
+impl Default for SimulationProfile {
+    fn default() -> Self {
+        SimulationProfile {
+            max_concurrent_downloads: 8,
+            bytes_per_block: 1024 * 1024,
+            download_bytes_per_sec: 50 * 1024 * 1024,
+            download_latency: Duration::from_millis(100),
+            delete_duration: Duration::from_millis(100),
+            failure_rate: 0.0,
+            failure_seed: 0,
+            max_attempts: 1,
+            retry_backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+impl SimulationProfile {
+    pub fn chunk_bytes(&self, chunk: &DataChunk) -> u64 {
+        (chunk.block_range.end - chunk.block_range.start) * self.bytes_per_block
+    }
+
+    fn download_duration(&self, chunk: &DataChunk) -> Duration {
+        self.download_latency + Duration::from_secs_f64(self.chunk_bytes(chunk) as f64 / self.download_bytes_per_sec.max(1) as f64)
+    }
+
+    /// Whether the attempt of the download fails, derived from the seed, so the simulation is repeatable
+    fn fails(&self, chunk_id: &ChunkId, attempt: u32) -> bool {
+        let digest = sha256::digest(format!("{}{}{}", self.failure_seed, hex::encode(chunk_id), attempt));
+        let fraction = u64::from_str_radix(&digest[..13], 16).unwrap() as f64 / (1u64 << 52) as f64;
+        fraction < self.failure_rate
+    }
+}
+
+/// How the manager converged to one assignment
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepReport {
+    /// Plans of the assigned datasets, as `ensure_chunks` would return them
+    pub plans: Vec<SyncPlan>,
+    pub download_attempts: usize,
+    pub failed_attempts: usize,
+    /// Chunks which failed all their attempts
+    pub failed_chunks: Vec<ChunkId>,
+    pub deletions: usize,
+    /// Time until all the operations completed
+    pub duration: Duration,
+    /// Largest size of the chunks on disk, including the downloads in progress and the replaced chunks not yet deleted
+    pub peak_disk_bytes: u64,
+    /// Size of the chunks on disk once converged
+    pub disk_bytes: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SimulationReport {
+    pub steps: Vec<StepReport>,
+}
+
+impl SimulationReport {
+    pub fn duration(&self) -> Duration {
+        self.steps.iter().map(|step| step.duration).sum()
+    }
+
+    pub fn peak_disk_bytes(&self) -> u64 {
+        self.steps.iter().map(|step| step.peak_disk_bytes).max().unwrap_or_default()
+    }
+}
+
+/// Simulate `ensure_chunks` of the assignments one after another, each applied once the previous one converged,
+/// starting with the `held` chunks `Ready`. Nothing is downloaded or written, the operations only take
+/// the synthetic time and disk space of the profile.
+///
+/// The reconciliation is the one of the manager: the same sync plans, downloads limited by `max_concurrent_downloads`
+/// and started in the order `ensure_chunks` issues them, deletions not waiting for a download slot,
+/// replaced chunks deleted once their replacement is ready and failed chunks downloaded again by the next assignment.
+pub fn simulate(profile: &SimulationProfile, held: &[DataChunk], assignments: &[Assignment]) -> SimulationReport {
+    let mut catalogue: BTreeMap<ChunkId, (DataChunk, ChunkStatus)> = held.iter()
+        .map(|chunk| (chunk.id, (chunk.clone(), ChunkStatus::Ready)))
+        .collect();
+    let steps = assignments.iter()
+        .map(|assignment| simulate_step(profile, &mut catalogue, assignment))
+        .collect();
+    SimulationReport { steps }
+}
+
+enum Operation {
+    Download { chunk: Box<DataChunk>, replaced: Vec<ChunkId>, attempt: u32 },
+    Delete(ChunkId),
+}
+
+fn simulate_step(profile: &SimulationProfile, catalogue: &mut BTreeMap<ChunkId, (DataChunk, ChunkStatus)>, assignment: &Assignment) -> StepReport {
+    let plans: Vec<SyncPlan> = assignment.iter()
+        .map(|(dataset_id, manifest)| plan_sync(*dataset_id, manifest, catalogue.values().map(|(chunk, status)| (chunk, status))))
+        .collect();
+    let mut disk_bytes: u64 = catalogue.values()
+        .filter(|(_, status)| *status == ChunkStatus::Ready)
+        .map(|(chunk, _)| profile.chunk_bytes(chunk))
+        .sum();
+    let mut report = StepReport {
+        plans: plans.clone(),
+        download_attempts: 0,
+        failed_attempts: 0,
+        failed_chunks: Vec::new(),
+        deletions: 0,
+        duration: Duration::ZERO,
+        peak_disk_bytes: disk_bytes,
+        disk_bytes,
+    };
+
+    let mut queued = VecDeque::new();
+    let mut deletions = Vec::new();
+    for plan in plans {
+        queued.extend(plan.downloads.into_iter().map(|chunk| Operation::Download { chunk: Box::new(chunk), replaced: Vec::new(), attempt: 1 }));
+        deletions.extend(plan.deletions.into_iter().map(Operation::Delete));
+        queued.extend(plan.replacements.into_iter().map(|replacement| {
+            Operation::Download { chunk: Box::new(replacement.chunk), replaced: replacement.replaced, attempt: 1 }
+        }));
+    }
+    for operation in queued.iter() {
+        if let Operation::Download { chunk, .. } = operation {
+            catalogue.insert(chunk.id, (DataChunk::clone(chunk), ChunkStatus::Downloading));
+        }
+    }
+
+    let mut timeline = Timeline::default();
+    let mut running_downloads = 0;
+    let mut now = Duration::ZERO;
+    for deletion in deletions {
+        timeline.start(profile, deletion, now, &mut disk_bytes);
+    }
+    loop {
+        while running_downloads < profile.max_concurrent_downloads.max(1) {
+            let Some(download) = queued.pop_front() else { break };
+            running_downloads += 1;
+            report.download_attempts += 1;
+            timeline.start(profile, download, now, &mut disk_bytes);
+        }
+        report.peak_disk_bytes = report.peak_disk_bytes.max(disk_bytes);
+        let Some((completes_at, operation)) = timeline.next() else { break };
+        now = completes_at;
+        match operation {
+            Operation::Download { chunk, replaced, attempt } => {
+                running_downloads -= 1;
+                let in_place = replaced.contains(&chunk.id);
+                if !profile.fails(&chunk.id, attempt) {
+                    catalogue.insert(chunk.id, (DataChunk::clone(&chunk), ChunkStatus::Ready));
+                    // a chunk replaced by a new version of itself has its files swapped in place
+                    if in_place {
+                        disk_bytes -= profile.chunk_bytes(&chunk);
+                    }
+                    for replaced_id in replaced.into_iter().filter(|replaced_id| *replaced_id != chunk.id) {
+                        timeline.start(profile, Operation::Delete(replaced_id), now, &mut disk_bytes);
+                    }
+                    continue;
+                }
+                disk_bytes -= profile.chunk_bytes(&chunk);
+                report.failed_attempts += 1;
+                if attempt < profile.max_attempts {
+                    // the worker keeps its slot while it waits for the next attempt
+                    running_downloads += 1;
+                    report.download_attempts += 1;
+                    timeline.start(profile, Operation::Download { chunk, replaced, attempt: attempt + 1 }, now, &mut disk_bytes);
+                } else {
+                    if in_place {
+                        disk_bytes -= profile.chunk_bytes(&chunk);
+                    }
+                    report.failed_chunks.push(chunk.id);
+                    catalogue.insert(chunk.id, (*chunk, ChunkStatus::Failed));
+                }
+            }
+            Operation::Delete(chunk_id) => {
+                report.deletions += 1;
+                if let Some((chunk, _)) = catalogue.remove(&chunk_id) {
+                    disk_bytes -= profile.chunk_bytes(&chunk);
+                }
+            }
+        }
+    }
+    report.duration = now;
+    report.disk_bytes = disk_bytes;
+    report
+}
+
+/// Operations in progress by the time they complete, the sequence number keeps the order of equal times
+#[derive(Default)]
+struct Timeline {
+    running: BinaryHeap<Reverse<(Duration, usize)>>,
+    operations: BTreeMap<usize, Operation>,
+    sequence: usize,
+}
+
+impl Timeline {
+    fn start(&mut self, profile: &SimulationProfile, operation: Operation, at: Duration, disk_bytes: &mut u64) {
+        let completes_at = match &operation {
+            Operation::Download { chunk, attempt, .. } => {
+                // the files take their space as they arrive, counted in full from the start
+                *disk_bytes += profile.chunk_bytes(chunk);
+                let backoff = if *attempt > 1 { profile.retry_backoff } else { Duration::ZERO };
+                at + backoff + profile.download_duration(chunk)
+            }
+            Operation::Delete(_) => at + profile.delete_duration,
+        };
+        self.sequence += 1;
+        self.running.push(Reverse((completes_at, self.sequence)));
+        self.operations.insert(self.sequence, operation);
+    }
+
+    fn next(&mut self) -> Option<(Duration, Operation)> {
+        let Reverse((completes_at, sequence)) = self.running.pop()?;
+        Some((completes_at, self.operations.remove(&sequence)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+    use super::*;
+
+    const DATASET: DatasetId = [1u8; 32];
+
+    fn chunk(block_range: Range<u64>, files: &[&str]) -> DataChunk {
+        DataChunk::new(DATASET, block_range).with_example_files(files)
+    }
+
+    fn assignment(chunks: Vec<DataChunk>) -> Assignment {
+        BTreeMap::from([(DATASET, chunks)])
+    }
+
+    /// A block takes 1 byte and a second to download
+    fn profile() -> SimulationProfile {
+        SimulationProfile {
+            max_concurrent_downloads: 2,
+            bytes_per_block: 1,
+            download_bytes_per_sec: 1,
+            download_latency: Duration::ZERO,
+            delete_duration: Duration::from_secs(1),
+            ..SimulationProfile::default()
+        }
+    }
+
+    #[test]
+    fn test_downloads_share_the_slots() {
+        // Act
+        let report = simulate(&profile(), &[], &[assignment(vec![chunk(0..10, &["a"]), chunk(10..20, &["a"]), chunk(20..25, &["a"])])]);
+
+        // Assert
+        let step = &report.steps[0];
+        assert_eq!(step.plans[0].download_count(), 3);
+        assert_eq!(step.download_attempts, 3);
+        // the third download starts once the first one completes
+        assert_eq!(step.duration, Duration::from_secs(15));
+        assert_eq!(step.peak_disk_bytes, 25);
+        assert_eq!(step.disk_bytes, 25);
+    }
+
+    #[test]
+    fn test_replaced_chunks_are_deleted_after_their_replacement() {
+        // Arrange
+        let held = vec![chunk(0..10, &["a"]), chunk(10..20, &["a"])];
+
+        // Act
+        let report = simulate(&profile(), &held, &[assignment(vec![chunk(0..20, &["a"])])]);
+
+        // Assert
+        let step = &report.steps[0];
+        assert_eq!(step.plans[0].replacements.len(), 1);
+        assert_eq!(step.deletions, 2);
+        assert_eq!(step.duration, Duration::from_secs(21));
+        assert_eq!(step.peak_disk_bytes, 40);
+        assert_eq!(step.disk_bytes, 20);
+    }
+
+    #[test]
+    fn test_failed_chunks_are_downloaded_by_next_assignment() {
+        // Arrange
+        let profile = SimulationProfile { failure_rate: 1.0, max_attempts: 3, retry_backoff: Duration::from_secs(1), ..profile() };
+        let chunks = vec![chunk(0..10, &["a"])];
+
+        // Act
+        let report = simulate(&profile, &[], &[assignment(chunks.clone()), assignment(chunks.clone())]);
+
+        // Assert
+        for step in report.steps.iter() {
+            assert_eq!(step.download_attempts, 3);
+            assert_eq!(step.failed_chunks, vec![chunks[0].id]);
+            assert_eq!(step.duration, Duration::from_secs(32));
+            assert_eq!(step.disk_bytes, 0);
+        }
+        assert_eq!(report.duration(), Duration::from_secs(64));
+    }
+
+    #[test]
+    fn test_failures_are_repeatable() {
+        // Arrange
+        let profile = SimulationProfile { failure_rate: 0.5, failure_seed: 42, ..profile() };
+        let chunks: Vec<DataChunk> = (0..20).map(|i| chunk(i * 10..i * 10 + 10, &["a"])).collect();
+
+        // Act
+        let first = simulate(&profile, &[], &[assignment(chunks.clone())]);
+        let second = simulate(&profile, &[], &[assignment(chunks)]);
+
+        // Assert
+        assert_eq!(first, second);
+        assert!(!first.steps[0].failed_chunks.is_empty());
+        assert!(first.steps[0].failed_chunks.len() < 20);
+    }
+}
@@ -0,0 +1,137 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::data_chunk::DataChunk;
+
+/// Bytes of a downloaded chunk or file differ from the size declared by the manifest, e.g. a truncated transfer
+/// or a file replaced on the remote storage
+#[derive(Clone, Debug, PartialEq)]
+pub struct SizeMismatch {
+    /// `None` for the total of the chunk
+    pub file_name: Option<String>,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl fmt::Display for SizeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file_name {
+            Some(file_name) => write!(f, "file {} has {} bytes, expected {}", file_name, self.actual, self.expected),
+            None => write!(f, "chunk has {} bytes, expected {}", self.actual, self.expected),
+        }
+    }
+}
+
+impl Error for SizeMismatch {}
+
+/// Compare the files of the chunk in the directory with their declared sizes and their total with the size of the chunk.
+/// A mismatch is reported as an `InvalidData` error wrapping the `SizeMismatch`.
+pub fn verify(chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+    if chunk.size.is_none() && chunk.file_sizes.is_empty() {
+        return Ok(());
+    }
+    let mut total = 0;
+    for file_name in chunk.files.keys() {
+        let actual = fs::metadata(chunk_dir.join(file_name))?.len();
+        match chunk.file_sizes.get(file_name) {
+            Some(expected) if *expected != actual => {
+                let mismatch = SizeMismatch { file_name: Some(file_name.clone()), expected: *expected, actual };
+                return Err(io::Error::new(io::ErrorKind::InvalidData, mismatch));
+            }
+            _ => total += actual,
+        }
+    }
+    match chunk.size {
+        Some(expected) if expected != total => Err(io::Error::new(io::ErrorKind::InvalidData, SizeMismatch { file_name: None, expected, actual: total })),
+        _ => Ok(()),
+    }
+}
+
+/// Whether the error is a failed size check, rather than a problem reading the files
+pub fn is_size_mismatch(error: &io::Error) -> bool {
+    error.get_ref().is_some_and(|error| error.is::<SizeMismatch>())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use crate::chunk_errors::ChunkErrorKind;
+    use crate::config::DataManagerConfig;
+    use crate::data_catalogue::ChunkStatus;
+    use crate::data_manager::DataManager;
+    use crate::transfer::ChunkTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// Remote storage writing 6 bytes per file
+    struct FixedContentTransfer;
+
+    impl ChunkTransfer for FixedContentTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::create_dir_all(chunk_dir)?;
+            for file_name in chunk.files.keys() {
+                fs::write(chunk_dir.join(file_name), b"blocks")?;
+            }
+            Ok(())
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    fn chunk(block: u64, size: Option<u64>, file_sizes: HashMap<String, u64>) -> DataChunk {
+        DataChunk { size, file_sizes, ..DataChunk::new([8u8; 32], block..block + 10).with_example_files(&["blocks.parquet", "logs.parquet"]) }
+    }
+
+    #[test]
+    fn test_expected_size_needs_every_file_sized() {
+        assert_eq!(chunk(0, Some(12), HashMap::new()).expected_size(), Some(12));
+        assert_eq!(chunk(0, None, HashMap::from([("blocks.parquet".to_string(), 6)])).expected_size(), None);
+        let file_sizes = HashMap::from([("blocks.parquet".to_string(), 6), ("logs.parquet".to_string(), 4)]);
+        assert_eq!(chunk(0, None, file_sizes).expected_size(), Some(10));
+    }
+
+    #[test]
+    fn test_chunk_of_unexpected_size_fails_download() {
+        // Arrange
+        let dir = test_support::temp_dir("sizes");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(FixedContentTransfer))
+            .build();
+        let sized = chunk(0, Some(12), HashMap::from([("blocks.parquet".to_string(), 6)]));
+        let truncated = chunk(10, None, HashMap::from([("logs.parquet".to_string(), 100)]));
+        let grown = chunk(20, Some(10), HashMap::new());
+
+        // Act
+        for chunk in [&sized, &truncated, &grown] {
+            data_manager.download_chunk(chunk.clone());
+        }
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(200));
+        });
+
+        // Assert
+        assert_eq!(data_manager.get_chunk_info(sized.id).unwrap().status, ChunkStatus::Ready);
+        assert_eq!(data_manager.get_chunk_info(sized.id).unwrap().chunk.size, Some(12));
+        for (chunk, message) in [(&truncated, "file logs.parquet has 6 bytes, expected 100"), (&grown, "chunk has 12 bytes, expected 10")] {
+            let info = data_manager.get_chunk_info(chunk.id).unwrap();
+            assert_eq!(info.status, ChunkStatus::Failed);
+            assert_eq!(info.errors[0].kind, ChunkErrorKind::Verification);
+            assert_eq!(info.errors[0].message, message);
+            assert!(!data_manager.data_source.chunk_path(chunk.clone()).path.exists());
+        }
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Number of buckets the samples of a window are counted in, so memory doesn't grow with traffic
+const BUCKETS_PER_WINDOW: u32 = 60;
+
+/// Latencies tracked against a target
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Slo {
+    /// From the call of `find_chunk` to its result
+    FindChunk,
+    /// From the download request to the chunk being `Ready`, failed downloads never meet the target
+    ChunkReady,
+}
+
+impl fmt::Display for Slo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Slo::FindChunk => write!(f, "find_chunk"),
+            Slo::ChunkReady => write!(f, "chunk_ready"),
+        }
+    }
+}
+
+/// `objective` of the operations must finish within `threshold`, e.g. 99% in 1 ms
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SloTarget {
+    pub threshold: Duration,
+    pub objective: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SloConfig {
+    pub find_chunk: SloTarget,
+    pub chunk_ready: SloTarget,
+    /// Only operations finished within the window count into the report
+    pub window: Duration,
+}
+
+impl Default for SloConfig {
+    fn default() -> Self {
+        SloConfig {
+            find_chunk: SloTarget { threshold: Duration::from_millis(1), objective: 0.99 },
+            chunk_ready: SloTarget { threshold: Duration::from_secs(10 * 60), objective: 0.99 },
+            window: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl SloConfig {
+    pub fn target(&self, slo: Slo) -> SloTarget {
+        match slo {
+            Slo::FindChunk => self.find_chunk,
+            Slo::ChunkReady => self.chunk_ready,
+        }
+    }
+}
+
+/// State of a single SLO within the window
+#[derive(Clone, Debug, PartialEq)]
+pub struct SloStatus {
+    pub slo: Slo,
+    pub target: SloTarget,
+    pub total: u64,
+    /// Operations which didn't finish within the threshold
+    pub breaches: u64,
+    /// Share of the operations within the threshold, 1.0 when there were no operations
+    pub compliance: f64,
+    /// How fast the error budget is being spent, 1.0 spends exactly the budget over the window,
+    /// anything above means the objective is going to be missed
+    pub burn_rate: f64,
+}
+
+impl SloStatus {
+    pub fn is_met(&self) -> bool {
+        self.compliance >= self.target.objective
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SloReport {
+    pub window: Duration,
+    pub slos: Vec<SloStatus>,
+}
+
+impl SloReport {
+    pub fn get(&self, slo: Slo) -> Option<&SloStatus> {
+        self.slos.iter().find(|status| status.slo == slo)
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    started_at: Instant,
+    total: u64,
+    breaches: u64,
+}
+
+/// Counts the operations meeting and breaching their targets, shared by all the threads
+#[derive(Clone)]
+pub struct SloTracker {
+    config: SloConfig,
+    buckets: Arc<Mutex<HashMap<Slo, VecDeque<Bucket>>>>,
+}
+
+impl SloTracker {
+    pub fn new(config: SloConfig) -> Self {
+        SloTracker {
+            config,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn record(&self, slo: Slo, latency: Duration) {
+        self.record_at(slo, latency, Instant::now());
+    }
+
+    fn record_at(&self, slo: Slo, latency: Duration, now: Instant) {
+        let breached = latency > self.config.target(slo).threshold;
+        let bucket_length = self.config.window / BUCKETS_PER_WINDOW;
+        let mut buckets = self.buckets.lock().unwrap();
+        let buckets = buckets.entry(slo).or_default();
+        match buckets.back_mut() {
+            Some(bucket) if now.duration_since(bucket.started_at) < bucket_length => {
+                bucket.total += 1;
+                bucket.breaches += breached as u64;
+            }
+            _ => buckets.push_back(Bucket { started_at: now, total: 1, breaches: breached as u64 }),
+        }
+        self.drop_expired(buckets, now);
+    }
+
+    pub fn report(&self) -> SloReport {
+        self.report_at(Instant::now())
+    }
+
+    fn report_at(&self, now: Instant) -> SloReport {
+        let mut all_buckets = self.buckets.lock().unwrap();
+        let slos = [Slo::FindChunk, Slo::ChunkReady]
+            .into_iter()
+            .map(|slo| {
+                let buckets = all_buckets.entry(slo).or_default();
+                self.drop_expired(buckets, now);
+                let total: u64 = buckets.iter().map(|bucket| bucket.total).sum();
+                let breaches: u64 = buckets.iter().map(|bucket| bucket.breaches).sum();
+                status(slo, self.config.target(slo), total, breaches)
+            })
+            .collect();
+        SloReport { window: self.config.window, slos }
+    }
+
+    fn drop_expired(&self, buckets: &mut VecDeque<Bucket>, now: Instant) {
+        while buckets.front().is_some_and(|bucket| now.duration_since(bucket.started_at) > self.config.window) {
+            buckets.pop_front();
+        }
+    }
+}
+
+fn status(slo: Slo, target: SloTarget, total: u64, breaches: u64) -> SloStatus {
+    let error_rate = if total == 0 { 0.0 } else { breaches as f64 / total as f64 };
+    let error_budget = 1.0 - target.objective;
+    SloStatus {
+        slo,
+        target,
+        total,
+        breaches,
+        compliance: 1.0 - error_rate,
+        burn_rate: if error_budget > 0.0 { error_rate / error_budget } else if breaches > 0 { f64::INFINITY } else { 0.0 },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_report() {
+        let report = SloTracker::new(SloConfig::default()).report();
+        let find_chunk = report.get(Slo::FindChunk).unwrap();
+        assert_eq!(find_chunk.total, 0);
+        assert_eq!(find_chunk.burn_rate, 0.0);
+        assert!(find_chunk.is_met());
+    }
+
+    #[test]
+    fn test_burn_rate() {
+        // Arrange
+        let tracker = SloTracker::new(SloConfig::default());
+
+        // Act
+        for _ in 0..96 {
+            tracker.record(Slo::FindChunk, Duration::from_micros(100));
+        }
+        for _ in 0..4 {
+            tracker.record(Slo::FindChunk, Duration::from_millis(5));
+        }
+        tracker.record(Slo::ChunkReady, Duration::from_secs(60));
+
+        // Assert
+        let report = tracker.report();
+        let find_chunk = report.get(Slo::FindChunk).unwrap();
+        assert_eq!((find_chunk.total, find_chunk.breaches), (100, 4));
+        assert!((find_chunk.burn_rate - 4.0).abs() < 1e-9);
+        assert!(!find_chunk.is_met());
+        assert!(report.get(Slo::ChunkReady).unwrap().is_met());
+    }
+
+    #[test]
+    fn test_old_operations_leave_the_window() {
+        // Arrange
+        let tracker = SloTracker::new(SloConfig { window: Duration::from_secs(60), ..SloConfig::default() });
+        let now = Instant::now();
+
+        // Act
+        tracker.record_at(Slo::FindChunk, Duration::from_millis(5), now);
+        tracker.record_at(Slo::FindChunk, Duration::ZERO, now + Duration::from_secs(30));
+
+        // Assert
+        assert_eq!(tracker.report_at(now + Duration::from_secs(45)).get(Slo::FindChunk).unwrap().total, 2);
+        let status = tracker.report_at(now + Duration::from_secs(75)).get(Slo::FindChunk).unwrap().clone();
+        assert_eq!((status.total, status.breaches), (1, 0));
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::SystemTime;
+use crate::coverage::{Coverage, CoverageState};
+use crate::data_catalogue::{ChunkInfo, ChunkStatus};
+use crate::data_chunk::{ChunkId, DatasetId};
+
+/// Copy of the catalogue taken under a single lock, later changes of the catalogue don't show in it
+#[derive(Clone, Debug)]
+pub struct CatalogueSnapshot {
+    pub taken_at: SystemTime,
+    pub chunks: Vec<ChunkInfo>,
+}
+
+/// Chunks by status
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatusCounts {
+    pub downloading: usize,
+    pub ready: usize,
+    pub deleting: usize,
+    pub deleted: usize,
+    pub failed: usize,
+}
+
+impl StatusCounts {
+    fn add(&mut self, status: &ChunkStatus) {
+        match status {
+            ChunkStatus::Downloading => self.downloading += 1,
+            ChunkStatus::Ready => self.ready += 1,
+            ChunkStatus::Deleting => self.deleting += 1,
+            ChunkStatus::Deleted => self.deleted += 1,
+            ChunkStatus::Failed => self.failed += 1,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.downloading + self.ready + self.deleting + self.deleted + self.failed
+    }
+}
+
+/// Summary of the ready chunks, of a dataset or of all of them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReadyUsage {
+    /// Bytes of the ready chunks, as recorded on disk, or as declared by the manifest for the chunks not measured yet
+    pub bytes: u64,
+    /// Ready chunks neither measured nor with a declared size, their bytes are missing from `bytes`
+    pub unsized_chunks: usize,
+    /// Blocks of the ready chunks, blocks held by overlapping chunks count once per chunk
+    pub blocks: u64,
+}
+
+impl ReadyUsage {
+    fn add(&mut self, other: &ReadyUsage) {
+        self.bytes += other.bytes;
+        self.unsized_chunks += other.unsized_chunks;
+        self.blocks += other.blocks;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DatasetSnapshotStats {
+    pub chunks: StatusCounts,
+    pub ready: ReadyUsage,
+    pub coverage: Coverage,
+}
+
+/// Summary numbers of the catalogue all computed from the same `CatalogueSnapshot`, so they add up
+/// even while a large sync changes the catalogue, see `DataManagerImpl::stats_at_snapshot`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotStats {
+    pub taken_at: SystemTime,
+    /// Sums of the counts of the datasets
+    pub chunks: StatusCounts,
+    pub ready: ReadyUsage,
+    pub datasets: BTreeMap<DatasetId, DatasetSnapshotStats>,
+}
+
+impl CatalogueSnapshot {
+    pub fn coverage(&self, dataset_id: DatasetId) -> Coverage {
+        Coverage::new(dataset_id, self.chunks.iter())
+    }
+
+    /// Statistics of the snapshot, with the bytes of the ready chunks recorded on disk, see `StorageUsage::recorded_bytes`
+    pub fn stats(&self, recorded_bytes: &HashMap<ChunkId, u64>) -> SnapshotStats {
+        let mut by_dataset: BTreeMap<DatasetId, (StatusCounts, ReadyUsage)> = BTreeMap::new();
+        for info in self.chunks.iter() {
+            let (counts, ready) = by_dataset.entry(info.chunk.dataset_id).or_default();
+            counts.add(&info.status);
+            if info.status != ChunkStatus::Ready {
+                continue;
+            }
+            ready.blocks += info.chunk.block_range.end - info.chunk.block_range.start;
+            match recorded_bytes.get(&info.chunk.id).copied().or_else(|| info.chunk.expected_size()) {
+                Some(bytes) => ready.bytes += bytes,
+                None => ready.unsized_chunks += 1,
+            }
+        }
+        let mut stats = SnapshotStats { taken_at: self.taken_at, chunks: StatusCounts::default(), ready: ReadyUsage::default(), datasets: BTreeMap::new() };
+        for (dataset_id, (counts, ready)) in by_dataset {
+            stats.chunks.downloading += counts.downloading;
+            stats.chunks.ready += counts.ready;
+            stats.chunks.deleting += counts.deleting;
+            stats.chunks.deleted += counts.deleted;
+            stats.chunks.failed += counts.failed;
+            stats.ready.add(&ready);
+            stats.datasets.insert(dataset_id, DatasetSnapshotStats { chunks: counts, ready, coverage: self.coverage(dataset_id) });
+        }
+        stats
+    }
+}
+
+impl DatasetSnapshotStats {
+    /// Blocks in the `Ready` segments of the coverage, the blocks held by overlapping chunks count once
+    pub fn covered_blocks(&self) -> u64 {
+        self.coverage.segments.iter()
+            .filter(|segment| segment.state == CoverageState::Ready)
+            .map(|segment| segment.block_range.end - segment.block_range.start)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io;
+    use std::ops::Range;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crate::config::DataManagerConfig;
+    use crate::data_chunk::DataChunk;
+    use crate::transfer::ChunkTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    struct SlowTransfer;
+
+    impl ChunkTransfer for SlowTransfer {
+        fn download(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            thread::sleep(Duration::from_millis(2));
+            fs::create_dir_all(chunk_dir)?;
+            fs::write(chunk_dir.join("blocks.parquet"), b"blocks")
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    fn chunk(dataset: u8, block_range: Range<u64>) -> DataChunk {
+        DataChunk { size: Some(100), ..test_support::chunk(dataset, block_range) }
+    }
+
+    #[test]
+    fn test_stats() {
+        let (measured, declared, downloading) = (chunk(1, 0..10), chunk(1, 10..20), chunk(2, 0..10));
+        let without_size = DataChunk { size: None, ..chunk(2, 10..30) };
+        let snapshot = CatalogueSnapshot {
+            taken_at: SystemTime::now(),
+            chunks: vec![
+                ChunkInfo::new(measured.clone(), ChunkStatus::Ready),
+                ChunkInfo::new(declared, ChunkStatus::Ready),
+                ChunkInfo::new(downloading, ChunkStatus::Downloading),
+                ChunkInfo::new(without_size, ChunkStatus::Ready),
+            ],
+        };
+
+        let stats = snapshot.stats(&HashMap::from([(measured.id, 6)]));
+
+        assert_eq!(stats.chunks, StatusCounts { downloading: 1, ready: 3, ..StatusCounts::default() });
+        assert_eq!(stats.ready, ReadyUsage { bytes: 106, unsized_chunks: 1, blocks: 40 });
+        assert_eq!(stats.datasets[&[1u8; 32]].ready, ReadyUsage { bytes: 106, unsized_chunks: 0, blocks: 20 });
+        assert_eq!(stats.datasets[&[2u8; 32]].chunks.total(), 2);
+        assert_eq!(stats.datasets[&[2u8; 32]].covered_blocks(), 20);
+    }
+
+    #[test]
+    fn test_stats_add_up_during_a_sync() {
+        // Arrange
+        let dir = test_support::temp_dir("snapshot");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(SlowTransfer))
+            .build();
+        // without a declared size, so the ready bytes are the ones measured on disk
+        let chunks: Vec<DataChunk> = (0..40u64).map(|i| DataChunk { size: None, ..chunk(17 + (i % 2) as u8, i * 10..i * 10 + 10) }).collect();
+
+        // Act
+        data_manager.download_chunks(chunks.clone());
+        let started = Instant::now();
+        let mut snapshots = Vec::new();
+        while snapshots.last().is_none_or(|stats: &SnapshotStats| stats.chunks.ready < chunks.len()) {
+            assert!(started.elapsed() < Duration::from_secs(30), "the chunks never got ready");
+            snapshots.push(data_manager.stats_at_snapshot());
+        }
+        // the sizes on disk are recorded by a status listener, after the waiting threads are woken up
+        let mut settled = data_manager.stats_at_snapshot();
+        while settled.ready.unsized_chunks > 0 {
+            assert!(started.elapsed() < Duration::from_secs(30), "the sizes on disk are never recorded");
+            thread::yield_now();
+            settled = data_manager.stats_at_snapshot();
+        }
+
+        // Assert
+        for stats in snapshots.iter() {
+            assert_eq!(stats.chunks.total(), chunks.len());
+            assert_eq!(stats.datasets.values().map(|dataset| dataset.chunks.total()).sum::<usize>(), stats.chunks.total());
+            for dataset in stats.datasets.values() {
+                assert_eq!(dataset.ready.blocks, dataset.chunks.ready as u64 * 10);
+                assert_eq!(dataset.covered_blocks(), dataset.ready.blocks);
+            }
+        }
+        assert_eq!(settled.ready, ReadyUsage { bytes: 40 * 6, unsized_chunks: 0, blocks: 400 });
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
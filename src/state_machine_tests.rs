@@ -0,0 +1,229 @@
+//! Model based tests of the catalogue state machine.
+//!
+//! Random sequences of downloads, deletions, lookups, restarts and crashes are run against the data manager
+//! and against a model holding only the set of ready chunks. After every operation the catalogue,
+//! the data directory and `find_chunk` must agree with the model.
+//! Failing sequences are reported with their seed, so they can be replayed.
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::config::DataManagerConfig;
+use crate::data_catalogue::ChunkStatus;
+use crate::data_chunk::{ChunkId, DataChunk, DataChunkRef, DatasetId};
+use crate::data_manager::DataManager;
+use crate::test_support;
+use crate::transfer::ChunkTransfer;
+use crate::DataManagerImpl;
+
+const SEEDS: u64 = 6;
+const OPERATIONS: usize = 40;
+const DATASETS: [DatasetId; 2] = [[1u8; 32], [2u8; 32]];
+
+/// Remote storage writing a placeholder file instantly, the download of the failing chunks always fails.
+/// The download of the crashing chunk reports it started and never returns, like in a process which was killed.
+struct ModelTransfer {
+    failing: HashSet<ChunkId>,
+    crashing: Mutex<Option<(ChunkId, mpsc::Sender<()>)>>,
+}
+
+impl ChunkTransfer for ModelTransfer {
+    fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+        let crashing = self.crashing.lock().unwrap().take_if(|(chunk_id, _)| *chunk_id == chunk.id);
+        if let Some((_, started)) = crashing {
+            let _ = started.send(());
+            loop {
+                thread::park();
+            }
+        }
+        if self.failing.contains(&chunk.id) {
+            return Err(io::Error::new(io::ErrorKind::ConnectionReset, "remote storage unavailable"));
+        }
+        fs::create_dir_all(chunk_dir)?;
+        fs::write(chunk_dir.join("blocks.parquet"), [])
+    }
+
+    fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+        fs::remove_dir_all(chunk_dir)
+    }
+}
+
+/// Deterministic xorshift generator, so a failing sequence is replayed from its seed
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Operation {
+    Download(usize),
+    Delete(usize),
+    Find(usize, u64),
+    Restart,
+    /// Restart while the download of the chunk is transferring
+    Crash(usize),
+}
+
+impl Operation {
+    fn generate(rng: &mut Rng, chunks: usize) -> Self {
+        match rng.below(11) {
+            0..=3 => Operation::Download(rng.below(chunks)),
+            4..=6 => Operation::Delete(rng.below(chunks)),
+            7..=8 => Operation::Find(rng.below(DATASETS.len()), rng.below(160) as u64),
+            9 => Operation::Restart,
+            _ => Operation::Crash(rng.below(chunks)),
+        }
+    }
+}
+
+struct Harness {
+    config: DataManagerConfig,
+    transfer: Arc<ModelTransfer>,
+    chunks: Vec<DataChunk>,
+    data_manager: DataManagerImpl,
+    /// ids of the chunks the model holds ready
+    ready: HashSet<ChunkId>,
+}
+
+impl Harness {
+    fn new(dir: &Path) -> Self {
+        let chunks: Vec<DataChunk> = [(0, 0..10), (0, 10..20), (0, 20..40), (1, 0..50), (1, 50..100), (1, 100..150)]
+            .into_iter()
+            .map(|(dataset, block_range)| DataChunk::new(DATASETS[dataset], block_range).with_example_files(&["blocks.parquet"]))
+            .collect();
+        let data_dir = dir.join("data");
+        fs::create_dir_all(&data_dir).unwrap();
+        let config = DataManagerConfig::new(data_dir)
+            .with_catalogue_file(dir.join("registry.parquet"))
+            .with_watchdog(None)
+            .with_storage_sampling(None);
+        let transfer = Arc::new(ModelTransfer { failing: HashSet::from([chunks[5].id]), crashing: Mutex::new(None) });
+        let data_manager = Harness::start(&config, &transfer);
+        Harness { config, transfer, chunks, data_manager, ready: HashSet::new() }
+    }
+
+    fn start(config: &DataManagerConfig, transfer: &Arc<ModelTransfer>) -> DataManagerImpl {
+        DataManagerImpl::builder()
+            .config(config.clone())
+            .chunk_transfer(transfer.clone())
+            .build()
+    }
+
+    fn apply(&mut self, operation: Operation) {
+        match operation {
+            Operation::Download(chunk) => {
+                let chunk = self.chunks[chunk].clone();
+                if !self.transfer.failing.contains(&chunk.id) {
+                    self.ready.insert(chunk.id);
+                }
+                self.data_manager.download_chunk(chunk);
+            }
+            Operation::Delete(chunk) => {
+                let chunk_id = self.chunks[chunk].id;
+                self.ready.remove(&chunk_id);
+                self.data_manager.delete_chunk(chunk_id);
+            }
+            Operation::Find(dataset, block_number) => {
+                let expected = self.expected_path(&DATASETS[dataset], block_number);
+                // the reference pins the chunk, so it's dropped right away
+                let found = self.data_manager.find_chunk(DATASETS[dataset], block_number).map(|chunk| chunk.path().to_path_buf());
+                assert_eq!(found, expected, "find_chunk of block {}", block_number);
+            }
+            Operation::Restart => {
+                self.settle();
+                self.data_manager = Harness::start(&self.config, &self.transfer);
+            }
+            Operation::Crash(chunk) => {
+                let chunk = self.chunks[chunk].clone();
+                // a ready chunk isn't downloaded again, so there's no transfer to crash in
+                if !self.ready.contains(&chunk.id) {
+                    let (started, transferring) = mpsc::channel();
+                    *self.transfer.crashing.lock().unwrap() = Some((chunk.id, started));
+                    self.data_manager.download_chunk(chunk.clone());
+                    transferring.recv_timeout(Duration::from_secs(5)).expect("download of the crashing chunk never started");
+                    // the download is resumed after the restart
+                    if !self.transfer.failing.contains(&chunk.id) {
+                        self.ready.insert(chunk.id);
+                    }
+                }
+                // the crashed data manager is left with its transfer hanging, nothing of it runs anymore
+                self.data_manager = Harness::start(&self.config, &self.transfer);
+            }
+        }
+        self.settle();
+    }
+
+    /// Wait for the background workers to finish the transitions, including saving the catalogue
+    fn settle(&self) {
+        let started_at = Instant::now();
+        while self.chunks.iter().any(|chunk| self.data_manager.tasks_manager.is_active(&chunk.id))
+            || self.data_manager.data_catalogue.registry.read().unwrap().values()
+                .any(|info| matches!(info.status, ChunkStatus::Downloading | ChunkStatus::Deleting)) {
+            assert!(started_at.elapsed() < Duration::from_secs(5), "chunks stuck in a transition");
+            futures::executor::block_on(async {
+                thread::sleep(Duration::from_millis(2));
+            });
+        }
+    }
+
+    fn expected_path(&self, dataset_id: &DatasetId, block_number: u64) -> Option<PathBuf> {
+        self.chunks.iter()
+            .find(|chunk| chunk.dataset_id == *dataset_id && chunk.block_range.contains(&block_number) && self.ready.contains(&chunk.id))
+            .map(|chunk| self.data_manager.data_source.chunk_path(chunk.clone()).path)
+    }
+
+    fn check_consistency(&self) {
+        let mut listed = self.data_manager.list_chunks();
+        listed.sort();
+        let mut on_disk = self.data_manager.data_source.get_local_chunk_ids();
+        on_disk.sort();
+        let mut expected: Vec<ChunkId> = self.ready.iter().copied().collect();
+        expected.sort();
+        assert_eq!(listed, expected, "ready chunks of the catalogue");
+        assert_eq!(on_disk, expected, "chunks in the data directory");
+        for chunk in self.chunks.iter() {
+            assert!(self.data_manager.may_have_chunk(&chunk.id) || !self.ready.contains(&chunk.id), "filter of the ready chunks");
+        }
+    }
+}
+
+#[test]
+fn test_catalogue_matches_model() {
+    for seed in 1..=SEEDS {
+        // Arrange
+        let dir = test_support::temp_dir(&format!("model_{}", seed));
+        let mut harness = Harness::new(&dir);
+        let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let mut operations = Vec::new();
+
+        for _ in 0..OPERATIONS {
+            // Act
+            let operation = Operation::generate(&mut rng, harness.chunks.len());
+            operations.push(operation);
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                harness.apply(operation);
+                harness.check_consistency();
+            }));
+
+            // Assert
+            if let Err(panic) = result {
+                let _ = fs::remove_dir_all(&dir);
+                panic!("seed {} diverged from the model after {:?}: {:?}", seed, operations, panic.downcast_ref::<String>());
+            }
+        }
+
+        // cleanup
+        drop(harness);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
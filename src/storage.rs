@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use crate::data_catalogue::ChunkStatus;
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
+use crate::maintenance::MaintenancePriorities;
+use crate::workers::Workers;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StorageSamplingConfig {
+    /// How often some chunks are measured again
+    pub interval: Duration,
+    /// How many chunks are measured at once, chunks never measured go first, then the ones measured longest ago
+    pub chunks_per_sample: usize,
+}
+
+impl Default for StorageSamplingConfig {
+    fn default() -> Self {
+        StorageSamplingConfig {
+            interval: Duration::from_secs(60),
+            chunks_per_sample: 16,
+        }
+    }
+}
+
+/// Disk usage of the chunks held locally
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StorageStats {
+    /// Bytes of all the measured chunks
+    pub total_bytes: u64,
+    pub dataset_bytes: HashMap<DatasetId, u64>,
+    pub measured_chunks: usize,
+    /// Chunks found on startup, which weren't sampled yet, their bytes are missing from the totals
+    pub unmeasured_chunks: usize,
+    /// Difference between the sampled and the recorded sizes, summed over all the samples
+    pub corrected_bytes: i64,
+    pub last_sample: Option<SystemTime>,
+}
+
+#[derive(Clone, Debug)]
+struct ChunkUsage {
+    dataset_id: DatasetId,
+    bytes: Option<u64>,
+    measured_at: Option<Instant>,
+}
+
+/// Sizes of the chunks recorded when they get ready or deleted, so the usage is known without walking the data directory.
+/// Sampling corrects the sizes which drifted, e.g. when files were changed by other processes.
+#[derive(Clone, Default)]
+pub struct StorageUsage {
+    chunks: Arc<RwLock<HashMap<ChunkId, ChunkUsage>>>,
+    corrected_bytes: Arc<RwLock<(i64, Option<SystemTime>)>>,
+}
+
+impl StorageUsage {
+    /// Track a chunk whose size isn't known yet, e.g. found on startup
+    pub fn track(&self, chunk: &DataChunk) {
+        self.chunks.write().unwrap()
+            .entry(chunk.id)
+            .or_insert(ChunkUsage { dataset_id: chunk.dataset_id, bytes: None, measured_at: None });
+    }
+
+    pub fn record(&self, chunk: &DataChunk, bytes: u64) {
+        self.chunks.write().unwrap().insert(
+            chunk.id,
+            ChunkUsage { dataset_id: chunk.dataset_id, bytes: Some(bytes), measured_at: Some(Instant::now()) },
+        );
+    }
+
+    pub fn remove(&self, chunk_id: &ChunkId) {
+        self.chunks.write().unwrap().remove(chunk_id);
+    }
+
+    /// Chunks to be measured next, the ones of the datasets with a higher maintenance priority first
+    pub fn chunks_to_sample(&self, count: usize, priorities: &MaintenancePriorities) -> Vec<ChunkId> {
+        let chunks = self.chunks.read().unwrap();
+        let mut candidates: Vec<(&ChunkId, &ChunkUsage)> = chunks.iter().collect();
+        // never measured chunks sort first
+        candidates.sort_by_key(|(_, usage)| usage.measured_at);
+        priorities.sort_by_priority(&mut candidates, |(_, usage)| usage.dataset_id);
+        candidates.into_iter().take(count).map(|(id, _)| *id).collect()
+    }
+
+    /// Replace the recorded size of the chunk by the sampled one, chunks removed meanwhile stay removed
+    pub fn correct(&self, chunk_id: &ChunkId, bytes: u64) {
+        let mut chunks = self.chunks.write().unwrap();
+        let Some(usage) = chunks.get_mut(chunk_id) else { return };
+        let mut corrected_bytes = self.corrected_bytes.write().unwrap();
+        if let Some(recorded) = usage.bytes {
+            corrected_bytes.0 += bytes as i64 - recorded as i64;
+        }
+        corrected_bytes.1 = Some(SystemTime::now());
+        usage.bytes = Some(bytes);
+        usage.measured_at = Some(Instant::now());
+    }
+
+    /// Bytes of the measured chunks by chunk id
+    pub fn recorded_bytes(&self) -> HashMap<ChunkId, u64> {
+        self.chunks.read().unwrap().iter().filter_map(|(chunk_id, usage)| Some((*chunk_id, usage.bytes?))).collect()
+    }
+
+    pub fn stats(&self) -> StorageStats {
+        let chunks = self.chunks.read().unwrap();
+        let (corrected_bytes, last_sample) = *self.corrected_bytes.read().unwrap();
+        let mut stats = StorageStats { corrected_bytes, last_sample, ..StorageStats::default() };
+        for usage in chunks.values() {
+            match usage.bytes {
+                Some(bytes) => {
+                    stats.total_bytes += bytes;
+                    *stats.dataset_bytes.entry(usage.dataset_id).or_default() += bytes;
+                    stats.measured_chunks += 1;
+                }
+                None => stats.unmeasured_chunks += 1,
+            }
+        }
+        stats
+    }
+}
+
+/// Sum of the sizes of the files in the chunk directory
+pub fn chunk_size(chunk_dir: &Path) -> io::Result<u64> {
+    let mut bytes = 0;
+    for entry in fs::read_dir(chunk_dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        bytes += if metadata.is_dir() { chunk_size(&entry.path())? } else { metadata.len() };
+    }
+    Ok(bytes)
+}
+
+/// Background job measuring a few chunks at a time to correct the recorded sizes.
+/// The job stops when the sampler is dropped.
+pub struct StorageSampler {
+    _stop: mpsc::Sender<()>,
+}
+
+impl StorageSampler {
+    pub(crate) fn start(config: StorageSamplingConfig, storage: StorageUsage, workers: Workers) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(config.interval) {
+                sample(&storage, &workers, config.chunks_per_sample);
+            }
+        });
+        StorageSampler { _stop: stop }
+    }
+}
+
+/// Measure the next chunks, returns their ids
+pub(crate) fn sample(storage: &StorageUsage, workers: &Workers, count: usize) -> Vec<ChunkId> {
+    let mut sampled = Vec::new();
+    for chunk_id in storage.chunks_to_sample(count, &workers.maintenance_priorities) {
+        let chunk = {
+            let registry = workers.data_catalogue.registry.read().unwrap();
+            // chunks being processed are measured again once they are ready
+            match registry.get(&chunk_id) {
+                Some(info) if info.status == ChunkStatus::Ready => info.chunk.clone(),
+                _ => continue,
+            }
+        };
+        if let Ok(bytes) = chunk_size(&workers.data_source.chunk_path(chunk).path) {
+            storage.correct(&chunk_id, bytes);
+            sampled.push(chunk_id);
+        }
+    }
+    sampled
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use serial_test::serial;
+    use crate::data_catalogue::load_catalogue_with_local_chunks;
+    use crate::data_manager::DataManager;
+    use crate::local_data_source::{get_test_chunk_111111_0_35, LOCAL_DATA_DIR};
+    use crate::DataManagerImpl;
+    use super::*;
+
+    #[test]
+    fn test_recorded_sizes_are_corrected() {
+        // Arrange
+        let storage = StorageUsage::default();
+        let chunk = get_test_chunk_111111_0_35();
+        let unmeasured_chunk = DataChunk { id: [1u8; 32], dataset_id: [9u8; 32], ..chunk.clone() };
+        storage.record(&chunk, 100);
+        storage.track(&unmeasured_chunk);
+
+        // Act
+        let to_sample = storage.chunks_to_sample(1, &MaintenancePriorities::default());
+        let prioritized = storage.chunks_to_sample(1, &MaintenancePriorities::default().with_priority(chunk.dataset_id, 1));
+        storage.correct(&chunk.id, 90);
+        storage.correct(&[2u8; 32], 50);
+
+        // Assert
+        assert_eq!(to_sample, vec![unmeasured_chunk.id]);
+        assert_eq!(prioritized, vec![chunk.id]);
+        let stats = storage.stats();
+        assert_eq!((stats.total_bytes, stats.measured_chunks, stats.unmeasured_chunks), (90, 1, 1));
+        assert_eq!(stats.dataset_bytes.get(&chunk.dataset_id), Some(&90));
+        assert_eq!(stats.corrected_bytes, -10);
+        assert!(stats.last_sample.is_some());
+    }
+
+    #[test]
+    #[serial]
+    fn test_sampling_measures_local_chunks() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let chunk = get_test_chunk_111111_0_35();
+        let chunk_dir = data_manager.data_source.chunk_path(chunk.clone()).path;
+        assert_eq!(data_manager.storage_stats().unmeasured_chunks, 8);
+
+        // Act
+        let sampled = sample(&data_manager.storage, &data_manager.workers(), 8);
+
+        // Assert
+        assert_eq!(sampled.len(), 8);
+        let stats = data_manager.storage_stats();
+        assert_eq!(stats.unmeasured_chunks, 0);
+        assert_eq!(stats.corrected_bytes, 0);
+        assert!(stats.dataset_bytes.get(&chunk.dataset_id).unwrap() >= &chunk_size(&chunk_dir).unwrap());
+    }
+}
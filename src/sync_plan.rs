@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+use crate::planning::ChunkStatus;
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
+
+/// A chunk from the manifest, which replaces chunks held locally
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replacement {
+    /// The chunk to be downloaded
+    pub chunk: DataChunk,
+    /// Chunks, which are deleted once the new chunk is ready.
+    /// When the chunk only changes its files, this contains the chunk's own id.
+    pub replaced: Vec<ChunkId>,
+}
+
+/// Operations needed to make the local state of a dataset match its manifest
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyncPlan {
+    pub dataset_id: DatasetId,
+    /// Chunks of the manifest, which are not held locally
+    pub downloads: Vec<DataChunk>,
+    /// Chunks held locally, which are not in the manifest
+    pub deletions: Vec<ChunkId>,
+    /// Chunks of the manifest, which supersede chunks held locally
+    pub replacements: Vec<Replacement>,
+    /// Chunks of the manifest, which are still being deleted, so they can't be downloaded yet.
+    /// A later sync plans their downloads once the deletions complete.
+    pub pending_redownloads: Vec<DataChunk>,
+}
+
+impl SyncPlan {
+    pub fn is_empty(&self) -> bool {
+        self.downloads.is_empty() && self.deletions.is_empty() && self.replacements.is_empty() && self.pending_redownloads.is_empty()
+    }
+
+    /// Number of chunks, which are going to be downloaded when the plan is executed
+    pub fn download_count(&self) -> usize {
+        self.downloads.len() + self.replacements.len()
+    }
+}
+
+/// Plan the sync of a dataset against its `manifest`, given the chunks currently in the catalogue with their status.
+///
+/// Chunks which are `Ready` or `Downloading` count as held, chunks which are being deleted don't,
+/// and the manifest chunks among them are pending re-download. Manifest chunks of other datasets are ignored.
+pub fn plan_sync<'a>(
+    dataset_id: DatasetId,
+    manifest: &[DataChunk],
+    catalogue_chunks: impl IntoIterator<Item = (&'a DataChunk, &'a ChunkStatus)>,
+) -> SyncPlan {
+    let catalogue_chunks: Vec<(&DataChunk, &ChunkStatus)> = catalogue_chunks
+        .into_iter()
+        .filter(|(chunk, _)| chunk.dataset_id == dataset_id)
+        .collect();
+    let mut held: Vec<&DataChunk> = catalogue_chunks
+        .iter()
+        .filter(|(_, status)| matches!(status, ChunkStatus::Ready | ChunkStatus::Downloading))
+        .map(|(chunk, _)| *chunk)
+        .collect();
+    let deleting: HashSet<ChunkId> = catalogue_chunks
+        .iter()
+        .filter(|(_, status)| matches!(status, ChunkStatus::Deleting))
+        .map(|(chunk, _)| chunk.id)
+        .collect();
+    held.sort_by_key(|chunk| (chunk.block_range.start, chunk.block_range.end));
+
+    let mut manifest: Vec<&DataChunk> = manifest
+        .iter()
+        .filter(|chunk| chunk.dataset_id == dataset_id)
+        .collect();
+    manifest.sort_by_key(|chunk| (chunk.block_range.start, chunk.block_range.end));
+    let manifest_ids: HashSet<ChunkId> = manifest.iter().map(|chunk| chunk.id).collect();
+
+    let mut plan = SyncPlan {
+        dataset_id,
+        downloads: Vec::new(),
+        deletions: Vec::new(),
+        replacements: Vec::new(),
+        pending_redownloads: Vec::new(),
+    };
+    let mut replaced_ids: HashSet<ChunkId> = HashSet::new();
+
+    for chunk in manifest {
+        // the download would be refused until the chunk is `Deleted`
+        if deleting.contains(&chunk.id) {
+            plan.pending_redownloads.push(chunk.clone());
+            continue;
+        }
+        if let Some(held_chunk) = held.iter().find(|held_chunk| held_chunk.id == chunk.id) {
+            if !same_file_names(held_chunk, chunk) {
+                plan.replacements.push(Replacement { chunk: chunk.clone(), replaced: vec![chunk.id] });
+            }
+            continue;
+        }
+
+        // local chunks, which dropped out of the manifest and overlap the new chunk, are replaced by it
+        let replaced: Vec<ChunkId> = held
+            .iter()
+            .filter(|held_chunk| !manifest_ids.contains(&held_chunk.id) && !replaced_ids.contains(&held_chunk.id))
+            .filter(|held_chunk| overlaps(held_chunk, chunk))
+            .map(|held_chunk| held_chunk.id)
+            .collect();
+        if replaced.is_empty() {
+            plan.downloads.push(chunk.clone());
+        } else {
+            replaced_ids.extend(replaced.iter().copied());
+            plan.replacements.push(Replacement { chunk: chunk.clone(), replaced });
+        }
+    }
+
+    plan.deletions = held
+        .iter()
+        .filter(|held_chunk| !manifest_ids.contains(&held_chunk.id) && !replaced_ids.contains(&held_chunk.id))
+        .map(|held_chunk| held_chunk.id)
+        .collect();
+    plan
+}
+
+fn overlaps(a: &DataChunk, b: &DataChunk) -> bool {
+    a.block_range.start < b.block_range.end && b.block_range.start < a.block_range.end
+}
+
+fn same_file_names(a: &DataChunk, b: &DataChunk) -> bool {
+    a.files.len() == b.files.len() && a.files.keys().all(|file_name| b.files.contains_key(file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+    use super::*;
+
+    fn chunk(block_range: Range<u64>, files: &[&str]) -> DataChunk {
+        DataChunk::new([1u8; 32], block_range).with_example_files(files)
+    }
+
+    fn info(chunk: &DataChunk, status: ChunkStatus) -> (DataChunk, ChunkStatus) {
+        (chunk.clone(), status)
+    }
+
+    fn catalogue_chunks(chunks: &[(DataChunk, ChunkStatus)]) -> impl Iterator<Item = (&DataChunk, &ChunkStatus)> {
+        chunks.iter().map(|(chunk, status)| (chunk, status))
+    }
+
+    #[test]
+    fn test_plan_is_empty_when_in_sync() {
+        let held = vec![info(&chunk(0..10, &["a"]), ChunkStatus::Ready)];
+        let plan = plan_sync([1u8; 32], &[chunk(0..10, &["a"])], catalogue_chunks(&held));
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn test_plan_downloads_and_deletions() {
+        // Arrange
+        let held = vec![
+            info(&chunk(0..10, &["a"]), ChunkStatus::Ready),
+            info(&chunk(10..20, &["a"]), ChunkStatus::Ready),
+            info(&chunk(20..30, &["a"]), ChunkStatus::Deleting),
+        ];
+        let manifest = vec![chunk(0..10, &["a"]), chunk(20..30, &["a"]), chunk(30..40, &["a"])];
+
+        // Act
+        let plan = plan_sync([1u8; 32], &manifest, catalogue_chunks(&held));
+
+        // Assert
+        assert_eq!(plan.downloads, vec![chunk(30..40, &["a"])]);
+        assert_eq!(plan.deletions, vec![chunk(10..20, &["a"]).id]);
+        assert!(plan.replacements.is_empty());
+        assert_eq!(plan.pending_redownloads, vec![chunk(20..30, &["a"])]);
+        assert_eq!(plan.download_count(), 1);
+    }
+
+    #[test]
+    fn test_plan_replacements() {
+        // Arrange
+        let held = vec![
+            info(&chunk(0..10, &["a"]), ChunkStatus::Ready),
+            info(&chunk(10..20, &["a"]), ChunkStatus::Ready),
+            info(&chunk(20..30, &["a"]), ChunkStatus::Ready),
+        ];
+        let manifest = vec![chunk(0..20, &["a"]), chunk(20..30, &["a", "b"])];
+
+        // Act
+        let plan = plan_sync([1u8; 32], &manifest, catalogue_chunks(&held));
+
+        // Assert
+        assert!(plan.downloads.is_empty());
+        assert!(plan.deletions.is_empty());
+        assert_eq!(plan.replacements, vec![
+            Replacement { chunk: chunk(0..20, &["a"]), replaced: vec![chunk(0..10, &["a"]).id, chunk(10..20, &["a"]).id] },
+            Replacement { chunk: chunk(20..30, &["a", "b"]), replaced: vec![chunk(20..30, &["a"]).id] },
+        ]);
+    }
+
+    #[test]
+    fn test_plan_ignores_other_datasets() {
+        let mut other = chunk(0..10, &["a"]);
+        other.dataset_id = [2u8; 32];
+        let held = vec![info(&other, ChunkStatus::Ready)];
+        let plan = plan_sync([1u8; 32], &[other.clone()], catalogue_chunks(&held));
+        assert!(plan.is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+//! Fixtures shared by the tests of the modules
+
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::builder::DataManagerBuilder;
+use crate::config::DataManagerConfig;
+use crate::data_chunk::DataChunk;
+use crate::transfer::ChunkTransfer;
+use crate::DataManagerImpl;
+
+/// Transfer writing a `blocks.parquet` placeholder into the chunk directory instead of downloading
+pub(crate) struct PlaceholderTransfer;
+
+impl ChunkTransfer for PlaceholderTransfer {
+    fn download(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(chunk_dir)?;
+        fs::write(chunk_dir.join("blocks.parquet"), b"blocks")
+    }
+
+    fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+        fs::remove_dir_all(chunk_dir)
+    }
+}
+
+/// Directory of a test in the temp directory, suffixed by the process id so parallel runs don't share it
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("data_manager_{}_{}", name, std::process::id()))
+}
+
+/// Chunk of the dataset `[dataset; 32]` holding a single `blocks.parquet`
+pub(crate) fn chunk(dataset: u8, block_range: Range<u64>) -> DataChunk {
+    DataChunk::new([dataset; 32], block_range).with_example_files(&["blocks.parquet"])
+}
+
+/// Builder of a manager keeping its data and catalogue in `dir` and downloading with `PlaceholderTransfer`
+pub(crate) fn manager_builder(dir: &Path) -> DataManagerBuilder {
+    DataManagerImpl::builder()
+        .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+        .chunk_transfer(Arc::new(PlaceholderTransfer))
+}
+
+/// Polls `condition` until it holds, failing the test with `what` after 5 seconds
+pub(crate) fn wait_until(what: &str, mut condition: impl FnMut() -> bool) {
+    let started = Instant::now();
+    while !condition() {
+        assert!(started.elapsed() < Duration::from_secs(5), "{}", what);
+        thread::yield_now();
+    }
+}
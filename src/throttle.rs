@@ -0,0 +1,117 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Bandwidth shared by all the downloads
+#[derive(Clone, Debug, PartialEq)]
+pub struct BandwidthLimit {
+    /// Average rate of all the downloads together
+    pub bytes_per_second: u64,
+    /// Bytes which may be transferred at once after an idle period, at least the size of a single write
+    pub burst_bytes: u64,
+}
+
+impl BandwidthLimit {
+    /// Limit with a burst of one second of the rate
+    pub fn new(bytes_per_second: u64) -> Self {
+        BandwidthLimit { bytes_per_second, burst_bytes: bytes_per_second }
+    }
+}
+
+struct Bucket {
+    /// Negative when the bytes transferred ran ahead of the rate, the next transfers wait until it's paid back
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Token bucket keeping all the concurrent downloads within the `BandwidthLimit`, cheap to clone
+#[derive(Clone)]
+pub struct BandwidthThrottle {
+    limit: BandwidthLimit,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl BandwidthThrottle {
+    pub fn new(limit: BandwidthLimit) -> Self {
+        let bucket = Bucket { tokens: limit.burst_bytes as f64, refilled_at: Instant::now() };
+        BandwidthThrottle { limit, bucket: Arc::new(Mutex::new(bucket)) }
+    }
+
+    pub fn limit(&self) -> &BandwidthLimit {
+        &self.limit
+    }
+
+    /// Take the bytes from the bucket, blocking the current thread until the rate allows them.
+    /// The bytes are reserved right away, so the waiting transfers are served in the order they asked.
+    pub fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let rate = self.limit.bytes_per_second as f64;
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * rate;
+            bucket.tokens = (bucket.tokens + refill).min(self.limit.burst_bytes as f64) - bytes as f64;
+            bucket.refilled_at = now;
+            (bucket.tokens < 0.0).then(|| Duration::from_secs_f64(-bucket.tokens / rate))
+        };
+        if let Some(wait) = wait {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Writer taking every write from the bucket before it's passed on
+    pub fn writer<'a>(&self, inner: &'a mut dyn Write) -> ThrottledWriter<'a> {
+        ThrottledWriter { throttle: self.clone(), inner }
+    }
+}
+
+/// Writer keeping a streamed download within the bandwidth, see `BandwidthThrottle::writer`
+pub struct ThrottledWriter<'a> {
+    throttle: BandwidthThrottle,
+    inner: &'a mut dyn Write,
+}
+
+impl Write for ThrottledWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // big writes are split, so a single write never waits for more than the burst
+        let len = buf.len().min(self.throttle.limit.burst_bytes.max(1) as usize);
+        self.throttle.acquire(len as u64);
+        self.inner.write(&buf[..len])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrent_writes_share_the_rate() {
+        // Arrange
+        let throttle = BandwidthThrottle::new(BandwidthLimit { bytes_per_second: 10_000, burst_bytes: 1_000 });
+        let started_at = Instant::now();
+
+        // Act
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                let throttle = throttle.clone();
+                thread::spawn(move || {
+                    let mut written = Vec::new();
+                    throttle.writer(&mut written).write_all(&[0u8; 1_000]).unwrap();
+                    written.len()
+                })
+            })
+            .collect();
+        let written: usize = writers.into_iter().map(|writer| writer.join().unwrap()).sum();
+
+        // Assert
+        assert_eq!(written, 4_000);
+        // the first 1000 bytes are the burst, the other 3000 take 300ms at the rate
+        let elapsed = started_at.elapsed();
+        assert!(elapsed >= Duration::from_millis(290), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(600), "{:?}", elapsed);
+    }
+}
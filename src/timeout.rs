@@ -0,0 +1,204 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Limits of the chunk downloads, so a stalled connection doesn't hold a download worker forever,
+/// see `ResumableTransfer::with_timeouts`. A timed out download fails with `io::ErrorKind::TimedOut`,
+/// it's continued from the next mirror and retried by the `RetryPolicy` like any other failed download.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DownloadTimeouts {
+    /// Longest wait for the connection to the remote storage, passed in the `FileRequest`
+    pub connect: Option<Duration>,
+    /// Longest wait for the next bytes of a response, passed in the `FileRequest` and checked between the writes
+    pub read: Option<Duration>,
+    /// Longest download of all the files of a chunk, including the failovers to the mirrors
+    pub total: Option<Duration>,
+}
+
+impl DownloadTimeouts {
+    pub fn with_connect(mut self, connect: Duration) -> Self {
+        self.connect = Some(connect);
+        self
+    }
+
+    pub fn with_read(mut self, read: Duration) -> Self {
+        self.read = Some(read);
+        self
+    }
+
+    pub fn with_total(mut self, total: Duration) -> Self {
+        self.total = Some(total);
+        self
+    }
+
+    /// Read timeout of a request sent before the deadline of the download, never past the deadline
+    pub(crate) fn read_timeout(&self, deadline: Option<Instant>) -> Option<Duration> {
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        match (self.read, remaining) {
+            (Some(read), Some(remaining)) => Some(read.min(remaining)),
+            (read, remaining) => read.or(remaining),
+        }
+    }
+}
+
+pub(crate) fn timed_out(what: &str, after: Duration) -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, format!("{} timed out after {:?}", what, after))
+}
+
+/// Fails the download once the deadline passed, before the request of the next file
+pub(crate) fn check_deadline(timeouts: &DownloadTimeouts, deadline: Option<Instant>) -> io::Result<()> {
+    match (timeouts.total, deadline) {
+        (Some(total), Some(deadline)) if Instant::now() >= deadline => Err(timed_out("download", total)),
+        _ => Ok(()),
+    }
+}
+
+/// Writer failing the write which comes too late, after the deadline of the download or after a longer pause than the read timeout,
+/// so a source which doesn't apply the timeouts itself still can't trickle the bytes in forever
+pub struct TimeoutWriter<'a> {
+    inner: &'a mut dyn Write,
+    timeouts: DownloadTimeouts,
+    deadline: Option<Instant>,
+    last_write: Instant,
+}
+
+impl<'a> TimeoutWriter<'a> {
+    pub fn new(inner: &'a mut dyn Write, timeouts: DownloadTimeouts, deadline: Option<Instant>) -> Self {
+        TimeoutWriter { inner, timeouts, deadline, last_write: Instant::now() }
+    }
+}
+
+impl Write for TimeoutWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        check_deadline(&self.timeouts, self.deadline)?;
+        if let Some(read) = self.timeouts.read.filter(|read| self.last_write.elapsed() > *read) {
+            return Err(timed_out("read", read));
+        }
+        let written = self.inner.write(buf)?;
+        self.last_write = Instant::now();
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use crate::auth::FileRequest;
+    use crate::config::DataManagerConfig;
+    use crate::data_catalogue::ChunkStatus;
+    use crate::data_manager::DataManager;
+    use crate::retry::RetryPolicy;
+    use crate::transfer::{ChunkTransfer, RangeSource, ResumableTransfer};
+    use crate::DataManagerImpl;
+    use crate::test_support::{self, chunk};
+    use super::*;
+
+    /// Remote file served from memory, which pauses for `stall` after every `step` bytes of the first `stalls` requests
+    struct StallingSource {
+        content: Vec<u8>,
+        step: usize,
+        stall: Duration,
+        stalls: Mutex<usize>,
+        requests: Mutex<Vec<(u64, FileRequest)>>,
+    }
+
+    impl RangeSource for StallingSource {
+        fn content_length(&self, _url: &str) -> io::Result<u64> {
+            Ok(self.content.len() as u64)
+        }
+
+        fn read_range(&self, url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            self.request_range(&FileRequest::new(url), offset, writer)
+        }
+
+        fn request_content_length(&self, _request: &FileRequest) -> io::Result<u64> {
+            Ok(self.content.len() as u64)
+        }
+
+        fn request_range(&self, request: &FileRequest, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            self.requests.lock().unwrap().push((offset, request.clone()));
+            let stalls = {
+                let mut stalls = self.stalls.lock().unwrap();
+                let stalling = *stalls > 0;
+                *stalls = stalls.saturating_sub(1);
+                stalling
+            };
+            for bytes in self.content[offset as usize..].chunks(self.step) {
+                writer.write_all(bytes)?;
+                if stalls {
+                    thread::sleep(self.stall);
+                }
+            }
+            Ok(self.content.len() as u64 - offset)
+        }
+    }
+
+    fn source(step: usize, stall: Duration, stalls: usize) -> Arc<StallingSource> {
+        Arc::new(StallingSource { content: (0..=255).collect(), step, stall, stalls: Mutex::new(stalls), requests: Mutex::new(Vec::new()) })
+    }
+
+    #[test]
+    fn test_requests_carry_the_timeouts_and_the_total_is_enforced() {
+        // Arrange
+        let dir = test_support::temp_dir("total_timeout");
+        let source = source(64, Duration::from_millis(40), 1);
+        let timeouts = DownloadTimeouts::default()
+            .with_connect(Duration::from_secs(1))
+            .with_read(Duration::from_secs(5))
+            .with_total(Duration::from_millis(100));
+        let transfer = ResumableTransfer::new(source.clone()).with_timeouts(timeouts);
+
+        // Act
+        let timed_out = transfer.download(&chunk(12, 0..10), &dir);
+        let resumed = transfer.download(&chunk(12, 0..10), &dir);
+
+        // Assert
+        let error = timed_out.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(error.to_string(), "download timed out after 100ms");
+        assert!(resumed.is_ok());
+        assert_eq!(fs::read(dir.join("blocks.parquet")).unwrap(), (0..=255).collect::<Vec<u8>>());
+        let requests = source.requests.lock().unwrap();
+        assert_eq!(requests[0].0, 0);
+        assert!(requests[1].0 > 0);
+        assert!(requests.iter().all(|(_, request)| request.connect_timeout == Some(Duration::from_secs(1))));
+        // the read timeout never outlasts the download
+        assert!(requests.iter().all(|(_, request)| request.read_timeout.is_some_and(|read_timeout| read_timeout <= Duration::from_millis(100))));
+
+        // cleanup
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_stalled_download_is_retried() {
+        // Arrange
+        let dir = test_support::temp_dir("read_timeout");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let source = source(100, Duration::from_millis(150), 1);
+        let transfer = ResumableTransfer::new(source.clone()).with_timeouts(DownloadTimeouts::default().with_read(Duration::from_millis(50)));
+        let retry = RetryPolicy { max_attempts: 2, initial_backoff: Duration::from_millis(10), jitter: 0.0, ..RetryPolicy::default() };
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")).with_download_retry(retry))
+            .chunk_transfer(Arc::new(transfer))
+            .build();
+
+        // Act
+        data_manager.download_chunk(chunk(12, 0..10));
+        data_manager.data_catalogue.wait_until_downloaded(&[chunk(12, 0..10).id]);
+
+        // Assert
+        assert_eq!(data_manager.get_chunk_info(chunk(12, 0..10).id).map(|info| info.status), Some(ChunkStatus::Ready));
+        let offsets: Vec<u64> = source.requests.lock().unwrap().iter().map(|(offset, _)| *offset).collect();
+        // the bytes received before the stall are kept
+        assert_eq!(offsets, vec![0, 100]);
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::block_time::BlockTime;
+use crate::data_catalogue::ChunkStatus;
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
+use crate::fair_queue::DownloadPriority;
+use crate::hooks::LifecycleEvent;
+use crate::workers::Workers;
+
+/// How often the follower checks whether any dataset is due, when no dataset is followed
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Published chunks of the datasets, e.g. the manifest a scheduler plans with
+pub trait ManifestSource: Send + Sync {
+    /// Chunks of the dataset starting at or after `from_block`
+    fn published_chunks(&self, dataset_id: &DatasetId, from_block: u64) -> io::Result<Vec<DataChunk>>;
+
+    /// Timestamps of the blocks starting at or after `from_block`, for manifests which carry them
+    fn block_times(&self, _dataset_id: &DatasetId, _from_block: u64) -> io::Result<Vec<BlockTime>> {
+        Ok(Vec::new())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TipFollowingConfig {
+    /// How often the manifest is checked for new chunks of the dataset
+    pub poll_interval: Duration,
+}
+
+impl Default for TipFollowingConfig {
+    fn default() -> Self {
+        TipFollowingConfig {
+            poll_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Background job downloading the chunks published past the local tip of the followed datasets with high priority,
+/// so the datasets stay current without a scheduler. The job stops when the follower is dropped.
+pub struct TipFollower {
+    datasets: Arc<RwLock<HashMap<DatasetId, TipFollowingConfig>>>,
+    _stop: mpsc::Sender<()>,
+}
+
+impl TipFollower {
+    pub(crate) fn start(datasets: HashMap<DatasetId, TipFollowingConfig>, manifest: Arc<dyn ManifestSource>, workers: Workers) -> Self {
+        let datasets = Arc::new(RwLock::new(datasets));
+        let (stop, stopped) = mpsc::channel::<()>();
+        let followed = datasets.clone();
+        thread::spawn(move || {
+            let mut last_polls: HashMap<DatasetId, Instant> = HashMap::new();
+            loop {
+                let interval = followed.read().unwrap().values().map(|config| config.poll_interval).min().unwrap_or(IDLE_INTERVAL);
+                if !matches!(stopped.recv_timeout(interval), Err(RecvTimeoutError::Timeout)) {
+                    break;
+                }
+                let due: Vec<DatasetId> = followed.read().unwrap().iter()
+                    .filter(|(dataset_id, config)| last_polls.get(*dataset_id).is_none_or(|polled_at| polled_at.elapsed() >= config.poll_interval))
+                    .map(|(dataset_id, _)| *dataset_id)
+                    .collect();
+                for dataset_id in due {
+                    last_polls.insert(dataset_id, Instant::now());
+                    // a manifest which isn't available is polled again on the next interval
+                    let _ = poll_tip(&workers, manifest.as_ref(), &dataset_id);
+                }
+            }
+        });
+        TipFollower { datasets, _stop: stop }
+    }
+
+    pub fn follow(&self, dataset_id: DatasetId, config: TipFollowingConfig) {
+        self.datasets.write().unwrap().insert(dataset_id, config);
+    }
+
+    /// Returns `false` when the dataset wasn't followed
+    pub fn unfollow(&self, dataset_id: &DatasetId) -> bool {
+        self.datasets.write().unwrap().remove(dataset_id).is_some()
+    }
+
+    pub fn followed_datasets(&self) -> Vec<DatasetId> {
+        self.datasets.read().unwrap().keys().copied().collect()
+    }
+}
+
+/// End of the last block of the dataset held or being downloaded locally
+pub(crate) fn local_tip(workers: &Workers, dataset_id: &DatasetId) -> u64 {
+    workers.data_catalogue.registry.read().unwrap()
+        .values()
+        .filter(|info| info.chunk.dataset_id == *dataset_id)
+        .filter(|info| matches!(info.status, ChunkStatus::Ready | ChunkStatus::Downloading))
+        .map(|info| info.chunk.block_range.end)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Start downloading the chunks published past the local tip, returns the chunks which started.
+/// The block times the manifest carries are added to the block time index.
+pub(crate) fn poll_tip(workers: &Workers, manifest: &dyn ManifestSource, dataset_id: &DatasetId) -> io::Result<Vec<ChunkId>> {
+    let tip = local_tip(workers, dataset_id);
+    workers.block_times.record(*dataset_id, &manifest.block_times(dataset_id, tip)?)?;
+    let mut started = Vec::new();
+    for chunk in manifest.published_chunks(dataset_id, tip)? {
+        if chunk.dataset_id != *dataset_id || chunk.block_range.start < tip || !workers.data_catalogue.start_download(&chunk) {
+            continue;
+        }
+        started.push(chunk.id);
+        workers.hooks.emit(LifecycleEvent::DownloadStart(chunk.clone()));
+        workers.spawn_prioritized_download(chunk, DownloadPriority::High);
+    }
+    Ok(started)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use serial_test::serial;
+    use crate::data_catalogue::load_catalogue_with_local_chunks;
+    use crate::data_manager::DataManager;
+    use crate::local_data_source::{get_test_chunk_111111_107_135, get_test_chunk_111111_95_106, LOCAL_DATA_DIR};
+    use crate::DataManagerImpl;
+    use super::*;
+
+    struct StaticManifest(Vec<DataChunk>);
+
+    impl ManifestSource for StaticManifest {
+        fn published_chunks(&self, dataset_id: &DatasetId, from_block: u64) -> io::Result<Vec<DataChunk>> {
+            Ok(self.0.iter()
+                .filter(|chunk| chunk.dataset_id == *dataset_id && chunk.block_range.start >= from_block)
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_chunks_past_the_tip_are_downloaded() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let chunk = get_test_chunk_111111_95_106();
+        let older_chunk = DataChunk { id: [9u8; 32], block_range: 50..60, ..chunk.clone() };
+        let manifest = StaticManifest(vec![older_chunk, chunk.clone()]);
+
+        // Act
+        let started = poll_tip(&data_manager.workers(), &manifest, &chunk.dataset_id).unwrap();
+        let started_again = poll_tip(&data_manager.workers(), &manifest, &chunk.dataset_id).unwrap();
+
+        // Assert
+        assert_eq!(started, vec![chunk.id]);
+        assert!(started_again.is_empty());
+        assert_eq!(local_tip(&data_manager.workers(), &chunk.dataset_id), 106);
+
+        // cleanup
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(200));
+        });
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(200));
+        });
+    }
+
+    #[test]
+    #[serial]
+    fn test_followed_dataset_is_polled_in_background() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let chunk = get_test_chunk_111111_95_106();
+        let next_chunk = get_test_chunk_111111_107_135();
+        let data_manager = DataManagerImpl::builder()
+            .data_dir(PathBuf::from(LOCAL_DATA_DIR))
+            .manifest_source(Arc::new(StaticManifest(vec![chunk.clone(), next_chunk.clone()])))
+            .build();
+
+        // Act
+        data_manager.follow_tip(chunk.dataset_id, TipFollowingConfig { poll_interval: Duration::from_millis(50) });
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(400));
+        });
+        let unfollowed = data_manager.unfollow_tip(&chunk.dataset_id);
+
+        // Assert
+        assert!(unfollowed);
+        let ready_chunk_ids = data_manager.list_chunks();
+        assert!(ready_chunk_ids.contains(&chunk.id));
+        assert!(ready_chunk_ids.contains(&next_chunk.id));
+
+        // cleanup
+        data_manager.forget_chunk(next_chunk.id).unwrap();
+        data_manager.delete_chunk(chunk.id);
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(200));
+        });
+    }
+}
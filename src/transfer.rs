@@ -0,0 +1,983 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::auth::{self, AuthProvider, FileRequest};
+use crate::cancellation::{self, CancellableWriter};
+use crate::compression::{ContentEncoding, DecodingWriter};
+use crate::data_chunk::{DataChunk, DatasetId};
+use crate::progress::{FileProgress, ProgressWriter};
+use crate::proxy::ProxyConfig;
+use crate::tls::TlsConfig;
+use crate::storage;
+use crate::throttle::BandwidthThrottle;
+use crate::timeout::{self, DownloadTimeouts, TimeoutWriter};
+
+/// Suffix of the files being downloaded, they get their final names once they are complete
+pub const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Moves the files of chunks between the remote storage and the data directory.
+/// The data source writes through it, so tests can swap the remote storage for one they control.
+pub trait ChunkTransfer: Send + Sync {
+    /// Write the files of the chunk into the directory, creating it when it doesn't exist
+    fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()>;
+
+    /// Same as `download`, keeping the downloads together within the bandwidth of the throttle.
+    /// By default the bytes in the directory are taken from the throttle once the download completes,
+    /// so the following downloads wait. Transfers streaming the files should take every write instead.
+    fn throttled_download(&self, chunk: &DataChunk, chunk_dir: &Path, throttle: &BandwidthThrottle) -> io::Result<()> {
+        self.download(chunk, chunk_dir)?;
+        throttle.acquire(storage::chunk_size(chunk_dir)?);
+        Ok(())
+    }
+
+    /// Same as `download`, or `throttled_download` with a throttle, giving up once `cancelled` returns `true`.
+    /// By default it's checked only before the transfer starts, transfers streaming the files should check it
+    /// on every write, e.g. by writing through a `CancellableWriter`.
+    fn cancellable_download(
+        &self,
+        chunk: &DataChunk,
+        chunk_dir: &Path,
+        throttle: Option<&BandwidthThrottle>,
+        cancelled: &dyn Fn() -> bool,
+    ) -> io::Result<()> {
+        if cancelled() {
+            return Err(cancellation::cancelled());
+        }
+        match throttle {
+            Some(throttle) => self.throttled_download(chunk, chunk_dir, throttle),
+            None => self.download(chunk, chunk_dir),
+        }
+    }
+
+    /// Same as `cancellable_download`, reporting the progress of the files by their names as they're received.
+    /// By default every file is reported once the download completes, transfers streaming the files should report every write,
+    /// e.g. by writing through a `ProgressWriter`.
+    fn tracked_download(
+        &self,
+        chunk: &DataChunk,
+        chunk_dir: &Path,
+        throttle: Option<&BandwidthThrottle>,
+        cancelled: &dyn Fn() -> bool,
+        progress: &dyn Fn(&str, FileProgress),
+    ) -> io::Result<()> {
+        self.cancellable_download(chunk, chunk_dir, throttle, cancelled)?;
+        for file_name in chunk.files.keys() {
+            if let Ok(metadata) = fs::metadata(chunk_dir.join(file_name)) {
+                progress(file_name, FileProgress { downloaded_bytes: metadata.len(), total_bytes: Some(metadata.len()) });
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the directory of the chunk with its files
+    fn delete(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()>;
+}
+
+/// Refuse the chunk before anything is transferred when one of its file names would be written outside the chunk directory
+pub fn check_file_names(chunk: &DataChunk) -> io::Result<()> {
+    match chunk.invalid_file_name() {
+        Some(file_name) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("chunk file name {:?} isn't a plain file name", file_name))),
+        None => Ok(()),
+    }
+}
+
+/// Simulated remote storage, copying the chunks of `./remote_data_dir` and taking 100ms per operation
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SimulatedTransfer;
+
+impl ChunkTransfer for SimulatedTransfer {
+    fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+        thread::sleep(Duration::from_millis(20));
+        if chunk.dataset_id == [17u8; 32] && chunk.block_range.start == 95 && chunk.block_range.end == 106 {
+            if let Some(dataset_dir) = chunk_dir.parent() {
+                fs::create_dir_all(dataset_dir)?;
+            }
+            copy_dir_all(
+                Path::new("./remote_data_dir/dataset_id=1111111111111111111111111111111111111111111111111111111111111111/block_range=95_106"),
+                chunk_dir
+            )?;
+        };
+        thread::sleep(Duration::from_millis(80));
+        Ok(())
+    }
+
+    fn delete(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+        thread::sleep(Duration::from_millis(20));
+        if chunk.id.eq(&[170, 13, 118, 225, 28, 2, 234, 149, 141, 239, 145, 9, 120, 116, 116, 137, 16, 29, 106, 129, 18, 70, 73, 152, 183, 85, 25, 49, 33, 116, 247, 65]) {
+            fs::remove_dir_all(chunk_dir)?;
+        };
+        thread::sleep(Duration::from_millis(80));
+        Ok(())
+    }
+}
+
+/// Remote storage serving byte ranges of the chunk files, e.g. an HTTP client sending `Range: bytes=<offset>-` requests
+pub trait RangeSource: Send + Sync {
+    /// Length of the file at the url, e.g. the `Content-Length` of a `HEAD` request
+    fn content_length(&self, url: &str) -> io::Result<u64>;
+
+    /// Write the bytes of the file at the url from the offset to its end, returns the number of bytes written.
+    /// The bytes written before a failure are kept, so the next download continues after them.
+    fn read_range(&self, url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64>;
+
+    /// Same as `content_length`, sending the headers of the request, see `AuthProvider`, through its proxy, see `ProxyConfig`,
+    /// over a connection with its certificates, see `TlsConfig`.
+    /// By default only plain requests are sent, e.g. of signed urls, sources which can send headers, use a proxy or certificates should override it.
+    fn request_content_length(&self, request: &FileRequest) -> io::Result<u64> {
+        plain_request(request)?;
+        self.content_length(&request.url)
+    }
+
+    /// Encoding the file is sent with for the request, e.g. the `Content-Encoding` of the response to the `HEAD` request,
+    /// which is one of its `accept_encoding` or a file the server keeps compressed. `content_length` and `request_range`
+    /// are then of the encoded file. By default the files are sent as they are.
+    fn request_content_encoding(&self, _request: &FileRequest) -> io::Result<ContentEncoding> {
+        Ok(ContentEncoding::Identity)
+    }
+
+    /// Same as `read_range`, sending the headers of the request through its proxy, see `request_content_length`
+    fn request_range(&self, request: &FileRequest, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+        plain_request(request)?;
+        self.read_range(&request.url, offset, writer)
+    }
+}
+
+/// Fails the requests with headers, a proxy or certificates, rather than sending them without their authentication, around the proxy or to an untrusted endpoint
+fn plain_request(request: &FileRequest) -> io::Result<()> {
+    if !request.headers.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, format!("the source can't send the headers of the request for {}", request.url)));
+    }
+    if let Some(proxy) = &request.proxy {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, format!("the source can't send the request for {} through the proxy {}", request.url, proxy)));
+    }
+    if request.tls.is_some() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, format!("the source can't use the certificates of the request for {}", request.url)));
+    }
+    Ok(())
+}
+
+/// Order in which the URLs of a file with mirrors are tried, see `DataChunk::mirrors`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MirrorOrder {
+    /// The URL in `files` first, then the mirrors in their order
+    #[default]
+    InOrder,
+    /// Every file starts at the next URL, so the downloads are spread over the mirrors
+    RoundRobin,
+}
+
+/// Returns a fresh URL for the file of the chunk, given its name and the URL about to be fetched,
+/// e.g. a pre-signed URL signed again, since the URLs of the chunks waiting in a long queue expire before their downloads start
+pub type UrlRefresher = Arc<dyn Fn(&DataChunk, &str, &str) -> io::Result<String> + Send + Sync>;
+
+/// Download of a file of a chunk in progress
+struct ChunkDownload<'a> {
+    chunk: &'a DataChunk,
+    file_name: &'a str,
+    /// The download times out by then, see `DownloadTimeouts::total`
+    deadline: Option<Instant>,
+}
+
+/// Downloads the files of the chunks with range requests, resuming the files a previous download didn't finish,
+/// e.g. after a network drop or a restart of the process.
+/// A file is written with the `.partial` suffix and renamed to its name once its length matches the remote file.
+/// When a URL of a file fails, its download continues from the next mirror.
+/// Files sent compressed, see `RangeSource::request_content_encoding`, are decoded as they are received,
+/// they are downloaded again from their start rather than resumed.
+#[derive(Clone)]
+pub struct ResumableTransfer {
+    source: Arc<dyn RangeSource>,
+    mirror_order: MirrorOrder,
+    /// Authentication of the requests for the files of the datasets, the other datasets are requested without it
+    auth: HashMap<DatasetId, AuthProvider>,
+    proxy: Option<ProxyConfig>,
+    tls: Option<Arc<TlsConfig>>,
+    timeouts: DownloadTimeouts,
+    url_refresher: Option<UrlRefresher>,
+    /// Datasets whose files are requested compressed
+    compressed_datasets: HashSet<DatasetId>,
+    /// URL the next file starts at with `MirrorOrder::RoundRobin`
+    next_mirror: Arc<AtomicUsize>,
+}
+
+impl ResumableTransfer {
+    pub fn new(source: Arc<dyn RangeSource>) -> Self {
+        ResumableTransfer {
+            source,
+            mirror_order: MirrorOrder::default(),
+            auth: HashMap::new(),
+            proxy: None,
+            tls: None,
+            timeouts: DownloadTimeouts::default(),
+            url_refresher: None,
+            compressed_datasets: HashSet::new(),
+            next_mirror: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn with_mirror_order(mut self, mirror_order: MirrorOrder) -> Self {
+        self.mirror_order = mirror_order;
+        self
+    }
+
+    /// Authenticate every request for the files of the dataset, including the mirrors
+    pub fn with_auth(mut self, dataset_id: DatasetId, auth: AuthProvider) -> Self {
+        self.auth.insert(dataset_id, auth);
+        self
+    }
+
+    /// Send the requests for the files through the proxies, e.g. `ProxyConfig::from_env()`
+    pub fn with_proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Connect to the `https://` urls of the files with the certificates, e.g. of internal endpoints with a private PKI
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(Arc::new(tls));
+        self
+    }
+
+    /// Give up on the connections and the downloads which take too long, see `DownloadTimeouts`
+    pub fn with_timeouts(mut self, timeouts: DownloadTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Ask the refresher for a fresh URL right before every URL of a file is fetched, and once more when a request for it
+    /// is refused, e.g. with `403 Forbidden` for an expired signature, reported by the source as `PermissionDenied`
+    pub fn with_url_refresher(mut self, url_refresher: UrlRefresher) -> Self {
+        self.url_refresher = Some(url_refresher);
+        self
+    }
+
+    /// Request the files of the dataset zstd or gzip compressed, to cut the egress of highly compressible datasets.
+    /// They are decoded as they are received, and resumed from their start.
+    #[cfg(feature = "compression")]
+    pub fn with_compressed_transfer(mut self, dataset_id: DatasetId) -> Self {
+        self.compressed_datasets.insert(dataset_id);
+        self
+    }
+
+    /// The URL from the refresher, or the URL itself without one
+    fn fresh_url(&self, download: &ChunkDownload, url: &str) -> io::Result<String> {
+        match &self.url_refresher {
+            Some(url_refresher) => url_refresher(download.chunk, download.file_name, url),
+            None => Ok(url.to_string()),
+        }
+    }
+
+    /// Request for the file at the url, signed again for every request
+    fn request(&self, download: &ChunkDownload, url: &str) -> io::Result<FileRequest> {
+        timeout::check_deadline(&self.timeouts, download.deadline)?;
+        let dataset_id = &download.chunk.dataset_id;
+        let mut request = match self.auth.get(dataset_id) {
+            Some(auth) => auth.request(dataset_id, url)?,
+            None => FileRequest::new(url),
+        };
+        // by the url of the signed request, which may be on another host
+        request.proxy = self.proxy.as_ref().and_then(|proxy| proxy.proxy_for(&request.url)).map(str::to_string);
+        if request.url.get(..8).is_some_and(|scheme| scheme.eq_ignore_ascii_case("https://")) {
+            request.tls = self.tls.clone();
+        }
+        request.connect_timeout = self.timeouts.connect;
+        request.read_timeout = self.timeouts.read_timeout(download.deadline);
+        if self.compressed_datasets.contains(dataset_id) {
+            request.accept_encoding = vec![ContentEncoding::Zstd, ContentEncoding::Gzip];
+        }
+        Ok(request)
+    }
+
+    /// Send the request for the file at the url. When it's refused as unauthorized, the cached credentials of the dataset,
+    /// see `CachedCredentials`, are fetched again, the url is refreshed, see `with_url_refresher`, and the request is sent once more.
+    /// The refreshed url is kept for the next requests of the file.
+    fn send<T>(&self, download: &ChunkDownload, url: &mut String, send: impl Fn(&FileRequest) -> io::Result<T>) -> io::Result<T> {
+        match send(&self.request(download, url)?) {
+            Err(error) if auth::is_unauthorized(&error) => {
+                let refreshed_credentials = self.auth.get(&download.chunk.dataset_id).is_some_and(|auth| auth.refresh());
+                if self.url_refresher.is_none() && !refreshed_credentials {
+                    return Err(error);
+                }
+                *url = self.fresh_url(download, url)?;
+                send(&self.request(download, url)?)
+            }
+            result => result,
+        }
+    }
+
+    /// Try the URLs of the file in the mirror order until one succeeds, returns the error of the last one when all fail.
+    /// The bytes received from a failed URL are kept, the next one continues after them.
+    fn download_file_from_mirrors(
+        &self,
+        download: &ChunkDownload,
+        file_path: &Path,
+        throttle: Option<&BandwidthThrottle>,
+        cancelled: &dyn Fn() -> bool,
+        progress: &dyn Fn(FileProgress),
+    ) -> io::Result<()> {
+        let urls = download.chunk.file_urls(download.file_name);
+        let first = match self.mirror_order {
+            MirrorOrder::InOrder => 0,
+            MirrorOrder::RoundRobin => self.next_mirror.fetch_add(1, Ordering::Relaxed) % urls.len().max(1),
+        };
+        let mut result = Err(io::Error::new(io::ErrorKind::NotFound, format!("{} has no url", file_path.display())));
+        for url in urls[first..].iter().chain(&urls[..first]) {
+            result = self.download_file(download, url, file_path, throttle, cancelled, progress);
+            // a cancelled download isn't continued from another mirror, neither is one past its deadline
+            if result.is_ok() || cancelled() || timeout::check_deadline(&self.timeouts, download.deadline).is_err() {
+                break;
+            }
+        }
+        result
+    }
+
+    fn download_file(
+        &self,
+        download: &ChunkDownload,
+        url: &str,
+        file_path: &Path,
+        throttle: Option<&BandwidthThrottle>,
+        cancelled: &dyn Fn() -> bool,
+        progress: &dyn Fn(FileProgress),
+    ) -> io::Result<()> {
+        let mut url = self.fresh_url(download, url)?;
+        let (length, encoding) = self.send(download, &mut url, |request| {
+            Ok((self.source.request_content_length(request)?, self.source.request_content_encoding(request)?))
+        })?;
+        // the length of a decoded file isn't known before it's decoded
+        let encoded = encoding != ContentEncoding::Identity;
+        if !encoded && fs::metadata(file_path).is_ok_and(|metadata| metadata.len() == length) {
+            progress(FileProgress { downloaded_bytes: length, total_bytes: Some(length) });
+            return Ok(());
+        }
+        let mut partial_path = file_path.as_os_str().to_owned();
+        partial_path.push(PARTIAL_SUFFIX);
+        let partial_path = Path::new(&partial_path);
+        let mut offset = fs::metadata(partial_path).map_or(0, |metadata| metadata.len());
+        // the remote file changed since the partial file was written, or it's sent compressed,
+        // and a compressed stream can't be decoded from its middle
+        if offset > length || (encoded && offset > 0) {
+            fs::remove_file(partial_path)?;
+            offset = 0;
+        }
+        progress(FileProgress { downloaded_bytes: offset, total_bytes: Some(length) });
+        let mut received = offset;
+        if offset < length {
+            received = self.send(download, &mut url, |request| {
+                // a request sent again continues after the bytes written before it was refused, or from the start when encoded
+                let offset = match encoded {
+                    true => 0,
+                    false => fs::metadata(partial_path).map_or(0, |metadata| metadata.len()),
+                };
+                let mut partial_file = OpenOptions::new().create(true).append(!encoded).write(encoded).truncate(encoded).open(partial_path)?;
+                let mut decoding_writer = DecodingWriter::new(encoding, &mut partial_file)?;
+                let mut cancellable_writer = CancellableWriter::new(&mut decoding_writer, cancelled);
+                let mut timeout_writer = TimeoutWriter::new(&mut cancellable_writer, self.timeouts, download.deadline);
+                let mut writer = ProgressWriter::new(&mut timeout_writer, FileProgress { downloaded_bytes: offset, total_bytes: Some(length) }, progress);
+                let written = match throttle {
+                    Some(throttle) => self.source.request_range(request, offset, &mut throttle.writer(&mut writer))?,
+                    None => self.source.request_range(request, offset, &mut writer)?,
+                };
+                decoding_writer.finish()?;
+                partial_file.sync_all()?;
+                Ok(offset + written)
+            })?;
+        }
+        if encoded && received != length {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} received {} {} bytes, the remote file has {}", partial_path.display(), received, encoding, length),
+            ));
+        }
+        let downloaded = fs::metadata(partial_path).map_or(0, |metadata| metadata.len());
+        if !encoded && downloaded != length {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} has {} bytes, the remote file has {}", partial_path.display(), downloaded, length),
+            ));
+        }
+        fs::rename(partial_path, file_path)
+    }
+}
+
+impl ResumableTransfer {
+    fn download_files(
+        &self,
+        chunk: &DataChunk,
+        chunk_dir: &Path,
+        throttle: Option<&BandwidthThrottle>,
+        cancelled: &dyn Fn() -> bool,
+        progress: &dyn Fn(&str, FileProgress),
+    ) -> io::Result<()> {
+        check_file_names(chunk)?;
+        fs::create_dir_all(chunk_dir)?;
+        let deadline = self.timeouts.total.map(|total| Instant::now() + total);
+        for file_name in chunk.files.keys() {
+            let download = ChunkDownload { chunk, file_name, deadline };
+            let file_progress = |file_progress| progress(file_name, file_progress);
+            self.download_file_from_mirrors(&download, &chunk_dir.join(file_name), throttle, cancelled, &file_progress)?;
+        }
+        Ok(())
+    }
+}
+
+impl ChunkTransfer for ResumableTransfer {
+    fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+        self.download_files(chunk, chunk_dir, None, &|| false, &|_, _| {})
+    }
+
+    /// The bytes are taken from the throttle as they are received
+    fn throttled_download(&self, chunk: &DataChunk, chunk_dir: &Path, throttle: &BandwidthThrottle) -> io::Result<()> {
+        self.download_files(chunk, chunk_dir, Some(throttle), &|| false, &|_, _| {})
+    }
+
+    /// A cancelled download stops at the next write, the bytes received so far stay in the `.partial` file
+    fn cancellable_download(
+        &self,
+        chunk: &DataChunk,
+        chunk_dir: &Path,
+        throttle: Option<&BandwidthThrottle>,
+        cancelled: &dyn Fn() -> bool,
+    ) -> io::Result<()> {
+        self.download_files(chunk, chunk_dir, throttle, cancelled, &|_, _| {})
+    }
+
+    /// Every write is reported, a resumed file starts from the bytes of its `.partial` file
+    fn tracked_download(
+        &self,
+        chunk: &DataChunk,
+        chunk_dir: &Path,
+        throttle: Option<&BandwidthThrottle>,
+        cancelled: &dyn Fn() -> bool,
+        progress: &dyn Fn(&str, FileProgress),
+    ) -> io::Result<()> {
+        self.download_files(chunk, chunk_dir, throttle, cancelled, progress)
+    }
+
+    fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+        fs::remove_dir_all(chunk_dir)
+    }
+}
+
+fn copy_dir_all(src: &Path, dst: &Path) -> io::Result<()> {
+    if !dst.exists() {
+        fs::create_dir(dst)?;
+    }
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let src_path = entry.path();
+        let dst_path = dst.join(entry.file_name());
+        if file_type.is_dir() {
+            copy_dir_all(&src_path, &dst_path)?;
+        } else {
+            fs::copy(&src_path, &dst_path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use crate::config::DataManagerConfig;
+    use crate::data_catalogue::ChunkStatus;
+    use crate::download_handle::DownloadError;
+    use crate::data_manager::DataManager;
+    use crate::throttle::BandwidthLimit;
+    use std::collections::BTreeMap;
+    use crate::auth::{CachedCredentials, CredentialProvider, Credentials, RequestSigner};
+    use crate::data_chunk::is_plain_file_name;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// Remote file served from memory, the first request breaks off after `fail_after` bytes
+    struct FlakySource {
+        content: Vec<u8>,
+        fail_after: Mutex<Option<usize>>,
+        offsets: Mutex<Vec<u64>>,
+    }
+
+    impl RangeSource for FlakySource {
+        fn content_length(&self, _url: &str) -> io::Result<u64> {
+            Ok(self.content.len() as u64)
+        }
+
+        fn read_range(&self, _url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            self.offsets.lock().unwrap().push(offset);
+            let remaining = &self.content[offset as usize..];
+            if let Some(fail_after) = self.fail_after.lock().unwrap().take() {
+                writer.write_all(&remaining[..fail_after])?;
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "connection reset"));
+            }
+            writer.write_all(remaining)?;
+            Ok(remaining.len() as u64)
+        }
+    }
+
+    /// Remote files served from memory by several hosts, the hosts which are down refuse every request
+    struct MirroredSource {
+        content: Vec<u8>,
+        down_hosts: Vec<&'static str>,
+        requests: Mutex<Vec<String>>,
+    }
+
+    impl RangeSource for MirroredSource {
+        fn content_length(&self, url: &str) -> io::Result<u64> {
+            self.requests.lock().unwrap().push(url.to_string());
+            if self.down_hosts.iter().any(|host| url.contains(host)) {
+                return Err(io::Error::new(io::ErrorKind::ConnectionRefused, format!("{} is down", url)));
+            }
+            Ok(self.content.len() as u64)
+        }
+
+        fn read_range(&self, _url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            writer.write_all(&self.content[offset as usize..])?;
+            Ok(self.content.len() as u64 - offset)
+        }
+    }
+
+    /// Remote file served only to the requests with the bearer token or a signed url, recording the requests
+    struct AuthenticatedSource {
+        content: Vec<u8>,
+        requests: Mutex<Vec<FileRequest>>,
+    }
+
+    impl AuthenticatedSource {
+        fn authorize(&self, request: &FileRequest) -> io::Result<()> {
+            self.requests.lock().unwrap().push(request.clone());
+            let authorized = request.headers.get("Authorization").is_some_and(|value| value == "Bearer secret") || request.url.contains("?sig=");
+            match authorized {
+                true => Ok(()),
+                false => Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("401 Unauthorized {}", request.url))),
+            }
+        }
+    }
+
+    impl RangeSource for AuthenticatedSource {
+        fn content_length(&self, url: &str) -> io::Result<u64> {
+            self.request_content_length(&FileRequest::new(url))
+        }
+
+        fn read_range(&self, url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            self.request_range(&FileRequest::new(url), offset, writer)
+        }
+
+        fn request_content_length(&self, request: &FileRequest) -> io::Result<u64> {
+            self.authorize(request)?;
+            Ok(self.content.len() as u64)
+        }
+
+        fn request_range(&self, request: &FileRequest, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            self.authorize(request)?;
+            writer.write_all(&self.content[offset as usize..])?;
+            Ok(self.content.len() as u64 - offset)
+        }
+    }
+
+    /// Remote file served only to the requests with the current token, which the test rotates
+    struct RotatingTokenSource {
+        content: Vec<u8>,
+        token: Mutex<String>,
+        requests: Mutex<Vec<FileRequest>>,
+    }
+
+    impl RotatingTokenSource {
+        fn authorize(&self, request: &FileRequest) -> io::Result<()> {
+            self.requests.lock().unwrap().push(request.clone());
+            match request.headers.get("X-Token") == Some(&*self.token.lock().unwrap()) {
+                true => Ok(()),
+                false => Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("401 Unauthorized {}", request.url))),
+            }
+        }
+    }
+
+    impl RangeSource for RotatingTokenSource {
+        fn content_length(&self, url: &str) -> io::Result<u64> {
+            self.request_content_length(&FileRequest::new(url))
+        }
+
+        fn read_range(&self, url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            self.request_range(&FileRequest::new(url), offset, writer)
+        }
+
+        fn request_content_length(&self, request: &FileRequest) -> io::Result<u64> {
+            self.authorize(request)?;
+            Ok(self.content.len() as u64)
+        }
+
+        fn request_range(&self, request: &FileRequest, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            self.authorize(request)?;
+            writer.write_all(&self.content[offset as usize..])?;
+            Ok(self.content.len() as u64 - offset)
+        }
+    }
+
+    /// Remote file served only at the url with the current signature, which the test expires
+    struct PresignedSource {
+        content: Vec<u8>,
+        signature: Mutex<String>,
+        urls: Mutex<Vec<String>>,
+    }
+
+    impl PresignedSource {
+        fn authorize(&self, url: &str) -> io::Result<()> {
+            self.urls.lock().unwrap().push(url.to_string());
+            match url.ends_with(&format!("?sig={}", self.signature.lock().unwrap())) {
+                true => Ok(()),
+                false => Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("403 Forbidden {}", url))),
+            }
+        }
+    }
+
+    impl RangeSource for PresignedSource {
+        fn content_length(&self, url: &str) -> io::Result<u64> {
+            self.authorize(url)?;
+            Ok(self.content.len() as u64)
+        }
+
+        fn read_range(&self, url: &str, offset: u64, writer: &mut dyn Write) -> io::Result<u64> {
+            self.authorize(url)?;
+            writer.write_all(&self.content[offset as usize..])?;
+            Ok(self.content.len() as u64 - offset)
+        }
+    }
+
+    fn mirrored_chunk(file_names: &[&str]) -> DataChunk {
+        DataChunk {
+            files: file_names.iter().map(|name| (name.to_string(), format!("https://a.example.com/{}", name))).collect(),
+            mirrors: file_names.iter()
+                .map(|name| (name.to_string(), vec![format!("https://b.example.com/{}", name), format!("https://c.example.com/{}", name)]))
+                .collect(),
+            ..chunk()
+        }
+    }
+
+    fn chunk() -> DataChunk {
+        DataChunk { id: [3u8; 32], ..test_support::chunk(3, 0..10) }
+    }
+
+    #[test]
+    fn test_interrupted_download_is_resumed() {
+        // Arrange
+        let chunk_dir = test_support::temp_dir("resumable");
+        let source = Arc::new(FlakySource { content: (0..=255).collect(), fail_after: Mutex::new(Some(100)), offsets: Mutex::new(Vec::new()) });
+        let transfer = ResumableTransfer::new(source.clone());
+
+        // Act
+        let interrupted = transfer.download(&chunk(), &chunk_dir);
+        let partial_length = fs::metadata(chunk_dir.join("blocks.parquet.partial")).unwrap().len();
+        let resumed = transfer.download(&chunk(), &chunk_dir);
+
+        // Assert
+        assert_eq!(interrupted.unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(partial_length, 100);
+        resumed.unwrap();
+        assert_eq!(*source.offsets.lock().unwrap(), vec![0, 100]);
+        assert_eq!(fs::read(chunk_dir.join("blocks.parquet")).unwrap(), source.content);
+        assert!(!chunk_dir.join("blocks.parquet.partial").exists());
+
+        // cleanup
+        transfer.delete(&chunk(), &chunk_dir).unwrap();
+    }
+
+    #[test]
+    fn test_stale_partial_file_is_downloaded_again() {
+        // Arrange
+        let chunk_dir = test_support::temp_dir("incomplete");
+        fs::create_dir_all(&chunk_dir).unwrap();
+        // longer than the remote file, e.g. the remote file was replaced by a shorter one
+        fs::write(chunk_dir.join("blocks.parquet.partial"), [0u8; 300]).unwrap();
+        let source = Arc::new(FlakySource { content: (0..=255).collect(), fail_after: Mutex::new(Some(10)), offsets: Mutex::new(Vec::new()) });
+        let transfer = ResumableTransfer::new(source.clone());
+
+        // Act
+        let result = transfer.download(&chunk(), &chunk_dir);
+
+        // Assert
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(*source.offsets.lock().unwrap(), vec![0]);
+        assert_eq!(fs::read(chunk_dir.join("blocks.parquet.partial")).unwrap(), source.content[..10]);
+        assert!(!chunk_dir.join("blocks.parquet").exists());
+
+        // cleanup
+        transfer.delete(&chunk(), &chunk_dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_names_outside_the_chunk_directory_are_refused() {
+        // Arrange
+        let dir = test_support::temp_dir("file_names");
+        let source = Arc::new(FlakySource { content: vec![7u8; 64], fail_after: Mutex::new(None), offsets: Mutex::new(Vec::new()) });
+        let transfer = ResumableTransfer::new(source.clone());
+        let escaping = dir.join("escaped.parquet").display().to_string();
+
+        // Act
+        let results: Vec<io::Result<()>> = ["../escaped.parquet", escaping.as_str(), "", "nested/blocks.parquet"]
+            .into_iter()
+            .map(|file_name| transfer.download(&mirrored_chunk(&[file_name]), &dir.join("chunk")))
+            .collect();
+
+        // Assert
+        for result in results {
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(source.offsets.lock().unwrap().is_empty());
+        assert!(!dir.join("escaped.parquet").exists());
+        assert!(is_plain_file_name("blocks.parquet"));
+
+        // cleanup
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_failed_urls_fall_back_to_mirrors() {
+        // Arrange
+        let chunk_dir = test_support::temp_dir("mirrors");
+        let source = Arc::new(MirroredSource { content: vec![7u8; 64], down_hosts: vec!["a.example.com"], requests: Mutex::new(Vec::new()) });
+        let in_order = ResumableTransfer::new(source.clone());
+        let round_robin = ResumableTransfer::new(source.clone()).with_mirror_order(MirrorOrder::RoundRobin);
+        let chunk = mirrored_chunk(&["blocks.parquet"]);
+
+        // Act
+        in_order.download(&chunk, &chunk_dir.join("in_order")).unwrap();
+        let in_order_requests = std::mem::take(&mut *source.requests.lock().unwrap());
+        for i in 0..3 {
+            round_robin.download(&chunk, &chunk_dir.join(format!("round_robin_{}", i))).unwrap();
+        }
+        let round_robin_requests = std::mem::take(&mut *source.requests.lock().unwrap());
+
+        // Assert
+        assert_eq!(in_order_requests, vec!["https://a.example.com/blocks.parquet", "https://b.example.com/blocks.parquet"]);
+        assert_eq!(fs::read(chunk_dir.join("in_order/blocks.parquet")).unwrap(), source.content);
+        assert_eq!(round_robin_requests, vec![
+            "https://a.example.com/blocks.parquet",
+            "https://b.example.com/blocks.parquet",
+            "https://b.example.com/blocks.parquet",
+            "https://c.example.com/blocks.parquet",
+        ]);
+
+        // cleanup
+        fs::remove_dir_all(chunk_dir).unwrap();
+    }
+
+    #[test]
+    fn test_chunk_fails_once_all_mirrors_fail() {
+        // Arrange
+        let dir = test_support::temp_dir("exhausted_mirrors");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let source = Arc::new(MirroredSource {
+            content: vec![7u8; 64],
+            down_hosts: vec!["a.example.com", "b.example.com", "c.example.com"],
+            requests: Mutex::new(Vec::new()),
+        });
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(ResumableTransfer::new(source.clone())))
+            .build();
+        let chunk = mirrored_chunk(&["blocks.parquet"]);
+
+        // Act
+        let result = data_manager.download_chunk_with_handle(chunk.clone()).wait();
+
+        // Assert
+        assert!(matches!(result, Err(DownloadError::Failed(Some(_)))));
+        let info = data_manager.get_chunk_info(chunk.id).unwrap();
+        assert_eq!(info.status, ChunkStatus::Failed);
+        assert_eq!(info.errors[0].message, "https://c.example.com/blocks.parquet is down");
+        assert_eq!(source.requests.lock().unwrap().len(), 3);
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_download_is_resumed_by_data_manager() {
+        // Arrange
+        let dir = test_support::temp_dir("resumed_download");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let source = Arc::new(FlakySource { content: (0..=255).collect(), fail_after: Mutex::new(Some(100)), offsets: Mutex::new(Vec::new()) });
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(ResumableTransfer::new(source.clone())))
+            .build();
+
+        // Act
+        data_manager.download_chunk(chunk());
+        data_manager.data_catalogue.wait_until_downloaded(&[chunk().id]);
+        let failed_status = data_manager.get_chunk_info(chunk().id).map(|info| info.status);
+        // the interrupted download stays staged, a restart doesn't find a half populated chunk
+        let failed_chunk_dir_exists = data_manager.data_source.chunk_path(chunk()).path.exists();
+        let scanned_after_failure = data_manager.data_source.get_local_chunk_ids();
+        data_manager.download_chunk(chunk());
+        data_manager.data_catalogue.wait_until_downloaded(&[chunk().id]);
+
+        // Assert
+        assert_eq!(failed_status, Some(ChunkStatus::Failed));
+        assert!(!failed_chunk_dir_exists);
+        assert!(scanned_after_failure.is_empty());
+        assert_eq!(data_manager.get_chunk_info(chunk().id).map(|info| info.status), Some(ChunkStatus::Ready));
+        assert_eq!(*source.offsets.lock().unwrap(), vec![0, 100]);
+        assert_eq!(data_manager.data_source.get_local_chunk_ids().len(), 1);
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_downloads_are_throttled() {
+        // Arrange
+        let dir = test_support::temp_dir("throttled_download");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let source = Arc::new(FlakySource { content: vec![1u8; 300], fail_after: Mutex::new(None), offsets: Mutex::new(Vec::new()) });
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data"))
+                .with_catalogue_file(dir.join("registry.parquet"))
+                .with_download_bandwidth(BandwidthLimit { bytes_per_second: 1_000, burst_bytes: 100 }))
+            .chunk_transfer(Arc::new(ResumableTransfer::new(source)))
+            .build();
+        let started_at = std::time::Instant::now();
+
+        // Act
+        data_manager.download_chunk(chunk());
+        data_manager.data_catalogue.wait_until_downloaded(&[chunk().id]);
+
+        // Assert
+        assert_eq!(data_manager.get_chunk_info(chunk().id).map(|info| info.status), Some(ChunkStatus::Ready));
+        // 100 bytes of burst, the other 200 bytes take 200ms
+        assert!(started_at.elapsed() >= Duration::from_millis(190));
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_requests_are_authenticated_per_dataset() {
+        // Arrange
+        let chunk_dir = test_support::temp_dir("auth");
+        let source = Arc::new(AuthenticatedSource { content: vec![5u8; 32], requests: Mutex::new(Vec::new()) });
+        let signatures = Arc::new(AtomicUsize::new(0));
+        let signer: RequestSigner = {
+            let signatures = signatures.clone();
+            Arc::new(move |_dataset_id: &DatasetId, url: &str| Ok(FileRequest::new(format!("{}?sig={}", url, signatures.fetch_add(1, Ordering::SeqCst)))))
+        };
+        let (bearer, signed, anonymous) = (chunk(), DataChunk { dataset_id: [4u8; 32], ..chunk() }, DataChunk { dataset_id: [5u8; 32], ..chunk() });
+        let transfer = ResumableTransfer::new(source.clone())
+            .with_auth(bearer.dataset_id, AuthProvider::BearerToken("secret".to_string()))
+            .with_auth(signed.dataset_id, AuthProvider::Signer(signer));
+
+        // Act
+        let bearer_result = transfer.download(&bearer, &chunk_dir.join("bearer"));
+        let signed_result = transfer.download(&signed, &chunk_dir.join("signed"));
+        let anonymous_result = transfer.download(&anonymous, &chunk_dir.join("anonymous"));
+
+        // Assert
+        bearer_result.unwrap();
+        signed_result.unwrap();
+        assert_eq!(anonymous_result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        let requests = source.requests.lock().unwrap();
+        assert_eq!(requests[0], FileRequest::new("https://example.com/blocks.parquet").with_header("Authorization", "Bearer secret"));
+        // the content length and the range requests are signed separately
+        assert_eq!(requests[2].url, "https://example.com/blocks.parquet?sig=0");
+        assert_eq!(requests[3].url, "https://example.com/blocks.parquet?sig=1");
+        assert_eq!(signatures.load(Ordering::SeqCst), 2);
+
+        // cleanup
+        fs::remove_dir_all(chunk_dir).unwrap();
+    }
+
+    #[test]
+    fn test_refused_credentials_are_fetched_again() {
+        // Arrange
+        let chunk_dir = test_support::temp_dir("credentials");
+        let source = Arc::new(RotatingTokenSource { content: vec![6u8; 16], token: Mutex::new("token-1".to_string()), requests: Mutex::new(Vec::new()) });
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let provider: CredentialProvider = {
+            let fetches = fetches.clone();
+            Arc::new(move |_dataset_id: &DatasetId| Ok(Credentials {
+                headers: BTreeMap::from([("X-Token".to_string(), format!("token-{}", fetches.fetch_add(1, Ordering::SeqCst) + 1))]),
+                ..Credentials::default()
+            }))
+        };
+        let transfer = ResumableTransfer::new(source.clone())
+            .with_auth(chunk().dataset_id, AuthProvider::Credentials(CachedCredentials::new(provider)));
+
+        // Act
+        let first_result = transfer.download(&chunk(), &chunk_dir.join("first"));
+        *source.token.lock().unwrap() = "token-2".to_string();
+        let rotated_result = transfer.download(&chunk(), &chunk_dir.join("rotated"));
+
+        // Assert
+        first_result.unwrap();
+        rotated_result.unwrap();
+        assert_eq!(fs::read(chunk_dir.join("rotated").join("blocks.parquet")).unwrap(), vec![6u8; 16]);
+        // the cached token is used until it's refused, then fetched once
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        let tokens: Vec<String> = source.requests.lock().unwrap().iter().map(|request| request.headers["X-Token"].clone()).collect();
+        assert_eq!(tokens, vec!["token-1", "token-1", "token-1", "token-2", "token-2"]);
+
+        // cleanup
+        fs::remove_dir_all(chunk_dir).unwrap();
+    }
+
+    #[test]
+    fn test_expired_urls_are_refreshed() {
+        // Arrange
+        let chunk_dir = test_support::temp_dir("presigned");
+        let source = Arc::new(PresignedSource { content: vec![7u8; 16], signature: Mutex::new("1".to_string()), urls: Mutex::new(Vec::new()) });
+        let refreshes = Arc::new(AtomicUsize::new(0));
+        let refresher: UrlRefresher = {
+            let refreshes = refreshes.clone();
+            Arc::new(move |_chunk: &DataChunk, file_name: &str, _url: &str| {
+                Ok(format!("https://example.com/{}?sig={}", file_name, refreshes.fetch_add(1, Ordering::SeqCst) + 1))
+            })
+        };
+        let stale = DataChunk {
+            files: HashMap::from([("blocks.parquet".to_string(), "https://example.com/blocks.parquet?sig=0".to_string())]),
+            ..chunk()
+        };
+        let transfer = ResumableTransfer::new(source.clone()).with_url_refresher(refresher);
+
+        // Act
+        let without_refresher = ResumableTransfer::new(source.clone()).download(&stale, &chunk_dir.join("stale"));
+        let first_result = transfer.download(&stale, &chunk_dir.join("first"));
+        // the signature handed out next expires before it's used
+        *source.signature.lock().unwrap() = "3".to_string();
+        let expired_result = transfer.download(&stale, &chunk_dir.join("expired"));
+
+        // Assert
+        assert_eq!(without_refresher.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        first_result.unwrap();
+        expired_result.unwrap();
+        assert_eq!(fs::read(chunk_dir.join("expired").join("blocks.parquet")).unwrap(), vec![7u8; 16]);
+        assert_eq!(refreshes.load(Ordering::SeqCst), 3);
+        let signatures: Vec<String> = source.urls.lock().unwrap().iter().map(|url| url.rsplit('=').next().unwrap().to_string()).collect();
+        assert_eq!(signatures, vec!["0", "1", "1", "2", "3", "3"]);
+
+        // cleanup
+        fs::remove_dir_all(chunk_dir).unwrap();
+    }
+
+    #[test]
+    fn test_headers_proxies_and_certificates_are_refused_by_sources_which_cant_use_them() {
+        let source = FlakySource { content: vec![1u8; 8], fail_after: Mutex::new(None), offsets: Mutex::new(Vec::new()) };
+        assert_eq!(source.request_content_length(&FileRequest::new("https://example.com/a")).unwrap(), 8);
+        let authenticated = FileRequest::new("https://example.com/a").with_header("Authorization", "Bearer secret");
+        assert_eq!(source.request_content_length(&authenticated).unwrap_err().kind(), io::ErrorKind::Unsupported);
+        assert_eq!(source.request_range(&authenticated, 0, &mut Vec::new()).unwrap_err().kind(), io::ErrorKind::Unsupported);
+        let proxied = FileRequest::new("https://example.com/a").with_proxy("http://proxy:3128");
+        assert_eq!(source.request_content_length(&proxied).unwrap_err().kind(), io::ErrorKind::Unsupported);
+        let with_certificates = FileRequest { tls: Some(Arc::new(TlsConfig::default())), ..FileRequest::new("https://example.com/a") };
+        assert_eq!(source.request_range(&with_certificates, 0, &mut Vec::new()).unwrap_err().kind(), io::ErrorKind::Unsupported);
+        assert!(source.offsets.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+use std::io;
+use std::path::Path;
+use crate::data_chunk::DataChunk;
+#[cfg(feature = "dataframes")]
+use {
+    std::fs::{self, File},
+    polars::prelude::*,
+    crate::data_chunk::BLOCK_NUMBER_COLUMN,
+};
+
+/// Rewrites the files of a downloaded chunk before it's marked `Ready`,
+/// e.g. dropping columns, redacting fields or re-sorting by block,
+/// for deployments with compliance constraints on the stored data.
+///
+/// Transformers run in the worker thread in the order they were registered.
+/// When any of them fails, the chunk is marked `Failed` and can be downloaded again.
+pub trait ChunkTransformer: Send + Sync {
+    fn transform(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()>;
+
+    /// Whether the transformer lays the files out for faster scans,
+    /// the chunk is then recorded as optimized in the catalogue
+    fn optimizes_layout(&self) -> bool {
+        false
+    }
+}
+
+/// Rewrites the parquet files of a chunk sorted by the block number and split into row groups of the given size,
+/// so predicate pushdown of later scans can skip most of the row groups
+#[cfg(feature = "dataframes")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParquetOptimizer {
+    /// Column the rows are sorted by, files without it are only split into row groups
+    pub sort_column: String,
+    /// Number of rows in a row group
+    pub row_group_size: usize,
+}
+
+#[cfg(feature = "dataframes")]
+impl Default for ParquetOptimizer {
+    fn default() -> Self {
+        ParquetOptimizer {
+            sort_column: BLOCK_NUMBER_COLUMN.to_string(),
+            row_group_size: 100_000,
+        }
+    }
+}
+
+#[cfg(feature = "dataframes")]
+impl ChunkTransformer for ParquetOptimizer {
+    fn transform(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+        for entry in fs::read_dir(chunk_dir)? {
+            let path = entry?.path();
+            // empty files are placeholders without any rows to sort
+            if path.extension().is_some_and(|extension| extension == "parquet") && fs::metadata(&path)?.len() > 0 {
+                self.optimize_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn optimizes_layout(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(feature = "dataframes")]
+impl ParquetOptimizer {
+    fn optimize_file(&self, path: &Path) -> io::Result<()> {
+        let mut df = ParquetReader::new(File::open(path)?).finish().map_err(to_io_error)?;
+        if df.get_column_index(&self.sort_column).is_some() {
+            df = df.sort([self.sort_column.as_str()], SortMultipleOptions::default()).map_err(to_io_error)?;
+        }
+
+        // the file is replaced at once, so a crash never leaves it half written
+        let optimized_path = path.with_extension("parquet.optimized");
+        ParquetWriter::new(File::create(&optimized_path)?)
+            .with_row_group_size(Some(self.row_group_size.max(1)))
+            .finish(&mut df)
+            .map_err(to_io_error)?;
+        fs::rename(optimized_path, path)
+    }
+}
+
+#[cfg(feature = "dataframes")]
+fn to_io_error(error: PolarsError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(all(test, feature = "dataframes"))]
+mod tests {
+    use std::collections::HashMap;
+    use crate::test_support;
+    use super::*;
+
+    #[test]
+    fn test_parquet_is_sorted_into_row_groups() {
+        // Arrange
+        let chunk_dir = test_support::temp_dir("optimizer");
+        fs::create_dir_all(&chunk_dir).unwrap();
+        let path = chunk_dir.join("part-1.parquet");
+        let block_numbers: Vec<u64> = (0..1000).rev().collect();
+        let mut df = df!(
+            "block_number" => &block_numbers,
+            "value" => block_numbers.iter().map(|block_number| block_number * 10).collect::<Vec<u64>>()
+        ).unwrap();
+        ParquetWriter::new(File::create(&path).unwrap()).finish(&mut df).unwrap();
+        fs::write(chunk_dir.join("part-2.parquet"), []).unwrap();
+        let chunk = DataChunk { id: [1u8; 32], dataset_id: [1u8; 32], block_range: 1..6, files: HashMap::new(), mirrors: HashMap::new(), size: None, file_sizes: HashMap::new() };
+        let optimizer = ParquetOptimizer { row_group_size: 100, ..ParquetOptimizer::default() };
+
+        // Act
+        optimizer.transform(&chunk, &chunk_dir).unwrap();
+
+        // Assert
+        let mut reader = ParquetReader::new(File::open(&path).unwrap());
+        assert_eq!(reader.get_metadata().unwrap().row_groups.len(), 10);
+        let optimized = reader.finish().unwrap();
+        let values: Vec<u64> = optimized.column("value").unwrap().u64().unwrap().into_no_null_iter().collect();
+        assert_eq!(values, (0..1000).map(|block_number| block_number * 10).collect::<Vec<u64>>());
+        fs::remove_dir_all(chunk_dir).unwrap();
+    }
+}
@@ -0,0 +1,291 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use crate::data_chunk::DatasetId;
+#[cfg(feature = "dataframes")]
+use {
+    std::path::Path,
+    std::sync::Arc,
+    polars::io::HiveOptions,
+    polars::prelude::*,
+    crate::data_chunk::{DataChunk, BLOCK_NUMBER_COLUMN},
+};
+
+/// Trusted source of the block hashes of a chain, e.g. a client of the RPC endpoint of an archive node
+pub trait TrustedChain: Send + Sync {
+    /// Hash of the block on the canonical chain, `None` when the chain has no such block, e.g. a skipped slot
+    fn block_hash(&self, dataset_id: &DatasetId, block_number: u64) -> io::Result<Option<String>>;
+}
+
+/// Checks sampled block hashes of every downloaded chunk of a dataset against a trusted chain
+/// before the chunk is marked `Ready`, so chunks from another fork never become available.
+/// The hashes are read from the parquet files of the chunk, so it needs the `dataframes` feature.
+#[cfg(feature = "dataframes")]
+#[derive(Clone)]
+pub struct BlockHashVerifier {
+    chain: Arc<dyn TrustedChain>,
+    /// File of the chunk with a row per block
+    pub file_name: String,
+    pub hash_column: String,
+    /// Number of blocks checked per chunk, spread evenly over its block range including the first and the last block
+    pub samples: usize,
+}
+
+#[cfg(feature = "dataframes")]
+impl BlockHashVerifier {
+    pub fn new(chain: Arc<dyn TrustedChain>) -> Self {
+        BlockHashVerifier {
+            chain,
+            file_name: "blocks.parquet".to_string(),
+            hash_column: "hash".to_string(),
+            samples: 3,
+        }
+    }
+
+    pub fn with_file(mut self, file_name: &str, hash_column: &str) -> Self {
+        self.file_name = file_name.to_string();
+        self.hash_column = hash_column.to_string();
+        self
+    }
+
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    /// Compare the sampled blocks of the chunk files in the directory with the trusted chain.
+    /// A mismatch is reported as an `InvalidData` error wrapping the `HashMismatch`.
+    pub fn verify(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+        let blocks = sample_blocks(chunk, self.samples);
+        let local_hashes = self.read_hashes(&chunk_dir.join(&self.file_name), &blocks).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        for (block_number, local_hash) in blocks.into_iter().zip(local_hashes) {
+            let trusted_hash = self.chain.block_hash(&chunk.dataset_id, block_number)?;
+            if local_hash != trusted_hash {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, HashMismatch { block_number, local_hash, trusted_hash }));
+            }
+        }
+        Ok(())
+    }
+
+    /// Hashes of the blocks in the file, `None` for the blocks without a row
+    fn read_hashes(&self, file_path: &Path, blocks: &[u64]) -> PolarsResult<Vec<Option<String>>> {
+        // typed literals, so the row group statistics can be compared with them
+        let predicate = blocks.iter()
+            .map(|block_number| col(BLOCK_NUMBER_COLUMN).eq(lit(*block_number).cast(DataType::UInt64)))
+            .reduce(|predicate, block| predicate.or(block))
+            .unwrap_or(lit(false));
+        // the `dataset_id=` and `block_range=` directories aren't hive partitions of the data
+        let hive_options = HiveOptions { enabled: Some(false), ..HiveOptions::default() };
+        let df = LazyFrame::scan_parquet(file_path, ScanArgsParquet { hive_options, ..ScanArgsParquet::default() })?
+            .filter(predicate)
+            .select([col(BLOCK_NUMBER_COLUMN).cast(DataType::UInt64), col(&self.hash_column)])
+            .collect()?;
+        let block_numbers = df.column(BLOCK_NUMBER_COLUMN)?.u64()?;
+        let hashes = df.column(&self.hash_column)?.str()?;
+        Ok(blocks.iter()
+            .map(|block_number| {
+                (0..df.height())
+                    .find(|i| block_numbers.get(*i) == Some(*block_number))
+                    .and_then(|i| hashes.get(i))
+                    .map(str::to_string)
+            })
+            .collect())
+    }
+}
+
+/// A sampled block of a chunk differs from the trusted chain
+#[derive(Clone, Debug, PartialEq)]
+pub struct HashMismatch {
+    pub block_number: u64,
+    pub local_hash: Option<String>,
+    pub trusted_hash: Option<String>,
+}
+
+impl fmt::Display for HashMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "block {} has hash {}, the trusted chain has {}",
+            self.block_number,
+            self.local_hash.as_deref().unwrap_or("none"),
+            self.trusted_hash.as_deref().unwrap_or("none")
+        )
+    }
+}
+
+impl Error for HashMismatch {}
+
+/// Whether the error is a failed verification, rather than a problem reading the files or reaching the chain
+pub fn is_hash_mismatch(error: &io::Error) -> bool {
+    error.get_ref().is_some_and(|error| error.is::<HashMismatch>())
+}
+
+/// Evenly spread blocks of the chunk, always including the first and the last one
+#[cfg(feature = "dataframes")]
+fn sample_blocks(chunk: &DataChunk, samples: usize) -> Vec<u64> {
+    let (first, last) = (chunk.block_range.start, chunk.block_range.end.saturating_sub(1).max(chunk.block_range.start));
+    if samples <= 1 {
+        return vec![last];
+    }
+    let mut blocks: Vec<u64> = (0..samples as u64)
+        .map(|sample| first + (last - first) * sample / (samples as u64 - 1))
+        .collect();
+    blocks.dedup();
+    blocks
+}
+
+#[cfg(all(test, feature = "dataframes"))]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::Duration;
+    use crate::chunk_errors::ChunkErrorKind;
+    use crate::config::DataManagerConfig;
+    use crate::data_catalogue::ChunkStatus;
+    use crate::data_manager::DataManager;
+    use crate::devtools::{self, DatasetSpec};
+    use crate::planning::DirectoryLayout;
+    use crate::local_data_source::LocalDataSource;
+    use crate::transfer::ChunkTransfer;
+    use crate::DataManagerImpl;
+    use crate::test_support;
+    use super::*;
+
+    /// Chain with the hashes the generated datasets have, other than from the forked block on
+    struct GeneratedChain {
+        fork_at: Option<u64>,
+    }
+
+    impl TrustedChain for GeneratedChain {
+        fn block_hash(&self, _dataset_id: &DatasetId, block_number: u64) -> io::Result<Option<String>> {
+            if self.fork_at.is_some_and(|fork_at| block_number >= fork_at) {
+                return Ok(Some("0xfork".to_string()));
+            }
+            Ok(Some(format!("0x{}", sha256::digest(format!("blocks.parquet:{}:0", block_number)))))
+        }
+    }
+
+    /// Remote storage serving the chunks generated into its directory
+    struct GeneratedTransfer {
+        remote_dir: PathBuf,
+    }
+
+    impl ChunkTransfer for GeneratedTransfer {
+        fn download(&self, chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            let remote_dir = LocalDataSource::new(self.remote_dir.clone()).chunk_path(chunk.clone()).path;
+            fs::create_dir_all(chunk_dir)?;
+            for file_name in chunk.files.keys() {
+                fs::copy(remote_dir.join(file_name), chunk_dir.join(file_name))?;
+            }
+            Ok(())
+        }
+
+        fn delete(&self, _chunk: &DataChunk, chunk_dir: &Path) -> io::Result<()> {
+            fs::remove_dir_all(chunk_dir)
+        }
+    }
+
+    #[test]
+    fn test_sampled_blocks_are_spread_over_chunk() {
+        let chunk = DataChunk { id: [0u8; 32], dataset_id: [0u8; 32], block_range: 100..110, files: Default::default(), mirrors: Default::default(), size: None, file_sizes: Default::default() };
+        assert_eq!(sample_blocks(&chunk, 3), vec![100, 104, 109]);
+        assert_eq!(sample_blocks(&chunk, 1), vec![109]);
+        assert_eq!(sample_blocks(&DataChunk { block_range: 5..6, ..chunk }, 3), vec![5]);
+    }
+
+    #[test]
+    fn test_chunk_from_another_fork_is_rejected() {
+        // Arrange
+        let data_dir = test_support::temp_dir("verification");
+        let chunk = devtools::generate(&data_dir, DirectoryLayout::default(), &[DatasetSpec::new([4u8; 32], 100, 1, 10)]).unwrap().remove(0);
+        let chunk_dir = LocalDataSource::new(data_dir.clone()).chunk_path(chunk.clone()).path;
+        let canonical = BlockHashVerifier::new(Arc::new(GeneratedChain { fork_at: None }));
+        let forked = BlockHashVerifier::new(Arc::new(GeneratedChain { fork_at: Some(105) }));
+
+        // Act
+        let canonical_result = canonical.verify(&chunk, &chunk_dir);
+        let forked_result = forked.verify(&chunk, &chunk_dir);
+
+        // Assert
+        canonical_result.unwrap();
+        let error = forked_result.unwrap_err();
+        assert!(is_hash_mismatch(&error));
+        assert!(error.to_string().starts_with("block 109 has hash 0x"));
+        assert!(error.to_string().ends_with("the trusted chain has 0xfork"));
+
+        // cleanup
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_downloaded_chunk_from_another_fork_fails() {
+        // Arrange
+        let dir = test_support::temp_dir("verified_downloads");
+        let remote_dir = dir.join("remote");
+        let chunks = devtools::generate(&remote_dir, DirectoryLayout::default(), &[DatasetSpec::new([4u8; 32], 100, 1, 10), DatasetSpec::new([5u8; 32], 100, 1, 10)]).unwrap();
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data")).with_catalogue_file(dir.join("registry.parquet")))
+            .chunk_transfer(Arc::new(GeneratedTransfer { remote_dir }))
+            .block_hash_verifier([4u8; 32], BlockHashVerifier::new(Arc::new(GeneratedChain { fork_at: None })))
+            .block_hash_verifier([5u8; 32], BlockHashVerifier::new(Arc::new(GeneratedChain { fork_at: Some(100) })))
+            .build();
+
+        // Act
+        for chunk in chunks.iter() {
+            data_manager.download_chunk(chunk.clone());
+        }
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(300));
+        });
+
+        // Assert
+        let canonical = data_manager.get_chunk_info(chunks[0].id).unwrap();
+        let forked = data_manager.get_chunk_info(chunks[1].id).unwrap();
+        assert_eq!(canonical.status, ChunkStatus::Ready);
+        assert_eq!(forked.status, ChunkStatus::Failed);
+        assert_eq!(forked.errors[0].kind, ChunkErrorKind::Verification);
+        assert!(data_manager.find_chunk(chunks[1].dataset_id, 105).is_none());
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_ready_chunks_are_verified_again_by_priority() {
+        // Arrange
+        let dir = test_support::temp_dir("reverification");
+        let remote_dir = dir.join("remote");
+        let chunks = devtools::generate(&remote_dir, DirectoryLayout::default(), &[DatasetSpec::new([4u8; 32], 100, 1, 10), DatasetSpec::new([5u8; 32], 100, 1, 10)]).unwrap();
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let data_manager = DataManagerImpl::builder()
+            .config(DataManagerConfig::new(dir.join("data"))
+                .with_catalogue_file(dir.join("registry.parquet"))
+                .with_maintenance_priority([5u8; 32], 10))
+            .chunk_transfer(Arc::new(GeneratedTransfer { remote_dir }))
+            .block_hash_verifier([4u8; 32], BlockHashVerifier::new(Arc::new(GeneratedChain { fork_at: None })))
+            .block_hash_verifier([5u8; 32], BlockHashVerifier::new(Arc::new(GeneratedChain { fork_at: None })))
+            .build();
+        for chunk in chunks.iter() {
+            data_manager.download_chunk(chunk.clone());
+        }
+        futures::executor::block_on(async {
+            thread::sleep(Duration::from_millis(300));
+        });
+
+        // Act
+        let first_run = data_manager.verify_chunks(1);
+        let second_run = data_manager.verify_chunks(2);
+
+        // Assert
+        assert_eq!(first_run.verified, vec![chunks[1].id]);
+        assert_eq!(second_run.verified, vec![chunks[1].id, chunks[0].id]);
+        assert!(first_run.mismatched.is_empty() && second_run.mismatched.is_empty());
+
+        // cleanup
+        drop(data_manager);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
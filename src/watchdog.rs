@@ -0,0 +1,174 @@
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use crate::chunk_errors::ChunkErrorKind;
+use crate::correlation;
+use crate::data_catalogue::{ChunkInfo, ChunkStatus};
+use crate::data_chunk::ChunkId;
+use crate::workers::Workers;
+
+/// What to do with a chunk stuck in `Downloading` or `Deleting`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StaleOperationRepair {
+    /// Run the download or the deletion again
+    Requeue,
+    /// Give up on the chunk and mark it `Failed`
+    MarkFailed,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchdogConfig {
+    /// A chunk is stuck when it's in `Downloading` or `Deleting` for longer than this
+    /// and no background task is working on it
+    pub max_operation_age: Duration,
+    /// How often the catalogue is checked for stuck chunks
+    pub check_interval: Duration,
+    pub repair: StaleOperationRepair,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            max_operation_age: Duration::from_secs(10 * 60),
+            check_interval: Duration::from_secs(60),
+            repair: StaleOperationRepair::Requeue,
+        }
+    }
+}
+
+/// Background job repairing chunks stuck in `Downloading` or `Deleting`,
+/// e.g. when a worker thread died. The job stops when the watchdog is dropped.
+pub struct Watchdog {
+    _stop: mpsc::Sender<()>,
+}
+
+impl Watchdog {
+    pub(crate) fn start(config: WatchdogConfig, workers: Workers) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(config.check_interval) {
+                repair_stale_operations(&workers, &config);
+            }
+        });
+        Watchdog { _stop: stop }
+    }
+}
+
+/// Repair the chunks which are stuck, returns their ids
+pub(crate) fn repair_stale_operations(workers: &Workers, config: &WatchdogConfig) -> Vec<ChunkId> {
+    let mut stale: Vec<ChunkInfo> = workers.data_catalogue.registry.read().unwrap()
+        .values()
+        .filter(|info| matches!(info.status, ChunkStatus::Downloading | ChunkStatus::Deleting))
+        .filter(|info| info.updated_at.elapsed() >= config.max_operation_age)
+        .filter(|info| !workers.tasks_manager.is_active(&info.chunk.id))
+        // stuck chunks of frozen datasets are left alone until the dataset is unfrozen
+        .filter(|info| !workers.data_catalogue.is_frozen(&info.chunk.dataset_id))
+        .cloned()
+        .collect();
+    // with limited download slots, the repairs of the prioritized datasets are queued first
+    workers.maintenance_priorities.sort_by_priority(&mut stale, |info| info.chunk.dataset_id);
+
+    for info in stale.iter() {
+        let chunk = info.chunk.clone();
+        // the repair continues the request, which started the operation
+        correlation::scope(info.correlation_id.clone(), || match (config.repair, &info.status) {
+            (StaleOperationRepair::Requeue, ChunkStatus::Downloading) => {
+                // updating the status restarts the age of the operation
+                workers.data_catalogue.update_chunk(&chunk, &ChunkStatus::Downloading);
+                workers.spawn_download(chunk);
+            }
+            (StaleOperationRepair::Requeue, _) => {
+                workers.data_catalogue.update_chunk(&chunk, &ChunkStatus::Deleting);
+                workers.spawn_deletion(chunk);
+            }
+            (StaleOperationRepair::MarkFailed, status) => {
+                let kind = if *status == ChunkStatus::Downloading { ChunkErrorKind::Download } else { ChunkErrorKind::Delete };
+                // recorded first, so whoever sees the chunk `Failed` also sees why
+                workers.data_catalogue.record_error(&chunk.id, kind, format!("chunk was {} for too long", status));
+                workers.data_catalogue.update_chunk(&chunk, &ChunkStatus::Failed);
+            }
+        });
+    }
+    stale.iter().map(|info| info.chunk.id).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use serial_test::serial;
+    use crate::data_catalogue::load_catalogue_with_local_chunks;
+    use crate::data_manager::DataManager;
+    use crate::local_data_source::{get_test_chunk_111111_95_106, LOCAL_DATA_DIR};
+    use crate::DataManagerImpl;
+    use super::*;
+
+    fn config(repair: StaleOperationRepair) -> WatchdogConfig {
+        WatchdogConfig {
+            max_operation_age: Duration::ZERO,
+            check_interval: Duration::from_secs(60),
+            repair,
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_stuck_download_is_marked_failed() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let chunk = get_test_chunk_111111_95_106();
+        data_manager.data_catalogue.update_chunk(&chunk, &ChunkStatus::Downloading);
+
+        // Act
+        let repaired = repair_stale_operations(&data_manager.workers(), &config(StaleOperationRepair::MarkFailed));
+
+        // Assert
+        assert_eq!(repaired, vec![chunk.id]);
+        let registry = data_manager.data_catalogue.registry.read().unwrap();
+        assert_eq!(registry.get(&chunk.id).unwrap().status, ChunkStatus::Failed);
+    }
+
+    #[test]
+    #[serial]
+    fn test_stuck_download_is_requeued() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let chunk = get_test_chunk_111111_95_106();
+        data_manager.data_catalogue.update_chunk(&chunk, &ChunkStatus::Downloading);
+
+        // Act
+        let repaired = repair_stale_operations(&data_manager.workers(), &config(StaleOperationRepair::Requeue));
+        thread::sleep(Duration::from_millis(200));
+
+        // Assert
+        assert_eq!(repaired, vec![chunk.id]);
+        {
+            let registry = data_manager.data_catalogue.registry.read().unwrap();
+            assert_eq!(registry.get(&chunk.id).unwrap().status, ChunkStatus::Ready);
+        }
+
+        // cleanup
+        data_manager.delete_chunk(chunk.id);
+        thread::sleep(Duration::from_millis(200));
+    }
+
+    #[test]
+    #[serial]
+    fn test_running_operations_are_not_stale() {
+        // Arrange
+        load_catalogue_with_local_chunks();
+        let data_manager = DataManagerImpl::new(PathBuf::from(LOCAL_DATA_DIR));
+        let chunk = get_test_chunk_111111_95_106();
+        data_manager.data_catalogue.update_chunk(&chunk, &ChunkStatus::Downloading);
+        let _active_task = data_manager.tasks_manager.track_task(&chunk.id);
+
+        // Act
+        let repaired = repair_stale_operations(&data_manager.workers(), &config(StaleOperationRepair::MarkFailed));
+
+        // Assert
+        assert!(repaired.is_empty());
+        let registry = data_manager.data_catalogue.registry.read().unwrap();
+        assert_eq!(registry.get(&chunk.id).unwrap().status, ChunkStatus::Downloading);
+    }
+}
@@ -0,0 +1,475 @@
+#[cfg(feature = "dataframes")]
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::adaptive_concurrency::ConcurrencyController;
+use crate::block_time::BlockTimeIndex;
+use crate::cancellation::{self, Cancellations};
+use crate::checksum::{self, ChecksumRegistry};
+use crate::chunk_errors::ChunkErrorKind;
+use crate::correlation;
+use crate::data_catalogue::{ChunkStatus, DataCatalogue};
+use crate::data_chunk::{ChunkId, DataChunk, DatasetId};
+use crate::data_source::DataSource;
+use crate::download_pool::DownloadPool;
+use crate::event_loop::TasksManager;
+use crate::io_operation::OperationSender;
+use crate::fair_queue::{DownloadPriority, FairQueue};
+use crate::free_space::SpacePreflight;
+use crate::hooks::{HookDispatcher, LifecycleEvent};
+use crate::lineage::LineageKind;
+use crate::local_data_source::LocalDataSource;
+use crate::maintenance::MaintenancePriorities;
+use crate::operation::{OperationKind, OperationResult};
+use crate::permissions::{self, PermissionsConfig};
+use crate::rate_limit;
+use crate::repair;
+use crate::retry::{self, RetryPolicy};
+use crate::size_check;
+use crate::slo::{Slo, SloTracker};
+use crate::transform::ChunkTransformer;
+use crate::verification;
+#[cfg(feature = "dataframes")]
+use crate::verification::BlockHashVerifier;
+
+/// Everything the background workers need, cheap to clone into the worker threads
+#[derive(Clone)]
+pub struct Workers {
+    /// Data directory the downloads are staged and verified in
+    pub data_source: LocalDataSource,
+    /// Storage the verified chunks are published to and deleted from, see `DataManagerBuilder::data_source`
+    pub backend: Arc<dyn DataSource>,
+    pub data_catalogue: DataCatalogue,
+    pub tasks_manager: TasksManager,
+    pub hooks: HookDispatcher,
+    pub slo: SloTracker,
+    pub transformers: Arc<Vec<Arc<dyn ChunkTransformer>>>,
+    /// Slots the downloads wait for, unlimited when `None`
+    pub download_queue: Option<FairQueue>,
+    pub block_times: BlockTimeIndex,
+    /// Verifiers of the downloaded chunks per dataset
+    #[cfg(feature = "dataframes")]
+    pub hash_verifiers: Arc<HashMap<DatasetId, BlockHashVerifier>>,
+    /// Order of the datasets in the maintenance jobs
+    pub maintenance_priorities: Arc<MaintenancePriorities>,
+    /// Checksums of the files of the downloaded chunks
+    pub checksums: ChecksumRegistry,
+    /// Downloads which are no longer needed
+    pub cancellations: Cancellations,
+    /// Retries of the failed downloads, a failed download fails the chunk right away when `None`
+    pub download_retry: Option<RetryPolicy>,
+    /// Threads the downloads run on, the downloads over its limit wait for a thread in the order they were spawned
+    pub download_pool: DownloadPool,
+    /// How long a deletion waits for the readers holding the chunk, until they drop it when `None`
+    pub deletion_grace: Option<Duration>,
+    /// Receives the bytes of the downloads and limits the deletions running at once, unlimited when `None`
+    pub concurrency_controller: Option<ConcurrencyController>,
+    /// Checks the free space of the data directory before the downloads start, unchecked when `None`
+    pub space_preflight: Option<SpacePreflight>,
+    /// Modes and ownership given to the downloaded files, left as the transfer wrote them when `None`
+    pub permissions: Option<PermissionsConfig>,
+}
+
+impl Workers {
+    /// Download the chunk in background, the chunk must already be `Downloading` in the catalogue
+    pub fn spawn_download(&self, chunk: DataChunk) {
+        self.spawn_prioritized_download(chunk, DownloadPriority::Normal);
+    }
+
+    /// Same as `spawn_download`, with the priority used when the downloads wait for a slot
+    pub fn spawn_prioritized_download(&self, chunk: DataChunk, priority: DownloadPriority) {
+        let task_waker = self.tasks_manager.add_future_to_manager_pool();
+        let active_task = self.tasks_manager.track_task(&chunk.id);
+        let requested_at = Instant::now();
+        self.spawn_pooled(priority, chunk.dataset_id, chunk.id, move |workers| {
+            let _active_task = active_task;
+            let result = workers.download_with_retries(&chunk, priority, None);
+            let operation = workers.download_result(&chunk, &result, requested_at);
+            workers.finish_download(chunk, &result, requested_at);
+            workers.report(task_waker, Some(operation));
+        });
+    }
+
+    /// Delete the chunk in background, the chunk must already be `Deleting` in the catalogue
+    pub fn spawn_deletion(&self, chunk: DataChunk) {
+        let task_waker = self.tasks_manager.add_future_to_manager_pool();
+        let active_task = self.tasks_manager.track_task(&chunk.id);
+        self.spawn_thread(move |workers| {
+            let _active_task = active_task;
+            let operation = workers.delete(chunk);
+            workers.report(task_waker, Some(operation));
+        });
+    }
+
+    /// Delete the chunk in background to free space, the chunk must already be `Deleting` in the catalogue
+    pub fn spawn_eviction(&self, chunk: DataChunk) {
+        let task_waker = self.tasks_manager.add_future_to_manager_pool();
+        let active_task = self.tasks_manager.track_task(&chunk.id);
+        self.spawn_thread(move |workers| {
+            let _active_task = active_task;
+            let operation = workers.remove_files(&chunk, OperationKind::Evict);
+            if operation.is_ok() {
+                workers.hooks.emit(LifecycleEvent::Evict(chunk));
+            }
+            workers.report(task_waker, Some(operation));
+        });
+    }
+
+    /// Download the replacement chunk and delete the replaced chunks only once it's ready,
+    /// so the blocks stay available during the replacement.
+    /// The chunk must already be `Downloading` in the catalogue.
+    pub fn spawn_replacement(&self, chunk: DataChunk, replaced_chunks: Vec<DataChunk>, priority: DownloadPriority) {
+        let task_waker = self.tasks_manager.add_future_to_manager_pool();
+        let active_task = self.tasks_manager.track_task(&chunk.id);
+        let requested_at = Instant::now();
+        // only the download takes a thread of the pool, the waits run aside so they never hold one
+        self.spawn_thread(move |workers| {
+            // files of the chunk are replaced in place, so readers of the old files must finish first
+            workers.data_catalogue.pins.wait_until_unpinned(&chunk.id);
+            workers.spawn_pooled(priority, chunk.dataset_id, chunk.id, move |workers| {
+                let result = workers.download_with_retries(&chunk, priority, None);
+                let operation = workers.download_result(&chunk, &result, requested_at);
+                // recorded before the chunk gets ready, so whoever finds it also finds where it came from.
+                // A chunk downloaded again in place doesn't derive from itself.
+                let parents: Vec<DataChunk> = replaced_chunks.iter().filter(|replaced| replaced.id != chunk.id).cloned().collect();
+                if result.is_ok() && !parents.is_empty() && !workers.cancellations.is_cancelled(&chunk.id) {
+                    workers.data_catalogue.record_lineage(&chunk, LineageKind::of(&chunk, &parents), &parents);
+                }
+                if workers.finish_download(chunk, &result, requested_at) {
+                    workers.spawn_thread(move |workers| {
+                        let _active_task = active_task;
+                        // replaced chunks still being downloaded are deleted once they're ready
+                        workers.data_catalogue.wait_until_downloaded(&replaced_chunks.iter().map(|replaced| replaced.id).collect::<Vec<_>>());
+                        for replaced_chunk in replaced_chunks {
+                            if workers.data_catalogue.start_deletion(&replaced_chunk) {
+                                // the deletions have no future of their own, their results go straight to the log
+                                let deletion = workers.delete(replaced_chunk);
+                                workers.hooks.emit(LifecycleEvent::Operation(deletion.clone()));
+                                workers.tasks_manager.operations.record(deletion);
+                            }
+                        }
+                        workers.report(task_waker, Some(operation));
+                    });
+                } else {
+                    // when the download failed the replaced chunks stay, as nothing replaces them
+                    drop(active_task);
+                    workers.report(task_waker, Some(operation));
+                }
+            });
+        });
+    }
+
+    /// Download the chunk once the overlapping chunks are no longer being downloaded and it fits in the data directory.
+    /// The chunk isn't in the catalogue while it waits, it's downloaded only if it can start then.
+    pub fn spawn_queued_download(&self, chunk: DataChunk, overlapping_chunk_ids: Vec<ChunkId>, priority: DownloadPriority) {
+        let task_waker = self.tasks_manager.add_future_to_manager_pool();
+        let active_task = self.tasks_manager.track_task(&chunk.id);
+        let requested_at = Instant::now();
+        self.spawn_thread(move |workers| {
+            workers.data_catalogue.wait_until_downloaded(&overlapping_chunk_ids);
+            let fits = workers.space_preflight.as_ref()
+                .is_none_or(|preflight| preflight.wait_for_space(&workers.data_catalogue, &chunk).is_ok());
+            if !fits || !workers.data_catalogue.start_download(&chunk) {
+                drop(active_task);
+                workers.report(task_waker, None);
+                return;
+            }
+            workers.hooks.emit(LifecycleEvent::DownloadStart(chunk.clone()));
+            workers.spawn_pooled(priority, chunk.dataset_id, chunk.id, move |workers| {
+                let _active_task = active_task;
+                let result = workers.download_with_retries(&chunk, priority, None);
+                let operation = workers.download_result(&chunk, &result, requested_at);
+                workers.finish_download(chunk, &result, requested_at);
+                workers.report(task_waker, Some(operation));
+            });
+        });
+    }
+
+    /// Fetch the damaged files of the chunk again and swap them into its directory.
+    /// The chunk must already be `Downloading` in the catalogue, or `Ready` with its repair started, see `DataCatalogue::start_repair`,
+    /// and its other files must be intact. A ready chunk is read meanwhile, the files are swapped in by renaming them,
+    /// so its readers keep the files they opened.
+    pub fn spawn_repair(&self, chunk: DataChunk, damaged_files: Vec<String>) {
+        let task_waker = self.tasks_manager.add_future_to_manager_pool();
+        let active_task = self.tasks_manager.track_task(&chunk.id);
+        let requested_at = Instant::now();
+        self.spawn_pooled(DownloadPriority::High, chunk.dataset_id, chunk.id, move |workers| {
+            let _active_task = active_task;
+            let result = workers.download_with_retries(&chunk, DownloadPriority::High, Some(&damaged_files));
+            let operation = workers.download_result(&chunk, &result, requested_at);
+            if workers.data_catalogue.is_repairing(&chunk.id) {
+                workers.finish_repair(chunk, &result);
+            } else {
+                workers.finish_download(chunk, &result, requested_at);
+            }
+            workers.report(task_waker, Some(operation));
+        });
+    }
+
+    /// Deliver the result of the operation to the hooks, then to its future, which adds it to the operation log
+    fn report(&self, task_waker: OperationSender, operation: Option<OperationResult>) {
+        if let Some(operation) = &operation {
+            if let (Some(controller), OperationKind::Download, Some(bytes)) = (&self.concurrency_controller, operation.kind, operation.bytes) {
+                controller.record_transfer(bytes);
+            }
+            self.hooks.emit(LifecycleEvent::Operation(operation.clone()));
+        }
+        TasksManager::wake_the_future(task_waker, operation);
+    }
+
+    /// Result of the download, a cancelled download failed even when its files were received
+    fn download_result(&self, chunk: &DataChunk, result: &io::Result<bool>, requested_at: Instant) -> OperationResult {
+        match result {
+            Ok(_) if self.cancellations.is_cancelled(&chunk.id) => {
+                OperationResult::failed(OperationKind::Download, chunk.id, requested_at, &cancellation::cancelled())
+            }
+            Ok(_) => OperationResult::succeeded(OperationKind::Download, chunk.id, requested_at, Some(self.data_source.chunk_bytes(chunk))),
+            Err(error) => OperationResult::failed(OperationKind::Download, chunk.id, requested_at, error),
+        }
+    }
+
+    /// Run the work in a new thread, within the correlation scope of the caller
+    fn spawn_thread(&self, work: impl FnOnce(Workers) + Send + 'static) {
+        let workers = self.clone();
+        let correlation_id = correlation::current();
+        thread::spawn(move || correlation::scope(correlation_id, || work(workers)));
+    }
+
+    /// Run the work once the download pool has a free thread for the priority and it's the turn of the dataset,
+    /// within the correlation scope of the caller
+    fn spawn_pooled(&self, priority: DownloadPriority, dataset_id: DatasetId, chunk_id: ChunkId, work: impl FnOnce(Workers) + Send + 'static) {
+        let workers = self.clone();
+        let correlation_id = correlation::current();
+        self.download_pool.submit(priority, dataset_id, chunk_id, move || correlation::scope(correlation_id, || work(workers)));
+    }
+
+    /// Download the chunk, retrying the failed attempts after a backoff.
+    /// An attempt refused by the origin is retried after its `Retry-After` rather than the backoff when it's longer,
+    /// and reported to the hooks, see `LifecycleHooks::on_throttled`.
+    /// Every failed attempt but the last is recorded in the history of the chunk, the last one is the result.
+    /// With `damaged_files` only those are transferred, next to the other files of the chunk directory.
+    fn download_with_retries(&self, chunk: &DataChunk, priority: DownloadPriority, damaged_files: Option<&[String]>) -> io::Result<bool> {
+        let mut attempt = 1;
+        loop {
+            let result = self.download(chunk, priority, damaged_files);
+            let Err(error) = &result else { return result };
+            let refusal = rate_limit::as_rate_limited(error);
+            if let Some(refusal) = refusal {
+                self.hooks.emit(LifecycleEvent::Throttled(chunk.clone(), refusal.retry_after));
+            }
+            // the download slot is released while waiting, so other downloads continue meanwhile
+            let backoff = self.download_retry.as_ref()
+                .filter(|_| retry::is_retryable(error) && !self.cancellations.is_cancelled(&chunk.id))
+                .and_then(|policy| match refusal {
+                    Some(refusal) => policy.throttled_backoff(attempt, refusal.retry_after),
+                    None => policy.backoff(attempt),
+                });
+            let Some(backoff) = backoff else { return result };
+            self.data_catalogue.record_error(&chunk.id, ChunkErrorKind::Download, error.to_string());
+            thread::sleep(backoff);
+            attempt += 1;
+        }
+    }
+
+    /// Download the chunk files, run the transformers over them and verify them, returns whether the files were optimized
+    fn download(&self, chunk: &DataChunk, priority: DownloadPriority, damaged_files: Option<&[String]>) -> io::Result<bool> {
+        let _slot = match &self.download_queue {
+            // only cancelled downloads are withdrawn from the queue
+            Some(download_queue) => Some(download_queue.acquire(&chunk.id, &chunk.dataset_id, priority).ok_or_else(cancellation::cancelled)?),
+            None => None,
+        };
+        self.cancellations.check(&chunk.id)?;
+        let chunk_dir = self.data_source.chunk_path(chunk.clone()).path;
+        // a directory with partial files was left by an interrupted download, it was never ready so nobody reads its files
+        let replaces_files = chunk_dir.exists() && !self.data_source.has_partial_files(chunk);
+        // the files are complete in the chunk directory only once they're verified, a failed transfer leaves them staged to be continued
+        let staging_dir = match damaged_files {
+            Some(damaged_files) => self.data_source.download_damaged_files_staged(chunk, damaged_files)?,
+            None => {
+                // the checksums are of the files as published, so the content store can only stand in for them while nothing transforms them
+                let known_checksums = if self.transformers.is_empty() { self.checksums.file_checksums(chunk) } else { Default::default() };
+                self.data_source.download_chunk_staged_from_store(chunk, &known_checksums)?
+            }
+        };
+        // the sizes and the checksums are of the files as published, before the transformers changed them
+        let result = self.cancellations.check(&chunk.id)
+            .and_then(|_| size_check::verify(chunk, &staging_dir))
+            .and_then(|_| self.checksums.verify(chunk, &staging_dir))
+            .and_then(|_| self.transform(chunk, &staging_dir))
+            .and_then(|optimized| self.verify(chunk, &staging_dir).map(|_| optimized))
+            .and_then(|optimized| permissions::prepare_chunk_dir(&staging_dir, self.permissions.as_ref()).map(|_| optimized))
+            .and_then(|optimized| self.data_source.store_staged_files(chunk, &staging_dir).map(|_| optimized))
+            .and_then(|optimized| self.cancellations.check(&chunk.id).map(|_| optimized))
+            .and_then(|optimized| match replaces_files {
+                // files of a chunk on disk are swapped one by one, rather than the whole directory
+                true => self.data_source.swap_chunk_files(chunk, &staging_dir).map(|_| optimized),
+                false => self.backend.publish_staged_chunk(chunk, &staging_dir).map(|_| optimized),
+            });
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&staging_dir);
+        }
+        result
+    }
+
+    /// Whether the block hashes of the chunks of the dataset are checked
+    #[cfg(feature = "dataframes")]
+    pub(crate) fn has_hash_verifier(&self, dataset_id: &DatasetId) -> bool {
+        self.hash_verifiers.contains_key(dataset_id)
+    }
+
+    /// Without the `dataframes` feature there are no verifiers
+    #[cfg(not(feature = "dataframes"))]
+    pub(crate) fn has_hash_verifier(&self, _dataset_id: &DatasetId) -> bool {
+        false
+    }
+
+    /// Check the block hashes of the files in the directory, when the dataset has a verifier
+    #[cfg(feature = "dataframes")]
+    pub(crate) fn verify(&self, chunk: &DataChunk, dir: &Path) -> io::Result<()> {
+        match self.hash_verifiers.get(&chunk.dataset_id) {
+            Some(verifier) => verifier.verify(chunk, dir),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(feature = "dataframes"))]
+    pub(crate) fn verify(&self, _chunk: &DataChunk, _dir: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Run the transformers over the files in the directory, returns whether the files were optimized
+    fn transform(&self, chunk: &DataChunk, dir: &Path) -> io::Result<bool> {
+        for transformer in self.transformers.iter() {
+            transformer.transform(chunk, dir)?;
+        }
+        Ok(self.transformers.iter().any(|transformer| transformer.optimizes_layout()))
+    }
+
+    /// Record the result of the download, returns whether the chunk is ready
+    fn finish_download(&self, chunk: DataChunk, result: &io::Result<bool>, requested_at: Instant) -> bool {
+        if self.cancellations.take(&chunk.id) {
+            self.discard(chunk);
+            return false;
+        }
+        // a failed download never got ready, so it always breaches the target
+        let latency = if result.is_ok() { requested_at.elapsed() } else { Duration::MAX };
+        self.slo.record(Slo::ChunkReady, latency);
+        match result {
+            Ok(optimized) => {
+                self.data_catalogue.mark_ready(&chunk, *optimized);
+                self.hooks.emit(LifecycleEvent::DownloadComplete(chunk));
+                true
+            }
+            Err(error) => {
+                // recorded first, so whoever sees the chunk `Failed` also sees why
+                self.data_catalogue.record_error(&chunk.id, download_error_kind(error), error.to_string());
+                self.data_catalogue.update_chunk(&chunk, &ChunkStatus::Failed);
+                self.hooks.emit(LifecycleEvent::DownloadFailed(chunk, error.to_string()));
+                false
+            }
+        }
+    }
+
+    /// Record the result of the repair of a ready chunk, which stays `Ready` whether the files were swapped in or not
+    fn finish_repair(&self, chunk: DataChunk, result: &io::Result<bool>) {
+        match result {
+            Ok(optimized) => {
+                self.data_catalogue.finish_repair(&chunk, Some(*optimized));
+                self.hooks.emit(LifecycleEvent::DownloadComplete(chunk));
+            }
+            Err(error) => {
+                self.data_catalogue.record_error(&chunk.id, download_error_kind(error), error.to_string());
+                self.data_catalogue.finish_repair(&chunk, None);
+                self.hooks.emit(LifecycleEvent::DownloadFailed(chunk, error.to_string()));
+            }
+        }
+    }
+
+    /// Remove the files of a cancelled download, the chunk is marked `Deleted`, or `Failed` when its files can't be removed
+    fn discard(&self, chunk: DataChunk) {
+        // the files of the cancelled transfer aren't continued
+        let _ = self.data_source.discard_staged_files(&chunk);
+        // the files may have been published to the backend before the cancellation arrived
+        if !self.backend.may_hold_chunk(&chunk) {
+            self.data_catalogue.update_chunk(&chunk, &ChunkStatus::Deleted);
+            return;
+        }
+        // a chunk downloaded again in place may still be read
+        self.wait_for_readers(&chunk);
+        match self.backend.delete_chunk(&chunk) {
+            Ok(_) => {
+                self.data_catalogue.update_chunk(&chunk, &ChunkStatus::Deleted);
+                self.hooks.emit(LifecycleEvent::Delete(chunk));
+            }
+            Err(error) => {
+                // recorded first, so whoever sees the chunk `Failed` also sees why
+                self.data_catalogue.record_error(&chunk.id, ChunkErrorKind::Delete, error.to_string());
+                self.data_catalogue.update_chunk(&chunk, &ChunkStatus::Failed);
+            }
+        }
+    }
+
+    fn delete(&self, chunk: DataChunk) -> OperationResult {
+        let operation = self.remove_files(&chunk, OperationKind::Delete);
+        if operation.is_ok() {
+            self.hooks.emit(LifecycleEvent::Delete(chunk));
+        }
+        operation
+    }
+
+    /// Wait until nobody holds the chunk. With a deletion grace, the readers still holding it are notified
+    /// and the wait ends once the grace elapses, so a long-running reader can't keep the files forever.
+    fn wait_for_readers(&self, chunk: &DataChunk) {
+        let pins = &self.data_catalogue.pins;
+        let Some(grace) = self.deletion_grace else {
+            pins.wait_until_unpinned(&chunk.id);
+            return;
+        };
+        if pins.pin_count(&chunk.id) == 0 {
+            return;
+        }
+        pins.deprecate(&chunk.id, Instant::now() + grace);
+        self.hooks.emit(LifecycleEvent::Deprecate(chunk.clone(), grace));
+        pins.wait_until_unpinned_for(&chunk.id, grace);
+        pins.clear_deprecation(&chunk.id);
+    }
+
+    /// A chunk which couldn't be deleted stays `Ready` while its files are intact, otherwise it's `Failed`,
+    /// with the error in its history either way
+    fn remove_files(&self, chunk: &DataChunk, kind: OperationKind) -> OperationResult {
+        let started_at = Instant::now();
+        // the chunk must remain untouched until all its references are dropped, or the grace of its readers elapses
+        self.wait_for_readers(chunk);
+        let deleted = {
+            let _permit = self.concurrency_controller.as_ref().map(ConcurrencyController::acquire_deletion);
+            self.backend.delete_chunk(chunk)
+        };
+        match deleted {
+            Ok(deleted) => {
+                self.data_catalogue.update_chunk(chunk, &ChunkStatus::Deleted);
+                OperationResult::succeeded(kind, chunk.id, started_at, deleted.bytes)
+            }
+            Err(error) => {
+                self.data_catalogue.record_error(&chunk.id, ChunkErrorKind::Delete, error.to_string());
+                // the deletion may have removed some of the files already, a chunk left incomplete isn't served again
+                let chunk_dir = self.data_source.chunk_path(chunk.clone()).path;
+                let intact = chunk_dir.is_dir() && repair::damaged_files(chunk, &chunk_dir, &self.checksums.file_checksums(chunk)).is_empty();
+                self.data_catalogue.update_chunk(chunk, if intact { &ChunkStatus::Ready } else { &ChunkStatus::Failed });
+                OperationResult::failed(kind, chunk.id, started_at, &error)
+            }
+        }
+    }
+}
+
+/// Failed verifications are told from failed transfers in the history of the chunk
+fn download_error_kind(error: &io::Error) -> ChunkErrorKind {
+    if verification::is_hash_mismatch(error) || checksum::is_checksum_mismatch(error) || size_check::is_size_mismatch(error) {
+        ChunkErrorKind::Verification
+    } else {
+        ChunkErrorKind::Download
+    }
+}